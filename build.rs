@@ -0,0 +1,123 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+// Compila as shaders de src/resources/shaders pra SPIR-V em OUT_DIR/shaders e gera a
+// tabela que o shader.rs inclui (ver `spirv!` e `shader::embedded`). Os .glsl só entram
+// pelos #include
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let source_dir = Path::new("src/resources/shaders");
+    println!("cargo:rerun-if-changed={}", source_dir.display());
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").map_err(|e| format!("OUT_DIR: {}", e))?);
+    let shader_dir = out_dir.join("shaders");
+    fs::create_dir_all(&shader_dir)
+        .map_err(|e| format!("Failed to create {}: {}", shader_dir.display(), e))?;
+
+    let compiler = shaderc::Compiler::new().ok_or_else(|| {
+        "Failed to initialize shaderc. It needs CMake, Python and a C++ compiler to build, or \
+         SHADERC_LIB_DIR pointing to a prebuilt libshaderc_combined."
+            .to_string()
+    })?;
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(source_dir)
+        .and_then(|entries| entries.map(|entry| entry.map(|e| e.path())).collect())
+        .map_err(|e| format!("Failed to list {}: {}", source_dir.display(), e))?;
+    paths.sort();
+
+    // Todas as shaders são compiladas, pra mostrar todos os erros de uma vez
+    let mut names = vec![];
+    let mut failures = vec![];
+    for path in paths {
+        let kind = match path.extension().and_then(|e| e.to_str()) {
+            Some("vert") => shaderc::ShaderKind::Vertex,
+            Some("frag") => shaderc::ShaderKind::Fragment,
+            Some("comp") => shaderc::ShaderKind::Compute,
+            Some("tesc") => shaderc::ShaderKind::TessControl,
+            Some("tese") => shaderc::ShaderKind::TessEvaluation,
+            Some("geom") => shaderc::ShaderKind::Geometry,
+            _ => continue,
+        };
+
+        match compile(&compiler, source_dir, &path, kind, &shader_dir) {
+            Ok(name) => names.push(name),
+            Err(e) => failures.push(e),
+        }
+    }
+    if !failures.is_empty() {
+        return Err(format!(
+            "{} shader(s) failed to compile:\n{}",
+            failures.len(),
+            failures.join("\n")
+        ));
+    }
+
+    let mut table = String::from("pub static EMBEDDED: &[(&str, &[u8])] = &[\n");
+    for name in &names {
+        table += &format!(
+            "    ({:?}, include_bytes!(concat!(env!(\"OUT_DIR\"), \"/shaders/{}.spv\"))),\n",
+            name, name
+        );
+    }
+    table += "];\n";
+    let table_path = out_dir.join("shaders.rs");
+    fs::write(&table_path, table)
+        .map_err(|e| format!("Failed to write {}: {}", table_path.display(), e))
+}
+
+// Retorna o nome do arquivo, que é a chave na tabela
+fn compile(
+    compiler: &shaderc::Compiler,
+    source_dir: &Path,
+    path: &Path,
+    kind: shaderc::ShaderKind,
+    shader_dir: &Path,
+) -> Result<String, String> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("{}: file name is not UTF-8", path.display()))?
+        .to_string();
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut options = shaderc::CompileOptions::new()
+        .ok_or_else(|| "Failed to create shaderc compile options.".to_string())?;
+    options.set_target_env(
+        shaderc::TargetEnv::Vulkan,
+        shaderc::EnvVersion::Vulkan1_0 as u32,
+    );
+    let include_dir = source_dir.to_path_buf();
+    options.set_include_callback(move |requested, _, _, _| {
+        let path = include_dir.join(requested);
+        let content =
+            fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: path.display().to_string(),
+            content,
+        })
+    });
+
+    let artifact = compiler
+        .compile_into_spirv(&source, kind, &name, "main", Some(&options))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if artifact.get_num_warnings() > 0 {
+        for line in artifact.get_warning_messages().lines() {
+            println!("cargo:warning={}", line);
+        }
+    }
+
+    let output = shader_dir.join(format!("{}.spv", name));
+    fs::write(&output, artifact.as_binary_u8())
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+
+    Ok(name)
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::{Handle, KhrGetPhysicalDeviceProperties2Extension};
+
+use crate::{app::AppData, error::RendererError};
+
+// Fração do orçamento de um heap a partir da qual o aviso dispara, se ninguém pedir outra
+const DEFAULT_WARNING_THRESHOLD: f32 = 0.9;
+
+lazy_static! {
+    // Cada vkAllocateMemory é um bloco: o tipo de memória e o tamanho dele. A maioria guarda
+    // um recurso só; os do render graph se revezam entre imagens transientes
+    static ref BLOCKS: Mutex<HashMap<u64, (u32, vk::DeviceSize)>> = Mutex::new(HashMap::new());
+}
+
+// Todas as alocações de memória do dispositivo passam por aqui, pra contar os blocos
+pub unsafe fn allocate(
+    device: &Device,
+    size: vk::DeviceSize,
+    type_index: u32,
+) -> Result<vk::DeviceMemory> {
+    let info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(size)
+        .memory_type_index(type_index);
+
+    // Perder o dispositivo aqui continua sendo DeviceLost; o resto é falta de memória
+    let memory = device.allocate_memory(&info, None).map_err(|e| match e {
+        vk::ErrorCode::DEVICE_LOST => RendererError::DeviceLost,
+        _ => RendererError::Allocation(format!(
+            "{} bytes from memory type {} ({})",
+            size, type_index, e
+        )),
+    })?;
+    BLOCKS
+        .lock()
+        .unwrap()
+        .insert(memory.as_raw(), (type_index, size));
+
+    Ok(memory)
+}
+
+pub unsafe fn free(device: &Device, memory: vk::DeviceMemory) {
+    BLOCKS.lock().unwrap().remove(&memory.as_raw());
+    device.free_memory(memory, None);
+}
+
+// Um heap de memória do dispositivo. Com o VK_EXT_memory_budget o uso e o orçamento vêm
+// do driver (e contam outros processos); sem ele, o uso é só o que alocamos e o
+// orçamento é o heap inteiro
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HeapStats {
+    pub flags: vk::MemoryHeapFlags,
+    pub size: vk::DeviceSize,
+    pub usage: vk::DeviceSize,
+    pub budget: vk::DeviceSize,
+    // Nossos blocos nesse heap: quantos e quanto somam
+    pub blocks: u32,
+    pub allocated: vk::DeviceSize,
+}
+
+impl HeapStats {
+    pub fn device_local(&self) -> bool {
+        self.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL)
+    }
+
+    pub fn usage_ratio(&self) -> f32 {
+        if self.budget == 0 {
+            0.0
+        } else {
+            self.usage as f32 / self.budget as f32
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStats {
+    pub heaps: Vec<HeapStats>,
+    // Se uso e orçamento vieram do driver
+    pub from_driver: bool,
+}
+
+impl MemoryStats {
+    pub unsafe fn query(instance: &Instance, data: &AppData) -> Self {
+        let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let memory = if data.capabilities.memory_budget {
+            let mut properties =
+                vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut budget);
+            instance
+                .get_physical_device_memory_properties2_khr(data.physical_device, &mut properties);
+            properties.memory_properties
+        } else {
+            instance.get_physical_device_memory_properties(data.physical_device)
+        };
+
+        let mut heaps = (0..memory.memory_heap_count as usize)
+            .map(|i| HeapStats {
+                flags: memory.memory_heaps[i].flags,
+                size: memory.memory_heaps[i].size,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        for (type_index, size) in BLOCKS.lock().unwrap().values() {
+            let heap = memory.memory_types[*type_index as usize].heap_index as usize;
+            heaps[heap].blocks += 1;
+            heaps[heap].allocated += size;
+        }
+
+        for (i, heap) in heaps.iter_mut().enumerate() {
+            if data.capabilities.memory_budget {
+                heap.usage = budget.heap_usage[i];
+                heap.budget = budget.heap_budget[i];
+            } else {
+                heap.usage = heap.allocated;
+                heap.budget = heap.size;
+            }
+        }
+
+        Self {
+            heaps,
+            from_driver: data.capabilities.memory_budget,
+        }
+    }
+
+    // Soma dos heaps DEVICE_LOCAL, que é o que importa como "memória de vídeo"
+    pub fn device_local(&self) -> HeapStats {
+        self.heaps
+            .iter()
+            .filter(|h| h.device_local())
+            .fold(HeapStats::default(), |total, heap| HeapStats {
+                flags: heap.flags,
+                size: total.size + heap.size,
+                usage: total.usage + heap.usage,
+                budget: total.budget + heap.budget,
+                blocks: total.blocks + heap.blocks,
+                allocated: total.allocated + heap.allocated,
+            })
+    }
+}
+
+// Chamado com as estatísticas e o índice do heap que passou do limite
+pub type MemoryWarningCallback = Box<dyn FnMut(&MemoryStats, usize) + Send>;
+
+// Confere os heaps a cada frame e chama o callback quando um deles passa da fração
+// `threshold` do orçamento. Só avisa de novo depois que o uso voltar pra baixo do limite
+pub struct MemoryWatch {
+    pub threshold: f32,
+    callback: MemoryWarningCallback,
+    over: Vec<bool>,
+}
+
+impl Default for MemoryWatch {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_WARNING_THRESHOLD,
+            Box::new(|stats, heap| {
+                let heap = &stats.heaps[heap];
+                warn!(
+                    "Memory heap ({:?}) at {}/{} MB of its budget.",
+                    heap.flags,
+                    heap.usage >> 20,
+                    heap.budget >> 20
+                );
+            }),
+        )
+    }
+}
+
+impl fmt::Debug for MemoryWatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryWatch")
+            .field("threshold", &self.threshold)
+            .field("over", &self.over)
+            .finish()
+    }
+}
+
+impl MemoryWatch {
+    pub fn new(threshold: f32, callback: MemoryWarningCallback) -> Self {
+        Self {
+            threshold,
+            callback,
+            over: vec![],
+        }
+    }
+
+    pub fn check(&mut self, stats: &MemoryStats) {
+        self.over.resize(stats.heaps.len(), false);
+        for (i, heap) in stats.heaps.iter().enumerate() {
+            let over = heap.usage_ratio() > self.threshold;
+            if over && !self.over[i] {
+                (self.callback)(stats, i);
+            }
+            self.over[i] = over;
+        }
+    }
+}
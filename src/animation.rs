@@ -0,0 +1,505 @@
+use std::{mem::size_of, ptr};
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator, app::AppData, buffer::create_buffer, leaks, scene::Transform, MAX_FRAMES_IN_FLIGHT,
+};
+
+// Matrizes de junta de todos os animadores somados, por frame. Quem passar disso é
+// desenhado na pose de repouso
+pub const MAX_JOINTS: usize = 4096;
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    // Sempre antes dessa junta na lista
+    pub parent: Option<usize>,
+    // Leva do espaço da malha pro da junta na pose em que a malha foi modelada
+    pub inverse_bind: glm::Mat4,
+    // Transformação local quando nenhum clipe mexe nela
+    pub rest: Transform,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    // Os pais vêm antes dos filhos, pra pose ser calculada numa passada só
+    pub fn new(joints: Vec<Joint>) -> Result<Self> {
+        if joints.len() > MAX_JOINTS {
+            return Err(anyhow!(
+                "Skeleton has {} joints (maximum is {}).",
+                joints.len(),
+                MAX_JOINTS
+            ));
+        }
+
+        for (index, joint) in joints.iter().enumerate() {
+            if let Some(parent) = joint.parent {
+                if parent >= index {
+                    return Err(anyhow!(
+                        "Joint '{}' ({}) must come after its parent ({}).",
+                        joint.name,
+                        index,
+                        parent
+                    ));
+                }
+            }
+        }
+
+        Ok(Self { joints })
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name == name)
+    }
+}
+
+// Valores de um canal, um por instante em `Channel::times`
+#[derive(Clone, Debug)]
+pub enum Keyframes {
+    Translation(Vec<glm::Vec3>),
+    Rotation(Vec<glm::Quat>),
+    Scale(Vec<glm::Vec3>),
+}
+
+impl Keyframes {
+    fn len(&self) -> usize {
+        match self {
+            Keyframes::Translation(values) => values.len(),
+            Keyframes::Rotation(values) => values.len(),
+            Keyframes::Scale(values) => values.len(),
+        }
+    }
+}
+
+// Anima uma propriedade de uma junta, com interpolação linear entre os quadros (esférica
+// na rotação)
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub joint: usize,
+    // Em segundos, crescente
+    pub times: Vec<f32>,
+    pub values: Keyframes,
+}
+
+impl Channel {
+    // Quadro antes de `time` e quanto já andou até o próximo
+    fn keyframe(&self, time: f32) -> (usize, usize, f32) {
+        let next = self.times.partition_point(|t| *t <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        if next == self.times.len() {
+            return (next - 1, next - 1, 0.0);
+        }
+
+        let (a, b) = (self.times[next - 1], self.times[next]);
+        let t = if b > a { (time - a) / (b - a) } else { 0.0 };
+        (next - 1, next, t)
+    }
+
+    fn apply(&self, time: f32, transform: &mut Transform) {
+        let (a, b, t) = self.keyframe(time);
+        match &self.values {
+            Keyframes::Translation(values) => {
+                transform.translation = glm::lerp(&values[a], &values[b], t);
+            }
+            Keyframes::Rotation(values) => {
+                transform.rotation =
+                    glm::quat_normalize(&glm::quat_slerp(&values[a], &values[b], t));
+            }
+            Keyframes::Scale(values) => {
+                transform.scale = glm::lerp(&values[a], &values[b], t);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    // Último quadro de qualquer canal
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .filter_map(|c| c.times.last())
+            .fold(0.0, |a, b| a.max(*b))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AnimatorId(usize);
+
+// Toca os clipes de um esqueleto. O App avança todos uma vez por frame e manda a pose pra
+// GPU; as malhas com o mesmo `AnimatorId` no nó são deformadas por ela
+#[derive(Clone, Debug)]
+pub struct Animator {
+    skeleton: Skeleton,
+    clips: Vec<AnimationClip>,
+    current: Option<usize>,
+    time: f32,
+    playing: bool,
+    // Volta pro começo no fim do clipe; senão para no último quadro
+    pub looping: bool,
+    // Multiplica o tempo do frame, negativo toca de trás pra frente
+    pub speed: f32,
+}
+
+impl Animator {
+    pub fn new(skeleton: Skeleton, clips: Vec<AnimationClip>) -> Result<Self> {
+        for clip in &clips {
+            for channel in &clip.channels {
+                if channel.joint >= skeleton.joints.len() {
+                    return Err(anyhow!(
+                        "Clip '{}' animates joint {} (skeleton has {}).",
+                        clip.name,
+                        channel.joint,
+                        skeleton.joints.len()
+                    ));
+                }
+                if channel.times.is_empty() || channel.times.len() != channel.values.len() {
+                    return Err(anyhow!(
+                        "Clip '{}' has a channel with {} times and {} values.",
+                        clip.name,
+                        channel.times.len(),
+                        channel.values.len()
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            skeleton,
+            clips,
+            current: None,
+            time: 0.0,
+            playing: false,
+            looping: true,
+            speed: 1.0,
+        })
+    }
+
+    pub fn skeleton(&self) -> &Skeleton {
+        &self.skeleton
+    }
+
+    pub fn clips(&self) -> &[AnimationClip] {
+        &self.clips
+    }
+
+    // Começa o clipe do início (do fim, com `speed` negativo)
+    pub fn play(&mut self, name: &str, looping: bool) -> Result<()> {
+        let index = self
+            .clips
+            .iter()
+            .position(|c| c.name == name)
+            .ok_or_else(|| anyhow!("Animation clip '{}' does not exist.", name))?;
+
+        self.current = Some(index);
+        self.time = if self.speed < 0.0 {
+            self.clips[index].duration()
+        } else {
+            0.0
+        };
+        self.playing = true;
+        self.looping = looping;
+        Ok(())
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn resume(&mut self) {
+        self.playing = self.current.is_some();
+    }
+
+    // Volta pra pose de repouso
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.time = 0.0;
+        self.playing = false;
+    }
+
+    pub fn clip(&self) -> Option<&AnimationClip> {
+        self.current.map(|i| &self.clips[i])
+    }
+
+    // Falso também quando um clipe sem loop chegou no fim
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        let duration = self.clip().map_or(0.0, |c| c.duration());
+        self.time = time.clamp(0.0, duration);
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        let duration = match self.clip() {
+            Some(clip) if self.playing => clip.duration(),
+            _ => return,
+        };
+
+        self.time += delta * self.speed;
+        if duration <= 0.0 {
+            self.time = 0.0;
+        } else if self.looping {
+            self.time = self.time.rem_euclid(duration);
+        } else if (self.speed > 0.0 && self.time >= duration)
+            || (self.speed < 0.0 && self.time <= 0.0)
+        {
+            self.time = self.time.clamp(0.0, duration);
+            self.playing = false;
+        }
+    }
+
+    // Uma matriz por junta, do espaço da malha pro mundo da pose atual (antes da matriz
+    // do nó), na ordem do esqueleto
+    pub fn joint_matrices(&self) -> Vec<glm::Mat4> {
+        let mut locals = self
+            .skeleton
+            .joints
+            .iter()
+            .map(|j| j.rest)
+            .collect::<Vec<_>>();
+        if let Some(clip) = self.clip() {
+            for channel in &clip.channels {
+                channel.apply(self.time, &mut locals[channel.joint]);
+            }
+        }
+
+        let mut globals: Vec<glm::Mat4> = Vec::with_capacity(locals.len());
+        for (joint, local) in self.skeleton.joints.iter().zip(&locals) {
+            let global = match joint.parent {
+                Some(parent) => globals[parent] * local.matrix(),
+                None => local.matrix(),
+            };
+            globals.push(global);
+        }
+
+        globals
+            .iter()
+            .zip(&self.skeleton.joints)
+            .map(|(global, joint)| global * joint.inverse_bind)
+            .collect()
+    }
+}
+
+// Pesos de até 4 juntas por vértice, na ordem dos vértices da malha
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct VertexSkin {
+    pub joints: [u32; 4],
+    // Somam 1
+    pub weights: glm::Vec4,
+}
+
+impl VertexSkin {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(size_of::<VertexSkin>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    // Locations 4 e 5, depois das do Vertex
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(4)
+                .format(vk::Format::R32G32B32A32_UINT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(5)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(size_of::<[u32; 4]>() as u32)
+                .build(),
+        ]
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PaletteBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+}
+
+// Os animadores e as matrizes de junta na GPU: um storage buffer mapeado por frame em voo
+// (set 2 da pipeline com skinning), com as poses de todos os animadores uma atrás da
+// outra. Cada desenho acha o começo da sua pelo firstInstance
+#[derive(Clone, Debug, Default)]
+pub struct Skinning {
+    animators: Vec<Animator>,
+    pub layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    buffers: Vec<PaletteBuffer>,
+    // Primeira junta de cada animador no último `write`
+    offsets: Vec<Option<u32>>,
+}
+
+impl Skinning {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let mut skinning = Self::default();
+        skinning.create_device_objects(instance, device, data)?;
+        Ok(skinning)
+    }
+
+    // Os animadores continuam; só os buffers são recriados
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        let bindings = &[vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build()];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(MAX_FRAMES_IN_FLIGHT as u32)
+            .build()];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(MAX_FRAMES_IN_FLIGHT as u32);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = vec![self.layout; MAX_FRAMES_IN_FLIGHT];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(&set_layouts);
+        self.sets = device.allocate_descriptor_sets(&info)?;
+
+        let size = (MAX_JOINTS * size_of::<glm::Mat4>()) as vk::DeviceSize;
+        self.buffers.clear();
+        for set in &self.sets {
+            let (buffer, memory) = create_buffer(
+                instance,
+                device,
+                data,
+                size,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let mapped =
+                device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())? as *mut u8;
+
+            let buffer_info = &[vk::DescriptorBufferInfo::builder()
+                .buffer(buffer)
+                .offset(0)
+                .range(size)
+                .build()];
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(buffer_info);
+            device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+            self.buffers.push(PaletteBuffer {
+                buffer,
+                memory,
+                mapped,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn add(&mut self, animator: Animator) -> AnimatorId {
+        self.animators.push(animator);
+        AnimatorId(self.animators.len() - 1)
+    }
+
+    pub fn get(&self, id: AnimatorId) -> Option<&Animator> {
+        self.animators.get(id.0)
+    }
+
+    pub fn get_mut(&mut self, id: AnimatorId) -> Option<&mut Animator> {
+        self.animators.get_mut(id.0)
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        self.animators.iter_mut().for_each(|a| a.update(delta));
+    }
+
+    // Escreve as poses no buffer do frame. Só depois da fence do frame
+    pub unsafe fn write(&mut self, slot: usize) {
+        let mapped = self.buffers[slot].mapped as *mut glm::Mat4;
+        let mut count = 0;
+        let mut overflowed = false;
+
+        self.offsets.clear();
+        for animator in &self.animators {
+            let matrices = animator.joint_matrices();
+            if count + matrices.len() > MAX_JOINTS {
+                self.offsets.push(None);
+                overflowed = true;
+                continue;
+            }
+
+            ptr::copy_nonoverlapping(matrices.as_ptr(), mapped.add(count), matrices.len());
+            self.offsets.push(Some(count as u32));
+            count += matrices.len();
+        }
+
+        if overflowed {
+            warn!(
+                "More than {} joints this frame, some animators were skipped.",
+                MAX_JOINTS
+            );
+        }
+    }
+
+    // Primeira junta do animador no buffer do frame, None se não coube
+    pub fn offset(&self, id: AnimatorId) -> Option<u32> {
+        self.offsets.get(id.0).copied().flatten()
+    }
+
+    pub fn set(&self, slot: usize) -> vk::DescriptorSet {
+        self.sets[slot]
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for buffer in self.buffers.drain(..) {
+            device.unmap_memory(buffer.memory);
+            leaks::destroy_buffer(device, buffer.buffer);
+            allocator::free(device, buffer.memory);
+        }
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+        self.offsets.clear();
+    }
+}
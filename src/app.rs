@@ -2,21 +2,89 @@ use anyhow::{anyhow, Result};
 use vulkanalia::{
     loader::{LibloadingLoader, LIBRARY},
     prelude::v1_0::*,
-    vk::{ExtDebugUtilsExtension, KhrSurfaceExtension},
+    vk::{
+        ExtDebugUtilsExtension, ExtFullScreenExclusiveExtension, Handle,
+        KhrGetPhysicalDeviceProperties2Extension, KhrSurfaceExtension, KhrSwapchainExtension,
+    },
     window as vk_window,
 };
-use winit::window::Window;
+use winit::{event::WindowEvent, window::Window};
 
 use log::*;
-use std::collections::HashSet;
+use nalgebra_glm as glm;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{c_void, CStr, CString};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "audio")]
+use crate::audio::{Audio, EmitterId};
+#[cfg(feature = "profiling")]
+use crate::profiler::GpuProfiler;
+#[cfg(feature = "xr")]
+use crate::xr::XrContext;
 use crate::{
-    error::{self, SuitabilityError},
-    info::{QueueFamilyIndices, SwapchainData, SwapchainSupport},
-    DEVICE_EXTENSIONS, VALIDATION_ENABLED, VALIDATION_LAYER,
+    allocator::{MemoryStats, MemoryWarningCallback, MemoryWatch},
+    animation::{Animator, AnimatorId, Skinning, VertexSkin},
+    assets::{self, AssetHandle, AssetManager, AssetState},
+    camera::{Camera, Projection},
+    capture::{CaptureOutput, FrameRecorder},
+    config::{
+        AppConfig, Buffering, DebugView, GpuSelector, PostProcessing, PresentModePreference,
+        QueueRequest, RenderPath, ValidationFeatures,
+    },
+    decal::DecalRenderer,
+    deferred::DeferredLighting,
+    display::{self, FullscreenMode, WindowedState},
+    error::{self, RendererError, SuitabilityError},
+    exposure::{AutoExposure, AutoExposureSettings},
+    features::{DeviceCapabilities, DeviceRequirements, Feature, PortabilitySubsetFeatures},
+    fog::{FogSettings, VolumetricFog},
+    fxaa::Fxaa,
+    godrays::{GodRaySettings, GodRays},
+    grading::{ColorGrading, Lut},
+    graph::{FrameGraph, FramePass},
+    info::{self, QueueFamilyIndices, SwapchainData, SwapchainSupport},
+    irradiance::{IrradianceGrid, IrradianceGridSettings},
+    latency::PresentTimer,
+    leaks,
+    limiter::FrameLimiter,
+    marker,
+    material::{Material, MaterialId, MaterialParams, MaterialTextureId, Materials},
+    mesh::{MeshData, MeshId, MeshLod, MeshRenderer},
+    multiview::{MultiviewRenderer, View, DEFAULT_IPD},
+    occlusion::OcclusionCulling,
+    outline::{Outline, OutlineSettings},
+    overlay::Overlay,
+    pacing::FramePacer,
+    pass::{AttachmentOps, LoadOp, PostPassData, PostStage, RenderPassData, SCENE_TRANSFER},
+    picking::Picking,
+    pipeline::{self, BlendMode},
+    post::{PostChain, PostEffectId},
+    probe::ReflectionProbe,
+    profiler::zone,
+    report::{self, Report},
+    sampler,
+    scene::{DrawItem, EntityId, Hit, Node, NodeId, RayTest, Scene, SceneDecal, SceneLight},
+    shader::spirv,
+    shadow::{ShadowMap, ShadowSettings},
+    sky::{Sky, SkySettings},
+    sprite::{Camera2D, Sprite, SpriteBatch, SpriteTextureId},
+    stats::FrameStats,
+    streaming::{self, TextureStreamer},
+    terrain::{Terrain, TerrainSettings},
+    text::TextRenderer,
+    tonemap::{ToneMapper, ToneMapping},
+    ui::Ui,
+    uniforms::{FrameDescriptors, FrameUniforms, LightUniforms},
+    upload::{UploadId, UploadQueue, UploadTarget},
+    watcher::FileWatcher,
+    DEVICE_EXTENSIONS, MAX_FRAMES_IN_FLIGHT, PORTABILITY_ENUMERATION_EXTENSION,
+    PORTABILITY_SUBSET_EXTENSION, VALIDATION_LAYER,
 };
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct App {
     // o Entry é próprio do vulkanalia e é quem lida com o carregamento das funções
     entry: Entry,
@@ -27,187 +95,3600 @@ pub struct App {
     data: AppData,
     // Referência lógica ao dispositivo (GPU)
     device: Device,
+    // Frame atual, dentre os MAX_FRAMES_IN_FLIGHT que podem estar na GPU ao mesmo tempo
+    frame: usize,
+    // Ordem dos passes do frame e as barreiras entre eles
+    frame_graph: FrameGraph,
+    // Marcado pelo loop de eventos quando a janela muda de tamanho
+    pub resized: bool,
+    // Gravação de frames (vídeo/sequência de PNGs), se ligada
+    recorder: Option<FrameRecorder>,
+    present_timer: PresentTimer,
+    limiter: FrameLimiter,
+    pacer: FramePacer,
+    // Uploads grandes são espalhados por vários frames
+    uploads: UploadQueue,
+    stats: FrameStats,
+    // Uso de memória por heap, consultado a cada frame, e o aviso de quando passa do limite
+    memory_stats: MemoryStats,
+    memory_watch: MemoryWatch,
+    last_frame_start: Option<Instant>,
+    // Quantas vezes o dispositivo lógico foi recriado depois de ser perdido
+    generation: u64,
+    #[cfg(feature = "profiling")]
+    profiler: Option<GpuProfiler>,
+    // Música e sons posicionais, com a feature audio. None sem dispositivo de saída
+    #[cfg(feature = "audio")]
+    audio: Option<Audio>,
+    // Estatísticas na tela (F1)
+    overlay: Overlay,
+    // Interface de debug montada com `ui`
+    ui: Ui,
+    // Textos pedidos com `draw_text`
+    text: TextRenderer,
+    // Sprites pedidos com `draw_sprite`
+    sprites: SpriteBatch,
+    // Malhas na GPU, desenhadas a partir da cena
+    meshes: MeshRenderer,
+    // Pirâmide Hi-Z e o compute que escolhe quais malhas desenhar indiretamente
+    occlusion: OcclusionCulling,
+    // Buffer de ids pro `pick`, com o `picking` da configuração
+    picking: Option<Picking>,
+    // Contorno da seleção, com o `outline` da configuração
+    outline: Outline,
+    // Cena pros dois olhos num alvo de duas camadas, com o `stereo` da configuração
+    stereo: Option<MultiviewRenderer>,
+    // Views dos olhos pedidas com `set_stereo_views`; sem elas, a câmera com o IPD padrão
+    stereo_views: Option<[View; 2]>,
+    // Headset pelo OpenXR, com o `xr` da configuração
+    #[cfg(feature = "xr")]
+    xr: Option<XrContext>,
+    // Cubo com a cena vista de um ponto, pros reflexos especulares em volta dele
+    reflection_probe: ReflectionProbe,
+    // Sondas com a luz ambiente difusa de cada parte da cena
+    irradiance: IrradianceGrid,
+    // Animadores e as matrizes de junta que as malhas com skinning leem
+    skinning: Skinning,
+    materials: Materials,
+    // Mips grandes das texturas dos materiais, quando o streaming está ligado
+    streamer: Option<TextureStreamer>,
+    // Malhas e texturas carregadas em segundo plano, com `asset_threads` > 0
+    assets: Option<AssetManager>,
+    // Avisa o `assets` do que mudou na pasta de `hot_reload`
+    watcher: Option<FileWatcher>,
+    // Shadow map da luz direcional, renderizado antes do pass principal
+    shadows: ShadowMap,
+    // Resolução da luz sobre o G-buffer, quando o caminho é o deferred
+    deferred: DeferredLighting,
+    // Decals projetados no G-buffer antes da luz, também só no deferred
+    decals: DecalRenderer,
+    // Céu procedural atrás da cena, que também vira a luz ambiente
+    sky: Sky,
+    // Terreno procedural, com tessellation quando o dispositivo tem
+    terrain: Terrain,
+    // Névoa volumétrica entre a cena e as transparentes
+    fog: VolumetricFog,
+    // Raios de sol por cima da cena, depois das transparentes
+    god_rays: GodRays,
+    // Histograma do alvo HDR e a luminância adaptada que o tone mapper usa
+    exposure: AutoExposure,
+    // Efeitos em compute em cima do alvo HDR, antes do tone mapping
+    post_chain: PostChain,
+    // Leva o alvo HDR da cena pra swapchain
+    tone_mapper: ToneMapper,
+    // LUT 3D depois do tone mapping, quando ligado
+    grading: ColorGrading,
+    // Antialiasing no fim da cadeia, quando ligado
+    fxaa: Fxaa,
+    scene: Scene,
+    // Malhas pedidas com `draw_mesh` além das da cena, só pro próximo frame
+    queued_draws: Vec<DrawItem>,
+    // Mesma coisa com as luzes de `add_light`
+    queued_lights: Vec<SceneLight>,
+    // E com os decals de `add_decal`
+    queued_decals: Vec<SceneDecal>,
+    ambient: glm::Vec3,
+    camera: Camera,
+}
+
+// Configura o App antes de criar ele, no mesmo espírito dos builders do vulkanalia
+#[derive(Clone, Debug, Default)]
+pub struct AppBuilder {
+    config: AppConfig,
+}
+
+impl AppBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn requirements(mut self, requirements: DeviceRequirements) -> Self {
+        self.config.requirements = requirements;
+        self
+    }
+
+    // Prioridade (0.0 a 1.0) das filas de gráficos e apresentação
+    pub fn queue_priority(mut self, priority: f32) -> Self {
+        self.config.queues.main_priority = priority.clamp(0.0, 1.0);
+        self
+    }
+
+    // Pede uma fila adicional com as capacidades dadas. Se a família escolhida não tiver
+    // filas sobrando, a fila é compartilhada com a primeira daquela família
+    pub fn extra_queue(mut self, flags: vk::QueueFlags, priority: f32) -> Self {
+        self.config.queues.extra.push(QueueRequest {
+            flags,
+            priority: priority.clamp(0.0, 1.0),
+        });
+        self
+    }
+
+    // Espera o frame anterior chegar na tela antes de começar o próximo. Sem o
+    // VK_KHR_present_wait, espera só a GPU terminar o frame anterior
+    pub fn low_latency(mut self, enabled: bool) -> Self {
+        self.config.low_latency = enabled;
+        self
+    }
+
+    // Saída HDR10 (A2B10G10R10 + ST 2084) quando disponível
+    pub fn hdr(mut self, enabled: bool) -> Self {
+        self.config.hdr = enabled;
+        self
+    }
+
+    // Display P3 ou sRGB estendido quando disponível, com a curva de tom aplicada nas
+    // primárias da saída
+    pub fn wide_gamut(mut self, enabled: bool) -> Self {
+        self.config.wide_gamut = enabled;
+        self
+    }
+
+    // Formatos da swapchain em ordem de preferência; o negociado sai em
+    // `App::surface_format`
+    pub fn surface_formats(mut self, formats: Vec<vk::SurfaceFormatKHR>) -> Self {
+        self.config.surface_formats = formats;
+        self
+    }
+
+    // Índice ou parte do nome; a que bater primeiro é usada mesmo se for integrada
+    pub fn gpu(mut self, gpu: GpuSelector) -> Self {
+        self.config.gpu = Some(gpu);
+        self
+    }
+
+    // Dispositivos CPU (lavapipe, SwiftShader) entram na escolha, depois das GPUs
+    pub fn allow_software(mut self, enabled: bool) -> Self {
+        self.config.allow_software = enabled;
+        self
+    }
+
+    // Liga ou desliga a camada de validação, que por padrão segue VALIDATION_ENABLED
+    pub fn validation_layers(mut self, enabled: bool) -> Self {
+        self.config.validation_layers = enabled;
+        self
+    }
+
+    pub fn present_mode(mut self, present_mode: PresentModePreference) -> Self {
+        self.config.present_mode = present_mode;
+        self
+    }
+
+    // Double buffering diminui a latência; a surface pode exigir mais imagens
+    pub fn buffering(mut self, buffering: Buffering) -> Self {
+        self.config.buffering = buffering;
+        self
+    }
+
+    pub fn validation(mut self, features: ValidationFeatures) -> Self {
+        self.config.validation = features;
+        self
+    }
+
+    // Profundidade invertida (1 perto, 0 longe), ligada por padrão. Troca o compare e o
+    // clear do depth
+    pub fn reverse_z(mut self, enabled: bool) -> Self {
+        self.config.reverse_z = enabled;
+        self
+    }
+
+    pub fn infinite_far_plane(mut self, enabled: bool) -> Self {
+        self.config.infinite_far_plane = enabled;
+        self
+    }
+
+    // Limite de FPS pros modos sem v-sync; None deixa rodar solto
+    pub fn fps_limit(mut self, fps: Option<u32>) -> Self {
+        self.config.fps_limit = fps;
+        self
+    }
+
+    // Presents em intervalos constantes, com VK_GOOGLE_display_timing se tiver
+    pub fn frame_pacing(mut self, enabled: bool) -> Self {
+        self.config.frame_pacing = enabled;
+        self
+    }
+
+    // O loop de eventos fica parado até chegar um evento ou `needs_redraw` pedir
+    pub fn redraw_on_demand(mut self, enabled: bool) -> Self {
+        self.config.redraw_on_demand = enabled;
+        self
+    }
+
+    pub fn fixed_frame_time(mut self, frame_time: Option<Duration>) -> Self {
+        self.config.fixed_frame_time = frame_time;
+        self
+    }
+
+    // Resolução, bias e filtro das sombras da luz direcional
+    pub fn shadows(mut self, settings: ShadowSettings) -> Self {
+        self.config.shadows = settings;
+        self
+    }
+
+    pub fn render_path(mut self, path: RenderPath) -> Self {
+        self.config.render_path = path;
+        self
+    }
+
+    pub fn depth_prepass(mut self, enabled: bool) -> Self {
+        self.config.depth_prepass = enabled;
+        self
+    }
+
+    pub fn tone_mapping(mut self, tone_mapping: ToneMapping) -> Self {
+        self.config.tone_mapping = tone_mapping;
+        self
+    }
+
+    pub fn post_processing(mut self, post_processing: PostProcessing) -> Self {
+        self.config.post_processing = post_processing;
+        self
+    }
+
+    // LUT (.cube ou PNG em faixa) do color grading, que já fica ligado
+    pub fn color_grading_lut<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.color_grading_lut = Some(path.into());
+        self.config.post_processing.color_grading = true;
+        self
+    }
+
+    pub fn auto_exposure(mut self, settings: AutoExposureSettings) -> Self {
+        self.config.auto_exposure = settings;
+        self
+    }
+
+    pub fn sky(mut self, settings: SkySettings) -> Self {
+        self.config.sky = settings;
+        self
+    }
+
+    pub fn terrain(mut self, settings: TerrainSettings) -> Self {
+        self.config.terrain = settings;
+        self
+    }
+
+    pub fn fog(mut self, settings: FogSettings) -> Self {
+        self.config.fog = settings;
+        self
+    }
+
+    pub fn god_rays(mut self, settings: GodRaySettings) -> Self {
+        self.config.god_rays = settings;
+        self
+    }
+
+    pub fn occlusion_culling(mut self, enabled: bool) -> Self {
+        self.config.occlusion_culling = enabled;
+        self
+    }
+
+    pub fn picking(mut self, enabled: bool) -> Self {
+        self.config.picking = enabled;
+        self
+    }
+
+    // Contorno dos objetos selecionados com `App::set_selection`
+    pub fn outline(mut self, enabled: bool) -> Self {
+        self.config.outline = enabled;
+        self
+    }
+
+    pub fn stereo(mut self, enabled: bool) -> Self {
+        self.config.stereo = enabled;
+        self
+    }
+
+    pub fn reflection_probes(mut self, enabled: bool) -> Self {
+        self.config.reflection_probes = enabled;
+        self
+    }
+
+    pub fn irradiance_probes(mut self, enabled: bool) -> Self {
+        self.config.irradiance_probes = enabled;
+        self
+    }
+
+    pub fn xr(mut self, enabled: bool) -> Self {
+        self.config.xr = enabled;
+        self
+    }
+
+    // Quantos bytes no máximo são enviados pra GPU por frame
+    pub fn upload_budget(mut self, bytes: u64) -> Self {
+        self.config.upload_budget = bytes;
+        self
+    }
+
+    pub fn max_anisotropy(mut self, value: f32) -> Self {
+        self.config.max_anisotropy = value;
+        self
+    }
+
+    pub fn texture_streaming(mut self, enabled: bool) -> Self {
+        self.config.texture_streaming = enabled;
+        self
+    }
+
+    pub fn asset_threads(mut self, threads: usize) -> Self {
+        self.config.asset_threads = threads;
+        self
+    }
+
+    // Música de fundo em loop, tocada assim que o App é criado (feature audio)
+    pub fn music<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.music = Some(path.into());
+        self
+    }
+
+    // Liga as threads de assets também (uma, se nenhuma foi pedida)
+    pub fn hot_reload<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.config.hot_reload = Some(dir.into());
+        self.config.asset_threads = self.config.asset_threads.max(1);
+        self
+    }
+
+    pub unsafe fn build(self, window: &Window) -> Result<App> {
+        App::create_with_config(window, self.config)
+    }
 }
 
 impl App {
     pub unsafe fn create(window: &Window) -> Result<Self> {
+        AppBuilder::new().build(window)
+    }
+
+    pub unsafe fn create_with_config(window: &Window, config: AppConfig) -> Result<Self> {
         // Cria o Loader, que vai carregar o ponteiro das funçẽos do Vulkan
         let loader = LibloadingLoader::new(LIBRARY)?;
         // Entry realmente carrega os erros e tal
-        let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
+        let entry =
+            Entry::new(loader).map_err(|b| RendererError::InstanceCreation(b.to_string()))?;
+
+        let mut data = AppData {
+            config,
+            ..Default::default()
+        };
+
+        // O runtime de VR diz o que a instância e o dispositivo precisam ter
+        #[cfg(feature = "xr")]
+        let mut xr = App::create_xr(&mut data);
+        #[cfg(not(feature = "xr"))]
+        if data.config.xr {
+            warn!("VR needs the xr feature, ignoring.");
+        }
+
+        // Instância do Vulkan, necessário pra usar ele
+        let instance = App::create_instance(window, &entry, &mut data)?;
+        data.surface = vk_window::create_surface(&instance, window)?;
+        #[cfg(feature = "xr")]
+        if let Some(xr) = &xr {
+            data.xr_physical_device = Some(xr.physical_device(&instance)?);
+        }
+        App::pick_physical_device(&instance, &mut data)?;
+
+        let device = App::create_logical_device(&instance, &mut data)?;
+        #[cfg(feature = "xr")]
+        if let Some(xr) = &mut xr {
+            App::create_xr_session(&instance, &device, &data, xr)?;
+        }
+        data.frame_descriptors = FrameDescriptors::create(&instance, &device, &data)?;
+
+        let mut ops = AttachmentOps::default();
+        if data.config.reverse_z {
+            ops.depth = LoadOp::Clear(0.0);
+        }
+
+        data.swapchain = SwapchainData::create_swapchain(window, &instance, &device, &mut data)?;
+        data.render_pass = RenderPassData::create(&instance, &device, &data, ops)?;
+        data.post_pass = PostPassData::create(&instance, &device, &data)?;
+        App::create_pipeline(&device, &mut data)?;
+        let overlay = Overlay::create(&device, &data)?;
+        let ui = Ui::create(&device, &data, window)?;
+        let sprites = SpriteBatch::create(&device, &data)?;
+        App::create_command_pool(&instance, &device, &mut data)?;
+        App::create_command_buffers(&device, &mut data)?;
+        App::create_sync_objects(&device, &mut data)?;
+        #[cfg(feature = "profiling")]
+        let profiler = App::create_profiler(&instance, &device, &data);
+        #[cfg(feature = "audio")]
+        let audio = App::create_audio(&data);
+        #[cfg(not(feature = "audio"))]
+        if data.config.music.is_some() {
+            warn!("Music needs the audio feature, ignoring.");
+        }
+
+        let mut uploads = UploadQueue::new(data.config.upload_budget);
+        let text = TextRenderer::create(&instance, &device, &data, &mut uploads)?;
+        let materials = Materials::create(&instance, &device, &data, &mut uploads)?;
+        let streamer = App::create_streamer(&instance, &device, &data)?;
+        let assets = match data.config.asset_threads {
+            0 => None,
+            _ => Some(AssetManager::create(&instance, &device, &data)?),
+        };
+        let watcher = match (&data.config.hot_reload, &assets) {
+            (Some(dir), Some(_)) => Some(FileWatcher::new(dir)?),
+            (Some(_), None) => {
+                warn!("Hot reload needs asset_threads > 0, disabling it.");
+                None
+            }
+            _ => None,
+        };
+        let skinning = Skinning::create(&instance, &device, &data)?;
+        let occlusion = OcclusionCulling::create(&instance, &device, &data)?;
+        let meshes = MeshRenderer::create(&device, &data, &materials, &skinning, &occlusion)?;
+        let deferred = DeferredLighting::create(&device, &data)?;
+        let decals = DecalRenderer::create(&device, &data, &materials)?;
+        let sky = Sky::create(&device, &data, data.config.sky)?;
+        let outline = Outline::create(&device, &data)?;
+        let terrain = Terrain::create(&device, &data, data.config.terrain)?;
+        let fog = VolumetricFog::create(&instance, &device, &data, data.config.fog)?;
+        let god_rays = GodRays::create(&instance, &device, &data, data.config.god_rays)?;
+        let exposure = AutoExposure::create(&instance, &device, &data, data.config.auto_exposure)?;
+        let post_chain = PostChain::create(&instance, &device, &data)?;
+        let tone_mapper = ToneMapper::create(&device, &data, &exposure, data.config.tone_mapping)?;
+        let lut = match &data.config.color_grading_lut {
+            Some(path) => Lut::load(path)?,
+            None => Lut::default(),
+        };
+        let grading = ColorGrading::create(&instance, &device, &data, &mut uploads, lut)?;
+        let fxaa = Fxaa::create(&device, &data)?;
+        let shadows = ShadowMap::create(&instance, &device, &data, data.config.shadows)?;
+        let picking = App::create_picking(&instance, &device, &data)?;
+        let stereo = App::create_stereo(&instance, &device, &data)?;
+        let reflection_probe = ReflectionProbe::create(&instance, &device, &data)?;
+        let irradiance = IrradianceGrid::create(&instance, &device, &data)?;
+        data.frame_descriptors.write_shadow_map(
+            &device,
+            shadows.view(),
+            &shadows.point_views(),
+            shadows.sampler,
+        );
+        data.frame_descriptors.write_reflection_probe(
+            &device,
+            reflection_probe.view(),
+            reflection_probe.sampler,
+        );
+        data.frame_descriptors
+            .write_irradiance_probes(&device, irradiance.buffer());
+        let present_timer =
+            PresentTimer::new(data.capabilities.present_wait, data.config.low_latency);
+        let limiter = FrameLimiter::new(data.config.fps_limit);
+        let mut pacer = FramePacer::new(
+            data.capabilities.display_timing,
+            data.config.frame_pacing,
+            data.config.fps_limit,
+        );
+        pacer.reset(&device, data.swapchain.chain)?;
+        let frame_graph = FrameGraph::create(&instance, &device, &data)?;
+
+        let mut camera = Camera::default();
+        camera.position = glm::vec3(0.0, 0.0, 2.0);
+        camera.reverse_z = data.config.reverse_z;
+        if data.config.infinite_far_plane {
+            if let Projection::Perspective { far, .. } = &mut camera.projection {
+                *far = None;
+            }
+        }
+        camera.set_extent(data.swapchain.logical_extent());
+
+        Ok(Self {
+            entry,
+            instance,
+            data,
+            device,
+            frame: 0,
+            frame_graph,
+            resized: false,
+            recorder: None,
+            present_timer,
+            limiter,
+            pacer,
+            uploads,
+            stats: FrameStats::default(),
+            memory_stats: MemoryStats::default(),
+            memory_watch: MemoryWatch::default(),
+            last_frame_start: None,
+            generation: 0,
+            #[cfg(feature = "profiling")]
+            profiler,
+            #[cfg(feature = "audio")]
+            audio,
+            overlay,
+            ui,
+            text,
+            sprites,
+            meshes,
+            occlusion,
+            picking,
+            outline,
+            stereo,
+            stereo_views: None,
+            #[cfg(feature = "xr")]
+            xr,
+            reflection_probe,
+            irradiance,
+            skinning,
+            materials,
+            streamer,
+            assets,
+            watcher,
+            shadows,
+            deferred,
+            decals,
+            sky,
+            terrain,
+            fog,
+            god_rays,
+            exposure,
+            post_chain,
+            tone_mapper,
+            grading,
+            fxaa,
+            scene: Scene::new(),
+            queued_draws: vec![],
+            queued_lights: vec![],
+            queued_decals: vec![],
+            ambient: glm::vec3(0.03, 0.03, 0.03),
+            camera,
+        })
+    }
+
+    unsafe fn create_streamer(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<Option<TextureStreamer>> {
+        if !data.config.texture_streaming {
+            return Ok(None);
+        }
+
+        Ok(Some(TextureStreamer::create(instance, device, data)?))
+    }
+
+    unsafe fn create_picking(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<Option<Picking>> {
+        if !data.config.picking {
+            return Ok(None);
+        }
+
+        Ok(Some(Picking::create(instance, device, data)?))
+    }
+
+    // Do tamanho da swapchain, cada olho com a imagem inteira
+    unsafe fn create_stereo(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<Option<MultiviewRenderer>> {
+        if !data.config.stereo {
+            return Ok(None);
+        }
+        if !data.capabilities.multiview {
+            warn!("Stereo rendering needs VK_KHR_multiview, disabling it.");
+            return Ok(None);
+        }
+
+        let extent = data.xr_extent.unwrap_or(data.swapchain.extent);
+        Ok(Some(MultiviewRenderer::create(
+            instance, device, data, 2, extent,
+        )?))
+    }
+
+    // Sem runtime ou sem headset o app segue só na janela
+    #[cfg(feature = "xr")]
+    fn create_xr(data: &mut AppData) -> Option<XrContext> {
+        if !data.config.xr {
+            return None;
+        }
+
+        let result = XrContext::new().and_then(|xr| {
+            data.xr_instance_extensions = xr.instance_extensions()?;
+            data.xr_device_extensions = xr.device_extensions()?;
+            Ok(xr)
+        });
+        match result {
+            Ok(xr) => {
+                data.xr_extent = Some(xr.extent());
+                // O headset recebe o que o pass estéreo desenha
+                data.config.stereo = true;
+                Some(xr)
+            }
+            Err(e) => {
+                warn!("VR disabled: {:#}", e);
+                data.xr_instance_extensions.clear();
+                data.xr_device_extensions.clear();
+                None
+            }
+        }
+    }
+
+    // A sessão usa a fila de gráficos, a mesma do submit do frame
+    #[cfg(feature = "xr")]
+    unsafe fn create_xr_session(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        xr: &mut XrContext,
+    ) -> Result<()> {
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+        xr.create_session(instance, device, data, indices.graphics)
+    }
+
+    #[cfg(feature = "profiling")]
+    unsafe fn create_profiler(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Option<GpuProfiler> {
+        match GpuProfiler::create(instance, device, data) {
+            Ok(profiler) => Some(profiler),
+            Err(e) => {
+                warn!("GPU profiling disabled: {}", e);
+                None
+            }
+        }
+    }
+
+    // Sem saída de áudio o app segue mudo
+    #[cfg(feature = "audio")]
+    fn create_audio(data: &AppData) -> Option<Audio> {
+        let mut audio = match Audio::new() {
+            Ok(audio) => audio,
+            Err(e) => {
+                warn!("Audio disabled: {}", e);
+                return None;
+            }
+        };
+
+        if let Some(path) = &data.config.music {
+            if let Err(e) = audio.play_music(path, true) {
+                warn!("Failed to play music: {}", e);
+            }
+        }
+        Some(audio)
+    }
+
+    unsafe fn create_logical_device(instance: &Instance, data: &mut AppData) -> Result<Device> {
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+        let families = instance.get_physical_device_queue_family_properties(data.physical_device);
+        let main_priority = data.config.queues.main_priority;
+
+        // Prioridades das filas que vamos criar em cada família. A posição no vetor é o
+        // índice da fila dentro da família
+        let mut priorities: HashMap<u32, Vec<f32>> = HashMap::new();
+        priorities.insert(indices.graphics, vec![main_priority]);
+        priorities
+            .entry(indices.present)
+            .or_insert_with(|| vec![main_priority]);
+        for family in [indices.compute, indices.transfer].into_iter().flatten() {
+            priorities
+                .entry(family)
+                .or_insert_with(|| vec![main_priority]);
+        }
+        info!(
+            "Queue families: graphics {}, present {}, compute {:?}, transfer {:?}.",
+            indices.graphics, indices.present, indices.compute, indices.transfer
+        );
+
+        // O streaming de texturas e os assets pedem a sua fila junto com as do app, por
+        // último
+        let mut requests = data.config.queues.extra.clone();
+        if data.config.texture_streaming || data.config.asset_threads > 0 {
+            requests.push(QueueRequest {
+                flags: vk::QueueFlags::TRANSFER,
+                priority: streaming::QUEUE_PRIORITY,
+            });
+        }
+
+        let mut extra = vec![];
+        for (request_index, request) in requests.iter().enumerate() {
+            let family = match info::find_queue_family(&families, request.flags) {
+                Some(family) => family,
+                None => {
+                    warn!(
+                        "No queue family supports {:?}, skipping queue.",
+                        request.flags
+                    );
+                    continue;
+                }
+            };
+
+            let list = priorities.entry(family).or_insert_with(Vec::new);
+            let index = if (list.len() as u32) < families[family as usize].queue_count {
+                list.push(request.priority);
+                list.len() - 1
+            } else {
+                warn!(
+                    "Queue family {} has no queues left, sharing queue 0 for {:?}.",
+                    family, request.flags
+                );
+                0
+            };
+
+            extra.push((request_index, family, index as u32));
+        }
+
+        let queue_info = priorities
+            .iter()
+            .map(|(family, priorities)| {
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(*family)
+                    .queue_priorities(priorities)
+            })
+            .collect::<Vec<_>>();
+
+        // Layers específicas ao dispositivo.
+        // Em Vulkan moderno  as layers do escopo da instância, isso é pra
+        // ser compatível com versões anteriores que permitiam extensões
+        // especificas para cada dispositivo virtual
+        let layers = if data.config.validation_layers {
+            vec![VALIDATION_LAYER.as_ptr()]
+        } else {
+            vec![]
+        };
+
+        // Recursos do dispositivo: os obrigatórios (verificados no check_physical_device())
+        // mais os opcionais que esse dispositivo tem
+        let supported = instance.get_physical_device_features(data.physical_device);
+        let mut requirements = data.config.requirements.clone();
+        // O terreno usa tessellation quando tem, e pode ser ligado a qualquer momento
+        if !requirements.optional.contains(&Feature::TessellationShader) {
+            requirements.optional.push(Feature::TessellationShader);
+        }
+        // Pro wireframe de debug
+        if !requirements.optional.contains(&Feature::FillModeNonSolid) {
+            requirements.optional.push(Feature::FillModeNonSolid);
+        }
+        // Filtro anisotrópico nas texturas dos materiais
+        if data.config.max_anisotropy > 1.0
+            && !requirements.optional.contains(&Feature::SamplerAnisotropy)
+        {
+            requirements.optional.push(Feature::SamplerAnisotropy);
+        }
+        // Um desenho indireto por lote, cada instância achando a matriz pelo firstInstance
+        if data.config.occlusion_culling {
+            for feature in [
+                Feature::MultiDrawIndirect,
+                Feature::DrawIndirectFirstInstance,
+            ] {
+                if !requirements.optional.contains(&feature) {
+                    requirements.optional.push(feature);
+                }
+            }
+        }
+        data.capabilities = requirements.negotiate(&supported);
+        let anisotropy_limit = if data.capabilities.has(Feature::SamplerAnisotropy) {
+            instance
+                .get_physical_device_properties(data.physical_device)
+                .limits
+                .max_sampler_anisotropy
+        } else {
+            1.0
+        };
+        sampler::configure(anisotropy_limit, data.config.max_anisotropy);
+        let features = data.capabilities.vk_features();
+        info!("Enabled device features: {:?}", data.capabilities.enabled);
+
+        let mut extensions = DEVICE_EXTENSIONS
+            .iter()
+            .map(|n| n.as_ptr())
+            .collect::<Vec<_>>();
+
+        // Implementações que não são 100% conformes (MoltenVK) expõem essa extensão, e
+        // a especificação exige que ela seja habilitada quando presente
+        data.capabilities.portability_subset = App::has_device_extension(
+            instance,
+            data.physical_device,
+            &PORTABILITY_SUBSET_EXTENSION,
+        )?;
+        // Com a extensão, o que ela restringe só vale se for ligado junto no dispositivo
+        let mut portability_features = PortabilitySubsetFeatures::default();
+        if data.capabilities.portability_subset {
+            info!("Enabling portability subset.");
+            extensions.push(PORTABILITY_SUBSET_EXTENSION.as_ptr());
+            if data.physical_device_properties2 {
+                let mut features = vk::PhysicalDeviceFeatures2::default();
+                features.next = &mut portability_features as *mut _ as *mut c_void;
+                instance.get_physical_device_features2_khr(data.physical_device, &mut features);
+                portability_features.next = std::ptr::null_mut();
+            }
+            data.capabilities.portability = portability_features.subset();
+            info!(
+                "Portability subset allows: {:?}",
+                data.capabilities.portability
+            );
+        }
+        pipeline::configure(data.capabilities.portability);
+
+        // Horário pedido e real de cada present, pro frame pacing
+        data.capabilities.display_timing = App::has_device_extension(
+            instance,
+            data.physical_device,
+            &vk::GOOGLE_DISPLAY_TIMING_EXTENSION.name,
+        )?;
+        if data.capabilities.display_timing {
+            info!("Enabling display timing.");
+            extensions.push(vk::GOOGLE_DISPLAY_TIMING_EXTENSION.name.as_ptr());
+        }
+
+        // Medição de latência real (até a imagem aparecer na tela)
+        data.capabilities.present_wait = App::supports_present_wait(instance, data)?;
+        let mut present_id_features = vk::PhysicalDevicePresentIdFeaturesKHR::builder()
+            .present_id(data.capabilities.present_wait);
+        let mut present_wait_features = vk::PhysicalDevicePresentWaitFeaturesKHR::builder()
+            .present_wait(data.capabilities.present_wait);
+        if data.capabilities.present_wait {
+            info!("Enabling present id/wait.");
+            extensions.push(vk::KHR_PRESENT_ID_EXTENSION.name.as_ptr());
+            extensions.push(vk::KHR_PRESENT_WAIT_EXTENSION.name.as_ptr());
+        }
+
+        data.capabilities.full_screen_exclusive = data.surface_capabilities2
+            && App::has_device_extension(
+                instance,
+                data.physical_device,
+                &vk::EXT_FULL_SCREEN_EXCLUSIVE_EXTENSION.name,
+            )?;
+        if data.capabilities.full_screen_exclusive {
+            extensions.push(vk::EXT_FULL_SCREEN_EXCLUSIVE_EXTENSION.name.as_ptr());
+        }
+
+        data.capabilities.memory_budget = data.physical_device_properties2
+            && App::has_device_extension(
+                instance,
+                data.physical_device,
+                &vk::EXT_MEMORY_BUDGET_EXTENSION.name,
+            )?;
+        if data.capabilities.memory_budget {
+            extensions.push(vk::EXT_MEMORY_BUDGET_EXTENSION.name.as_ptr());
+        }
+
+        data.capabilities.draw_indirect_count = data.config.occlusion_culling
+            && App::has_device_extension(
+                instance,
+                data.physical_device,
+                &vk::KHR_DRAW_INDIRECT_COUNT_EXTENSION.name,
+            )?;
+        if data.capabilities.draw_indirect_count {
+            extensions.push(vk::KHR_DRAW_INDIRECT_COUNT_EXTENSION.name.as_ptr());
+        }
+
+        // Descritores dos materiais gravados direto no command buffer, sem sets no pool
+        data.capabilities.push_descriptor = data.physical_device_properties2
+            && App::has_device_extension(
+                instance,
+                data.physical_device,
+                &vk::KHR_PUSH_DESCRIPTOR_EXTENSION.name,
+            )?;
+        if data.capabilities.push_descriptor {
+            info!("Enabling push descriptors.");
+            extensions.push(vk::KHR_PUSH_DESCRIPTOR_EXTENSION.name.as_ptr());
+        }
+
+        // Sem push descriptors, os sets dos materiais são reescritos com um template
+        data.capabilities.descriptor_update_template = !data.capabilities.push_descriptor
+            && App::has_device_extension(
+                instance,
+                data.physical_device,
+                &vk::KHR_DESCRIPTOR_UPDATE_TEMPLATE_EXTENSION.name,
+            )?;
+        if data.capabilities.descriptor_update_template {
+            extensions.push(vk::KHR_DESCRIPTOR_UPDATE_TEMPLATE_EXTENSION.name.as_ptr());
+        }
+
+        // O que o runtime de VR pede e ainda não está na lista
+        for name in &data.xr_device_extensions {
+            if !extensions
+                .iter()
+                .any(|e| CStr::from_ptr(*e) == name.as_c_str())
+            {
+                extensions.push(name.as_ptr());
+            }
+        }
+
+        // Várias views num pass só, pro caminho estéreo e as faces das sondas
+        let config = &data.config;
+        data.capabilities.multiview =
+            (config.stereo || config.reflection_probes || config.irradiance_probes)
+                && App::supports_multiview(instance, data)?;
+        let mut multiview_features =
+            vk::PhysicalDeviceMultiviewFeatures::builder().multiview(data.capabilities.multiview);
+        if data.capabilities.multiview {
+            info!("Enabling multiview.");
+            extensions.push(vk::KHR_MULTIVIEW_EXTENSION.name.as_ptr());
+        }
+
+        let mut info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(&queue_info)
+            .enabled_layer_names(&layers)
+            .enabled_extension_names(&extensions)
+            .enabled_features(&features);
+
+        if data.capabilities.present_wait {
+            info = info
+                .push_next(&mut present_id_features)
+                .push_next(&mut present_wait_features);
+        }
+        if data.capabilities.multiview {
+            info = info.push_next(&mut multiview_features);
+        }
+        if data.capabilities.portability_subset {
+            portability_features.next = info.next as *mut c_void;
+            info.next = &portability_features as *const _ as *const c_void;
+        }
+
+        let device = instance
+            .create_device(data.physical_device, &info, None)
+            .map_err(|e| RendererError::DeviceCreation(e.to_string()))?;
+
+        data.present_queue = device.get_device_queue(indices.present, 0);
+        data.graphics_queue = device.get_device_queue(indices.graphics, 0);
+        data.compute_queue = ExtraQueue {
+            family: indices.compute_family(),
+            queue: device.get_device_queue(indices.compute_family(), 0),
+        };
+        data.transfer_queue = ExtraQueue {
+            family: indices.transfer_family(),
+            queue: device.get_device_queue(indices.transfer_family(), 0),
+        };
+        let mut extra_queues = extra
+            .iter()
+            .map(|(request_index, family, index)| {
+                let queue = ExtraQueue {
+                    family: *family,
+                    queue: device.get_device_queue(*family, *index),
+                };
+                (*request_index, queue)
+            })
+            .collect::<Vec<_>>();
+        let transfer_index = data.config.queues.extra.len();
+        data.streaming_queue = extra_queues
+            .iter()
+            .find(|(i, _)| *i == transfer_index)
+            .map(|(_, queue)| *queue);
+        data.transfer_families = match data.streaming_queue {
+            Some(transfer) if transfer.family != indices.graphics => {
+                vec![indices.graphics, transfer.family]
+            }
+            _ => vec![],
+        };
+        extra_queues.retain(|(i, _)| *i != transfer_index);
+        data.extra_queues = extra_queues.into_iter().map(|(_, queue)| queue).collect();
+
+        Ok(device)
+    }
+
+    unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> Result<()> {
+        // Com VR, o dispositivo ligado no headset e nenhum outro
+        if let Some(physical_device) = data.xr_physical_device {
+            App::check_physical_device(instance, data, physical_device, true)?;
+            let properties = instance.get_physical_device_properties(physical_device);
+            info!(
+                "Selected physical device ('{}') for VR.",
+                properties.device_name
+            );
+            data.physical_device = physical_device;
+            return Ok(());
+        }
+
+        // Dedicadas primeiro, software por último; empate fica na ordem do driver
+        let mut devices = instance
+            .enumerate_physical_devices()?
+            .into_iter()
+            .enumerate()
+            .collect::<Vec<_>>();
+        devices.sort_by_key(|(_, physical_device)| {
+            match instance
+                .get_physical_device_properties(*physical_device)
+                .device_type
+            {
+                vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+                vk::PhysicalDeviceType::CPU => 2,
+                _ => 1,
+            }
+        });
+
+        if App::select_physical_device(instance, data, &devices, false)? {
+            return Ok(());
+        }
+
+        match &data.config.gpu {
+            Some(gpu) => Err(anyhow!("No suitable physical device matches {:?}.", gpu)),
+            None => {
+                // Melhor rodar devagar do que não rodar
+                warn!("No discrete GPU qualifies, trying the other devices.");
+                if App::select_physical_device(instance, data, &devices, true)? {
+                    return Ok(());
+                }
+                Err(anyhow!(RendererError::NoSuitableDevice))
+            }
+        }
+    }
+
+    // Com `fallback`, aceita qualquer tipo de dispositivo
+    unsafe fn select_physical_device(
+        instance: &Instance,
+        data: &mut AppData,
+        devices: &[(usize, vk::PhysicalDevice)],
+        fallback: bool,
+    ) -> Result<bool> {
+        for (index, physical_device) in devices.iter().copied() {
+            let properties = instance.get_physical_device_properties(physical_device);
+
+            let name = properties.device_name.to_string();
+            if let Some(gpu) = &data.config.gpu {
+                if !gpu.matches(index, &name) {
+                    debug!("Skipping physical device {} ('{}'), not requested.", index, name);
+                    continue;
+                }
+            }
+
+            if let Err(error) =
+                App::check_physical_device(instance, data, physical_device, fallback)
+            {
+                warn!(
+                    "Skipping phyisical device ('{}'): {}",
+                    properties.device_name, error
+                );
+            } else {
+                info!("Selected physical device ('{}').", properties.device_name);
+                if properties.device_type == vk::PhysicalDeviceType::CPU {
+                    warn!("'{}' is a software rasterizer, expect low frame rates.", name);
+                }
+                data.physical_device = physical_device;
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    unsafe fn check_physical_device(
+        instance: &Instance,
+        data: &mut AppData,
+        physical_device: vk::PhysicalDevice,
+        fallback: bool,
+    ) -> Result<()> {
+        let properties = instance.get_physical_device_properties(physical_device);
+        // GPUs da Apple (via MoltenVK) são integradas, mas é tudo que temos nelas
+        let portability =
+            App::has_device_extension(instance, physical_device, &PORTABILITY_SUBSET_EXTENSION)?;
+        let chosen = data.config.gpu.is_some() || fallback;
+        match properties.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => {}
+            vk::PhysicalDeviceType::CPU if !chosen && !data.config.allow_software => {
+                return Err(anyhow!(SuitabilityError(
+                    "Software rasterizers need --allow-software"
+                )));
+            }
+            vk::PhysicalDeviceType::CPU => {}
+            _ if !chosen && !portability => {
+                return Err(anyhow!(SuitabilityError("Only discrete GPUs supported")));
+            }
+            _ => {}
+        }
+
+        let features = instance.get_physical_device_features(physical_device);
+        if let Some(feature) = data.config.requirements.missing(&features) {
+            debug!("Missing required feature {:?}.", feature);
+            return Err(anyhow!(SuitabilityError("required device feature")));
+        }
+
+        QueueFamilyIndices::get(instance, data, physical_device)?;
+
+        SwapchainData::check_physical_device_extensions(instance, physical_device)?;
+
+        let support = SwapchainSupport::get(instance, data, physical_device)?;
+        if support.formats.is_empty() || support.present_modes.is_empty() {
+            return Err(anyhow!(SuitabilityError("Insuficient swapchain support")));
+        }
+
+        Ok(())
+    }
+
+    unsafe fn has_device_extension(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        extension: &vk::ExtensionName,
+    ) -> Result<bool> {
+        Ok(instance
+            .enumerate_device_extension_properties(physical_device, None)?
+            .iter()
+            .any(|e| e.extension_name == *extension))
+    }
+
+    unsafe fn supports_present_wait(instance: &Instance, data: &AppData) -> Result<bool> {
+        let physical_device = data.physical_device;
+        if !data.physical_device_properties2
+            || !App::has_device_extension(
+                instance,
+                physical_device,
+                &vk::KHR_PRESENT_ID_EXTENSION.name,
+            )?
+            || !App::has_device_extension(
+                instance,
+                physical_device,
+                &vk::KHR_PRESENT_WAIT_EXTENSION.name,
+            )?
+        {
+            return Ok(false);
+        }
+
+        let mut present_id = vk::PhysicalDevicePresentIdFeaturesKHR::default();
+        let mut present_wait = vk::PhysicalDevicePresentWaitFeaturesKHR::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut present_id)
+            .push_next(&mut present_wait);
+        instance.get_physical_device_features2_khr(physical_device, &mut features);
+
+        Ok(present_id.present_id == vk::TRUE && present_wait.present_wait == vk::TRUE)
+    }
+
+    unsafe fn supports_multiview(instance: &Instance, data: &AppData) -> Result<bool> {
+        let physical_device = data.physical_device;
+        if !data.physical_device_properties2
+            || !App::has_device_extension(
+                instance,
+                physical_device,
+                &vk::KHR_MULTIVIEW_EXTENSION.name,
+            )?
+        {
+            return Ok(false);
+        }
+
+        let mut multiview = vk::PhysicalDeviceMultiviewFeatures::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut multiview);
+        instance.get_physical_device_features2_khr(physical_device, &mut features);
+
+        Ok(multiview.multiview == vk::TRUE)
+    }
+
+    pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+        let vertex_shader = spirv!("basic.vert");
+        let fragment_shader = spirv!("basic.frag");
+
+        let vertex_shader_module = App::create_shader_module(device, &vertex_shader[..])?;
+        let fragment_shader_module = App::create_shader_module(device, &fragment_shader[..])?;
+
+        let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertex_shader_module)
+            .name(b"main\0");
+
+        let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragment_shader_module)
+            // ```specialization_info``` define constantes da shader. O benefício dessas constantes
+            // é a eliminação de IFs contendo elas
+            // .specialization_info(specialization_info)
+            .name(b"main\0");
+
+        // Os vértices ainda vêm direto da shader, então não tem nada pra descrever aqui
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .primitive_restart_enable(false);
+
+        let extent = data.swapchain.extent;
+        let viewport = vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+
+        let scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D { x: 0, y: 0 })
+            .extent(extent);
+
+        let viewports = &[viewport];
+        let scissors = &[scissor];
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(viewports)
+            .scissors(scissors);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(vk::CullModeFlags::BACK)
+            .front_face(vk::FrontFace::CLOCKWISE)
+            .depth_bias_enable(false);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .sample_shading_enable(false)
+            .rasterization_samples(vk::SampleCountFlags::_1);
+
+        let depth_compare_op = if data.config.reverse_z {
+            vk::CompareOp::GREATER
+        } else {
+            vk::CompareOp::LESS
+        };
+
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(true)
+            .depth_write_enable(true)
+            .depth_compare_op(depth_compare_op)
+            .depth_bounds_test_enable(false)
+            .stencil_test_enable(false);
+
+        let attachment = vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::all())
+            .blend_enable(false);
+
+        let attachments = &[attachment];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .logic_op(vk::LogicOp::COPY)
+            .attachments(attachments)
+            .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+        // Câmera e projeção vêm do uniform buffer do frame; a matriz do modelo vai por
+        // push constant
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(size_of::<glm::Mat4>() as u32);
+
+        // Função de transferência da saída (sRGB, PQ...)
+        let transfer_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(size_of::<glm::Mat4>() as u32)
+            .size(size_of::<u32>() as u32);
+
+        let set_layouts = &[data.frame_descriptors.layout];
+        let push_constant_ranges = &[push_constant_range, transfer_range];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+        data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+        let stages = &[vert_stage, frag_stage];
+        let info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend_state)
+            .layout(data.pipeline_layout)
+            .render_pass(data.render_pass.pass)
+            .subpass(data.render_pass.forward_subpass());
+
+        data.pipeline = device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0[0];
+        leaks::created(data.pipeline);
+
+        device.destroy_shader_module(vertex_shader_module, None);
+        device.destroy_shader_module(fragment_shader_module, None);
+
+        Ok(())
+    }
+
+    unsafe fn create_command_pool(
+        instance: &Instance,
+        device: &Device,
+        data: &mut AppData,
+    ) -> Result<()> {
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+        // Regravamos os command buffers todo frame, então eles precisam poder ser resetados
+        let info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(indices.graphics);
+
+        data.command_pool = device.create_command_pool(&info, None)?;
+
+        Ok(())
+    }
+
+    unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> Result<()> {
+        let info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(data.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(data.swapchain.images.len() as u32);
+
+        data.command_buffers = device.allocate_command_buffers(&info)?;
+
+        Ok(())
+    }
+
+    unsafe fn create_sync_objects(device: &Device, data: &mut AppData) -> Result<()> {
+        let semaphore_info = vk::SemaphoreCreateInfo::builder();
+        // Começa sinalizada pra que o primeiro frame não fique esperando pra sempre
+        let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            data.image_available_semaphores
+                .push(device.create_semaphore(&semaphore_info, None)?);
+            data.render_finished_semaphores
+                .push(device.create_semaphore(&semaphore_info, None)?);
+            data.in_flight_fences
+                .push(device.create_fence(&fence_info, None)?);
+        }
+
+        data.images_in_flight = data
+            .swapchain
+            .images
+            .iter()
+            .map(|_| vk::Fence::null())
+            .collect();
+
+        Ok(())
+    }
+
+    pub unsafe fn create_shader_module(
+        device: &Device,
+        bytecode: &[u8],
+    ) -> Result<vk::ShaderModule> {
+        let bytecode = Vec::<u8>::from(bytecode);
+        let (prefix, code, suffix) = bytecode.align_to::<u32>();
+
+        if !prefix.is_empty() || !suffix.is_empty() {
+            return Err(anyhow!(RendererError::ShaderCompile(
+                "bytecode is not properly aligned".to_string()
+            )));
+        }
+
+        let info = vk::ShaderModuleCreateInfo::builder()
+            .code_size(bytecode.len())
+            .code(code);
+
+        Ok(device.create_shader_module(&info, None)?)
+    }
+
+    #[cfg(target_os = "android")]
+    unsafe fn surface_rotated(&self) -> Result<bool> {
+        let capabilities = self
+            .instance
+            .get_physical_device_surface_capabilities_khr(
+                self.data.physical_device,
+                self.data.surface,
+            )
+            .map_err(RendererError::from)?;
+        Ok(capabilities.current_transform != self.data.swapchain.transform)
+    }
+
+    pub unsafe fn render(&mut self, window: &Window) -> Result<()> {
+        let result = self.render_frame(window);
+
+        // Perder o dispositivo (driver travou/resetou, GPU removida) ou a surface não
+        // precisa derrubar o app: dá pra recriar tudo e seguir. As chamadas do frame que
+        // podem perder um ou outro passam o erro pelo RendererError::from
+        let result = match result.as_ref().err().and_then(|e| e.downcast_ref()) {
+            Some(RendererError::DeviceLost) => self.recover_device(window),
+            Some(RendererError::SurfaceLost) => self.recover_surface(window),
+            _ => result,
+        };
+
+        #[cfg(feature = "profiling")]
+        if let Some(client) = tracy_client::Client::running() {
+            client.frame_mark();
+        }
+
+        result
+    }
+
+    unsafe fn render_frame(&mut self, window: &Window) -> Result<()> {
+        let frame_start = Instant::now();
+        if let Some(last) = self.last_frame_start {
+            self.stats.frame_time = frame_start - last;
+        }
+        self.last_frame_start = Some(frame_start);
+        self.overlay.push_frame_time(self.stats.frame_time);
+
+        let zone = zone!("Wait for frame");
+        let in_flight_fence = self.data.in_flight_fences[self.frame];
+        self.device
+            .wait_for_fences(&[in_flight_fence], true, u64::MAX)
+            .map_err(RendererError::from)?;
+        // Sem o present wait (comum no Linux) o modo de baixa latência fica no fence
+        // explícito do frame anterior: a CPU não passa da GPU, mas não sabe do display
+        if self.present_timer.low_latency && !self.present_timer.supported {
+            let previous = (self.frame + MAX_FRAMES_IN_FLIGHT - 1) % MAX_FRAMES_IN_FLIGHT;
+            self.device
+                .wait_for_fences(&[self.data.in_flight_fences[previous]], true, u64::MAX)
+                .map_err(RendererError::from)?;
+        }
+        zone.end();
+
+        #[cfg(feature = "profiling")]
+        if let Some(profiler) = &mut self.profiler {
+            profiler.collect(&self.device, self.frame)?;
+        }
+
+        self.present_timer.update(&mut self.stats);
+        self.pacer.update(&self.device, self.data.swapchain.chain)?;
+        self.stats.pacing = self.pacer.stats();
+
+        self.memory_stats = MemoryStats::query(&self.instance, &self.data);
+        self.memory_watch.check(&self.memory_stats);
+
+        // O staging que esse frame usou da última vez não é mais lido pela GPU
+        self.uploads.release(&self.device, self.frame);
+        self.meshes.release_retired(&self.device);
+        pipeline::release_retired(&self.device);
+
+        // Antes de gravar, pra malhas e texturas que terminaram já aparecerem nesse frame
+        if let Some(assets) = &mut self.assets {
+            for path in self.watcher.iter().flat_map(FileWatcher::changed) {
+                assets.reload(&path)?;
+            }
+            assets.update(
+                &self.instance,
+                &self.device,
+                &self.data,
+                &mut self.meshes,
+                &mut self.materials,
+            )?;
+        }
+
+        // A cópia que esse frame fez da última vez já terminou, então dá pra ler
+        if let Some(recorder) = &mut self.recorder {
+            recorder.collect(&self.device, self.frame)?;
+        }
+        if let Some(picking) = &mut self.picking {
+            picking.collect(&self.device, self.frame)?;
+        }
+
+        // Screenshot já lido, não precisa mais do recorder
+        if self.recorder.as_ref().is_some_and(|r| r.is_finished()) {
+            self.stop_recording()?;
+        }
+
+        let zone = zone!("Acquire");
+        let result = self.device.acquire_next_image_khr(
+            self.data.swapchain.chain,
+            u64::MAX,
+            self.data.image_available_semaphores[self.frame],
+            vk::Fence::null(),
+        );
+        zone.end();
+
+        let image_index = match result {
+            Ok((image_index, _)) => image_index as usize,
+            Err(vk::ErrorCode::OUT_OF_DATE_KHR) => return self.recreate_swapchain(window),
+            Err(e) => return Err(anyhow!(RendererError::from(e))),
+        };
+
+        // Se um frame anterior ainda está usando essa imagem, esperamos ele terminar
+        let image_in_flight = self.data.images_in_flight[image_index];
+        if !image_in_flight.is_null() {
+            self.device
+                .wait_for_fences(&[image_in_flight], true, u64::MAX)
+                .map_err(RendererError::from)?;
+        }
+
+        self.data.images_in_flight[image_index] = in_flight_fence;
+
+        // Espera a vez do headset e pega as poses dos olhos pra esse frame
+        #[cfg(feature = "xr")]
+        if let Some(xr) = &mut self.xr {
+            xr.poll_events()?;
+            if let Some(views) = xr.begin_frame(&self.camera)? {
+                self.stereo_views = Some(views);
+            }
+        }
+
+        let zone = zone!("Record");
+        self.record_command_buffer(image_index)?;
+        zone.end();
+
+        let wait_semaphores = &[self.data.image_available_semaphores[self.frame]];
+        let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let command_buffers = &[self.data.command_buffers[image_index]];
+        let signal_semaphores = &[self.data.render_finished_semaphores[self.frame]];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(wait_semaphores)
+            .wait_dst_stage_mask(wait_stages)
+            .command_buffers(command_buffers)
+            .signal_semaphores(signal_semaphores);
+
+        let zone = zone!("Submit");
+        self.device
+            .reset_fences(&[in_flight_fence])
+            .map_err(RendererError::from)?;
+        self.device
+            .queue_submit(self.data.graphics_queue, &[submit_info], in_flight_fence)
+            .map_err(RendererError::from)?;
+        zone.end();
+
+        #[cfg(feature = "xr")]
+        if let Some(xr) = &mut self.xr {
+            xr.end_frame()?;
+        }
+
+        let swapchains = &[self.data.swapchain.chain];
+        let image_indices = &[image_index as u32];
+        let mut present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(signal_semaphores)
+            .swapchains(swapchains)
+            .image_indices(image_indices);
+
+        let present_ids = self
+            .present_timer
+            .next_present_id(&self.device, self.data.swapchain.chain, frame_start)
+            .map(|id| [id]);
+        let mut present_id_info = vk::PresentIdKHR::builder();
+        if let Some(present_ids) = &present_ids {
+            present_id_info = present_id_info.present_ids(present_ids);
+            present_info = present_info.push_next(&mut present_id_info);
+        }
+
+        let present_times = self.pacer.next_present_time().map(|time| [time]);
+        let mut present_times_info = vk::PresentTimesInfoGOOGLE::builder();
+        if let Some(present_times) = &present_times {
+            present_times_info = present_times_info.times(present_times);
+            present_info = present_info.push_next(&mut present_times_info);
+        }
+
+        let zone = zone!("Present");
+        let result = self
+            .device
+            .queue_present_khr(self.data.present_queue, &present_info);
+        zone.end();
+
+        let changed = result == Ok(vk::SuccessCode::SUBOPTIMAL_KHR)
+            || result == Err(vk::ErrorCode::OUT_OF_DATE_KHR);
+        // Girar a tela 180° não muda o tamanho e, com a swapchain pré-rotacionada, nem
+        // todo Android devolve SUBOPTIMAL. Conferimos a rotação da surface a cada frame
+        #[cfg(target_os = "android")]
+        let changed = changed || self.surface_rotated()?;
+
+        if self.resized || changed {
+            self.resized = false;
+            self.recreate_swapchain(window)?;
+        } else if let Err(e) = result {
+            return Err(anyhow!(RendererError::from(e)));
+        }
+
+        self.frame = (self.frame + 1) % self.data.config.buffering.frames_in_flight();
+        error::end_frame();
+
+        // No FIFO o present já segura o ritmo
+        let unsynced = matches!(
+            self.data.swapchain.present_mode,
+            vk::PresentModeKHR::MAILBOX | vk::PresentModeKHR::IMMEDIATE
+        );
+        let zone = zone!("Frame limiter");
+        if !self.pacer.presented(!unsynced) && unsynced {
+            self.limiter.wait();
+        }
+        zone.end();
+
+        Ok(())
+    }
+
+    unsafe fn record_command_buffer(&mut self, image_index: usize) -> Result<()> {
+        let command_buffer = self.data.command_buffers[image_index];
+        self.device
+            .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+
+        let info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device.begin_command_buffer(command_buffer, &info)?;
+
+        #[cfg(feature = "profiling")]
+        if let Some(profiler) = &self.profiler {
+            profiler.reset(&self.device, command_buffer, self.frame);
+        }
+
+        // Só faz algo no primeiro frame depois de (re)criar os alvos
+        let far_depth = if self.data.config.reverse_z { 0.0 } else { 1.0 };
+        self.data
+            .render_pass
+            .initialize_targets(&self.device, command_buffer, far_depth);
+
+        self.scene.update();
+        let mut draw_list = self.scene.draw_list();
+        draw_list.append(&mut self.queued_draws);
+        let mut lights = self.scene.lights();
+        lights.append(&mut self.queued_lights);
+        let mut decals = self.scene.decals();
+        decals.append(&mut self.queued_decals);
+
+        // O compare das pipelines e o clear do depth vêm da configuração, então a câmera
+        // (que pode ter sido trocada pelo `camera_mut`) segue ela
+        self.camera.reverse_z = self.data.config.reverse_z;
+        self.meshes.set_camera(&self.camera);
+        // As sombras usam a lista inteira: o que está fora da câmera ainda pode fazer
+        // sombra dentro dela
+        let visible = self.meshes.cull(&draw_list, &self.camera.frustum());
+        self.stats.culled = (draw_list.len() - visible.len()) as u32;
+        self.stats.submitted = visible.len() as u32;
+        // As transparentes não passam pelo occlusion culling nem pelo pre-pass: vão por
+        // último, no pass delas
+        let (transparent, opaque): (Vec<_>, Vec<_>) = visible
+            .iter()
+            .copied()
+            .partition(|item| self.materials.is_transparent(item.material));
+
+        // Os visíveis pedem os mips que precisam pelo tamanho na tela, e o próximo lote
+        // sai assim que o anterior chegar
+        if let Some(streamer) = &mut self.streamer {
+            streamer.collect(&self.device, &mut self.materials)?;
+            let height = self.data.swapchain.extent.height as f32;
+            for item in &visible {
+                if let Some(size) = self.meshes.screen_size(item) {
+                    self.materials.request_detail(item.material, size * height);
+                }
+            }
+            streamer.submit(&self.instance, &self.device, &self.data, &mut self.materials)?;
+        }
+        self.materials.refresh_sets(&self.device, self.frame);
+
+        let mut light_uniforms = LightUniforms::new(self.ambient, &lights);
+        light_uniforms.sky = self.sky.uniform();
+        light_uniforms.probe = self.reflection_probe.uniform();
+        light_uniforms.irradiance_grid = self.irradiance.uniform();
+        light_uniforms.set_point_shadows(&self.shadows.settings());
+        let cascades = self.shadows.cascades(&lights, &self.camera);
+        if let Some(cascades) = &cascades {
+            light_uniforms.set_shadow(
+                cascades,
+                &self.shadows.settings(),
+                self.shadows.show_cascades,
+            );
+        }
+        self.data
+            .frame_descriptors
+            .write_lights(self.frame, &light_uniforms);
+
+        // Os animadores andam junto com o frame, e as poses vão pro buffer dele
+        let delta = self
+            .data
+            .config
+            .fixed_frame_time
+            .unwrap_or(self.stats.frame_time)
+            .as_secs_f32();
+        self.skinning.update(delta);
+        self.skinning.write(self.frame);
+
+        // Câmera e materiais do frame, lidos pelo pre-pass e pelo pass da cena
+        let uniforms = FrameUniforms::new(&self.camera, &self.data.swapchain.pre_rotation());
+        self.data.frame_descriptors.write(self.frame, &uniforms);
+        self.materials.write(self.frame);
+
+        // O grafo já tem a ordem e as barreiras; falta só saber quais imagens são desse
+        // frame
+        self.frame_graph.bind(
+            &self.data,
+            &self.shadows,
+            &self.occlusion,
+            self.picking.as_ref(),
+            self.stereo.as_ref(),
+            self.xr_image(),
+            image_index,
+        );
+
+        let mut direct = vec![];
+        let mut draw_calls = 0;
+        for index in 0..self.frame_graph.graph.len() {
+            let (pass, name, color) = self.frame_graph.graph.pass(index);
+            self.frame_graph
+                .graph
+                .record_barrier(&self.device, command_buffer, index);
+            self.begin_pass(command_buffer, name, color);
+
+            match pass {
+                FramePass::Uploads => self.uploads.record(
+                    &self.instance,
+                    &self.device,
+                    &self.data,
+                    command_buffer,
+                    self.frame,
+                )?,
+                // O compute escolhe quais das malhas visíveis não estão atrás do depth do
+                // frame anterior; o que ele não cobre vai pelo caminho direto
+                FramePass::OcclusionCulling => {
+                    direct = self.occlusion.record_cull(
+                        &self.device,
+                        &self.data,
+                        command_buffer,
+                        self.frame,
+                        &self.meshes,
+                        &self.uploads,
+                        &self.materials,
+                        &opaque,
+                    );
+                }
+                FramePass::Shadows => {
+                    draw_calls += self.shadows.record(
+                        &self.device,
+                        command_buffer,
+                        &self.meshes,
+                        &self.uploads,
+                        cascades.as_ref(),
+                        &light_uniforms,
+                        &draw_list,
+                    )?;
+                }
+                // A lista inteira, como nas sombras: as faces olham pra todo lado
+                FramePass::ReflectionProbe => {
+                    draw_calls += self.reflection_probe.record(
+                        &self.device,
+                        &self.data,
+                        command_buffer,
+                        self.frame,
+                        &self.meshes,
+                        &self.uploads,
+                        self.sky.settings.sun_direction,
+                        glm::comp_max(&self.ambient),
+                        &draw_list,
+                    );
+                }
+                FramePass::IrradianceProbes => {
+                    draw_calls += self.irradiance.record(
+                        &self.device,
+                        &self.data,
+                        command_buffer,
+                        self.frame,
+                        &self.meshes,
+                        &self.uploads,
+                        self.sky.settings.sun_direction,
+                        self.ambient,
+                        &draw_list,
+                    );
+                }
+                FramePass::DepthPrepass => {
+                    draw_calls += self.record_depth_prepass(command_buffer, &direct)
+                }
+                FramePass::Scene => {
+                    draw_calls +=
+                        self.record_scene_pass(command_buffer, &direct, &visible, &decals)?
+                }
+                FramePass::Fog => {
+                    draw_calls += self.fog.record(
+                        &self.device,
+                        &self.data,
+                        command_buffer,
+                        self.frame,
+                        self.meshes.debug_view,
+                    );
+                }
+                FramePass::Transparent => {
+                    draw_calls += self.record_transparent_pass(command_buffer, &transparent)
+                }
+                FramePass::GodRays => {
+                    let view_projection =
+                        self.data.swapchain.pre_rotation() * self.camera.view_projection();
+                    draw_calls += self.god_rays.record(
+                        &self.device,
+                        &self.data,
+                        command_buffer,
+                        &view_projection,
+                        &light_uniforms.directional,
+                        self.meshes.debug_view,
+                    );
+                }
+                FramePass::Picking => {
+                    if let Some(picking) = &mut self.picking {
+                        let view_projection =
+                            self.data.swapchain.pre_rotation() * self.camera.view_projection();
+                        draw_calls += picking.record(
+                            &self.device,
+                            &self.data,
+                            command_buffer,
+                            self.frame,
+                            &self.meshes,
+                            &self.uploads,
+                            &view_projection,
+                            &visible,
+                        );
+                    }
+                }
+                // A lista inteira: o culling da câmera cortaria o que só um olho vê
+                FramePass::Stereo => {
+                    if let Some(stereo) = &self.stereo {
+                        let views = self
+                            .stereo_views
+                            .unwrap_or_else(|| View::eyes(&self.camera, DEFAULT_IPD));
+                        draw_calls += stereo.record(
+                            &self.device,
+                            &self.data,
+                            command_buffer,
+                            self.frame,
+                            &self.meshes,
+                            &self.uploads,
+                            &views,
+                            self.sky.settings.sun_direction,
+                            glm::comp_max(&self.ambient),
+                            &draw_list,
+                        );
+                    }
+                }
+                FramePass::XrCopy => self.record_xr_copy(command_buffer),
+                FramePass::HiZPyramid => {
+                    let view_projection =
+                        self.data.swapchain.pre_rotation() * self.camera.view_projection();
+                    self.occlusion.record_pyramid(
+                        &self.device,
+                        &self.data,
+                        command_buffer,
+                        &view_projection,
+                    );
+                }
+                FramePass::AutoExposure => {
+                    self.exposure.record(
+                        &self.device,
+                        &self.data,
+                        command_buffer,
+                        self.frame,
+                        delta,
+                    );
+                }
+                FramePass::PostEffects => {
+                    self.post_chain
+                        .record(&self.device, &self.data, command_buffer);
+                }
+                FramePass::Post => {
+                    draw_calls += self.record_post_pass(command_buffer, image_index)?
+                }
+                FramePass::Ui => {
+                    if self.ui.has_content() {
+                        draw_calls += self.ui.record(
+                            &self.instance,
+                            &self.device,
+                            &self.data,
+                            command_buffer,
+                            image_index,
+                            self.frame,
+                            &self.uploads,
+                        )?;
+                    } else {
+                        // Interface não montada nesse frame: não tem o que animar
+                        self.ui.clear_repaint();
+                    }
+                }
+                FramePass::FrameCapture => {
+                    if let Some(recorder) = &mut self.recorder {
+                        recorder.record(
+                            &self.device,
+                            command_buffer,
+                            self.frame,
+                            self.data.swapchain.images[image_index],
+                        );
+                    }
+                }
+            }
+
+            self.end_pass(command_buffer);
+        }
+        self.frame_graph
+            .graph
+            .record_end(&self.device, command_buffer);
+
+        self.stats.draw_calls = draw_calls;
+
+        self.device.end_command_buffer(command_buffer)?;
+
+        Ok(())
+    }
+
+    // Cena 3D no alvo HDR: malhas (ou G-buffer + decals + luz no deferred), o triângulo de
+    // teste e o contorno da seleção (procurada em `visible`, que inclui os desenhos
+    // indiretos). Retorna quantos draw calls fez
+    unsafe fn record_scene_pass(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        draw_list: &[DrawItem],
+        visible: &[DrawItem],
+        decals: &[SceneDecal],
+    ) -> Result<u32> {
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(self.data.swapchain.extent);
+
+        let clear_values = self.data.render_pass.clear_values();
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.data.render_pass.pass)
+            .framebuffer(self.data.render_pass.framebuffer)
+            .render_area(render_area)
+            .clear_values(&clear_values);
+
+        self.device
+            .cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+        // Deferred: as malhas vão pro G-buffer no primeiro subpass, os decals se misturam
+        // nele no segundo e o terceiro começa pela luz. O resto é desenhado por cima, como
+        // no forward
+        let mut draw_calls = 0;
+        let deferred = self.data.render_pass.path == RenderPath::Deferred;
+        if deferred {
+            draw_calls += self.meshes.record(
+                &self.device,
+                &self.data,
+                command_buffer,
+                self.frame,
+                &self.uploads,
+                &self.materials,
+                &self.skinning,
+                draw_list,
+            )?;
+            draw_calls += self.meshes.record_indirect(
+                &self.device,
+                &self.data,
+                command_buffer,
+                self.frame,
+                &self.materials,
+                &self.occlusion,
+            );
+            self.device
+                .cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+            draw_calls += self.decals.record(
+                &self.device,
+                &self.data,
+                command_buffer,
+                self.frame,
+                &self.uploads,
+                &self.materials,
+                decals,
+            );
+            self.device
+                .cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+        }
+
+        // Nas visões de debug só as malhas escrevem os dados que o debug.frag espera; céu,
+        // terreno e o triângulo de teste ficam de fora
+        let debug_view = self.meshes.debug_view;
+        let scene_extras = debug_view == DebugView::Final;
+
+        // O céu vai primeiro, sem depth, e tudo é desenhado por cima. No deferred a luz
+        // descarta os pixels sem nada no G-buffer, então ele continua aparecendo ali
+        if scene_extras {
+            draw_calls += self
+                .sky
+                .record(&self.device, &self.data, command_buffer, self.frame);
+        }
+
+        if deferred {
+            draw_calls += self.deferred.record(
+                &self.device,
+                &self.data,
+                command_buffer,
+                self.frame,
+                debug_view,
+            );
+        }
+
+        if scene_extras {
+            draw_calls += self
+                .terrain
+                .record(&self.device, &self.data, command_buffer, self.frame);
+
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.data.pipeline,
+            );
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.data.pipeline_layout,
+                0,
+                &[self.data.frame_descriptors.set(self.frame)],
+                &[],
+            );
+
+            let model = glm::identity::<f32, 4>();
+            let model_bytes =
+                std::slice::from_raw_parts(model.as_ptr() as *const u8, size_of::<glm::Mat4>());
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.data.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                model_bytes,
+            );
+
+            let transfer = SCENE_TRANSFER as u32;
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.data.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                size_of::<glm::Mat4>() as u32,
+                &transfer.to_ne_bytes(),
+            );
+            self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            draw_calls += 1;
+        }
+
+        if !deferred {
+            draw_calls += self.meshes.record(
+                &self.device,
+                &self.data,
+                command_buffer,
+                self.frame,
+                &self.uploads,
+                &self.materials,
+                &self.skinning,
+                draw_list,
+            )?;
+            draw_calls += self.meshes.record_indirect(
+                &self.device,
+                &self.data,
+                command_buffer,
+                self.frame,
+                &self.materials,
+                &self.occlusion,
+            );
+        }
+
+        let view_projection = self.data.swapchain.pre_rotation() * self.camera.view_projection();
+        draw_calls += self.outline.record(
+            &self.device,
+            &self.data,
+            command_buffer,
+            &self.meshes,
+            &self.uploads,
+            &view_projection,
+            visible,
+        );
+
+        self.device.cmd_end_render_pass(command_buffer);
+
+        Ok(draw_calls)
+    }
+
+    // Depth das malhas que o pass da cena vai desenhar, a lista direta e os desenhos
+    // indiretos. Retorna quantos draw calls fez
+    unsafe fn record_depth_prepass(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        draw_list: &[DrawItem],
+    ) -> u32 {
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(self.data.swapchain.extent);
+
+        let clear_values = self.data.render_pass.prepass_clear_values();
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.data.render_pass.prepass)
+            .framebuffer(self.data.render_pass.prepass_framebuffer)
+            .render_area(render_area)
+            .clear_values(&clear_values);
+
+        self.device
+            .cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+        let draw_calls = self.meshes.record_prepass(
+            &self.device,
+            &self.data,
+            command_buffer,
+            self.frame,
+            &self.uploads,
+            &self.materials,
+            &self.skinning,
+            &self.occlusion,
+            draw_list,
+        );
+        self.device.cmd_end_render_pass(command_buffer);
+
+        draw_calls
+    }
+
+    // Malhas de material transparente por cima da cena, já ordenadas de trás pra frente.
+    // Sem nenhuma, o render pass nem começa. Retorna quantos draw calls fez
+    unsafe fn record_transparent_pass(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        items: &[DrawItem],
+    ) -> u32 {
+        if items.is_empty() {
+            return 0;
+        }
+
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(self.data.swapchain.extent);
+
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.data.render_pass.transparent)
+            .framebuffer(self.data.render_pass.transparent_framebuffer)
+            .render_area(render_area);
+
+        self.device
+            .cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+        let draw_calls = self.meshes.record_transparent(
+            &self.device,
+            &self.data,
+            command_buffer,
+            self.frame,
+            &self.uploads,
+            &self.materials,
+            &self.skinning,
+            items,
+        );
+        self.device.cmd_end_render_pass(command_buffer);
+
+        draw_calls
+    }
+
+    // Estágios do pós-processamento, do alvo HDR até a imagem da swapchain, com o 2D
+    // (sprites, overlay, texto) por cima do último. Retorna quantos draw calls fez
+    unsafe fn record_post_pass(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) -> Result<u32> {
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(self.data.swapchain.extent);
+
+        let mut draw_calls = 0;
+        let count = self.data.post_pass.stages.len();
+        for (index, stage) in self.data.post_pass.stages.iter().enumerate() {
+            let (pass, framebuffer) = self.data.post_pass.target(index, image_index);
+            let info = vk::RenderPassBeginInfo::builder()
+                .render_pass(pass)
+                .framebuffer(framebuffer)
+                .render_area(render_area);
+            self.device
+                .cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+            draw_calls += match stage {
+                PostStage::ToneMap => self.tone_mapper.record(
+                    &self.device,
+                    &self.data,
+                    command_buffer,
+                    self.exposure.active(),
+                    self.meshes.debug_view,
+                ),
+                PostStage::ColorGrading => {
+                    self.grading
+                        .record(&self.device, &self.data, command_buffer, &self.uploads)
+                }
+                PostStage::Fxaa => self.fxaa.record(&self.device, &self.data, command_buffer),
+            };
+
+            // O último continua aberto pro 2D
+            if index < count - 1 {
+                self.device.cmd_end_render_pass(command_buffer);
+            }
+        }
+
+        draw_calls += self.sprites.record(
+            &self.instance,
+            &self.device,
+            &self.data,
+            command_buffer,
+            self.frame,
+            &self.uploads,
+        )?;
+
+        draw_calls += self.overlay.record(
+            &self.instance,
+            &self.device,
+            &self.data,
+            command_buffer,
+            self.frame,
+            &self.stats,
+            &self.memory_stats,
+        )?;
+        draw_calls += self.text.record(
+            &self.instance,
+            &self.device,
+            &self.data,
+            command_buffer,
+            self.frame,
+            &self.uploads,
+        )?;
+
+        self.device.cmd_end_render_pass(command_buffer);
+
+        Ok(draw_calls)
+    }
+
+    // Rótulo de debug (RenderDoc) e zona de GPU (Tracy) em volta de um pass
+    unsafe fn begin_pass(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        name: &str,
+        color: [f32; 4],
+    ) {
+        marker::begin(&self.instance, &self.data, command_buffer, name, color);
+
+        #[cfg(feature = "profiling")]
+        if let Some(profiler) = &mut self.profiler {
+            profiler.begin(&self.device, command_buffer, self.frame, name);
+        }
+    }
+
+    unsafe fn end_pass(&mut self, command_buffer: vk::CommandBuffer) {
+        #[cfg(feature = "profiling")]
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end(&self.device, command_buffer, self.frame);
+        }
+
+        marker::end(&self.instance, &self.data, command_buffer);
+    }
+
+    // Troca as operações/valores de clear dos render targets. Se só os valores mudaram,
+    // o próximo frame já usa eles; se o tipo da operação mudou, o render pass é recriado.
+    // Com reverse-Z (o padrão) o longe é 0.0, então é com ele que o depth é limpo
+    pub unsafe fn set_attachment_ops(&mut self, window: &Window, ops: AttachmentOps) -> Result<()> {
+        let rebuild = ops.requires_rebuild(&self.data.render_pass.ops);
+        self.data.render_pass.ops = ops;
+
+        if rebuild {
+            self.recreate_swapchain(window)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn tone_mapping(&self) -> ToneMapping {
+        self.tone_mapper.settings
+    }
+
+    // Vale a partir do próximo frame, não recria nada
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapper.settings = tone_mapping;
+    }
+
+    pub fn auto_exposure(&self) -> AutoExposureSettings {
+        self.exposure.settings
+    }
+
+    // Luminância média que a exposição automática está perseguindo, de uns frames atrás.
+    // None com ela desligada
+    pub fn adapted_luminance(&self) -> Option<f32> {
+        self.exposure.luminance()
+    }
+
+    // Também vale a partir do próximo frame. A luminância adaptada continua de onde estava
+    pub fn set_auto_exposure(&mut self, settings: AutoExposureSettings) {
+        self.exposure.settings = settings;
+    }
+
+    pub fn render_path(&self) -> RenderPath {
+        self.data.config.render_path
+    }
+
+    // Troca entre forward e deferred. O render pass muda de forma, então tudo que depende
+    // da swapchain é recriado
+    pub unsafe fn set_render_path(&mut self, window: &Window, path: RenderPath) -> Result<()> {
+        if path == self.data.config.render_path {
+            return Ok(());
+        }
+
+        self.data.config.render_path = path;
+        self.recreate_swapchain(window)
+    }
+
+    pub fn depth_prepass(&self) -> bool {
+        self.data.config.depth_prepass
+    }
+
+    // Liga ou desliga o pre-pass de depth, pra comparar o custo com e sem. O pass da cena
+    // passa a carregar o depth (e as malhas mudam de compare), então tudo que depende da
+    // swapchain é recriado
+    pub unsafe fn set_depth_prepass(&mut self, window: &Window, enabled: bool) -> Result<()> {
+        if enabled == self.data.config.depth_prepass {
+            return Ok(());
+        }
+
+        self.data.config.depth_prepass = enabled;
+        self.recreate_swapchain(window)
+    }
+
+    pub fn post_processing(&self) -> PostProcessing {
+        self.data.config.post_processing
+    }
+
+    // Liga ou desliga estágios do pós-processamento. A cadeia de passes muda, então tudo
+    // que depende da swapchain é recriado
+    pub unsafe fn set_post_processing(
+        &mut self,
+        window: &Window,
+        post_processing: PostProcessing,
+    ) -> Result<()> {
+        if post_processing == self.data.config.post_processing {
+            return Ok(());
+        }
+
+        self.data.config.post_processing = post_processing;
+        self.recreate_swapchain(window)
+    }
+
+    // Põe um efeito de tela cheia no fim da cadeia de compute, já ligado. `shader` é o
+    // SPIR-V; o que ele lê e escreve está em post.rs
+    pub unsafe fn add_post_effect(&mut self, name: &str, shader: &[u8]) -> Result<PostEffectId> {
+        self.post_chain.add(&self.device, name, shader)
+    }
+
+    pub fn post_effect(&self, name: &str) -> Option<PostEffectId> {
+        self.post_chain.find(name)
+    }
+
+    // Nome e se está ligado, na ordem da cadeia
+    pub fn post_effects(&self) -> Vec<(PostEffectId, String, bool)> {
+        self.post_chain
+            .effects()
+            .map(|(id, name, enabled)| (id, name.to_string(), enabled))
+            .collect()
+    }
+
+    pub fn set_post_effect_enabled(&mut self, id: PostEffectId, enabled: bool) {
+        self.post_chain.set_enabled(id, enabled);
+    }
+
+    // Push constants do efeito, até 128 bytes
+    pub fn set_post_effect_constants(&mut self, id: PostEffectId, constants: &[u8]) -> Result<()> {
+        self.post_chain.set_constants(id, constants)
+    }
+
+    // Troca a LUT do color grading (a antiga pode estar em uso, então espera a GPU). Não
+    // liga o estágio; até o upload terminar ele repassa a cor
+    pub unsafe fn set_color_grading_lut(&mut self, lut: Lut) -> Result<()> {
+        self.grading.set_lut(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+            lut,
+        )
+    }
+
+    // Mesma coisa a partir de um .cube ou PNG em faixa
+    pub unsafe fn load_color_grading_lut(&mut self, path: &Path) -> Result<()> {
+        let lut = Lut::load(path)?;
+        self.set_color_grading_lut(lut)?;
+        self.data.config.color_grading_lut = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    // As filas pedidas com AppBuilder::extra_queue, na mesma ordem
+    pub fn extra_queues(&self) -> &[ExtraQueue] {
+        &self.data.extra_queues
+    }
+
+    pub fn capabilities(&self) -> &DeviceCapabilities {
+        &self.data.capabilities
+    }
+
+    // Uso e orçamento de cada heap no começo do último frame, e os blocos que alocamos
+    pub fn memory_stats(&self) -> &MemoryStats {
+        &self.memory_stats
+    }
+
+    // Troca o aviso padrão (um warn! no log) por `callback`, chamado quando um heap passa
+    // da fração `threshold` do orçamento
+    pub fn on_memory_warning(&mut self, threshold: f32, callback: MemoryWarningCallback) {
+        self.memory_watch = MemoryWatch::new(threshold, callback);
+    }
+
+    pub unsafe fn start_recording(&mut self, output: CaptureOutput) -> Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(FrameRecorder::create(
+            &self.instance,
+            &self.device,
+            &self.data,
+            output,
+        )?);
+
+        Ok(())
+    }
+
+    pub unsafe fn stop_recording(&mut self) -> Result<()> {
+        if let Some(recorder) = self.recorder.take() {
+            self.device.device_wait_idle()?;
+            recorder.destroy(&self.device);
+        }
+
+        Ok(())
+    }
+
+    // Salva o próximo frame apresentado como PNG. Substitui uma gravação em andamento
+    pub unsafe fn screenshot(&mut self, path: impl Into<PathBuf>) -> Result<()> {
+        self.start_recording(CaptureOutput::Screenshot(path.into()))
+    }
+
+    // Agenda a cópia de `bytes` pro destino, respeitando o orçamento por frame
+    pub fn upload(&mut self, target: UploadTarget, bytes: Vec<u8>) -> UploadId {
+        self.uploads.enqueue(target, bytes)
+    }
+
+    pub fn is_upload_pending(&self, id: UploadId) -> bool {
+        self.uploads.is_pending(id)
+    }
+
+    pub fn set_upload_budget(&mut self, bytes: u64) {
+        self.uploads.budget = bytes;
+    }
+
+    // Troca o modo de apresentação (liga/desliga v-sync) recriando a swapchain
+    pub unsafe fn set_present_mode(
+        &mut self,
+        window: &Window,
+        present_mode: PresentModePreference,
+    ) -> Result<()> {
+        let support = SwapchainSupport::get(&self.instance, &self.data, self.data.physical_device)?;
+        if !support.present_modes.contains(&present_mode.to_vk()) {
+            return Err(anyhow!(
+                "Present mode {:?} is not supported by the surface.",
+                present_mode
+            ));
+        }
+
+        self.device.device_wait_idle()?;
+        let ops = self.data.render_pass.ops;
+        let previous = self.data.config.present_mode;
+        self.data.config.present_mode = present_mode;
+        self.destroy_swapchain();
+        if let Err(e) = self.create_swapchain_objects(window, ops) {
+            // Volta pro modo que estava funcionando, pra não ficar sem swapchain nem com a
+            // configuração apontando pra um modo que falhou
+            self.data.config.present_mode = previous;
+            self.create_swapchain_objects(window, ops)?;
+            return Err(e);
+        }
+        info!("Switched present mode to {:?}.", self.present_mode());
+
+        Ok(())
+    }
+
+    // O modo que a swapchain atual realmente usa, que pode ser o FIFO quando o preferido
+    // não é suportado (ver `preferred_present_mode`)
+    pub fn present_mode(&self) -> PresentModePreference {
+        PresentModePreference::from_vk(self.data.swapchain.present_mode)
+            .unwrap_or(self.data.config.present_mode)
+    }
+
+    // O que foi pedido, na configuração ou no `set_present_mode`
+    pub fn preferred_present_mode(&self) -> PresentModePreference {
+        self.data.config.present_mode
+    }
+
+    // Formato e colorspace que a swapchain atual negociou com a surface
+    pub fn surface_format(&self) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format: self.data.swapchain.format,
+            color_space: self.data.swapchain.color_space,
+        }
+    }
+
+    // Recria a swapchain com o novo número de imagens e passa a usar os frames em voo dele
+    pub unsafe fn set_buffering(&mut self, window: &Window, buffering: Buffering) -> Result<()> {
+        self.device.device_wait_idle()?;
+
+        // Staging e leituras presos a frames que podem não ser mais usados
+        for slot in 0..MAX_FRAMES_IN_FLIGHT {
+            self.uploads.release(&self.device, slot);
+            if let Some(picking) = &mut self.picking {
+                picking.collect(&self.device, slot)?;
+            }
+        }
+
+        self.data.config.buffering = buffering;
+        self.frame = 0;
+        self.recreate_swapchain(window)?;
+        info!(
+            "Switched to {:?} buffering ({} swapchain images).",
+            buffering,
+            self.data.swapchain.images.len()
+        );
+
+        Ok(())
+    }
+
+    pub fn buffering(&self) -> Buffering {
+        self.data.config.buffering
+    }
+
+    pub fn redraw_on_demand(&self) -> bool {
+        self.data.config.redraw_on_demand
+    }
+
+    pub fn set_redraw_on_demand(&mut self, enabled: bool) {
+        self.data.config.redraw_on_demand = enabled;
+    }
+
+    // O que o próprio renderer ainda tem pra fazer nos próximos frames, mesmo sem
+    // nenhum evento novo
+    pub fn needs_redraw(&self) -> bool {
+        // O headset tem o próprio ritmo, e as mensagens do runtime chegam pelo render
+        #[cfg(feature = "xr")]
+        if self.xr.is_some() {
+            return true;
+        }
+
+        !self.uploads.is_idle()
+            || self.recorder.is_some()
+            || self.ui.needs_repaint()
+            || self.assets.as_ref().is_some_and(|a| a.pending() > 0)
+            || self.picking.as_ref().is_some_and(Picking::is_pending)
+    }
+
+    // None (ou 0) tira o limite
+    pub fn set_fps_limit(&mut self, fps: Option<u32>) {
+        self.data.config.fps_limit = fps;
+        self.limiter.set_fps(fps);
+        self.pacer.set_fps_limit(fps);
+    }
+
+    pub fn fps_limit(&self) -> Option<u32> {
+        self.limiter.fps()
+    }
+
+    // Vai pro próximo modo suportado na ordem FIFO -> MAILBOX -> IMMEDIATE. Dá no máximo
+    // uma volta: saindo do FIFO_RELAXED o `next` nunca volta pra ele
+    pub unsafe fn cycle_present_mode(&mut self, window: &Window) -> Result<()> {
+        let start = self.present_mode();
+        let mut candidate = start.next();
+        for _ in 0..3 {
+            if candidate == start {
+                break;
+            }
+            match self.set_present_mode(window, candidate) {
+                Ok(()) => return Ok(()),
+                Err(e) => debug!("{}", e),
+            }
+            candidate = candidate.next();
+        }
+
+        warn!("No other present mode could be used, keeping {:?}.", start);
+        Ok(())
+    }
+
+    // Troca entre janela, tela cheia sem borda e tela cheia exclusiva. A swapchain é
+    // recriada pra pegar o novo tamanho (e o modo exclusivo, se suportado)
+    pub unsafe fn set_fullscreen(&mut self, window: &Window, mode: FullscreenMode) -> Result<()> {
+        if self.data.fullscreen == mode {
+            return Ok(());
+        }
+
+        // Solta o display antes do winit mudar o modo de vídeo
+        self.device.device_wait_idle()?;
+        if self.data.swapchain.exclusive {
+            self.device
+                .release_full_screen_exclusive_mode_ext(self.data.swapchain.chain)?;
+            self.data.swapchain.exclusive = false;
+        }
+
+        if self.data.fullscreen == FullscreenMode::Windowed {
+            self.data.windowed = Some(WindowedState::save(window));
+        }
+
+        self.data.fullscreen = display::apply(window, mode);
+
+        if mode == FullscreenMode::Windowed {
+            if let Some(windowed) = self.data.windowed.take() {
+                windowed.restore(window);
+            }
+        }
+
+        self.recreate_swapchain(window)
+    }
+
+    // Alt+Enter: alterna entre janela e tela cheia sem borda
+    pub unsafe fn toggle_borderless(&mut self, window: &Window) -> Result<()> {
+        let mode = if self.data.fullscreen == FullscreenMode::Windowed {
+            FullscreenMode::Borderless
+        } else {
+            FullscreenMode::Windowed
+        };
+
+        self.set_fullscreen(window, mode)
+    }
+
+    pub fn fullscreen(&self) -> FullscreenMode {
+        self.data.fullscreen
+    }
+
+    // Relatório de diagnóstico com o estado atual: dispositivo, configuração e o frame
+    pub unsafe fn report(&self) -> Report {
+        let mut report = Report::collect();
+        report.add(
+            "device.json",
+            report::device_json(&self.instance, &self.data),
+        );
+        report.add("config.txt", format!("{:#?}\n", self.data.config));
+        report.add(
+            "frame.txt",
+            format!(
+                "frame: {}\n{:#?}\n{:#?}\n{:#?}\npending upload bytes: {}\nrecording: {}\n",
+                self.frame,
+                self.data.swapchain,
+                self.data.render_pass,
+                self.stats,
+                self.uploads.pending_bytes(),
+                self.is_recording(),
+            ),
+        );
+        report.add("frame_graph.txt", format!("{:#?}\n", self.frame_graph));
+        report
+    }
+
+    pub fn toggle_overlay(&mut self) {
+        self.overlay.visible = !self.overlay.visible;
+    }
+
+    // Monta a interface do egui desse frame. Precisa ser chamado todo frame, antes do
+    // `render`, pra interface continuar na tela
+    pub unsafe fn ui(&mut self, window: &Window, f: impl FnOnce(&egui::CtxRef)) -> Result<()> {
+        self.ui.run(window, f);
+        self.ui
+            .update_font(&self.instance, &self.device, &self.data, &mut self.uploads)
+    }
+
+    // Texto na tela no próximo frame, em pixels a partir do canto superior esquerdo
+    pub fn draw_text(&mut self, position: glm::Vec2, size: f32, color: glm::Vec4, text: &str) {
+        self.text.draw_text(position, size, color, text);
+    }
+
+    pub fn measure_text(&self, size: f32, text: &str) -> glm::Vec2 {
+        self.text.measure(size, text)
+    }
+
+    // Textura de sprite a partir de um PNG. Fica disponível quando o upload terminar
+    pub unsafe fn load_sprite_texture(&mut self, path: &Path) -> Result<SpriteTextureId> {
+        self.sprites.load_texture(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+            path,
+        )
+    }
+
+    // Textura de sprite a partir de pixels RGBA8 em sRGB
+    pub unsafe fn add_sprite_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    ) -> Result<SpriteTextureId> {
+        self.sprites.add_texture(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+            width,
+            height,
+            pixels,
+        )
+    }
+
+    pub fn sprite_texture_size(&self, id: SpriteTextureId) -> Option<(u32, u32)> {
+        self.sprites.texture_size(id)
+    }
+
+    // Sprite desenhado no próximo frame, por cima da cena 3D
+    pub fn draw_sprite(&mut self, sprite: Sprite) {
+        self.sprites.draw(sprite);
+    }
+
+    pub fn sprite_camera(&mut self) -> &mut Camera2D {
+        &mut self.sprites.camera
+    }
+
+    // Malha disponível pros nós da cena. Aparece quando o upload terminar
+    pub unsafe fn add_mesh(&mut self, mesh: MeshData) -> Result<MeshId> {
+        self.add_mesh_lods(mesh, vec![])
+    }
+
+    // Com versões mais simples pra quando ela fica pequena na tela, feitas à mão ou com
+    // `MeshData::generate_lods`
+    pub unsafe fn add_mesh_lods(&mut self, mesh: MeshData, lods: Vec<MeshLod>) -> Result<MeshId> {
+        self.meshes.add(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+            mesh,
+            lods,
+            vec![],
+        )
+    }
+
+    // Um OBJ inteiro como uma malha só, lido pelas threads de assets. Até terminar, o id
+    // (de `mesh_asset`) desenha um cubo
+    pub unsafe fn load_mesh_async(&mut self, path: &Path) -> Result<AssetHandle<assets::Mesh>> {
+        let assets = self
+            .assets
+            .as_mut()
+            .ok_or_else(|| anyhow!("Async loading is disabled (asset_threads is 0)."))?;
+        assets.load_mesh(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+            &mut self.meshes,
+            path,
+        )
+    }
+
+    // O que for carregado síncrono também é recarregado, se o hot reload está ligado
+    fn hot_reload_assets(&mut self) -> Option<&mut AssetManager> {
+        match self.watcher {
+            Some(_) => self.assets.as_mut(),
+            None => None,
+        }
+    }
+
+    pub fn mesh_asset(&self, handle: AssetHandle<assets::Mesh>) -> MeshId {
+        self.assets.as_ref().unwrap().mesh(handle)
+    }
+
+    // `Failed` continua com o placeholder
+    pub fn asset_state<T>(&self, handle: AssetHandle<T>) -> AssetState {
+        self.assets.as_ref().unwrap().state(handle)
+    }
+
+    // Malha deformada pelas juntas do animador do nó, com um `VertexSkin` por vértice na
+    // ordem de `mesh.vertices` (então as tangentes já têm que ter sido geradas). Sem
+    // animador no nó, aparece na pose de repouso
+    pub unsafe fn add_skinned_mesh(
+        &mut self,
+        mesh: MeshData,
+        skin: Vec<VertexSkin>,
+    ) -> Result<MeshId> {
+        if skin.is_empty() {
+            return Err(anyhow!("Skinned mesh has no joint weights."));
+        }
+
+        self.meshes.add(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+            mesh,
+            vec![],
+            skin,
+        )
+    }
+
+    // Avança junto com os frames. Os nós apontam pra ele com `Node::with_animator`
+    pub fn add_animator(&mut self, animator: Animator) -> AnimatorId {
+        self.skinning.add(animator)
+    }
+
+    pub fn animator(&self, id: AnimatorId) -> Option<&Animator> {
+        self.skinning.get(id)
+    }
+
+    pub fn animator_mut(&mut self, id: AnimatorId) -> Option<&mut Animator> {
+        self.skinning.get_mut(id)
+    }
+
+    // Carrega um OBJ como um nó (com o nome do arquivo) com um filho por objeto, embaixo
+    // de `parent` ou na raiz
+    pub unsafe fn load_model(&mut self, path: &Path, parent: Option<NodeId>) -> Result<NodeId> {
+        let meshes = MeshData::load_obj(path)?;
+
+        let name = path
+            .file_stem()
+            .map_or("model".into(), |s| s.to_string_lossy().into_owned());
+        let root = match parent {
+            Some(parent) => self.scene.add_child(parent, Node::new(name))?,
+            None => self.scene.add(Node::new(name)),
+        };
+
+        for (name, mesh) in meshes {
+            let mesh = match self.add_mesh(mesh) {
+                Ok(mesh) => mesh,
+                Err(e) => {
+                    self.scene.remove(root);
+                    return Err(e);
+                }
+            };
+            if let Some(assets) = self.hot_reload_assets() {
+                assets.watch_mesh(path, Some(name.clone()), mesh);
+            }
+            self.scene
+                .add_child(root, Node::new(name).with_mesh(mesh))?;
+        }
+
+        Ok(root)
+    }
+
+    pub unsafe fn add_material(&mut self, material: Material) -> Result<MaterialId> {
+        self.materials.add(&self.device, material)
+    }
+
+    // Textura pra materiais a partir de um PNG ou KTX2. `srgb` pra cores, UNORM pra dados
+    pub unsafe fn load_material_texture(
+        &mut self,
+        path: &Path,
+        srgb: bool,
+    ) -> Result<MaterialTextureId> {
+        let id = self.materials.load_texture(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+            path,
+            srgb,
+        )?;
+        if let Some(assets) = self.hot_reload_assets() {
+            assets.watch_texture(path, srgb, id);
+        }
+
+        Ok(id)
+    }
+
+    // Como `load_material_texture`, mas lido e decodificado pelas threads de assets. O id
+    // (de `texture_asset`) já pode ir nos materiais, com um placeholder até terminar
+    pub unsafe fn load_texture_async(
+        &mut self,
+        path: &Path,
+        srgb: bool,
+    ) -> Result<AssetHandle<assets::Texture>> {
+        let assets = self
+            .assets
+            .as_mut()
+            .ok_or_else(|| anyhow!("Async loading is disabled (asset_threads is 0)."))?;
+        assets.load_texture(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+            &mut self.materials,
+            path,
+            srgb,
+        )
+    }
+
+    pub fn texture_asset(&self, handle: AssetHandle<assets::Texture>) -> MaterialTextureId {
+        self.assets.as_ref().unwrap().texture(handle)
+    }
+
+    pub unsafe fn add_material_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+        srgb: bool,
+    ) -> Result<MaterialTextureId> {
+        self.materials.add_texture(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+            width,
+            height,
+            pixels,
+            srgb,
+        )
+    }
+
+    pub fn material(&self, id: MaterialId) -> Option<&Material> {
+        self.materials.get(id)
+    }
+
+    // Trocar entre opaco e um dos blends muda o pass em que as malhas do material são
+    // desenhadas, a partir do próximo frame
+    pub fn set_material_blend(&mut self, id: MaterialId, blend: BlendMode) {
+        self.materials.set_blend(id, blend);
+    }
 
-        let mut data = AppData::default();
+    // Os parâmetros podem mudar a qualquer hora; valem a partir do próximo frame
+    pub fn material_params_mut(&mut self, id: MaterialId) -> Option<&mut MaterialParams> {
+        self.materials.params_mut(id)
+    }
 
-        // Instância do Vulkan, necessário pra usar ele
-        let instance = App::create_instance(window, &entry, &mut data)?;
-        data.surface = vk_window::create_surface(&instance, window)?;
-        App::pick_physical_device(&instance, &mut data)?;
+    // Malha desenhada no próximo frame junto com a cena, pra quem guarda os objetos em
+    // outro lugar (ex: um ECS)
+    pub fn draw_mesh(&mut self, item: DrawItem) {
+        self.queued_draws.push(item);
+    }
 
-        let device = App::create_logical_device(&instance, &mut data)?;
+    // Debug: o que a cena mostra no lugar da imagem final (ver DebugView)
+    pub fn set_debug_view(&mut self, view: DebugView) {
+        self.meshes.debug_view = view;
+    }
 
-        data.swapchain = SwapchainData::create_swapchain(window, &instance, &device, &mut data)?;
-        App::create_pipeline(&device, &mut data)?;
-        // SwapchainData::create_swapchain_image_views(&device, &mut data)?;
+    pub fn debug_view(&self) -> DebugView {
+        self.meshes.debug_view
+    }
 
-        Ok(Self {
-            entry,
-            instance,
-            data,
-            device,
-        })
+    // Atalho pra visão das normais; desligar volta pra imagem final
+    pub fn set_show_normals(&mut self, enabled: bool) {
+        self.set_debug_view(if enabled {
+            DebugView::Normals
+        } else {
+            DebugView::Final
+        });
     }
 
-    unsafe fn create_logical_device(instance: &Instance, data: &mut AppData) -> Result<Device> {
-        let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    pub fn show_normals(&self) -> bool {
+        self.meshes.debug_view == DebugView::Normals
+    }
 
-        let mut unique_indices = HashSet::new();
-        unique_indices.insert(indices.graphics);
-        unique_indices.insert(indices.present);
+    // Malhas e terreno só com as arestas. Ignorado (com um aviso) sem o fillModeNonSolid
+    pub fn set_wireframe(&mut self, enabled: bool) {
+        if !self.meshes.set_wireframe(enabled) {
+            warn!("Wireframe needs the fillModeNonSolid device feature.");
+        }
+        self.terrain.wireframe = self.meshes.wireframe();
+    }
 
-        let queue_priorities = &[1.0];
-        let queue_info = unique_indices
-            .iter()
-            .map(|i| {
-                vk::DeviceQueueCreateInfo::builder()
-                    .queue_family_index(*i)
-                    .queue_priorities(queue_priorities)
-            })
-            .collect::<Vec<_>>();
+    pub fn wireframe(&self) -> bool {
+        self.meshes.wireframe()
+    }
 
-        // Layers específicas ao dispositivo.
-        // Em Vulkan moderno  as layers do escopo da instância, isso é pra
-        // ser compatível com versões anteriores que permitiam extensões
-        // especificas para cada dispositivo virtual
-        let layers = if VALIDATION_ENABLED {
-            vec![VALIDATION_LAYER.as_ptr()]
-        } else {
-            vec![]
-        };
+    // Anisotropia das texturas dos materiais, até o limite do dispositivo (1 desliga). Os
+    // sets dos materiais são reescritos nos próximos frames
+    pub unsafe fn set_max_anisotropy(&mut self, value: f32) -> Result<()> {
+        if value == self.data.config.max_anisotropy {
+            return Ok(());
+        }
 
-        // Recursos do dispositivo (o qual verificamos a existência no check_physical_device())
-        let features = vk::PhysicalDeviceFeatures::builder();
+        self.data.config.max_anisotropy = value;
+        sampler::set_max_anisotropy(value);
+        self.materials.update_samplers(&self.device)
+    }
 
-        let extensions = DEVICE_EXTENSIONS
-            .iter()
-            .map(|n| n.as_ptr())
-            .collect::<Vec<_>>();
+    pub fn max_anisotropy(&self) -> f32 {
+        self.data.config.max_anisotropy
+    }
 
-        let info = vk::DeviceCreateInfo::builder()
-            .queue_create_infos(&queue_info)
-            .enabled_layer_names(&layers)
-            .enabled_extension_names(&extensions)
-            .enabled_features(&features);
+    // 1 sem o samplerAnisotropy no dispositivo
+    pub fn max_anisotropy_limit(&self) -> f32 {
+        sampler::anisotropy_limit()
+    }
 
-        let device = instance.create_device(data.physical_device, &info, None)?;
+    // Desligado, tudo da lista vai pra cena (as estatísticas mostram 0 cortadas)
+    pub fn set_frustum_culling(&mut self, enabled: bool) {
+        self.meshes.frustum_culling = enabled;
+    }
 
-        data.present_queue = device.get_device_queue(indices.present, 0);
-        data.graphics_queue = device.get_device_queue(indices.graphics, 0);
+    pub fn frustum_culling(&self) -> bool {
+        self.meshes.frustum_culling
+    }
 
-        Ok(device)
+    // Só tem efeito com o `occlusion_culling` da configuração e um dispositivo que
+    // suporte (ver `occlusion_culling_supported`)
+    pub fn set_occlusion_culling(&mut self, enabled: bool) {
+        self.occlusion.enabled = enabled;
     }
 
-    unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> Result<()> {
-        for physical_device in instance.enumerate_physical_devices()? {
-            let properties = instance.get_physical_device_properties(physical_device);
+    pub fn occlusion_culling(&self) -> bool {
+        self.occlusion.enabled
+    }
 
-            if let Err(error) = App::check_physical_device(instance, data, physical_device) {
-                warn!(
-                    "Skipping phyisical device ('{}'): {}",
-                    properties.device_name, error
-                );
-            } else {
-                info!("Selected physical device ('{}').", properties.device_name);
-                data.physical_device = physical_device;
-                return Ok(());
-            }
+    pub fn occlusion_culling_supported(&self) -> bool {
+        self.occlusion.is_supported()
+    }
+
+    // Quem está no pixel (x, y) do framebuffer. A leitura é assíncrona: a resposta de um
+    // pixel chega um ou dois frames depois do primeiro pedido, e até lá (ou sem nada
+    // ali) é None. Chamar de novo a cada frame mantém a resposta em dia com a cena.
+    // Precisa do `picking` na configuração
+    pub fn pick(&mut self, x: u32, y: u32) -> Option<EntityId> {
+        let extent = self.data.swapchain.extent;
+        self.picking.as_mut()?.pick(x, y, extent)
+    }
+
+    // Entidades com contorno a partir do próximo frame. Precisa do `outline` na
+    // configuração (e de um formato de depth com stencil)
+    pub fn set_selection(&mut self, entities: &[EntityId]) {
+        if !entities.is_empty() && !self.outline.is_supported() {
+            warn!("Selection outline is disabled, ignoring the selection.");
         }
+        self.outline.set_selection(entities);
+    }
 
-        Err(anyhow!("Failed to find suitable physical device."))
+    pub fn selection(&self) -> Vec<EntityId> {
+        self.outline.selection()
     }
 
-    unsafe fn check_physical_device(
-        instance: &Instance,
-        data: &mut AppData,
-        physical_device: vk::PhysicalDevice,
-    ) -> Result<()> {
-        let properties = instance.get_physical_device_properties(physical_device);
-        if properties.device_type != vk::PhysicalDeviceType::DISCRETE_GPU {
-            return Err(anyhow!(SuitabilityError("Only discrete GPUs supported")));
+    pub fn set_outline_settings(&mut self, settings: OutlineSettings) {
+        self.outline.settings = settings;
+    }
+
+    pub fn outline_settings(&self) -> OutlineSettings {
+        self.outline.settings
+    }
+
+    // Views dos olhos do pass estéreo, a partir do próximo frame (ex: a pose do headset).
+    // None volta pra câmera, com os olhos a DEFAULT_IPD um do outro
+    pub fn set_stereo_views(&mut self, views: Option<[View; 2]>) {
+        self.stereo_views = views;
+    }
+
+    // Alvo do pass estéreo (uma camada por olho, em SHADER_READ_ONLY_OPTIMAL depois do
+    // frame), com o `stereo` da configuração e um dispositivo com multiview
+    pub fn stereo_target(&self) -> Option<(vk::Image, vk::ImageView)> {
+        self.stereo.as_ref().map(|s| (s.image(), s.view()))
+    }
+
+    // Põe a sonda de reflexo em `position`, com reflexos até `radius` dela, e captura a
+    // cena em volta no próximo frame. Precisa do `reflection_probes` na configuração
+    pub fn place_reflection_probe(&mut self, position: glm::Vec3, radius: f32) {
+        if !self.reflection_probe.is_supported() {
+            warn!("Reflection probes are disabled, ignoring the placement.");
+            return;
         }
+        self.reflection_probe.place(position, radius);
+    }
 
-        let features = instance.get_physical_device_features(physical_device);
-        if features.geometry_shader != vk::TRUE {
-            return Err(anyhow!(SuitabilityError("Missing geometry shader support")));
+    pub fn remove_reflection_probe(&mut self) {
+        self.reflection_probe.remove();
+    }
+
+    // Captura de novo no mesmo lugar (a cena em volta mudou)
+    pub fn capture_reflection_probe(&mut self) {
+        self.reflection_probe.capture();
+    }
+
+    // Troca a grade de sondas de irradiância e começa a capturar, uma sonda por frame. None
+    // tira a grade. Precisa do `irradiance_probes` na configuração
+    pub fn set_irradiance_grid(&mut self, settings: Option<IrradianceGridSettings>) {
+        if settings.is_some() && !self.irradiance.is_supported() {
+            warn!("Irradiance probes are disabled, ignoring the grid.");
+            return;
         }
+        self.irradiance.set_settings(settings);
+    }
 
-        QueueFamilyIndices::get(instance, data, physical_device)?;
+    pub fn irradiance_grid(&self) -> Option<IrradianceGridSettings> {
+        self.irradiance.settings()
+    }
 
-        SwapchainData::check_physical_device_extensions(instance, physical_device)?;
+    // Captura a grade de novo (a cena mudou); as sondas antigas valem até lá
+    pub fn capture_irradiance_grid(&mut self) {
+        self.irradiance.capture();
+    }
 
-        let support = SwapchainSupport::get(instance, data, physical_device)?;
-        if support.formats.is_empty() || support.present_modes.is_empty() {
-            return Err(anyhow!(SuitabilityError("Insuficient swapchain support")));
+    // Imagem do headset adquirida nesse frame; nula sem VR
+    fn xr_image(&self) -> vk::Image {
+        #[cfg(feature = "xr")]
+        if let Some(xr) = &self.xr {
+            return xr.image();
         }
 
+        vk::Image::null()
+    }
+
+    // Os olhos vão pra imagem do headset
+    unsafe fn record_xr_copy(&self, command_buffer: vk::CommandBuffer) {
+        #[cfg(feature = "xr")]
+        if let (Some(xr), Some(stereo)) = (&self.xr, &self.stereo) {
+            xr.record_copy(&self.device, command_buffer, stereo);
+        }
+    }
+
+    // O runtime encerrou a sessão (saída pelo menu do headset, por exemplo)
+    #[cfg(feature = "xr")]
+    pub fn xr_exit_requested(&self) -> bool {
+        self.xr.as_ref().is_some_and(XrContext::exit_requested)
+    }
+
+    // Como o `pick`, mas na CPU e na hora: o raio da câmera por (x, y) contra os nós da
+    // cena. Não vê o que foi desenhado com `draw_mesh`
+    pub fn raycast(&self, x: f32, y: f32, test: RayTest) -> Option<Hit> {
+        let ray = self.camera.ray(x, y, self.data.swapchain.extent);
+        self.scene.raycast(&ray, &self.meshes, test)
+    }
+
+    // Luz usada no próximo frame junto com as da cena
+    pub fn shadow_settings(&self) -> ShadowSettings {
+        self.shadows.settings()
+    }
+
+    // Bias, filtro e alcance valem a partir do próximo frame. Trocar a resolução ou o
+    // número de cascatas recria o shadow map, então espera a GPU terminar
+    pub unsafe fn set_shadow_settings(&mut self, settings: ShadowSettings) -> Result<()> {
+        if self.shadows.requires_resize(&settings) {
+            self.device.device_wait_idle()?;
+            self.shadows
+                .resize(&self.instance, &self.device, &self.data, &settings)?;
+            self.data.frame_descriptors.write_shadow_map(
+                &self.device,
+                self.shadows.view(),
+                &self.shadows.point_views(),
+                self.shadows.sampler,
+            );
+        }
+
+        self.shadows.set_settings(settings);
         Ok(())
     }
 
-    pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
-        let vertex_shader = include_bytes!("resources/shaders/vert.spv");
-        let fragment_shader = include_bytes!("resources/shaders/frag.spv");
+    // Pinta cada cascata de sombra de uma cor, pra ver onde ficam as divisões
+    pub fn set_show_cascades(&mut self, enabled: bool) {
+        self.shadows.show_cascades = enabled;
+    }
 
-        let vertex_shader_module = App::create_shader_module(device, &vertex_shader[..])?;
-        let fragment_shader_module = App::create_shader_module(device, &fragment_shader[..])?;
+    pub fn show_cascades(&self) -> bool {
+        self.shadows.show_cascades
+    }
 
-        let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::VERTEX)
-            .module(vertex_shader_module)
-            .name(b"main\0");
+    pub fn add_light(&mut self, light: SceneLight) {
+        self.queued_lights.push(light);
+    }
 
-        let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .module(fragment_shader_module)
-            // ```specialization_info``` define constantes da shader. O benefício dessas constantes
-            // é a eliminação de IFs contendo elas
-            // .specialization_info(specialization_info)
-            .name(b"main\0");
+    // Só pro próximo frame, como o `add_light`. Sem efeito no forward
+    pub fn add_decal(&mut self, decal: SceneDecal) {
+        self.queued_decals.push(decal);
+    }
 
-        device.destroy_shader_module(vertex_shader_module, None);
-        device.destroy_shader_module(fragment_shader_module, None);
+    pub fn ambient(&self) -> glm::Vec3 {
+        self.ambient
+    }
+
+    // Linear, somada em todas as superfícies iluminadas. Com o céu ligado, quem vale é a
+    // irradiância dele
+    pub fn set_ambient(&mut self, ambient: glm::Vec3) {
+        self.ambient = ambient;
+    }
+
+    pub fn sky(&self) -> SkySettings {
+        self.sky.settings
+    }
+
+    // Vale a partir do próximo frame. A luz direcional da cena não acompanha o sol sozinha
+    pub fn set_sky(&mut self, settings: SkySettings) {
+        self.sky.settings = settings;
+    }
 
+    pub fn terrain(&self) -> TerrainSettings {
+        self.terrain.settings
+    }
+
+    // Também vale a partir do próximo frame, não recria nada
+    pub fn set_terrain(&mut self, settings: TerrainSettings) {
+        self.terrain.settings = settings;
+    }
+
+    pub fn fog(&self) -> FogSettings {
+        self.fog.settings
+    }
+
+    // Os parâmetros valem a partir do próximo frame; ligar ou desligar cria ou tira os
+    // volumes e o pass do grafo, então recria tudo que depende da swapchain
+    pub unsafe fn set_fog(&mut self, window: &Window, settings: FogSettings) -> Result<()> {
+        let toggled = settings.enabled != self.data.config.fog.enabled;
+        self.fog.settings = settings;
+        self.data.config.fog = settings;
+        if toggled {
+            self.recreate_swapchain(window)?;
+        }
         Ok(())
     }
 
-    pub unsafe fn create_shader_module(device: &Device, bytecode: &[u8]) -> Result<vk::ShaderModule> {
-        let bytecode = Vec::<u8>::from(bytecode);
-        let (prefix, code, suffix) = bytecode.align_to::<u32>();
+    pub fn god_rays(&self) -> GodRaySettings {
+        self.god_rays.settings
+    }
 
-        if !prefix.is_empty() || !suffix.is_empty() {
-            return Err(anyhow!("Shader bytecode is not properly aligned"));
+    // Igual à névoa: só ligar ou desligar recria o que depende da swapchain
+    pub unsafe fn set_god_rays(&mut self, window: &Window, settings: GodRaySettings) -> Result<()> {
+        let toggled = settings.enabled != self.data.config.god_rays.enabled;
+        self.god_rays.settings = settings;
+        self.data.config.god_rays = settings;
+        if toggled {
+            self.recreate_swapchain(window)?;
         }
+        Ok(())
+    }
 
-        let info = vk::ShaderModuleCreateInfo::builder()
-            .code_size(bytecode.len())
-            .code(code);
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
 
-        Ok(device.create_shader_module(&info, None)?)
+    // As matrizes de mundo são recalculadas no começo de cada frame
+    pub fn scene_mut(&mut self) -> &mut Scene {
+        &mut self.scene
+    }
+
+    // Retorna true se o evento foi consumido pela interface
+    pub fn ui_event(&mut self, event: &WindowEvent) -> bool {
+        self.ui.on_event(event)
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    // None sem a saída de áudio. Sons presos a nós usam `Audio::play_on_node` com a `scene`
+    #[cfg(feature = "audio")]
+    pub fn audio_mut(&mut self) -> Option<&mut Audio> {
+        self.audio.as_mut()
+    }
+
+    #[cfg(feature = "audio")]
+    pub fn play_on_node(&mut self, path: impl AsRef<Path>, node: NodeId) -> Result<EmitterId> {
+        match &mut self.audio {
+            Some(audio) => audio.play_on_node(path, &self.scene, node),
+            None => Err(anyhow!("Audio output is not available.")),
+        }
+    }
+
+    // Um passo fixo do áudio: o ouvinte vai pra câmera e os emissores presos a nós
+    // acompanham a cena
+    #[cfg(feature = "audio")]
+    pub fn update_audio(&mut self) {
+        if let Some(audio) = &mut self.audio {
+            audio.set_listener(self.camera.position, self.camera.right());
+            audio.update(&self.scene);
+        }
+    }
+
+    // O aspect é atualizado sozinho quando a swapchain muda de tamanho
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    pub fn set_low_latency(&mut self, enabled: bool) {
+        self.present_timer.low_latency = enabled;
+    }
+
+    pub fn set_frame_pacing(&mut self, enabled: bool) {
+        self.pacer.enabled = enabled;
+    }
+
+    pub fn frame_pacing(&self) -> bool {
+        self.pacer.enabled
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    pub fn attachment_ops(&self) -> AttachmentOps {
+        self.data.render_pass.ops
+    }
+
+    pub unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
+        self.device
+            .device_wait_idle()
+            .map_err(RendererError::from)?;
+        let ops = self.data.render_pass.ops;
+        self.destroy_swapchain();
+        self.create_swapchain_objects(window, ops)
+    }
+
+    // Tudo que depende da swapchain. O command pool já precisa existir
+    unsafe fn create_swapchain_objects(
+        &mut self,
+        window: &Window,
+        ops: AttachmentOps,
+    ) -> Result<()> {
+        self.data.swapchain =
+            SwapchainData::create_swapchain(window, &self.instance, &self.device, &self.data)?;
+        self.data.render_pass =
+            RenderPassData::create(&self.instance, &self.device, &self.data, ops)?;
+        self.data.post_pass = PostPassData::create(&self.instance, &self.device, &self.data)?;
+        self.frame_graph = FrameGraph::create(&self.instance, &self.device, &self.data)?;
+        App::create_pipeline(&self.device, &mut self.data)?;
+        self.overlay.create_pipeline(&self.device, &self.data)?;
+        self.ui.create_swapchain_objects(&self.device, &self.data)?;
+        self.text.create_pipeline(&self.device, &self.data)?;
+        self.sprites.create_pipeline(&self.device, &self.data)?;
+        self.occlusion
+            .create_pipeline(&self.instance, &self.device, &self.data)?;
+        self.meshes.create_pipeline(
+            &self.device,
+            &self.data,
+            &self.materials,
+            &self.skinning,
+            &self.occlusion,
+        )?;
+        self.deferred.create_pipeline(&self.device, &self.data)?;
+        self.decals
+            .create_pipeline(&self.device, &self.data, &self.materials)?;
+        self.sky.create_pipeline(&self.device, &self.data)?;
+        self.outline.create_pipeline(&self.device, &self.data)?;
+        self.terrain.create_pipeline(&self.device, &self.data)?;
+        self.fog
+            .create_pipeline(&self.instance, &self.device, &self.data)?;
+        self.god_rays
+            .create_pipeline(&self.instance, &self.device, &self.data)?;
+        self.exposure.write_target(&self.device, &self.data);
+        self.post_chain
+            .create_targets(&self.instance, &self.device, &self.data)?;
+        self.tone_mapper
+            .create_pipeline(&self.device, &self.data, &self.exposure)?;
+        self.grading.create_pipeline(&self.device, &self.data)?;
+        self.fxaa.create_pipeline(&self.device, &self.data)?;
+        App::create_command_buffers(&self.device, &mut self.data)?;
+        self.data
+            .images_in_flight
+            .resize(self.data.swapchain.images.len(), vk::Fence::null());
+        self.camera.set_extent(self.data.swapchain.logical_extent());
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.resize(&self.instance, &self.device, &self.data)?;
+        }
+        if let Some(stereo) = &mut self.stereo {
+            let extent = self.data.xr_extent.unwrap_or(self.data.swapchain.extent);
+            stereo.resize(&self.instance, &self.device, &self.data, extent)?;
+        }
+
+        self.pacer.reset(&self.device, self.data.swapchain.chain)?;
+
+        Ok(())
     }
 
-    pub unsafe fn render(&self, window: &Window) -> Result<()> {
+    // A surface morreu (ex: o compositor reiniciou): só ela e a swapchain são recriadas
+    unsafe fn recover_surface(&mut self, window: &Window) -> Result<()> {
+        warn!("Surface lost, recreating surface and swapchain.");
+
+        self.device.device_wait_idle()?;
+        let ops = self.data.render_pass.ops;
+        self.destroy_swapchain();
+
+        self.instance.destroy_surface_khr(self.data.surface, None);
+        self.data.surface = vk_window::create_surface(&self.instance, window)?;
+
+        self.create_swapchain_objects(window, ops)
+    }
+
+    // O dispositivo lógico morreu: tudo que foi criado nele é jogado fora e recriado num
+    // dispositivo novo. Recursos criados por fora (buffers, texturas) também morreram, e
+    // quem é dono deles deve recriar e reenviar quando `device_generation` mudar
+    unsafe fn recover_device(&mut self, window: &Window) -> Result<()> {
+        warn!("Device lost, recreating logical device and per-frame resources.");
+
+        // Num dispositivo perdido isso retorna DEVICE_LOST na hora, mas não trava
+        let _ = self.device.device_wait_idle();
+        let ops = self.data.render_pass.ops;
+        // A gravação em andamento é encerrada junto (o que já foi lido é salvo)
+        self.destroy_device_objects();
+
+        // O dispositivo físico pode ter sumido também (eGPU desconectada)
+        App::pick_physical_device(&self.instance, &mut self.data)?;
+        self.device = App::create_logical_device(&self.instance, &mut self.data)?;
+        #[cfg(feature = "xr")]
+        if let Some(xr) = &mut self.xr {
+            App::create_xr_session(&self.instance, &self.device, &self.data, xr)?;
+        }
+        self.data.frame_descriptors =
+            FrameDescriptors::create(&self.instance, &self.device, &self.data)?;
+
+        App::create_command_pool(&self.instance, &self.device, &mut self.data)?;
+        self.ui.create_device_objects(&self.device)?;
+        self.text.create_device_objects(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+        )?;
+        self.sprites.create_device_objects(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+        )?;
+        self.materials.create_device_objects(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+        )?;
+        self.streamer = App::create_streamer(&self.instance, &self.device, &self.data)?;
+        if let Some(assets) = &mut self.assets {
+            assets.create_device_objects(&self.instance, &self.device, &self.data)?;
+        }
+        self.meshes.create_device_objects(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+        )?;
+        self.skinning
+            .create_device_objects(&self.instance, &self.device, &self.data)?;
+        self.occlusion
+            .create_device_objects(&self.instance, &self.device, &self.data)?;
+        self.picking = App::create_picking(&self.instance, &self.device, &self.data)?;
+        self.shadows
+            .create_device_objects(&self.instance, &self.device, &self.data)?;
+        self.data.frame_descriptors.write_shadow_map(
+            &self.device,
+            self.shadows.view(),
+            &self.shadows.point_views(),
+            self.shadows.sampler,
+        );
+        self.exposure
+            .create_device_objects(&self.instance, &self.device, &self.data)?;
+        self.post_chain
+            .create_device_objects(&self.instance, &self.device, &self.data)?;
+        self.grading.create_device_objects(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut self.uploads,
+        )?;
+        self.create_swapchain_objects(window, ops)?;
+        self.stereo = App::create_stereo(&self.instance, &self.device, &self.data)?;
+        self.reflection_probe
+            .create_device_objects(&self.instance, &self.device, &self.data)?;
+        self.data.frame_descriptors.write_reflection_probe(
+            &self.device,
+            self.reflection_probe.view(),
+            self.reflection_probe.sampler,
+        );
+        self.irradiance
+            .create_device_objects(&self.instance, &self.device, &self.data)?;
+        self.data
+            .frame_descriptors
+            .write_irradiance_probes(&self.device, self.irradiance.buffer());
+        App::create_sync_objects(&self.device, &mut self.data)?;
+        #[cfg(feature = "profiling")]
+        {
+            self.profiler = App::create_profiler(&self.instance, &self.device, &self.data);
+        }
+
+        self.present_timer = PresentTimer::new(
+            self.data.capabilities.present_wait,
+            self.present_timer.low_latency,
+        );
+        self.pacer = FramePacer::new(
+            self.data.capabilities.display_timing,
+            self.pacer.enabled,
+            self.data.config.fps_limit,
+        );
+        self.pacer.reset(&self.device, self.data.swapchain.chain)?;
+        self.frame = 0;
+        self.generation += 1;
+
+        info!(
+            "Recovered from device loss (generation {}).",
+            self.generation
+        );
+
         Ok(())
     }
 
+    // Incrementa toda vez que o dispositivo é recriado depois de um DEVICE_LOST
+    pub fn device_generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Tudo que pertence ao dispositivo lógico, inclusive ele mesmo
+    unsafe fn destroy_device_objects(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.destroy(&self.device);
+        }
+        #[cfg(feature = "xr")]
+        if let Some(xr) = &mut self.xr {
+            xr.destroy_session();
+        }
+
+        self.uploads.destroy(&self.device);
+        if let Some(assets) = &mut self.assets {
+            assets.destroy(&self.device);
+        }
+        self.overlay.destroy(&self.device);
+        #[cfg(feature = "profiling")]
+        if let Some(mut profiler) = self.profiler.take() {
+            profiler.destroy(&self.device);
+        }
+        self.destroy_swapchain();
+        self.ui.destroy(&self.device);
+        self.text.destroy(&self.device);
+        self.sprites.destroy(&self.device);
+        self.meshes.destroy(&self.device);
+        self.occlusion.destroy(&self.device);
+        if let Some(mut picking) = self.picking.take() {
+            picking.destroy(&self.device);
+        }
+        if let Some(mut stereo) = self.stereo.take() {
+            stereo.destroy(&self.device);
+        }
+        self.reflection_probe.destroy(&self.device);
+        self.irradiance.destroy(&self.device);
+        self.skinning.destroy(&self.device);
+        if let Some(mut streamer) = self.streamer.take() {
+            streamer.destroy(&self.device);
+        }
+        self.materials.destroy(&self.device);
+        self.shadows.destroy(&self.device);
+        self.exposure.destroy(&self.device);
+        self.post_chain.destroy(&self.device);
+        self.grading.destroy(&self.device);
+        self.data.frame_descriptors.destroy(&self.device);
+        // Os samplers ficam no cache até aqui, todo mundo que usava já foi destruído
+        sampler::clear(&self.device);
+        pipeline::clear(&self.device);
+
+        self.data
+            .in_flight_fences
+            .drain(..)
+            .for_each(|f| self.device.destroy_fence(f, None));
+        self.data
+            .render_finished_semaphores
+            .drain(..)
+            .for_each(|s| self.device.destroy_semaphore(s, None));
+        self.data
+            .image_available_semaphores
+            .drain(..)
+            .for_each(|s| self.device.destroy_semaphore(s, None));
+        self.data.images_in_flight.clear();
+        self.device
+            .destroy_command_pool(self.data.command_pool, None);
+
+        // O que sobrou vazou (só com a feature leak-tracking)
+        leaks::report();
+
+        // Por último o próprio dispositivo
+        self.device.destroy_device(None);
+    }
+
+    unsafe fn destroy_swapchain(&mut self) {
+        // A thread do present wait pode estar esperando na swapchain
+        self.present_timer.reset();
+        self.device
+            .free_command_buffers(self.data.command_pool, &self.data.command_buffers);
+        leaks::destroy_pipeline(&self.device, self.data.pipeline);
+        self.device
+            .destroy_pipeline_layout(self.data.pipeline_layout, None);
+        self.overlay.destroy_pipeline(&self.device);
+        self.ui.destroy_swapchain_objects(&self.device);
+        self.text.destroy_pipeline(&self.device);
+        self.sprites.destroy_pipeline(&self.device);
+        self.meshes.destroy_pipeline(&self.device);
+        self.occlusion.destroy_pipeline(&self.device);
+        self.deferred.destroy_pipeline(&self.device);
+        self.decals.destroy_pipeline(&self.device);
+        self.sky.destroy_pipeline(&self.device);
+        self.outline.destroy_pipeline(&self.device);
+        self.terrain.destroy_pipeline(&self.device);
+        self.fog.destroy_pipeline(&self.device);
+        self.god_rays.destroy_pipeline(&self.device);
+        self.post_chain.destroy_targets(&self.device);
+        self.tone_mapper.destroy_pipeline(&self.device);
+        self.grading.destroy_pipeline(&self.device);
+        self.fxaa.destroy_pipeline(&self.device);
+        self.frame_graph.destroy(&self.device);
+        self.data.post_pass.destroy(&self.device);
+        self.data.render_pass.destroy(&self.device);
+        self.data.swapchain.destroy(&self.device);
+    }
+
     pub unsafe fn destroy(&mut self) {
-        if VALIDATION_ENABLED {
+        // Nada pode ser destruído enquanto a GPU ainda está usando. Se falhar (dispositivo
+        // perdido) não tem mais nada rodando nela, e o resto ainda tem que ser liberado
+        if let Err(e) = self.device.device_wait_idle() {
+            error!("Failed to wait for the device before shutdown: {}", e);
+        }
+
+        self.destroy_device_objects();
+        if let Some(assets) = &mut self.assets {
+            assets.stop_workers();
+        }
+
+        if self.data.config.validation_layers {
             // destruimos nosso logger ...
             self.instance
                 .destroy_debug_utils_messenger_ext(self.data.messenger, None);
         }
 
-        // ... Nossa swapchain...
-        self.data.swapchain.destroy(&self.device);
-        // ... Nosso dispositivo virtual...
-        self.device.destroy_device(None);
         // ... Nosso Surface...
         self.instance.destroy_surface_khr(self.data.surface, None);
         // ... E nós mesmos...
@@ -233,10 +3714,63 @@ impl App {
             .map(|e| e.as_ptr())
             .collect::<Vec<_>>();
 
+        // No macOS/iOS o loader só lista o MoltenVK se pedirmos pelas implementações de
+        // portabilidade explicitamente
+        let available_extensions = entry
+            .enumerate_instance_extension_properties(None)?
+            .iter()
+            .map(|e| e.extension_name)
+            .collect::<HashSet<_>>();
+
+        // Necessária pro VK_KHR_portability_subset e pra consultar recursos de extensões
+        // (vkGetPhysicalDeviceFeatures2) numa instância 1.0
+        data.physical_device_properties2 =
+            available_extensions.contains(&vk::KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION.name);
+        if data.physical_device_properties2 {
+            extensions.push(
+                vk::KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION
+                    .name
+                    .as_ptr(),
+            );
+        }
+
+        // Colorspaces além do sRGB (HDR10, Display P3...)
+        data.swapchain_colorspace =
+            available_extensions.contains(&vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION.name);
+        if data.swapchain_colorspace {
+            extensions.push(vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION.name.as_ptr());
+        }
+
+        // Exigida pelo VK_EXT_full_screen_exclusive
+        data.surface_capabilities2 =
+            available_extensions.contains(&vk::KHR_GET_SURFACE_CAPABILITIES2_EXTENSION.name);
+        if data.surface_capabilities2 {
+            extensions.push(vk::KHR_GET_SURFACE_CAPABILITIES2_EXTENSION.name.as_ptr());
+        }
+
+        // O que o runtime de VR pede e ainda não está na lista
+        for name in &data.xr_instance_extensions {
+            if !extensions
+                .iter()
+                .any(|e| CStr::from_ptr(*e) == name.as_c_str())
+            {
+                extensions.push(name.as_ptr());
+            }
+        }
+
+        let flags = if available_extensions.contains(&PORTABILITY_ENUMERATION_EXTENSION) {
+            info!("Enabling extensions for portability enumeration.");
+            extensions.push(PORTABILITY_ENUMERATION_EXTENSION.as_ptr());
+            // VK_INSTANCE_CREATE_ENUMERATE_PORTABILITY_BIT_KHR
+            vk::InstanceCreateFlags::from_bits_unchecked(0x1)
+        } else {
+            vk::InstanceCreateFlags::empty()
+        };
+
         let mut layers: Vec<*const i8> = Vec::new();
 
-        // Se a validação estiver ligada (= modo debug)
-        if VALIDATION_ENABLED {
+        // Se a validação estiver ligada (= modo debug, ou --no-validation)
+        if data.config.validation_layers {
             // Verificamos as layers disponíveis
             let available_layers = entry
                 .enumerate_instance_layer_properties()?
@@ -246,26 +3780,54 @@ impl App {
 
             // Caso não tenha a que queremos (as de validação)
             if !available_layers.contains(&VALIDATION_LAYER) {
-                return Err(anyhow!("Validation layer requested but not supported."));
+                return Err(anyhow!(RendererError::InstanceCreation(
+                    "validation layer requested but not supported".to_string()
+                )));
             }
 
             // Adicionamos as Validation Layers e extensões de debug para melhores erros
             extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
             layers = vec![VALIDATION_LAYER.as_ptr()];
+
+            // Implementada pela própria camada de validação
+            if data.config.validation.any() {
+                extensions.push(vk::EXT_VALIDATION_FEATURES_EXTENSION.name.as_ptr());
+            }
+        } else if data.config.validation.any() {
+            warn!("Validation features requested with validation disabled, ignoring.");
+        }
+
+        // Sem validação o debug utils só serve pros rótulos que o RenderDoc mostra
+        data.debug_utils = data.config.validation_layers
+            || (cfg!(feature = "renderdoc")
+                && available_extensions.contains(&vk::EXT_DEBUG_UTILS_EXTENSION.name));
+        if data.debug_utils && !data.config.validation_layers {
+            extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
         }
 
         // Cria a Instância com os parâmetros
-        let info = vk::InstanceCreateInfo::builder()
+        let mut info = vk::InstanceCreateInfo::builder()
+            .flags(flags)
             .application_info(&application_info)
             .enabled_layer_names(&layers)
             .enabled_extension_names(&extensions);
 
+        let enables = data.config.validation.to_vk();
+        let mut validation_features =
+            vk::ValidationFeaturesEXT::builder().enabled_validation_features(&enables);
+        if data.config.validation_layers && !enables.is_empty() {
+            info!("Enabling validation features {:?}.", enables);
+            info = info.push_next(&mut validation_features);
+        }
+
         // Usa o entry, que contém as funções carregadas, pra criar uma instância de Vulkan
         // com as informações que especificamos
-        let instance = entry.create_instance(&info, None)?;
+        let instance = entry
+            .create_instance(&info, None)
+            .map_err(|e| RendererError::InstanceCreation(e.to_string()))?;
 
         // Caso a validação esteja ligada, adicionamos um logger customizado
-        if VALIDATION_ENABLED {
+        if data.config.validation_layers {
             let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
                 .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
                 .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
@@ -283,8 +3845,60 @@ impl App {
 pub struct AppData {
     pub messenger: vk::DebugUtilsMessengerEXT,
     pub physical_device: vk::PhysicalDevice,
+    pub config: AppConfig,
+    // Se VK_KHR_get_physical_device_properties2 foi habilitada na instância
+    pub physical_device_properties2: bool,
+    // Se VK_EXT_swapchain_colorspace foi habilitada na instância
+    pub swapchain_colorspace: bool,
+    // Se VK_KHR_get_surface_capabilities2 foi habilitada na instância
+    pub surface_capabilities2: bool,
+    // Se VK_EXT_debug_utils foi habilitada (rótulos nos command buffers)
+    pub debug_utils: bool,
+    pub fullscreen: FullscreenMode,
+    pub windowed: Option<WindowedState>,
+    // O que foi realmente habilitado no dispositivo lógico
+    pub capabilities: DeviceCapabilities,
     pub graphics_queue: vk::Queue,
     pub surface: vk::SurfaceKHR,
     pub present_queue: vk::Queue,
+    // Fila 0 das famílias dedicadas de compute e de transferência. Sem a família, é a
+    // própria fila de gráficos
+    pub compute_queue: ExtraQueue,
+    pub transfer_queue: ExtraQueue,
+    pub extra_queues: Vec<ExtraQueue>,
+    // Fila do streaming de texturas e dos assets, quando algum dos dois está ligado e o
+    // dispositivo tem uma família com transferência. Sem ela os dois usam a de gráficos
+    pub streaming_queue: Option<ExtraQueue>,
+    // Gráficos e transferência, quando a fila de transferência é de outra família: o que
+    // ela escreve é criado CONCURRENT entre as duas, sem troca de dono. Vazio nos outros
+    // casos
+    pub transfer_families: Vec<u32>,
     pub swapchain: SwapchainData,
+    // Pass da cena, no alvo HDR
+    pub render_pass: RenderPassData,
+    // Tone mapping e 2D, direto na swapchain
+    pub post_pass: PostPassData,
+    // Uniform buffer (câmera) de cada frame em voo, lido pela pipeline principal
+    pub frame_descriptors: FrameDescriptors,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    pub command_pool: vk::CommandPool,
+    pub command_buffers: Vec<vk::CommandBuffer>,
+    // Sincronização entre CPU e GPU (fences) e entre etapas da GPU (semáforos)
+    pub image_available_semaphores: Vec<vk::Semaphore>,
+    pub render_finished_semaphores: Vec<vk::Semaphore>,
+    pub in_flight_fences: Vec<vk::Fence>,
+    pub images_in_flight: Vec<vk::Fence>,
+    // O que o runtime de OpenXR exige: extensões da instância e do dispositivo, o
+    // dispositivo físico ligado no headset e o tamanho de cada olho. Vazios sem VR
+    pub xr_instance_extensions: Vec<CString>,
+    pub xr_device_extensions: Vec<CString>,
+    pub xr_physical_device: Option<vk::PhysicalDevice>,
+    pub xr_extent: Option<vk::Extent2D>,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ExtraQueue {
+    pub family: u32,
+    pub queue: vk::Queue,
 }
@@ -0,0 +1,500 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    material::{MaterialTextureId, Materials, PreparedTexture},
+    mesh::{MeshData, MeshId, MeshRenderer, PreparedMesh},
+    texture::{self, FormatSupport, TextureData},
+    upload::{TransferContext, UploadQueue},
+};
+
+// Tipos que só dizem pro que o handle aponta
+#[derive(Debug)]
+pub enum Mesh {}
+#[derive(Debug)]
+pub enum Texture {}
+
+// Asset pedido com `App::load_*_async`. O id por trás dele (MeshId ou MaterialTextureId)
+// já vale na hora, com um placeholder no lugar, e passa a mostrar o arquivo quando ele
+// termina de carregar
+pub struct AssetHandle<T> {
+    index: usize,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> AssetHandle<T> {
+    fn new(index: usize) -> Self {
+        Self {
+            index,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for AssetHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for AssetHandle<T> {}
+
+impl<T> PartialEq for AssetHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for AssetHandle<T> {}
+
+impl<T> Hash for AssetHandle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for AssetHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AssetHandle").field(&self.index).finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AssetState {
+    Loading,
+    Ready,
+    // Continua com o que tinha: o placeholder, ou a versão anterior num reload
+    Failed(String),
+}
+
+#[derive(Clone, Debug)]
+enum Job {
+    // Com um nome, só aquele objeto do OBJ; sem, todos juntos
+    Mesh(PathBuf, Option<String>),
+    Texture(PathBuf, bool),
+}
+
+impl Job {
+    fn path(&self) -> &Path {
+        match self {
+            Job::Mesh(path, _) | Job::Texture(path, _) => path,
+        }
+    }
+}
+
+// O que as threads devolvem, só na CPU
+#[derive(Debug)]
+enum Decoded {
+    Mesh(MeshData),
+    Texture(TextureData),
+}
+
+#[derive(Debug)]
+enum Prepared {
+    Mesh(PreparedMesh),
+    Texture(PreparedTexture),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Target {
+    Mesh(MeshId),
+    Texture(MaterialTextureId),
+}
+
+#[derive(Debug)]
+enum Stage {
+    Loading,
+    Decoded(Decoded),
+    // Criado na GPU, com os uploads na fila de transferência
+    Uploading(Prepared),
+    Ready,
+    Failed(String),
+}
+
+#[derive(Debug)]
+struct Entry {
+    // Guardado pra carregar de novo quando o arquivo muda
+    job: Job,
+    target: Target,
+    stage: Stage,
+    // Mudou no disco enquanto carregava; vai de novo quando terminar
+    stale: bool,
+}
+
+// Carrega malhas e texturas em segundo plano: threads leem e decodificam os arquivos, e
+// o resultado sobe pela fila de transferência enquanto o placeholder é desenhado. A troca
+// acontece em `update`, entre frames
+#[derive(Debug)]
+pub struct AssetManager {
+    jobs: Option<mpsc::Sender<(usize, Job)>>,
+    results: mpsc::Receiver<(usize, Result<Decoded>)>,
+    workers: Vec<JoinHandle<()>>,
+    entries: Vec<Entry>,
+    // Separada da fila do frame, gravada no command buffer de `transfer`
+    uploads: UploadQueue,
+    transfer: TransferContext,
+}
+
+impl AssetManager {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let support = Arc::new(FormatSupport::query(instance, data));
+        let (jobs, receiver) = mpsc::channel::<(usize, Job)>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (sender, results) = mpsc::channel();
+
+        let workers = (0..data.config.asset_threads)
+            .map(|i| {
+                let receiver = receiver.clone();
+                let sender = sender.clone();
+                let support = support.clone();
+                thread::Builder::new()
+                    .name(format!("assets-{}", i))
+                    .spawn(move || loop {
+                        // O lock só dura o recv; o resto roda em paralelo
+                        let job = receiver.lock().unwrap().recv();
+                        let (index, job) = match job {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        };
+                        if sender.send((index, decode(&job, &support))).is_err() {
+                            break;
+                        }
+                    })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            jobs: Some(jobs),
+            results,
+            workers,
+            entries: vec![],
+            uploads: UploadQueue::for_transfer_queue(data.config.upload_budget),
+            transfer: TransferContext::create(instance, device, data)?,
+        })
+    }
+
+    // Depois de perder o dispositivo. O que estava subindo é preparado de novo
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        self.transfer = TransferContext::create(instance, device, data)?;
+        Ok(())
+    }
+
+    // Um cubo no lugar até o OBJ carregar. Todos os objetos do arquivo viram uma malha
+    pub unsafe fn load_mesh(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        meshes: &mut MeshRenderer,
+        path: &Path,
+    ) -> Result<AssetHandle<Mesh>> {
+        let placeholder = meshes.add(
+            instance,
+            device,
+            data,
+            uploads,
+            MeshData::cube(1.0),
+            vec![],
+            vec![],
+        )?;
+
+        let index = self.push(Target::Mesh(placeholder), Job::Mesh(path.into(), None))?;
+        Ok(AssetHandle::new(index))
+    }
+
+    // Um xadrez cinza no lugar das texturas de cor, ou a normal reta nas de dados (o caso
+    // mais comum delas, e neutro no shading)
+    pub unsafe fn load_texture(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        materials: &mut Materials,
+        path: &Path,
+        srgb: bool,
+    ) -> Result<AssetHandle<Texture>> {
+        let placeholder = if srgb {
+            let pixels = (0..16)
+                .flat_map(|i| {
+                    let value = if (i % 4 + i / 4) % 2 == 0 { 96 } else { 160 };
+                    [value, value, value, 255]
+                })
+                .collect();
+            materials.add_texture(instance, device, data, uploads, 4, 4, pixels, true)?
+        } else {
+            let pixels = vec![128, 128, 255, 255];
+            materials.add_texture(instance, device, data, uploads, 1, 1, pixels, false)?
+        };
+
+        let index = self.push(
+            Target::Texture(placeholder),
+            Job::Texture(path.into(), srgb),
+        )?;
+        Ok(AssetHandle::new(index))
+    }
+
+    // Malha já criada a partir de `path` (um objeto dele, com `object`), que passa a ser
+    // recarregada quando o arquivo muda
+    pub fn watch_mesh(&mut self, path: &Path, object: Option<String>, id: MeshId) {
+        self.entries.push(Entry {
+            job: Job::Mesh(path.into(), object),
+            target: Target::Mesh(id),
+            stage: Stage::Ready,
+            stale: false,
+        });
+    }
+
+    pub fn watch_texture(&mut self, path: &Path, srgb: bool, id: MaterialTextureId) {
+        self.entries.push(Entry {
+            job: Job::Texture(path.into(), srgb),
+            target: Target::Texture(id),
+            stage: Stage::Ready,
+            stale: false,
+        });
+    }
+
+    fn push(&mut self, target: Target, job: Job) -> Result<usize> {
+        let index = self.entries.len();
+        self.send(index, job.clone())?;
+        self.entries.push(Entry {
+            job,
+            target,
+            stage: Stage::Loading,
+            stale: false,
+        });
+
+        Ok(index)
+    }
+
+    fn send(&self, index: usize, job: Job) -> Result<()> {
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send((index, job)).ok())
+            .ok_or_else(|| anyhow!("Asset threads stopped."))
+    }
+
+    // `path` mudou no disco (canônico, como vem do FileWatcher). Tudo que veio dele é lido
+    // de novo; até terminar continua o conteúdo antigo, não o placeholder
+    pub fn reload(&mut self, path: &Path) -> Result<()> {
+        for index in 0..self.entries.len() {
+            let entry = &mut self.entries[index];
+            if entry.job.path().canonicalize().ok().as_deref() != Some(path) {
+                continue;
+            }
+
+            match entry.stage {
+                Stage::Ready | Stage::Failed(_) => {
+                    debug!("Reloading {}.", path.display());
+                    entry.stage = Stage::Loading;
+                    let job = entry.job.clone();
+                    self.send(index, job)?;
+                }
+                _ => entry.stale = true,
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn mesh(&self, handle: AssetHandle<Mesh>) -> MeshId {
+        match self.entries[handle.index].target {
+            Target::Mesh(id) => id,
+            Target::Texture(_) => unreachable!(),
+        }
+    }
+
+    pub fn texture(&self, handle: AssetHandle<Texture>) -> MaterialTextureId {
+        match self.entries[handle.index].target {
+            Target::Texture(id) => id,
+            Target::Mesh(_) => unreachable!(),
+        }
+    }
+
+    pub fn state<T>(&self, handle: AssetHandle<T>) -> AssetState {
+        match &self.entries[handle.index].stage {
+            Stage::Ready => AssetState::Ready,
+            Stage::Failed(error) => AssetState::Failed(error.clone()),
+            _ => AssetState::Loading,
+        }
+    }
+
+    // Quantos ainda não terminaram (nem falharam)
+    pub fn pending(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|e| !matches!(e.stage, Stage::Ready | Stage::Failed(_)))
+            .count()
+    }
+
+    // Uma vez por frame, depois de esperar a fence dele e antes de gravar: recolhe o que
+    // as threads terminaram, cria na GPU, manda as cópias e troca o placeholder pelo que
+    // já chegou
+    pub unsafe fn update(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        meshes: &mut MeshRenderer,
+        materials: &mut Materials,
+    ) -> Result<()> {
+        if self.transfer.poll(device)? {
+            self.uploads.release(device, 0);
+        }
+
+        while let Ok((index, result)) = self.results.try_recv() {
+            let entry = &mut self.entries[index];
+            entry.stage = match result {
+                Ok(decoded) => Stage::Decoded(decoded),
+                Err(e) => {
+                    error!("Failed to load {}: {:#}", entry.job.path().display(), e);
+                    Stage::Failed(e.to_string())
+                }
+            };
+        }
+
+        for entry in &mut self.entries {
+            entry.stage = match std::mem::replace(&mut entry.stage, Stage::Loading) {
+                Stage::Decoded(decoded) => {
+                    match prepare(
+                        instance,
+                        device,
+                        data,
+                        &mut self.uploads,
+                        meshes,
+                        materials,
+                        decoded,
+                    ) {
+                        Ok(prepared) => Stage::Uploading(prepared),
+                        Err(e) => {
+                            error!("Failed to create {}: {:#}", entry.job.path().display(), e);
+                            Stage::Failed(e.to_string())
+                        }
+                    }
+                }
+                Stage::Uploading(prepared) if !is_uploading(&self.uploads, &prepared) => {
+                    match (entry.target, prepared) {
+                        (Target::Mesh(id), Prepared::Mesh(mesh)) => meshes.replace(id, mesh),
+                        (Target::Texture(id), Prepared::Texture(texture)) => {
+                            materials.replace_texture(id, texture)
+                        }
+                        _ => unreachable!(),
+                    }
+                    debug!("Loaded {}.", entry.job.path().display());
+                    Stage::Ready
+                }
+                stage => stage,
+            };
+        }
+
+        for index in 0..self.entries.len() {
+            let entry = &mut self.entries[index];
+            if entry.stale && matches!(entry.stage, Stage::Ready | Stage::Failed(_)) {
+                entry.stale = false;
+                entry.stage = Stage::Loading;
+                let job = entry.job.clone();
+                self.send(index, job)?;
+            }
+        }
+
+        if !self.transfer.is_busy() && self.uploads.pending_bytes() > 0 {
+            let command_buffer = self.transfer.begin(device)?;
+            self.uploads
+                .record(instance, device, data, command_buffer, 0)?;
+            self.transfer.submit(device)?;
+        }
+
+        Ok(())
+    }
+
+    // O que estava subindo volta pra CPU, pra ir de novo com o próximo dispositivo
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.transfer.destroy(device);
+        self.uploads.destroy(device);
+
+        for entry in &mut self.entries {
+            if let Stage::Uploading(_) = entry.stage {
+                let stage = std::mem::replace(&mut entry.stage, Stage::Loading);
+                entry.stage = Stage::Decoded(match stage {
+                    Stage::Uploading(Prepared::Mesh(mesh)) => {
+                        let (mesh, _) = mesh.destroy(device);
+                        Decoded::Mesh(mesh)
+                    }
+                    Stage::Uploading(Prepared::Texture(texture)) => {
+                        Decoded::Texture(texture.destroy(device))
+                    }
+                    _ => unreachable!(),
+                });
+            }
+        }
+    }
+
+    // Sem o sender as threads terminam o arquivo atual e saem
+    pub fn stop_workers(&mut self) {
+        drop(self.jobs.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn decode(job: &Job, support: &FormatSupport) -> Result<Decoded> {
+    match job {
+        Job::Mesh(path, None) => {
+            let mut mesh = MeshData::default();
+            for (_, object) in MeshData::load_obj(path)? {
+                mesh.append(&object);
+            }
+            Ok(Decoded::Mesh(mesh))
+        }
+        Job::Mesh(path, Some(name)) => MeshData::load_obj(path)?
+            .into_iter()
+            .find(|(object, _)| object == name)
+            .map(|(_, mesh)| Decoded::Mesh(mesh))
+            .ok_or_else(|| anyhow!("No object named '{}' in {}.", name, path.display())),
+        Job::Texture(path, srgb) => Ok(Decoded::Texture(texture::decode(path, *srgb, support)?)),
+    }
+}
+
+fn is_uploading(uploads: &UploadQueue, prepared: &Prepared) -> bool {
+    match prepared {
+        Prepared::Mesh(mesh) => mesh.uploads().iter().any(|id| uploads.is_pending(*id)),
+        Prepared::Texture(texture) => uploads.is_pending(texture.upload()),
+    }
+}
+
+unsafe fn prepare(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    uploads: &mut UploadQueue,
+    meshes: &MeshRenderer,
+    materials: &Materials,
+    decoded: Decoded,
+) -> Result<Prepared> {
+    Ok(match decoded {
+        Decoded::Mesh(mesh) => {
+            Prepared::Mesh(meshes.prepare(instance, device, data, uploads, mesh, vec![])?)
+        }
+        Decoded::Texture(texture) => {
+            Prepared::Texture(materials.prepare_texture(instance, device, data, uploads, texture)?)
+        }
+    })
+}
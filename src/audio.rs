@@ -0,0 +1,170 @@
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use nalgebra_glm as glm;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source, SpatialSink};
+
+use crate::scene::{NodeId, Scene};
+
+// Distância entre as "orelhas" do ouvinte, em unidades do mundo
+const EAR_DISTANCE: f32 = 0.2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EmitterId(u64);
+
+struct Emitter {
+    sink: SpatialSink,
+    position: glm::Vec3,
+    // Nó da cena que o som acompanha. Se o nó for removido, fica na última posição
+    node: Option<NodeId>,
+}
+
+// Áudio simples: uma música de fundo e sons posicionais de uma vez só. O App atualiza o
+// ouvinte pela câmera e os emissores presos a nós pela cena a cada passo fixo
+pub struct Audio {
+    // O stream precisa continuar vivo enquanto qualquer som toca
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    music: Option<Sink>,
+    emitters: HashMap<EmitterId, Emitter>,
+    next_id: u64,
+    listener: glm::Vec3,
+    // Direção "pra direita" do ouvinte, usada pra posicionar as orelhas
+    listener_right: glm::Vec3,
+}
+
+impl Audio {
+    pub fn new() -> Result<Self> {
+        let (stream, handle) = OutputStream::try_default()?;
+
+        Ok(Self {
+            _stream: stream,
+            handle,
+            music: None,
+            emitters: HashMap::new(),
+            next_id: 0,
+            listener: glm::vec3(0.0, 0.0, 0.0),
+            listener_right: glm::vec3(1.0, 0.0, 0.0),
+        })
+    }
+
+    fn decode(path: &Path) -> Result<Decoder<BufReader<File>>> {
+        let file = File::open(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        Ok(Decoder::new(BufReader::new(file))?)
+    }
+
+    pub fn play_music(&mut self, path: impl AsRef<Path>, looping: bool) -> Result<()> {
+        let source = Self::decode(path.as_ref())?;
+        let sink = Sink::try_new(&self.handle)?;
+
+        if looping {
+            sink.append(source.repeat_infinite());
+        } else {
+            sink.append(source);
+        }
+
+        // Substituir o sink para a música anterior
+        self.music = Some(sink);
+        Ok(())
+    }
+
+    pub fn stop_music(&mut self) {
+        self.music = None;
+    }
+
+    pub fn set_music_volume(&self, volume: f32) {
+        if let Some(music) = &self.music {
+            music.set_volume(volume);
+        }
+    }
+
+    // Toca um som uma vez na posição dada. O id serve pra mover o emissor enquanto o som
+    // ainda está tocando
+    pub fn play_at(&mut self, path: impl AsRef<Path>, position: glm::Vec3) -> Result<EmitterId> {
+        let source = Self::decode(path.as_ref())?;
+        let (left, right) = self.ears();
+        let sink = SpatialSink::try_new(&self.handle, position.into(), left, right)?;
+        sink.append(source);
+
+        let id = EmitterId(self.next_id);
+        self.next_id += 1;
+        self.emitters.insert(
+            id,
+            Emitter {
+                sink,
+                position,
+                node: None,
+            },
+        );
+
+        Ok(id)
+    }
+
+    // Toca um som uma vez preso a um nó da cena, que ele acompanha enquanto toca
+    pub fn play_on_node(
+        &mut self,
+        path: impl AsRef<Path>,
+        scene: &Scene,
+        node: NodeId,
+    ) -> Result<EmitterId> {
+        let position = Self::node_position(scene, node)
+            .ok_or_else(|| anyhow!("Node {:?} is not in the scene", node))?;
+        let id = self.play_at(path, position)?;
+        self.attach(id, Some(node));
+        Ok(id)
+    }
+
+    // Prende (ou solta, com None) um emissor que ainda está tocando a um nó da cena
+    pub fn attach(&mut self, id: EmitterId, node: Option<NodeId>) {
+        if let Some(emitter) = self.emitters.get_mut(&id) {
+            emitter.node = node;
+        }
+    }
+
+    fn node_position(scene: &Scene, node: NodeId) -> Option<glm::Vec3> {
+        scene.get(node).map(|node| node.world().column(3).xyz())
+    }
+
+    pub fn set_emitter_position(&mut self, id: EmitterId, position: glm::Vec3) {
+        if let Some(emitter) = self.emitters.get_mut(&id) {
+            emitter.position = position;
+            emitter.sink.set_emitter_position(position.into());
+        }
+    }
+
+    pub fn set_listener(&mut self, position: glm::Vec3, right: glm::Vec3) {
+        self.listener = position;
+        self.listener_right = glm::normalize(&right);
+    }
+
+    fn ears(&self) -> ([f32; 3], [f32; 3]) {
+        let offset = self.listener_right * (EAR_DISTANCE / 2.0);
+        (
+            (self.listener - offset).into(),
+            (self.listener + offset).into(),
+        )
+    }
+
+    // Chamado uma vez por passo de atualização: reposiciona as orelhas e os emissores
+    // presos a nós (com as matrizes do último `Scene::update`) e descarta os sons que já
+    // terminaram
+    pub fn update(&mut self, scene: &Scene) {
+        let (left, right) = self.ears();
+
+        self.emitters.retain(|id, emitter| {
+            if emitter.sink.empty() {
+                trace!("Audio emitter {:?} finished.", id);
+                return false;
+            }
+
+            if let Some(position) = emitter.node.and_then(|n| Self::node_position(scene, n)) {
+                emitter.position = position;
+                emitter.sink.set_emitter_position(position.into());
+            }
+            emitter.sink.set_left_ear_position(left);
+            emitter.sink.set_right_ear_position(right);
+            true
+        });
+    }
+}
@@ -0,0 +1,267 @@
+use std::{ptr, slice};
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{allocator, app::AppData, info::get_memory_type_index, leaks, MAX_FRAMES_IN_FLIGHT};
+
+pub unsafe fn create_buffer(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+    create_buffer_shared(instance, device, data, size, usage, properties, &[])
+}
+
+// Usado por mais de uma família de filas ao mesmo tempo (CONCURRENT), como as imagens de
+// `create_image_mips_shared`. Com menos de duas famílias fica EXCLUSIVE
+pub unsafe fn create_buffer_shared(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+    families: &[u32],
+) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+    let mut info = vk::BufferCreateInfo::builder()
+        .size(size)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    if families.len() > 1 {
+        info = info
+            .sharing_mode(vk::SharingMode::CONCURRENT)
+            .queue_family_indices(families);
+    }
+
+    let buffer = device.create_buffer(&info, None)?;
+    leaks::created(buffer);
+
+    let requirements = device.get_buffer_memory_requirements(buffer);
+    let type_index = get_memory_type_index(instance, data, properties, requirements)?;
+    let memory = allocator::allocate(device, requirements.size, type_index)?;
+    device.bind_buffer_memory(buffer, memory, 0)?;
+
+    Ok((buffer, memory))
+}
+
+// Buffer HOST_VISIBLE mapeado o tempo todo, reescrito pela CPU a cada frame. Tem um por
+// frame em voo, pra não sobrescrever o que a GPU ainda está lendo
+#[derive(Debug)]
+pub struct DynamicBuffer {
+    usage: vk::BufferUsageFlags,
+    slots: Vec<Option<MappedBuffer>>,
+}
+
+#[derive(Debug)]
+struct MappedBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+    size: vk::DeviceSize,
+}
+
+impl DynamicBuffer {
+    pub fn new(usage: vk::BufferUsageFlags) -> Self {
+        Self {
+            usage,
+            slots: (0..MAX_FRAMES_IN_FLIGHT).map(|_| None).collect(),
+        }
+    }
+
+    // Copia `bytes` pro buffer do frame `slot` (crescendo se precisar) e devolve ele.
+    // A fence do frame já precisa ter sido esperada
+    pub unsafe fn write(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        slot: usize,
+        bytes: &[u8],
+    ) -> Result<vk::Buffer> {
+        let needed = (bytes.len() as vk::DeviceSize).max(1);
+        let fits = matches!(&self.slots[slot], Some(b) if b.size >= needed);
+
+        if !fits {
+            if let Some(old) = self.slots[slot].take() {
+                old.destroy(device);
+            }
+
+            let size = needed.next_power_of_two();
+            let (buffer, memory) = create_buffer(
+                instance,
+                device,
+                data,
+                size,
+                self.usage,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let mapped =
+                device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())? as *mut u8;
+
+            self.slots[slot] = Some(MappedBuffer {
+                buffer,
+                memory,
+                mapped,
+                size,
+            });
+        }
+
+        let target = self.slots[slot].as_ref().unwrap();
+        leaks::check(target.buffer, "dynamic buffer write");
+        ptr::copy_nonoverlapping(bytes.as_ptr(), target.mapped, bytes.len());
+
+        Ok(target.buffer)
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for slot in self.slots.iter_mut() {
+            if let Some(buffer) = slot.take() {
+                buffer.destroy(device);
+            }
+        }
+    }
+}
+
+impl MappedBuffer {
+    unsafe fn destroy(self, device: &Device) {
+        device.unmap_memory(self.memory);
+        leaks::destroy_buffer(device, self.buffer);
+        allocator::free(device, self.memory);
+    }
+}
+
+// Onde a memória de um storage buffer fica
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StorageLocation {
+    // DEVICE_LOCAL: só as shaders (e cópias/cmd_fill_buffer) mexem nele
+    Device,
+    // HOST_VISIBLE e mapeado o tempo todo, pra CPU ler o que a GPU escreveu depois da
+    // fence do frame. Lento pra GPU, então só pra resultados pequenos
+    Readback,
+}
+
+// Storage buffer (SSBO) de tamanho fixo, pra compute e fragment lerem e escreverem. A
+// sincronização fica com quem usa: `barrier` (ou um BufferAccess no grafo do frame)
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StorageBuffer {
+    pub buffer: vk::Buffer,
+    pub size: vk::DeviceSize,
+    memory: vk::DeviceMemory,
+    // Só nos de readback
+    mapped: Option<*mut u8>,
+}
+
+impl StorageBuffer {
+    // `usage` é o que precisar além de STORAGE_BUFFER e TRANSFER_DST (INDIRECT_BUFFER,
+    // TRANSFER_SRC...)
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        location: StorageLocation,
+    ) -> Result<Self> {
+        let usage =
+            usage | vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST;
+
+        let properties = match location {
+            StorageLocation::Device => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            StorageLocation::Readback => {
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+            }
+        };
+        let (buffer, memory) = create_buffer(instance, device, data, size, usage, properties)?;
+
+        let mapped = match location {
+            StorageLocation::Device => None,
+            StorageLocation::Readback => {
+                Some(device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())? as *mut u8)
+            }
+        };
+
+        Ok(Self {
+            buffer,
+            size,
+            memory,
+            mapped,
+        })
+    }
+
+    // Binding de storage buffer no layout de um set
+    pub fn binding(binding: u32, stages: vk::ShaderStageFlags) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(stages)
+            .build()
+    }
+
+    // Aponta o `binding` do set pro buffer inteiro
+    pub unsafe fn write_descriptor(&self, device: &Device, set: vk::DescriptorSet, binding: u32) {
+        let buffer_info = &[vk::DescriptorBufferInfo::builder()
+            .buffer(self.buffer)
+            .offset(0)
+            .range(self.size)
+            .build()];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(buffer_info);
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    // Barreira no buffer inteiro, entre dois usos na mesma fila
+    pub unsafe fn barrier(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) {
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(self.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[barrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+    }
+
+    // O conteúdo de um buffer de readback. A escrita da GPU precisa ter terminado (fence
+    // esperada) e ficado visível pro host (barreira com HOST_READ)
+    pub unsafe fn read(&self) -> &[u8] {
+        let mapped = self
+            .mapped
+            .expect("storage buffer is not a readback buffer");
+        slice::from_raw_parts(mapped, self.size as usize)
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        if self.mapped.is_some() {
+            device.unmap_memory(self.memory);
+        }
+        leaks::destroy_buffer(device, self.buffer);
+        allocator::free(device, self.memory);
+    }
+}
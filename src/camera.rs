@@ -0,0 +1,322 @@
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+// Como o volume visível vira o espaço de recorte
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective {
+        // Campo de visão vertical, em radianos
+        fov_y: f32,
+        near: f32,
+        // None: sem plano distante (só faz sentido com reverse-Z)
+        far: Option<f32>,
+    },
+    Orthographic {
+        // Altura do volume em unidades do mundo; a largura vem do aspect
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+// Câmera da cena 3D. Mundo com y pra cima e mão direita; a câmera olha pro -Z local.
+// As matrizes já saem no formato do Vulkan (y do NDC pra baixo, profundidade de 0 a 1)
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    pub position: glm::Vec3,
+    pub rotation: glm::Quat,
+    pub projection: Projection,
+    // Profundidade 1 no plano próximo e 0 no distante, pra usar melhor a precisão do
+    // float. Precisa de compare GREATER e clear 0.0 no depth
+    pub reverse_z: bool,
+    aspect: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::perspective(60f32.to_radians(), 0.1, Some(100.0))
+    }
+}
+
+impl Camera {
+    pub fn perspective(fov_y: f32, near: f32, far: Option<f32>) -> Self {
+        Self::new(Projection::Perspective { fov_y, near, far })
+    }
+
+    pub fn orthographic(height: f32, near: f32, far: f32) -> Self {
+        Self::new(Projection::Orthographic { height, near, far })
+    }
+
+    fn new(projection: Projection) -> Self {
+        Self {
+            position: glm::vec3(0.0, 0.0, 0.0),
+            rotation: glm::quat_identity(),
+            projection,
+            reverse_z: false,
+            aspect: 1.0,
+        }
+    }
+
+    // Chamado quando a swapchain muda de tamanho
+    pub fn set_extent(&mut self, extent: vk::Extent2D) {
+        if extent.height > 0 {
+            self.aspect = extent.width as f32 / extent.height as f32;
+        }
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    pub fn forward(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.rotation, &glm::vec3(0.0, 0.0, -1.0))
+    }
+
+    pub fn right(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.rotation, &glm::vec3(1.0, 0.0, 0.0))
+    }
+
+    pub fn up(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.rotation, &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    // Gira a câmera pra olhar pro ponto, mantendo o y do mundo pra cima
+    pub fn look_at(&mut self, target: glm::Vec3) {
+        let direction = target - self.position;
+        if glm::length(&direction) <= f32::EPSILON {
+            return;
+        }
+
+        // Olhando reto pra cima ou pra baixo o "pra cima" do mundo não serve
+        let direction = glm::normalize(&direction);
+        let up = if glm::dot(&direction, &glm::vec3(0.0, 1.0, 0.0)).abs() > 0.999 {
+            glm::vec3(0.0, 0.0, -1.0)
+        } else {
+            glm::vec3(0.0, 1.0, 0.0)
+        };
+
+        // Colunas da rotação: os eixos locais da câmera no mundo
+        let right = glm::normalize(&glm::cross(&direction, &up));
+        let up = glm::cross(&right, &direction);
+        let rotation = glm::mat3(
+            right.x,
+            up.x,
+            -direction.x,
+            right.y,
+            up.y,
+            -direction.y,
+            right.z,
+            up.z,
+            -direction.z,
+        );
+
+        self.rotation = glm::mat3_to_quat(&rotation);
+    }
+
+    pub fn view(&self) -> glm::Mat4 {
+        let rotation = glm::quat_to_mat4(&glm::quat_conjugate(&self.rotation));
+        rotation * glm::translation(&-self.position)
+    }
+
+    pub fn projection_matrix(&self) -> glm::Mat4 {
+        let mut projection = match self.projection {
+            Projection::Perspective { fov_y, near, far } => match (far, self.reverse_z) {
+                (Some(far), false) => glm::perspective_rh_zo(self.aspect, fov_y, near, far),
+                // Trocar os planos inverte a profundidade
+                (Some(far), true) => glm::perspective_rh_zo(self.aspect, fov_y, far, near),
+                (None, reverse_z) => infinite_perspective(self.aspect, fov_y, near, reverse_z),
+            },
+            Projection::Orthographic { height, near, far } => {
+                let half = glm::vec2(height * self.aspect, height) / 2.0;
+                let (near, far) = if self.reverse_z {
+                    (far, near)
+                } else {
+                    (near, far)
+                };
+
+                glm::ortho_rh_zo(-half.x, half.x, -half.y, half.y, near, far)
+            }
+        };
+
+        // O y do NDC do Vulkan aponta pra baixo
+        projection[(1, 1)] *= -1.0;
+        projection
+    }
+
+    pub fn view_projection(&self) -> glm::Mat4 {
+        self.projection_matrix() * self.view()
+    }
+
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&self.view_projection())
+    }
+
+    // Raio do mundo que passa pelo ponto (x, y) da tela, em pixels do `extent` com a
+    // origem em cima à esquerda. Sai do plano próximo
+    pub fn ray(&self, x: f32, y: f32, extent: vk::Extent2D) -> Ray {
+        let inverse = glm::inverse(&self.view_projection());
+        let ndc = glm::vec2(
+            x / extent.width.max(1) as f32 * 2.0 - 1.0,
+            y / extent.height.max(1) as f32 * 2.0 - 1.0,
+        );
+        let unproject = |depth: f32| {
+            let point = inverse * glm::vec4(ndc.x, ndc.y, depth, 1.0);
+            point.xyz() / point.w
+        };
+
+        // O segundo ponto fica no meio da profundidade, que é finito mesmo sem plano
+        // distante
+        let near = unproject(if self.reverse_z { 1.0 } else { 0.0 });
+        let middle = unproject(0.5);
+
+        Ray {
+            origin: near,
+            direction: glm::normalize(&(middle - near)),
+        }
+    }
+
+    // Fração da altura da tela que uma esfera do mundo ocupa (aproximada pela distância
+    // até o centro). Passa de 1 com a câmera dentro dela
+    pub fn screen_size(&self, center: &glm::Vec3, radius: f32) -> f32 {
+        match self.projection {
+            Projection::Perspective { fov_y, .. } => {
+                let distance = glm::distance(&self.position, center);
+                if distance <= radius {
+                    return f32::INFINITY;
+                }
+                radius / (distance * (fov_y / 2.0).tan())
+            }
+            Projection::Orthographic { height, .. } => 2.0 * radius / height,
+        }
+    }
+}
+
+// Os 6 planos do volume visível, no espaço do mundo. Cada plano é (normal, d), com a
+// normal pra dentro; não são normalizados, o teste só olha o sinal
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    planes: [glm::Vec4; 6],
+}
+
+impl Frustum {
+    // Tira os planos das linhas da matriz (Gribb/Hartmann), com a profundidade de 0 a w
+    // do Vulkan. Com reverse-Z o near e o far só trocam de lugar; sem plano distante o
+    // plano que sobra é (0, 0, 0, near), que aceita qualquer ponto
+    pub fn from_matrix(view_projection: &glm::Mat4) -> Self {
+        let m = view_projection;
+        let row = |i: usize| glm::vec4(m[(i, 0)], m[(i, 1)], m[(i, 2)], m[(i, 3)]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        Self {
+            planes: [w + x, w - x, w + y, w - y, z, w - z],
+        }
+    }
+
+    // Se a caixa (mínimo, máximo, no espaço local) levada pro mundo pode aparecer. Usa a
+    // caixa alinhada aos eixos que envolve a transformada, então erra pro lado de desenhar
+    pub fn intersects(&self, (min, max): (glm::Vec3, glm::Vec3), world: &glm::Mat4) -> bool {
+        let center = (min + max) / 2.0;
+        let center = (world * glm::vec4(center.x, center.y, center.z, 1.0)).xyz();
+        let extent = glm::abs(&glm::mat4_to_mat3(world)) * ((max - min) / 2.0);
+
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            glm::dot(&normal, &center) + glm::dot(&glm::abs(&normal), &extent) + plane.w >= 0.0
+        })
+    }
+}
+
+// Semirreta a partir de `origin`. As distâncias dos testes são em múltiplos de
+// `direction`, então com ela normalizada são unidades do mundo
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: glm::Vec3,
+    pub direction: glm::Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: glm::Vec3, direction: glm::Vec3) -> Self {
+        Self {
+            origin,
+            direction: glm::normalize(&direction),
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> glm::Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    // O mesmo raio no espaço de `matrix`. A direção não é normalizada de novo, pra
+    // distância continuar valendo nos dois espaços
+    pub fn transform(&self, matrix: &glm::Mat4) -> Self {
+        let o = self.origin;
+        let d = self.direction;
+        Self {
+            origin: (matrix * glm::vec4(o.x, o.y, o.z, 1.0)).xyz(),
+            direction: (matrix * glm::vec4(d.x, d.y, d.z, 0.0)).xyz(),
+        }
+    }
+
+    // Distância até entrar na caixa (mínimo, máximo), pelo método dos slabs. Começando
+    // dentro dela é 0
+    pub fn intersect_aabb(&self, (min, max): (glm::Vec3, glm::Vec3)) -> Option<f32> {
+        let mut near = 0f32;
+        let mut far = f32::INFINITY;
+        for i in 0..3 {
+            // Divisão por 0 dá infinito, que os min/max já tratam certo
+            let inverse = 1.0 / self.direction[i];
+            let a = (min[i] - self.origin[i]) * inverse;
+            let b = (max[i] - self.origin[i]) * inverse;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+
+        (near <= far).then(|| near)
+    }
+
+    // Möller-Trumbore, pelos dois lados do triângulo
+    pub fn intersect_triangle(&self, a: &glm::Vec3, b: &glm::Vec3, c: &glm::Vec3) -> Option<f32> {
+        let ab = b - a;
+        let ac = c - a;
+        let p = glm::cross(&self.direction, &ac);
+        let determinant = glm::dot(&ab, &p);
+        if determinant.abs() <= f32::EPSILON {
+            return None;
+        }
+
+        let inverse = 1.0 / determinant;
+        let t = self.origin - a;
+        let u = glm::dot(&t, &p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = glm::cross(&t, &ab);
+        let v = glm::dot(&self.direction, &q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = glm::dot(&ac, &q) * inverse;
+        (distance >= 0.0).then(|| distance)
+    }
+}
+
+fn infinite_perspective(aspect: f32, fov_y: f32, near: f32, reverse_z: bool) -> glm::Mat4 {
+    let f = 1.0 / (fov_y / 2.0).tan();
+    let mut projection = glm::Mat4::zeros();
+    projection[(0, 0)] = f / aspect;
+    projection[(1, 1)] = f;
+    projection[(3, 2)] = -1.0;
+
+    if reverse_z {
+        // z = near / -z_view: 1 no plano próximo, 0 no infinito
+        projection[(2, 3)] = near;
+    } else {
+        // z = 1 - near / -z_view: 0 no plano próximo, 1 no infinito
+        projection[(2, 2)] = -1.0;
+        projection[(2, 3)] = -near;
+    }
+
+    projection
+}
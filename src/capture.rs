@@ -0,0 +1,389 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    ptr,
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{allocator, app::AppData, buffer, leaks, MAX_FRAMES_IN_FLIGHT};
+
+// Pra onde vão os frames gravados
+#[derive(Clone, Debug)]
+pub enum CaptureOutput {
+    // Um PNG numerado por frame dentro do diretório
+    Png(PathBuf),
+    // Frames crus mandados pro stdin do ffmpeg, que codifica o vídeo
+    Ffmpeg { path: PathBuf, framerate: u32 },
+    // Só o próximo frame, salvo nesse arquivo
+    Screenshot(PathBuf),
+}
+
+struct CapturedFrame {
+    number: u64,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+// Um buffer de staging por frame em voo. `pending` guarda o número do frame copiado pra
+// ele que ainda não foi lido (a GPU pode não ter terminado a cópia)
+#[derive(Copy, Clone, Debug, Default)]
+struct StagingSlot {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    pending: Option<u64>,
+}
+
+// Copia cada frame renderizado pra um anel de buffers de staging e escreve eles no disco
+// numa thread separada, pra não travar o loop de renderização
+#[derive(Debug)]
+pub struct FrameRecorder {
+    slots: Vec<StagingSlot>,
+    extent: vk::Extent2D,
+    bgra: bool,
+    next_frame: u64,
+    // Quantos frames gravar antes de parar sozinho (screenshots)
+    limit: Option<u64>,
+    sender: Option<Sender<CapturedFrame>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl FrameRecorder {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        output: CaptureOutput,
+    ) -> Result<Self> {
+        if !data
+            .swapchain
+            .usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err(anyhow!("Swapchain images can't be copied on this surface."));
+        }
+
+        if let CaptureOutput::Png(directory) = &output {
+            fs::create_dir_all(directory)?;
+        }
+
+        let limit = match output {
+            CaptureOutput::Screenshot(_) => Some(1),
+            _ => None,
+        };
+
+        let (sender, receiver) = mpsc::channel::<CapturedFrame>();
+        let writer = thread::spawn(move || {
+            let mut ffmpeg = None;
+
+            for frame in receiver {
+                if let Err(e) = write_frame(&output, &mut ffmpeg, &frame) {
+                    error!("Failed to write captured frame {}: {}", frame.number, e);
+                }
+            }
+
+            // Fechar o stdin faz o ffmpeg terminar o arquivo
+            if let Some((mut child, _, _)) = ffmpeg.take() {
+                drop(child.stdin.take());
+                let _ = child.wait();
+            }
+        });
+
+        let mut recorder = Self {
+            slots: vec![],
+            extent: data.swapchain.extent,
+            bgra: false,
+            next_frame: 0,
+            limit,
+            sender: Some(sender),
+            writer: Some(writer),
+        };
+
+        recorder.create_slots(instance, device, data)?;
+        info!("Started frame recording.");
+
+        Ok(recorder)
+    }
+
+    unsafe fn create_slots(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        self.extent = data.swapchain.extent;
+        self.bgra = matches!(
+            data.swapchain.format,
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM
+        );
+
+        let size = (self.extent.width * self.extent.height * 4) as vk::DeviceSize;
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let (buffer, memory) = buffer::create_buffer(
+                instance,
+                device,
+                data,
+                size,
+                vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+
+            self.slots.push(StagingSlot {
+                buffer,
+                memory,
+                pending: None,
+            });
+        }
+
+        Ok(())
+    }
+
+    unsafe fn destroy_slots(&mut self, device: &Device) {
+        self.slots.drain(..).for_each(|s| {
+            leaks::destroy_buffer(device, s.buffer);
+            allocator::free(device, s.memory);
+        });
+    }
+
+    // O tamanho da swapchain mudou; os frames pendentes já devem ter sido coletados
+    pub unsafe fn resize(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        self.collect_all(device)?;
+        self.destroy_slots(device);
+        self.create_slots(instance, device, data)
+    }
+
+    // Deve ser chamado depois de esperar a fence do frame `slot`: a cópia já terminou
+    pub unsafe fn collect(&mut self, device: &Device, slot: usize) -> Result<()> {
+        let slot = &mut self.slots[slot];
+        let number = match slot.pending.take() {
+            Some(number) => number,
+            None => return Ok(()),
+        };
+
+        let size = (self.extent.width * self.extent.height * 4) as usize;
+        let mut pixels = vec![0u8; size];
+
+        let memory = device.map_memory(
+            slot.memory,
+            0,
+            size as vk::DeviceSize,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        ptr::copy_nonoverlapping(memory as *const u8, pixels.as_mut_ptr(), size);
+        device.unmap_memory(slot.memory);
+
+        // PNG e o ffmpeg (com rgba) esperam os canais em RGBA
+        if self.bgra {
+            pixels.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+        }
+
+        if let Some(sender) = &self.sender {
+            let frame = CapturedFrame {
+                number,
+                width: self.extent.width,
+                height: self.extent.height,
+                pixels,
+            };
+
+            sender
+                .send(frame)
+                .map_err(|_| anyhow!("Frame writer thread stopped."))?;
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn collect_all(&mut self, device: &Device) -> Result<()> {
+        for slot in 0..self.slots.len() {
+            self.collect(device, slot)?;
+        }
+
+        Ok(())
+    }
+
+    // Grava a cópia da imagem da swapchain (já em PRESENT_SRC_KHR) pro buffer do `slot`
+    pub unsafe fn record(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        image: vk::Image,
+    ) {
+        if self.limit.is_some_and(|l| self.next_frame >= l) {
+            return;
+        }
+
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let to_transfer = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[to_transfer],
+        );
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(subresource)
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            });
+
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.slots[slot].buffer,
+            &[region],
+        );
+
+        let to_present = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::empty());
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[to_present],
+        );
+
+        self.slots[slot].pending = Some(self.next_frame);
+        self.next_frame += 1;
+    }
+
+    // Gravou tudo que devia e não tem mais nada esperando leitura
+    pub fn is_finished(&self) -> bool {
+        self.limit.is_some_and(|l| self.next_frame >= l)
+            && self.slots.iter().all(|s| s.pending.is_none())
+    }
+
+    // A GPU precisa estar ociosa (device_wait_idle) antes disso
+    pub unsafe fn destroy(mut self, device: &Device) {
+        if let Err(e) = self.collect_all(device) {
+            warn!("Failed to collect last captured frames: {}", e);
+        }
+
+        self.destroy_slots(device);
+
+        // Sem o sender a thread termina de escrever o que falta e sai
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+
+        info!("Stopped frame recording after {} frames.", self.next_frame);
+    }
+}
+
+fn write_frame(
+    output: &CaptureOutput,
+    ffmpeg: &mut Option<(Child, u32, u32)>,
+    frame: &CapturedFrame,
+) -> Result<()> {
+    match output {
+        CaptureOutput::Png(directory) => {
+            let path = directory.join(format!("frame_{:06}.png", frame.number));
+            write_png(&path, frame)?;
+        }
+        CaptureOutput::Screenshot(path) => {
+            write_png(path, frame)?;
+            info!("Saved screenshot to {:?}.", path);
+        }
+        CaptureOutput::Ffmpeg { path, framerate } => {
+            if ffmpeg.is_none() {
+                let child = Command::new("ffmpeg")
+                    .args(&["-y", "-f", "rawvideo", "-pix_fmt", "rgba", "-s"])
+                    .arg(format!("{}x{}", frame.width, frame.height))
+                    .arg("-r")
+                    .arg(framerate.to_string())
+                    .args(&["-i", "-", "-pix_fmt", "yuv420p"])
+                    .arg(path)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()?;
+
+                *ffmpeg = Some((child, frame.width, frame.height));
+            }
+
+            let (child, width, height) = ffmpeg.as_mut().unwrap();
+
+            // O ffmpeg não aceita mudança de resolução no meio do stream
+            if frame.width != *width || frame.height != *height {
+                return Err(anyhow!("Frame size changed mid-recording, dropping frame."));
+            }
+
+            let stdin = child
+                .stdin
+                .as_mut()
+                .ok_or_else(|| anyhow!("ffmpeg stdin closed."))?;
+            stdin.write_all(&frame.pixels)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_png(path: &Path, frame: &CapturedFrame) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, frame.width, frame.height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&frame.pixels)?;
+
+    Ok(())
+}
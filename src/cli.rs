@@ -0,0 +1,201 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::{builder::FalseyValueParser, Parser};
+
+use crate::{
+    app::AppBuilder,
+    config::{Buffering, GpuSelector, PresentModePreference, RenderPath, ValidationFeatures},
+    remote,
+};
+
+// Tamanho da janela quando não vem nos argumentos
+pub const DEFAULT_WIDTH: u32 = 600;
+pub const DEFAULT_HEIGHT: u32 = 600;
+
+// Argumentos da linha de comando, por cima dos padrões do AppConfig. Os que têm `env`
+// também podem vir da variável LV_* correspondente, se não estiverem na linha de comando
+#[derive(Debug, Parser)]
+#[command(about = "Learning Vulkan (Oh boy)")]
+pub struct Args {
+    /// Window width, in logical pixels
+    #[arg(long, default_value_t = DEFAULT_WIDTH)]
+    pub width: u32,
+
+    /// Window height, in logical pixels
+    #[arg(long, default_value_t = DEFAULT_HEIGHT)]
+    pub height: u32,
+
+    /// GPU to use, by index or part of its name (integrated GPUs are allowed)
+    #[arg(long)]
+    pub gpu: Option<GpuSelector>,
+
+    /// Accept software Vulkan devices (lavapipe, SwiftShader) when no GPU is preferred
+    #[arg(long)]
+    pub allow_software: bool,
+
+    /// Disable the validation layer
+    #[arg(long)]
+    pub no_validation: bool,
+
+    /// Extra validation checks, comma separated: gpu, sync, best-practices or all
+    #[arg(long, env = "LV_VALIDATION", value_parser = ValidationFeatures::parse)]
+    pub validation_features: Option<ValidationFeatures>,
+
+    /// fifo, fifo-relaxed, mailbox or immediate
+    #[arg(long, value_parser = PresentModePreference::parse)]
+    pub present_mode: Option<PresentModePreference>,
+
+    /// driver, double or triple
+    #[arg(long, value_parser = Buffering::parse)]
+    pub buffering: Option<Buffering>,
+
+    /// Present at a steady interval (display refresh or FPS limit)
+    #[arg(long)]
+    pub frame_pacing: bool,
+
+    /// Cap the frame rate when v-sync is off
+    #[arg(long, env = "LV_FPS_LIMIT")]
+    pub fps_limit: Option<u32>,
+
+    /// Only render a frame when something changes
+    #[arg(long, env = "LV_ON_DEMAND", value_parser = FalseyValueParser::new())]
+    pub on_demand: bool,
+
+    /// Reload assets loaded from this directory when their files change
+    #[arg(long, value_name = "DIR", env = "LV_HOT_RELOAD")]
+    pub hot_reload: Option<PathBuf>,
+
+    /// Action bindings file, with lines like `move_forward = W, Up`
+    #[arg(long, value_name = "FILE", env = "LV_BINDINGS")]
+    pub bindings: Option<PathBuf>,
+
+    /// Analog stick dead zone, from 0 to 0.95
+    #[arg(long, env = "LV_DEAD_ZONE")]
+    pub dead_zone: Option<f32>,
+
+    /// Render without showing the window
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Exit after rendering this many frames
+    #[arg(long)]
+    pub frames: Option<u64>,
+
+    /// Save the last frame (see --frames) as a PNG
+    #[arg(long, requires = "frames")]
+    pub screenshot: Option<PathBuf>,
+
+    /// Advance animations by this many milliseconds every frame, instead of the clock
+    #[arg(long)]
+    pub frame_time: Option<f32>,
+
+    /// Use the deferred render path
+    #[arg(long)]
+    pub deferred: bool,
+
+    /// Also render both eyes into a layered target in one pass (needs VK_KHR_multiview)
+    #[arg(long)]
+    pub stereo: bool,
+
+    /// Send the stereo render to an OpenXR headset (needs the xr feature)
+    #[arg(long)]
+    pub xr: bool,
+
+    /// Maximum anisotropic filtering for material textures (1 disables it)
+    #[arg(long)]
+    pub anisotropy: Option<f32>,
+
+    /// Only write a diagnostics report and exit
+    #[arg(long)]
+    pub report: bool,
+
+    /// Loop this audio file as background music (needs the audio feature)
+    #[arg(long)]
+    pub music: Option<PathBuf>,
+
+    /// Lead a review session: followers connecting to this address copy the camera and
+    /// scene toggles
+    #[arg(long, value_name = "ADDRESS", conflicts_with = "sync_follow")]
+    pub sync_lead: Option<String>,
+
+    /// Follow the review session led at this address
+    #[arg(long, value_name = "ADDRESS")]
+    pub sync_follow: Option<String>,
+
+    /// Serve the HTTP remote control on this port (loopback only) or host:port
+    #[arg(long, value_name = "ADDRESS", env = "LV_REMOTE")]
+    pub remote: Option<String>,
+
+    /// Directory the remote control writes screenshots to
+    #[arg(
+        long,
+        value_name = "DIR",
+        env = "LV_REMOTE_CAPTURES",
+        default_value = remote::DEFAULT_CAPTURE_DIR
+    )]
+    pub remote_captures: PathBuf,
+
+    /// Directory the remote control may load scenes and LUTs from
+    #[arg(
+        long,
+        value_name = "DIR",
+        env = "LV_REMOTE_ASSETS",
+        default_value = remote::DEFAULT_ASSET_DIR
+    )]
+    pub remote_assets: PathBuf,
+}
+
+impl Args {
+    pub fn apply(&self, mut builder: AppBuilder) -> AppBuilder {
+        if let Some(gpu) = &self.gpu {
+            builder = builder.gpu(gpu.clone());
+        }
+        if self.allow_software {
+            builder = builder.allow_software(true);
+        }
+        if self.no_validation {
+            builder = builder.validation_layers(false);
+        }
+        if let Some(features) = self.validation_features {
+            builder = builder.validation(features);
+        }
+        if let Some(present_mode) = self.present_mode {
+            builder = builder.present_mode(present_mode);
+        }
+        if let Some(buffering) = self.buffering {
+            builder = builder.buffering(buffering);
+        }
+        if self.frame_pacing {
+            builder = builder.frame_pacing(true);
+        }
+        if self.fps_limit.is_some() {
+            builder = builder.fps_limit(self.fps_limit);
+        }
+        if self.on_demand {
+            builder = builder.redraw_on_demand(true);
+        }
+        if let Some(dir) = &self.hot_reload {
+            builder = builder.hot_reload(dir.clone());
+        }
+        if let Some(ms) = self.frame_time {
+            builder = builder.fixed_frame_time(Some(Duration::from_secs_f32(ms / 1000.0)));
+        }
+        if self.deferred {
+            builder = builder.render_path(RenderPath::Deferred);
+        }
+        if self.stereo {
+            builder = builder.stereo(true);
+        }
+        if self.xr {
+            builder = builder.xr(true);
+        }
+        if let Some(anisotropy) = self.anisotropy {
+            builder = builder.max_anisotropy(anisotropy);
+        }
+        if let Some(music) = &self.music {
+            builder = builder.music(music.clone());
+        }
+
+        builder
+    }
+}
@@ -0,0 +1,433 @@
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    exposure::AutoExposureSettings, features::DeviceRequirements, fog::FogSettings,
+    godrays::GodRaySettings, sampler::DEFAULT_MAX_ANISOTROPY, shadow::ShadowSettings,
+    sky::SkySettings, terrain::TerrainSettings, tonemap::ToneMapping,
+    upload::DEFAULT_UPLOAD_BUDGET, MAX_FRAMES_IN_FLIGHT, VALIDATION_ENABLED,
+};
+
+// Uma fila a mais pedida pelo app (ex.: transferências em segundo plano com prioridade
+// baixa), além da de gráficos/apresentação
+#[derive(Copy, Clone, Debug)]
+pub struct QueueRequest {
+    pub flags: vk::QueueFlags,
+    pub priority: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct QueueConfig {
+    // Prioridade das filas de gráficos e apresentação
+    pub main_priority: f32,
+    pub extra: Vec<QueueRequest>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            main_priority: 1.0,
+            extra: vec![],
+        }
+    }
+}
+
+// Modo de apresentação desejado. Se a surface não suportar, caímos pro FIFO, que
+// sempre existe
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PresentModePreference {
+    // V-sync
+    Fifo,
+    // V-sync, mas apresenta atrasado em vez de esperar o próximo vblank
+    FifoRelaxed,
+    // Triple buffering: sem tearing e sem bloquear
+    Mailbox,
+    // Sem v-sync, com tearing
+    Immediate,
+}
+
+impl PresentModePreference {
+    pub fn to_vk(self) -> vk::PresentModeKHR {
+        match self {
+            PresentModePreference::Fifo => vk::PresentModeKHR::FIFO,
+            PresentModePreference::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+            PresentModePreference::Mailbox => vk::PresentModeKHR::MAILBOX,
+            PresentModePreference::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        }
+    }
+
+    // None pros modos que a gente nunca pede (os SHARED_*)
+    pub fn from_vk(mode: vk::PresentModeKHR) -> Option<Self> {
+        match mode {
+            vk::PresentModeKHR::FIFO => Some(PresentModePreference::Fifo),
+            vk::PresentModeKHR::FIFO_RELAXED => Some(PresentModePreference::FifoRelaxed),
+            vk::PresentModeKHR::MAILBOX => Some(PresentModePreference::Mailbox),
+            vk::PresentModeKHR::IMMEDIATE => Some(PresentModePreference::Immediate),
+            _ => None,
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "fifo" => Ok(PresentModePreference::Fifo),
+            "fifo-relaxed" => Ok(PresentModePreference::FifoRelaxed),
+            "mailbox" => Ok(PresentModePreference::Mailbox),
+            "immediate" => Ok(PresentModePreference::Immediate),
+            _ => Err(anyhow!(
+                "Unknown present mode '{}' (fifo, fifo-relaxed, mailbox or immediate).",
+                value
+            )),
+        }
+    }
+
+    // Ordem usada pelo atalho que alterna os modos
+    pub fn next(self) -> Self {
+        match self {
+            PresentModePreference::Fifo | PresentModePreference::FifoRelaxed => {
+                PresentModePreference::Mailbox
+            }
+            PresentModePreference::Mailbox => PresentModePreference::Immediate,
+            PresentModePreference::Immediate => PresentModePreference::Fifo,
+        }
+    }
+}
+
+// Quantas imagens a swapchain tem, e com isso quantos frames a CPU grava adiantado
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Buffering {
+    // Uma imagem além do mínimo da surface, com MAX_FRAMES_IN_FLIGHT frames em voo
+    #[default]
+    Driver,
+    // Duas imagens e um frame em voo: menos latência, a CPU espera mais
+    Double,
+    // Três imagens e dois frames em voo
+    Triple,
+}
+
+impl Buffering {
+    // Antes de limitar ao que a surface aceita
+    pub fn image_count(self, capabilities: &vk::SurfaceCapabilitiesKHR) -> u32 {
+        match self {
+            Buffering::Driver => capabilities.min_image_count + 1,
+            Buffering::Double => 2,
+            Buffering::Triple => 3,
+        }
+    }
+
+    // Nunca passa de MAX_FRAMES_IN_FLIGHT, que é pra quantos frames os recursos existem
+    pub fn frames_in_flight(self) -> usize {
+        match self {
+            Buffering::Driver | Buffering::Triple => MAX_FRAMES_IN_FLIGHT,
+            Buffering::Double => 1,
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "driver" => Ok(Buffering::Driver),
+            "double" => Ok(Buffering::Double),
+            "triple" => Ok(Buffering::Triple),
+            _ => Err(anyhow!(
+                "Unknown buffering '{}' (driver, double or triple).",
+                value
+            )),
+        }
+    }
+}
+
+// Como a cena 3D é iluminada
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RenderPath {
+    // Cada malha calcula a luz na hora em que é desenhada
+    #[default]
+    Forward,
+    // As malhas só escrevem o G-buffer (cor, normal, parâmetros do material e
+    // profundidade), e a luz é calculada uma vez por pixel num triângulo de tela cheia
+    Deferred,
+}
+
+// O que a cena mostra no lugar da imagem final, pra depurar. Escrito pelas shaders das
+// malhas (ou pela resolução do deferred) e mostrado pelo debug.frag no lugar do tone
+// mapping
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    #[default]
+    Final,
+    // Cor base do material, sem luz
+    Albedo,
+    // Já com o normal map, de [-1, 1] pra [0, 1]
+    Normals,
+    // Distância até a câmera, de perto (branco) a longe (preto)
+    Depth,
+    // Rugosidade no vermelho, metálico no verde (só nos materiais PBR)
+    RoughnessMetallic,
+    // Quantas vezes cada pixel foi desenhado, do azul (1) ao vermelho (8 ou mais)
+    Overdraw,
+    // Cascata do shadow map de cada pixel
+    Cascades,
+}
+
+impl DebugView {
+    // Na ordem das teclas 1 a 7
+    pub const ALL: [DebugView; 7] = [
+        DebugView::Final,
+        DebugView::Albedo,
+        DebugView::Normals,
+        DebugView::Depth,
+        DebugView::RoughnessMetallic,
+        DebugView::Overdraw,
+        DebugView::Cascades,
+    ];
+
+    // Mesmo número do debug.glsl
+    pub fn shader_index(self) -> u32 {
+        DebugView::ALL.iter().position(|v| *v == self).unwrap() as u32
+    }
+}
+
+// Estágios opcionais do pós-processamento, depois do tone mapping. Ligar ou desligar
+// muda a cadeia de passes, então recria o que depende da swapchain
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PostProcessing {
+    // LUT 3D depois do tone mapping (a neutra, se nenhuma foi carregada)
+    pub color_grading: bool,
+    // Antialiasing barato em cima da imagem final (não temos MSAA)
+    pub fxaa: bool,
+}
+
+// Checagens extras da camada de validação (VK_EXT_validation_features), sem precisar
+// mexer no vkconfig. Só valem com `validation_layers`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationFeatures {
+    // Instrumenta os shaders pra pegar acessos fora dos limites em descritores/buffers
+    pub gpu_assisted: bool,
+    // Hazards de sincronização (leitura depois de escrita sem barreira, etc.)
+    pub synchronization: bool,
+    // Avisos de uso ineficiente da API
+    pub best_practices: bool,
+}
+
+impl ValidationFeatures {
+    // Lista separada por vírgulas: `gpu`, `sync`, `best-practices` ou `all`
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut features = Self::default();
+
+        for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match name {
+                "gpu" => features.gpu_assisted = true,
+                "sync" => features.synchronization = true,
+                "best-practices" => features.best_practices = true,
+                "all" => {
+                    features = Self {
+                        gpu_assisted: true,
+                        synchronization: true,
+                        best_practices: true,
+                    }
+                }
+                _ => return Err(anyhow!("Unknown validation feature '{}'.", name)),
+            }
+        }
+
+        Ok(features)
+    }
+
+    pub fn any(&self) -> bool {
+        self.gpu_assisted || self.synchronization || self.best_practices
+    }
+
+    pub fn to_vk(self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut enables = vec![];
+
+        if self.gpu_assisted {
+            enables.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+            enables.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+        }
+        if self.synchronization {
+            enables.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        if self.best_practices {
+            enables.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+
+        enables
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GpuSelector {
+    Index(usize),
+    // Sem diferenciar maiúsculas
+    Name(String),
+}
+
+impl GpuSelector {
+    pub fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            GpuSelector::Index(i) => *i == index,
+            GpuSelector::Name(part) => name.to_lowercase().contains(&part.to_lowercase()),
+        }
+    }
+}
+
+impl FromStr for GpuSelector {
+    type Err = std::convert::Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.parse() {
+            Ok(index) => GpuSelector::Index(index),
+            Err(_) => GpuSelector::Name(value.into()),
+        })
+    }
+}
+
+// Tudo que pode ser configurado antes da criação do App
+#[derive(Clone, Debug)]
+pub struct AppConfig {
+    pub requirements: DeviceRequirements,
+    // GPU a usar, pelo índice na ordem do vkEnumeratePhysicalDevices ou por parte do nome.
+    // Escolhida assim, não precisa ser dedicada
+    pub gpu: Option<GpuSelector>,
+    // Aceita rasterizadores de software (lavapipe, SwiftShader), pra CI e máquinas
+    // virtuais. Sem GPU que sirva eles são usados de qualquer jeito, com um aviso
+    pub allow_software: bool,
+    // Camada de validação e o logger dela. Por padrão, VALIDATION_ENABLED
+    pub validation_layers: bool,
+    pub queues: QueueConfig,
+    pub low_latency: bool,
+    // Máximo de bytes enviados pra GPU por frame pela fila de uploads
+    pub upload_budget: u64,
+    pub present_mode: PresentModePreference,
+    pub buffering: Buffering,
+    // Pede saída HDR10 se a surface suportar (VK_EXT_swapchain_colorspace)
+    pub hdr: bool,
+    // Pede Display P3 ou sRGB estendido se a surface tiver (VK_EXT_swapchain_colorspace),
+    // pra telas de gamut largo. O HDR10 vem antes, se pedido
+    pub wide_gamut: bool,
+    // Formatos da swapchain em ordem de preferência, ex.: 10 bits UNORM e depois BGRA8
+    // sRGB. O primeiro que a surface tiver é usado (o HDR10, se pedido, vem antes de todos)
+    pub surface_formats: Vec<vk::SurfaceFormatKHR>,
+    pub validation: ValidationFeatures,
+    // Depth invertido: 1 no plano próximo, 0 no distante (compare GREATER, clear 0.0).
+    // Ligado por padrão: a precisão do float fica onde o depth perspectivo é mais
+    // apertado, e cenas grandes param de brigar no z
+    pub reverse_z: bool,
+    // A câmera do App começa sem plano distante (ver camera::Projection). Com o reverse-Z
+    // isso quase não custa precisão
+    pub infinite_far_plane: bool,
+    // FPS máximo quando a apresentação não limita (MAILBOX/IMMEDIATE)
+    pub fps_limit: Option<u32>,
+    // Intervalo constante entre presents (ver pacing.rs), no refresh do display ou no
+    // limite de FPS
+    pub frame_pacing: bool,
+    // Só desenha quando algo muda (ControlFlow::Wait), pra ferramentas e editores
+    pub redraw_on_demand: bool,
+    // Delta de todo frame no lugar do relógio, pra animações e exposição andarem igual
+    // em qualquer máquina (testes de imagem)
+    pub fixed_frame_time: Option<Duration>,
+    pub shadows: ShadowSettings,
+    pub render_path: RenderPath,
+    // Desenha o depth das malhas opacas antes, num pass só de depth, e a cor depois com
+    // compare EQUAL: cada pixel é sombreado uma vez só. Só no forward (ver
+    // RenderPassData::has_prepass)
+    pub depth_prepass: bool,
+    pub tone_mapping: ToneMapping,
+    pub auto_exposure: AutoExposureSettings,
+    pub post_processing: PostProcessing,
+    // .cube ou PNG em faixa pro color grading
+    pub color_grading_lut: Option<PathBuf>,
+    pub sky: SkySettings,
+    // Névoa volumétrica em froxels (ver fog.rs)
+    pub fog: FogSettings,
+    // Raios de sol em espaço de tela, somados depois das transparentes (ver godrays.rs)
+    pub god_rays: GodRaySettings,
+    pub terrain: TerrainSettings,
+    // Testa as malhas contra o depth do frame anterior num compute e desenha as visíveis
+    // com desenho indireto (ver occlusion.rs)
+    pub occlusion_culling: bool,
+    // Pass que desenha o id de cada objeto pro `App::pick` (ver picking.rs)
+    pub picking: bool,
+    // Contorno dos objetos passados pro `App::set_selection`. Pede um depth com stencil
+    // (ver outline.rs)
+    pub outline: bool,
+    // Pass que desenha a cena pros dois olhos de uma vez num alvo de duas camadas, com
+    // VK_KHR_multiview (ver multiview.rs)
+    pub stereo: bool,
+    // Manda o caminho estéreo pro headset pelo OpenXR, com as poses dele. Liga o `stereo`
+    // e só existe com a feature xr (ver xr.rs)
+    pub xr: bool,
+    // Sonda de reflexo posta com `App::place_reflection_probe`: a cena capturada num cubo
+    // dá os reflexos especulares em volta dela. Precisa de VK_KHR_multiview (ver probe.rs)
+    pub reflection_probes: bool,
+    // Grade de sondas posta com `App::set_irradiance_grid`: a luz que chega em cada sonda,
+    // em harmônicos esféricos, vira a luz ambiente difusa em volta dela. Precisa de
+    // VK_KHR_multiview (ver irradiance.rs)
+    pub irradiance_probes: bool,
+    // Anisotropia máxima das texturas dos materiais, limitada pelo dispositivo. 1 desliga
+    // (ver sampler.rs)
+    pub max_anisotropy: f32,
+    // Texturas dos materiais sobem só com os mips pequenos, e os grandes vêm pela fila de
+    // transferência conforme o tamanho na tela (ver streaming.rs)
+    pub texture_streaming: bool,
+    // Threads que leem e decodificam os assets pedidos com `App::load_*_async`. Com 0 o
+    // carregamento assíncrono fica desligado
+    pub asset_threads: usize,
+    // Pasta vigiada: malhas e texturas carregadas de dentro dela são recarregadas quando o
+    // arquivo muda (ver watcher.rs). Usa as threads de assets
+    pub hot_reload: Option<PathBuf>,
+    // Música de fundo em loop desde o começo. Só toca com a feature audio (ver audio.rs)
+    pub music: Option<PathBuf>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            requirements: DeviceRequirements::default(),
+            gpu: None,
+            allow_software: false,
+            validation_layers: VALIDATION_ENABLED,
+            queues: QueueConfig::default(),
+            low_latency: false,
+            upload_budget: DEFAULT_UPLOAD_BUDGET,
+            present_mode: PresentModePreference::Mailbox,
+            buffering: Buffering::default(),
+            hdr: false,
+            wide_gamut: false,
+            surface_formats: vec![vk::SurfaceFormatKHR {
+                format: vk::Format::B8G8R8A8_SRGB,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            }],
+            validation: ValidationFeatures::default(),
+            reverse_z: true,
+            infinite_far_plane: false,
+            fps_limit: None,
+            frame_pacing: false,
+            redraw_on_demand: false,
+            fixed_frame_time: None,
+            shadows: ShadowSettings::default(),
+            render_path: RenderPath::default(),
+            depth_prepass: false,
+            tone_mapping: ToneMapping::default(),
+            auto_exposure: AutoExposureSettings::default(),
+            post_processing: PostProcessing::default(),
+            color_grading_lut: None,
+            sky: SkySettings::default(),
+            fog: FogSettings::default(),
+            god_rays: GodRaySettings::default(),
+            terrain: TerrainSettings::default(),
+            occlusion_culling: false,
+            picking: false,
+            outline: false,
+            stereo: false,
+            xr: false,
+            reflection_probes: false,
+            irradiance_probes: false,
+            max_anisotropy: DEFAULT_MAX_ANISOTROPY,
+            texture_streaming: false,
+            asset_threads: 0,
+            hot_reload: None,
+            music: None,
+        }
+    }
+}
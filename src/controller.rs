@@ -0,0 +1,221 @@
+use nalgebra_glm as glm;
+use winit::{error::ExternalError, window::Window};
+
+use crate::{
+    camera::Camera,
+    input::{GamepadAxis, Input},
+};
+
+// Limite do pitch, pra câmera não virar de cabeça pra baixo
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+// Câmera em primeira pessoa: as ações move_* andam no plano da visão (cima/baixo no y
+// do mundo), boost acelera e o mouse gira (só com o cursor capturado). No controle, o
+// analógico esquerdo anda e o direito gira
+#[derive(Clone, Debug)]
+pub struct FlyCamera {
+    // Unidades por segundo
+    pub speed: f32,
+    // Multiplicador da velocidade com boost
+    pub boost: f32,
+    // Radianos por pixel de movimento do mouse
+    pub sensitivity: f32,
+    // Radianos por segundo com o analógico no fim do curso
+    pub look_speed: f32,
+    grabbed: bool,
+}
+
+impl Default for FlyCamera {
+    fn default() -> Self {
+        Self {
+            speed: 3.0,
+            boost: 4.0,
+            sensitivity: 0.002,
+            look_speed: 2.5,
+            grabbed: false,
+        }
+    }
+}
+
+impl FlyCamera {
+    pub fn is_grabbed(&self) -> bool {
+        self.grabbed
+    }
+
+    // Prende e esconde o cursor (ou solta). Alguns sistemas não suportam prender, e aí o
+    // cursor só fica escondido
+    pub fn set_grab(&mut self, window: &Window, grabbed: bool) -> Result<(), ExternalError> {
+        self.grabbed = grabbed;
+        window.set_cursor_visible(!grabbed);
+        window.set_cursor_grab(grabbed)
+    }
+
+    // Yaw e pitch saem da própria câmera, então mexer nela por fora (ex: `look_at`) não
+    // briga com o controlador
+    pub fn update(&mut self, input: &Input, camera: &mut Camera, delta: f32) {
+        let mouse_delta = if self.grabbed {
+            input.mouse_delta()
+        } else {
+            glm::vec2(0.0, 0.0)
+        };
+
+        let look = input.right_stick() * self.look_speed * delta;
+
+        let forward = camera.forward();
+        let yaw = (-forward.x).atan2(-forward.z) - mouse_delta.x * self.sensitivity - look.x;
+        let pitch = (forward.y.clamp(-1.0, 1.0).asin() - mouse_delta.y * self.sensitivity + look.y)
+            .clamp(-MAX_PITCH, MAX_PITCH);
+
+        let yaw = glm::quat_angle_axis(yaw, &glm::vec3(0.0, 1.0, 0.0));
+        let pitch = glm::quat_angle_axis(pitch, &glm::vec3(1.0, 0.0, 0.0));
+        camera.rotation = yaw * pitch;
+
+        let mut direction = glm::vec3(0.0, 0.0, 0.0);
+        let axes = [
+            ("move_forward", camera.forward()),
+            ("move_back", -camera.forward()),
+            ("move_right", camera.right()),
+            ("move_left", -camera.right()),
+            ("move_up", glm::vec3(0.0, 1.0, 0.0)),
+            ("move_down", glm::vec3(0.0, -1.0, 0.0)),
+        ];
+        for (action, axis) in axes {
+            if input.action_down(action) {
+                direction += axis;
+            }
+        }
+
+        // O analógico anda mais devagar se não for até o fim
+        let stick = input.left_stick();
+        direction += camera.forward() * stick.y + camera.right() * stick.x;
+
+        let length = glm::length(&direction);
+        if length > f32::EPSILON {
+            let boost = if input.action_down("boost") {
+                self.boost
+            } else {
+                1.0
+            };
+
+            camera.position += direction / length.max(1.0) * self.speed * boost * delta;
+        }
+    }
+}
+
+// Câmera de visualizador de modelos: gira em volta de um alvo com orbit_rotate (botão
+// esquerdo), arrasta o alvo com orbit_pan (botão do meio) e aproxima com a roda. No
+// controle, o analógico direito gira, o esquerdo arrasta e os gatilhos aproximam
+#[derive(Clone, Debug)]
+pub struct OrbitCamera {
+    pub target: glm::Vec3,
+    // Radianos por pixel
+    pub rotate_sensitivity: f32,
+    // Fração da distância por pixel, pra o arrasto acompanhar o zoom
+    pub pan_sensitivity: f32,
+    // Fração da distância por passo da roda
+    pub zoom_sensitivity: f32,
+    pub min_distance: f32,
+    // Radianos por segundo com o analógico no fim do curso
+    pub gamepad_speed: f32,
+}
+
+impl OrbitCamera {
+    pub fn new(target: glm::Vec3) -> Self {
+        Self {
+            target,
+            rotate_sensitivity: 0.005,
+            pan_sensitivity: 0.0015,
+            zoom_sensitivity: 0.1,
+            min_distance: 0.05,
+            gamepad_speed: 2.0,
+        }
+    }
+
+    // Distância e ângulos saem da posição atual da câmera em relação ao alvo
+    pub fn update(&mut self, input: &Input, camera: &mut Camera, delta: f32) {
+        let offset = camera.position - self.target;
+        let mut distance = glm::length(&offset).max(self.min_distance);
+        let mut yaw = offset.x.atan2(offset.z);
+        let mut pitch = (offset.y / distance).clamp(-1.0, 1.0).asin();
+        let mouse_delta = input.mouse_delta();
+
+        if input.action_down("orbit_rotate") {
+            yaw -= mouse_delta.x * self.rotate_sensitivity;
+            pitch = (pitch + mouse_delta.y * self.rotate_sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        if input.action_down("orbit_pan") {
+            let pan = self.pan_sensitivity * distance;
+            self.target += camera.up() * mouse_delta.y * pan - camera.right() * mouse_delta.x * pan;
+        }
+
+        let rotate = input.right_stick() * self.gamepad_speed * delta;
+        yaw -= rotate.x;
+        pitch = (pitch + rotate.y).clamp(-MAX_PITCH, MAX_PITCH);
+
+        let pan = input.left_stick() * distance * delta;
+        self.target += camera.right() * pan.x + camera.up() * pan.y;
+
+        let zoom = input.wheel() * self.zoom_sensitivity
+            + (input.axis(GamepadAxis::RightTrigger) - input.axis(GamepadAxis::LeftTrigger))
+                * delta;
+        distance = (distance * (1.0 - zoom)).max(self.min_distance);
+
+        let direction = glm::vec3(
+            pitch.cos() * yaw.sin(),
+            pitch.sin(),
+            pitch.cos() * yaw.cos(),
+        );
+        camera.position = self.target + direction * distance;
+        camera.look_at(self.target);
+    }
+}
+
+// Controlador ativo, trocado em tempo de execução
+#[derive(Clone, Debug)]
+pub enum CameraController {
+    Fly(FlyCamera),
+    Orbit(OrbitCamera),
+}
+
+impl CameraController {
+    // Distância do alvo ao trocar pra órbita
+    const ORBIT_DISTANCE: f32 = 2.0;
+
+    pub fn update(&mut self, input: &Input, camera: &mut Camera, delta: f32) {
+        match self {
+            CameraController::Fly(fly) => fly.update(input, camera, delta),
+            CameraController::Orbit(orbit) => orbit.update(input, camera, delta),
+        }
+    }
+
+    // Só a câmera em primeira pessoa captura o cursor
+    pub fn can_grab(&self) -> bool {
+        matches!(self, CameraController::Fly(fly) if !fly.is_grabbed())
+    }
+
+    pub fn is_grabbed(&self) -> bool {
+        matches!(self, CameraController::Fly(fly) if fly.is_grabbed())
+    }
+
+    pub fn set_grab(&mut self, window: &Window, grabbed: bool) -> Result<(), ExternalError> {
+        match self {
+            CameraController::Fly(fly) => fly.set_grab(window, grabbed),
+            CameraController::Orbit(_) => Ok(()),
+        }
+    }
+
+    // Alterna entre os dois, sem a câmera pular: a órbita começa num alvo logo à frente
+    pub fn switch(&mut self, window: &Window, camera: &Camera) -> Result<(), ExternalError> {
+        let result = self.set_grab(window, false);
+
+        *self = match self {
+            CameraController::Fly(_) => CameraController::Orbit(OrbitCamera::new(
+                camera.position + camera.forward() * Self::ORBIT_DISTANCE,
+            )),
+            CameraController::Orbit(_) => CameraController::Fly(FlyCamera::default()),
+        };
+
+        result
+    }
+}
@@ -0,0 +1,224 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::texture::{block_info, TextureData};
+
+// "DDS " + DDS_HEADER; com o FourCC "DX10" ainda vem o DDS_HEADER_DXT10
+const HEADER_SIZE: usize = 4 + 124;
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+// D3D10_RESOURCE_DIMENSION_TEXTURE2D
+const DIMENSION_TEXTURE2D: u32 = 3;
+
+// DDS 2D com os mips que vierem no arquivo. Formatos BC1 a BC7 (pelo FourCC antigo ou pelo
+// cabeçalho DX10) e alguns RGBA8 do DX10
+pub fn load(path: &Path, srgb: bool) -> Result<TextureData> {
+    let bytes = fs::read(path)?;
+    parse(&bytes, srgb).with_context(|| format!("Failed to load DDS from {}", path.display()))
+}
+
+// Nos FourCC antigos o arquivo não diz se a cor é sRGB, então vale o `srgb`. No DX10 vale o
+// formato do arquivo
+pub fn parse(bytes: &[u8], srgb: bool) -> Result<TextureData> {
+    if bytes.len() < HEADER_SIZE || &bytes[..4] != b"DDS " {
+        return Err(anyhow!("Not a DDS file."));
+    }
+
+    let word = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let (height, width, depth) = (word(12), word(16), word(24));
+    let mip_levels = word(28).max(1);
+    let (pixel_flags, four_cc) = (word(80), word(84));
+    let caps2 = word(112);
+
+    if width == 0 || height == 0 {
+        return Err(anyhow!("Texture has no texels ({}x{}).", width, height));
+    }
+    // floor(log2(max(w, h))) + 1: daí pra frente os níveis seriam todos 1x1
+    let max_levels = 32 - width.max(height).leading_zeros();
+    if mip_levels > max_levels {
+        return Err(anyhow!(
+            "{} mip levels for {}x{}, at most {} fit.",
+            mip_levels,
+            width,
+            height,
+            max_levels
+        ));
+    }
+
+    if caps2 & DDSCAPS2_CUBEMAP != 0 || depth > 1 {
+        return Err(anyhow!(
+            "Only 2D textures are supported, not cubes or volumes."
+        ));
+    }
+    if pixel_flags & DDPF_FOURCC == 0 {
+        return Err(anyhow!(
+            "Uncompressed DDS without a DX10 header is not supported."
+        ));
+    }
+
+    let (format, mut offset) = if &four_cc.to_le_bytes() == b"DX10" {
+        if bytes.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+            return Err(anyhow!("Truncated DX10 header."));
+        }
+
+        let (dxgi_format, dimension, array_size) = (word(128), word(132), word(140));
+        if dimension != DIMENSION_TEXTURE2D || array_size > 1 {
+            return Err(anyhow!("Only 2D textures are supported, not arrays."));
+        }
+
+        let format = dxgi_format_to_vk(dxgi_format)
+            .ok_or_else(|| anyhow!("Unsupported DXGI format {}.", dxgi_format))?;
+        (format, HEADER_SIZE + DX10_HEADER_SIZE)
+    } else {
+        let format = four_cc_to_vk(&four_cc.to_le_bytes(), srgb).ok_or_else(|| {
+            anyhow!(
+                "Unsupported FourCC {:?}.",
+                String::from_utf8_lossy(&four_cc.to_le_bytes())
+            )
+        })?;
+        (format, HEADER_SIZE)
+    };
+
+    // Os níveis vêm em sequência, do maior pro menor, cada um com blocos inteiros
+    let (block_size, bytes_per_block) = block_info(format);
+    let blocks = |texels: u32| (texels / block_size + (texels % block_size != 0) as u32) as usize;
+    let mut levels = vec![];
+    for level in 0..mip_levels {
+        let level_width = width.checked_shr(level).unwrap_or(0).max(1);
+        let level_height = height.checked_shr(level).unwrap_or(0).max(1);
+        let end = blocks(level_width)
+            .checked_mul(blocks(level_height))
+            .and_then(|b| b.checked_mul(bytes_per_block as usize))
+            .and_then(|size| size.checked_add(offset));
+        let level_bytes = end.and_then(|end| bytes.get(offset..end)).ok_or_else(|| {
+            anyhow!(
+                "File ends in mip level {} of {} ({}x{}).",
+                level,
+                mip_levels,
+                level_width,
+                level_height
+            )
+        })?;
+
+        levels.push(level_bytes.to_vec());
+        offset += level_bytes.len();
+    }
+
+    Ok(TextureData {
+        width,
+        height,
+        format,
+        levels,
+    })
+}
+
+fn four_cc_to_vk(four_cc: &[u8; 4], srgb: bool) -> Option<vk::Format> {
+    let format = match (four_cc, srgb) {
+        (b"DXT1", false) => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        (b"DXT1", true) => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        (b"DXT2" | b"DXT3", false) => vk::Format::BC2_UNORM_BLOCK,
+        (b"DXT2" | b"DXT3", true) => vk::Format::BC2_SRGB_BLOCK,
+        (b"DXT4" | b"DXT5", false) => vk::Format::BC3_UNORM_BLOCK,
+        (b"DXT4" | b"DXT5", true) => vk::Format::BC3_SRGB_BLOCK,
+        (b"ATI1" | b"BC4U", _) => vk::Format::BC4_UNORM_BLOCK,
+        (b"BC4S", _) => vk::Format::BC4_SNORM_BLOCK,
+        (b"ATI2" | b"BC5U", _) => vk::Format::BC5_UNORM_BLOCK,
+        (b"BC5S", _) => vk::Format::BC5_SNORM_BLOCK,
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+// Valores do DXGI_FORMAT
+fn dxgi_format_to_vk(format: u32) -> Option<vk::Format> {
+    let format = match format {
+        28 => vk::Format::R8G8B8A8_UNORM,
+        29 => vk::Format::R8G8B8A8_SRGB,
+        71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        87 => vk::Format::B8G8R8A8_UNORM,
+        91 => vk::Format::B8G8R8A8_SRGB,
+        95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cabeçalho DXT1 sem os dados dos níveis
+    fn header(width: u32, height: u32, mip_levels: u32) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(b"DDS ");
+        let mut word = |offset: usize, value: u32| {
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes())
+        };
+        word(12, height);
+        word(16, width);
+        word(28, mip_levels);
+        word(80, DDPF_FOURCC);
+        word(84, u32::from_le_bytes(*b"DXT1"));
+        bytes
+    }
+
+    #[test]
+    fn reads_every_level() {
+        // 8x8, 4x4, 2x2 e 1x1: 4 + 1 + 1 + 1 blocos de 8 bytes
+        let mut bytes = header(8, 8, 4);
+        bytes.extend((0..7 * 8).map(|i| i as u8));
+
+        let texture = parse(&bytes, false).unwrap();
+        assert_eq!((texture.width, texture.height), (8, 8));
+        assert_eq!(texture.format, vk::Format::BC1_RGBA_UNORM_BLOCK);
+        let sizes = texture.levels.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes, [32, 8, 8, 8]);
+        assert_eq!(texture.levels[1][0], 32);
+    }
+
+    #[test]
+    fn rejects_zero_dimensions() {
+        assert!(parse(&header(0, 8, 1), false).is_err());
+        assert!(parse(&header(8, 0, 1), false).is_err());
+    }
+
+    #[test]
+    fn rejects_too_many_mip_levels() {
+        let mut bytes = header(8, 8, 5);
+        bytes.extend([0; 8 * 8]);
+        assert!(parse(&bytes, false).is_err());
+
+        assert!(parse(&header(1, 1, u32::MAX), false).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_levels() {
+        let mut bytes = header(8, 8, 2);
+        bytes.extend([0; 32 + 7]);
+        assert!(parse(&bytes, false).is_err());
+    }
+
+    #[test]
+    fn rejects_huge_dimensions_without_overflowing() {
+        assert!(parse(&header(u32::MAX, u32::MAX, 1), false).is_err());
+        assert!(parse(&header(u32::MAX, u32::MAX, 32), false).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use std::{mem::size_of, slice};
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    image, leaks,
+    material::Materials,
+    pipeline::{BlendMode, Pipeline, PipelineDesc},
+    scene::SceneDecal,
+    shader::spirv,
+    upload::UploadQueue,
+};
+
+// Decals do deferred, num subpass entre o G-buffer e a luz. Cada um desenha as faces de
+// trás do seu cubo (então funciona com a câmera dentro dele), reconstrói a posição de
+// cada pixel pelo depth e mistura o material na cor e na normal do G-buffer. No forward
+// não cria nada e não grava nada
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DecalRenderer {
+    // Set 2, com o depth como input attachment. O 0 é o do frame e o 1 o do material
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    // O attachment pode ter stencil junto, e o input attachment só pode ter um aspecto
+    depth_view: vk::ImageView,
+    pipeline: Pipeline,
+}
+
+impl DecalRenderer {
+    pub unsafe fn create(device: &Device, data: &AppData, materials: &Materials) -> Result<Self> {
+        let mut decals = Self::default();
+        decals.create_pipeline(device, data, materials)?;
+        Ok(decals)
+    }
+
+    // O depth é recriado com a swapchain, então o set vai junto com a pipeline
+    pub unsafe fn create_pipeline(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        materials: &Materials,
+    ) -> Result<()> {
+        let subpass = match data.render_pass.decal_subpass() {
+            Some(subpass) => subpass,
+            None => return Ok(()),
+        };
+
+        let bindings = &[vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::INPUT_ATTACHMENT)
+            .descriptor_count(1)
+            .build()];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[self.layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(set_layouts);
+        self.set = device.allocate_descriptor_sets(&info)?[0];
+
+        let depth = &data.render_pass.depth;
+        self.depth_view = image::create_image_view(
+            device,
+            depth.image,
+            depth.format,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        let image_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .image_view(self.depth_view)
+            .build()];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+            .image_info(image_info);
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+        let vertex_shader = spirv!("decal.vert");
+        let fragment_shader = spirv!("decal.frag");
+
+        // Inversa do mundo nas duas; se mistura a normal e 1 / tamanho do framebuffer
+        // só na fragment
+        let matrix = size_of::<glm::Mat4>() as u32;
+        let push_constants = &[
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                .offset(0)
+                .size(matrix)
+                .build(),
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(matrix)
+                .size(16)
+                .build(),
+        ];
+        let set_layouts = &[
+            data.frame_descriptors.layout,
+            materials.set_layout,
+            self.layout,
+        ];
+
+        let mut desc = PipelineDesc::new(
+            &vertex_shader[..],
+            &fragment_shader[..],
+            data.render_pass.pass,
+        );
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        desc.front_face = vk::FrontFace::COUNTER_CLOCKWISE;
+        desc.cull_mode = vk::CullModeFlags::FRONT;
+        // O subpass não tem depth attachment; quem corta é o teste de volume na shader
+        desc.depth_test = false;
+        desc.depth_write = false;
+        desc.blend = BlendMode::Alpha;
+        desc.color_mask =
+            vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B;
+        desc.color_attachments = 2;
+        desc.subpass = subpass;
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    // Grava os decals, já dentro do subpass deles. Os materiais que ainda não chegaram na
+    // GPU ficam pro próximo frame. Retorna quantos draw calls fez
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        uploads: &UploadQueue,
+        materials: &Materials,
+        decals: &[SceneDecal],
+    ) -> u32 {
+        if data.render_pass.decal_subpass().is_none() || decals.is_empty() {
+            return 0;
+        }
+
+        let extent = data.swapchain.extent;
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            0,
+            &[data.frame_descriptors.set(slot)],
+            &[],
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            2,
+            &[self.set],
+            &[],
+        );
+
+        let mut draw_calls = 0;
+        for decal in decals {
+            if !materials.is_ready(decal.decal.material, uploads) {
+                continue;
+            }
+
+            // Escala zero em algum eixo: o volume não tem nada dentro
+            let inverse_world = match decal.world.try_inverse() {
+                Some(inverse) => inverse,
+                None => continue,
+            };
+
+            let constants = [
+                (decal.decal.normals as u32).to_ne_bytes(),
+                0u32.to_ne_bytes(),
+                (1.0 / extent.width as f32).to_ne_bytes(),
+                (1.0 / extent.height as f32).to_ne_bytes(),
+            ]
+            .concat();
+
+            materials.bind(
+                device,
+                command_buffer,
+                self.pipeline.layout,
+                decal.decal.material,
+                slot,
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                slice::from_raw_parts(inverse_world.as_ptr() as *const u8, size_of::<glm::Mat4>()),
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                size_of::<glm::Mat4>() as u32,
+                &constants,
+            );
+            device.cmd_draw(command_buffer, 36, 1, 0, 0);
+            draw_calls += 1;
+        }
+
+        draw_calls
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+        leaks::destroy_image_view(device, self.depth_view);
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+        *self = Self::default();
+    }
+}
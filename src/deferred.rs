@@ -0,0 +1,176 @@
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    config::{DebugView, RenderPath},
+    pass::{GBUFFER_FORMATS, SCENE_TRANSFER},
+    pipeline::{Pipeline, PipelineDesc},
+    shader::spirv,
+};
+
+// Resolução do deferred: um triângulo de tela cheia no segundo subpass, que lê o G-buffer
+// como input attachments e calcula a luz de cada pixel uma vez só. No forward não cria
+// nada e não grava nada
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DeferredLighting {
+    // Set 1, com o G-buffer. O set 0 é o do frame, igual ao das malhas
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: Pipeline,
+}
+
+impl DeferredLighting {
+    pub unsafe fn create(device: &Device, data: &AppData) -> Result<Self> {
+        let mut lighting = Self::default();
+        lighting.create_pipeline(device, data)?;
+        Ok(lighting)
+    }
+
+    // O G-buffer é recriado com a swapchain, então o set vai junto com a pipeline
+    pub unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        if data.render_pass.path != RenderPath::Deferred {
+            return Ok(());
+        }
+
+        let bindings = (0..GBUFFER_FORMATS.len() as u32)
+            .map(|i| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(i)
+                    .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        self.layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::INPUT_ATTACHMENT)
+            .descriptor_count(GBUFFER_FORMATS.len() as u32)
+            .build()];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[self.layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(set_layouts);
+        self.set = device.allocate_descriptor_sets(&info)?[0];
+
+        // Input attachments não usam sampler
+        let image_infos = data
+            .render_pass
+            .gbuffer
+            .iter()
+            .map(|g| {
+                [vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(g.view)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+        let writes = image_infos
+            .iter()
+            .enumerate()
+            .map(|(i, image_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.set)
+                    .dst_binding(i as u32)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+                    .image_info(image_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+        let vertex_shader = spirv!("fullscreen.vert");
+        let fragment_shader = spirv!("deferred.frag");
+
+        // Função de transferência, visão de debug e 1 / tamanho do framebuffer
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(16)
+            .build()];
+        let set_layouts = &[data.frame_descriptors.layout, self.layout];
+
+        let mut desc = PipelineDesc::new(
+            &vertex_shader[..],
+            &fragment_shader[..],
+            data.render_pass.pass,
+        );
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        // O depth das malhas continua preso no subpass pro que vem depois, mas a
+        // resolução cobre a tela toda e não testa nem escreve nele
+        desc.depth_test = false;
+        desc.depth_write = false;
+        desc.subpass = data.render_pass.forward_subpass();
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    // Grava a resolução, já dentro do subpass da luz. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        debug_view: DebugView,
+    ) -> u32 {
+        if data.render_pass.path != RenderPath::Deferred {
+            return 0;
+        }
+
+        let extent = data.swapchain.extent;
+        let constants = [
+            (SCENE_TRANSFER as u32).to_ne_bytes(),
+            debug_view.shader_index().to_ne_bytes(),
+            (1.0 / extent.width as f32).to_ne_bytes(),
+            (1.0 / extent.height as f32).to_ne_bytes(),
+        ]
+        .concat();
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            0,
+            &[data.frame_descriptors.set(slot), self.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &constants,
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+        1
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,96 @@
+use log::*;
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    monitor::VideoMode,
+    window::{Fullscreen, Window},
+};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FullscreenMode {
+    #[default]
+    Windowed,
+    // Janela sem borda do tamanho do monitor; troca instantânea, mas passa pelo compositor
+    Borderless,
+    // Modo de vídeo exclusivo (e, com VK_EXT_full_screen_exclusive, controle exclusivo
+    // do display pela swapchain)
+    Exclusive,
+}
+
+// Tamanho e posição da janela antes de entrar em tela cheia, pra voltar igual
+#[derive(Copy, Clone, Debug)]
+pub struct WindowedState {
+    pub position: Option<PhysicalPosition<i32>>,
+    pub size: PhysicalSize<u32>,
+}
+
+impl WindowedState {
+    pub fn save(window: &Window) -> Self {
+        Self {
+            position: window.outer_position().ok(),
+            size: window.inner_size(),
+        }
+    }
+
+    pub fn restore(&self, window: &Window) {
+        window.set_inner_size(self.size);
+        if let Some(position) = self.position {
+            window.set_outer_position(position);
+        }
+    }
+}
+
+// Modo de vídeo com a resolução atual do monitor e a maior taxa de atualização
+pub fn best_video_mode(window: &Window) -> Option<VideoMode> {
+    let monitor = window.current_monitor()?;
+    let size = monitor.size();
+
+    monitor
+        .video_modes()
+        .filter(|m| m.size() == size)
+        .max_by_key(|m| (m.bit_depth(), m.refresh_rate()))
+        .or_else(|| monitor.video_modes().max_by_key(|m| m.size().width))
+}
+
+pub fn video_modes(window: &Window) -> Vec<VideoMode> {
+    window
+        .current_monitor()
+        .map(|m| m.video_modes().collect())
+        .unwrap_or_default()
+}
+
+// Aplica o modo na janela do winit. A swapchain precisa ser recriada depois
+pub fn apply(window: &Window, mode: FullscreenMode) -> FullscreenMode {
+    match mode {
+        FullscreenMode::Windowed => {
+            window.set_fullscreen(None);
+            FullscreenMode::Windowed
+        }
+        FullscreenMode::Borderless => {
+            window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor())));
+            FullscreenMode::Borderless
+        }
+        FullscreenMode::Exclusive => match best_video_mode(window) {
+            Some(video_mode) => {
+                info!(
+                    "Entering exclusive fullscreen ({}x{} @ {}Hz).",
+                    video_mode.size().width,
+                    video_mode.size().height,
+                    video_mode.refresh_rate()
+                );
+                window.set_fullscreen(Some(Fullscreen::Exclusive(video_mode)));
+                FullscreenMode::Exclusive
+            }
+            None => {
+                warn!("No video modes available, falling back to borderless fullscreen.");
+                apply(window, FullscreenMode::Borderless)
+            }
+        },
+    }
+}
+
+// HMONITOR do monitor atual, exigido pelo VK_EXT_full_screen_exclusive no Windows
+#[cfg(windows)]
+pub fn hmonitor(window: &Window) -> Option<*mut std::ffi::c_void> {
+    use winit::platform::windows::MonitorHandleExtWindows;
+    window.current_monitor().map(|m| m.hmonitor())
+}
@@ -0,0 +1,120 @@
+use hecs::World;
+use nalgebra_glm as glm;
+
+use crate::{
+    app::App,
+    camera::Camera,
+    material::MaterialId,
+    mesh::MeshId,
+    scene::{DrawItem, EntityId, Light, SceneLight, Transform},
+};
+
+// Componentes que o renderizador entende. `Transform`, `Light` e `Camera` são os mesmos
+// tipos da cena, mas aqui não tem hierarquia: a transformação já é no mundo
+
+// Desenha uma malha na posição do `Transform` da entidade
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeshRenderer {
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    pub visible: bool,
+}
+
+impl MeshRenderer {
+    pub fn new(mesh: MeshId) -> Self {
+        Self {
+            mesh,
+            material: MaterialId::default(),
+            visible: true,
+        }
+    }
+
+    pub fn with_material(mut self, material: MaterialId) -> Self {
+        self.material = material;
+        self
+    }
+}
+
+// Marca qual câmera manda quando tem mais de uma entidade com `Camera`
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MainCamera;
+
+// O que o frame precisa do mundo, tirado de uma vez pra não segurar o World durante a
+// gravação
+#[derive(Clone, Debug, Default)]
+pub struct RenderExtract {
+    pub draw_list: Vec<DrawItem>,
+    pub lights: Vec<SceneLight>,
+    pub camera: Option<Camera>,
+}
+
+pub fn extract(world: &World) -> RenderExtract {
+    let draw_list = world
+        .query::<(&Transform, &MeshRenderer)>()
+        .iter()
+        .filter(|(_, (_, renderer))| renderer.visible)
+        .map(|(entity, (transform, renderer))| DrawItem {
+            mesh: renderer.mesh,
+            material: renderer.material,
+            world: transform.matrix(),
+            animator: None,
+            // Volta pra entidade com `Entity::from_bits`
+            entity: Some(EntityId::External(entity.to_bits().get())),
+        })
+        .collect();
+
+    let lights = world
+        .query::<(&Transform, &Light)>()
+        .iter()
+        .map(|(_, (transform, light))| SceneLight {
+            light: *light,
+            position: transform.translation,
+            direction: glm::quat_rotate_vec3(&transform.rotation, &glm::vec3(0.0, 0.0, -1.0)),
+        })
+        .collect();
+
+    // A marcada com `MainCamera`, senão qualquer uma
+    let mut camera = None;
+    for (_, (transform, entity_camera, main)) in world
+        .query::<(&Transform, &Camera, Option<&MainCamera>)>()
+        .iter()
+    {
+        let mut entity_camera = *entity_camera;
+        entity_camera.position = transform.translation;
+        entity_camera.rotation = transform.rotation;
+
+        camera = Some(entity_camera);
+        if main.is_some() {
+            break;
+        }
+    }
+
+    RenderExtract {
+        draw_list,
+        lights,
+        camera,
+    }
+}
+
+// Sistema de renderização: extrai o mundo e entrega pro app desenhar no próximo frame.
+// Só a posição, a rotação e a projeção da câmera são copiadas; o aspect e o reverse-Z
+// continuam sendo os do app
+pub fn render(world: &World, app: &mut App) -> RenderExtract {
+    let extract = extract(world);
+
+    for item in &extract.draw_list {
+        app.draw_mesh(*item);
+    }
+    for light in &extract.lights {
+        app.add_light(*light);
+    }
+
+    if let Some(camera) = &extract.camera {
+        let target = app.camera_mut();
+        target.position = camera.position;
+        target.rotation = camera.rotation;
+        target.projection = camera.projection;
+    }
+
+    extract
+}
@@ -1,7 +1,48 @@
+use lazy_static::lazy_static;
 use log::*;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::collections::{HashMap, VecDeque};
 use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::sync::Mutex;
 use vulkanalia::vk;
 
+// Quantas mensagens da validação guardar pro relatório de erro
+const RECENT_MESSAGES: usize = 256;
+
+lazy_static! {
+    static ref MESSAGES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+    // Quantas vezes cada mensagem apareceu nesse frame. Só a primeira é logada
+    static ref REPEATED: Mutex<HashMap<String, u32>> = Mutex::new(HashMap::new());
+}
+
+// As últimas mensagens de WARNING pra cima, mais antigas primeiro
+pub fn recent_messages() -> Vec<String> {
+    MESSAGES.lock().unwrap().iter().cloned().collect()
+}
+
+// Chamado no fim de cada frame: avisa quantas repetições foram escondidas e recomeça
+pub fn end_frame() {
+    let mut repeated = REPEATED.lock().unwrap();
+    let suppressed = repeated.values().map(|n| n - 1).sum::<u32>();
+    if suppressed > 0 {
+        debug!(
+            "Suppressed {} repeated validation messages this frame.",
+            suppressed
+        );
+    }
+
+    repeated.clear();
+}
+
+unsafe fn string(pointer: *const c_char) -> Option<String> {
+    if pointer.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(pointer).to_string_lossy().into_owned())
+    }
+}
+
 // Troço verboso dos infernos mas é bem auto-explicativo
 pub extern "system" fn debug_callback(
     severity: vk::DebugUtilsMessageSeverityFlagsEXT,
@@ -10,16 +51,63 @@ pub extern "system" fn debug_callback(
     _: *mut c_void,
 ) -> vk::Bool32 {
     let data = unsafe { *data };
-    let message = unsafe { CStr::from_ptr(data.message) }.to_string_lossy();
-
-    if severity >= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR {
-        error!("({:?}) {}", type_, message);
-    } else if severity >= vk::DebugUtilsMessageSeverityFlagsEXT::WARNING {
-        warn!("({:?}) {}", type_, message);
-    } else if severity >= vk::DebugUtilsMessageSeverityFlagsEXT::INFO {
-        debug!("({:?}) {}", type_, message);
+    let message = unsafe { string(data.message) }.unwrap_or_default();
+    let id = unsafe { string(data.message_id_name) }.unwrap_or_else(|| "?".to_string());
+
+    // Os objetos envolvidos, com o nome se alguém deu um (vkSetDebugUtilsObjectNameEXT)
+    let objects = (0..data.object_count as usize)
+        .map(|i| {
+            let object = unsafe { *data.objects.add(i) };
+            match unsafe { string(object.object_name) } {
+                Some(name) => format!(
+                    "{:?} {:#x} '{}'",
+                    object.object_type, object.object_handle, name
+                ),
+                None => format!("{:?} {:#x}", object.object_type, object.object_handle),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut text = format!("({:?}) [{}] {}", type_, id, message);
+    if !objects.is_empty() {
+        text.push_str(&format!(" (objects: {})", objects.join(", ")));
+    }
+
+    // A mesma mensagem costuma se repetir a cada draw; mostramos só uma vez por frame
+    {
+        let mut repeated = REPEATED.lock().unwrap();
+        let count = repeated.entry(text.clone()).or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            return vk::FALSE;
+        }
+    }
+
+    if severity.intersects(
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+            | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+    ) {
+        let mut messages = MESSAGES.lock().unwrap();
+        if messages.len() == RECENT_MESSAGES {
+            messages.pop_front();
+        }
+        messages.push_back(format!("[{:?}] {}", severity, text));
+    }
+
+    if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        error!("{}", text);
+
+        // Só captura com RUST_BACKTRACE=1, mostra de onde no nosso código veio a chamada
+        let backtrace = Backtrace::capture();
+        if backtrace.status() == BacktraceStatus::Captured {
+            error!("Backtrace:\n{}", backtrace);
+        }
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        warn!("{}", text);
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        info!("{}", text);
     } else {
-        trace!("({:?}) {}", type_, message);
+        trace!("{}", text);
     }
 
     vk::FALSE
@@ -27,4 +115,45 @@ pub extern "system" fn debug_callback(
 
 #[derive(Debug, thiserror::Error)]
 #[error("Missing {0}.")]
-pub struct SuitabilityError(pub &'static str);
\ No newline at end of file
+pub struct SuitabilityError(pub &'static str);
+
+// Falhas que quem chama pode querer tratar (`downcast_ref::<RendererError>()` no
+// anyhow::Error), em vez de só mostrar a mensagem
+#[derive(Debug, thiserror::Error)]
+pub enum RendererError {
+    #[error("Failed to create Vulkan instance: {0}")]
+    InstanceCreation(String),
+    #[error("Failed to find suitable physical device.")]
+    NoSuitableDevice,
+    #[error("Failed to create logical device: {0}")]
+    DeviceCreation(String),
+    #[error("Swapchain is out of date.")]
+    SwapchainOutOfDate,
+    #[error("Failed to compile shader: {0}")]
+    ShaderCompile(String),
+    // Shader que compila mas o naga rejeita (WGSL), com a mensagem apontando a linha
+    #[error("Shader validation failed: {0}")]
+    ShaderValidation(String),
+    #[error("Failed to allocate memory: {0}")]
+    Allocation(String),
+    #[error("Device lost.")]
+    DeviceLost,
+    #[error("Surface lost.")]
+    SurfaceLost,
+    #[error("Vulkan error: {0}")]
+    Vulkan(vk::ErrorCode),
+}
+
+impl From<vk::ErrorCode> for RendererError {
+    fn from(code: vk::ErrorCode) -> Self {
+        match code {
+            vk::ErrorCode::DEVICE_LOST => RendererError::DeviceLost,
+            vk::ErrorCode::SURFACE_LOST_KHR => RendererError::SurfaceLost,
+            vk::ErrorCode::OUT_OF_DATE_KHR => RendererError::SwapchainOutOfDate,
+            vk::ErrorCode::OUT_OF_HOST_MEMORY | vk::ErrorCode::OUT_OF_DEVICE_MEMORY => {
+                RendererError::Allocation(code.to_string())
+            }
+            _ => RendererError::Vulkan(code),
+        }
+    }
+}
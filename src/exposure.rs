@@ -0,0 +1,407 @@
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    buffer::{StorageBuffer, StorageLocation},
+    info::QueueFamilyIndices,
+    pipeline::Pipeline,
+    sampler::{self, SamplerDesc},
+    shader::spirv,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+// Quantas faixas de luminância o histograma tem. A 0 guarda os pixels pretos (ou abaixo
+// do mínimo), que ficam de fora da média
+const HISTOGRAM_BINS: u64 = 256;
+// Luminância inicial da adaptação: o cinza médio, que dá exposição 1
+const INITIAL_LUMINANCE: f32 = 0.18;
+// Tamanho do grupo do histogram.comp, em pixels
+const TILE_SIZE: u32 = 16;
+
+// Exposição automática: a média da luminância da cena (num histograma, pra ignorar os
+// extremos) é perseguida aos poucos, como o olho se adaptando
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AutoExposureSettings {
+    pub enabled: bool,
+    // Faixa em EV100 que a adaptação cobre. O que está fora é tratado como o limite
+    pub min_ev: f32,
+    pub max_ev: f32,
+    // Quão rápido a exposição chega na nova média, por segundo
+    pub speed: f32,
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_ev: -4.0,
+            max_ev: 16.0,
+            speed: 1.5,
+        }
+    }
+}
+
+impl AutoExposureSettings {
+    // EV100 = log2(L * 100 / 12.5), com L em cd/m²
+    fn min_log_luminance(&self) -> f32 {
+        self.min_ev - 3.0
+    }
+
+    fn log_luminance_range(&self) -> f32 {
+        (self.max_ev - self.min_ev).max(1.0)
+    }
+}
+
+// Histograma e luminância adaptada vivem num storage buffer só na GPU, que o tone mapping
+// lê direto. A CPU só recebe uma cópia da luminância, pra mostrar
+#[derive(Clone, Debug, Default)]
+pub struct AutoExposure {
+    pub settings: AutoExposureSettings,
+    // A fila de gráficos também faz compute (quase sempre); se não fizer, fica desligada
+    supported: bool,
+    // uint histogram[256], float luminance
+    buffer: StorageBuffer,
+    // Cópia da luminância, uma por frame em voo, e se o frame dela fez a cópia
+    readback: Vec<StorageBuffer>,
+    pending: Vec<bool>,
+    // A última que voltou da GPU
+    luminance: f32,
+    // Zerado e com a luminância inicial no primeiro frame
+    initialized: bool,
+    sampler: vk::Sampler,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    histogram: Pipeline,
+    adaptation: Pipeline,
+}
+
+impl AutoExposure {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        settings: AutoExposureSettings,
+    ) -> Result<Self> {
+        let mut exposure = Self {
+            settings,
+            ..Default::default()
+        };
+
+        exposure.create_device_objects(instance, device, data)?;
+        exposure.write_target(device, data);
+        Ok(exposure)
+    }
+
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+        let families = instance.get_physical_device_queue_family_properties(data.physical_device);
+        self.supported = families[indices.graphics as usize]
+            .queue_flags
+            .contains(vk::QueueFlags::COMPUTE);
+        if !self.supported {
+            warn!("Graphics queue has no compute support, auto exposure disabled.");
+        }
+
+        self.buffer = StorageBuffer::create(
+            instance,
+            device,
+            data,
+            Self::buffer_size(),
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            StorageLocation::Device,
+        )?;
+        self.readback = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| {
+                StorageBuffer::create(
+                    instance,
+                    device,
+                    data,
+                    4,
+                    vk::BufferUsageFlags::empty(),
+                    StorageLocation::Readback,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.pending = vec![false; MAX_FRAMES_IN_FLIGHT];
+        self.luminance = INITIAL_LUMINANCE;
+        self.initialized = false;
+
+        self.sampler = sampler::get(
+            device,
+            &SamplerDesc {
+                filter: vk::Filter::NEAREST,
+                ..Default::default()
+            },
+        )?;
+
+        // Alvo HDR no binding 0, o buffer no 1
+        let bindings = &[
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            StorageBuffer::binding(1, vk::ShaderStageFlags::COMPUTE),
+        ];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .build(),
+        ];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[self.layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(set_layouts);
+        self.set = device.allocate_descriptor_sets(&info)?[0];
+
+        self.buffer.write_descriptor(device, self.set, 1);
+
+        // Faixa de luminância (mínimo e 1 / tamanho) no histograma; faixa, fração da
+        // adaptação nesse frame e número de pixels na média
+        let histogram_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(8)
+            .build()];
+        let adaptation_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(16)
+            .build()];
+
+        let histogram_shader = spirv!("histogram.comp");
+        let adaptation_shader = spirv!("exposure.comp");
+        self.histogram = Pipeline::create_compute(
+            device,
+            &histogram_shader[..],
+            set_layouts,
+            histogram_constants,
+        )?;
+        self.adaptation = Pipeline::create_compute(
+            device,
+            &adaptation_shader[..],
+            set_layouts,
+            adaptation_constants,
+        )?;
+
+        Ok(())
+    }
+
+    // O alvo HDR é recriado com a swapchain
+    pub unsafe fn write_target(&self, device: &Device, data: &AppData) {
+        let image_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(data.render_pass.color.view)
+            .sampler(self.sampler)
+            .build()];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(image_info);
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer.buffer
+    }
+
+    pub fn buffer_size() -> vk::DeviceSize {
+        HISTOGRAM_BINS * 4 + 4
+    }
+
+    // Se o tone mapping deve usar a luminância adaptada
+    pub fn active(&self) -> bool {
+        self.supported && self.settings.enabled
+    }
+
+    // Luminância adaptada, atrasada uns frames (a cópia só é lida depois da fence)
+    pub fn luminance(&self) -> Option<f32> {
+        self.active().then_some(self.luminance)
+    }
+
+    // Entre o pass da cena e o tone mapping: monta o histograma do alvo HDR e anda com a
+    // adaptação. `delta` é o tempo do último frame, em segundos
+    pub unsafe fn record(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        delta: f32,
+    ) {
+        // A fence desse slot já foi esperada, então a cópia dele chegou
+        if std::mem::take(&mut self.pending[slot]) {
+            let bytes = self.readback[slot].read();
+            self.luminance = f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        // O tone mapping lê a luminância mesmo no primeiro frame
+        if !self.initialized {
+            device.cmd_fill_buffer(command_buffer, self.buffer.buffer, 0, HISTOGRAM_BINS * 4, 0);
+            device.cmd_update_buffer(
+                command_buffer,
+                self.buffer.buffer,
+                HISTOGRAM_BINS * 4,
+                &INITIAL_LUMINANCE.to_ne_bytes(),
+            );
+            self.buffer.barrier(
+                device,
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            );
+            self.initialized = true;
+        }
+
+        if !self.active() {
+            return;
+        }
+
+        // O frame anterior pode ainda estar lendo (tone mapping, cópia) ou escrevendo o buffer
+        self.buffer.barrier(
+            device,
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER
+                | vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        let extent = data.swapchain.extent;
+        let settings = &self.settings;
+        let histogram_constants = [
+            settings.min_log_luminance().to_ne_bytes(),
+            (1.0 / settings.log_luminance_range()).to_ne_bytes(),
+        ]
+        .concat();
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.histogram.pipeline,
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.histogram.layout,
+            0,
+            &[self.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.histogram.layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &histogram_constants,
+        );
+        device.cmd_dispatch(
+            command_buffer,
+            (extent.width + TILE_SIZE - 1) / TILE_SIZE,
+            (extent.height + TILE_SIZE - 1) / TILE_SIZE,
+            1,
+        );
+
+        self.buffer.barrier(
+            device,
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        // Exponencial no tempo, pra não depender do FPS
+        let adaptation = 1.0 - (-delta * settings.speed).exp();
+        let pixels = (extent.width * extent.height) as f32;
+        let adaptation_constants = [
+            settings.min_log_luminance().to_ne_bytes(),
+            settings.log_luminance_range().to_ne_bytes(),
+            adaptation.clamp(0.0, 1.0).to_ne_bytes(),
+            pixels.to_ne_bytes(),
+        ]
+        .concat();
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.adaptation.pipeline,
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.adaptation.layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &adaptation_constants,
+        );
+        device.cmd_dispatch(command_buffer, 1, 1, 1);
+
+        // O tone mapping lê a luminância; a cópia leva ela pra CPU
+        self.buffer.barrier(
+            device,
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ,
+        );
+
+        let readback = &self.readback[slot];
+        let region = vk::BufferCopy::builder()
+            .src_offset(HISTOGRAM_BINS * 4)
+            .dst_offset(0)
+            .size(4);
+        device.cmd_copy_buffer(
+            command_buffer,
+            self.buffer.buffer,
+            readback.buffer,
+            &[region],
+        );
+        readback.barrier(
+            device,
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::HOST,
+            vk::AccessFlags::HOST_READ,
+        );
+        self.pending[slot] = true;
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.histogram.destroy(device);
+        self.adaptation.destroy(device);
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+        self.buffer.destroy(device);
+        self.readback.drain(..).for_each(|b| b.destroy(device));
+    }
+}
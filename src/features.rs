@@ -0,0 +1,232 @@
+use std::{collections::HashSet, ffi::c_void, ptr};
+
+use vulkanalia::prelude::v1_0::*;
+
+// Recursos do dispositivo que algum módulo pode querer usar. Mapeiam direto pros campos
+// de vk::PhysicalDeviceFeatures
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Feature {
+    GeometryShader,
+    TessellationShader,
+    SamplerAnisotropy,
+    FillModeNonSolid,
+    WideLines,
+    MultiDrawIndirect,
+    DrawIndirectFirstInstance,
+    TextureCompressionBc,
+    TextureCompressionAstc,
+    ShaderInt64,
+}
+
+impl Feature {
+    pub fn is_supported(&self, features: &vk::PhysicalDeviceFeatures) -> bool {
+        let value = match self {
+            Feature::GeometryShader => features.geometry_shader,
+            Feature::TessellationShader => features.tessellation_shader,
+            Feature::SamplerAnisotropy => features.sampler_anisotropy,
+            Feature::FillModeNonSolid => features.fill_mode_non_solid,
+            Feature::WideLines => features.wide_lines,
+            Feature::MultiDrawIndirect => features.multi_draw_indirect,
+            Feature::DrawIndirectFirstInstance => features.draw_indirect_first_instance,
+            Feature::TextureCompressionBc => features.texture_compression_bc,
+            Feature::TextureCompressionAstc => features.texture_compression_astc_ldr,
+            Feature::ShaderInt64 => features.shader_int64,
+        };
+
+        value == vk::TRUE
+    }
+
+    pub fn enable(&self, features: &mut vk::PhysicalDeviceFeatures) {
+        let field = match self {
+            Feature::GeometryShader => &mut features.geometry_shader,
+            Feature::TessellationShader => &mut features.tessellation_shader,
+            Feature::SamplerAnisotropy => &mut features.sampler_anisotropy,
+            Feature::FillModeNonSolid => &mut features.fill_mode_non_solid,
+            Feature::WideLines => &mut features.wide_lines,
+            Feature::MultiDrawIndirect => &mut features.multi_draw_indirect,
+            Feature::DrawIndirectFirstInstance => &mut features.draw_indirect_first_instance,
+            Feature::TextureCompressionBc => &mut features.texture_compression_bc,
+            Feature::TextureCompressionAstc => &mut features.texture_compression_astc_ldr,
+            Feature::ShaderInt64 => &mut features.shader_int64,
+        };
+
+        *field = vk::TRUE;
+    }
+}
+
+// O que o app precisa (`required`, sem isso o dispositivo é descartado) e o que ele
+// aproveita se tiver (`optional`)
+#[derive(Clone, Debug, Default)]
+pub struct DeviceRequirements {
+    pub required: Vec<Feature>,
+    pub optional: Vec<Feature>,
+}
+
+impl DeviceRequirements {
+    pub fn missing(&self, features: &vk::PhysicalDeviceFeatures) -> Option<Feature> {
+        self.required
+            .iter()
+            .cloned()
+            .find(|f| !f.is_supported(features))
+    }
+
+    // Os recursos obrigatórios mais os opcionais que o dispositivo realmente tem
+    pub fn negotiate(&self, features: &vk::PhysicalDeviceFeatures) -> DeviceCapabilities {
+        let enabled = self
+            .required
+            .iter()
+            .chain(self.optional.iter())
+            .cloned()
+            .filter(|f| f.is_supported(features))
+            .collect::<HashSet<_>>();
+
+        DeviceCapabilities {
+            enabled,
+            portability_subset: false,
+            portability: PortabilitySubset::default(),
+            present_wait: false,
+            display_timing: false,
+            full_screen_exclusive: false,
+            memory_budget: false,
+            draw_indirect_count: false,
+            multiview: false,
+            push_descriptor: false,
+            descriptor_update_template: false,
+        }
+    }
+}
+
+// Os recursos que de fato foram ligados no dispositivo lógico, pra outros módulos consultarem
+#[derive(Clone, Debug, Default)]
+pub struct DeviceCapabilities {
+    pub enabled: HashSet<Feature>,
+    // Implementação não conforme (MoltenVK): coisas como triangle fans, polígonos em modo
+    // POINT e swizzle em image views podem não existir
+    pub portability_subset: bool,
+    // O que o portability subset ainda permite (tudo, sem ele)
+    pub portability: PortabilitySubset,
+    // VK_KHR_present_id + VK_KHR_present_wait
+    pub present_wait: bool,
+    // VK_GOOGLE_display_timing: pedir e medir o horário em que cada present aparece
+    pub display_timing: bool,
+    // VK_EXT_full_screen_exclusive (só existe no Windows)
+    pub full_screen_exclusive: bool,
+    // VK_EXT_memory_budget: uso e orçamento de memória por heap
+    pub memory_budget: bool,
+    // VK_KHR_draw_indirect_count, pro occlusion culling
+    pub draw_indirect_count: bool,
+    // VK_KHR_multiview, pro caminho estéreo e a captura das sondas
+    pub multiview: bool,
+    // VK_KHR_push_descriptor: os descritores dos materiais vão direto no command buffer
+    pub push_descriptor: bool,
+    // VK_KHR_descriptor_update_template: cada set de material reescrito numa chamada só
+    pub descriptor_update_template: bool,
+}
+
+impl DeviceCapabilities {
+    pub fn has(&self, feature: Feature) -> bool {
+        self.enabled.contains(&feature)
+    }
+
+    pub fn vk_features(&self) -> vk::PhysicalDeviceFeatures {
+        let mut features = vk::PhysicalDeviceFeatures::default();
+        self.enabled.iter().for_each(|f| f.enable(&mut features));
+        features
+    }
+}
+
+// Os recursos do VK_KHR_portability_subset que o renderer pode acabar usando. Sem a
+// extensão a implementação é conforme e tudo vale
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PortabilitySubset {
+    pub triangle_fans: bool,
+    pub point_polygons: bool,
+    pub image_view_format_swizzle: bool,
+}
+
+impl Default for PortabilitySubset {
+    fn default() -> Self {
+        Self {
+            triangle_fans: true,
+            point_polygons: true,
+            image_view_format_swizzle: true,
+        }
+    }
+}
+
+impl PortabilitySubset {
+    pub fn allows_topology(&self, topology: vk::PrimitiveTopology) -> bool {
+        topology != vk::PrimitiveTopology::TRIANGLE_FAN || self.triangle_fans
+    }
+
+    pub fn allows_polygon_mode(&self, mode: vk::PolygonMode) -> bool {
+        mode != vk::PolygonMode::POINT || self.point_polygons
+    }
+
+    // Qualquer coisa além da identidade é um swizzle
+    pub fn allows_swizzle(&self, components: &vk::ComponentMapping) -> bool {
+        let identity = [components.r, components.g, components.b, components.a]
+            .iter()
+            .all(|c| *c == vk::ComponentSwizzle::IDENTITY);
+        identity || self.image_view_format_swizzle
+    }
+}
+
+// VkPhysicalDevicePortabilitySubsetFeaturesKHR. A extensão é provisória e o vulkanalia não
+// gera a struct, então ela está aqui com o layout da especificação
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PortabilitySubsetFeatures {
+    pub s_type: vk::StructureType,
+    pub next: *mut c_void,
+    pub constant_alpha_color_blend_factors: vk::Bool32,
+    pub events: vk::Bool32,
+    pub image_view_format_reinterpretation: vk::Bool32,
+    pub image_view_format_swizzle: vk::Bool32,
+    pub image_view_2d_on_3d_image: vk::Bool32,
+    pub multisample_array_image: vk::Bool32,
+    pub mutable_comparison_samplers: vk::Bool32,
+    pub point_polygons: vk::Bool32,
+    pub sampler_mip_lod_bias: vk::Bool32,
+    pub separate_stencil_mask_ref: vk::Bool32,
+    pub shader_sample_rate_interpolation_functions: vk::Bool32,
+    pub tessellation_isolines: vk::Bool32,
+    pub tessellation_point_mode: vk::Bool32,
+    pub triangle_fans: vk::Bool32,
+    pub vertex_attribute_access_beyond_stride: vk::Bool32,
+}
+
+impl Default for PortabilitySubsetFeatures {
+    fn default() -> Self {
+        Self {
+            // VK_STRUCTURE_TYPE_PHYSICAL_DEVICE_PORTABILITY_SUBSET_FEATURES_KHR
+            s_type: vk::StructureType::from_raw(1000163000),
+            next: ptr::null_mut(),
+            constant_alpha_color_blend_factors: vk::FALSE,
+            events: vk::FALSE,
+            image_view_format_reinterpretation: vk::FALSE,
+            image_view_format_swizzle: vk::FALSE,
+            image_view_2d_on_3d_image: vk::FALSE,
+            multisample_array_image: vk::FALSE,
+            mutable_comparison_samplers: vk::FALSE,
+            point_polygons: vk::FALSE,
+            sampler_mip_lod_bias: vk::FALSE,
+            separate_stencil_mask_ref: vk::FALSE,
+            shader_sample_rate_interpolation_functions: vk::FALSE,
+            tessellation_isolines: vk::FALSE,
+            tessellation_point_mode: vk::FALSE,
+            triangle_fans: vk::FALSE,
+            vertex_attribute_access_beyond_stride: vk::FALSE,
+        }
+    }
+}
+
+impl PortabilitySubsetFeatures {
+    pub fn subset(&self) -> PortabilitySubset {
+        PortabilitySubset {
+            triangle_fans: self.triangle_fans == vk::TRUE,
+            point_polygons: self.point_polygons == vk::TRUE,
+            image_view_format_swizzle: self.image_view_format_swizzle == vk::TRUE,
+        }
+    }
+}
@@ -0,0 +1,500 @@
+use anyhow::Result;
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator,
+    app::AppData,
+    config::DebugView,
+    image::{self, TrackedImage},
+    info::QueueFamilyIndices,
+    leaks, pass,
+    pipeline::{BlendMode, Pipeline, PipelineDesc},
+    sampler::{self, SamplerDesc},
+    shader::spirv,
+};
+
+// Froxels: o frustum da câmera fatiado em x e y na tela e em z ao longo do olhar
+const FROXEL_GRID: vk::Extent3D = vk::Extent3D {
+    width: 160,
+    height: 90,
+    depth: 64,
+};
+const FROXEL_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+// Tamanho do grupo das duas computes, em x e y
+const GROUP_SIZE: u32 = 8;
+// Parâmetros do meio, albedo e alcance, 1 / tamanho do framebuffer (ver fog.glsl)
+const CONSTANTS_SIZE: u32 = 40;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FogSettings {
+    // Cria os volumes e o pass; trocar recria a swapchain (ver `App::set_fog`)
+    pub enabled: bool,
+    // Extinção por unidade de distância, de `height` pra baixo
+    pub density: f32,
+    // Quão rápido a densidade cai acima de `height`. 0 deixa igual em todo lugar
+    pub height_falloff: f32,
+    pub height: f32,
+    // Fração da luz que é espalhada em vez de absorvida, por canal
+    pub albedo: glm::Vec3,
+    // g do Henyey-Greenstein: acima de 0 espalha pra frente (o halo em volta do sol)
+    pub anisotropy: f32,
+    // Até que distância da câmera a névoa acumula. O que está além recebe a mesma da borda
+    pub range: f32,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            density: 0.02,
+            height_falloff: 0.1,
+            height: 0.0,
+            albedo: glm::vec3(0.9, 0.9, 0.9),
+            anisotropy: 0.3,
+            range: 64.0,
+        }
+    }
+}
+
+// Névoa volumétrica em froxels, entre o pass da cena e o das transparentes. Um compute
+// calcula a luz espalhada e a extinção em cada froxel, com as luzes e sombras do frame;
+// outro acumula da câmera pra longe; e um triângulo de tela cheia aplica o acumulado até
+// o depth de cada pixel por cima do alvo HDR. As transparentes ficam de fora
+#[derive(Debug, Default)]
+pub struct VolumetricFog {
+    pub settings: FogSettings,
+    // Ligada na configuração, com compute na fila de gráficos e o depth da cena legível
+    supported: bool,
+    // rgb: luz espalhada por unidade de distância, a: extinção
+    scattering: TrackedImage,
+    scattering_memory: vk::DeviceMemory,
+    scattering_view: vk::ImageView,
+    // rgb: luz acumulada até o fim da fatia, a: transmitância
+    integrated: TrackedImage,
+    integrated_memory: vk::DeviceMemory,
+    integrated_view: vk::ImageView,
+    // Só o aspecto de depth, pra amostrar
+    depth_view: vk::ImageView,
+    // Set 1 das três pipelines; o 0 é o do frame
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    inject: Pipeline,
+    integrate: Pipeline,
+    composite: Pipeline,
+    // Carrega o alvo HDR e desenha por cima (ver `pass::create_overlay_pass`)
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+}
+
+impl VolumetricFog {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        settings: FogSettings,
+    ) -> Result<Self> {
+        let mut fog = Self {
+            settings,
+            ..Default::default()
+        };
+        fog.create_pipeline(instance, device, data)?;
+        Ok(fog)
+    }
+
+    // O pass da cena guarda o depth amostrável quando a névoa está ligada
+    pub unsafe fn samples_depth(instance: &Instance, data: &AppData, format: vk::Format) -> bool {
+        data.config.fog.enabled && image::supports_sampling(instance, data, format)
+    }
+
+    // Tudo aqui depende do depth e do alvo HDR, então é recriado com a swapchain
+    pub unsafe fn create_pipeline(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        if !data.config.fog.enabled {
+            return Ok(());
+        }
+
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+        let families = instance.get_physical_device_queue_family_properties(data.physical_device);
+        let compute = families[indices.graphics as usize]
+            .queue_flags
+            .contains(vk::QueueFlags::COMPUTE);
+        let depth = &data.render_pass.depth;
+        self.supported = compute && Self::samples_depth(instance, data, depth.format);
+        if !self.supported {
+            warn!("No compute queue or sampled depth, volumetric fog disabled.");
+            return Ok(());
+        }
+
+        let (scattering, scattering_memory) = image::create_image_3d(
+            instance,
+            device,
+            data,
+            FROXEL_GRID,
+            FROXEL_FORMAT,
+            vk::ImageUsageFlags::STORAGE,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.scattering = TrackedImage::new(scattering, vk::ImageAspectFlags::COLOR);
+        self.scattering_memory = scattering_memory;
+        self.scattering_view = image::create_image_view_layers(
+            device,
+            scattering,
+            FROXEL_FORMAT,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageViewType::_3D,
+            0,
+            1,
+        )?;
+
+        let (integrated, integrated_memory) = image::create_image_3d(
+            instance,
+            device,
+            data,
+            FROXEL_GRID,
+            FROXEL_FORMAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.integrated = TrackedImage::new(integrated, vk::ImageAspectFlags::COLOR);
+        self.integrated_memory = integrated_memory;
+        self.integrated_view = image::create_image_view_layers(
+            device,
+            integrated,
+            FROXEL_FORMAT,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageViewType::_3D,
+            0,
+            1,
+        )?;
+
+        self.depth_view = image::create_image_view(
+            device,
+            depth.image,
+            depth.format,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        self.create_descriptors(device)?;
+        let (render_pass, framebuffer) = pass::create_overlay_pass(device, data)?;
+        self.render_pass = render_pass;
+        self.framebuffer = framebuffer;
+
+        let set_layouts = &[data.frame_descriptors.layout, self.layout];
+        let compute_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(CONSTANTS_SIZE)
+            .build()];
+        let inject_shader = spirv!("fog_inject.comp");
+        let integrate_shader = spirv!("fog_integrate.comp");
+        self.inject =
+            Pipeline::create_compute(device, &inject_shader[..], set_layouts, compute_constants)?;
+        self.integrate = Pipeline::create_compute(
+            device,
+            &integrate_shader[..],
+            set_layouts,
+            compute_constants,
+        )?;
+
+        let vertex_shader = spirv!("fullscreen.vert");
+        let fragment_shader = spirv!("fog.frag");
+        let fragment_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(CONSTANTS_SIZE)
+            .build()];
+
+        let mut desc =
+            PipelineDesc::new(&vertex_shader[..], &fragment_shader[..], self.render_pass);
+        desc.set_layouts = set_layouts;
+        desc.push_constants = fragment_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        desc.depth_test = false;
+        desc.depth_write = false;
+        desc.blend = BlendMode::Premultiplied;
+        self.composite = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    unsafe fn create_descriptors(&mut self, device: &Device) -> Result<()> {
+        // Os dois volumes como storage pras computes, o acumulado e o depth pra composição
+        let bindings = &[
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(2)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(3)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(2)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(2)
+                .build(),
+        ];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[self.layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(set_layouts);
+        self.set = device.allocate_descriptor_sets(&info)?[0];
+
+        // Trilinear entre os froxels; o depth sem filtro
+        let linear = sampler::get(device, &SamplerDesc::default())?;
+        let nearest = sampler::get(
+            device,
+            &SamplerDesc {
+                filter: vk::Filter::NEAREST,
+                ..Default::default()
+            },
+        )?;
+
+        // Os volumes ficam sempre em GENERAL
+        let scattering_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.scattering_view)
+            .build()];
+        let integrated_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.integrated_view)
+            .build()];
+        let sampled_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.integrated_view)
+            .sampler(linear)
+            .build()];
+        let depth_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .image_view(self.depth_view)
+            .sampler(nearest)
+            .build()];
+
+        let writes = [
+            (0, vk::DescriptorType::STORAGE_IMAGE, scattering_info),
+            (1, vk::DescriptorType::STORAGE_IMAGE, integrated_info),
+            (2, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, sampled_info),
+            (3, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, depth_info),
+        ]
+        .map(|(binding, type_, info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(self.set)
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(type_)
+                .image_info(info)
+                .build()
+        });
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+        Ok(())
+    }
+
+    // Depois do pass da cena, com as luzes do frame já escritas. Retorna quantos draw
+    // calls fez
+    pub unsafe fn record(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        debug_view: DebugView,
+    ) -> u32 {
+        if !self.supported || debug_view != DebugView::Final {
+            return 0;
+        }
+
+        let extent = data.swapchain.extent;
+        let settings = &self.settings;
+        let constants = [
+            settings.density.max(0.0).to_ne_bytes(),
+            settings.height_falloff.max(0.0).to_ne_bytes(),
+            settings.height.to_ne_bytes(),
+            settings.anisotropy.clamp(-0.99, 0.99).to_ne_bytes(),
+            settings.albedo.x.to_ne_bytes(),
+            settings.albedo.y.to_ne_bytes(),
+            settings.albedo.z.to_ne_bytes(),
+            settings.range.max(1.0).to_ne_bytes(),
+            (1.0 / extent.width as f32).to_ne_bytes(),
+            (1.0 / extent.height as f32).to_ne_bytes(),
+        ]
+        .concat();
+        let sets = &[data.frame_descriptors.set(slot), self.set];
+
+        // O frame anterior pode ainda estar lendo os dois volumes
+        self.scattering.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+        );
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.inject.pipeline,
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.inject.layout,
+            0,
+            sets,
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.inject.layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &constants,
+        );
+        device.cmd_dispatch(
+            command_buffer,
+            (FROXEL_GRID.width + GROUP_SIZE - 1) / GROUP_SIZE,
+            (FROXEL_GRID.height + GROUP_SIZE - 1) / GROUP_SIZE,
+            FROXEL_GRID.depth,
+        );
+
+        self.scattering.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+        self.integrated.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+        );
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.integrate.pipeline,
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.integrate.layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &constants,
+        );
+        device.cmd_dispatch(
+            command_buffer,
+            (FROXEL_GRID.width + GROUP_SIZE - 1) / GROUP_SIZE,
+            (FROXEL_GRID.height + GROUP_SIZE - 1) / GROUP_SIZE,
+            1,
+        );
+
+        self.integrated.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(extent);
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(render_area);
+        device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.composite.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.composite.layout,
+            0,
+            sets,
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.composite.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &constants,
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+        device.cmd_end_render_pass(command_buffer);
+
+        1
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        if !self.supported {
+            return;
+        }
+
+        self.inject.destroy(device);
+        self.integrate.destroy(device);
+        self.composite.destroy(device);
+        device.destroy_framebuffer(self.framebuffer, None);
+        device.destroy_render_pass(self.render_pass, None);
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+        leaks::destroy_image_view(device, self.depth_view);
+        leaks::destroy_image_view(device, self.scattering_view);
+        leaks::destroy_image(device, self.scattering.image);
+        allocator::free(device, self.scattering_memory);
+        leaks::destroy_image_view(device, self.integrated_view);
+        leaks::destroy_image(device, self.integrated.image);
+        allocator::free(device, self.integrated_memory);
+
+        *self = Self {
+            settings: self.settings,
+            ..Default::default()
+        };
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    pass::PostStage,
+    pipeline::{Pipeline, PipelineDesc},
+    sampler::{self, SamplerDesc},
+    shader::spirv,
+    texture,
+};
+
+// Estágio de FXAA: procura bordas pela luminância da imagem já com tone mapping e mistura
+// os pixels ao longo delas. Sem ele na cadeia não cria nada e não grava nada
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Fxaa {
+    sampler: vk::Sampler,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: Pipeline,
+}
+
+impl Fxaa {
+    pub unsafe fn create(device: &Device, data: &AppData) -> Result<Self> {
+        let mut fxaa = Self::default();
+        fxaa.create_pipeline(device, data)?;
+        Ok(fxaa)
+    }
+
+    // Lê o alvo do estágio anterior, que é recriado com a swapchain
+    pub unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        if !data.post_pass.has_stage(PostStage::Fxaa) {
+            return Ok(());
+        }
+
+        // Linear: a busca ao longo da borda amostra entre os pixels
+        self.sampler = sampler::get(device, &SamplerDesc::default())?;
+
+        self.layout = texture::create_set_layout(device)?;
+
+        let pool_sizes = &[vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .build()];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[self.layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(set_layouts);
+        self.set = device.allocate_descriptor_sets(&info)?[0];
+
+        let image_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(data.post_pass.input(PostStage::Fxaa))
+            .sampler(self.sampler)
+            .build()];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(image_info);
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+        let vertex_shader = spirv!("fullscreen.vert");
+        let fragment_shader = spirv!("fxaa.frag");
+
+        // 1 / tamanho do framebuffer e as transferências da entrada e da saída
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(16)
+            .build()];
+        let set_layouts = &[self.layout];
+
+        let mut desc = PipelineDesc::new(
+            &vertex_shader[..],
+            &fragment_shader[..],
+            data.post_pass.stage_pass(PostStage::Fxaa),
+        );
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        desc.depth_test = false;
+        desc.depth_write = false;
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    // Grava o estágio, já dentro do pass dele. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+    ) -> u32 {
+        if !data.post_pass.has_stage(PostStage::Fxaa) {
+            return 0;
+        }
+
+        // A entrada vem codificada; se a saída for um formato _SRGB (o hardware codifica de
+        // novo na escrita) ou scRGB, a shader recodifica
+        let input = data.post_pass.input_transfer(PostStage::Fxaa);
+        let output = data.post_pass.transfer(PostStage::Fxaa);
+
+        let extent = data.swapchain.extent;
+        let constants = [
+            (1.0 / extent.width as f32).to_ne_bytes(),
+            (1.0 / extent.height as f32).to_ne_bytes(),
+            (input as u32).to_ne_bytes(),
+            (output as u32).to_ne_bytes(),
+        ]
+        .concat();
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            0,
+            &[self.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &constants,
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+        1
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+        *self = Self::default();
+    }
+}
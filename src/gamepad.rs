@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Result};
+use gilrs::{Axis, Button, Event, EventType, GamepadId, Gilrs};
+use log::*;
+
+use crate::input::{GamepadAxis, GamepadButton, Input};
+
+// Lê os controles pelo gilrs e joga no `Input`. Só um controle manda por vez: o primeiro
+// conectado, ou o próximo que mexer quando ele sai
+pub struct Gamepads {
+    gilrs: Gilrs,
+    active: Option<GamepadId>,
+}
+
+impl Gamepads {
+    pub fn new() -> Result<Self> {
+        let gilrs = Gilrs::new().map_err(|e| anyhow!("Failed to initialize gamepads: {}", e))?;
+
+        let active = gilrs.gamepads().next().map(|(id, gamepad)| {
+            info!("Using gamepad '{}'.", gamepad.name());
+            id
+        });
+
+        Ok(Self { gilrs, active })
+    }
+
+    // Chamado uma vez por frame, antes da lógica ler o `Input`
+    pub fn poll(&mut self, input: &mut Input) {
+        while let Some(Event { id, event, .. }) = self.gilrs.next_event() {
+            match event {
+                EventType::Connected => {
+                    let name = self.gilrs.gamepad(id).name().to_string();
+                    info!("Gamepad '{}' connected.", name);
+
+                    if self.active.is_none() {
+                        self.active = Some(id);
+                    }
+                }
+                EventType::Disconnected => {
+                    info!("Gamepad '{}' disconnected.", self.gilrs.gamepad(id).name());
+
+                    if self.active == Some(id) {
+                        input.release_gamepad();
+                        self.active = None;
+                    }
+                }
+                event => {
+                    // Sem controle ativo, o primeiro que mexer assume
+                    if *self.active.get_or_insert(id) != id {
+                        continue;
+                    }
+
+                    match event {
+                        EventType::ButtonPressed(button, _) => {
+                            if let Some(button) = map_button(button) {
+                                input.set_gamepad_button(button, true);
+                            }
+                        }
+                        EventType::ButtonReleased(button, _) => {
+                            if let Some(button) = map_button(button) {
+                                input.set_gamepad_button(button, false);
+                            }
+                        }
+                        // Os gatilhos analógicos chegam como botões com valor
+                        EventType::ButtonChanged(Button::LeftTrigger2, value, _) => {
+                            input.set_gamepad_axis(GamepadAxis::LeftTrigger, value);
+                        }
+                        EventType::ButtonChanged(Button::RightTrigger2, value, _) => {
+                            input.set_gamepad_axis(GamepadAxis::RightTrigger, value);
+                        }
+                        EventType::AxisChanged(axis, value, _) => {
+                            if let Some(axis) = map_axis(axis) {
+                                input.set_gamepad_axis(axis, value);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn map_button(button: Button) -> Option<GamepadButton> {
+    let button = match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::North => GamepadButton::North,
+        Button::West => GamepadButton::West,
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+        Button::RightTrigger2 => GamepadButton::RightTrigger,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    };
+
+    Some(button)
+}
+
+fn map_axis(axis: Axis) -> Option<GamepadAxis> {
+    let axis = match axis {
+        Axis::LeftStickX => GamepadAxis::LeftX,
+        Axis::LeftStickY => GamepadAxis::LeftY,
+        Axis::RightStickX => GamepadAxis::RightX,
+        Axis::RightStickY => GamepadAxis::RightY,
+        _ => return None,
+    };
+
+    Some(axis)
+}
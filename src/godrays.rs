@@ -0,0 +1,284 @@
+use anyhow::Result;
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    config::DebugView,
+    image, leaks, pass,
+    pipeline::{BlendMode, Pipeline, PipelineDesc},
+    sampler::{self, SamplerDesc},
+    shader::spirv,
+    uniforms::DirectionalLightUniform,
+};
+
+// Limite das amostras por pixel, pra um valor exagerado não travar a GPU
+const MAX_SAMPLES: u32 = 256;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GodRaySettings {
+    // Cria o pass; trocar recria a swapchain (ver `App::set_god_rays`)
+    pub enabled: bool,
+    // Multiplica a cor da luz direcional nos raios
+    pub intensity: f32,
+    // Amostras do depth em cada pixel, no caminho até o sol. Menos fica mais barato e
+    // mais listrado
+    pub samples: u32,
+    // Quanto cada amostra pesa em relação à anterior, andando na direção do sol
+    pub decay: f32,
+}
+
+impl Default for GodRaySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            intensity: 0.3,
+            samples: 64,
+            decay: 0.97,
+        }
+    }
+}
+
+// Raios de sol por um blur radial do céu em direção à posição do sol na tela, somados
+// por cima do alvo HDR depois das transparentes. Quem tapa o sol é o depth das opacas;
+// com o sol atrás da câmera ou longe da tela não grava nada
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GodRays {
+    pub settings: GodRaySettings,
+    // Ligado na configuração e com o depth da cena legível
+    supported: bool,
+    // Só o aspecto de depth, pra amostrar
+    depth_view: vk::ImageView,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: Pipeline,
+    // Carrega o alvo HDR e desenha por cima (ver `pass::create_overlay_pass`)
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+}
+
+impl GodRays {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        settings: GodRaySettings,
+    ) -> Result<Self> {
+        let mut rays = Self {
+            settings,
+            ..Default::default()
+        };
+        rays.create_pipeline(instance, device, data)?;
+        Ok(rays)
+    }
+
+    pub unsafe fn samples_depth(instance: &Instance, data: &AppData, format: vk::Format) -> bool {
+        data.config.god_rays.enabled && image::supports_sampling(instance, data, format)
+    }
+
+    // Lê o depth e escreve no alvo HDR, então é recriado com a swapchain
+    pub unsafe fn create_pipeline(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        if !data.config.god_rays.enabled {
+            return Ok(());
+        }
+
+        let depth = &data.render_pass.depth;
+        self.supported = Self::samples_depth(instance, data, depth.format);
+        if !self.supported {
+            warn!("Depth format can't be sampled, god rays disabled.");
+            return Ok(());
+        }
+
+        self.depth_view = image::create_image_view(
+            device,
+            depth.image,
+            depth.format,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        let bindings = &[vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .build()];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[self.layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(set_layouts);
+        self.set = device.allocate_descriptor_sets(&info)?[0];
+
+        // A comparação com o depth do céu tem que ser exata: nada de filtro
+        let sampler = sampler::get(
+            device,
+            &SamplerDesc {
+                filter: vk::Filter::NEAREST,
+                ..Default::default()
+            },
+        )?;
+        let image_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .image_view(self.depth_view)
+            .sampler(sampler)
+            .build()];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(image_info);
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+        let (render_pass, framebuffer) = pass::create_overlay_pass(device, data)?;
+        self.render_pass = render_pass;
+        self.framebuffer = framebuffer;
+
+        let vertex_shader = spirv!("fullscreen.vert");
+        let fragment_shader = spirv!("godrays.frag");
+
+        // Sol na tela, amostras e decaimento; cor e depth do céu; 1 / tamanho do
+        // framebuffer
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(40)
+            .build()];
+
+        let mut desc =
+            PipelineDesc::new(&vertex_shader[..], &fragment_shader[..], self.render_pass);
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        desc.depth_test = false;
+        desc.depth_write = false;
+        desc.blend = BlendMode::Additive;
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    // Depois das transparentes. `view_projection` já com a pré-rotação da swapchain.
+    // Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        view_projection: &glm::Mat4,
+        sun: &DirectionalLightUniform,
+        debug_view: DebugView,
+    ) -> u32 {
+        if !self.supported || debug_view != DebugView::Final {
+            return 0;
+        }
+
+        // O sol está no infinito, do lado contrário de pra onde a luz vai
+        let clip =
+            view_projection * glm::vec4(-sun.direction.x, -sun.direction.y, -sun.direction.z, 0.0);
+        if clip.w <= 0.0 {
+            return 0;
+        }
+        let position = glm::vec2(clip.x / clip.w, clip.y / clip.w) * 0.5 + glm::vec2(0.5, 0.5);
+
+        // Some aos poucos quando o sol sai da tela, até meia tela além da borda
+        let outside = glm::vec2(
+            ((position.x - 0.5).abs() - 0.5).max(0.0),
+            ((position.y - 0.5).abs() - 0.5).max(0.0),
+        );
+        let fade = (1.0 - 2.0 * glm::length(&outside)).max(0.0);
+        let color = sun.color.xyz() * self.settings.intensity.max(0.0) * fade;
+        if glm::comp_max(&color) <= 0.0 {
+            return 0;
+        }
+
+        let sky_depth = if data.config.reverse_z { 0.0f32 } else { 1.0 };
+        let samples = self.settings.samples.clamp(1, MAX_SAMPLES);
+        let extent = data.swapchain.extent;
+        let constants = [
+            position.x.to_ne_bytes(),
+            position.y.to_ne_bytes(),
+            (samples as f32).to_ne_bytes(),
+            self.settings.decay.clamp(0.0, 1.0).to_ne_bytes(),
+            color.x.to_ne_bytes(),
+            color.y.to_ne_bytes(),
+            color.z.to_ne_bytes(),
+            sky_depth.to_ne_bytes(),
+            (1.0 / extent.width as f32).to_ne_bytes(),
+            (1.0 / extent.height as f32).to_ne_bytes(),
+        ]
+        .concat();
+
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(extent);
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(render_area);
+        device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            0,
+            &[self.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &constants,
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+        device.cmd_end_render_pass(command_buffer);
+
+        1
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        if !self.supported {
+            return;
+        }
+
+        self.pipeline.destroy(device);
+        device.destroy_framebuffer(self.framebuffer, None);
+        device.destroy_render_pass(self.render_pass, None);
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+        leaks::destroy_image_view(device, self.depth_view);
+
+        *self = Self {
+            settings: self.settings,
+            ..Default::default()
+        };
+    }
+}
@@ -0,0 +1,510 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator,
+    app::AppData,
+    image, leaks,
+    pass::PostStage,
+    pipeline::{Pipeline, PipelineDesc},
+    sampler::{self, SamplerDesc},
+    shader::spirv,
+    texture,
+    upload::{UploadId, UploadQueue, UploadTarget},
+};
+
+// Lado da LUT neutra, usada enquanto nenhuma outra é carregada
+const IDENTITY_SIZE: u32 = 16;
+// O Resolve exporta até 65; mais que isso é engano (ou um arquivo gigante)
+const MAX_LUT_SIZE: u32 = 128;
+// 10 bits por canal sobram pra uma LUT, e o filtro linear é obrigatório nesse formato
+const LUT_FORMAT: vk::Format = vk::Format::A2B10G10R10_UNORM_PACK32;
+
+// LUT 3D na ordem da imagem: o vermelho varia mais rápido, depois o verde, depois o azul
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    pub size: u32,
+    // Intervalo da cor de entrada que a LUT cobre (DOMAIN_MIN/DOMAIN_MAX do .cube)
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    pub texels: Vec<[f32; 3]>,
+}
+
+impl Default for Lut {
+    fn default() -> Self {
+        Self::identity(IDENTITY_SIZE)
+    }
+}
+
+impl Lut {
+    // Devolve a mesma cor que entra
+    pub fn identity(size: u32) -> Self {
+        let max = (size - 1) as f32;
+        let texels = (0..size * size * size)
+            .map(|i| {
+                [
+                    (i % size) as f32 / max,
+                    (i / size % size) as f32 / max,
+                    (i / (size * size)) as f32 / max,
+                ]
+            })
+            .collect();
+
+        Self {
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            texels,
+        }
+    }
+
+    // .cube (Resolve, Adobe) ou PNG em faixa, pela extensão
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase());
+
+        let lut = match extension.as_deref() {
+            Some("cube") => {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read LUT from {}", path.display()))?;
+                Self::parse_cube(&text)
+            }
+            Some("png") => {
+                let (width, height, pixels) = texture::load_png(path)?;
+                Self::from_strip(width, height, &pixels)
+            }
+            _ => Err(anyhow!("Expected a .cube or .png file.")),
+        };
+
+        lut.with_context(|| format!("Failed to load LUT from {}", path.display()))
+    }
+
+    pub fn parse_cube(text: &str) -> Result<Self> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut texels = vec![];
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let result = match keyword {
+                "LUT_3D_SIZE" => match words.next().map(str::parse::<u32>) {
+                    Some(Ok(s)) => {
+                        size = Some(s);
+                        Ok(())
+                    }
+                    _ => Err(anyhow!("Expected an integer size.")),
+                },
+                "LUT_1D_SIZE" => Err(anyhow!("1D LUTs are not supported.")),
+                "DOMAIN_MIN" => parse_triplet(words).map(|v| domain_min = v),
+                "DOMAIN_MAX" => parse_triplet(words).map(|v| domain_max = v),
+                // Da especificação antiga da Adobe: o mesmo intervalo nos três canais
+                "LUT_3D_INPUT_RANGE" => parse_pair(words).map(|[min, max]| {
+                    domain_min = [min; 3];
+                    domain_max = [max; 3];
+                }),
+                // TITLE e o que mais aparecer antes dos dados
+                k if k.starts_with(|c: char| c.is_ascii_alphabetic()) => Ok(()),
+                _ => parse_triplet(line.split_whitespace()).map(|v| texels.push(v)),
+            };
+
+            result.with_context(|| format!("Line {}", number + 1))?;
+        }
+
+        let size = size.ok_or_else(|| anyhow!("Missing LUT_3D_SIZE."))?;
+        if !(2..=MAX_LUT_SIZE).contains(&size) {
+            return Err(anyhow!(
+                "LUT size {} is outside 2..={}.",
+                size,
+                MAX_LUT_SIZE
+            ));
+        }
+        if texels.len() != (size * size * size) as usize {
+            return Err(anyhow!(
+                "Expected {} entries for a LUT of size {}, found {}.",
+                size * size * size,
+                size,
+                texels.len()
+            ));
+        }
+        if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            return Err(anyhow!("DOMAIN_MAX must be greater than DOMAIN_MIN."));
+        }
+
+        Ok(Self {
+            size,
+            domain_min,
+            domain_max,
+            texels,
+        })
+    }
+
+    // Faixa de `size` quadrados lado a lado (size² x size): o vermelho cresce pra direita
+    // dentro de cada quadrado, o verde pra baixo e o azul de um quadrado pro outro
+    pub fn from_strip(width: u32, height: u32, rgba: &[u8]) -> Result<Self> {
+        let size = height;
+        if width != size * size || !(2..=MAX_LUT_SIZE).contains(&size) {
+            return Err(anyhow!(
+                "Expected a strip of N² x N pixels, got {}x{}.",
+                width,
+                height
+            ));
+        }
+
+        let mut texels = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let pixel = ((g * width + b * size + r) * 4) as usize;
+                    texels.push([
+                        rgba[pixel] as f32 / 255.0,
+                        rgba[pixel + 1] as f32 / 255.0,
+                        rgba[pixel + 2] as f32 / 255.0,
+                    ]);
+                }
+            }
+        }
+
+        Ok(Self {
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            texels,
+        })
+    }
+
+    // Pixels no LUT_FORMAT
+    fn packed(&self) -> Vec<u8> {
+        let channel = |v: f32| (v.clamp(0.0, 1.0) * 1023.0).round() as u32;
+
+        self.texels
+            .iter()
+            .flat_map(|[r, g, b]| {
+                (3 << 30 | channel(*b) << 20 | channel(*g) << 10 | channel(*r)).to_ne_bytes()
+            })
+            .collect()
+    }
+}
+
+fn parse_triplet<'a>(words: impl Iterator<Item = &'a str>) -> Result<[f32; 3]> {
+    let values = words
+        .map(str::parse::<f32>)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match values[..] {
+        [x, y, z] => Ok([x, y, z]),
+        _ => Err(anyhow!("Expected three values, got {}.", values.len())),
+    }
+}
+
+fn parse_pair<'a>(words: impl Iterator<Item = &'a str>) -> Result<[f32; 2]> {
+    let values = words
+        .map(str::parse::<f32>)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match values[..] {
+        [x, y] => Ok([x, y]),
+        _ => Err(anyhow!("Expected two values, got {}.", values.len())),
+    }
+}
+
+// Estágio de color grading, logo depois do tone mapping: passa a cor por uma LUT 3D. A
+// LUT vive com o dispositivo e pode ser trocada a qualquer hora; o resto vai com a
+// swapchain e só existe com o estágio na cadeia
+#[derive(Clone, Debug, Default)]
+pub struct ColorGrading {
+    // Cópia na CPU, pra recriar depois de perder o dispositivo
+    lut: Lut,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    lut_sampler: vk::Sampler,
+    upload: Option<UploadId>,
+    sampler: vk::Sampler,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: Pipeline,
+}
+
+impl ColorGrading {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        lut: Lut,
+    ) -> Result<Self> {
+        let mut grading = Self {
+            lut,
+            ..Default::default()
+        };
+
+        grading.create_device_objects(instance, device, data, uploads)?;
+        grading.create_pipeline(device, data)?;
+        Ok(grading)
+    }
+
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+    ) -> Result<()> {
+        let size = self.lut.size;
+        let (image, memory) = image::create_image_3d(
+            instance,
+            device,
+            data,
+            vk::Extent3D {
+                width: size,
+                height: size,
+                depth: size,
+            },
+            LUT_FORMAT,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.image = image;
+        self.memory = memory;
+        self.view = image::create_image_view_layers(
+            device,
+            image,
+            LUT_FORMAT,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageViewType::_3D,
+            0,
+            1,
+        )?;
+
+        // Trilinear entre as entradas da LUT
+        self.lut_sampler = sampler::get(device, &SamplerDesc::default())?;
+
+        let target = UploadTarget::Image {
+            image,
+            width: size,
+            height: size,
+            depth: size,
+            mip_level: 0,
+            block_size: 1,
+            bytes_per_block: 4,
+            final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        self.upload = Some(uploads.enqueue(target, self.lut.packed()));
+
+        Ok(())
+    }
+
+    // A troca espera a GPU, já que a LUT antiga pode estar em uso. Até a nova terminar de
+    // subir o estágio só repassa a cor
+    pub unsafe fn set_lut(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        lut: Lut,
+    ) -> Result<()> {
+        device.device_wait_idle()?;
+        self.destroy(device);
+
+        self.lut = lut;
+        self.create_device_objects(instance, device, data, uploads)?;
+        if !self.set.is_null() {
+            self.write_lut(device);
+        }
+
+        Ok(())
+    }
+
+    pub fn lut(&self) -> &Lut {
+        &self.lut
+    }
+
+    pub unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        if !data.post_pass.has_stage(PostStage::ColorGrading) {
+            return Ok(());
+        }
+
+        // A entrada é lida texel a texel
+        self.sampler = sampler::get(
+            device,
+            &SamplerDesc {
+                filter: vk::Filter::NEAREST,
+                ..Default::default()
+            },
+        )?;
+
+        // Entrada no binding 0, LUT no 1
+        let bindings = (0..2)
+            .map(|i| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(i)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        self.layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(2)
+            .build()];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[self.layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(set_layouts);
+        self.set = device.allocate_descriptor_sets(&info)?[0];
+
+        let image_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(data.post_pass.input(PostStage::ColorGrading))
+            .sampler(self.sampler)
+            .build()];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(image_info);
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+        self.write_lut(device);
+
+        let vertex_shader = spirv!("fullscreen.vert");
+        let fragment_shader = spirv!("grading.frag");
+
+        // Transferências de entrada e saída, se a LUT já está na GPU, o tamanho dela e o
+        // domínio (vec4 por causa do alinhamento)
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(48)
+            .build()];
+        let set_layouts = &[self.layout];
+
+        let mut desc = PipelineDesc::new(
+            &vertex_shader[..],
+            &fragment_shader[..],
+            data.post_pass.stage_pass(PostStage::ColorGrading),
+        );
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        desc.depth_test = false;
+        desc.depth_write = false;
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    unsafe fn write_lut(&self, device: &Device) {
+        let image_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(self.view)
+            .sampler(self.lut_sampler)
+            .build()];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(image_info);
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    // Grava o estágio, já dentro do pass dele. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        uploads: &UploadQueue,
+    ) -> u32 {
+        if !data.post_pass.has_stage(PostStage::ColorGrading) {
+            return 0;
+        }
+
+        let ready = self.upload.is_some_and(|id| !uploads.is_pending(id));
+        let lut = &self.lut;
+        let domain_scale = |c: usize| 1.0 / (lut.domain_max[c] - lut.domain_min[c]);
+
+        let constants = [
+            (data.post_pass.input_transfer(PostStage::ColorGrading) as u32).to_ne_bytes(),
+            (data.post_pass.transfer(PostStage::ColorGrading) as u32).to_ne_bytes(),
+            (ready as u32).to_ne_bytes(),
+            (lut.size as f32).to_ne_bytes(),
+            lut.domain_min[0].to_ne_bytes(),
+            lut.domain_min[1].to_ne_bytes(),
+            lut.domain_min[2].to_ne_bytes(),
+            0.0f32.to_ne_bytes(),
+            domain_scale(0).to_ne_bytes(),
+            domain_scale(1).to_ne_bytes(),
+            domain_scale(2).to_ne_bytes(),
+            0.0f32.to_ne_bytes(),
+        ]
+        .concat();
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            0,
+            &[self.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &constants,
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+        1
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+        self.pipeline = Pipeline::default();
+        self.pool = vk::DescriptorPool::null();
+        self.layout = vk::DescriptorSetLayout::null();
+        self.sampler = vk::Sampler::null();
+        self.set = vk::DescriptorSet::null();
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        leaks::destroy_image_view(device, self.view);
+        leaks::destroy_image(device, self.image);
+        allocator::free(device, self.memory);
+        self.upload = None;
+    }
+}
@@ -0,0 +1,1200 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator,
+    app::AppData,
+    image::{self, create_image_view, write_access},
+    info::get_memory_type_index,
+    leaks,
+    multiview::MultiviewRenderer,
+    occlusion::OcclusionCulling,
+    picking::Picking,
+    shadow::ShadowMap,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResourceId(usize);
+
+// Como um pass usa uma imagem: o layout em que ela tem que estar quando ele começa
+// (UNDEFINED: o conteúdo é descartado, igual ao initialLayout de um render pass) e o
+// layout em que ele deixa ela. Passes que mudam o layout por dentro e voltam declaram
+// o mesmo nos dois
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImageAccess {
+    pub layout: vk::ImageLayout,
+    pub final_layout: vk::ImageLayout,
+    pub stages: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+impl ImageAccess {
+    pub fn sampled(stages: vk::PipelineStageFlags) -> Self {
+        Self::read(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, stages)
+    }
+
+    // Leitura numa shader, num layout qualquer (depth de leitura, GENERAL...)
+    pub fn read(layout: vk::ImageLayout, stages: vk::PipelineStageFlags) -> Self {
+        Self {
+            layout,
+            final_layout: layout,
+            stages,
+            access: vk::AccessFlags::SHADER_READ,
+        }
+    }
+
+    pub fn storage(stages: vk::PipelineStageFlags, access: vk::AccessFlags) -> Self {
+        Self {
+            layout: vk::ImageLayout::GENERAL,
+            final_layout: vk::ImageLayout::GENERAL,
+            stages,
+            access,
+        }
+    }
+
+    pub fn color_attachment(layout: vk::ImageLayout, final_layout: vk::ImageLayout) -> Self {
+        Self {
+            layout,
+            final_layout,
+            stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            access: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        }
+    }
+
+    pub fn depth_attachment(layout: vk::ImageLayout, final_layout: vk::ImageLayout) -> Self {
+        Self {
+            layout,
+            final_layout,
+            stages: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        }
+    }
+
+    pub fn transfer_read(layout: vk::ImageLayout) -> Self {
+        Self {
+            layout,
+            final_layout: layout,
+            stages: vk::PipelineStageFlags::TRANSFER,
+            access: vk::AccessFlags::TRANSFER_READ,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BufferAccess {
+    pub stages: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+impl BufferAccess {
+    pub fn new(stages: vk::PipelineStageFlags, access: vk::AccessFlags) -> Self {
+        Self { stages, access }
+    }
+
+    pub fn indirect() -> Self {
+        Self::new(
+            vk::PipelineStageFlags::DRAW_INDIRECT,
+            vk::AccessFlags::INDIRECT_COMMAND_READ,
+        )
+    }
+}
+
+// Imagem que o grafo cria e que só existe entre o primeiro e o último pass que usa ela.
+// Duas que nunca estão vivas ao mesmo tempo podem dividir a mesma memória
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TransientImage {
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+    pub aspects: vk::ImageAspectFlags,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum ResourceKind {
+    // Vem de fora, com o handle trocado a cada frame (`bind_image`). Fica em `layout`
+    // entre um frame e outro
+    Image {
+        aspects: vk::ImageAspectFlags,
+        layout: vk::ImageLayout,
+    },
+    // Só entra nas dependências: as barreiras de buffer são globais
+    Buffer,
+    Transient(TransientImage),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Resource {
+    name: &'static str,
+    kind: ResourceKind,
+    image: vk::Image,
+    view: vk::ImageView,
+    // Transiente que usava a mesma memória antes dessa
+    aliases: Option<ResourceId>,
+}
+
+impl Resource {
+    fn aspects(&self) -> vk::ImageAspectFlags {
+        match self.kind {
+            ResourceKind::Image { aspects, .. } => aspects,
+            ResourceKind::Transient(desc) => desc.aspects,
+            ResourceKind::Buffer => vk::ImageAspectFlags::empty(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Usage {
+    Image(ImageAccess),
+    Buffer(BufferAccess),
+}
+
+impl Usage {
+    fn stages(&self) -> vk::PipelineStageFlags {
+        match self {
+            Usage::Image(a) => a.stages,
+            Usage::Buffer(a) => a.stages,
+        }
+    }
+
+    fn access(&self) -> vk::AccessFlags {
+        match self {
+            Usage::Image(a) => a.access,
+            Usage::Buffer(a) => a.access,
+        }
+    }
+
+    fn writes(&self) -> bool {
+        self.access().intersects(write_access())
+    }
+
+    // Depende do conteúdo anterior. Imagens em UNDEFINED descartam ele
+    fn reads(&self) -> bool {
+        let reads = !(self.access() - write_access()).is_empty();
+        match self {
+            Usage::Image(a) => reads && a.layout != vk::ImageLayout::UNDEFINED,
+            Usage::Buffer(_) => reads,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PassNode<P> {
+    pass: P,
+    name: &'static str,
+    color: [f32; 4],
+    uses: Vec<(ResourceId, Usage)>,
+}
+
+// Declara o que um pass lê e escreve. A ordem das declarações entre passes é a ordem em
+// que as leituras enxergam as escritas
+pub struct PassBuilder<'a, P> {
+    graph: &'a mut RenderGraph<P>,
+    index: usize,
+}
+
+impl<'a, P> PassBuilder<'a, P> {
+    pub fn image(self, id: ResourceId, access: ImageAccess) -> Self {
+        self.graph.passes[self.index]
+            .uses
+            .push((id, Usage::Image(access)));
+        self
+    }
+
+    pub fn buffer(self, id: ResourceId, access: BufferAccess) -> Self {
+        self.graph.passes[self.index]
+            .uses
+            .push((id, Usage::Buffer(access)));
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ImageBarrier {
+    resource: ResourceId,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+}
+
+// Uma chamada de vkCmdPipelineBarrier: transições de layout por imagem, e o resto numa
+// barreira de memória global
+#[derive(Clone, Debug, Default)]
+struct Barrier {
+    src_stages: vk::PipelineStageFlags,
+    dst_stages: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+    images: Vec<ImageBarrier>,
+}
+
+impl Barrier {
+    fn is_empty(&self) -> bool {
+        self.src_stages.is_empty() && self.images.is_empty()
+    }
+}
+
+// O que aconteceu com um recurso até o ponto atual da simulação
+#[derive(Copy, Clone, Debug)]
+struct State {
+    layout: vk::ImageLayout,
+    // Última escrita
+    write_stages: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    // Leituras depois dela, e o que elas já enxergam
+    read_stages: vk::PipelineStageFlags,
+    visible_stages: vk::PipelineStageFlags,
+    visible_access: vk::AccessFlags,
+}
+
+impl State {
+    fn new(layout: vk::ImageLayout) -> Self {
+        Self {
+            layout,
+            write_stages: vk::PipelineStageFlags::empty(),
+            write_access: vk::AccessFlags::empty(),
+            read_stages: vk::PipelineStageFlags::empty(),
+            visible_stages: vk::PipelineStageFlags::empty(),
+            visible_access: vk::AccessFlags::empty(),
+        }
+    }
+
+    // Estágios que ainda podem estar mexendo no recurso
+    fn last_stages(&self) -> vk::PipelineStageFlags {
+        self.write_stages | self.read_stages
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CompiledPass {
+    pass: usize,
+    barrier: Barrier,
+}
+
+// Grafo dos passes de um frame. Cada pass declara os recursos que usa e como, e o
+// `compile` tira os passes cujo resultado ninguém lê, acha uma ordem que respeita as
+// dependências, cria (e sobrepõe na memória) as imagens transientes e calcula as
+// barreiras e transições de layout antes de cada pass. Quem grava é o dono do grafo:
+// pra cada pass de `len`, `record_barrier` e depois os comandos do pass
+#[derive(Clone, Debug)]
+pub struct RenderGraph<P> {
+    resources: Vec<Resource>,
+    passes: Vec<PassNode<P>>,
+    order: Vec<CompiledPass>,
+    // Devolve as imagens importadas pro layout de entre frames
+    end: Barrier,
+    memory: Vec<vk::DeviceMemory>,
+}
+
+impl<P: Copy> RenderGraph<P> {
+    pub fn new() -> Self {
+        Self {
+            resources: vec![],
+            passes: vec![],
+            order: vec![],
+            end: Barrier::default(),
+            memory: vec![],
+        }
+    }
+
+    pub fn import_image(
+        &mut self,
+        name: &'static str,
+        aspects: vk::ImageAspectFlags,
+        layout: vk::ImageLayout,
+    ) -> ResourceId {
+        self.add_resource(name, ResourceKind::Image { aspects, layout })
+    }
+
+    pub fn import_buffer(&mut self, name: &'static str) -> ResourceId {
+        self.add_resource(name, ResourceKind::Buffer)
+    }
+
+    pub fn transient_image(&mut self, name: &'static str, desc: TransientImage) -> ResourceId {
+        self.add_resource(name, ResourceKind::Transient(desc))
+    }
+
+    fn add_resource(&mut self, name: &'static str, kind: ResourceKind) -> ResourceId {
+        self.resources.push(Resource {
+            name,
+            kind,
+            image: vk::Image::null(),
+            view: vk::ImageView::null(),
+            aliases: None,
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    // `name` e `color` vão pros marcadores de debug
+    pub fn add_pass(&mut self, pass: P, name: &'static str, color: [f32; 4]) -> PassBuilder<P> {
+        self.passes.push(PassNode {
+            pass,
+            name,
+            color,
+            uses: vec![],
+        });
+        let index = self.passes.len() - 1;
+        PassBuilder { graph: self, index }
+    }
+
+    // Imagem importada desse frame. Nula: o recurso não existe agora, e as barreiras dele
+    // são puladas
+    pub fn bind_image(&mut self, id: ResourceId, image: vk::Image) {
+        self.resources[id.0].image = image;
+    }
+
+    pub fn image(&self, id: ResourceId) -> vk::Image {
+        self.resources[id.0].image
+    }
+
+    // Só das transientes
+    pub fn view(&self, id: ResourceId) -> vk::ImageView {
+        self.resources[id.0].view
+    }
+
+    pub unsafe fn compile(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        let kept = self.cull();
+        let order = self.sort(&kept)?;
+        self.allocate(instance, device, data, &order)?;
+
+        // Uma volta pra saber como os recursos terminam o frame, que é como eles começam
+        // o próximo; a segunda é a que vale
+        let initial = self
+            .resources
+            .iter()
+            .map(|r| match r.kind {
+                ResourceKind::Image { layout, .. } => State::new(layout),
+                _ => State::new(vk::ImageLayout::UNDEFINED),
+            })
+            .collect::<Vec<_>>();
+        let (_, _, last) = self.simulate(&order, initial.clone());
+        let initial = initial
+            .iter()
+            .zip(&last)
+            .map(|(start, end)| State {
+                layout: start.layout,
+                ..*end
+            })
+            .collect();
+        let (barriers, end, _) = self.simulate(&order, initial);
+
+        self.order = order
+            .into_iter()
+            .zip(barriers)
+            .map(|(pass, barrier)| CompiledPass { pass, barrier })
+            .collect();
+        self.end = end;
+
+        Ok(())
+    }
+
+    // Um pass fica se não escreve nada declarado (o efeito dele está em outro lugar), se
+    // escreve algo importado ou se escreve uma transiente que um pass que fica lê depois
+    fn cull(&self) -> Vec<bool> {
+        let mut kept = vec![false; self.passes.len()];
+        let mut read = vec![false; self.resources.len()];
+        for (index, pass) in self.passes.iter().enumerate().rev() {
+            let writes = pass
+                .uses
+                .iter()
+                .filter(|(_, u)| u.writes())
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            let needed = writes.is_empty()
+                || writes.iter().any(|id| {
+                    !matches!(self.resources[id.0].kind, ResourceKind::Transient(_)) || read[id.0]
+                });
+            if !needed {
+                continue;
+            }
+
+            // Indo de trás pra frente: a escrita desse pass esconde as anteriores, a não
+            // ser que ele mesmo leia o que estava lá
+            kept[index] = true;
+            for id in writes {
+                read[id.0] = false;
+            }
+            for (id, usage) in &pass.uses {
+                if usage.reads() {
+                    read[id.0] = true;
+                }
+            }
+        }
+
+        kept
+    }
+
+    // Ordem topológica pelas dependências de leitura e escrita, e no empate a ordem em
+    // que os passes foram declarados
+    fn sort(&self, kept: &[bool]) -> Result<Vec<usize>> {
+        let mut dependencies = vec![vec![]; self.passes.len()];
+        let mut last_writer = vec![None; self.resources.len()];
+        let mut readers = vec![vec![]; self.resources.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            if !kept[index] {
+                continue;
+            }
+
+            for (id, usage) in &pass.uses {
+                let deps = &mut dependencies[index];
+                deps.extend(last_writer[id.0]);
+                if usage.writes() {
+                    deps.append(&mut readers[id.0]);
+                    last_writer[id.0] = Some(index);
+                } else {
+                    readers[id.0].push(index);
+                }
+                deps.retain(|d| *d != index);
+            }
+        }
+
+        let mut remaining = dependencies
+            .iter()
+            .map(|d| {
+                let mut d = d.clone();
+                d.sort_unstable();
+                d.dedup();
+                d.len()
+            })
+            .collect::<Vec<_>>();
+        let mut ready = (0..self.passes.len())
+            .filter(|i| kept[*i] && remaining[*i] == 0)
+            .map(Reverse)
+            .collect::<BinaryHeap<_>>();
+
+        let mut order = vec![];
+        while let Some(Reverse(index)) = ready.pop() {
+            order.push(index);
+            for (other, deps) in dependencies.iter().enumerate() {
+                if kept[other] && remaining[other] > 0 && deps.contains(&index) {
+                    remaining[other] -= 1;
+                    if remaining[other] == 0 {
+                        ready.push(Reverse(other));
+                    }
+                }
+            }
+        }
+
+        if order.len() != kept.iter().filter(|k| **k).count() {
+            return Err(anyhow!("Render graph has a dependency cycle."));
+        }
+
+        Ok(order)
+    }
+
+    // Cria as transientes e distribui a memória: uma imagem reaproveita o bloco de outra
+    // que já foi usada pela última vez antes dela começar
+    unsafe fn allocate(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        order: &[usize],
+    ) -> Result<()> {
+        // (primeiro, último) uso de cada recurso, em posições de `order`
+        let mut lifetimes = vec![None; self.resources.len()];
+        for (position, pass) in order.iter().enumerate() {
+            for (id, _) in &self.passes[*pass].uses {
+                let lifetime: &mut Option<(usize, usize)> = &mut lifetimes[id.0];
+                *lifetime =
+                    Some(lifetime.map_or((position, position), |(first, _)| (first, position)));
+            }
+        }
+
+        let mut transients = self
+            .resources
+            .iter()
+            .enumerate()
+            .filter_map(|(index, r)| match r.kind {
+                ResourceKind::Transient(desc) => lifetimes[index].map(|l| (index, desc, l)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        transients.sort_by_key(|(_, _, (first, _))| *first);
+
+        // (memória, tamanho, tipo, último uso, dono atual)
+        let mut blocks: Vec<(vk::DeviceMemory, vk::DeviceSize, u32, usize, usize)> = vec![];
+        for (index, desc, (first, last)) in transients {
+            let info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::_2D)
+                .extent(vk::Extent3D {
+                    width: desc.extent.width,
+                    height: desc.extent.height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .format(desc.format)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .usage(desc.usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .samples(vk::SampleCountFlags::_1);
+            let image = device.create_image(&info, None)?;
+            leaks::created(image);
+            let requirements = device.get_image_memory_requirements(image);
+
+            let free = blocks.iter_mut().find(|(_, size, type_index, end, _)| {
+                *end < first
+                    && *size >= requirements.size
+                    && requirements.memory_type_bits & (1 << *type_index) != 0
+            });
+            let memory = match free {
+                Some(block) => {
+                    self.resources[index].aliases = Some(ResourceId(block.4));
+                    block.3 = last;
+                    block.4 = index;
+                    block.0
+                }
+                None => {
+                    let type_index = get_memory_type_index(
+                        instance,
+                        data,
+                        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                        requirements,
+                    )?;
+                    let memory = allocator::allocate(device, requirements.size, type_index)?;
+                    blocks.push((memory, requirements.size, type_index, last, index));
+                    self.memory.push(memory);
+                    memory
+                }
+            };
+            device.bind_image_memory(image, memory, 0)?;
+
+            let resource = &mut self.resources[index];
+            resource.image = image;
+            resource.view = create_image_view(device, image, desc.format, desc.aspects)?;
+        }
+
+        Ok(())
+    }
+
+    // Anda pelos passes na ordem, decidindo a barreira antes de cada um. Retorna as
+    // barreiras, a do fim do frame e o estado final de cada recurso
+    fn simulate(
+        &self,
+        order: &[usize],
+        mut states: Vec<State>,
+    ) -> (Vec<Barrier>, Barrier, Vec<State>) {
+        let mut barriers = vec![];
+        for pass in order {
+            let mut barrier = Barrier::default();
+            for (id, usage) in &self.passes[*pass].uses {
+                let resource = &self.resources[id.0];
+                // A primeira vez de uma transiente espera quem usava a memória antes
+                if let Some(previous) = resource.aliases {
+                    let state = states[previous.0];
+                    if states[id.0].last_stages().is_empty() && !state.last_stages().is_empty() {
+                        states[id.0].write_stages = state.last_stages();
+                        states[id.0].write_access = state.write_access;
+                    }
+                }
+
+                let state = &mut states[id.0];
+                let (stages, access) = (usage.stages(), usage.access());
+                let (layout, final_layout) = match usage {
+                    Usage::Image(a) => (a.layout, a.final_layout),
+                    Usage::Buffer(_) => (state.layout, state.layout),
+                };
+                let transition = layout != vk::ImageLayout::UNDEFINED && layout != state.layout;
+
+                // Escrita antes (leitura ou escrita depois) que esse uso ainda não enxerga,
+                // ou leitura antes de uma escrita
+                let unseen = !state.write_stages.is_empty()
+                    && (usage.writes()
+                        || !state.visible_stages.contains(stages)
+                        || !state.visible_access.contains(access));
+                let war = usage.writes() && !state.read_stages.is_empty();
+
+                if transition || unseen || war {
+                    let src_stages = state.last_stages();
+                    barrier.src_stages |= if src_stages.is_empty() {
+                        vk::PipelineStageFlags::TOP_OF_PIPE
+                    } else {
+                        src_stages
+                    };
+                    barrier.dst_stages |= stages;
+
+                    if transition {
+                        barrier.images.push(ImageBarrier {
+                            resource: *id,
+                            old_layout: state.layout,
+                            new_layout: layout,
+                            src_access: state.write_access,
+                            dst_access: access,
+                        });
+                    } else {
+                        barrier.src_access |= state.write_access;
+                        barrier.dst_access |= access;
+                    }
+                }
+
+                if usage.writes() {
+                    *state = State {
+                        layout: final_layout,
+                        write_stages: stages,
+                        write_access: access & write_access(),
+                        ..State::new(final_layout)
+                    };
+                } else {
+                    state.layout = final_layout;
+                    state.read_stages |= stages;
+                    state.visible_stages |= stages;
+                    state.visible_access |= access;
+                }
+            }
+
+            barriers.push(barrier);
+        }
+
+        let mut end = Barrier::default();
+        for (index, resource) in self.resources.iter().enumerate() {
+            let state = states[index];
+            if let ResourceKind::Image { layout, .. } = resource.kind {
+                if layout != vk::ImageLayout::UNDEFINED && layout != state.layout {
+                    end.src_stages |= state.last_stages();
+                    end.dst_stages |= vk::PipelineStageFlags::BOTTOM_OF_PIPE;
+                    end.images.push(ImageBarrier {
+                        resource: ResourceId(index),
+                        old_layout: state.layout,
+                        new_layout: layout,
+                        src_access: state.write_access,
+                        dst_access: vk::AccessFlags::empty(),
+                    });
+                }
+            }
+        }
+        if end.src_stages.is_empty() && !end.images.is_empty() {
+            end.src_stages = vk::PipelineStageFlags::TOP_OF_PIPE;
+        }
+
+        (barriers, end, states)
+    }
+
+    // Passes que sobraram, na ordem de execução
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn pass(&self, index: usize) -> (P, &'static str, [f32; 4]) {
+        let node = &self.passes[self.order[index].pass];
+        (node.pass, node.name, node.color)
+    }
+
+    // Antes dos comandos do pass `index`
+    pub unsafe fn record_barrier(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        index: usize,
+    ) {
+        self.record(device, command_buffer, &self.order[index].barrier);
+    }
+
+    // Depois do último pass
+    pub unsafe fn record_end(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        self.record(device, command_buffer, &self.end);
+    }
+
+    unsafe fn record(&self, device: &Device, command_buffer: vk::CommandBuffer, barrier: &Barrier) {
+        if barrier.is_empty() {
+            return;
+        }
+
+        let images = barrier
+            .images
+            .iter()
+            .filter(|b| !self.resources[b.resource.0].image.is_null())
+            .map(|b| {
+                let resource = &self.resources[b.resource.0];
+                let subresource = vk::ImageSubresourceRange::builder()
+                    .aspect_mask(resource.aspects())
+                    .base_mip_level(0)
+                    .level_count(vk::REMAINING_MIP_LEVELS)
+                    .base_array_layer(0)
+                    .layer_count(vk::REMAINING_ARRAY_LAYERS);
+                vk::ImageMemoryBarrier::builder()
+                    .old_layout(b.old_layout)
+                    .new_layout(b.new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(resource.image)
+                    .subresource_range(subresource)
+                    .src_access_mask(b.src_access)
+                    .dst_access_mask(b.dst_access)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let memory = vk::MemoryBarrier::builder()
+            .src_access_mask(barrier.src_access)
+            .dst_access_mask(barrier.dst_access)
+            .build();
+        let memory = if barrier.src_access.is_empty() && barrier.dst_access.is_empty() {
+            vec![]
+        } else {
+            vec![memory]
+        };
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            barrier.src_stages,
+            barrier.dst_stages,
+            vk::DependencyFlags::empty(),
+            &memory,
+            &[] as &[vk::BufferMemoryBarrier],
+            &images,
+        );
+    }
+
+    // As transientes e a memória delas. As declarações continuam, pra compilar de novo
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for resource in &mut self.resources {
+            if let ResourceKind::Transient(_) = resource.kind {
+                leaks::destroy_image_view(device, resource.view);
+                leaks::destroy_image(device, resource.image);
+                resource.view = vk::ImageView::null();
+                resource.image = vk::Image::null();
+                resource.aliases = None;
+            }
+        }
+        for memory in self.memory.drain(..) {
+            allocator::free(device, memory);
+        }
+        self.order.clear();
+    }
+}
+
+// Os passes do frame, na ordem em que são declarados
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FramePass {
+    Uploads,
+    OcclusionCulling,
+    Shadows,
+    ReflectionProbe,
+    IrradianceProbes,
+    DepthPrepass,
+    Scene,
+    Fog,
+    Transparent,
+    GodRays,
+    Picking,
+    Stereo,
+    XrCopy,
+    HiZPyramid,
+    AutoExposure,
+    PostEffects,
+    Post,
+    Ui,
+    FrameCapture,
+}
+
+// O grafo do frame do App, com os recursos que mudam de handle entre um frame e outro
+#[derive(Clone, Debug)]
+pub struct FrameGraph {
+    pub graph: RenderGraph<FramePass>,
+    hdr: ResourceId,
+    depth: ResourceId,
+    shadow_map: ResourceId,
+    pyramid: ResourceId,
+    picking: ResourceId,
+    stereo: ResourceId,
+    xr: ResourceId,
+    swapchain: ResourceId,
+}
+
+impl FrameGraph {
+    // Depende dos render passes e dos alvos, então é refeito junto com a swapchain
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let mut graph = RenderGraph::new();
+
+        let depth_format = data.render_pass.depth.format;
+        let stencil = image::has_stencil_component(depth_format);
+        let depth_aspects = if stencil {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        };
+        let (color_layout, depth_layout) = data.render_pass.scene_ops().initial_layouts(stencil);
+
+        let hdr = graph.import_image(
+            "HDR target",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        let depth = graph.import_image(
+            "Scene depth",
+            depth_aspects,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        );
+        let shadow_map = graph.import_image(
+            "Shadow map",
+            vk::ImageAspectFlags::DEPTH,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        );
+        let pyramid = graph.import_image(
+            "Hi-Z pyramid",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::GENERAL,
+        );
+        let picking = graph.import_image(
+            "Picking IDs",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        let stereo = graph.import_image(
+            "Stereo target",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        // O runtime entrega e recebe as imagens do headset em COLOR_ATTACHMENT_OPTIMAL
+        let xr = graph.import_image(
+            "XR swapchain image",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+        let swapchain = graph.import_image(
+            "Swapchain image",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        );
+        let draws = graph.import_buffer("Indirect draws");
+        let exposure = graph.import_buffer("Exposure");
+        let irradiance = graph.import_buffer("Irradiance probes");
+
+        let compute = vk::PipelineStageFlags::COMPUTE_SHADER;
+        let fragment = vk::PipelineStageFlags::FRAGMENT_SHADER;
+
+        graph.add_pass(FramePass::Uploads, "Uploads", [0.9, 0.6, 0.1, 1.0]);
+        // Lê a pirâmide do frame anterior
+        graph
+            .add_pass(
+                FramePass::OcclusionCulling,
+                "Occlusion culling",
+                [0.3, 0.8, 0.5, 1.0],
+            )
+            .image(
+                pyramid,
+                ImageAccess::read(vk::ImageLayout::GENERAL, compute),
+            )
+            .buffer(
+                draws,
+                BufferAccess::new(
+                    compute | vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            );
+        graph
+            .add_pass(FramePass::Shadows, "Shadows", [0.4, 0.4, 0.4, 1.0])
+            .image(
+                shadow_map,
+                ImageAccess::depth_attachment(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                ),
+            );
+        // Captura e filtra o cubo da sonda quando pedido. O cubo não passa pelo grafo:
+        // a sonda cuida das próprias barreiras, como os cubos de sombra
+        graph.add_pass(
+            FramePass::ReflectionProbe,
+            "Reflection probe",
+            [0.5, 0.8, 0.9, 1.0],
+        );
+        // Uma sonda da grade por frame, com os harmônicos escritos pelo compute
+        graph
+            .add_pass(
+                FramePass::IrradianceProbes,
+                "Irradiance probes",
+                [0.9, 0.7, 0.5, 1.0],
+            )
+            .buffer(
+                irradiance,
+                BufferAccess::new(compute, vk::AccessFlags::SHADER_WRITE),
+            );
+        // Só existe com o pre-pass ligado, senão o pass da cena limpa o depth ele mesmo
+        if data.render_pass.has_prepass() {
+            let (_, prepass_layout) = data.render_pass.ops.initial_layouts(stencil);
+            graph
+                .add_pass(
+                    FramePass::DepthPrepass,
+                    "Depth pre-pass",
+                    [0.3, 0.3, 0.6, 1.0],
+                )
+                .buffer(draws, BufferAccess::indirect())
+                .image(
+                    depth,
+                    ImageAccess::depth_attachment(
+                        prepass_layout,
+                        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    ),
+                );
+        }
+        graph
+            .add_pass(FramePass::Scene, "Scene pass", [0.2, 0.6, 0.9, 1.0])
+            .image(
+                shadow_map,
+                ImageAccess::read(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL, fragment),
+            )
+            .buffer(draws, BufferAccess::indirect())
+            .buffer(
+                irradiance,
+                BufferAccess::new(fragment, vk::AccessFlags::SHADER_READ),
+            )
+            .image(
+                hdr,
+                ImageAccess::color_attachment(
+                    color_layout,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ),
+            )
+            .image(
+                depth,
+                ImageAccess::depth_attachment(
+                    depth_layout,
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ),
+            );
+        // Os computes da névoa leem as luzes do frame, e a composição o depth e o alvo HDR
+        if data.config.fog.enabled {
+            graph
+                .add_pass(FramePass::Fog, "Volumetric fog", [0.7, 0.7, 0.8, 1.0])
+                .image(
+                    shadow_map,
+                    ImageAccess::read(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL, compute),
+                )
+                .buffer(
+                    irradiance,
+                    BufferAccess::new(compute, vk::AccessFlags::SHADER_READ),
+                )
+                .image(
+                    depth,
+                    ImageAccess::read(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL, fragment),
+                )
+                .image(
+                    hdr,
+                    ImageAccess::color_attachment(
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                );
+        }
+        // Por cima da cena, testando o depth dela sem escrever. Sombreadas no forward, com
+        // as mesmas sombras e sondas
+        graph
+            .add_pass(FramePass::Transparent, "Transparent", [0.4, 0.8, 0.9, 1.0])
+            .image(
+                shadow_map,
+                ImageAccess::read(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL, fragment),
+            )
+            .buffer(
+                irradiance,
+                BufferAccess::new(fragment, vk::AccessFlags::SHADER_READ),
+            )
+            .image(
+                hdr,
+                ImageAccess::color_attachment(
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ),
+            )
+            .image(
+                depth,
+                ImageAccess {
+                    layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    stages: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                    access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+                },
+            );
+        // Lê o depth pra achar o céu e soma no alvo HDR, por cima das transparentes
+        if data.config.god_rays.enabled {
+            graph
+                .add_pass(FramePass::GodRays, "God rays", [1.0, 0.9, 0.5, 1.0])
+                .image(
+                    depth,
+                    ImageAccess::read(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL, fragment),
+                )
+                .image(
+                    hdr,
+                    ImageAccess::color_attachment(
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                );
+        }
+        // O render pass deixa os ids em TRANSFER_SRC e a cópia pro host vem logo depois
+        graph
+            .add_pass(FramePass::Picking, "Picking", [0.9, 0.9, 0.3, 1.0])
+            .image(
+                picking,
+                ImageAccess {
+                    layout: vk::ImageLayout::UNDEFINED,
+                    final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::TRANSFER,
+                    access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::TRANSFER_READ,
+                },
+            );
+        // Os dois olhos de uma vez; o render pass deixa o alvo pronto pra leitura
+        graph
+            .add_pass(FramePass::Stereo, "Stereo", [0.6, 0.3, 0.9, 1.0])
+            .image(
+                stereo,
+                ImageAccess {
+                    layout: vk::ImageLayout::UNDEFINED,
+                    final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                },
+            );
+        // Um blit das duas camadas, que também converte do HDR pro formato do headset
+        graph
+            .add_pass(FramePass::XrCopy, "XR copy", [0.6, 0.5, 0.9, 1.0])
+            .image(
+                stereo,
+                ImageAccess::transfer_read(vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+            )
+            .image(
+                xr,
+                ImageAccess {
+                    layout: vk::ImageLayout::UNDEFINED,
+                    final_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    stages: vk::PipelineStageFlags::TRANSFER,
+                    access: vk::AccessFlags::TRANSFER_WRITE,
+                },
+            );
+        // O compute passa o depth pra leitura e devolve
+        graph
+            .add_pass(FramePass::HiZPyramid, "Hi-Z pyramid", [0.3, 0.5, 0.8, 1.0])
+            .image(
+                depth,
+                ImageAccess::read(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL, compute),
+            )
+            .image(
+                pyramid,
+                ImageAccess::storage(compute, vk::AccessFlags::SHADER_WRITE),
+            );
+        graph
+            .add_pass(
+                FramePass::AutoExposure,
+                "Auto exposure",
+                [1.0, 0.4, 0.3, 1.0],
+            )
+            .image(hdr, ImageAccess::sampled(compute))
+            .buffer(
+                exposure,
+                BufferAccess::new(
+                    compute | vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::SHADER_READ
+                        | vk::AccessFlags::SHADER_WRITE
+                        | vk::AccessFlags::TRANSFER_READ
+                        | vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            );
+        // Os efeitos em compute leem o alvo HDR e copiam o resultado de volta nele; as
+        // imagens do meio são só da cadeia. Depois da exposição, que mede só a cena
+        graph
+            .add_pass(FramePass::PostEffects, "Post effects", [0.9, 0.6, 0.2, 1.0])
+            .image(
+                hdr,
+                ImageAccess {
+                    layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    stages: compute | vk::PipelineStageFlags::TRANSFER,
+                    access: vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_WRITE,
+                },
+            );
+        graph
+            .add_pass(FramePass::Post, "Post pass", [0.9, 0.8, 0.2, 1.0])
+            .image(hdr, ImageAccess::sampled(fragment))
+            .buffer(
+                exposure,
+                BufferAccess::new(fragment, vk::AccessFlags::SHADER_READ),
+            )
+            .image(
+                swapchain,
+                ImageAccess::color_attachment(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                ),
+            );
+        graph
+            .add_pass(FramePass::Ui, "UI", [0.8, 0.3, 0.8, 1.0])
+            .image(
+                swapchain,
+                ImageAccess::color_attachment(
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                ),
+            );
+        // O recorder leva a imagem pra TRANSFER_SRC e devolve
+        graph
+            .add_pass(
+                FramePass::FrameCapture,
+                "Frame capture",
+                [0.5, 0.5, 0.5, 1.0],
+            )
+            .image(
+                swapchain,
+                ImageAccess::transfer_read(vk::ImageLayout::PRESENT_SRC_KHR),
+            );
+
+        graph.compile(instance, device, data)?;
+
+        Ok(Self {
+            graph,
+            hdr,
+            depth,
+            shadow_map,
+            pyramid,
+            picking,
+            stereo,
+            xr,
+            swapchain,
+        })
+    }
+
+    // Os handles desse frame, antes de gravar
+    pub fn bind(
+        &mut self,
+        data: &AppData,
+        shadows: &ShadowMap,
+        occlusion: &OcclusionCulling,
+        picking: Option<&Picking>,
+        stereo: Option<&MultiviewRenderer>,
+        xr_image: vk::Image,
+        image_index: usize,
+    ) {
+        let graph = &mut self.graph;
+        graph.bind_image(self.hdr, data.render_pass.color.image);
+        graph.bind_image(self.depth, data.render_pass.depth.image);
+        graph.bind_image(self.shadow_map, shadows.image());
+        graph.bind_image(self.pyramid, occlusion.pyramid());
+        graph.bind_image(
+            self.picking,
+            picking.map_or(vk::Image::null(), Picking::image),
+        );
+        graph.bind_image(
+            self.stereo,
+            stereo.map_or(vk::Image::null(), MultiviewRenderer::image),
+        );
+        graph.bind_image(self.xr, xr_image);
+        graph.bind_image(self.swapchain, data.swapchain.images[image_index]);
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.graph.destroy(device);
+    }
+}
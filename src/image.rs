@@ -0,0 +1,568 @@
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{allocator, app::AppData, info::get_memory_type_index, leaks};
+
+// Uma imagem usada como attachment (profundidade, alvos offscreen...), junto da memória
+// e da view que a acompanham
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AttachmentImage {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+}
+
+impl AttachmentImage {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspects: vk::ImageAspectFlags,
+    ) -> Result<Self> {
+        let (image, memory) = create_image(
+            instance,
+            device,
+            data,
+            extent.width,
+            extent.height,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let view = create_image_view(device, image, format, aspects)?;
+
+        Ok(Self {
+            image,
+            memory,
+            view,
+            format,
+        })
+    }
+
+    // Com várias camadas e uma view _2D_ARRAY de todas, pra render passes com multiview
+    pub unsafe fn create_layers(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        extent: vk::Extent2D,
+        layers: u32,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspects: vk::ImageAspectFlags,
+    ) -> Result<Self> {
+        let (image, memory) = create_image_layers(
+            instance,
+            device,
+            data,
+            extent.width,
+            extent.height,
+            layers,
+            vk::ImageCreateFlags::empty(),
+            format,
+            vk::ImageTiling::OPTIMAL,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let view = create_image_view_layers(
+            device,
+            image,
+            format,
+            aspects,
+            vk::ImageViewType::_2D_ARRAY,
+            0,
+            layers,
+        )?;
+
+        Ok(Self {
+            image,
+            memory,
+            view,
+            format,
+        })
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        leaks::destroy_image_view(device, self.view);
+        leaks::destroy_image(device, self.image);
+        allocator::free(device, self.memory);
+    }
+}
+
+// Acessos que escrevem na memória. Os outros não precisam ser esperados por quem só lê
+pub fn write_access() -> vk::AccessFlags {
+    vk::AccessFlags::SHADER_WRITE
+        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+        | vk::AccessFlags::TRANSFER_WRITE
+        | vk::AccessFlags::HOST_WRITE
+        | vk::AccessFlags::MEMORY_WRITE
+}
+
+// Layout de uma imagem e o último uso dela (estágios e acessos desde a última barreira)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImageState {
+    pub layout: vk::ImageLayout,
+    pub stages: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+impl Default for ImageState {
+    fn default() -> Self {
+        Self {
+            layout: vk::ImageLayout::UNDEFINED,
+            stages: vk::PipelineStageFlags::empty(),
+            access: vk::AccessFlags::empty(),
+        }
+    }
+}
+
+// Imagem que lembra em que layout está e quem mexeu nela por último, pra montar a metade
+// `src` das barreiras sozinha. O estado anda na ordem em que os comandos são gravados, o
+// que vale enquanto os command buffers vão pra mesma fila na mesma ordem. Todos os mips e
+// camadas mudam juntos; barreiras de um mip só continuam sendo feitas à mão
+#[derive(Debug, Default)]
+pub struct TrackedImage {
+    pub image: vk::Image,
+    pub aspects: vk::ImageAspectFlags,
+    state: ImageState,
+}
+
+impl TrackedImage {
+    // Recém-criada, sem layout
+    pub fn new(image: vk::Image, aspects: vk::ImageAspectFlags) -> Self {
+        Self {
+            image,
+            aspects,
+            state: ImageState::default(),
+        }
+    }
+
+    pub fn state(&self) -> ImageState {
+        self.state
+    }
+
+    // Pra mudanças feitas por fora, como o finalLayout de um render pass
+    pub fn assume(&mut self, state: ImageState) {
+        self.state = state;
+    }
+
+    // Deixa a imagem em `layout`, pronta pra ser usada por `stages` com `access`. Leitura
+    // depois de leitura no mesmo layout não precisa de barreira
+    pub unsafe fn transition_to(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::ImageLayout,
+        stages: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+    ) {
+        leaks::check(self.image, "layout transition");
+
+        let state = self.state;
+        let hazard = state.access.intersects(write_access()) || access.intersects(write_access());
+        if layout == state.layout && !hazard {
+            self.state.stages |= stages;
+            self.state.access |= access;
+            return;
+        }
+
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(self.aspects)
+            .base_mip_level(0)
+            .level_count(vk::REMAINING_MIP_LEVELS)
+            .base_array_layer(0)
+            .layer_count(vk::REMAINING_ARRAY_LAYERS);
+
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(state.layout)
+            .new_layout(layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(state.access & write_access())
+            .dst_access_mask(access);
+
+        // Sem uso anterior não tem o que esperar
+        let src_stages = if state.stages.is_empty() {
+            vk::PipelineStageFlags::TOP_OF_PIPE
+        } else {
+            state.stages
+        };
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stages,
+            stages,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+
+        self.state = ImageState {
+            layout,
+            stages,
+            access,
+        };
+    }
+}
+
+pub unsafe fn create_image(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    width: u32,
+    height: u32,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<(vk::Image, vk::DeviceMemory)> {
+    create_image_layers(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        1,
+        vk::ImageCreateFlags::empty(),
+        format,
+        tiling,
+        usage,
+        properties,
+    )
+}
+
+// Mesma coisa, mas com várias camadas (array de texturas 2D, ou cubo com CUBE_COMPATIBLE
+// e 6 camadas)
+pub unsafe fn create_image_layers(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    width: u32,
+    height: u32,
+    layers: u32,
+    flags: vk::ImageCreateFlags,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<(vk::Image, vk::DeviceMemory)> {
+    let info = vk::ImageCreateInfo::builder()
+        .flags(flags)
+        .image_type(vk::ImageType::_2D)
+        .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(layers)
+        .format(format)
+        .tiling(tiling)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(vk::SampleCountFlags::_1);
+
+    let image = device.create_image(&info, None)?;
+    leaks::created(image);
+    let memory = allocate_image_memory(instance, device, data, image, properties)?;
+
+    Ok((image, memory))
+}
+
+// Imagem 3D (volume) com um nível de mip, como a LUT do color grading
+pub unsafe fn create_image_3d(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    extent: vk::Extent3D,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<(vk::Image, vk::DeviceMemory)> {
+    let info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::_3D)
+        .extent(extent)
+        .mip_levels(1)
+        .array_layers(1)
+        .format(format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(vk::SampleCountFlags::_1);
+
+    let image = device.create_image(&info, None)?;
+    leaks::created(image);
+    let memory = allocate_image_memory(instance, device, data, image, properties)?;
+
+    Ok((image, memory))
+}
+
+// Imagem 2D com uma cadeia de mips, do tamanho cheio até 1x1
+pub unsafe fn create_image_mips(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<(vk::Image, vk::DeviceMemory)> {
+    create_image_mips_shared(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        mip_levels,
+        format,
+        usage,
+        properties,
+        &[],
+    )
+}
+
+// Mesma coisa, mas usada por mais de uma família de filas ao mesmo tempo (CONCURRENT),
+// sem transferir a posse entre elas. Com menos de duas famílias fica EXCLUSIVE
+pub unsafe fn create_image_mips_shared(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+    families: &[u32],
+) -> Result<(vk::Image, vk::DeviceMemory)> {
+    let mut info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::_2D)
+        .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .mip_levels(mip_levels)
+        .array_layers(1)
+        .format(format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(vk::SampleCountFlags::_1);
+    if families.len() > 1 {
+        info = info
+            .sharing_mode(vk::SharingMode::CONCURRENT)
+            .queue_family_indices(families);
+    }
+
+    let image = device.create_image(&info, None)?;
+    leaks::created(image);
+    let memory = allocate_image_memory(instance, device, data, image, properties)?;
+
+    Ok((image, memory))
+}
+
+// Cubo (6 camadas, CUBE_COMPATIBLE) com uma cadeia de mips, como o das sondas de reflexo
+pub unsafe fn create_cube_mips(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    size: u32,
+    mip_levels: u32,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<(vk::Image, vk::DeviceMemory)> {
+    let info = vk::ImageCreateInfo::builder()
+        .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+        .image_type(vk::ImageType::_2D)
+        .extent(vk::Extent3D {
+            width: size,
+            height: size,
+            depth: 1,
+        })
+        .mip_levels(mip_levels)
+        .array_layers(6)
+        .format(format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(vk::SampleCountFlags::_1);
+
+    let image = device.create_image(&info, None)?;
+    leaks::created(image);
+    let memory = allocate_image_memory(instance, device, data, image, properties)?;
+
+    Ok((image, memory))
+}
+
+unsafe fn allocate_image_memory(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    image: vk::Image,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<vk::DeviceMemory> {
+    let requirements = device.get_image_memory_requirements(image);
+    let type_index = get_memory_type_index(instance, data, properties, requirements)?;
+    let memory = allocator::allocate(device, requirements.size, type_index)?;
+    device.bind_image_memory(image, memory, 0)?;
+
+    Ok(memory)
+}
+
+pub unsafe fn create_image_view(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspects: vk::ImageAspectFlags,
+) -> Result<vk::ImageView> {
+    create_image_view_layers(device, image, format, aspects, vk::ImageViewType::_2D, 0, 1)
+}
+
+// View de um intervalo de camadas. Uma camada só pode virar _2D (pra usar de attachment);
+// o intervalo todo, _2D_ARRAY (pra ler na shader)
+pub unsafe fn create_image_view_layers(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspects: vk::ImageAspectFlags,
+    view_type: vk::ImageViewType,
+    base_layer: u32,
+    layer_count: u32,
+) -> Result<vk::ImageView> {
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspects)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(base_layer)
+        .layer_count(layer_count);
+
+    let info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(view_type)
+        .format(format)
+        .subresource_range(subresource_range);
+
+    let view = device.create_image_view(&info, None)?;
+    leaks::created(view);
+
+    Ok(view)
+}
+
+// View de um intervalo de mips (um só pra escrever numa storage image, todos pra ler)
+pub unsafe fn create_image_view_mips(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspects: vk::ImageAspectFlags,
+    base_mip: u32,
+    mip_count: u32,
+) -> Result<vk::ImageView> {
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspects)
+        .base_mip_level(base_mip)
+        .level_count(mip_count)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(vk::ImageViewType::_2D)
+        .format(format)
+        .subresource_range(subresource_range);
+
+    let view = device.create_image_view(&info, None)?;
+    leaks::created(view);
+
+    Ok(view)
+}
+
+// View das 6 faces de um intervalo de mips do cubo: CUBE pra amostrar, _2D_ARRAY pra
+// escrever numa storage image
+pub unsafe fn create_cube_view(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+    view_type: vk::ImageViewType,
+    base_mip: u32,
+    mip_count: u32,
+) -> Result<vk::ImageView> {
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(base_mip)
+        .level_count(mip_count)
+        .base_array_layer(0)
+        .layer_count(6);
+
+    let info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(view_type)
+        .format(format)
+        .subresource_range(subresource_range);
+
+    let view = device.create_image_view(&info, None)?;
+    leaks::created(view);
+
+    Ok(view)
+}
+
+// Com stencil só quando alguém usa (o contorno da seleção, ver outline.rs); sem ele o
+// D32 puro ocupa metade (o D32_S8 costuma ter 64 bits por pixel). Sem nenhum formato com
+// stencil, o contorno fica desligado
+pub unsafe fn get_depth_format(instance: &Instance, data: &AppData) -> Result<vk::Format> {
+    let candidates: &[vk::Format] = if data.config.outline {
+        &[
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D24_UNORM_S8_UINT,
+            vk::Format::D32_SFLOAT,
+        ]
+    } else {
+        &[
+            vk::Format::D32_SFLOAT,
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D24_UNORM_S8_UINT,
+        ]
+    };
+
+    candidates
+        .iter()
+        .cloned()
+        .find(|f| {
+            let properties =
+                instance.get_physical_device_format_properties(data.physical_device, *f);
+            properties
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .ok_or_else(|| anyhow!("Failed to find supported depth format."))
+}
+
+// Se o formato, com tiling ótimo, pode ser amostrado numa shader (o depth da cena, pra
+// quem lê ele depois do pass)
+pub unsafe fn supports_sampling(instance: &Instance, data: &AppData, format: vk::Format) -> bool {
+    instance
+        .get_physical_device_format_properties(data.physical_device, format)
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+}
+
+pub fn has_stencil_component(format: vk::Format) -> bool {
+    format == vk::Format::D32_SFLOAT_S8_UINT || format == vk::Format::D24_UNORM_S8_UINT
+}
@@ -1,22 +1,33 @@
 use std::collections::HashSet;
 
 use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
 use vulkanalia::{
     vk::{
-        self, DeviceV1_0, Handle, HasBuilder, Image, InstanceV1_0, KhrSurfaceExtension,
-        KhrSwapchainExtension,
+        self, DeviceV1_0, ExtFullScreenExclusiveExtension, Handle, HasBuilder, Image, InstanceV1_0,
+        KhrSurfaceExtension, KhrSwapchainExtension,
     },
     Device, Instance,
 };
 use winit::window::Window;
 
 use crate::error;
-use crate::{app::AppData, error::SuitabilityError, DEVICE_EXTENSIONS};
+use crate::{
+    app::AppData,
+    display::FullscreenMode,
+    error::{RendererError, SuitabilityError},
+    leaks, DEVICE_EXTENSIONS,
+};
+use log::*;
 
 #[derive(Copy, Clone, Debug)]
 pub struct QueueFamilyIndices {
     pub graphics: u32,
     pub present: u32,
+    // Famílias dedicadas, sem gráficos: compute assíncrono e cópias. Sem elas, o trabalho
+    // vai pra família de gráficos (ver `compute_family` e `transfer_family`)
+    pub compute: Option<u32>,
+    pub transfer: Option<u32>,
 }
 
 impl QueueFamilyIndices {
@@ -27,31 +38,80 @@ impl QueueFamilyIndices {
     ) -> Result<Self> {
         let properties = instance.get_physical_device_queue_family_properties(physical_device);
 
-        let graphics = properties
-            .iter()
-            .position(|p| p.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+        let mut supports_present = vec![];
+        for index in 0..properties.len() {
+            supports_present.push(
+                instance
+                    .get_physical_device_surface_support_khr(
+                        physical_device,
+                        index as u32,
+                        data.surface,
+                    )
+                    .map_err(RendererError::from)?,
+            );
+        }
+
+        // Uma família que faz as duas coisas deixa a swapchain EXCLUSIVE. Só separamos
+        // (e a swapchain fica CONCURRENT) quando nenhuma faz
+        let is_graphics = |i: usize| properties[i].queue_flags.contains(vk::QueueFlags::GRAPHICS);
+        let graphics = (0..properties.len())
+            .find(|i| is_graphics(*i) && supports_present[*i])
+            .or_else(|| (0..properties.len()).find(|i| is_graphics(*i)))
             .map(|i| i as u32);
+        let present = match graphics {
+            Some(graphics) if supports_present[graphics as usize] => Some(graphics),
+            _ => supports_present.iter().position(|s| *s).map(|i| i as u32),
+        };
 
-        let mut present = None;
-        for (index, properties) in properties.iter().enumerate() {
-            if instance.get_physical_device_surface_support_khr(
-                physical_device,
-                index as u32,
-                data.surface,
-            )? {
-                present = Some(index as u32);
-                break;
-            }
-        }
+        // find_queue_family já prefere a mais dedicada: pra transferência, uma família só de
+        // cópia antes de uma de compute
+        let dedicated = |flags| {
+            find_queue_family(&properties, flags).filter(|i| {
+                !properties[*i as usize]
+                    .queue_flags
+                    .contains(vk::QueueFlags::GRAPHICS)
+            })
+        };
+        let compute = dedicated(vk::QueueFlags::COMPUTE);
+        let transfer = dedicated(vk::QueueFlags::TRANSFER);
 
         if let (Some(graphics), Some(present)) = (graphics, present) {
-            Ok(Self { graphics, present })
+            Ok(Self {
+                graphics,
+                present,
+                compute,
+                transfer,
+            })
         } else {
             Err(anyhow!(error::SuitabilityError(
                 "Missing required queue families"
             )))
         }
     }
+
+    pub fn compute_family(&self) -> u32 {
+        self.compute.unwrap_or(self.graphics)
+    }
+
+    pub fn transfer_family(&self) -> u32 {
+        self.transfer.unwrap_or(self.graphics)
+    }
+}
+
+// Acha uma família com as capacidades pedidas, preferindo a mais "dedicada" (a que tem
+// menos capacidades além das pedidas), ex.: uma família só de transferência
+pub fn find_queue_family(
+    properties: &[vk::QueueFamilyProperties],
+    flags: vk::QueueFlags,
+) -> Option<u32> {
+    let relevant = vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+
+    properties
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.queue_count > 0 && p.queue_flags.contains(flags))
+        .min_by_key(|(_, p)| (p.queue_flags & relevant & !flags).bits().count_ones())
+        .map(|(i, _)| i as u32)
 }
 
 #[derive(Clone, Debug)]
@@ -69,15 +129,57 @@ impl SwapchainSupport {
     ) -> Result<Self> {
         Ok(Self {
             capabilities: instance
-                .get_physical_device_surface_capabilities_khr(physical_device, data.surface)?,
+                .get_physical_device_surface_capabilities_khr(physical_device, data.surface)
+                .map_err(RendererError::from)?,
             formats: instance
-                .get_physical_device_surface_formats_khr(physical_device, data.surface)?,
+                .get_physical_device_surface_formats_khr(physical_device, data.surface)
+                .map_err(RendererError::from)?,
             present_modes: instance
-                .get_physical_device_surface_present_modes_khr(physical_device, data.surface)?,
+                .get_physical_device_surface_present_modes_khr(physical_device, data.surface)
+                .map_err(RendererError::from)?,
         })
     }
 }
 
+// Função de transferência aplicada na saída (no fim do fragment shader final)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum OutputTransfer {
+    // Formato _SRGB: o hardware já codifica na escrita
+    #[default]
+    Hardware = 0,
+    // Formato UNORM com colorspace sRGB: codificamos na shader
+    Srgb = 1,
+    // HDR10: BT.2020 + PQ
+    Pq = 2,
+    // Display P3 com a curva do sRGB, num formato UNORM: trocamos as primárias e
+    // codificamos na shader
+    DisplayP3 = 3,
+    // scRGB: primárias do BT.709, linear, em ponto flutuante e sem limite em [0, 1]
+    ExtendedSrgb = 4,
+}
+
+impl OutputTransfer {
+    pub fn for_surface_format(format: vk::SurfaceFormatKHR) -> Self {
+        if format.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT {
+            OutputTransfer::Pq
+        } else if format.color_space == vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT {
+            OutputTransfer::DisplayP3
+        } else if format.color_space == vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT {
+            OutputTransfer::ExtendedSrgb
+        } else if matches!(
+            format.format,
+            vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_SRGB_PACK32
+        ) {
+            OutputTransfer::Hardware
+        } else {
+            OutputTransfer::Srgb
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct SwapchainData {
     pub chain: vk::SwapchainKHR,
@@ -85,6 +187,15 @@ pub struct SwapchainData {
     pub format: vk::Format,
     pub extent: vk::Extent2D,
     pub image_views: Vec<vk::ImageView>,
+    pub present_mode: vk::PresentModeKHR,
+    pub color_space: vk::ColorSpaceKHR,
+    // Como a cor linear da cena deve ser codificada pra essa swapchain
+    pub transfer: OutputTransfer,
+    // Rotação da tela que nós aplicamos (em vez do compositor) antes de apresentar
+    pub transform: vk::SurfaceTransformFlagsKHR,
+    pub usage: vk::ImageUsageFlags,
+    // Se a swapchain está com o display em modo exclusivo
+    pub exclusive: bool,
 }
 
 impl SwapchainData {
@@ -98,19 +209,50 @@ impl SwapchainData {
         let support = SwapchainSupport::get(instance, data, data.physical_device)?;
 
         // Formato da Swapchain: Modo de canal de cores e colorspace
-        let surface_format = Self::get_swapchain_surface_format(&support.formats);
+        let hdr = data.config.hdr && data.swapchain_colorspace;
+        let wide_gamut = data.config.wide_gamut && data.swapchain_colorspace;
+        let surface_format = Self::get_swapchain_surface_format(
+            &support.formats,
+            &data.config.surface_formats,
+            hdr,
+            wide_gamut,
+        );
+        let transfer = OutputTransfer::for_surface_format(surface_format);
         // Present mode: V-buffer, triple buffer...
-        let present_mode = Self::get_swapchain_present_mode(&support.present_modes);
+        let present_mode = Self::get_swapchain_present_mode(
+            &support.present_modes,
+            data.config.present_mode.to_vk(),
+        );
         // Extent: Tamanho da imagem (surface onde vamos desenhar)
         let extent = Self::get_swapchain_extent(window, support.capabilities);
+        // Passando a rotação atual adiante o compositor não precisa girar a imagem com uma
+        // cópia extra; em troca, a rotação é aplicada na projeção (ver pre_rotation())
+        let transform = support.capabilities.current_transform;
 
-        let mut image_count = support.capabilities.min_image_count + 1;
+        let requested = data.config.buffering.image_count(&support.capabilities);
+        let mut image_count = requested.max(support.capabilities.min_image_count);
 
         if support.capabilities.max_image_count != 0
             && image_count > support.capabilities.max_image_count
         {
             image_count = support.capabilities.max_image_count;
         }
+        if image_count != requested {
+            debug!(
+                "Surface doesn't allow {} swapchain images, using {}.",
+                requested, image_count
+            );
+        }
+
+        // Se der, deixamos copiar das imagens (captura de frames, screenshots)
+        let mut usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        if support
+            .capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
 
         let mut queue_family_indices = vec![];
         let image_sharing_mode = if indices.graphics != indices.present {
@@ -122,24 +264,55 @@ impl SwapchainData {
         };
 
         // Um monstro que descreve exatamente como queremos nossa swapchain
-        let info = vk::SwapchainCreateInfoKHR::builder()
+        let mut info = vk::SwapchainCreateInfoKHR::builder()
             .surface(data.surface)
             .min_image_count(image_count)
             .image_format(surface_format.format)
             .image_color_space(surface_format.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(usage)
             .image_sharing_mode(image_sharing_mode)
             .queue_family_indices(&queue_family_indices)
-            .pre_transform(support.capabilities.current_transform)
+            .pre_transform(transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
             .present_mode(present_mode)
             .clipped(true)
             .old_swapchain(vk::SwapchainKHR::null());
 
-        let chain = device.create_swapchain_khr(&info, None)?;
-        let images = device.get_swapchain_images_khr(chain)?;
+        // Em tela cheia exclusiva, nós decidimos quando a swapchain pega o display
+        let exclusive =
+            data.fullscreen == FullscreenMode::Exclusive && data.capabilities.full_screen_exclusive;
+        let mut exclusive_info = vk::SurfaceFullScreenExclusiveInfoEXT::builder()
+            .full_screen_exclusive(vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED);
+        #[cfg(windows)]
+        let mut exclusive_win32_info = vk::SurfaceFullScreenExclusiveWin32InfoEXT::builder()
+            .hmonitor(crate::display::hmonitor(window).unwrap_or(std::ptr::null_mut()));
+
+        if exclusive {
+            info = info.push_next(&mut exclusive_info);
+            #[cfg(windows)]
+            {
+                info = info.push_next(&mut exclusive_win32_info);
+            }
+        }
+
+        let chain = device
+            .create_swapchain_khr(&info, None)
+            .map_err(RendererError::from)?;
+
+        let exclusive = exclusive
+            && match device.acquire_full_screen_exclusive_mode_ext(chain) {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("Failed to acquire exclusive fullscreen: {}", e);
+                    false
+                }
+            };
+
+        let images = device
+            .get_swapchain_images_khr(chain)
+            .map_err(RendererError::from)?;
         let format = surface_format.format;
         let image_views = Self::create_swapchain_image_views(device, &images, &format)?;
 
@@ -149,10 +322,57 @@ impl SwapchainData {
             format,
             images,
             image_views,
+            present_mode,
+            color_space: surface_format.color_space,
+            transfer,
+            transform,
+            usage,
+            exclusive,
         })
     }
 
+    fn is_rotated_sideways(&self) -> bool {
+        self.transform.intersects(
+            vk::SurfaceTransformFlagsKHR::ROTATE_90 | vk::SurfaceTransformFlagsKHR::ROTATE_270,
+        )
+    }
+
+    // Tamanho como o usuário vê a tela (pra aspect ratio e afins). A swapchain em si fica
+    // na orientação nativa do display
+    pub fn logical_extent(&self) -> vk::Extent2D {
+        if self.is_rotated_sideways() {
+            vk::Extent2D {
+                width: self.extent.height,
+                height: self.extent.width,
+            }
+        } else {
+            self.extent
+        }
+    }
+
+    // Matriz que deve ser multiplicada à esquerda da projeção
+    pub fn pre_rotation(&self) -> glm::Mat4 {
+        let degrees: f32 = if self.transform == vk::SurfaceTransformFlagsKHR::ROTATE_90 {
+            90.0
+        } else if self.transform == vk::SurfaceTransformFlagsKHR::ROTATE_180 {
+            180.0
+        } else if self.transform == vk::SurfaceTransformFlagsKHR::ROTATE_270 {
+            270.0
+        } else {
+            0.0
+        };
+
+        glm::rotation(degrees.to_radians(), &glm::vec3(0.0, 0.0, 1.0))
+    }
+
     pub unsafe fn destroy(&mut self, device: &Device) {
+        if self.exclusive {
+            let _ = device.release_full_screen_exclusive_mode_ext(self.chain);
+        }
+
+        self.image_views
+            .iter()
+            .for_each(|v| leaks::destroy_image_view(device, *v));
         device.destroy_swapchain_khr(self.chain, None);
     }
 
@@ -172,7 +392,7 @@ impl SwapchainData {
             .base_mip_level(0)
             .level_count(1)
             .base_array_layer(0)
-            .layer_count(0);
+            .layer_count(1);
 
         let data = images
             .iter()
@@ -185,9 +405,11 @@ impl SwapchainData {
                     .subresource_range(subresource_range)
                     .build();
 
-                device.create_image_view(&info, None)
+                let view = device.create_image_view(&info, None)?;
+                leaks::created(view);
+                Ok(view)
             })
-            .collect::<Result<Vec<_>, _>>()?;
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(data)
     }
@@ -197,7 +419,8 @@ impl SwapchainData {
         physical_device: vk::PhysicalDevice,
     ) -> Result<()> {
         let extensions = instance
-            .enumerate_device_extension_properties(physical_device, None)?
+            .enumerate_device_extension_properties(physical_device, None)
+            .map_err(RendererError::from)?
             .iter()
             .map(|e| e.extension_name)
             .collect::<HashSet<_>>();
@@ -212,35 +435,129 @@ impl SwapchainData {
 
     pub unsafe fn get_swapchain_surface_format(
         formats: &[vk::SurfaceFormatKHR],
+        preferences: &[vk::SurfaceFormatKHR],
+        hdr: bool,
+        wide_gamut: bool,
     ) -> vk::SurfaceFormatKHR {
-        formats
+        // HDR10: 10 bits por canal, primárias do BT.2020 e a curva PQ (ST 2084)
+        let hdr10 = formats.iter().cloned().find(|f| {
+            f.format == vk::Format::A2B10G10R10_UNORM_PACK32
+                && f.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT
+        });
+
+        if let (true, Some(format)) = (hdr, hdr10) {
+            return format;
+        } else if hdr {
+            warn!("HDR10 output requested but not supported by the surface.");
+        }
+
+        // Gamut largo: P3 em 10 bits, scRGB em 16 bits e P3 em 8 bits, nessa ordem. O P3
+        // só em UNORM, a curva é aplicada na shader junto com a troca de primárias
+        let wide = [
+            (
+                vk::Format::A2B10G10R10_UNORM_PACK32,
+                vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT,
+            ),
+            (
+                vk::Format::R16G16B16A16_SFLOAT,
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+            ),
+            (
+                vk::Format::B8G8R8A8_UNORM,
+                vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT,
+            ),
+            (
+                vk::Format::R8G8B8A8_UNORM,
+                vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT,
+            ),
+        ]
+        .iter()
+        .find_map(|(format, color_space)| {
+            formats
+                .iter()
+                .cloned()
+                .find(|f| f.format == *format && f.color_space == *color_space)
+        });
+
+        if let (true, Some(format)) = (wide_gamut, wide) {
+            return format;
+        } else if wide_gamut {
+            warn!("Wide gamut output requested but not supported by the surface.");
+        }
+
+        let srgb = vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_SRGB,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        };
+
+        // Um único UNDEFINED quer dizer que a surface aceita qualquer formato
+        if formats.len() == 1 && formats[0].format == vk::Format::UNDEFINED {
+            return preferences.first().cloned().unwrap_or(srgb);
+        }
+
+        let supported = |p: &vk::SurfaceFormatKHR| {
+            formats
+                .iter()
+                .any(|f| f.format == p.format && f.color_space == p.color_space)
+        };
+        if let Some(format) = preferences.iter().cloned().find(supported) {
+            return format;
+        }
+
+        // Nada da lista: o primeiro sRGB não linear, que qualquer tela mostra certo
+        let fallback = formats
             .iter()
             .cloned()
-            .find(|f| {
-                f.format == vk::Format::B8G8R8A8_SRGB
-                    && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            })
-            .unwrap_or_else(|| formats[0])
+            .find(|f| f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
+            .unwrap_or_else(|| formats[0]);
+        warn!(
+            "None of the preferred surface formats is supported, using {:?} ({:?}).",
+            fallback.format, fallback.color_space
+        );
+        fallback
     }
 
     pub unsafe fn get_swapchain_present_mode(
         present_modes: &[vk::PresentModeKHR],
+        preferred: vk::PresentModeKHR,
     ) -> vk::PresentModeKHR {
+        // FIFO é o único que toda surface tem
         present_modes
             .iter()
             .cloned()
-            .find(|f| *f == vk::PresentModeKHR::MAILBOX)
-            .unwrap_or_else(|| vk::PresentModeKHR::FIFO)
+            .find(|f| *f == preferred)
+            .unwrap_or_else(|| {
+                warn!(
+                    "Present mode {:?} is not supported, falling back to FIFO.",
+                    preferred
+                );
+                vk::PresentModeKHR::FIFO
+            })
     }
 
     pub unsafe fn get_swapchain_extent(
         window: &Window,
         capabilites: vk::SurfaceCapabilitiesKHR,
     ) -> vk::Extent2D {
+        let sideways = capabilites.current_transform.intersects(
+            vk::SurfaceTransformFlagsKHR::ROTATE_90 | vk::SurfaceTransformFlagsKHR::ROTATE_270,
+        );
+
         if capabilites.current_extent.width != u32::MAX {
-            capabilites.current_extent
+            // O Android informa o tamanho já girado; com o pre_transform a swapchain fica na
+            // orientação nativa, então desvira
+            let mut extent = capabilites.current_extent;
+            if cfg!(target_os = "android") && sideways {
+                std::mem::swap(&mut extent.width, &mut extent.height);
+            }
+            extent
         } else {
-            let size = window.inner_size();
+            let mut size = window.inner_size();
+            // O tamanho da janela está na orientação rotacionada, a swapchain não
+            if sideways {
+                std::mem::swap(&mut size.width, &mut size.height);
+            }
+
             let clamp = |min: u32, max: u32, v: u32| min.max(max.min(v));
             vk::Extent2D::builder()
                 .width(clamp(
@@ -257,3 +574,26 @@ impl SwapchainData {
         }
     }
 }
+
+// Procura um tipo de memória do dispositivo que satisfaça tanto os requisitos do recurso
+// quanto as propriedades que pedimos (HOST_VISIBLE, DEVICE_LOCAL...)
+pub unsafe fn get_memory_type_index(
+    instance: &Instance,
+    data: &AppData,
+    properties: vk::MemoryPropertyFlags,
+    requirements: vk::MemoryRequirements,
+) -> Result<u32> {
+    let memory = instance.get_physical_device_memory_properties(data.physical_device);
+
+    (0..memory.memory_type_count)
+        .find(|i| {
+            let suitable = (requirements.memory_type_bits & (1 << i)) != 0;
+            let memory_type = memory.memory_types[*i as usize];
+            suitable && memory_type.property_flags.contains(properties)
+        })
+        .ok_or_else(|| {
+            anyhow!(RendererError::Allocation(
+                "no suitable memory type".to_string()
+            ))
+        })
+}
@@ -0,0 +1,481 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use nalgebra_glm as glm;
+use winit::event::{
+    DeviceEvent, ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+    WindowEvent,
+};
+
+// Abaixo disso o analógico conta como parado (fração do curso)
+pub const DEFAULT_DEAD_ZONE: f32 = 0.15;
+
+// Botões do controle, com nomes de posição (South é o A do Xbox / X do PlayStation)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+// Eixos do controle. Analógicos de -1 a 1 com y pra cima, gatilhos de 0 a 1
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+// Qualquer coisa que pode ser ligada a uma ação
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+impl Button {
+    // Nomes iguais aos das variantes do winit (`W`, `LShift`, `F2`...), mais `Mouse1`..
+    // `Mouse3` pros botões do mouse, `MouseN` pros outros e `Pad*` pros do controle
+    pub fn parse(name: &str) -> Result<Self> {
+        if let Some(pad) = name.strip_prefix("Pad") {
+            return Ok(Button::Gamepad(parse_gamepad_button(pad)?));
+        }
+
+        let button = match name {
+            "Mouse1" | "MouseLeft" => Button::Mouse(MouseButton::Left),
+            "Mouse2" | "MouseRight" => Button::Mouse(MouseButton::Right),
+            "Mouse3" | "MouseMiddle" => Button::Mouse(MouseButton::Middle),
+            _ => match name.strip_prefix("Mouse").map(str::parse::<u16>) {
+                Some(Ok(n)) => Button::Mouse(MouseButton::Other(n)),
+                _ => Button::Key(parse_key(name)?),
+            },
+        };
+
+        Ok(button)
+    }
+}
+
+fn parse_gamepad_button(name: &str) -> Result<GamepadButton> {
+    use GamepadButton::*;
+
+    let button = match name {
+        "South" => South,
+        "East" => East,
+        "North" => North,
+        "West" => West,
+        "LeftBumper" => LeftBumper,
+        "RightBumper" => RightBumper,
+        "LeftTrigger" => LeftTrigger,
+        "RightTrigger" => RightTrigger,
+        "Select" => Select,
+        "Start" => Start,
+        "LeftStick" => LeftStick,
+        "RightStick" => RightStick,
+        "Up" => DPadUp,
+        "Down" => DPadDown,
+        "Left" => DPadLeft,
+        "Right" => DPadRight,
+        _ => return Err(anyhow!("Unknown gamepad button 'Pad{}'.", name)),
+    };
+
+    Ok(button)
+}
+
+fn parse_key(name: &str) -> Result<VirtualKeyCode> {
+    use VirtualKeyCode::*;
+
+    // Letras e números de uma tecla só
+    const LETTERS: [VirtualKeyCode; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    const DIGITS: [VirtualKeyCode; 10] =
+        [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    const FUNCTION: [VirtualKeyCode; 12] = [F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12];
+
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_uppercase() {
+            return Ok(LETTERS[(c as u8 - b'A') as usize]);
+        }
+        if c.is_ascii_digit() {
+            return Ok(DIGITS[(c as u8 - b'0') as usize]);
+        }
+    }
+
+    if let Some(Ok(n @ 1..=12)) = name.strip_prefix('F').map(str::parse::<usize>) {
+        return Ok(FUNCTION[n - 1]);
+    }
+
+    let key = match name {
+        "Escape" => Escape,
+        "Space" => Space,
+        "Return" | "Enter" => Return,
+        "Tab" => Tab,
+        "Back" | "Backspace" => Back,
+        "Delete" => Delete,
+        "Insert" => Insert,
+        "Home" => Home,
+        "End" => End,
+        "PageUp" => PageUp,
+        "PageDown" => PageDown,
+        "Up" => Up,
+        "Down" => Down,
+        "Left" => Left,
+        "Right" => Right,
+        "LShift" => LShift,
+        "RShift" => RShift,
+        "LControl" => LControl,
+        "RControl" => RControl,
+        "LAlt" => LAlt,
+        "RAlt" => RAlt,
+        "Minus" => Minus,
+        "Equals" => Equals,
+        "Comma" => Comma,
+        "Period" => Period,
+        "Grave" => Grave,
+        _ => return Err(anyhow!("Unknown key '{}'.", name)),
+    };
+
+    Ok(key)
+}
+
+// Ações nomeadas ("move_forward") ligadas a um ou mais botões
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    actions: HashMap<String, Vec<Button>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use VirtualKeyCode::*;
+
+        let mut bindings = Self {
+            actions: HashMap::new(),
+        };
+
+        let keys = [
+            ("move_forward", W),
+            ("move_back", S),
+            ("move_left", A),
+            ("move_right", D),
+            ("move_up", Space),
+            ("move_down", LControl),
+            ("boost", LShift),
+        ];
+        for (action, key) in keys {
+            bindings.bind(action, Button::Key(key));
+        }
+
+        bindings.bind("move_up", Button::Gamepad(GamepadButton::RightBumper));
+        bindings.bind("move_down", Button::Gamepad(GamepadButton::LeftBumper));
+        bindings.bind("boost", Button::Gamepad(GamepadButton::LeftStick));
+
+        bindings.bind("orbit_rotate", Button::Mouse(MouseButton::Left));
+        bindings.bind("orbit_pan", Button::Mouse(MouseButton::Middle));
+
+        bindings
+    }
+}
+
+impl Bindings {
+    pub fn bind(&mut self, action: &str, button: Button) {
+        let buttons = self.actions.entry(action.to_string()).or_default();
+        if !buttons.contains(&button) {
+            buttons.push(button);
+        }
+    }
+
+    pub fn buttons(&self, action: &str) -> &[Button] {
+        self.actions.get(action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // Uma ação por linha, `ação = Botão, Botão`. `#` começa um comentário.
+    // Cada ação do texto substitui os botões que ela tinha antes
+    pub fn parse(&mut self, text: &str) -> Result<()> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (action, buttons) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Line {}: expected 'action = buttons'.", number + 1))?;
+
+            let buttons = buttons
+                .split(',')
+                .map(str::trim)
+                .filter(|b| !b.is_empty())
+                .map(Button::parse)
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| format!("Line {}", number + 1))?;
+
+            self.actions.insert(action.trim().to_string(), buttons);
+        }
+
+        Ok(())
+    }
+
+    // Os padrões, com o que o arquivo redefinir por cima
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read bindings from {}", path.display()))?;
+
+        let mut bindings = Self::default();
+        bindings.parse(&text)?;
+        Ok(bindings)
+    }
+}
+
+// Estado do teclado, mouse e controle agregado a partir dos eventos. O "neste frame"
+// vale até o próximo `end_frame`
+#[derive(Clone, Debug)]
+pub struct Input {
+    pub bindings: Bindings,
+    down: HashSet<Button>,
+    pressed: HashSet<Button>,
+    released: HashSet<Button>,
+    // Movimento bruto do mouse (DeviceEvent), que não para na borda da janela
+    mouse_delta: glm::Vec2,
+    // Em "passos" da roda; positivo pra frente
+    wheel: f32,
+    cursor: Option<glm::Vec2>,
+    // Valores crus dos eixos, na ordem de `GamepadAxis`
+    axes: [f32; 6],
+    pub dead_zone: f32,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new(Bindings::default())
+    }
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Self {
+        Self {
+            bindings,
+            down: HashSet::new(),
+            pressed: HashSet::new(),
+            released: HashSet::new(),
+            mouse_delta: glm::vec2(0.0, 0.0),
+            wheel: 0.0,
+            cursor: None,
+            axes: [0.0; 6],
+            dead_zone: DEFAULT_DEAD_ZONE,
+        }
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.set(Button::Key(*key), *state),
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.set(Button::Mouse(*button), *state)
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.wheel += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // Touchpads mandam pixels; ~50 por "passo"
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0,
+                };
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Some(glm::vec2(position.x as f32, position.y as f32));
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            // Sem foco as teclas soltas não chegam, então soltamos todas
+            WindowEvent::Focused(false) => {
+                self.released.extend(self.down.drain());
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.mouse_delta += glm::vec2(delta.0 as f32, delta.1 as f32);
+        }
+    }
+
+    fn set(&mut self, button: Button, state: ElementState) {
+        match state {
+            // Repetição do teclado não conta como outro aperto
+            ElementState::Pressed => {
+                if self.down.insert(button) {
+                    self.pressed.insert(button);
+                }
+            }
+            ElementState::Released => {
+                if self.down.remove(&button) {
+                    self.released.insert(button);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "gamepad")]
+    pub fn set_gamepad_button(&mut self, button: GamepadButton, pressed: bool) {
+        let state = if pressed {
+            ElementState::Pressed
+        } else {
+            ElementState::Released
+        };
+
+        self.set(Button::Gamepad(button), state);
+    }
+
+    #[cfg(feature = "gamepad")]
+    pub fn set_gamepad_axis(&mut self, axis: GamepadAxis, value: f32) {
+        self.axes[axis as usize] = value;
+    }
+
+    // Quando o controle some, nada dele fica preso apertado
+    #[cfg(feature = "gamepad")]
+    pub fn release_gamepad(&mut self) {
+        let buttons: Vec<_> = self
+            .down
+            .iter()
+            .filter(|b| matches!(b, Button::Gamepad(_)))
+            .copied()
+            .collect();
+
+        for button in buttons {
+            self.set(button, ElementState::Released);
+        }
+
+        self.axes = [0.0; 6];
+    }
+
+    // Chamado depois que a lógica do frame leu o estado
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.mouse_delta = glm::vec2(0.0, 0.0);
+        self.wheel = 0.0;
+    }
+
+    // Algo apertado ou um analógico fora da zona morta: a lógica ainda tem o que fazer
+    pub fn is_active(&self) -> bool {
+        !self.down.is_empty()
+            || !self.pressed.is_empty()
+            || !self.released.is_empty()
+            || self.wheel != 0.0
+            || glm::length(&self.left_stick()) > 0.0
+            || glm::length(&self.right_stick()) > 0.0
+            || self.axis(GamepadAxis::LeftTrigger) > 0.0
+            || self.axis(GamepadAxis::RightTrigger) > 0.0
+    }
+
+    pub fn is_down(&self, button: Button) -> bool {
+        self.down.contains(&button)
+    }
+
+    pub fn just_pressed(&self, button: Button) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    pub fn just_released(&self, button: Button) -> bool {
+        self.released.contains(&button)
+    }
+
+    pub fn action_down(&self, action: &str) -> bool {
+        self.bindings
+            .buttons(action)
+            .iter()
+            .any(|b| self.is_down(*b))
+    }
+
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.bindings
+            .buttons(action)
+            .iter()
+            .any(|b| self.just_pressed(*b))
+    }
+
+    pub fn action_released(&self, action: &str) -> bool {
+        self.bindings
+            .buttons(action)
+            .iter()
+            .any(|b| self.just_released(*b))
+    }
+
+    pub fn mouse_delta(&self) -> glm::Vec2 {
+        self.mouse_delta
+    }
+
+    pub fn wheel(&self) -> f32 {
+        self.wheel
+    }
+
+    // Com a zona morta aplicada. Nos analógicos ela é radial, então prefira `left_stick`
+    // e `right_stick` pra ler os dois eixos juntos
+    pub fn axis(&self, axis: GamepadAxis) -> f32 {
+        let value = self.axes[axis as usize];
+        match axis {
+            GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => rescale(value, self.dead_zone),
+            _ => value.signum() * rescale(value.abs(), self.dead_zone),
+        }
+    }
+
+    pub fn left_stick(&self) -> glm::Vec2 {
+        self.stick(GamepadAxis::LeftX, GamepadAxis::LeftY)
+    }
+
+    pub fn right_stick(&self) -> glm::Vec2 {
+        self.stick(GamepadAxis::RightX, GamepadAxis::RightY)
+    }
+
+    fn stick(&self, x: GamepadAxis, y: GamepadAxis) -> glm::Vec2 {
+        let raw = glm::vec2(self.axes[x as usize], self.axes[y as usize]);
+        let length = glm::length(&raw);
+        if length <= self.dead_zone {
+            return glm::vec2(0.0, 0.0);
+        }
+
+        raw / length * rescale(length, self.dead_zone)
+    }
+
+    // Em pixels físicos; None com o cursor fora da janela
+    pub fn cursor_position(&self) -> Option<glm::Vec2> {
+        self.cursor
+    }
+}
+
+// Leva [dead_zone, 1] pra [0, 1], pra não ter um salto na borda da zona morta
+fn rescale(value: f32, dead_zone: f32) -> f32 {
+    if value <= dead_zone {
+        0.0
+    } else {
+        ((value - dead_zone) / (1.0 - dead_zone)).min(1.0)
+    }
+}
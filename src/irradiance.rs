@@ -0,0 +1,404 @@
+use std::mem::size_of;
+
+use anyhow::Result;
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    buffer::{StorageBuffer, StorageLocation},
+    mesh::MeshRenderer,
+    multiview::{MultiviewRenderer, View},
+    pipeline::Pipeline,
+    sampler::{self, SamplerDesc},
+    scene::DrawItem,
+    shader::spirv,
+    uniforms::IrradianceGridUniform,
+    upload::UploadQueue,
+};
+
+// Sondas além dessas não cabem no buffer
+pub const MAX_IRRADIANCE_PROBES: usize = 512;
+
+// A irradiância varia devagar com a direção: faces pequenas bastam
+const CAPTURE_SIZE: u32 = 32;
+const CAPTURE_NEAR: f32 = 0.05;
+const CAPTURE_FAR: f32 = 100.0;
+
+// Caixa coberta pela grade e quantas sondas em cada eixo. As sondas ficam nos cantos e
+// espalhadas por igual entre eles
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IrradianceGridSettings {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+    pub counts: [u32; 3],
+}
+
+impl IrradianceGridSettings {
+    pub fn count(&self) -> usize {
+        self.counts.iter().map(|c| *c as usize).product()
+    }
+
+    // Com uma sonda só no eixo, o espaçamento é 1 pra shader não dividir por zero
+    fn spacing(&self) -> glm::Vec3 {
+        let size = self.max - self.min;
+        let mut spacing = glm::vec3(1.0, 1.0, 1.0);
+        for axis in 0..3 {
+            if self.counts[axis] > 1 {
+                spacing[axis] = size[axis] / (self.counts[axis] - 1) as f32;
+            }
+        }
+        spacing
+    }
+
+    // x varia mais rápido, depois y, depois z (igual ao ambientLightAt no lights.glsl)
+    fn position(&self, index: usize) -> glm::Vec3 {
+        let [x, y, _] = self.counts.map(|c| c as usize);
+        let cell = glm::vec3(
+            (index % x) as f32,
+            (index / x % y) as f32,
+            (index / (x * y)) as f32,
+        );
+        self.min + self.spacing().component_mul(&cell)
+    }
+}
+
+// Grade de sondas com a luz que chega em cada ponto, em 9 harmônicos esféricos, pra luz
+// ambiente difusa variar pela cena. Cada sonda é capturada como um cubo pequeno num pass
+// com multiview (ver multiview.rs) e um compute projeta as faces nos harmônicos, direto
+// no buffer lido pela cena (set 0, binding 5). Uma sonda por frame: a grade vai sendo
+// preenchida aos poucos e só passa a valer depois da primeira varredura inteira
+#[derive(Debug)]
+pub struct IrradianceGrid {
+    settings: Option<IrradianceGridSettings>,
+    // Próxima sonda a capturar; None sem captura pendente
+    next: Option<usize>,
+    // Todas as sondas já foram capturadas uma vez
+    complete: bool,
+    // Com o `irradiance_probes` da configuração e um dispositivo com multiview
+    capture: Option<MultiviewRenderer>,
+    buffer: StorageBuffer,
+    sampler: vk::Sampler,
+    set_layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    project: Pipeline,
+}
+
+impl IrradianceGrid {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let mut grid = Self {
+            settings: None,
+            next: None,
+            complete: false,
+            capture: None,
+            buffer: StorageBuffer::default(),
+            sampler: vk::Sampler::null(),
+            set_layout: vk::DescriptorSetLayout::null(),
+            pool: vk::DescriptorPool::null(),
+            set: vk::DescriptorSet::null(),
+            project: Pipeline::default(),
+        };
+
+        grid.create_device_objects(instance, device, data)?;
+        Ok(grid)
+    }
+
+    fn buffer_size() -> vk::DeviceSize {
+        (MAX_IRRADIANCE_PROBES * 9 * size_of::<glm::Vec4>()) as vk::DeviceSize
+    }
+
+    // O buffer existe mesmo desligado, porque o set 0 sempre aponta pra ele
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        self.buffer = StorageBuffer::create(
+            instance,
+            device,
+            data,
+            Self::buffer_size(),
+            vk::BufferUsageFlags::empty(),
+            StorageLocation::Device,
+        )?;
+        // O buffer novo está vazio
+        self.complete = false;
+        self.next = self.settings.map(|_| 0);
+
+        if !data.config.irradiance_probes {
+            return Ok(());
+        }
+        if !data.capabilities.multiview {
+            warn!("Irradiance probes need VK_KHR_multiview, disabling them.");
+            return Ok(());
+        }
+
+        let capture = MultiviewRenderer::create_cube(instance, device, data, CAPTURE_SIZE)?;
+
+        self.sampler = sampler::get(
+            device,
+            &SamplerDesc {
+                filter: vk::Filter::NEAREST,
+                ..Default::default()
+            },
+        )?;
+
+        // As faces capturadas no binding 0, os harmônicos no 1
+        let bindings = &[
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            StorageBuffer::binding(1, vk::ShaderStageFlags::COMPUTE),
+        ];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .build(),
+        ];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[self.set_layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(set_layouts);
+        self.set = device.allocate_descriptor_sets(&info)?[0];
+
+        let faces_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(capture.view())
+            .sampler(self.sampler)
+            .build()];
+        let faces_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(faces_info);
+        device.update_descriptor_sets(&[faces_write], &[] as &[vk::CopyDescriptorSet]);
+        self.buffer.write_descriptor(device, self.set, 1);
+
+        // Só o índice da sonda
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(4)
+            .build()];
+        let shader = spirv!("irradiance.comp");
+        self.project =
+            Pipeline::create_compute(device, &shader[..], &[self.set_layout], push_constants)?;
+
+        self.capture = Some(capture);
+        Ok(())
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    pub fn buffer(&self) -> &StorageBuffer {
+        &self.buffer
+    }
+
+    pub fn settings(&self) -> Option<IrradianceGridSettings> {
+        self.settings
+    }
+
+    // Troca a grade e começa a capturar do zero. Até a varredura terminar, a luz ambiente
+    // volta pra de antes. Grades com mais de MAX_IRRADIANCE_PROBES sondas são recusadas
+    pub fn set_settings(&mut self, settings: Option<IrradianceGridSettings>) {
+        let settings = settings.filter(|s| s.count() > 0);
+        if let Some(settings) = &settings {
+            if settings.count() > MAX_IRRADIANCE_PROBES {
+                warn!(
+                    "Irradiance grid of {} probes is over the limit of {}, ignoring it.",
+                    settings.count(),
+                    MAX_IRRADIANCE_PROBES
+                );
+                return;
+            }
+        }
+
+        self.settings = settings;
+        self.next = settings.map(|_| 0);
+        self.complete = false;
+    }
+
+    // Captura tudo de novo (a cena mudou), com as sondas antigas valendo enquanto isso
+    pub fn capture(&mut self) {
+        self.next = self.settings.map(|_| 0);
+    }
+
+    // Zerado (luz ambiente de sempre) até a primeira varredura terminar
+    pub fn uniform(&self) -> IrradianceGridUniform {
+        match self.settings {
+            Some(settings) if self.complete => {
+                let (min, spacing) = (settings.min, settings.spacing());
+                let [x, y, z] = settings.counts;
+                IrradianceGridUniform {
+                    origin: glm::vec4(min.x, min.y, min.z, 1.0),
+                    spacing: glm::vec4(spacing.x, spacing.y, spacing.z, 0.0),
+                    counts: [x, y, z, 0],
+                }
+            }
+            _ => IrradianceGridUniform::default(),
+        }
+    }
+
+    // Antes do pass da cena: captura a próxima sonda pendente e projeta nos harmônicos.
+    // Sem captura de fundo, o que nenhuma malha cobre vale `ambient`. Retorna quantos
+    // draw calls fez
+    pub unsafe fn record(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        meshes: &MeshRenderer,
+        uploads: &UploadQueue,
+        sun_direction: glm::Vec3,
+        ambient: glm::Vec3,
+        items: &[DrawItem],
+    ) -> u32 {
+        let (capture, settings, index) = match (&mut self.capture, self.settings, self.next) {
+            (Some(capture), Some(settings), Some(index)) => (capture, settings, index),
+            _ => return 0,
+        };
+
+        let views = View::cube(
+            settings.position(index),
+            CAPTURE_NEAR,
+            CAPTURE_FAR,
+            data.config.reverse_z,
+        );
+        capture.background = ambient;
+        let draw_calls = capture.record(
+            device,
+            data,
+            command_buffer,
+            slot,
+            meshes,
+            uploads,
+            &views,
+            sun_direction,
+            glm::comp_max(&ambient),
+            items,
+        );
+
+        // O render pass já deixa as faces em SHADER_READ_ONLY, mas só visíveis pro
+        // fragment; o compute também precisa ver
+        Self::barrier(
+            device,
+            command_buffer,
+            capture.image(),
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.project.pipeline,
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.project.layout,
+            0,
+            &[self.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.project.layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &(index as u32).to_ne_bytes(),
+        );
+        device.cmd_dispatch(command_buffer, 1, 1, 1);
+
+        // A próxima captura só desenha por cima depois do compute ler
+        Self::barrier(
+            device,
+            command_buffer,
+            capture.image(),
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::empty(),
+        );
+
+        if index + 1 < settings.count() {
+            self.next = Some(index + 1);
+        } else {
+            self.next = None;
+            self.complete = true;
+        }
+
+        draw_calls
+    }
+
+    unsafe fn barrier(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) {
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(6);
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        if let Some(mut capture) = self.capture.take() {
+            capture.destroy(device);
+            self.project.destroy(device);
+            device.destroy_descriptor_pool(self.pool, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+        self.buffer.destroy(device);
+    }
+}
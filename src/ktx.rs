@@ -0,0 +1,141 @@
+use std::{fs, path::Path, sync::Once};
+
+use anyhow::{anyhow, Result};
+use basis_universal::{
+    DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscoderBlockFormat,
+};
+use ktx2::SupercompressionScheme;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::texture::{FormatSupport, TextureData};
+
+// Bytes de um bloco UASTC (4x4 texels), igual ao de BC7 e ASTC 4x4
+const UASTC_BLOCK_BYTES: usize = 16;
+
+static TRANSCODER_INIT: Once = Once::new();
+
+// Pra onde o UASTC vai, do melhor pro pior. Sem nenhum dos comprimidos vira RGBA8, que
+// pelo menos funciona em qualquer lugar
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Target {
+    Bc7,
+    Astc,
+    Rgba8,
+}
+
+impl Target {
+    fn choose(support: &FormatSupport, srgb: bool) -> Self {
+        [Target::Bc7, Target::Astc]
+            .into_iter()
+            .find(|t| support.can_sample(t.format(srgb)))
+            .unwrap_or(Target::Rgba8)
+    }
+
+    fn format(&self, srgb: bool) -> vk::Format {
+        match (self, srgb) {
+            (Target::Bc7, false) => vk::Format::BC7_UNORM_BLOCK,
+            (Target::Bc7, true) => vk::Format::BC7_SRGB_BLOCK,
+            (Target::Astc, false) => vk::Format::ASTC_4X4_UNORM_BLOCK,
+            (Target::Astc, true) => vk::Format::ASTC_4X4_SRGB_BLOCK,
+            (Target::Rgba8, false) => vk::Format::R8G8B8A8_UNORM,
+            (Target::Rgba8, true) => vk::Format::R8G8B8A8_SRGB,
+        }
+    }
+
+    fn block_format(&self) -> TranscoderBlockFormat {
+        match self {
+            Target::Bc7 => TranscoderBlockFormat::BC7,
+            Target::Astc => TranscoderBlockFormat::ASTC_4x4,
+            Target::Rgba8 => TranscoderBlockFormat::RGBA32,
+        }
+    }
+}
+
+// Lê um KTX2 2D com todos os mips que ele tiver. Com um formato Vulkan no cabeçalho os
+// níveis vão direto (o `srgb` é ignorado, o formato já diz); sem formato é Basis UASTC,
+// transcodificado pra BC7 ou ASTC conforme o que o dispositivo amostra
+pub fn load(path: &Path, srgb: bool, support: &FormatSupport) -> Result<TextureData> {
+    let bytes = fs::read(path)?;
+    let reader = ktx2::Reader::new(&bytes[..])
+        .map_err(|e| anyhow!("Invalid KTX2 file {:?}: {:?}.", path, e))?;
+    let header = reader.header();
+
+    if header.face_count != 1 || header.layer_count > 1 || header.pixel_depth > 1 {
+        return Err(anyhow!(
+            "Only 2D KTX2 textures are supported, {:?} is a cube, array or volume.",
+            path
+        ));
+    }
+
+    let levels = reader
+        .levels()
+        .map(|level| match header.supercompression_scheme {
+            None => Ok(level.to_vec()),
+            Some(SupercompressionScheme::Zstandard) => Ok(zstd::decode_all(level)?),
+            Some(scheme) => Err(anyhow!(
+                "Unsupported KTX2 supercompression {:?} in {:?} (ETC1S files need to be \
+                 re-encoded as UASTC).",
+                scheme,
+                path
+            )),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (width, height) = (header.pixel_width, header.pixel_height.max(1));
+    if let Some(format) = header.format {
+        return Ok(TextureData {
+            width,
+            height,
+            format: vk::Format::from_raw(format.value() as i32),
+            levels,
+        });
+    }
+
+    let target = Target::choose(support, srgb);
+    debug!("Transcoding {:?} to {:?}.", path, target);
+
+    TRANSCODER_INIT.call_once(basis_universal::transcoder_init);
+    let transcoder = LowLevelUastcTranscoder::new();
+    let levels = levels
+        .iter()
+        .enumerate()
+        .map(|(level, blocks)| {
+            let width = (width >> level).max(1);
+            let height = (height >> level).max(1);
+            let parameters = SliceParametersUastc {
+                num_blocks_x: (width + 3) / 4,
+                num_blocks_y: (height + 3) / 4,
+                has_alpha: true,
+                original_width: width,
+                original_height: height,
+            };
+
+            let expected = (parameters.num_blocks_x * parameters.num_blocks_y) as usize;
+            if blocks.len() != expected * UASTC_BLOCK_BYTES {
+                return Err(anyhow!(
+                    "Mip level {} of {:?} is not UASTC ({} bytes).",
+                    level,
+                    path,
+                    blocks.len()
+                ));
+            }
+
+            transcoder
+                .transcode_slice(
+                    blocks,
+                    parameters,
+                    DecodeFlags::HIGH_QUALITY,
+                    target.block_format(),
+                )
+                .map_err(|e| anyhow!("Failed to transcode {:?}: {:?}.", path, e))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(TextureData {
+        width,
+        height,
+        format: target.format(srgb),
+        levels,
+    })
+}
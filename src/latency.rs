@@ -0,0 +1,177 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::*;
+use vulkanalia::{prelude::v1_0::*, vk::KhrPresentWaitExtension};
+
+use crate::stats::FrameStats;
+
+// Quanto esperar, no modo de baixa latência, pelo present anterior antes de desistir
+const LOW_LATENCY_TIMEOUT: Duration = Duration::from_millis(100);
+// Cada espera da thread é curta, pra ela perceber logo quando tem que parar
+const WAIT_SLICE: u64 = 50_000_000;
+// Presents que podem estar esperando antes de acharmos que nunca vão completar
+const MAX_PENDING: u64 = 16;
+
+// Mede quanto tempo cada frame leva pra chegar na tela usando VK_KHR_present_id (cada
+// present ganha um id crescente) e VK_KHR_present_wait (esperar o present com um id)
+#[derive(Debug, Default)]
+pub struct PresentTimer {
+    pub supported: bool,
+    // Antes de começar o frame N, espera o frame N-1 aparecer na tela. Troca throughput
+    // por latência: a CPU nunca fica mais de um frame na frente do display
+    pub low_latency: bool,
+    next_id: u64,
+    // Último id que a thread viu chegar na tela
+    completed: u64,
+    waiter: Option<PresentWaiter>,
+}
+
+impl PresentTimer {
+    pub fn new(supported: bool, low_latency: bool) -> Self {
+        Self {
+            supported,
+            low_latency,
+            ..Default::default()
+        }
+    }
+
+    // Os ids são por swapchain, então recomeçamos quando ela é recriada. Tem que vir antes
+    // de destruir a swapchain, porque a thread pode estar esperando nela
+    pub fn reset(&mut self) {
+        self.waiter = None;
+        self.next_id = 0;
+        self.completed = 0;
+    }
+
+    // Id que deve ir no vk::PresentIdKHR desse frame
+    pub fn next_present_id(
+        &mut self,
+        device: &Device,
+        swapchain: vk::SwapchainKHR,
+        frame_start: Instant,
+    ) -> Option<u64> {
+        if !self.supported {
+            return None;
+        }
+
+        let waiter = self
+            .waiter
+            .get_or_insert_with(|| PresentWaiter::spawn(device.clone(), swapchain));
+        self.next_id += 1;
+        // Se a thread já saiu (erro na espera), os ids acumulam e o `update` recomeça
+        let _ = waiter.ids.send((self.next_id, frame_start));
+
+        Some(self.next_id)
+    }
+
+    // Chamado no começo do frame
+    pub fn update(&mut self, stats: &mut FrameStats) {
+        let waiter = match &self.waiter {
+            Some(waiter) => waiter,
+            None => return,
+        };
+
+        if self.low_latency && self.completed < self.next_id {
+            let deadline = Instant::now() + LOW_LATENCY_TIMEOUT;
+            while self.completed < self.next_id {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match waiter.done.recv_timeout(timeout) {
+                    Ok((id, latency)) => {
+                        self.completed = id;
+                        record(stats, latency);
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+
+        // Coleta (sem bloquear) todos os presents que já terminaram
+        while let Ok((id, latency)) = waiter.done.try_recv() {
+            self.completed = id;
+            record(stats, latency);
+        }
+
+        // Se algo deu errado e os presents nunca completam, não deixa a fila crescer
+        if self.next_id - self.completed > MAX_PENDING {
+            warn!("Present ids are not completing, resetting latency tracking.");
+            self.waiter = None;
+            self.completed = self.next_id;
+        }
+    }
+}
+
+fn record(stats: &mut FrameStats, latency: Duration) {
+    let previous = stats.present_latency.unwrap_or(Duration::ZERO);
+    stats.present_latency = Some(FrameStats::smooth(previous, latency));
+}
+
+// Thread que espera cada present em ordem e anota a hora em que a espera voltou, que é
+// quando a imagem foi pra tela (esperar no começo do frame atrasaria a medida em até um
+// frame)
+#[derive(Debug)]
+struct PresentWaiter {
+    ids: Sender<(u64, Instant)>,
+    done: Receiver<(u64, Duration)>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PresentWaiter {
+    fn spawn(device: Device, swapchain: vk::SwapchainKHR) -> Self {
+        let (ids, requests) = mpsc::channel::<(u64, Instant)>();
+        let (results, done) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            for (id, start) in requests {
+                loop {
+                    if stopped.load(Ordering::Relaxed) {
+                        return;
+                    }
+
+                    match unsafe { device.wait_for_present_khr(swapchain, id, WAIT_SLICE) } {
+                        Ok(vk::SuccessCode::TIMEOUT) => continue,
+                        Ok(_) => {
+                            if results.send((id, start.elapsed())).is_err() {
+                                return;
+                            }
+                            break;
+                        }
+                        Err(e) => {
+                            warn!("Waiting for present {} failed: {}", id, e);
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            ids,
+            done,
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for PresentWaiter {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Fechar o canal dos ids acorda a thread se ela estiver parada esperando um
+        let (ids, _) = mpsc::channel();
+        self.ids = ids;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,113 @@
+#[cfg(feature = "leak-tracking")]
+use lazy_static::lazy_static;
+#[cfg(feature = "leak-tracking")]
+use log::*;
+#[cfg(feature = "leak-tracking")]
+use std::backtrace::Backtrace;
+#[cfg(feature = "leak-tracking")]
+use std::collections::HashMap;
+#[cfg(feature = "leak-tracking")]
+use std::sync::Mutex;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::Handle;
+
+// Registro de buffers, imagens, views e pipelines vivos, com o backtrace de onde cada um
+// foi criado. Só existe com a feature "leak-tracking"; sem ela tudo aqui não faz nada e
+// os destroy_* são só a chamada do Vulkan
+
+#[cfg(feature = "leak-tracking")]
+type Key = (vk::ObjectType, u64);
+
+#[cfg(feature = "leak-tracking")]
+lazy_static! {
+    static ref LIVE: Mutex<HashMap<Key, Backtrace>> = Mutex::new(HashMap::new());
+    // Onde cada handle morreu, pra explicar um uso depois do destroy. Sai daqui quando o
+    // driver reaproveita o handle
+    static ref DESTROYED: Mutex<HashMap<Key, Backtrace>> = Mutex::new(HashMap::new());
+}
+
+pub fn created<H: Handle<Repr = u64>>(handle: H) {
+    #[cfg(feature = "leak-tracking")]
+    if !handle.is_null() {
+        let key = (H::TYPE, handle.as_raw());
+        DESTROYED.lock().unwrap().remove(&key);
+        LIVE.lock().unwrap().insert(key, Backtrace::force_capture());
+    }
+}
+
+pub fn destroyed<H: Handle<Repr = u64>>(handle: H) {
+    #[cfg(feature = "leak-tracking")]
+    if !handle.is_null() {
+        let key = (H::TYPE, handle.as_raw());
+        if LIVE.lock().unwrap().remove(&key).is_none() {
+            report_dead(key, "destroyed again");
+        }
+        DESTROYED
+            .lock()
+            .unwrap()
+            .insert(key, Backtrace::force_capture());
+    }
+}
+
+// Chamado pelos tipos que guardam um handle antes de usar ele, pra avisar se já foi
+// destruído
+pub fn check<H: Handle<Repr = u64>>(handle: H, what: &str) {
+    #[cfg(feature = "leak-tracking")]
+    if !handle.is_null() {
+        let key = (H::TYPE, handle.as_raw());
+        if !LIVE.lock().unwrap().contains_key(&key) {
+            report_dead(key, &format!("used after destroy ({})", what));
+        }
+    }
+}
+
+#[cfg(feature = "leak-tracking")]
+fn report_dead((kind, raw): Key, what: &str) {
+    match DESTROYED.lock().unwrap().get(&(kind, raw)) {
+        Some(backtrace) => error!(
+            "{:?} {:#x} {}. Destroyed at:\n{}",
+            kind, raw, what, backtrace
+        ),
+        // Nunca passou pelo registro: criado fora dos helpers
+        None => error!("{:?} {:#x} {}, but was never registered.", kind, raw, what),
+    }
+}
+
+// Chamado logo antes de destruir o dispositivo: o que ainda estiver vivo vazou. Esvazia o
+// registro, já que depois de um DEVICE_LOST tudo é criado de novo
+pub fn report() {
+    #[cfg(feature = "leak-tracking")]
+    {
+        let mut live = LIVE.lock().unwrap();
+        DESTROYED.lock().unwrap().clear();
+        if live.is_empty() {
+            info!("No leaked Vulkan resources.");
+            return;
+        }
+
+        error!("{} Vulkan resources leaked:", live.len());
+        for ((kind, raw), backtrace) in live.drain() {
+            error!("{:?} {:#x} created at:\n{}", kind, raw, backtrace);
+        }
+    }
+}
+
+pub unsafe fn destroy_buffer(device: &Device, buffer: vk::Buffer) {
+    destroyed(buffer);
+    device.destroy_buffer(buffer, None);
+}
+
+pub unsafe fn destroy_image(device: &Device, image: vk::Image) {
+    destroyed(image);
+    device.destroy_image(image, None);
+}
+
+pub unsafe fn destroy_image_view(device: &Device, view: vk::ImageView) {
+    destroyed(view);
+    device.destroy_image_view(view, None);
+}
+
+pub unsafe fn destroy_pipeline(device: &Device, pipeline: vk::Pipeline) {
+    destroyed(pipeline);
+    device.destroy_pipeline(pipeline, None);
+}
@@ -0,0 +1,70 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// O sleep do SO pode acordar alguns milissegundos atrasado, então dormimos até essa
+// margem antes do prazo e o resto é feito girando
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+// Segura o loop num FPS máximo. Só faz sentido nos modos que não esperam o vblank
+// (MAILBOX/IMMEDIATE); no FIFO quem limita é a própria apresentação
+#[derive(Clone, Debug, Default)]
+pub struct FrameLimiter {
+    interval: Option<Duration>,
+    // Quando o próximo frame pode começar
+    deadline: Option<Instant>,
+}
+
+impl FrameLimiter {
+    pub fn new(fps: Option<u32>) -> Self {
+        let mut limiter = Self::default();
+        limiter.set_fps(fps);
+        limiter
+    }
+
+    // None (ou 0) desliga o limite
+    pub fn set_fps(&mut self, fps: Option<u32>) {
+        self.set_interval(
+            fps.filter(|fps| *fps > 0)
+                .map(|fps| Duration::from_secs_f64(1.0 / fps as f64)),
+        );
+    }
+
+    pub fn set_interval(&mut self, interval: Option<Duration>) {
+        self.interval = interval;
+        self.deadline = None;
+    }
+
+    pub fn fps(&self) -> Option<u32> {
+        self.interval
+            .map(|interval| (1.0 / interval.as_secs_f64()).round() as u32)
+    }
+
+    // Chamado depois do present; volta quando o próximo frame já pode começar
+    pub fn wait(&mut self) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let deadline = match self.deadline {
+            // Se atrasamos mais de um frame (ex: um travamento), não tentamos compensar
+            // rodando vários frames seguidos sem espera
+            Some(deadline) if now < deadline + interval => deadline,
+            _ => now,
+        };
+
+        if let Some(remaining) = deadline.checked_duration_since(now) {
+            if remaining > SPIN_MARGIN {
+                thread::sleep(remaining - SPIN_MARGIN);
+            }
+            while Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+        }
+
+        self.deadline = Some(deadline + interval);
+    }
+}
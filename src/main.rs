@@ -6,10 +6,85 @@
 )]
 
 mod error;
+mod allocator;
+mod animation;
 mod app;
+mod assets;
+#[cfg(feature = "audio")]
+mod audio;
+mod buffer;
+mod camera;
+mod capture;
+mod cli;
+mod config;
+mod controller;
+mod dds;
+mod decal;
+mod deferred;
+mod display;
+#[cfg(feature = "ecs")]
+mod ecs;
+mod exposure;
 mod info;
+mod ktx;
+mod latency;
+mod leaks;
+mod limiter;
+mod features;
+mod fog;
+mod fxaa;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod godrays;
+mod grading;
+mod graph;
+mod image;
+mod input;
+mod irradiance;
+mod marker;
+mod material;
+mod mesh;
+mod multiview;
+mod occlusion;
+mod outline;
+mod overlay;
+mod pacing;
+mod pass;
+mod picking;
+mod pipeline;
+mod post;
+mod probe;
+mod profiler;
+#[cfg(feature = "renderdoc")]
+mod rdoc;
+mod remote;
+mod render_thread;
+mod report;
+mod sampler;
+mod scene;
+mod shader;
+mod shadow;
+mod sky;
+mod sprite;
+mod stats;
+mod streaming;
+mod sync;
+mod terrain;
+mod text;
+mod texture;
+mod time;
+mod tonemap;
+mod ui;
+mod uniforms;
+mod upload;
+mod watcher;
+#[cfg(feature = "xr")]
+mod xr;
+
+use std::sync::Arc;
 
 use anyhow::Result;
+use clap::Parser;
 use vulkanalia::prelude::v1_0::*;
 use winit::{event_loop::{EventLoop, ControlFlow}, window::WindowBuilder, dpi::LogicalSize, event::{WindowEvent, Event}};
 
@@ -17,40 +92,134 @@ const VALIDATION_ENABLED: bool = true /* cfg!(debug_assertions) */;
 const VALIDATION_LAYER: vk::ExtensionName =
     vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
 const DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[vk::KHR_SWAPCHAIN_EXTENSION.name];
+// Definidas aqui porque nem toda versão dos headers tem as extensões de portabilidade
+const PORTABILITY_ENUMERATION_EXTENSION: vk::ExtensionName =
+    vk::ExtensionName::from_bytes(b"VK_KHR_portability_enumeration");
+const PORTABILITY_SUBSET_EXTENSION: vk::ExtensionName =
+    vk::ExtensionName::from_bytes(b"VK_KHR_portability_subset");
+// Quantos frames a CPU pode preparar enquanto a GPU ainda está desenhando os anteriores
+const MAX_FRAMES_IN_FLIGHT: usize = 2;
 
 fn main() -> Result<()> {
     // Queremos logs bonitos
     pretty_env_logger::init();
 
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_title("Learning Vulkan (Oh boy)")
-        .with_inner_size(LogicalSize::new(600, 600))
-        .build(&event_loop)?;
+    let args = cli::Args::parse();
+
+    // O cliente do Tracy precisa estar rodando antes de qualquer zona
+    #[cfg(feature = "profiling")]
+    let _tracy = tracy_client::Client::start();
+
+    let event_loop = EventLoop::with_user_event();
+    let window = Arc::new(
+        WindowBuilder::new()
+            .with_title("Learning Vulkan (Oh boy)")
+            .with_inner_size(LogicalSize::new(args.width, args.height))
+            // Sem mostrar, mas ainda com surface e swapchain: o resto do renderer não muda
+            .with_visible(!args.headless)
+            .build(&event_loop)?,
+    );
+
+    let builder = args.apply(app::AppBuilder::new());
+
+    // --report só gera o pacote de diagnóstico e sai
+    if args.report {
+        unsafe {
+            let mut app = match builder.build(&window) {
+                Ok(app) => app,
+                Err(e) => {
+                    write_fatal_report(report::Report::collect(), &e);
+                    return Err(e);
+                }
+            };
+            app.report().write_timestamped()?;
+            app.destroy();
+        }
+        return Ok(());
+    }
+
+    // --bindings (ou LV_BINDINGS) redefine as ações (`move_forward = W, Up`)
+    let mut input = match &args.bindings {
+        Some(path) => input::Input::new(input::Bindings::load(path)?),
+        None => input::Input::default(),
+    };
+    if let Some(dead_zone) = args.dead_zone {
+        input.dead_zone = dead_zone.clamp(0.0, 0.95);
+    }
+
+    // --remote 8080 liga o controle remoto por HTTP no loopback (ou host:porta pra escutar
+    // em outro lugar). Os screenshots dele vão pro --remote-captures, e as cenas e LUTs só
+    // são lidas do --remote-assets
+    let remote = match &args.remote {
+        Some(address) => Some(remote::RemoteServer::listen(
+            address,
+            args.remote_captures.clone(),
+            args.remote_assets.clone(),
+        )?),
+        None => None,
+    };
 
-    let mut app = unsafe { app::App::create(&window)? };
-    let mut destroying = false;
+    // Sessão de revisão: um lidera e os outros copiam a câmera e as opções dele
+    let sync = match (&args.sync_lead, &args.sync_follow) {
+        (Some(address), _) => Some(sync::SyncSession::lead(address.as_str())?),
+        (_, Some(address)) => Some(sync::SyncSession::follow(address.as_str())?),
+        _ => None,
+    };
+
+    // O App vive na thread de renderização; aqui só repassamos os eventos pra ela
+    let mut render_thread = render_thread::RenderThread::spawn(
+        window.clone(),
+        builder,
+        input,
+        remote,
+        sync,
+        args.frames,
+        args.screenshot.clone(),
+        event_loop.create_proxy(),
+    )?;
 
     // Janela básica do winit
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+        *control_flow = ControlFlow::Wait;
 
         match event {
-            Event::MainEventsCleared if !destroying => unsafe {
-                app.render(&window).unwrap();
-            },
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
                 log::warn!("VAI TOAMR NO CU");
-                destroying = true;
+                render_thread.shutdown();
                 *control_flow = ControlFlow::Exit;
-                unsafe {
-                    app.destroy();
+            }
+            // O ScaleFactorChanged não tem versão 'static, mas vem seguido de um Resized
+            Event::WindowEvent { event, .. } => {
+                if let Some(event) = event.to_static() {
+                    render_thread.send(render_thread::RenderMessage::Window(event));
                 }
             }
+            Event::DeviceEvent { event, .. } => {
+                render_thread.send(render_thread::RenderMessage::Device(event));
+            }
+            // A thread terminou sozinha, depois de um erro fatal
+            Event::UserEvent(render_thread::RenderThreadExited) => {
+                render_thread.shutdown();
+                *control_flow = ControlFlow::Exit;
+            }
             _ => {}
         }
     });
 }
+
+// Erro fatal: salva o relatório junto com o erro antes de sair
+fn write_fatal_report(mut report: report::Report, error: &anyhow::Error) {
+    log::error!("Fatal error: {:?}", error);
+    report.add("error.txt", format!("{:?}\n", error));
+
+    match report.write_timestamped() {
+        Ok(path) => log::error!(
+            "Diagnostics saved to {:?}, please attach it to the issue.",
+            path
+        ),
+        Err(e) => log::error!("Failed to write diagnostics report: {}", e),
+    }
+}
@@ -0,0 +1,32 @@
+use std::ffi::CString;
+
+use vulkanalia::{prelude::v1_0::*, vk::ExtDebugUtilsExtension};
+
+use crate::app::AppData;
+
+// Rótulos nos command buffers (VK_EXT_debug_utils). Ferramentas como o RenderDoc usam eles
+// pra agrupar os comandos de cada pass na captura
+pub unsafe fn begin(
+    instance: &Instance,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    name: &str,
+    color: [f32; 4],
+) {
+    if !data.debug_utils {
+        return;
+    }
+
+    let name = CString::new(name).unwrap_or_default();
+    let label = vk::DebugUtilsLabelEXT::builder()
+        .label_name(name.as_bytes_with_nul())
+        .color(color);
+
+    instance.cmd_begin_debug_utils_label_ext(command_buffer, &label);
+}
+
+pub unsafe fn end(instance: &Instance, data: &AppData, command_buffer: vk::CommandBuffer) {
+    if data.debug_utils {
+        instance.cmd_end_debug_utils_label_ext(command_buffer);
+    }
+}
@@ -0,0 +1,1032 @@
+use std::{ffi::c_void, mem::size_of, path::Path, ptr, slice, time::Instant};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::{
+    prelude::v1_0::*,
+    vk::{KhrDescriptorUpdateTemplateExtension, KhrPushDescriptorExtension},
+};
+
+use crate::{
+    allocator,
+    app::AppData,
+    buffer::create_buffer,
+    leaks,
+    pipeline::BlendMode,
+    shader::spirv,
+    texture::{self, Texture, TextureData},
+    upload::{UploadId, UploadQueue},
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+// Quantos materiais podem existir ao mesmo tempo (um descriptor set por frame em voo cada,
+// sem push descriptors)
+const MAX_MATERIALS: u32 = 256;
+
+// Texturas por material, nos bindings 1 em diante: cor base, normal,
+// metálico/rugosidade, oclusão e emissiva
+const TEXTURE_BINDINGS: u32 = 5;
+
+// Com streaming, sobem no começo só os mips até esse tamanho (o maior lado, em texels).
+// Os maiores vêm depois, conforme o tamanho na tela pedir (ver streaming.rs)
+const STREAMING_RESIDENT_SIZE: u32 = 128;
+
+// Qual pipeline desenha o material. Todas usam a mesma vertex shader e o mesmo layout.
+// No deferred, a posição aqui é o modelo de shading gravado no G-buffer
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ShaderVariant {
+    // Só a cor base e a emissiva, sem luz nenhuma
+    Unlit,
+    // Luz saindo da câmera, pra ver a forma sem precisar de luzes na cena
+    Headlight,
+    // Difusa de Lambert e especular de Blinn-Phong, com as luzes do frame
+    BlinnPhong,
+    // Metálico/rugosidade do glTF 2.0, com especular de Cook-Torrance (GGX)
+    Pbr,
+}
+
+impl ShaderVariant {
+    pub const ALL: [ShaderVariant; 4] = [
+        ShaderVariant::Unlit,
+        ShaderVariant::Headlight,
+        ShaderVariant::BlinnPhong,
+        ShaderVariant::Pbr,
+    ];
+
+    pub fn fragment_shader(self) -> &'static [u8] {
+        match self {
+            ShaderVariant::Unlit => &spirv!("unlit.frag")[..],
+            ShaderVariant::Headlight => &spirv!("mesh.frag")[..],
+            ShaderVariant::BlinnPhong => &spirv!("blinn_phong.frag")[..],
+            ShaderVariant::Pbr => &spirv!("pbr.frag")[..],
+        }
+    }
+}
+
+// Bloco de parâmetros lido pelas shaders (set 1, binding 0). O layout segue o std140
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MaterialParams {
+    // Linear, multiplicada pela textura base
+    pub base_color: glm::Vec4,
+    // rgb linear, somada no fim; w sem uso
+    pub emissive: glm::Vec4,
+    // rgb: cor do especular, w: expoente do Blinn-Phong
+    pub specular: glm::Vec4,
+    // Os fatores abaixo multiplicam as texturas, como no glTF
+    pub metallic: f32,
+    pub roughness: f32,
+    // Quanto da textura de oclusão é aplicado (0 a 1)
+    pub occlusion_strength: f32,
+    // Escala do xy do normal map
+    pub normal_scale: f32,
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self {
+            base_color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            emissive: glm::vec4(0.0, 0.0, 0.0, 0.0),
+            specular: glm::vec4(0.5, 0.5, 0.5, 32.0),
+            // Os padrões do glTF
+            metallic: 1.0,
+            roughness: 1.0,
+            occlusion_strength: 1.0,
+            normal_scale: 1.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MaterialTextureId(usize);
+
+// O material 0 sempre existe e é o usado por quem não escolheu nenhum
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MaterialId(usize);
+
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub variant: ShaderVariant,
+    pub params: MaterialParams,
+    // Fora do Opaque o material vai pro pass das transparentes, com o alpha da cor base
+    // (vidro, folhagem). Sempre sombreado no forward, mesmo no caminho deferred
+    pub blend: BlendMode,
+    // As que ficarem None usam uma textura neutra (branca, ou a normal reta)
+    pub base_color_texture: Option<MaterialTextureId>,
+    // Espaço tangente, em UNORM
+    pub normal_texture: Option<MaterialTextureId>,
+    // g: rugosidade, b: metálico (UNORM), como no glTF
+    pub metallic_roughness_texture: Option<MaterialTextureId>,
+    // r: oclusão (UNORM)
+    pub occlusion_texture: Option<MaterialTextureId>,
+    // sRGB
+    pub emissive_texture: Option<MaterialTextureId>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            variant: ShaderVariant::Headlight,
+            params: MaterialParams::default(),
+            blend: BlendMode::Opaque,
+            base_color_texture: None,
+            normal_texture: None,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
+        }
+    }
+}
+
+impl Material {
+    pub fn new(variant: ShaderVariant) -> Self {
+        Self {
+            variant,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_base_color(mut self, color: glm::Vec4) -> Self {
+        self.params.base_color = color;
+        self
+    }
+
+    pub fn with_specular(mut self, color: glm::Vec3, shininess: f32) -> Self {
+        self.params.specular = glm::vec4(color.x, color.y, color.z, shininess);
+        self
+    }
+
+    pub fn with_metallic_roughness(mut self, metallic: f32, roughness: f32) -> Self {
+        self.params.metallic = metallic;
+        self.params.roughness = roughness;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn with_base_color_texture(mut self, texture: MaterialTextureId) -> Self {
+        self.base_color_texture = Some(texture);
+        self
+    }
+
+    pub fn with_normal_texture(mut self, texture: MaterialTextureId) -> Self {
+        self.normal_texture = Some(texture);
+        self
+    }
+
+    pub fn with_metallic_roughness_texture(mut self, texture: MaterialTextureId) -> Self {
+        self.metallic_roughness_texture = Some(texture);
+        self
+    }
+
+    pub fn with_occlusion_texture(mut self, texture: MaterialTextureId) -> Self {
+        self.occlusion_texture = Some(texture);
+        self
+    }
+
+    pub fn with_emissive_texture(mut self, texture: MaterialTextureId) -> Self {
+        self.emissive_texture = Some(texture);
+        self
+    }
+
+    // Textura de cada binding, já com a padrão no lugar das que faltam
+    fn textures(
+        &self,
+        defaults: &DefaultTextures,
+    ) -> [MaterialTextureId; TEXTURE_BINDINGS as usize] {
+        [
+            self.base_color_texture.unwrap_or(defaults.white),
+            self.normal_texture.unwrap_or(defaults.flat_normal),
+            self.metallic_roughness_texture.unwrap_or(defaults.white),
+            self.occlusion_texture.unwrap_or(defaults.white),
+            self.emissive_texture.unwrap_or(defaults.white),
+        ]
+    }
+}
+
+// Usadas no lugar das texturas que o material não tem, sem mudar o resultado
+#[derive(Copy, Clone, Debug)]
+struct DefaultTextures {
+    white: MaterialTextureId,
+    // (0, 0, 1) no espaço tangente
+    flat_normal: MaterialTextureId,
+}
+
+#[derive(Debug)]
+struct MaterialTexture {
+    texture: Texture,
+    // None depois de substituída por uma preparada, que já chegou
+    upload: Option<UploadId>,
+    // Mantido na CPU pra reenviar se o dispositivo for recriado
+    data: TextureData,
+    // Só nas texturas com mips grandes o bastante quando o streaming está ligado
+    stream: Option<StreamState>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct StreamState {
+    // Primeiro nível que já está na GPU; o sampler não desce abaixo dele
+    resident: u32,
+    // Nível mais detalhado pedido nesse frame e o maior tamanho na tela (pixels) de quem
+    // pediu, que decide a ordem
+    wanted: u32,
+    priority: f32,
+    // Os níveis que faltam já estão num layout que o descriptor aceita
+    prepared: bool,
+}
+
+// O que o streaming tem pra fazer: texturas novas pra preparar e o próximo nível de cada
+// textura que precisa de mais detalhe, do mais importante pro menos
+#[derive(Clone, Debug, Default)]
+pub struct StreamWork {
+    pub prepare: Vec<MaterialTextureId>,
+    pub levels: Vec<(MaterialTextureId, u32)>,
+}
+
+#[derive(Debug)]
+struct MaterialEntry {
+    material: Material,
+    // Um por frame em voo, cada um apontando pro bloco de parâmetros daquele frame
+    sets: Vec<vk::DescriptorSet>,
+}
+
+#[derive(Debug)]
+struct ParamsBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+}
+
+// Materiais e as texturas deles. Os parâmetros de todos os materiais ficam num buffer
+// mapeado por frame em voo, reescrito a cada frame, então podem mudar a qualquer hora.
+// As texturas de um material são fixas depois de criado
+#[derive(Debug)]
+pub struct Materials {
+    pub set_layout: vk::DescriptorSetLayout,
+    // Com VK_KHR_push_descriptor não tem pool nem sets: `bind` grava os descritores do
+    // material direto no command buffer
+    push_descriptors: bool,
+    pool: vk::DescriptorPool,
+    // Com VK_KHR_descriptor_update_template, cada set é escrito de uma vez a partir de um
+    // `SetDescriptors`. Nulo sem a extensão (ou com push descriptors)
+    template: vk::DescriptorUpdateTemplate,
+    buffers: Vec<ParamsBuffer>,
+    // Distância entre dois blocos no buffer, respeitando o alinhamento de offsets do
+    // dispositivo
+    stride: vk::DeviceSize,
+    textures: Vec<MaterialTexture>,
+    materials: Vec<MaterialEntry>,
+    defaults: DefaultTextures,
+    streaming: bool,
+    // Aumenta a cada sampler ou textura trocada (streaming e assets). Os descriptor sets
+    // de cada frame em voo são reescritos quando ficam pra trás, e o que foi trocado só
+    // morre depois que nenhum frame usa mais
+    texture_version: u64,
+    written: [u64; MAX_FRAMES_IN_FLIGHT],
+    retired: Vec<(u64, Retired)>,
+}
+
+// O que o template de um set de material lê: os offsets das entradas seguem essa ordem
+#[repr(C)]
+struct SetDescriptors {
+    params: vk::DescriptorBufferInfo,
+    textures: [vk::DescriptorImageInfo; TEXTURE_BINDINGS as usize],
+}
+
+#[derive(Debug)]
+enum Retired {
+    Texture(Texture),
+}
+
+impl Retired {
+    unsafe fn destroy(self, device: &Device) {
+        match self {
+            Retired::Texture(texture) => texture.destroy(device),
+        }
+    }
+}
+
+// Textura criada e com o upload na fila, mas que ainda não substituiu nenhuma (ver
+// `Materials::replace_texture`)
+#[derive(Debug)]
+pub struct PreparedTexture(MaterialTexture);
+
+impl PreparedTexture {
+    pub fn upload(&self) -> UploadId {
+        self.0.upload.unwrap()
+    }
+
+    // Destrói a imagem e devolve os dados, pra preparar de novo depois
+    pub unsafe fn destroy(self, device: &Device) -> TextureData {
+        self.0.texture.destroy(device);
+        self.0.data
+    }
+}
+
+impl Materials {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+    ) -> Result<Self> {
+        let mut materials = Self {
+            set_layout: vk::DescriptorSetLayout::null(),
+            push_descriptors: false,
+            pool: vk::DescriptorPool::null(),
+            template: vk::DescriptorUpdateTemplate::null(),
+            buffers: vec![],
+            stride: 0,
+            textures: vec![],
+            materials: vec![],
+            defaults: DefaultTextures {
+                white: MaterialTextureId(0),
+                flat_normal: MaterialTextureId(0),
+            },
+            streaming: data.config.texture_streaming,
+            texture_version: 0,
+            written: [0; MAX_FRAMES_IN_FLIGHT],
+            retired: vec![],
+        };
+
+        materials.create_descriptor_objects(instance, device, data)?;
+        materials.defaults.white =
+            materials.add_texture(instance, device, data, uploads, 1, 1, vec![255; 4], false)?;
+        materials.defaults.flat_normal = materials.add_texture(
+            instance,
+            device,
+            data,
+            uploads,
+            1,
+            1,
+            vec![128, 128, 255, 255],
+            false,
+        )?;
+        materials.add(device, Material::default())?;
+
+        Ok(materials)
+    }
+
+    unsafe fn create_descriptor_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        let mut bindings = vec![vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        for binding in 1..=TEXTURE_BINDINGS {
+            bindings.push(
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+            );
+        }
+        self.push_descriptors = data.capabilities.push_descriptor;
+        let flags = if self.push_descriptors {
+            vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR
+        } else {
+            vk::DescriptorSetLayoutCreateFlags::empty()
+        };
+        let info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(flags)
+            .bindings(&bindings);
+        self.set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+        self.pool = vk::DescriptorPool::null();
+        if !self.push_descriptors {
+            let sets = MAX_MATERIALS * MAX_FRAMES_IN_FLIGHT as u32;
+            let pool_sizes = &[
+                vk::DescriptorPoolSize::builder()
+                    .type_(vk::DescriptorType::UNIFORM_BUFFER)
+                    .descriptor_count(sets)
+                    .build(),
+                vk::DescriptorPoolSize::builder()
+                    .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(sets * TEXTURE_BINDINGS)
+                    .build(),
+            ];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .pool_sizes(pool_sizes)
+                .max_sets(sets);
+            self.pool = device.create_descriptor_pool(&info, None)?;
+        }
+
+        self.template = vk::DescriptorUpdateTemplate::null();
+        if data.capabilities.descriptor_update_template {
+            self.template = self.create_template(device)?;
+        }
+
+        let alignment = instance
+            .get_physical_device_properties(data.physical_device)
+            .limits
+            .min_uniform_buffer_offset_alignment
+            .max(1);
+        let size = size_of::<MaterialParams>() as vk::DeviceSize;
+        self.stride = (size + alignment - 1) / alignment * alignment;
+
+        let size = self.stride * MAX_MATERIALS as vk::DeviceSize;
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let (buffer, memory) = create_buffer(
+                instance,
+                device,
+                data,
+                size,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let mapped =
+                device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())? as *mut u8;
+
+            self.buffers.push(ParamsBuffer {
+                buffer,
+                memory,
+                mapped,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Um set inteiro numa entrada por binding, lendo de um `SetDescriptors`
+    unsafe fn create_template(&self, device: &Device) -> Result<vk::DescriptorUpdateTemplate> {
+        let buffer_size = size_of::<vk::DescriptorBufferInfo>();
+        let image_size = size_of::<vk::DescriptorImageInfo>();
+
+        let mut entries = vec![vk::DescriptorUpdateTemplateEntry::builder()
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .offset(0)
+            .stride(buffer_size)
+            .build()];
+        for binding in 1..=TEXTURE_BINDINGS {
+            entries.push(
+                vk::DescriptorUpdateTemplateEntry::builder()
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_count(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .offset(buffer_size + (binding as usize - 1) * image_size)
+                    .stride(image_size)
+                    .build(),
+            );
+        }
+
+        let info = vk::DescriptorUpdateTemplateCreateInfo::builder()
+            .descriptor_update_entries(&entries)
+            .template_type(vk::DescriptorUpdateTemplateType::DESCRIPTOR_SET)
+            .descriptor_set_layout(self.set_layout);
+
+        Ok(device.create_descriptor_update_template_khr(&info, None)?)
+    }
+
+    // Recria os descritores e reenvia as texturas depois de perder o dispositivo. Os ids
+    // continuam valendo
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+    ) -> Result<()> {
+        self.create_descriptor_objects(instance, device, data)?;
+
+        for index in 0..self.textures.len() {
+            let texture = std::mem::take(&mut self.textures[index].data);
+            self.textures[index] = self.create_texture(instance, device, data, uploads, texture)?;
+        }
+        self.texture_version = 0;
+        self.written = [0; MAX_FRAMES_IN_FLIGHT];
+
+        for index in 0..self.materials.len() {
+            self.materials[index].sets =
+                self.create_sets(device, index, &self.materials[index].material)?;
+        }
+
+        Ok(())
+    }
+
+    // `srgb` pra texturas de cor; dados (normais, rugosidade...) vão como UNORM. PNG ou
+    // KTX2, que sobe comprimido e com os mips do arquivo
+    pub unsafe fn load_texture(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        path: &Path,
+        srgb: bool,
+    ) -> Result<MaterialTextureId> {
+        let texture = texture::load(instance, data, path, srgb)?;
+        self.add_texture_data(instance, device, data, uploads, texture)
+    }
+
+    // Pixels RGBA8, linha por linha
+    pub unsafe fn add_texture(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+        srgb: bool,
+    ) -> Result<MaterialTextureId> {
+        if pixels.len() != (width * height * 4) as usize {
+            return Err(anyhow!(
+                "Expected {}x{} RGBA pixels, got {} bytes.",
+                width,
+                height,
+                pixels.len()
+            ));
+        }
+
+        let texture = TextureData::rgba8(width, height, pixels, srgb);
+        self.add_texture_data(instance, device, data, uploads, texture)
+    }
+
+    // Imagem já no formato final, com mips ou comprimida
+    pub unsafe fn add_texture_data(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        texture: TextureData,
+    ) -> Result<MaterialTextureId> {
+        texture.validate()?;
+        let texture = self.create_texture(instance, device, data, uploads, texture)?;
+        self.textures.push(texture);
+
+        Ok(MaterialTextureId(self.textures.len() - 1))
+    }
+
+    // Textura pra substituir uma existente com `replace_texture`, quando o upload dela
+    // terminar. `uploads` pode ser outra fila que não a do frame (ver assets.rs)
+    pub unsafe fn prepare_texture(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        texture: TextureData,
+    ) -> Result<PreparedTexture> {
+        texture.validate()?;
+        Ok(PreparedTexture(self.create_texture(
+            instance, device, data, uploads, texture,
+        )?))
+    }
+
+    // Troca a textura `id` pela preparada, cujo upload já terminou. Os materiais que usam
+    // ela passam a ver a nova a partir do próximo `refresh_sets` de cada frame
+    pub fn replace_texture(&mut self, id: MaterialTextureId, prepared: PreparedTexture) {
+        let mut texture = prepared.0;
+        texture.upload = None;
+
+        let old = std::mem::replace(&mut self.textures[id.0], texture);
+        self.texture_version += 1;
+        self.retired
+            .push((self.texture_version, Retired::Texture(old.texture)));
+    }
+
+    // Com streaming, só os mips pequenos vão pela fila de uploads
+    unsafe fn create_texture(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        texture_data: TextureData,
+    ) -> Result<MaterialTexture> {
+        let resident = if self.streaming {
+            (0..texture_data.levels.len() as u32)
+                .find(|level| {
+                    (texture_data.width.max(texture_data.height) >> level)
+                        <= STREAMING_RESIDENT_SIZE
+                })
+                .unwrap_or(texture_data.levels.len() as u32 - 1)
+        } else {
+            0
+        };
+
+        let mut texture = Texture::from_data(
+            instance,
+            device,
+            data,
+            &texture_data,
+            vk::Filter::LINEAR,
+            &data.transfer_families,
+        )?;
+        let upload = texture.enqueue_from(uploads, &texture_data, resident);
+
+        let stream = if resident > 0 {
+            texture.clamp_lod(device, resident)?;
+            Some(StreamState {
+                resident,
+                wanted: resident,
+                priority: 0.0,
+                prepared: false,
+            })
+        } else {
+            None
+        };
+
+        Ok(MaterialTexture {
+            texture,
+            upload: Some(upload),
+            data: texture_data,
+            stream,
+        })
+    }
+
+    pub unsafe fn add(&mut self, device: &Device, material: Material) -> Result<MaterialId> {
+        if self.materials.len() as u32 == MAX_MATERIALS {
+            return Err(anyhow!("Material limit ({}) reached.", MAX_MATERIALS));
+        }
+
+        if let Some(texture) = material
+            .textures(&self.defaults)
+            .iter()
+            .find(|t| t.0 >= self.textures.len())
+        {
+            return Err(anyhow!("Material texture {:?} does not exist.", texture));
+        }
+
+        let sets = self.create_sets(device, self.materials.len(), &material)?;
+        self.materials.push(MaterialEntry { material, sets });
+
+        Ok(MaterialId(self.materials.len() - 1))
+    }
+
+    unsafe fn create_sets(
+        &self,
+        device: &Device,
+        index: usize,
+        material: &Material,
+    ) -> Result<Vec<vk::DescriptorSet>> {
+        if self.push_descriptors {
+            return Ok(vec![]);
+        }
+
+        let set_layouts = vec![self.set_layout; MAX_FRAMES_IN_FLIGHT];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(&set_layouts);
+        let sets = device.allocate_descriptor_sets(&info)?;
+
+        for (slot, set) in sets.iter().enumerate() {
+            self.write_set(device, *set, index, slot, material);
+        }
+
+        Ok(sets)
+    }
+
+    // O bloco do material fica na mesma posição em todos os buffers
+    fn params_info(&self, index: usize, slot: usize) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo::builder()
+            .buffer(self.buffers[slot].buffer)
+            .offset(index as vk::DeviceSize * self.stride)
+            .range(size_of::<MaterialParams>() as vk::DeviceSize)
+            .build()
+    }
+
+    fn texture_infos(&self, material: &Material) -> Vec<vk::DescriptorImageInfo> {
+        material
+            .textures(&self.defaults)
+            .iter()
+            .map(|id| {
+                let texture = &self.textures[id.0].texture;
+                vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(texture.view)
+                    .sampler(texture.sampler)
+                    .build()
+            })
+            .collect()
+    }
+
+    // Escreve o set inteiro (parâmetros e texturas): com o template numa chamada só, sem ele
+    // numa só `vkUpdateDescriptorSets` com uma escrita por binding
+    unsafe fn write_set(
+        &self,
+        device: &Device,
+        set: vk::DescriptorSet,
+        index: usize,
+        slot: usize,
+        material: &Material,
+    ) {
+        let buffer_info = self.params_info(index, slot);
+        let image_infos = self.texture_infos(material);
+
+        if !self.template.is_null() {
+            let mut descriptors = SetDescriptors {
+                params: buffer_info,
+                textures: Default::default(),
+            };
+            descriptors.textures.copy_from_slice(&image_infos);
+            device.update_descriptor_set_with_template_khr(
+                set,
+                self.template,
+                &*(&descriptors as *const SetDescriptors as *const c_void),
+            );
+            return;
+        }
+
+        let mut writes = vec![vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(slice::from_ref(&buffer_info))
+            .build()];
+        for (binding, image_info) in image_infos.iter().enumerate() {
+            writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(binding as u32 + 1)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(slice::from_ref(image_info))
+                    .build(),
+            );
+        }
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    // Reescreve as texturas dos sets do frame `slot` se algum sampler mudou desde a última
+    // vez. A fence do frame já precisa ter sido esperada
+    pub unsafe fn refresh_sets(&mut self, device: &Device, slot: usize) {
+        if self.written[slot] == self.texture_version {
+            return;
+        }
+
+        // Com push descriptors o próximo `bind` já pega as texturas novas
+        if !self.push_descriptors {
+            let start = Instant::now();
+            for (index, entry) in self.materials.iter().enumerate() {
+                self.write_set(device, entry.sets[slot], index, slot, &entry.material);
+            }
+            debug!(
+                "Rewrote {} material sets in {:?} ({}).",
+                self.materials.len(),
+                start.elapsed(),
+                if self.template.is_null() {
+                    "vkUpdateDescriptorSets"
+                } else {
+                    "update template"
+                },
+            );
+        }
+        self.written[slot] = self.texture_version;
+
+        let oldest = *self.written.iter().min().unwrap();
+        let (unused, retired) = self
+            .retired
+            .drain(..)
+            .partition::<Vec<_>, _>(|(version, _)| *version <= oldest);
+        self.retired = retired;
+        unused.into_iter().for_each(|(_, r)| r.destroy(device));
+    }
+
+    // O material aparece com `pixels` de altura na tela nesse frame. Pede o nível em que
+    // cada textura dele tem mais ou menos um texel por pixel
+    pub fn request_detail(&mut self, id: MaterialId, pixels: f32) {
+        let material = match self.materials.get(id.0) {
+            Some(entry) => &entry.material,
+            None => return,
+        };
+
+        for texture_id in material.textures(&self.defaults) {
+            let texture = &mut self.textures[texture_id.0];
+            if let Some(stream) = &mut texture.stream {
+                let ratio = texture.data.height as f32 / pixels.max(1.0);
+                let level = ratio.log2().floor().max(0.0) as u32;
+                stream.wanted = stream.wanted.min(level);
+                stream.priority = stream.priority.max(pixels);
+            }
+        }
+    }
+
+    // Junta os pedidos do frame e zera eles pro próximo
+    pub fn take_stream_work(&mut self) -> StreamWork {
+        let mut work = StreamWork::default();
+        let mut levels = vec![];
+
+        for (index, texture) in self.textures.iter_mut().enumerate() {
+            if let Some(stream) = &mut texture.stream {
+                if !stream.prepared {
+                    work.prepare.push(MaterialTextureId(index));
+                } else if stream.wanted < stream.resident {
+                    levels.push((
+                        stream.priority,
+                        MaterialTextureId(index),
+                        stream.resident - 1,
+                    ));
+                }
+
+                stream.wanted = stream.resident;
+                stream.priority = 0.0;
+            }
+        }
+
+        levels.sort_by(|a, b| b.0.total_cmp(&a.0));
+        work.levels = levels
+            .into_iter()
+            .map(|(_, id, level)| (id, level))
+            .collect();
+        work
+    }
+
+    pub fn texture(&self, id: MaterialTextureId) -> &Texture {
+        &self.textures[id.0].texture
+    }
+
+    pub fn texture_level(&self, id: MaterialTextureId, level: u32) -> &[u8] {
+        &self.textures[id.0].data.levels[level as usize]
+    }
+
+    pub fn resident_level(&self, id: MaterialTextureId) -> u32 {
+        self.textures[id.0].stream.map_or(0, |s| s.resident)
+    }
+
+    // `image` é a que o streaming usou; se a textura foi substituída nesse meio tempo, o
+    // aviso é ignorado
+    pub fn mark_prepared(&mut self, id: MaterialTextureId, image: vk::Image) {
+        let texture = &mut self.textures[id.0];
+        if texture.texture.image != image {
+            return;
+        }
+
+        if let Some(stream) = &mut texture.stream {
+            stream.prepared = true;
+        }
+    }
+
+    // `level` já está na GPU: o sampler passa a descer até ele
+    pub unsafe fn level_arrived(
+        &mut self,
+        device: &Device,
+        id: MaterialTextureId,
+        image: vk::Image,
+        level: u32,
+    ) -> Result<()> {
+        let texture = &mut self.textures[id.0];
+        if texture.texture.image != image {
+            return Ok(());
+        }
+
+        if let Some(stream) = &mut texture.stream {
+            stream.resident = stream.resident.min(level);
+            texture.texture.clamp_lod(device, stream.resident)?;
+            self.texture_version += 1;
+        }
+
+        Ok(())
+    }
+
+    // Pega os samplers de novo no cache depois de mudar a anisotropia (ver sampler.rs)
+    pub unsafe fn update_samplers(&mut self, device: &Device) -> Result<()> {
+        for texture in &mut self.textures {
+            texture.texture.update_sampler(device)?;
+        }
+        self.texture_version += 1;
+        Ok(())
+    }
+
+    pub fn get(&self, id: MaterialId) -> Option<&Material> {
+        self.materials.get(id.0).map(|m| &m.material)
+    }
+
+    // Parâmetros e variante valem a partir do próximo frame
+    pub fn params_mut(&mut self, id: MaterialId) -> Option<&mut MaterialParams> {
+        self.materials.get_mut(id.0).map(|m| &mut m.material.params)
+    }
+
+    pub fn set_variant(&mut self, id: MaterialId, variant: ShaderVariant) {
+        if let Some(entry) = self.materials.get_mut(id.0) {
+            entry.material.variant = variant;
+        }
+    }
+
+    pub fn set_blend(&mut self, id: MaterialId, blend: BlendMode) {
+        if let Some(entry) = self.materials.get_mut(id.0) {
+            entry.material.blend = blend;
+        }
+    }
+
+    pub fn is_transparent(&self, id: MaterialId) -> bool {
+        self.get(id).is_some_and(|m| m.blend.is_transparent())
+    }
+
+    // Pronto pra desenhar quando todas as texturas já chegaram na GPU (com streaming, os
+    // mips pequenos)
+    pub fn is_ready(&self, id: MaterialId, uploads: &UploadQueue) -> bool {
+        self.materials.get(id.0).is_some_and(|entry| {
+            entry.material.textures(&self.defaults).iter().all(|t| {
+                let texture = &self.textures[t.0];
+                texture.upload.is_none_or(|u| !uploads.is_pending(u))
+                    && texture.stream.is_none_or(|s| s.prepared)
+            })
+        })
+    }
+
+    // Copia os parâmetros de todos os materiais pro buffer do frame `slot`. A fence do
+    // frame já precisa ter sido esperada
+    pub unsafe fn write(&self, slot: usize) {
+        let mapped = self.buffers[slot].mapped;
+        for (index, entry) in self.materials.iter().enumerate() {
+            ptr::copy_nonoverlapping(
+                &entry.material.params as *const MaterialParams as *const u8,
+                mapped.add(index * self.stride as usize),
+                size_of::<MaterialParams>(),
+            );
+        }
+    }
+
+    pub fn set(&self, id: MaterialId, slot: usize) -> vk::DescriptorSet {
+        self.materials[id.0].sets[slot]
+    }
+
+    // Liga o material no set 1 da pipeline, pro frame `slot`: o set dele ou, com push
+    // descriptors, os descritores gravados na hora
+    pub unsafe fn bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::PipelineLayout,
+        id: MaterialId,
+        slot: usize,
+    ) {
+        if !self.push_descriptors {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                layout,
+                1,
+                &[self.set(id, slot)],
+                &[],
+            );
+            return;
+        }
+
+        let buffer_info = &[self.params_info(id.0, slot)];
+        let image_infos = self.texture_infos(&self.materials[id.0].material);
+
+        let mut writes = vec![vk::WriteDescriptorSet::builder()
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(buffer_info)
+            .build()];
+        for (binding, image_info) in image_infos.iter().enumerate() {
+            writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_binding(binding as u32 + 1)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(slice::from_ref(image_info))
+                    .build(),
+            );
+        }
+
+        device.cmd_push_descriptor_set_khr(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            layout,
+            1,
+            &writes,
+        );
+    }
+
+    // Materiais e pixels continuam na CPU, pra `create_device_objects` recriar
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for buffer in self.buffers.drain(..) {
+            device.unmap_memory(buffer.memory);
+            leaks::destroy_buffer(device, buffer.buffer);
+            allocator::free(device, buffer.memory);
+        }
+
+        self.textures.iter().for_each(|t| t.texture.destroy(device));
+        self.retired.drain(..).for_each(|(_, r)| r.destroy(device));
+        self.materials.iter_mut().for_each(|m| m.sets.clear());
+        if !self.template.is_null() {
+            device.destroy_descriptor_update_template_khr(self.template, None);
+        }
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
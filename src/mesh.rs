@@ -0,0 +1,1679 @@
+use std::{collections::HashMap, mem::size_of, path::Path, slice};
+
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator,
+    animation::{Skinning, VertexSkin},
+    app::AppData,
+    buffer::create_buffer_shared,
+    camera::{Camera, Frustum},
+    config::{DebugView, RenderPath},
+    features::Feature,
+    leaks,
+    material::{Materials, ShaderVariant},
+    occlusion::OcclusionCulling,
+    pass::{GBUFFER_FORMATS, SCENE_TRANSFER},
+    pipeline::{BlendMode, Pipeline, PipelineDesc},
+    scene::DrawItem,
+    shader::spirv,
+    upload::{UploadId, UploadQueue, UploadTarget},
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+// Quanto a forma pode mudar por nível gerado, relativo ao tamanho da malha
+const LOD_TARGET_ERROR: f32 = 0.02;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Vertex {
+    pub position: glm::Vec3,
+    pub normal: glm::Vec3,
+    pub uv: glm::Vec2,
+    // xyz: tangente, w: sinal da bitangente (bitangente = cross(normal, tangente) * w)
+    pub tangent: glm::Vec4,
+}
+
+impl Vertex {
+    // A tangente fica zerada até `MeshData::generate_tangents`
+    pub fn new(position: glm::Vec3, normal: glm::Vec3, uv: glm::Vec2) -> Self {
+        Self {
+            position,
+            normal,
+            uv,
+            tangent: glm::Vec4::zeros(),
+        }
+    }
+
+    pub fn binding_descriptions() -> [vk::VertexInputBindingDescription; 1] {
+        [vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Vertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()]
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 4] {
+        let vec3 = size_of::<glm::Vec3>() as u32;
+        let vec2 = size_of::<glm::Vec2>() as u32;
+        // (location, formato, offset) de cada campo
+        let fields = [
+            (0, vk::Format::R32G32B32_SFLOAT, 0),
+            (1, vk::Format::R32G32B32_SFLOAT, vec3),
+            (2, vk::Format::R32G32_SFLOAT, 2 * vec3),
+            (3, vk::Format::R32G32B32A32_SFLOAT, 2 * vec3 + vec2),
+        ];
+
+        fields.map(|(location, format, offset)| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(location)
+                .format(format)
+                .offset(offset)
+                .build()
+        })
+    }
+}
+
+// Malha na CPU, triângulos indexados com faces em sentido anti-horário (igual ao OBJ)
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    // Cubo centrado na origem, com normais retas em cada face
+    pub fn cube(size: f32) -> Self {
+        let half = size / 2.0;
+        // (normal, eixo u, eixo v) de cada face, com u x v = normal
+        let faces = [
+            (
+                glm::vec3(1.0, 0.0, 0.0),
+                glm::vec3(0.0, 0.0, -1.0),
+                glm::vec3(0.0, 1.0, 0.0),
+            ),
+            (
+                glm::vec3(-1.0, 0.0, 0.0),
+                glm::vec3(0.0, 0.0, 1.0),
+                glm::vec3(0.0, 1.0, 0.0),
+            ),
+            (
+                glm::vec3(0.0, 1.0, 0.0),
+                glm::vec3(1.0, 0.0, 0.0),
+                glm::vec3(0.0, 0.0, -1.0),
+            ),
+            (
+                glm::vec3(0.0, -1.0, 0.0),
+                glm::vec3(1.0, 0.0, 0.0),
+                glm::vec3(0.0, 0.0, 1.0),
+            ),
+            (
+                glm::vec3(0.0, 0.0, 1.0),
+                glm::vec3(1.0, 0.0, 0.0),
+                glm::vec3(0.0, 1.0, 0.0),
+            ),
+            (
+                glm::vec3(0.0, 0.0, -1.0),
+                glm::vec3(-1.0, 0.0, 0.0),
+                glm::vec3(0.0, 1.0, 0.0),
+            ),
+        ];
+
+        let mut mesh = Self::default();
+        for (normal, u, v) in faces {
+            let first = mesh.vertices.len() as u32;
+            for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let position = (normal + u * x + v * y) * half;
+                let uv = glm::vec2((x + 1.0) / 2.0, (1.0 - y) / 2.0);
+                // O u cresce junto com o eixo u, e o v (pra cima) com o eixo v
+                let mut vertex = Vertex::new(position, normal, uv);
+                vertex.tangent = glm::vec4(u.x, u.y, u.z, 1.0);
+                mesh.vertices.push(vertex);
+            }
+            mesh.indices
+                .extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+
+        mesh
+    }
+
+    // Uma malha por objeto do arquivo. Materiais são ignorados por enquanto
+    pub fn load_obj(path: &Path) -> Result<Vec<(String, MeshData)>> {
+        let options = tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        };
+        let (models, _) = tobj::load_obj(path, &options)
+            .map_err(|e| anyhow!("Failed to load '{}': {}", path.display(), e))?;
+
+        let meshes = models
+            .into_iter()
+            .filter(|m| !m.mesh.indices.is_empty())
+            .map(|model| {
+                let mesh = &model.mesh;
+                let has_normals = mesh.normals.len() == mesh.positions.len();
+                let has_uvs = mesh.texcoords.len() / 2 == mesh.positions.len() / 3;
+
+                let vertices = (0..mesh.positions.len() / 3)
+                    .map(|i| {
+                        let position = glm::make_vec3(&mesh.positions[i * 3..i * 3 + 3]);
+                        let normal = if has_normals {
+                            glm::make_vec3(&mesh.normals[i * 3..i * 3 + 3])
+                        } else {
+                            glm::Vec3::zeros()
+                        };
+                        // O v do OBJ cresce pra cima, o das texturas pra baixo
+                        let uv = if has_uvs {
+                            glm::vec2(mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1])
+                        } else {
+                            glm::Vec2::zeros()
+                        };
+                        Vertex::new(position, normal, uv)
+                    })
+                    .collect();
+
+                let mut data = MeshData {
+                    vertices,
+                    indices: mesh.indices.clone(),
+                };
+                if !has_normals {
+                    data.compute_normals();
+                }
+                if !has_uvs || !data.generate_tangents() {
+                    data.fallback_tangents();
+                }
+
+                (model.name, data)
+            })
+            .collect::<Vec<_>>();
+
+        if meshes.is_empty() {
+            return Err(anyhow!("'{}' has no triangles.", path.display()));
+        }
+
+        Ok(meshes)
+    }
+
+    // Junta `other` no fim, como se fosse uma malha só
+    pub fn append(&mut self, other: &MeshData) {
+        let first = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&other.vertices);
+        self.indices.extend(other.indices.iter().map(|i| i + first));
+    }
+
+    // Normais suaves: média das normais das faces que usam cada vértice, pesada pela área
+    pub fn compute_normals(&mut self) {
+        self.vertices
+            .iter_mut()
+            .for_each(|v| v.normal = glm::Vec3::zeros());
+
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+            let edge1 = self.vertices[b].position - self.vertices[a].position;
+            let edge2 = self.vertices[c].position - self.vertices[a].position;
+            // O tamanho do produto vetorial já é o dobro da área
+            let normal = edge1.cross(&edge2);
+
+            for index in [a, b, c] {
+                self.vertices[index].normal += normal;
+            }
+        }
+
+        for vertex in &mut self.vertices {
+            if vertex.normal.norm_squared() > 0.0 {
+                vertex.normal = vertex.normal.normalize();
+            }
+        }
+    }
+
+    // Tangentes do MikkTSpace, o mesmo padrão que os bakers de normal map usam. Os
+    // vértices são separados por triângulo, geradas, e depois juntados de novo onde tudo
+    // (inclusive a tangente) bate. Retorna falso se a geração falhou
+    pub fn generate_tangents(&mut self) -> bool {
+        let mut corners = Corners {
+            vertices: self
+                .indices
+                .iter()
+                .map(|i| self.vertices[*i as usize])
+                .collect(),
+        };
+
+        if !mikktspace::generate_tangents(&mut corners) {
+            return false;
+        }
+
+        let mut welded = HashMap::new();
+        self.vertices.clear();
+        self.indices.clear();
+        for vertex in corners.vertices {
+            let key = vertex_key(&vertex);
+            let vertices = &mut self.vertices;
+            let index = *welded.entry(key).or_insert_with(|| {
+                vertices.push(vertex);
+                vertices.len() as u32 - 1
+            });
+            self.indices.push(index);
+        }
+
+        true
+    }
+
+    // Qualquer tangente perpendicular à normal, pra malhas sem uv (o normal map não faz
+    // sentido nelas, mas a shader precisa de uma base válida)
+    pub fn fallback_tangents(&mut self) {
+        for vertex in &mut self.vertices {
+            let n = vertex.normal;
+            let axis = if n.x.abs() < 0.9 {
+                glm::vec3(1.0, 0.0, 0.0)
+            } else {
+                glm::vec3(0.0, 1.0, 0.0)
+            };
+            let t = (axis - n * n.dot(&axis)).normalize();
+            vertex.tangent = glm::vec4(t.x, t.y, t.z, 1.0);
+        }
+    }
+
+    // Caixa alinhada aos eixos (mínimo, máximo)
+    pub fn bounds(&self) -> (glm::Vec3, glm::Vec3) {
+        let first = self
+            .vertices
+            .first()
+            .map_or(glm::Vec3::zeros(), |v| v.position);
+        self.vertices.iter().fold((first, first), |(min, max), v| {
+            (min.inf(&v.position), max.sup(&v.position))
+        })
+    }
+
+    // Versão com uns `ratio` dos triângulos, pelo simplificador do meshoptimizer. Ele para
+    // antes se o erro da forma passar de `target_error` (fração do tamanho da malha), então
+    // pode sobrar mais. Os vértices que ninguém usa mais são tirados
+    pub fn simplify(&self, ratio: f32, target_error: f32) -> Result<MeshData> {
+        let adapter = meshopt::VertexDataAdapter::new(
+            meshopt::typed_to_bytes(&self.vertices),
+            size_of::<Vertex>(),
+            0,
+        )
+        .map_err(|e| anyhow!("{:?}", e))?;
+        let target = (self.indices.len() / 3) as f32 * ratio.clamp(0.0, 1.0);
+        let indices = meshopt::simplify(&self.indices, &adapter, target as usize * 3, target_error);
+
+        let mut remap = HashMap::new();
+        let mut simplified = MeshData::default();
+        for index in indices {
+            let vertices = &mut simplified.vertices;
+            let new_index = *remap.entry(index).or_insert_with(|| {
+                vertices.push(self.vertices[index as usize]);
+                vertices.len() as u32 - 1
+            });
+            simplified.indices.push(new_index);
+        }
+
+        Ok(simplified)
+    }
+
+    // Até `levels` níveis, cada um com metade dos triângulos do anterior, trocados quando a
+    // malha fica menor que 1/4, 1/8... da altura da tela. Para antes se o simplificador não
+    // conseguir mais reduzir
+    pub fn generate_lods(&self, levels: usize) -> Result<Vec<MeshLod>> {
+        let mut lods: Vec<MeshLod> = vec![];
+        for level in 1..=levels as i32 {
+            // Sempre a partir da original, pro erro não acumular
+            let mesh = self.simplify(0.5f32.powi(level), LOD_TARGET_ERROR * level as f32)?;
+            let previous = lods
+                .last()
+                .map_or(self.indices.len(), |lod| lod.mesh.indices.len());
+            if mesh.indices.is_empty() || mesh.indices.len() as f32 > previous as f32 * 0.9 {
+                break;
+            }
+
+            lods.push(MeshLod {
+                mesh,
+                screen_size: 0.5f32.powi(level + 1),
+            });
+        }
+
+        Ok(lods)
+    }
+}
+
+// Versão mais simples de uma malha, usada quando ela ocupa menos que `screen_size` da
+// altura da tela. Feita à mão ou por `MeshData::generate_lods`
+#[derive(Clone, Debug, Default)]
+pub struct MeshLod {
+    pub mesh: MeshData,
+    pub screen_size: f32,
+}
+
+// Onde cada nível ficou nos buffers da malha (todos juntos, o 0 é a original)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LodRange {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+    pub screen_size: f32,
+}
+
+// Triângulos soltos, um vértice por canto, no formato que o mikktspace lê
+struct Corners {
+    vertices: Vec<Vertex>,
+}
+
+impl mikktspace::Geometry for Corners {
+    fn num_faces(&self) -> usize {
+        self.vertices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertices[face * 3 + vert].position.into()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertices[face * 3 + vert].normal.into()
+    }
+
+    // O mikktspace (e os normal maps) contam o v pra cima; o nosso cresce pra baixo
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        let uv = self.vertices[face * 3 + vert].uv;
+        [uv.x, 1.0 - uv.y]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.vertices[face * 3 + vert].tangent = glm::make_vec4(&tangent);
+    }
+}
+
+// Os bits de todos os campos, pra juntar vértices idênticos
+fn vertex_key(vertex: &Vertex) -> [u32; 12] {
+    let mut key = [0; 12];
+    let fields = vertex
+        .position
+        .iter()
+        .chain(vertex.normal.iter())
+        .chain(vertex.uv.iter())
+        .chain(vertex.tangent.iter());
+    for (slot, value) in key.iter_mut().zip(fields) {
+        *slot = value.to_bits();
+    }
+    key
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MeshId(usize);
+
+#[derive(Debug)]
+struct GpuMesh {
+    vertex_buffer: vk::Buffer,
+    vertex_memory: vk::DeviceMemory,
+    index_buffer: vk::Buffer,
+    index_memory: vk::DeviceMemory,
+    // Do mais detalhado pro menos
+    lods: Vec<LodRange>,
+    // Caixa da malha no espaço local, pro frustum culling
+    bounds: (glm::Vec3, glm::Vec3),
+    // Pesos das juntas (binding 1), só nas malhas com skinning
+    skin_buffer: vk::Buffer,
+    skin_memory: vk::DeviceMemory,
+    uploads: Vec<UploadId>,
+    // Mantidos na CPU pra reenviar se o dispositivo for recriado
+    data: MeshData,
+    lod_data: Vec<MeshLod>,
+    skin: Vec<VertexSkin>,
+}
+
+impl GpuMesh {
+    unsafe fn destroy(&self, device: &Device) {
+        leaks::destroy_buffer(device, self.vertex_buffer);
+        allocator::free(device, self.vertex_memory);
+        leaks::destroy_buffer(device, self.index_buffer);
+        allocator::free(device, self.index_memory);
+        leaks::destroy_buffer(device, self.skin_buffer);
+        allocator::free(device, self.skin_memory);
+    }
+}
+
+// Malha criada e com os uploads na fila, mas que ainda não substituiu nenhuma (ver
+// `MeshRenderer::replace`)
+#[derive(Debug)]
+pub struct PreparedMesh(GpuMesh);
+
+impl PreparedMesh {
+    pub fn uploads(&self) -> &[UploadId] {
+        &self.0.uploads
+    }
+
+    // Destrói os buffers e devolve os dados, pra preparar de novo depois
+    pub unsafe fn destroy(self, device: &Device) -> (MeshData, Vec<MeshLod>) {
+        self.0.destroy(device);
+        (self.0.data, self.0.lod_data)
+    }
+}
+
+// Como os triângulos viram pixels; uma pipeline de cada por variante
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum RasterMode {
+    Fill,
+    // Só as arestas, com o fillModeNonSolid
+    Wireframe,
+    // Sem depth e com blend aditivo, pra contar quantas vezes cada pixel foi pintado
+    Overdraw,
+}
+
+// As do pre-pass de depth, só com a vertex de cada caminho e nada na cor
+#[derive(Debug, Default)]
+struct PrepassPipelines {
+    direct: Pipeline,
+    skinned: Pipeline,
+    indirect: Pipeline,
+}
+
+// Guarda as malhas na GPU e desenha a lista achatada que sai da cena
+#[derive(Debug)]
+pub struct MeshRenderer {
+    meshes: Vec<GpuMesh>,
+    // Substituídas com `replace`, com quantos frames ainda podem usar cada uma
+    retired: Vec<(usize, GpuMesh)>,
+    // Uma por variante de shader dos materiais e modo de rasterização (o wireframe só
+    // quando o dispositivo tem o fillModeNonSolid)
+    pipelines: HashMap<(ShaderVariant, RasterMode), Pipeline>,
+    // As mesmas, com a vertex deformando pelas juntas do animador
+    skinned_pipelines: HashMap<(ShaderVariant, RasterMode), Pipeline>,
+    // E com a matriz de mundo vindo das instâncias do occlusion culling
+    indirect_pipelines: HashMap<(ShaderVariant, RasterMode), Pipeline>,
+    // Só quando o render pass tem o pre-pass de depth
+    prepass: Option<PrepassPipelines>,
+    // Materiais com blend, no render pass das transparentes: por variante, modo de blend
+    // e com ou sem skinning
+    transparent_pipelines: HashMap<(ShaderVariant, BlendMode, bool), Pipeline>,
+    // O que as shaders escrevem no lugar da cor (ver debug.glsl)
+    pub debug_view: DebugView,
+    // Só as arestas dos triângulos. Fica false se o dispositivo não suporta (ver
+    // `set_wireframe`)
+    wireframe: bool,
+    // Tira da lista o que está fora da câmera antes de gravar
+    pub frustum_culling: bool,
+    // Câmera do frame, pra escolher o nível de detalhe de cada instância
+    camera: Camera,
+}
+
+impl MeshRenderer {
+    pub unsafe fn create(
+        device: &Device,
+        data: &AppData,
+        materials: &Materials,
+        skinning: &Skinning,
+        occlusion: &OcclusionCulling,
+    ) -> Result<Self> {
+        let mut renderer = Self {
+            meshes: vec![],
+            retired: vec![],
+            pipelines: HashMap::new(),
+            skinned_pipelines: HashMap::new(),
+            indirect_pipelines: HashMap::new(),
+            prepass: None,
+            transparent_pipelines: HashMap::new(),
+            debug_view: DebugView::Final,
+            wireframe: false,
+            frustum_culling: true,
+            camera: Camera::default(),
+        };
+
+        renderer.create_pipeline(device, data, materials, skinning, occlusion)?;
+        Ok(renderer)
+    }
+
+    // Reenvia as malhas que já existiam depois de perder o dispositivo. Os ids continuam
+    // valendo
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+    ) -> Result<()> {
+        for index in 0..self.meshes.len() {
+            let mesh = std::mem::take(&mut self.meshes[index].data);
+            let lods = std::mem::take(&mut self.meshes[index].lod_data);
+            let skin = std::mem::take(&mut self.meshes[index].skin);
+            self.meshes[index] =
+                Self::create_mesh(instance, device, data, uploads, mesh, lods, skin)?;
+        }
+
+        Ok(())
+    }
+
+    // Dependem do render pass, então são recriadas junto com a swapchain
+    pub unsafe fn create_pipeline(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        materials: &Materials,
+        skinning: &Skinning,
+        occlusion: &OcclusionCulling,
+    ) -> Result<()> {
+        let vertex_shader = spirv!("mesh.vert");
+        let skinned_shader = spirv!("skinned.vert");
+        let indirect_shader = spirv!("mesh_indirect.vert");
+
+        let bindings = Vertex::binding_descriptions();
+        let attributes = Vertex::attribute_descriptions();
+        let skinned_bindings = [bindings[0], VertexSkin::binding_description()];
+        let skinned_attributes = [
+            attributes.as_slice(),
+            VertexSkin::attribute_descriptions().as_slice(),
+        ]
+        .concat();
+
+        // Matriz de mundo na vertex; função de transferência, a visão de debug e o
+        // modelo de shading (pro G-buffer) na fragment
+        let push_constants = &[
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size(size_of::<glm::Mat4>() as u32)
+                .build(),
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(size_of::<glm::Mat4>() as u32)
+                .size(3 * size_of::<u32>() as u32)
+                .build(),
+        ];
+
+        // Frame no set 0, material no set 1 e as juntas (ou as instâncias) no 2
+        let set_layouts = &[data.frame_descriptors.layout, materials.set_layout];
+        let skinned_set_layouts = &[
+            data.frame_descriptors.layout,
+            materials.set_layout,
+            skinning.layout,
+        ];
+        let indirect_set_layouts = &[
+            data.frame_descriptors.layout,
+            materials.set_layout,
+            occlusion.layout,
+        ];
+
+        // No deferred todas as variantes escrevem o G-buffer com a mesma shader, e o
+        // modelo de shading só é usado na resolução
+        let gbuffer_shader = spirv!("gbuffer.frag");
+        let overdraw_shader = spirv!("overdraw.frag");
+
+        // O dispositivo pode ter mudado depois de um DEVICE_LOST
+        let wireframe_supported = data.capabilities.has(Feature::FillModeNonSolid);
+        self.wireframe &= wireframe_supported;
+
+        let modes: &[RasterMode] = if wireframe_supported {
+            &[
+                RasterMode::Fill,
+                RasterMode::Wireframe,
+                RasterMode::Overdraw,
+            ]
+        } else {
+            &[RasterMode::Fill, RasterMode::Overdraw]
+        };
+        for (variant, &mode) in ShaderVariant::ALL
+            .iter()
+            .flat_map(|v| modes.iter().map(move |m| (*v, m)))
+        {
+            let fragment_shader = match (mode, data.render_pass.path) {
+                (RasterMode::Overdraw, _) => &overdraw_shader[..],
+                (_, RenderPath::Forward) => variant.fragment_shader(),
+                (_, RenderPath::Deferred) => &gbuffer_shader[..],
+            };
+
+            let mut desc =
+                PipelineDesc::new(&vertex_shader[..], fragment_shader, data.render_pass.pass);
+            desc.bindings = &bindings;
+            desc.attributes = &attributes;
+            desc.set_layouts = set_layouts;
+            desc.push_constants = push_constants;
+            // As malhas vêm em sentido anti-horário, e o y invertido da projeção mantém
+            // isso na tela
+            desc.front_face = vk::FrontFace::COUNTER_CLOCKWISE;
+            if data.config.reverse_z {
+                desc.depth_compare = vk::CompareOp::GREATER;
+            }
+            if data.render_pass.path == RenderPath::Deferred {
+                desc.color_attachments = GBUFFER_FORMATS.len() as u32;
+            }
+            match mode {
+                // O pre-pass já deixou o depth de cada pixel: só o que está na frente passa,
+                // e com o mesmo valor
+                RasterMode::Fill if data.render_pass.has_prepass() => {
+                    desc.depth_compare = vk::CompareOp::EQUAL;
+                    desc.depth_write = false;
+                }
+                RasterMode::Fill => {}
+                RasterMode::Wireframe => desc.polygon_mode = vk::PolygonMode::LINE,
+                RasterMode::Overdraw => {
+                    desc.depth_test = false;
+                    desc.depth_write = false;
+                    desc.blend = BlendMode::Additive;
+                }
+            }
+
+            self.pipelines
+                .insert((variant, mode), Pipeline::create(device, &desc)?);
+
+            desc.vertex_shader = &indirect_shader[..];
+            desc.set_layouts = indirect_set_layouts;
+            self.indirect_pipelines
+                .insert((variant, mode), Pipeline::create(device, &desc)?);
+
+            desc.vertex_shader = &skinned_shader[..];
+            desc.bindings = &skinned_bindings;
+            desc.attributes = &skinned_attributes;
+            desc.set_layouts = skinned_set_layouts;
+            self.skinned_pipelines
+                .insert((variant, mode), Pipeline::create(device, &desc)?);
+        }
+
+        // As mesmas vertex shaders, pra chegar exatamente no mesmo depth (elas declaram o
+        // gl_Position invariant), e nenhuma cor
+        if data.render_pass.has_prepass() {
+            let depth_shader = spirv!("shadow.frag");
+            let mut desc = PipelineDesc::new(
+                &vertex_shader[..],
+                &depth_shader[..],
+                data.render_pass.prepass,
+            );
+            desc.bindings = &bindings;
+            desc.attributes = &attributes;
+            desc.set_layouts = set_layouts;
+            desc.push_constants = &push_constants[..1];
+            desc.front_face = vk::FrontFace::COUNTER_CLOCKWISE;
+            if data.config.reverse_z {
+                desc.depth_compare = vk::CompareOp::GREATER;
+            }
+            desc.color_attachments = 0;
+            let direct = Pipeline::create(device, &desc)?;
+
+            desc.vertex_shader = &indirect_shader[..];
+            desc.set_layouts = indirect_set_layouts;
+            let indirect = Pipeline::create(device, &desc)?;
+
+            desc.vertex_shader = &skinned_shader[..];
+            desc.bindings = &skinned_bindings;
+            desc.attributes = &skinned_attributes;
+            desc.set_layouts = skinned_set_layouts;
+            let skinned = Pipeline::create(device, &desc)?;
+
+            self.prepass = Some(PrepassPipelines {
+                direct,
+                skinned,
+                indirect,
+            });
+        }
+
+        // Sempre com a fragment forward da variante, mesmo no deferred, e testando o depth
+        // da cena sem escrever nele
+        let blends = [
+            BlendMode::Alpha,
+            BlendMode::Premultiplied,
+            BlendMode::Additive,
+            BlendMode::Multiply,
+        ];
+        for (variant, blend) in ShaderVariant::ALL
+            .iter()
+            .flat_map(|v| blends.iter().map(move |b| (*v, *b)))
+        {
+            let mut desc = PipelineDesc::new(
+                &vertex_shader[..],
+                variant.fragment_shader(),
+                data.render_pass.transparent,
+            );
+            desc.bindings = &bindings;
+            desc.attributes = &attributes;
+            desc.set_layouts = set_layouts;
+            desc.push_constants = push_constants;
+            desc.front_face = vk::FrontFace::COUNTER_CLOCKWISE;
+            if data.config.reverse_z {
+                desc.depth_compare = vk::CompareOp::GREATER;
+            }
+            desc.depth_write = false;
+            desc.blend = blend;
+            self.transparent_pipelines
+                .insert((variant, blend, false), Pipeline::create(device, &desc)?);
+
+            desc.vertex_shader = &skinned_shader[..];
+            desc.bindings = &skinned_bindings;
+            desc.attributes = &skinned_attributes;
+            desc.set_layouts = skinned_set_layouts;
+            self.transparent_pipelines
+                .insert((variant, blend, true), Pipeline::create(device, &desc)?);
+        }
+
+        Ok(())
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    // Retorna false (e continua preenchido) sem o fillModeNonSolid
+    pub fn set_wireframe(&mut self, enabled: bool) -> bool {
+        let supported = self
+            .pipelines
+            .keys()
+            .any(|(_, mode)| *mode == RasterMode::Wireframe);
+        self.wireframe = enabled && supported;
+        self.wireframe == enabled
+    }
+
+    // O overdraw passa por cima do wireframe: conta os pixels dos triângulos inteiros
+    fn raster_mode(&self) -> RasterMode {
+        if self.debug_view == DebugView::Overdraw {
+            RasterMode::Overdraw
+        } else if self.wireframe {
+            RasterMode::Wireframe
+        } else {
+            RasterMode::Fill
+        }
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipelines
+            .drain()
+            .chain(self.skinned_pipelines.drain())
+            .chain(self.indirect_pipelines.drain())
+            .for_each(|(_, pipeline)| pipeline.destroy(device));
+        self.transparent_pipelines
+            .drain()
+            .for_each(|(_, pipeline)| pipeline.destroy(device));
+        if let Some(prepass) = self.prepass.take() {
+            prepass.direct.destroy(device);
+            prepass.skinned.destroy(device);
+            prepass.indirect.destroy(device);
+        }
+    }
+
+    // `lods` em qualquer ordem; são guardados do maior `screen_size` pro menor
+    pub unsafe fn add(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        mesh: MeshData,
+        mut lods: Vec<MeshLod>,
+        skin: Vec<VertexSkin>,
+    ) -> Result<MeshId> {
+        Self::validate(&mesh, &lods, &skin)?;
+        lods.sort_by(|a, b| b.screen_size.total_cmp(&a.screen_size));
+        let mesh = Self::create_mesh(instance, device, data, uploads, mesh, lods, skin)?;
+        self.meshes.push(mesh);
+
+        Ok(MeshId(self.meshes.len() - 1))
+    }
+
+    // Malha pra substituir uma existente com `replace`, quando os uploads dela terminarem.
+    // `uploads` pode ser outra fila que não a do frame (ver assets.rs)
+    pub unsafe fn prepare(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        mesh: MeshData,
+        mut lods: Vec<MeshLod>,
+    ) -> Result<PreparedMesh> {
+        Self::validate(&mesh, &lods, &[])?;
+        lods.sort_by(|a, b| b.screen_size.total_cmp(&a.screen_size));
+        Ok(PreparedMesh(Self::create_mesh(
+            instance,
+            device,
+            data,
+            uploads,
+            mesh,
+            lods,
+            vec![],
+        )?))
+    }
+
+    // Troca a malha `id` pela preparada, cujos uploads já terminaram. A antiga ainda pode
+    // estar sendo lida pelos frames em voo, então só é destruída em `release_retired`
+    pub fn replace(&mut self, id: MeshId, prepared: PreparedMesh) {
+        let mut mesh = prepared.0;
+        mesh.uploads.clear();
+
+        let old = std::mem::replace(&mut self.meshes[id.0], mesh);
+        self.retired.push((MAX_FRAMES_IN_FLIGHT, old));
+    }
+
+    // Chamado uma vez por frame, depois de esperar a fence dele
+    pub unsafe fn release_retired(&mut self, device: &Device) {
+        for (frames, _) in &mut self.retired {
+            *frames -= 1;
+        }
+
+        let (done, retired) = self
+            .retired
+            .drain(..)
+            .partition::<Vec<_>, _>(|(frames, _)| *frames == 0);
+        self.retired = retired;
+        done.iter().for_each(|(_, mesh)| mesh.destroy(device));
+    }
+
+    fn validate(mesh: &MeshData, lods: &[MeshLod], skin: &[VertexSkin]) -> Result<()> {
+        if mesh.indices.is_empty() || lods.iter().any(|lod| lod.mesh.indices.is_empty()) {
+            return Err(anyhow!("Mesh has no triangles."));
+        }
+
+        // Os pesos só valem pros vértices da original
+        if !skin.is_empty() && !lods.is_empty() {
+            return Err(anyhow!("Skinned meshes don't support LODs."));
+        }
+
+        if !skin.is_empty() && skin.len() != mesh.vertices.len() {
+            return Err(anyhow!(
+                "Mesh skin has {} entries for {} vertices.",
+                skin.len(),
+                mesh.vertices.len()
+            ));
+        }
+
+        for mesh in std::iter::once(&mesh).chain(lods.iter().map(|lod| &lod.mesh)) {
+            if let Some(index) = mesh
+                .indices
+                .iter()
+                .find(|i| **i as usize >= mesh.vertices.len())
+            {
+                return Err(anyhow!(
+                    "Mesh index {} out of range ({} vertices).",
+                    index,
+                    mesh.vertices.len()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    unsafe fn create_mesh(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        mesh: MeshData,
+        lod_data: Vec<MeshLod>,
+        skin: Vec<VertexSkin>,
+    ) -> Result<GpuMesh> {
+        // Os níveis vão em seguida da original, nos mesmos buffers
+        let mut vertices = mesh.vertices.clone();
+        let mut indices = mesh.indices.clone();
+        let mut lods = vec![LodRange {
+            first_index: 0,
+            index_count: indices.len() as u32,
+            vertex_offset: 0,
+            screen_size: f32::INFINITY,
+        }];
+        for lod in &lod_data {
+            lods.push(LodRange {
+                first_index: indices.len() as u32,
+                index_count: lod.mesh.indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+                screen_size: lod.screen_size,
+            });
+            vertices.extend_from_slice(&lod.mesh.vertices);
+            indices.extend_from_slice(&lod.mesh.indices);
+        }
+
+        let vertex_bytes = slice::from_raw_parts(
+            vertices.as_ptr() as *const u8,
+            vertices.len() * size_of::<Vertex>(),
+        );
+        let index_bytes = slice::from_raw_parts(
+            indices.as_ptr() as *const u8,
+            indices.len() * size_of::<u32>(),
+        );
+
+        let (vertex_buffer, vertex_memory) = create_buffer_shared(
+            instance,
+            device,
+            data,
+            vertex_bytes.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &data.transfer_families,
+        )?;
+        let (index_buffer, index_memory) = create_buffer_shared(
+            instance,
+            device,
+            data,
+            index_bytes.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &data.transfer_families,
+        )?;
+
+        let mut ids = vec![
+            uploads.enqueue(
+                UploadTarget::Buffer {
+                    buffer: vertex_buffer,
+                    offset: 0,
+                },
+                vertex_bytes.to_vec(),
+            ),
+            uploads.enqueue(
+                UploadTarget::Buffer {
+                    buffer: index_buffer,
+                    offset: 0,
+                },
+                index_bytes.to_vec(),
+            ),
+        ];
+
+        let (skin_buffer, skin_memory) = if skin.is_empty() {
+            (vk::Buffer::null(), vk::DeviceMemory::null())
+        } else {
+            let skin_bytes = slice::from_raw_parts(
+                skin.as_ptr() as *const u8,
+                skin.len() * size_of::<VertexSkin>(),
+            );
+            let (buffer, memory) = create_buffer_shared(
+                instance,
+                device,
+                data,
+                skin_bytes.len() as vk::DeviceSize,
+                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                &data.transfer_families,
+            )?;
+            ids.push(uploads.enqueue(
+                UploadTarget::Buffer { buffer, offset: 0 },
+                skin_bytes.to_vec(),
+            ));
+            (buffer, memory)
+        };
+
+        Ok(GpuMesh {
+            vertex_buffer,
+            vertex_memory,
+            index_buffer,
+            index_memory,
+            lods,
+            bounds: mesh.bounds(),
+            skin_buffer,
+            skin_memory,
+            uploads: ids,
+            data: mesh,
+            lod_data,
+            skin,
+        })
+    }
+
+    pub fn mesh_data(&self, id: MeshId) -> Option<&MeshData> {
+        self.meshes.get(id.0).map(|m| &m.data)
+    }
+
+    // Caixa alinhada aos eixos (mínimo, máximo) da malha, no espaço local
+    pub fn bounds(&self, id: MeshId) -> Option<(glm::Vec3, glm::Vec3)> {
+        self.meshes.get(id.0).map(|m| m.bounds)
+    }
+
+    // Chamado todo frame antes de gravar
+    pub fn set_camera(&mut self, camera: &Camera) {
+        self.camera = *camera;
+    }
+
+    // Nível de detalhe que o item usa nesse frame, pelo tamanho na tela da esfera que
+    // envolve a caixa da malha
+    pub fn lod(&self, item: &DrawItem) -> Option<LodRange> {
+        let mesh = self.meshes.get(item.mesh.0)?;
+        if mesh.lods.len() == 1 {
+            return Some(mesh.lods[0]);
+        }
+
+        let size = self.screen_size(item)?;
+
+        // O último nível em que a malha ainda cabe
+        Some(
+            *mesh
+                .lods
+                .iter()
+                .take_while(|lod| size < lod.screen_size)
+                .last()
+                .unwrap_or(&mesh.lods[0]),
+        )
+    }
+
+    // Fração da altura da tela ocupada pela esfera que envolve a caixa da malha
+    pub fn screen_size(&self, item: &DrawItem) -> Option<f32> {
+        let mesh = self.meshes.get(item.mesh.0)?;
+        let (min, max) = mesh.bounds;
+        let center = (min + max) / 2.0;
+        let center = (item.world * glm::vec4(center.x, center.y, center.z, 1.0)).xyz();
+        let scale = (0..3)
+            .map(|i| glm::column(&item.world, i).xyz().norm())
+            .fold(0.0, f32::max);
+        let radius = glm::distance(&min, &max) / 2.0 * scale;
+
+        Some(self.camera.screen_size(&center, radius))
+    }
+
+    // Malha e texturas do material já chegaram na GPU
+    pub fn is_ready(&self, item: &DrawItem, uploads: &UploadQueue, materials: &Materials) -> bool {
+        self.meshes
+            .get(item.mesh.0)
+            .is_some_and(|m| m.uploads.iter().all(|id| !uploads.is_pending(*id)))
+            && materials.is_ready(item.material, uploads)
+    }
+
+    // Desenhado deformando pelas juntas do animador
+    pub fn is_skinned(&self, item: &DrawItem) -> bool {
+        item.animator.is_some()
+            && self
+                .meshes
+                .get(item.mesh.0)
+                .is_some_and(|m| !m.skin.is_empty())
+    }
+
+    // O que sobra da lista depois de tirar o que está fora do frustum. Malhas com skinning
+    // ficam sempre: a pose pode sair da caixa da pose de repouso
+    pub fn cull(&self, items: &[DrawItem], frustum: &Frustum) -> Vec<DrawItem> {
+        if !self.frustum_culling {
+            return items.to_vec();
+        }
+
+        items
+            .iter()
+            .filter(|item| match self.meshes.get(item.mesh.0) {
+                Some(mesh) if !self.is_skinned(item) => {
+                    frustum.intersects(mesh.bounds, &item.world)
+                }
+                _ => true,
+            })
+            .copied()
+            .collect()
+    }
+
+    // Grava a lista de desenho dentro do render pass principal, com o set 0 do frame e o
+    // set 1 de cada material. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        uploads: &UploadQueue,
+        materials: &Materials,
+        skinning: &Skinning,
+        items: &[DrawItem],
+    ) -> Result<u32> {
+        let mut items = self.drawable(items, uploads, materials, skinning);
+
+        // Agrupa por pipeline, depois por material e malha, pra trocar de estado menos vezes
+        items.sort_by_key(|(variant, joints, item)| {
+            ((*variant, joints.is_some()), item.material, item.mesh)
+        });
+
+        Ok(self.record_items(
+            device,
+            data,
+            command_buffer,
+            slot,
+            materials,
+            skinning,
+            &items,
+            false,
+        ))
+    }
+
+    // As de material transparente, no render pass delas, da mais longe da câmera pra mais
+    // perto (pelo centro da caixa de cada uma). Nos modos de debug só as opacas aparecem.
+    // Retorna quantos draw calls fez
+    pub unsafe fn record_transparent(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        uploads: &UploadQueue,
+        materials: &Materials,
+        skinning: &Skinning,
+        items: &[DrawItem],
+    ) -> u32 {
+        if self.raster_mode() != RasterMode::Fill {
+            return 0;
+        }
+
+        let mut items = self
+            .drawable(items, uploads, materials, skinning)
+            .into_iter()
+            .map(|entry| (self.camera_distance(entry.2), entry))
+            .collect::<Vec<_>>();
+        items.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        let items = items
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect::<Vec<_>>();
+
+        self.record_items(
+            device,
+            data,
+            command_buffer,
+            slot,
+            materials,
+            skinning,
+            &items,
+            true,
+        )
+    }
+
+    // Malhas e texturas que ainda estão a caminho da GPU ficam de fora desse frame. Cada
+    // item sai com a variante do material e o offset das juntas, se tiver pose
+    fn drawable<'a>(
+        &self,
+        items: &'a [DrawItem],
+        uploads: &UploadQueue,
+        materials: &Materials,
+        skinning: &Skinning,
+    ) -> Vec<(ShaderVariant, Option<u32>, &'a DrawItem)> {
+        items
+            .iter()
+            .filter(|item| self.is_ready(item, uploads, materials))
+            .map(|item| {
+                let variant = materials.get(item.material).unwrap().variant;
+                (variant, self.joints(item, skinning), item)
+            })
+            .collect()
+    }
+
+    // Distância ao quadrado da câmera do frame até o centro da caixa do item, no mundo
+    fn camera_distance(&self, item: &DrawItem) -> f32 {
+        let center = self
+            .bounds(item.mesh)
+            .map_or(glm::Vec3::zeros(), |(min, max)| (min + max) / 2.0);
+        let world = item.world * glm::vec4(center.x, center.y, center.z, 1.0);
+        glm::distance2(&world.xyz(), &self.camera.position)
+    }
+
+    unsafe fn record_items(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        materials: &Materials,
+        skinning: &Skinning,
+        items: &[(ShaderVariant, Option<u32>, &DrawItem)],
+        transparent: bool,
+    ) -> u32 {
+        let mode = self.raster_mode();
+        let mut bound_pipeline = None;
+        let mut bound_material = None;
+        let mut bound_mesh = None;
+        for (variant, joints, item) in items {
+            let (variant, skinned) = (*variant, joints.is_some());
+            let pipeline = if transparent {
+                let blend = materials.get(item.material).unwrap().blend;
+                &self.transparent_pipelines[&(variant, blend, skinned)]
+            } else if skinned {
+                &self.skinned_pipelines[&(variant, mode)]
+            } else {
+                &self.pipelines[&(variant, mode)]
+            };
+
+            if bound_pipeline != Some(pipeline.pipeline) {
+                let fragment_constants = [
+                    SCENE_TRANSFER as u32,
+                    self.debug_view.shader_index(),
+                    variant as u32,
+                ]
+                .iter()
+                .flat_map(|c| c.to_ne_bytes())
+                .collect::<Vec<_>>();
+
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline,
+                );
+                Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.layout,
+                    0,
+                    &[data.frame_descriptors.set(slot)],
+                    &[],
+                );
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline.layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    size_of::<glm::Mat4>() as u32,
+                    &fragment_constants,
+                );
+                if skinned {
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline.layout,
+                        2,
+                        &[skinning.set(slot)],
+                        &[],
+                    );
+                }
+                bound_pipeline = Some(pipeline.pipeline);
+                bound_material = None;
+                bound_mesh = None;
+            }
+
+            if bound_material != Some(item.material) {
+                materials.bind(device, command_buffer, pipeline.layout, item.material, slot);
+                bound_material = Some(item.material);
+            }
+
+            let mesh = &self.meshes[item.mesh.0];
+            if bound_mesh != Some(item.mesh) {
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer], &[0]);
+                if skinned {
+                    device.cmd_bind_vertex_buffers(command_buffer, 1, &[mesh.skin_buffer], &[0]);
+                }
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    mesh.index_buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                bound_mesh = Some(item.mesh);
+            }
+
+            let model_bytes =
+                slice::from_raw_parts(item.world.as_ptr() as *const u8, size_of::<glm::Mat4>());
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                model_bytes,
+            );
+            // A skinned.vert acha a primeira junta do animador pelo gl_InstanceIndex
+            let lod = self.lod(item).unwrap();
+            device.cmd_draw_indexed(
+                command_buffer,
+                lod.index_count,
+                1,
+                lod.first_index,
+                lod.vertex_offset,
+                joints.unwrap_or(0),
+            );
+        }
+
+        items.len() as u32
+    }
+
+    // Sem pesos na malha, ou sem a pose do animador nesse frame: pose de repouso
+    fn joints(&self, item: &DrawItem, skinning: &Skinning) -> Option<u32> {
+        item.animator
+            .filter(|_| self.is_skinned(item))
+            .and_then(|id| skinning.offset(id))
+    }
+
+    // Os lotes que o occlusion culling montou nesse frame, um desenho indireto por lote
+    // com o número de instâncias que sobraram no compute. Retorna quantos draw calls fez
+    pub unsafe fn record_indirect(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        materials: &Materials,
+        occlusion: &OcclusionCulling,
+    ) -> u32 {
+        let mode = self.raster_mode();
+        let mut bound_variant = None;
+        let mut bound_material = None;
+        let mut bound_mesh = None;
+        for (index, batch) in occlusion.batches().iter().enumerate() {
+            let pipeline = &self.indirect_pipelines[&(batch.variant, mode)];
+
+            if bound_variant != Some(batch.variant) {
+                let fragment_constants = [
+                    SCENE_TRANSFER as u32,
+                    self.debug_view.shader_index(),
+                    batch.variant as u32,
+                ]
+                .iter()
+                .flat_map(|c| c.to_ne_bytes())
+                .collect::<Vec<_>>();
+
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline,
+                );
+                Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.layout,
+                    0,
+                    &[data.frame_descriptors.set(slot)],
+                    &[],
+                );
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.layout,
+                    2,
+                    &[occlusion.set(slot)],
+                    &[],
+                );
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline.layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    size_of::<glm::Mat4>() as u32,
+                    &fragment_constants,
+                );
+                bound_variant = Some(batch.variant);
+                bound_material = None;
+                bound_mesh = None;
+            }
+
+            if bound_material != Some(batch.material) {
+                materials.bind(
+                    device,
+                    command_buffer,
+                    pipeline.layout,
+                    batch.material,
+                    slot,
+                );
+                bound_material = Some(batch.material);
+            }
+
+            let mesh = &self.meshes[batch.mesh.0];
+            if bound_mesh != Some(batch.mesh) {
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer], &[0]);
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    mesh.index_buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                bound_mesh = Some(batch.mesh);
+            }
+
+            occlusion.draw(device, command_buffer, slot, index);
+        }
+
+        occlusion.batches().len() as u32
+    }
+
+    // O depth do que o pass da cena vai desenhar com `record` e `record_indirect`, no
+    // render pass do pre-pass. Tem que ser a mesma lista, com o mesmo filtro: o que ficar
+    // de fora de um dos dois some ou tampa o que está atrás. Nos modos de debug sem depth
+    // (wireframe, overdraw) não desenha nada e o render pass só limpa o depth. Retorna
+    // quantos draw calls fez
+    pub unsafe fn record_prepass(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        uploads: &UploadQueue,
+        materials: &Materials,
+        skinning: &Skinning,
+        occlusion: &OcclusionCulling,
+        items: &[DrawItem],
+    ) -> u32 {
+        let prepass = match &self.prepass {
+            Some(prepass) if self.raster_mode() == RasterMode::Fill => prepass,
+            _ => return 0,
+        };
+
+        let mut items = self
+            .drawable(items, uploads, materials, skinning)
+            .into_iter()
+            .map(|(_, joints, item)| (joints, item))
+            .collect::<Vec<_>>();
+        items.sort_by_key(|(joints, item)| (joints.is_some(), item.mesh));
+
+        let mut bound_skinned = None;
+        let mut bound_mesh = None;
+        for (joints, item) in &items {
+            let skinned = joints.is_some();
+            let pipeline = if skinned {
+                &prepass.skinned
+            } else {
+                &prepass.direct
+            };
+
+            if bound_skinned != Some(skinned) {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline,
+                );
+                Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.layout,
+                    0,
+                    &[data.frame_descriptors.set(slot)],
+                    &[],
+                );
+                if skinned {
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline.layout,
+                        2,
+                        &[skinning.set(slot)],
+                        &[],
+                    );
+                }
+                bound_skinned = Some(skinned);
+                bound_mesh = None;
+            }
+
+            let mesh = &self.meshes[item.mesh.0];
+            if bound_mesh != Some(item.mesh) {
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer], &[0]);
+                if skinned {
+                    device.cmd_bind_vertex_buffers(command_buffer, 1, &[mesh.skin_buffer], &[0]);
+                }
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    mesh.index_buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                bound_mesh = Some(item.mesh);
+            }
+
+            let model_bytes =
+                slice::from_raw_parts(item.world.as_ptr() as *const u8, size_of::<glm::Mat4>());
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                model_bytes,
+            );
+            let lod = self.lod(item).unwrap();
+            device.cmd_draw_indexed(
+                command_buffer,
+                lod.index_count,
+                1,
+                lod.first_index,
+                lod.vertex_offset,
+                joints.unwrap_or(0),
+            );
+        }
+
+        let batches = occlusion.batches();
+        if !batches.is_empty() {
+            let pipeline = &prepass.indirect;
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline,
+            );
+            Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.layout,
+                0,
+                &[data.frame_descriptors.set(slot)],
+                &[],
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.layout,
+                2,
+                &[occlusion.set(slot)],
+                &[],
+            );
+
+            let mut bound_mesh = None;
+            for (index, batch) in batches.iter().enumerate() {
+                let mesh = &self.meshes[batch.mesh.0];
+                if bound_mesh != Some(batch.mesh) {
+                    device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer], &[0]);
+                    device.cmd_bind_index_buffer(
+                        command_buffer,
+                        mesh.index_buffer,
+                        0,
+                        vk::IndexType::UINT32,
+                    );
+                    bound_mesh = Some(batch.mesh);
+                }
+
+                occlusion.draw(device, command_buffer, slot, index);
+            }
+        }
+
+        (items.len() + batches.len()) as u32
+    }
+
+    // Só a geometria, pros passes de profundidade (sombras). A pipeline já tem que estar
+    // ligada e esperar a matriz `view_projection * mundo` nos push constants da vertex.
+    // Malhas com skinning projetam a sombra da pose de repouso
+    pub unsafe fn record_depth(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        uploads: &UploadQueue,
+        layout: vk::PipelineLayout,
+        view_projection: &glm::Mat4,
+        items: &[DrawItem],
+    ) -> u32 {
+        self.record_geometry(
+            device,
+            command_buffer,
+            uploads,
+            layout,
+            view_projection,
+            items,
+            false,
+        )
+    }
+
+    // Igual ao `record_depth`, mais o índice do item em `items` + 1 num u32 logo depois da
+    // matriz, nos push constants da fragment: o id que o picking escreve (0 é o fundo)
+    pub unsafe fn record_ids(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        uploads: &UploadQueue,
+        layout: vk::PipelineLayout,
+        view_projection: &glm::Mat4,
+        items: &[DrawItem],
+    ) -> u32 {
+        self.record_geometry(
+            device,
+            command_buffer,
+            uploads,
+            layout,
+            view_projection,
+            items,
+            true,
+        )
+    }
+
+    unsafe fn record_geometry(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        uploads: &UploadQueue,
+        layout: vk::PipelineLayout,
+        view_projection: &glm::Mat4,
+        items: &[DrawItem],
+        ids: bool,
+    ) -> u32 {
+        let mut items = items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| {
+                self.meshes
+                    .get(item.mesh.0)
+                    .is_some_and(|m| m.uploads.iter().all(|id| !uploads.is_pending(*id)))
+            })
+            .collect::<Vec<_>>();
+        items.sort_by_key(|(_, item)| item.mesh);
+
+        let mut bound_mesh = None;
+        for (index, item) in &items {
+            let mesh = &self.meshes[item.mesh.0];
+            if bound_mesh != Some(item.mesh) {
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer], &[0]);
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    mesh.index_buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                bound_mesh = Some(item.mesh);
+            }
+
+            let matrix = view_projection * item.world;
+            let matrix_bytes =
+                slice::from_raw_parts(matrix.as_ptr() as *const u8, size_of::<glm::Mat4>());
+            device.cmd_push_constants(
+                command_buffer,
+                layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                matrix_bytes,
+            );
+            if ids {
+                device.cmd_push_constants(
+                    command_buffer,
+                    layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    size_of::<glm::Mat4>() as u32,
+                    &(*index as u32 + 1).to_ne_bytes(),
+                );
+            }
+            let lod = self.lod(item).unwrap();
+            device.cmd_draw_indexed(
+                command_buffer,
+                lod.index_count,
+                1,
+                lod.first_index,
+                lod.vertex_offset,
+                0,
+            );
+        }
+
+        items.len() as u32
+    }
+
+    // Os vértices (e os pesos) continuam na CPU, pra `create_device_objects` reenviar
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.meshes.iter().for_each(|m| m.destroy(device));
+        self.retired.drain(..).for_each(|(_, m)| m.destroy(device));
+    }
+}
@@ -0,0 +1,495 @@
+use std::{f32::consts::FRAC_PI_2, mem::size_of, ptr};
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    camera::Camera,
+    image::{self, AttachmentImage},
+    mesh::{MeshRenderer, Vertex},
+    pass::HDR_FORMAT,
+    pipeline::{Pipeline, PipelineDesc},
+    scene::DrawItem,
+    shader::spirv,
+    shadow::CUBE_FACES,
+    uniforms::UniformBuffer,
+    upload::UploadQueue,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+// Estéreo usa 2; um cubo (sondas de reflexo) usaria as 6
+pub const MAX_VIEWS: usize = 6;
+
+// Distância entre os olhos, em metros, quando ninguém (o headset) diz outra
+pub const DEFAULT_IPD: f32 = 0.064;
+
+// Uma das views desenhadas no mesmo pass, cada uma numa camada do alvo
+#[derive(Copy, Clone, Debug)]
+pub struct View {
+    pub view: glm::Mat4,
+    pub projection: glm::Mat4,
+}
+
+impl View {
+    pub fn view_projection(&self) -> glm::Mat4 {
+        self.projection * self.view
+    }
+
+    // Esquerdo e direito, deslocados meio `ipd` pra cada lado do eixo x da câmera. Os dois
+    // olham pra frente, com a projeção da câmera
+    pub fn eyes(camera: &Camera, ipd: f32) -> [View; 2] {
+        let view = camera.view();
+        let projection = camera.projection_matrix();
+        // O olho vai pra esquerda, o mundo vai pra direita na view
+        let eye = |offset: f32| View {
+            view: glm::translation(&glm::vec3(offset, 0.0, 0.0)) * view,
+            projection,
+        };
+
+        [eye(ipd / 2.0), eye(-ipd / 2.0)]
+    }
+
+    // As 6 faces de um cubo em `position`, na ordem das camadas de um cubemap. Como nos
+    // cubos de sombra, a projeção não inverte o y: a amostragem espera as faces assim
+    pub fn cube(position: glm::Vec3, near: f32, far: f32, reverse_z: bool) -> [View; 6] {
+        let projection = if reverse_z {
+            glm::perspective_rh_zo(1.0, FRAC_PI_2, far, near)
+        } else {
+            glm::perspective_rh_zo(1.0, FRAC_PI_2, near, far)
+        };
+
+        CUBE_FACES.map(|(direction, up)| View {
+            view: glm::look_at_rh(
+                &position,
+                &(position + glm::Vec3::from(direction)),
+                &glm::Vec3::from(up),
+            ),
+            projection,
+        })
+    }
+}
+
+// Lido pelas duas shaders (set 0, binding 0). O layout segue o std140
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct ViewUniforms {
+    view_projections: [glm::Mat4; MAX_VIEWS],
+    // xyz: pra onde o sol está, w: luz ambiente
+    light: glm::Vec4,
+}
+
+// Desenha a cena em várias views num pass só com VK_KHR_multiview: cada draw vai pra
+// todas as camadas do alvo, e a vertex escolhe a matriz pelo gl_ViewIndex. Por enquanto
+// só Lambert com o sol, sem os materiais; é a base pro VR e pros cubos de uma vez
+#[derive(Debug)]
+pub struct MultiviewRenderer {
+    // Cor do que nenhuma malha cobre
+    pub background: glm::Vec3,
+    views: u32,
+    extent: vk::Extent2D,
+    color: AttachmentImage,
+    depth: AttachmentImage,
+    pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    pipeline: Pipeline,
+    set_layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    buffers: Vec<UniformBuffer>,
+}
+
+impl MultiviewRenderer {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        views: u32,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        Self::create_with(
+            instance,
+            device,
+            data,
+            views,
+            extent,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+        )
+    }
+
+    // As 6 faces de um cubo `size` x `size`, pra desenhar com as views de `View::cube`
+    pub unsafe fn create_cube(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        size: u32,
+    ) -> Result<Self> {
+        let extent = vk::Extent2D {
+            width: size,
+            height: size,
+        };
+        // Sem o y invertido na projeção, os triângulos chegam na tela com a ordem trocada
+        Self::create_with(instance, device, data, 6, extent, vk::FrontFace::CLOCKWISE)
+    }
+
+    unsafe fn create_with(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        views: u32,
+        extent: vk::Extent2D,
+        front_face: vk::FrontFace,
+    ) -> Result<Self> {
+        let views = views.clamp(1, MAX_VIEWS as u32);
+        let depth_format = image::get_depth_format(instance, data)?;
+        let pass = Self::create_render_pass(device, depth_format, views)?;
+
+        let bindings = &[vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        let set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(MAX_FRAMES_IN_FLIGHT as u32)
+            .build()];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(MAX_FRAMES_IN_FLIGHT as u32);
+        let pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = vec![set_layout; MAX_FRAMES_IN_FLIGHT];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        let sets = device.allocate_descriptor_sets(&info)?;
+
+        let size = size_of::<ViewUniforms>() as vk::DeviceSize;
+        let mut buffers = vec![];
+        for set in &sets {
+            let buffer = UniformBuffer::create(instance, device, data, size)?;
+            let buffer_info = &[vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(size)
+                .build()];
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(buffer_info);
+            device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+            buffers.push(buffer);
+        }
+
+        let vertex_shader = spirv!("multiview.vert");
+        let fragment_shader = spirv!("multiview.frag");
+
+        let vertex_bindings = Vertex::binding_descriptions();
+        // Posição e normal
+        let attributes = &Vertex::attribute_descriptions()[..2];
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(size_of::<glm::Mat4>() as u32)
+            .build()];
+        let descriptor_layouts = &[set_layout];
+
+        let mut desc = PipelineDesc::new(&vertex_shader[..], &fragment_shader[..], pass);
+        desc.bindings = &vertex_bindings;
+        desc.attributes = attributes;
+        desc.set_layouts = descriptor_layouts;
+        desc.push_constants = push_constants;
+        desc.front_face = front_face;
+        if data.config.reverse_z {
+            desc.depth_compare = vk::CompareOp::GREATER;
+        }
+        let pipeline = Pipeline::create(device, &desc)?;
+
+        let mut renderer = Self {
+            background: glm::vec3(0.0, 0.0, 0.0),
+            views,
+            extent,
+            color: AttachmentImage::default(),
+            depth: AttachmentImage::default(),
+            pass,
+            framebuffer: vk::Framebuffer::null(),
+            pipeline,
+            set_layout,
+            pool,
+            sets,
+            buffers,
+        };
+        renderer.create_targets(instance, device, data, depth_format)?;
+
+        Ok(renderer)
+    }
+
+    unsafe fn create_targets(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        depth_format: vk::Format,
+    ) -> Result<()> {
+        self.color = AttachmentImage::create_layers(
+            instance,
+            device,
+            data,
+            self.extent,
+            self.views,
+            HDR_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+
+        let depth_aspects = if image::has_stencil_component(depth_format) {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        };
+        self.depth = AttachmentImage::create_layers(
+            instance,
+            device,
+            data,
+            self.extent,
+            self.views,
+            depth_format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            depth_aspects,
+        )?;
+
+        // Com multiview o framebuffer tem uma camada só; as views escolhem as do attachment
+        let attachments = &[self.color.view, self.depth.view];
+        let info = vk::FramebufferCreateInfo::builder()
+            .render_pass(self.pass)
+            .attachments(attachments)
+            .width(self.extent.width)
+            .height(self.extent.height)
+            .layers(1);
+        self.framebuffer = device.create_framebuffer(&info, None)?;
+
+        Ok(())
+    }
+
+    unsafe fn destroy_targets(&mut self, device: &Device) {
+        device.destroy_framebuffer(self.framebuffer, None);
+        self.depth.destroy(device);
+        self.color.destroy(device);
+    }
+
+    unsafe fn create_render_pass(
+        device: &Device,
+        depth_format: vk::Format,
+        views: u32,
+    ) -> Result<vk::RenderPass> {
+        // Fica pronto pra ser lido (ou copiado pra swapchain do headset) depois do pass
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(HDR_FORMAT)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        let depth_attachment = vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let color_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let color_attachments = &[color_attachment_ref];
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(color_attachments)
+            .depth_stencil_attachment(&depth_attachment_ref);
+
+        // O depth é só desse pass, mas o do frame anterior ainda pode estar em uso
+        let dependencies = &[
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+                .dst_access_mask(
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .build(),
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER,
+                )
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ)
+                .build(),
+        ];
+
+        // Todas as views no único subpass. A correlação diz pro driver que elas enxergam
+        // quase a mesma coisa (ele pode dividir trabalho entre elas)
+        let mask = (1u32 << views) - 1;
+        let view_masks = &[mask];
+        let correlation_masks = &[mask];
+        let mut multiview = vk::RenderPassMultiviewCreateInfo::builder()
+            .view_masks(view_masks)
+            .correlation_masks(correlation_masks);
+
+        let attachments = &[color_attachment, depth_attachment];
+        let subpasses = &[subpass];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(attachments)
+            .subpasses(subpasses)
+            .dependencies(dependencies)
+            .push_next(&mut multiview);
+
+        Ok(device.create_render_pass(&info, None)?)
+    }
+
+    // Alvo com uma camada por view, em SHADER_READ_ONLY_OPTIMAL depois do pass
+    pub fn image(&self) -> vk::Image {
+        self.color.image
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.color.view
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn views(&self) -> u32 {
+        self.views
+    }
+
+    // Os alvos mudam de tamanho; o render pass e a pipeline continuam. Só com a GPU parada
+    pub unsafe fn resize(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        if extent == self.extent {
+            return Ok(());
+        }
+
+        let depth_format = self.depth.format;
+        self.destroy_targets(device);
+        self.extent = extent;
+        self.create_targets(instance, device, data, depth_format)
+    }
+
+    // Desenha `items` em todas as views. Sem culling: o que sai de uma view pode estar na
+    // outra, então a lista deve ser a da cena inteira. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        meshes: &MeshRenderer,
+        uploads: &UploadQueue,
+        views: &[View],
+        sun_direction: glm::Vec3,
+        ambient: f32,
+        items: &[DrawItem],
+    ) -> u32 {
+        let mut uniforms = ViewUniforms {
+            view_projections: [glm::identity(); MAX_VIEWS],
+            light: glm::vec4(sun_direction.x, sun_direction.y, sun_direction.z, ambient),
+        };
+        for (matrix, view) in uniforms.view_projections.iter_mut().zip(views) {
+            *matrix = view.view_projection();
+        }
+        ptr::copy_nonoverlapping(
+            &uniforms as *const ViewUniforms as *const u8,
+            self.buffers[slot].mapped,
+            size_of::<ViewUniforms>(),
+        );
+
+        let depth = if data.config.reverse_z { 0.0 } else { 1.0 };
+        let clear_values = &[
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [self.background.x, self.background.y, self.background.z, 1.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil: 0 },
+            },
+        ];
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(self.extent);
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.pass)
+            .framebuffer(self.framebuffer)
+            .render_area(render_area)
+            .clear_values(clear_values);
+
+        device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, self.extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            0,
+            &[self.sets[slot]],
+            &[],
+        );
+
+        // Com a identidade no lugar da câmera, o push constant é só a matriz de mundo
+        let draw_calls = meshes.record_depth(
+            device,
+            command_buffer,
+            uploads,
+            self.pipeline.layout,
+            &glm::identity(),
+            items,
+        );
+        device.cmd_end_render_pass(command_buffer);
+
+        draw_calls
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.destroy_targets(device);
+        self.buffers.drain(..).for_each(|b| b.destroy(device));
+        self.sets.clear();
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+        self.pipeline.destroy(device);
+        device.destroy_render_pass(self.pass, None);
+    }
+}
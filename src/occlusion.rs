@@ -0,0 +1,886 @@
+use std::{mem::size_of, ptr, slice};
+
+use anyhow::Result;
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::{prelude::v1_0::*, vk::KhrDrawIndirectCountExtension};
+
+use crate::{
+    allocator,
+    app::AppData,
+    buffer::create_buffer,
+    features::Feature,
+    image::{self, create_image_mips, create_image_view_mips, TrackedImage},
+    info::QueueFamilyIndices,
+    leaks,
+    material::{MaterialId, Materials, ShaderVariant},
+    mesh::{MeshId, MeshRenderer},
+    pipeline::Pipeline,
+    sampler::{self, SamplerDesc},
+    scene::DrawItem,
+    shader::spirv,
+    upload::UploadQueue,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+// Instâncias por frame que passam pelo compute (igual ao tamanho de `counts` no
+// cull.comp). O que passar disso é desenhado direto, sem teste
+const MAX_INSTANCES: usize = 16384;
+// Tamanho do grupo do cull.comp e do hiz.comp
+const CULL_GROUP_SIZE: u32 = 64;
+const PYRAMID_GROUP_SIZE: u32 = 8;
+const PYRAMID_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+// VkDrawIndexedIndirectCommand
+const DRAW_COMMAND_SIZE: u32 = 20;
+
+// Uma instância como o cull.comp e a mesh_indirect.vert leem (std430)
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct GpuInstance {
+    world: glm::Mat4,
+    bounds_min: glm::Vec4,
+    bounds_max: glm::Vec4,
+    index_count: u32,
+    batch: u32,
+    first_draw: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    _padding: [u32; 3],
+}
+
+// Instâncias seguidas com a mesma variante, material e malha. Viram um desenho indireto
+// só, com até `max_draws` comandos a partir de `first_draw`
+#[derive(Copy, Clone, Debug)]
+pub struct IndirectBatch {
+    pub variant: ShaderVariant,
+    pub material: MaterialId,
+    pub mesh: MeshId,
+    first_draw: u32,
+    max_draws: u32,
+}
+
+#[derive(Debug)]
+struct InstanceBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut GpuInstance,
+}
+
+// Occlusion culling na GPU. Depois do pass da cena o depth vira uma pirâmide Hi-Z (cada
+// mip guarda a profundidade mais distante dos 2x2 de baixo); no frame seguinte um compute
+// testa a caixa de cada instância contra ela e escreve só as visíveis num buffer de
+// desenho indireto, lido com vkCmdDrawIndexedIndirectCount. A pirâmide é do frame
+// anterior, então o que acabou de aparecer pode levar um frame pra ser desenhado.
+// Precisa do `occlusion_culling` na configuração, de compute na fila de gráficos, do
+// VK_KHR_draw_indirect_count e dos recursos de multi draw indirect; sem isso tudo vai
+// pelo caminho direto
+#[derive(Debug, Default)]
+pub struct OcclusionCulling {
+    pub enabled: bool,
+    supported: bool,
+    // Set do culling: instâncias (também o set 2 da mesh_indirect.vert), comandos e
+    // a pirâmide
+    pub layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    instances: Vec<InstanceBuffer>,
+    // Contadores dos lotes e depois os comandos, um por frame em voo
+    draws: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    cull: Pipeline,
+    pyramid_layout: vk::DescriptorSetLayout,
+    downsample: Pipeline,
+    // Dependem do tamanho da swapchain
+    has_pyramid: bool,
+    sampler: vk::Sampler,
+    depth_view: vk::ImageView,
+    pyramid: TrackedImage,
+    pyramid_memory: vk::DeviceMemory,
+    pyramid_view: vk::ImageView,
+    level_views: Vec<vk::ImageView>,
+    pyramid_pool: vk::DescriptorPool,
+    // Um por nível: lê o anterior (ou o depth) e escreve o nível
+    pyramid_sets: Vec<vk::DescriptorSet>,
+    pyramid_extent: vk::Extent2D,
+    // Câmera do frame em que a pirâmide foi montada. None enquanto ela não tem conteúdo
+    view_projection: Option<glm::Mat4>,
+    batches: Vec<IndirectBatch>,
+}
+
+impl OcclusionCulling {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let mut occlusion = Self {
+            enabled: true,
+            ..Default::default()
+        };
+
+        occlusion.create_device_objects(instance, device, data)?;
+        occlusion.create_pipeline(instance, device, data)?;
+        Ok(occlusion)
+    }
+
+    // O depth da cena precisa poder ser lido numa shader (e guardado no fim do pass)
+    pub unsafe fn samples_depth(instance: &Instance, data: &AppData, format: vk::Format) -> bool {
+        data.config.occlusion_culling && image::supports_sampling(instance, data, format)
+    }
+
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+        let families = instance.get_physical_device_queue_family_properties(data.physical_device);
+        let compute = families[indices.graphics as usize]
+            .queue_flags
+            .contains(vk::QueueFlags::COMPUTE);
+        let capabilities = &data.capabilities;
+        self.supported = data.config.occlusion_culling
+            && compute
+            && capabilities.draw_indirect_count
+            && capabilities.has(Feature::MultiDrawIndirect)
+            && capabilities.has(Feature::DrawIndirectFirstInstance);
+        if data.config.occlusion_culling && !self.supported {
+            warn!("Occlusion culling unsupported on this device, drawing meshes directly.");
+        }
+
+        // Instâncias no binding 0 (lidas também pela vertex dos desenhos indiretos),
+        // comandos no 1 e a pirâmide no 2
+        let bindings = &[
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::VERTEX)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(2)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+        ];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(2 * MAX_FRAMES_IN_FLIGHT as u32)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(MAX_FRAMES_IN_FLIGHT as u32)
+                .build(),
+        ];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(MAX_FRAMES_IN_FLIGHT as u32);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = vec![self.layout; MAX_FRAMES_IN_FLIGHT];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(&set_layouts);
+        self.sets = device.allocate_descriptor_sets(&info)?;
+
+        let instances_size = (MAX_INSTANCES * size_of::<GpuInstance>()) as vk::DeviceSize;
+        let draws_size = Self::draws_offset() + MAX_INSTANCES as u64 * DRAW_COMMAND_SIZE as u64;
+        self.instances.clear();
+        self.draws.clear();
+        for set in &self.sets {
+            let (buffer, memory) = create_buffer(
+                instance,
+                device,
+                data,
+                instances_size,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let mapped =
+                device.map_memory(memory, 0, instances_size, vk::MemoryMapFlags::empty())?
+                    as *mut GpuInstance;
+            self.instances.push(InstanceBuffer {
+                buffer,
+                memory,
+                mapped,
+            });
+
+            let (draws, draws_memory) = create_buffer(
+                instance,
+                device,
+                data,
+                draws_size,
+                vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::INDIRECT_BUFFER
+                    | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?;
+            self.draws.push((draws, draws_memory));
+
+            let instance_info = &[vk::DescriptorBufferInfo::builder()
+                .buffer(buffer)
+                .offset(0)
+                .range(instances_size)
+                .build()];
+            let draws_info = &[vk::DescriptorBufferInfo::builder()
+                .buffer(draws)
+                .offset(0)
+                .range(draws_size)
+                .build()];
+            let writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(instance_info),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(1)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(draws_info),
+            ];
+            device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+        }
+
+        // Origem (o nível anterior ou o depth) no binding 0, o nível escrito no 1
+        let bindings = &[
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+        ];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.pyramid_layout = device.create_descriptor_set_layout(&info, None)?;
+
+        // Câmera, tamanho da pirâmide, instâncias, níveis, reverse-Z e se testa contra a
+        // pirâmide no culling; só o reverse-Z na pirâmide
+        let cull_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(88)
+            .build()];
+        let pyramid_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(4)
+            .build()];
+
+        let cull_shader = spirv!("cull.comp");
+        let pyramid_shader = spirv!("hiz.comp");
+        self.cull =
+            Pipeline::create_compute(device, &cull_shader[..], &[self.layout], cull_constants)?;
+        self.downsample = Pipeline::create_compute(
+            device,
+            &pyramid_shader[..],
+            &[self.pyramid_layout],
+            pyramid_constants,
+        )?;
+
+        Ok(())
+    }
+
+    // A pirâmide tem o tamanho do depth, então é recriada com a swapchain
+    pub unsafe fn create_pipeline(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        let depth = &data.render_pass.depth;
+        self.has_pyramid = Self::samples_depth(instance, data, depth.format);
+        self.view_projection = None;
+        if !self.has_pyramid {
+            return Ok(());
+        }
+
+        let extent = data.swapchain.extent;
+        let levels = 32 - extent.width.max(extent.height).max(1).leading_zeros();
+        self.pyramid_extent = extent;
+
+        self.sampler = sampler::get(
+            device,
+            &SamplerDesc {
+                filter: vk::Filter::NEAREST,
+                max_lod: levels as f32,
+                ..Default::default()
+            },
+        )?;
+
+        // A view do attachment pode ter o stencil junto, e a shader só lê a profundidade
+        self.depth_view = image::create_image_view(
+            device,
+            depth.image,
+            depth.format,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        let (pyramid, pyramid_memory) = create_image_mips(
+            instance,
+            device,
+            data,
+            extent.width,
+            extent.height,
+            levels,
+            PYRAMID_FORMAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.pyramid = TrackedImage::new(pyramid, vk::ImageAspectFlags::COLOR);
+        self.pyramid_memory = pyramid_memory;
+        self.pyramid_view = create_image_view_mips(
+            device,
+            pyramid,
+            PYRAMID_FORMAT,
+            vk::ImageAspectFlags::COLOR,
+            0,
+            levels,
+        )?;
+        self.level_views = (0..levels)
+            .map(|level| {
+                create_image_view_mips(
+                    device,
+                    pyramid,
+                    PYRAMID_FORMAT,
+                    vk::ImageAspectFlags::COLOR,
+                    level,
+                    1,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let pool_sizes = &[
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(levels)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(levels)
+                .build(),
+        ];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(levels);
+        self.pyramid_pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = vec![self.pyramid_layout; levels as usize];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pyramid_pool)
+            .set_layouts(&set_layouts);
+        self.pyramid_sets = device.allocate_descriptor_sets(&info)?;
+
+        // O nível 0 copia o depth; os outros reduzem o nível anterior, que fica em GENERAL
+        for (level, set) in self.pyramid_sets.iter().enumerate() {
+            let (source, layout) = if level == 0 {
+                (
+                    self.depth_view,
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                )
+            } else {
+                (self.level_views[level - 1], vk::ImageLayout::GENERAL)
+            };
+
+            let source_info = &[vk::DescriptorImageInfo::builder()
+                .image_layout(layout)
+                .image_view(source)
+                .sampler(self.sampler)
+                .build()];
+            let target_info = &[vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(self.level_views[level])
+                .build()];
+            let writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(source_info),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(1)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(target_info),
+            ];
+            device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+        }
+
+        let pyramid_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.pyramid_view)
+            .sampler(self.sampler)
+            .build()];
+        for set in &self.sets {
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(2)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(pyramid_info);
+            device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+        }
+
+        Ok(())
+    }
+
+    // Se os desenhos desse frame vão pelo compute
+    pub fn active(&self) -> bool {
+        self.enabled && self.supported && self.has_pyramid
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.supported && self.has_pyramid
+    }
+
+    // Nulo enquanto o depth não pode ser lido
+    pub fn pyramid(&self) -> vk::Image {
+        self.pyramid.image
+    }
+
+    pub fn batches(&self) -> &[IndirectBatch] {
+        &self.batches
+    }
+
+    pub fn set(&self, slot: usize) -> vk::DescriptorSet {
+        self.sets[slot]
+    }
+
+    fn draws_offset() -> vk::DeviceSize {
+        (MAX_INSTANCES * size_of::<u32>()) as vk::DeviceSize
+    }
+
+    // Antes do pass da cena: escreve as instâncias que dá pra desenhar indiretamente e
+    // grava o compute que escolhe as visíveis. Devolve o que ainda tem que ir pelo
+    // caminho direto (malhas com skinning, as que não estão prontas e as que não
+    // couberam), ou a lista inteira se o occlusion culling não estiver ativo
+    pub unsafe fn record_cull(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        meshes: &MeshRenderer,
+        uploads: &UploadQueue,
+        materials: &Materials,
+        items: &[DrawItem],
+    ) -> Vec<DrawItem> {
+        self.batches.clear();
+        if !self.active() {
+            return items.to_vec();
+        }
+
+        let (mut indirect, mut direct): (Vec<_>, Vec<_>) =
+            items.iter().copied().partition(|item| {
+                meshes.is_ready(item, uploads, materials) && !meshes.is_skinned(item)
+            });
+        if indirect.len() > MAX_INSTANCES {
+            warn!(
+                "More than {} instances this frame, the rest skips occlusion culling.",
+                MAX_INSTANCES
+            );
+            direct.extend(indirect.drain(MAX_INSTANCES..));
+        }
+        if indirect.is_empty() {
+            return direct;
+        }
+
+        // Agrupa por pipeline, material e malha, que é o que o desenho indireto não troca
+        indirect.sort_by_key(|item| {
+            let variant = materials.get(item.material).unwrap().variant;
+            (variant, item.material, item.mesh)
+        });
+
+        let mapped = self.instances[slot].mapped;
+        for (index, item) in indirect.iter().enumerate() {
+            let variant = materials.get(item.material).unwrap().variant;
+            let same_batch = self.batches.last().is_some_and(|b| {
+                (b.variant, b.material, b.mesh) == (variant, item.material, item.mesh)
+            });
+            if !same_batch {
+                self.batches.push(IndirectBatch {
+                    variant,
+                    material: item.material,
+                    mesh: item.mesh,
+                    first_draw: index as u32,
+                    max_draws: 0,
+                });
+            }
+
+            let batch = (self.batches.len() - 1) as u32;
+            let first_draw = self.batches[batch as usize].first_draw;
+            self.batches[batch as usize].max_draws += 1;
+
+            // O nível de detalhe é escolhido aqui, e o cull.comp só copia pro comando
+            let (min, max) = meshes.bounds(item.mesh).unwrap();
+            let lod = meshes.lod(item).unwrap();
+            let instance = GpuInstance {
+                world: item.world,
+                bounds_min: glm::vec4(min.x, min.y, min.z, 1.0),
+                bounds_max: glm::vec4(max.x, max.y, max.z, 1.0),
+                index_count: lod.index_count,
+                batch,
+                first_draw,
+                first_index: lod.first_index,
+                vertex_offset: lod.vertex_offset,
+                _padding: [0; 3],
+            };
+            ptr::write(mapped.add(index), instance);
+        }
+
+        let (draws, _) = self.draws[slot];
+        let counts_size = (self.batches.len() * size_of::<u32>()) as vk::DeviceSize;
+        device.cmd_fill_buffer(command_buffer, draws, 0, counts_size, 0);
+        Self::barrier(
+            device,
+            command_buffer,
+            draws,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        let extent = self.pyramid_extent;
+        let levels = self.level_views.len() as u32;
+        let view_projection = self.view_projection.unwrap_or_else(glm::identity::<f32, 4>);
+        let constants = [
+            slice::from_raw_parts(
+                view_projection.as_ptr() as *const u8,
+                size_of::<glm::Mat4>(),
+            )
+            .to_vec(),
+            (extent.width as f32).to_ne_bytes().to_vec(),
+            (extent.height as f32).to_ne_bytes().to_vec(),
+            (indirect.len() as u32).to_ne_bytes().to_vec(),
+            levels.to_ne_bytes().to_vec(),
+            (data.config.reverse_z as u32).to_ne_bytes().to_vec(),
+            (self.view_projection.is_some() as u32)
+                .to_ne_bytes()
+                .to_vec(),
+        ]
+        .concat();
+
+        if self.view_projection.is_some() {
+            self.pyramid.transition_to(
+                device,
+                command_buffer,
+                vk::ImageLayout::GENERAL,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            );
+        }
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.cull.pipeline,
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.cull.layout,
+            0,
+            &[self.sets[slot]],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.cull.layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &constants,
+        );
+        device.cmd_dispatch(
+            command_buffer,
+            (indirect.len() as u32 + CULL_GROUP_SIZE - 1) / CULL_GROUP_SIZE,
+            1,
+            1,
+        );
+
+        Self::barrier(
+            device,
+            command_buffer,
+            draws,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::DRAW_INDIRECT,
+            vk::AccessFlags::INDIRECT_COMMAND_READ,
+        );
+
+        direct
+    }
+
+    // Desenho indireto do lote `batch`, com a pipeline, o material e a malha dele já
+    // ligados
+    pub unsafe fn draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        batch: usize,
+    ) {
+        let (draws, _) = self.draws[slot];
+        let first_draw = self.batches[batch].first_draw as vk::DeviceSize;
+        device.cmd_draw_indexed_indirect_count_khr(
+            command_buffer,
+            draws,
+            Self::draws_offset() + first_draw * DRAW_COMMAND_SIZE as vk::DeviceSize,
+            draws,
+            (batch * size_of::<u32>()) as vk::DeviceSize,
+            self.batches[batch].max_draws,
+            DRAW_COMMAND_SIZE,
+        );
+    }
+
+    // Depois do pass da cena: reduz o depth até 1x1. `view_projection` é a câmera com que
+    // a cena foi desenhada (já com a pré-rotação), usada no teste do próximo frame
+    pub unsafe fn record_pyramid(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        view_projection: &glm::Mat4,
+    ) {
+        if !self.active() {
+            // Quando voltar a ligar, a pirâmide antiga não vale mais
+            self.view_projection = None;
+            return;
+        }
+
+        let depth = &data.render_pass.depth;
+        let depth_aspects = if image::has_stencil_component(depth.format) {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        };
+
+        // O depth sai do layout de attachment pra ser lido, e a pirâmide espera o culling
+        // desse frame terminar de ler antes de ser reescrita
+        let barrier = image_barrier(
+            depth.image,
+            depth_aspects,
+            0,
+            1,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::AccessFlags::SHADER_READ,
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+        self.pyramid.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+        );
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.downsample.pipeline,
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.downsample.layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &(data.config.reverse_z as u32).to_ne_bytes(),
+        );
+
+        let extent = self.pyramid_extent;
+        for (level, set) in self.pyramid_sets.iter().enumerate() {
+            // Cada nível lê o anterior
+            if level > 0 {
+                let barrier = image_barrier(
+                    self.pyramid.image,
+                    vk::ImageAspectFlags::COLOR,
+                    level as u32 - 1,
+                    1,
+                    vk::ImageLayout::GENERAL,
+                    vk::ImageLayout::GENERAL,
+                    vk::AccessFlags::SHADER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                );
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[] as &[vk::MemoryBarrier],
+                    &[] as &[vk::BufferMemoryBarrier],
+                    &[barrier],
+                );
+            }
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.downsample.layout,
+                0,
+                &[*set],
+                &[],
+            );
+            let width = (extent.width >> level).max(1);
+            let height = (extent.height >> level).max(1);
+            device.cmd_dispatch(
+                command_buffer,
+                (width + PYRAMID_GROUP_SIZE - 1) / PYRAMID_GROUP_SIZE,
+                (height + PYRAMID_GROUP_SIZE - 1) / PYRAMID_GROUP_SIZE,
+                1,
+            );
+        }
+
+        // O depth volta pro layout que o render pass espera, e a pirâmide inteira fica
+        // pronta pro culling do próximo frame
+        let barrier = image_barrier(
+            depth.image,
+            depth_aspects,
+            0,
+            1,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+        self.pyramid.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        self.view_projection = Some(*view_projection);
+    }
+
+    unsafe fn barrier(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) {
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[barrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        if !self.has_pyramid {
+            return;
+        }
+
+        device.destroy_descriptor_pool(self.pyramid_pool, None);
+        self.pyramid_sets.clear();
+        self.level_views
+            .drain(..)
+            .for_each(|v| leaks::destroy_image_view(device, v));
+        leaks::destroy_image_view(device, self.pyramid_view);
+        leaks::destroy_image(device, self.pyramid.image);
+        self.pyramid = TrackedImage::default();
+        allocator::free(device, self.pyramid_memory);
+        leaks::destroy_image_view(device, self.depth_view);
+        self.has_pyramid = false;
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.downsample.destroy(device);
+        self.cull.destroy(device);
+        device.destroy_descriptor_set_layout(self.pyramid_layout, None);
+        for buffer in self.instances.drain(..) {
+            device.unmap_memory(buffer.memory);
+            leaks::destroy_buffer(device, buffer.buffer);
+            allocator::free(device, buffer.memory);
+        }
+        for (buffer, memory) in self.draws.drain(..) {
+            leaks::destroy_buffer(device, buffer);
+            allocator::free(device, memory);
+        }
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+        self.batches.clear();
+    }
+}
+
+fn image_barrier(
+    image: vk::Image,
+    aspects: vk::ImageAspectFlags,
+    base_mip: u32,
+    mip_count: u32,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+) -> vk::ImageMemoryBarrier {
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspects)
+        .base_mip_level(base_mip)
+        .level_count(mip_count)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource)
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .build()
+}
@@ -0,0 +1,216 @@
+use std::{collections::HashSet, mem::size_of, slice};
+
+use anyhow::Result;
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    image,
+    mesh::{MeshRenderer, Vertex},
+    pipeline::{BlendMode, Pipeline, PipelineDesc, StencilState},
+    scene::{DrawItem, EntityId},
+    shader::spirv,
+    upload::UploadQueue,
+};
+
+// Valor que os selecionados deixam no stencil
+const SELECTED: u32 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OutlineSettings {
+    // Linear, na cena antes do tone mapping. O alpha mistura com o que está embaixo
+    pub color: glm::Vec4,
+    // Espessura em pixels, a mesma a qualquer distância
+    pub width: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            color: glm::vec4(1.0, 0.45, 0.05, 1.0),
+            width: 3.0,
+        }
+    }
+}
+
+// Contorno dos objetos selecionados, estilo editor. No fim do pass da cena os selecionados
+// são desenhados duas vezes: a primeira só marca o stencil, a segunda infla a malha pelas
+// normais e pinta onde o stencil não foi marcado, o que sobra só na borda. Os dois sem
+// depth, então o contorno aparece mesmo atrás de outros objetos
+#[derive(Debug, Default)]
+pub struct Outline {
+    pub settings: OutlineSettings,
+    selected: HashSet<EntityId>,
+    mark: Pipeline,
+    outline: Pipeline,
+    // O depth do pass da cena tem stencil (`outline` da configuração)
+    supported: bool,
+}
+
+impl Outline {
+    pub unsafe fn create(device: &Device, data: &AppData) -> Result<Self> {
+        let mut outline = Self::default();
+        outline.create_pipeline(device, data)?;
+        Ok(outline)
+    }
+
+    pub unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        self.supported = image::has_stencil_component(data.render_pass.depth.format);
+        if !self.supported {
+            if data.config.outline {
+                warn!("No depth-stencil format, selection outline disabled.");
+            }
+            return Ok(());
+        }
+
+        let vertex_shader = spirv!("outline.vert");
+        let fragment_shader = spirv!("outline.frag");
+
+        let bindings = Vertex::binding_descriptions();
+        let attributes = &Vertex::attribute_descriptions()[..2];
+
+        // Projeção vezes mundo e o deslocamento na vertex, a cor na fragment
+        let matrix = size_of::<glm::Mat4>() as u32;
+        let push_constants = &[
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size(matrix)
+                .build(),
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(matrix)
+                .size(size_of::<glm::Vec4>() as u32)
+                .build(),
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(matrix + size_of::<glm::Vec4>() as u32)
+                .size(size_of::<glm::Vec2>() as u32)
+                .build(),
+        ];
+
+        let mut desc = PipelineDesc::new(
+            &vertex_shader[..],
+            &fragment_shader[..],
+            data.render_pass.pass,
+        );
+        desc.bindings = &bindings;
+        desc.attributes = attributes;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        desc.depth_test = false;
+        desc.depth_write = false;
+        desc.subpass = data.render_pass.forward_subpass();
+
+        desc.stencil = Some(StencilState::write());
+        desc.color_mask = vk::ColorComponentFlags::empty();
+        self.mark = Pipeline::create(device, &desc)?;
+
+        desc.stencil = Some(StencilState::not_equal());
+        desc.color_mask = vk::ColorComponentFlags::all();
+        desc.blend = BlendMode::Alpha;
+        self.outline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.supported
+    }
+
+    pub fn set_selection(&mut self, entities: &[EntityId]) {
+        self.selected = entities.iter().copied().collect();
+    }
+
+    pub fn selection(&self) -> Vec<EntityId> {
+        self.selected.iter().copied().collect()
+    }
+
+    // No fim do subpass da cena, com o stencil ainda limpo. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        meshes: &MeshRenderer,
+        uploads: &UploadQueue,
+        view_projection: &glm::Mat4,
+        items: &[DrawItem],
+    ) -> u32 {
+        if !self.supported || self.selected.is_empty() {
+            return 0;
+        }
+
+        let items = items
+            .iter()
+            .filter(|item| item.entity.is_some_and(|e| self.selected.contains(&e)))
+            .cloned()
+            .collect::<Vec<_>>();
+        if items.is_empty() {
+            return 0;
+        }
+
+        // Pixels -> NDC, que vai de -1 a 1
+        let extent = data.swapchain.extent;
+        let offset = size_of::<glm::Mat4>() as u32 + size_of::<glm::Vec4>() as u32;
+        let extrusion = glm::vec2(
+            2.0 * self.settings.width / extent.width as f32,
+            2.0 * self.settings.width / extent.height as f32,
+        );
+
+        let mut draw_calls = 0;
+        for (pipeline, extrusion) in [
+            (&self.mark, glm::vec2(0.0, 0.0)),
+            (&self.outline, extrusion),
+        ] {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline,
+            );
+            Pipeline::set_viewport(device, command_buffer, extent);
+            device.cmd_set_stencil_reference(
+                command_buffer,
+                vk::StencilFaceFlags::FRONT_AND_BACK,
+                SELECTED,
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                size_of::<glm::Mat4>() as u32,
+                slice::from_raw_parts(
+                    self.settings.color.as_ptr() as *const u8,
+                    size_of::<glm::Vec4>(),
+                ),
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.layout,
+                vk::ShaderStageFlags::VERTEX,
+                offset,
+                slice::from_raw_parts(extrusion.as_ptr() as *const u8, size_of::<glm::Vec2>()),
+            );
+
+            draw_calls += meshes.record_depth(
+                device,
+                command_buffer,
+                uploads,
+                pipeline.layout,
+                view_projection,
+                &items,
+            );
+        }
+
+        draw_calls
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        if self.supported {
+            self.mark.destroy(device);
+            self.outline.destroy(device);
+        }
+    }
+}
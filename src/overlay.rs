@@ -0,0 +1,338 @@
+use std::{collections::VecDeque, mem::size_of, slice, time::Duration};
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator::MemoryStats,
+    app::AppData,
+    buffer::DynamicBuffer,
+    pipeline::{BlendMode, Pipeline, PipelineDesc},
+    shader::spirv,
+    stats::FrameStats,
+};
+
+// Quantos frames o gráfico mostra
+const HISTORY: usize = 120;
+// Tamanho de um pixel da fonte na tela
+const SCALE: f32 = 2.0;
+// Frame time que enche o gráfico (30 fps)
+const GRAPH_MAX_MS: f32 = 33.3;
+
+const MARGIN: f32 = 8.0;
+const PADDING: f32 = 6.0;
+const LINE_HEIGHT: f32 = 7.0 * SCALE;
+const GRAPH_HEIGHT: f32 = 48.0;
+const BAR_WIDTH: f32 = 2.0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct Vertex {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+// Estatísticas do frame desenhadas por cima da cena (F1). Só retângulos coloridos, com
+// uma fonte de pixels 3x5 pra não depender de textura
+#[derive(Debug)]
+pub struct Overlay {
+    pub visible: bool,
+    history: VecDeque<f32>,
+    pipeline: Pipeline,
+    vertices: DynamicBuffer,
+}
+
+impl Overlay {
+    pub unsafe fn create(device: &Device, data: &AppData) -> Result<Self> {
+        let mut overlay = Self {
+            visible: false,
+            history: VecDeque::with_capacity(HISTORY),
+            pipeline: Pipeline::default(),
+            vertices: DynamicBuffer::new(vk::BufferUsageFlags::VERTEX_BUFFER),
+        };
+
+        overlay.create_pipeline(device, data)?;
+        Ok(overlay)
+    }
+
+    // Depende do render pass, então é recriada junto com a swapchain
+    pub unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        let vertex_shader = spirv!("overlay.vert");
+        let fragment_shader = spirv!("overlay.frag");
+
+        let bindings = &[vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Vertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()];
+
+        let attributes = &[
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(size_of::<[f32; 2]>() as u32)
+                .build(),
+        ];
+
+        // Pré-rotação + tamanho da tela na vertex, função de transferência na fragment
+        let push_constants = &[
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size((size_of::<glm::Mat4>() + size_of::<[f32; 2]>()) as u32)
+                .build(),
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset((size_of::<glm::Mat4>() + size_of::<[f32; 2]>()) as u32)
+                .size(size_of::<u32>() as u32)
+                .build(),
+        ];
+
+        let mut desc = PipelineDesc::new(
+            &vertex_shader[..],
+            &fragment_shader[..],
+            data.post_pass.pass,
+        );
+        desc.bindings = bindings;
+        desc.attributes = attributes;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        desc.depth_test = false;
+        desc.depth_write = false;
+        desc.blend = BlendMode::Alpha;
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+    }
+
+    pub fn push_frame_time(&mut self, frame_time: Duration) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(frame_time.as_secs_f32() * 1000.0);
+    }
+
+    // Grava o desenho dentro do render pass principal. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        stats: &FrameStats,
+        memory: &MemoryStats,
+    ) -> Result<u32> {
+        if !self.visible {
+            return Ok(0);
+        }
+
+        let vertices = self.build(stats, memory);
+        let bytes = slice::from_raw_parts(
+            vertices.as_ptr() as *const u8,
+            vertices.len() * size_of::<Vertex>(),
+        );
+        let buffer = self.vertices.write(instance, device, data, slot, bytes)?;
+
+        let extent = data.swapchain.logical_extent();
+        let screen = [extent.width as f32, extent.height as f32];
+        let transform = data.swapchain.pre_rotation();
+        let mut constants = [0u8; size_of::<glm::Mat4>() + size_of::<[f32; 2]>()];
+        constants[..64].copy_from_slice(slice::from_raw_parts(transform.as_ptr() as *const u8, 64));
+        constants[64..].copy_from_slice(slice::from_raw_parts(screen.as_ptr() as *const u8, 8));
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            &constants,
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            constants.len() as u32,
+            &(data.swapchain.transfer as u32).to_ne_bytes(),
+        );
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[0]);
+        device.cmd_draw(command_buffer, vertices.len() as u32, 1, 0, 0);
+
+        Ok(1)
+    }
+
+    fn build(&self, stats: &FrameStats, memory: &MemoryStats) -> Vec<Vertex> {
+        let frame_ms = stats.frame_time.as_secs_f32() * 1000.0;
+        let fps = if frame_ms > 0.0 {
+            1000.0 / frame_ms
+        } else {
+            0.0
+        };
+
+        let mut lines = vec![
+            format!("FPS {:.0}", fps),
+            format!("MS {:.2}", frame_ms),
+            format!("DRAWS {}", stats.draw_calls),
+            format!("MESH {} CULL {}", stats.submitted, stats.culled),
+        ];
+        // Do começo do frame até a tela, só com o VK_KHR_present_wait
+        if let Some(latency) = stats.present_latency {
+            lines.push(format!("LAT {:.2}", latency.as_secs_f32() * 1000.0));
+        }
+        // Sem o VK_EXT_memory_budget o uso é só o nosso, contra o heap inteiro
+        let video = memory.device_local();
+        lines.push(format!(
+            "GPU {}/{} MB",
+            video.usage >> 20,
+            video.budget >> 20
+        ));
+        lines.push(format!(
+            "BLOCKS {} {} MB",
+            video.blocks,
+            video.allocated >> 20
+        ));
+
+        let width = HISTORY as f32 * BAR_WIDTH;
+        let height = lines.len() as f32 * LINE_HEIGHT + GRAPH_HEIGHT + PADDING;
+
+        let mut canvas = Canvas::default();
+        canvas.rect(
+            MARGIN,
+            MARGIN,
+            width + PADDING * 2.0,
+            height + PADDING * 2.0,
+            [0.0, 0.0, 0.0, 0.6],
+        );
+
+        let x = MARGIN + PADDING;
+        let mut y = MARGIN + PADDING;
+        for line in &lines {
+            canvas.text(x, y, line, [1.0, 1.0, 1.0, 1.0]);
+            y += LINE_HEIGHT;
+        }
+
+        // Gráfico do frame time, com a linha dos 60 fps
+        y += PADDING;
+        let bottom = y + GRAPH_HEIGHT;
+        for (i, ms) in self.history.iter().enumerate() {
+            let bar = (ms / GRAPH_MAX_MS).min(1.0) * GRAPH_HEIGHT;
+            let color = if *ms <= 16.7 {
+                [0.2, 0.9, 0.3, 1.0]
+            } else if *ms <= GRAPH_MAX_MS {
+                [0.95, 0.8, 0.2, 1.0]
+            } else {
+                [0.95, 0.25, 0.2, 1.0]
+            };
+
+            canvas.rect(
+                x + i as f32 * BAR_WIDTH,
+                bottom - bar,
+                BAR_WIDTH,
+                bar,
+                color,
+            );
+        }
+
+        let target = bottom - 16.7 / GRAPH_MAX_MS * GRAPH_HEIGHT;
+        canvas.rect(x, target, width, 1.0, [1.0, 1.0, 1.0, 0.5]);
+
+        canvas.vertices
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.vertices.destroy(device);
+    }
+}
+
+#[derive(Default)]
+struct Canvas {
+    vertices: Vec<Vertex>,
+}
+
+impl Canvas {
+    // Dois triângulos, em pixels a partir do canto superior esquerdo
+    fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        let (x1, y1) = (x + width, y + height);
+        for position in [[x, y], [x1, y], [x1, y1], [x, y], [x1, y1], [x, y1]] {
+            self.vertices.push(Vertex { position, color });
+        }
+    }
+
+    fn text(&mut self, x: f32, y: f32, text: &str, color: [f32; 4]) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i as f32 * 4.0 * SCALE;
+
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        self.rect(
+                            left + column as f32 * SCALE,
+                            y + row as f32 * SCALE,
+                            SCALE,
+                            SCALE,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Cada linha são 3 bits, o mais significativo à esquerda. Só o que o overlay escreve
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b111, 0b100, 0b100, 0b100, 0b111],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b111, 0b100, 0b101, 0b101, 0b111],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'O' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        _ => [0; 5],
+    }
+}
@@ -0,0 +1,192 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use vulkanalia::{prelude::v1_0::*, vk::GoogleDisplayTimingExtension};
+
+use crate::{error::RendererError, limiter::FrameLimiter, stats::FrameStats};
+
+// Números do ritmo com que as imagens chegam na tela
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PacingStats {
+    // Intervalo que o pacing tenta manter entre presents. Zero sem um alvo (sem limite
+    // de FPS e sem saber o refresh do display)
+    pub target_interval: Duration,
+    // Intervalo medido entre um present e o próximo (média móvel)
+    pub interval: Duration,
+    // Quanto cada intervalo foge do alvo, pra mais ou pra menos (média móvel)
+    pub jitter: Duration,
+    // Presents que apareceram mais de meio refresh depois do horário pedido
+    pub late: u64,
+    // Medido pelo display (VK_GOOGLE_display_timing); sem ele, pelo relógio da CPU na
+    // hora do present
+    pub display_timing: bool,
+}
+
+// Mantém o intervalo entre presents constante. Com VK_GOOGLE_display_timing, cada present
+// leva o horário em que deve aparecer (o último horário real mais N intervalos), e o
+// driver devolve quando ele de fato apareceu. Sem a extensão, a CPU segura o loop depois
+// do present, como o FrameLimiter
+#[derive(Debug, Default)]
+pub struct FramePacer {
+    pub enabled: bool,
+    supported: bool,
+    refresh: Option<Duration>,
+    fps_interval: Option<Duration>,
+    next_id: u32,
+    // Último present com o horário real conhecido: (id, nanossegundos no relógio do display)
+    anchor: Option<(u32, u64)>,
+    cpu: FrameLimiter,
+    last_present: Option<Instant>,
+    stats: PacingStats,
+}
+
+impl FramePacer {
+    pub fn new(supported: bool, enabled: bool, fps: Option<u32>) -> Self {
+        let mut pacer = Self {
+            enabled,
+            supported,
+            stats: PacingStats {
+                display_timing: supported,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        pacer.set_fps_limit(fps);
+        pacer
+    }
+
+    // Os ids e horários são por swapchain; o refresh pode mudar junto (outro monitor)
+    pub unsafe fn reset(&mut self, device: &Device, swapchain: vk::SwapchainKHR) -> Result<()> {
+        self.next_id = 0;
+        self.anchor = None;
+        self.last_present = None;
+
+        if self.supported {
+            let refresh = device
+                .get_refresh_cycle_duration_google(swapchain)
+                .map_err(RendererError::from)?;
+            self.refresh =
+                Some(Duration::from_nanos(refresh.refresh_duration)).filter(|r| !r.is_zero());
+        }
+        self.update_target();
+
+        Ok(())
+    }
+
+    // None (ou 0) deixa só o refresh do display como alvo
+    pub fn set_fps_limit(&mut self, fps: Option<u32>) {
+        self.fps_interval = fps
+            .filter(|fps| *fps > 0)
+            .map(|fps| Duration::from_secs_f64(1.0 / fps as f64));
+        self.update_target();
+    }
+
+    // Com o refresh conhecido, o alvo é um múltiplo inteiro dele: um intervalo no meio de
+    // dois vblanks alternaria entre os dois, que é justamente o stutter
+    fn update_target(&mut self) {
+        let target = match (self.refresh, self.fps_interval) {
+            (Some(refresh), Some(limit)) => {
+                let cycles = (limit.as_secs_f64() / refresh.as_secs_f64())
+                    .round()
+                    .max(1.0);
+                Some(refresh.mul_f64(cycles))
+            }
+            (Some(refresh), None) => Some(refresh),
+            (None, limit) => limit,
+        };
+
+        self.stats.target_interval = target.unwrap_or_default();
+        self.cpu = FrameLimiter::default();
+        self.cpu.set_interval(target);
+    }
+
+    pub fn stats(&self) -> PacingStats {
+        self.stats
+    }
+
+    // O que vai no vk::PresentTimesInfoGOOGLE desse present. Mesmo sem pacing o id vai,
+    // pra medir
+    pub fn next_present_time(&mut self) -> Option<vk::PresentTimeGOOGLE> {
+        if !self.supported {
+            return None;
+        }
+
+        self.next_id = self.next_id.wrapping_add(1);
+        let target = self.stats.target_interval.as_nanos() as u64;
+        let desired_present_time = match self.anchor {
+            Some((id, time)) if self.enabled && target > 0 => {
+                time + self.next_id.wrapping_sub(id) as u64 * target
+            }
+            _ => 0,
+        };
+
+        Some(vk::PresentTimeGOOGLE {
+            present_id: self.next_id,
+            desired_present_time,
+        })
+    }
+
+    // Chamado no começo do frame: lê os horários dos presents que já apareceram
+    pub unsafe fn update(&mut self, device: &Device, swapchain: vk::SwapchainKHR) -> Result<()> {
+        if !self.supported {
+            return Ok(());
+        }
+
+        let timings = device
+            .get_past_presentation_timing_google(swapchain)
+            .map_err(RendererError::from)?;
+        for timing in timings {
+            if let Some((_, previous)) = self.anchor {
+                let interval = timing.actual_present_time.saturating_sub(previous);
+                self.sample(Duration::from_nanos(interval));
+            }
+
+            let half_refresh = self.refresh.unwrap_or_default().as_nanos() as u64 / 2;
+            if timing.desired_present_time != 0
+                && timing.actual_present_time > timing.desired_present_time + half_refresh
+            {
+                self.stats.late += 1;
+            }
+
+            self.anchor = Some((timing.present_id, timing.actual_present_time));
+        }
+
+        Ok(())
+    }
+
+    // Chamado logo depois do present. Sem a extensão, mede pelo relógio da CPU e, nos
+    // modos que não esperam o vblank, segura o loop até o próximo intervalo. Retorna se
+    // segurou, pra ninguém esperar de novo
+    pub fn presented(&mut self, waits_for_vblank: bool) -> bool {
+        if self.supported {
+            return false;
+        }
+
+        let now = Instant::now();
+        if let Some(last) = self.last_present {
+            self.sample(now - last);
+        }
+        self.last_present = Some(now);
+
+        let waited = self.enabled && !waits_for_vblank && !self.stats.target_interval.is_zero();
+        if waited {
+            self.cpu.wait();
+        }
+
+        waited
+    }
+
+    fn sample(&mut self, interval: Duration) {
+        let target = self.stats.target_interval;
+        let error = if target.is_zero() {
+            Duration::ZERO
+        } else if interval > target {
+            interval - target
+        } else {
+            target - interval
+        };
+
+        self.stats.interval = FrameStats::smooth(self.stats.interval, interval);
+        self.stats.jitter = FrameStats::smooth(self.stats.jitter, error);
+    }
+}
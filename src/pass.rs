@@ -0,0 +1,1243 @@
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    config::RenderPath,
+    fog::VolumetricFog,
+    godrays::GodRays,
+    image::{self, AttachmentImage},
+    info::OutputTransfer,
+    occlusion::OcclusionCulling,
+};
+
+// A cena é desenhada num alvo de ponto flutuante, sem limite em 1.0, e só o tone mapping
+// leva ela pra swapchain
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// O alvo HDR guarda a cor linear, então as shaders da cena não codificam nada. Quem
+// aplica a transferência da swapchain é o tone mapping
+pub const SCENE_TRANSFER: OutputTransfer = OutputTransfer::Hardware;
+
+// Alvos do G-buffer, na ordem em que aparecem depois da cor e do depth no render pass
+// (e nos input attachments da resolução):
+// albedo (rgb: cor base, a: modelo de shading / 3), normal (xyz: normal no mundo,
+// w: profundidade ao longo do olhar, 0 onde não tem geometria), parâmetros do material
+// (metálico/rugosidade/oclusão ou cor e expoente do especular) e emissiva
+pub const GBUFFER_FORMATS: [vk::Format; 4] = [
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+
+// O que acontece com o conteúdo de um attachment quando o pass começa.
+// `Load` mantém o que estava lá no frame anterior (motion trails e afins)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LoadOp<T> {
+    Clear(T),
+    Load,
+    DontCare,
+}
+
+impl<T> LoadOp<T> {
+    pub fn vk_load_op(&self) -> vk::AttachmentLoadOp {
+        match self {
+            LoadOp::Clear(_) => vk::AttachmentLoadOp::CLEAR,
+            LoadOp::Load => vk::AttachmentLoadOp::LOAD,
+            LoadOp::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+        }
+    }
+
+    // Se alguém vai ler o conteúdo no próximo frame, precisamos guardar ele
+    pub fn vk_store_op(&self, store: bool) -> vk::AttachmentStoreOp {
+        if store || matches!(self, LoadOp::Load) {
+            vk::AttachmentStoreOp::STORE
+        } else {
+            vk::AttachmentStoreOp::DONT_CARE
+        }
+    }
+
+    pub fn same_kind(&self, other: &Self) -> bool {
+        self.vk_load_op() == other.vk_load_op()
+    }
+}
+
+// Operações de cada render target do pass principal
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AttachmentOps {
+    pub color: LoadOp<[f32; 4]>,
+    pub depth: LoadOp<f32>,
+    // Só existe quando o formato de depth tem stencil (ver image::get_depth_format)
+    pub stencil: LoadOp<u32>,
+}
+
+impl Default for AttachmentOps {
+    fn default() -> Self {
+        Self {
+            color: LoadOp::Clear([0.0, 0.0, 0.0, 1.0]),
+            depth: LoadOp::Clear(1.0),
+            stencil: LoadOp::Clear(0),
+        }
+    }
+}
+
+impl AttachmentOps {
+    // Mudar só o valor de clear não exige recriar o render pass, mas mudar o tipo
+    // da operação sim (ela faz parte do vk::RenderPass)
+    pub fn requires_rebuild(&self, other: &Self) -> bool {
+        !self.color.same_kind(&other.color)
+            || !self.depth.same_kind(&other.depth)
+            || !self.stencil.same_kind(&other.stencil)
+    }
+
+    // Layouts (cor, depth) em que o pass da cena espera os alvos. Se vamos carregar o
+    // conteúdo anterior, a cor ficou no layout em que o tone mapping leu ela; os
+    // descartados não precisam de layout nenhum
+    pub fn initial_layouts(&self, stencil: bool) -> (vk::ImageLayout, vk::ImageLayout) {
+        let color = if matches!(self.color, LoadOp::Load) {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        } else {
+            vk::ImageLayout::UNDEFINED
+        };
+
+        let loads_depth =
+            matches!(self.depth, LoadOp::Load) || (stencil && matches!(self.stencil, LoadOp::Load));
+        let depth = if loads_depth {
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        } else {
+            vk::ImageLayout::UNDEFINED
+        };
+
+        (color, depth)
+    }
+
+    // As do pass da cena quando o pre-pass já escreveu o depth: ele carrega o depth e o
+    // stencil, e as operações configuradas valem pro pre-pass
+    pub fn after_prepass(&self) -> Self {
+        Self {
+            depth: LoadOp::Load,
+            stencil: LoadOp::Load,
+            ..*self
+        }
+    }
+
+    // Um valor por attachment, na mesma ordem do render pass. Os que não são CLEAR
+    // são ignorados pelo Vulkan, então qualquer valor serve
+    pub fn clear_values(&self) -> Vec<vk::ClearValue> {
+        let color = match self.color {
+            LoadOp::Clear(c) => c,
+            _ => [0.0; 4],
+        };
+
+        let depth = match self.depth {
+            LoadOp::Clear(d) => d,
+            _ => 1.0,
+        };
+
+        let stencil = match self.stencil {
+            LoadOp::Clear(s) => s,
+            _ => 0,
+        };
+
+        vec![
+            vk::ClearValue {
+                color: vk::ClearColorValue { float32: color },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil },
+            },
+        ]
+    }
+}
+
+// Pass da cena, que desenha no alvo HDR. Forward: um subpass só. Deferred: o subpass 0
+// escreve o G-buffer, o 1 projeta os decals nele (lendo o depth) e o 2 resolve a luz e
+// desenha o resto da cena por cima, ainda com o depth das malhas. No forward com
+// `depth_prepass` um render pass só de depth vem antes, no mesmo depth. As transparentes
+// vêm depois, num render pass que carrega os dois
+#[derive(Clone, Debug, Default)]
+pub struct RenderPassData {
+    pub pass: vk::RenderPass,
+    pub ops: AttachmentOps,
+    pub path: RenderPath,
+    pub color: AttachmentImage,
+    pub depth: AttachmentImage,
+    // Vazio no forward
+    pub gbuffer: Vec<AttachmentImage>,
+    // Um só: nenhum attachment é da swapchain
+    pub framebuffer: vk::Framebuffer,
+    // Null sem o pre-pass
+    pub prepass: vk::RenderPass,
+    pub prepass_framebuffer: vk::Framebuffer,
+    pub transparent: vk::RenderPass,
+    pub transparent_framebuffer: vk::Framebuffer,
+    // Os alvos já passaram pelo `initialize_targets`
+    initialized: bool,
+}
+
+impl RenderPassData {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        ops: AttachmentOps,
+    ) -> Result<Self> {
+        let depth_format = image::get_depth_format(instance, data)?;
+        let depth_aspects = if image::has_stencil_component(depth_format) {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        };
+
+        // Lido pelo tone mapping depois do pass. A cadeia de efeitos (post.rs) copia o
+        // resultado dela de volta nele
+        let color = AttachmentImage::create(
+            instance,
+            device,
+            data,
+            data.swapchain.extent,
+            HDR_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+
+        // O occlusion culling lê o depth depois do pass, pra montar a pirâmide Hi-Z, a
+        // névoa pra saber até onde ela vai em cada pixel e os raios de sol pra achar o céu
+        let sampled = OcclusionCulling::samples_depth(instance, data, depth_format)
+            || VolumetricFog::samples_depth(instance, data, depth_format)
+            || GodRays::samples_depth(instance, data, depth_format);
+        let mut depth_usage = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+        if sampled {
+            depth_usage |= vk::ImageUsageFlags::SAMPLED;
+        }
+        // Carregando o depth, o primeiro frame limpa ele por fora (ver `initialize_targets`)
+        let stencil = image::has_stencil_component(depth_format);
+        if ops.initial_layouts(stencil).1 != vk::ImageLayout::UNDEFINED {
+            depth_usage |= vk::ImageUsageFlags::TRANSFER_DST;
+        }
+        // E os decals do deferred, como input attachment
+        if data.config.render_path == RenderPath::Deferred {
+            depth_usage |= vk::ImageUsageFlags::INPUT_ATTACHMENT;
+        }
+        let depth = AttachmentImage::create(
+            instance,
+            device,
+            data,
+            data.swapchain.extent,
+            depth_format,
+            depth_usage,
+            depth_aspects,
+        )?;
+
+        let path = data.config.render_path;
+        let gbuffer = match path {
+            RenderPath::Forward => vec![],
+            RenderPath::Deferred => GBUFFER_FORMATS
+                .iter()
+                .map(|format| {
+                    AttachmentImage::create(
+                        instance,
+                        device,
+                        data,
+                        data.swapchain.extent,
+                        *format,
+                        vk::ImageUsageFlags::COLOR_ATTACHMENT
+                            | vk::ImageUsageFlags::INPUT_ATTACHMENT,
+                        vk::ImageAspectFlags::COLOR,
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+
+        // No deferred as malhas já são sombreadas uma vez só, na resolução do G-buffer
+        let has_prepass = data.config.depth_prepass && path == RenderPath::Forward;
+        let scene_ops = if has_prepass {
+            ops.after_prepass()
+        } else {
+            ops
+        };
+        let pass = Self::create_render_pass(device, HDR_FORMAT, depth_format, &scene_ops, path)?;
+
+        let mut attachments = vec![color.view, depth.view];
+        attachments.extend(gbuffer.iter().map(|g| g.view));
+        let info = vk::FramebufferCreateInfo::builder()
+            .render_pass(pass)
+            .attachments(&attachments)
+            .width(data.swapchain.extent.width)
+            .height(data.swapchain.extent.height)
+            .layers(1);
+        let framebuffer = device.create_framebuffer(&info, None)?;
+
+        let (prepass, prepass_framebuffer) = if has_prepass {
+            let prepass = Self::create_prepass(device, depth_format, &ops)?;
+            let attachments = &[depth.view];
+            let info = vk::FramebufferCreateInfo::builder()
+                .render_pass(prepass)
+                .attachments(attachments)
+                .width(data.swapchain.extent.width)
+                .height(data.swapchain.extent.height)
+                .layers(1);
+            (prepass, device.create_framebuffer(&info, None)?)
+        } else {
+            (vk::RenderPass::null(), vk::Framebuffer::null())
+        };
+
+        let transparent = Self::create_transparent_pass(device, HDR_FORMAT, depth_format)?;
+        let attachments = &[color.view, depth.view];
+        let info = vk::FramebufferCreateInfo::builder()
+            .render_pass(transparent)
+            .attachments(attachments)
+            .width(data.swapchain.extent.width)
+            .height(data.swapchain.extent.height)
+            .layers(1);
+        let transparent_framebuffer = device.create_framebuffer(&info, None)?;
+
+        Ok(Self {
+            pass,
+            ops,
+            path,
+            color,
+            depth,
+            gbuffer,
+            framebuffer,
+            prepass,
+            prepass_framebuffer,
+            transparent,
+            transparent_framebuffer,
+            initialized: false,
+        })
+    }
+
+    // Alvos recém-criados estão em UNDEFINED, mas com `LoadOp::Load` o pass da cena espera
+    // a cor em SHADER_READ_ONLY e o depth em DEPTH_STENCIL_ATTACHMENT, e o grafo assume
+    // esses layouts no começo de todo frame. No primeiro frame depois de criar, limpa o
+    // que vai ser carregado (`far_depth` no depth) e deixa os dois nesses layouts
+    pub unsafe fn initialize_targets(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        far_depth: f32,
+    ) {
+        if self.initialized {
+            return;
+        }
+        self.initialized = true;
+
+        let stencil = image::has_stencil_component(self.depth.format);
+        let (_, depth_layout) = self.ops.initial_layouts(stencil);
+        let depth_aspects = if stencil {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        };
+
+        let color = Self::range(vk::ImageAspectFlags::COLOR);
+        let depth = Self::range(depth_aspects);
+
+        // A cor tem TRANSFER_DST sempre (a cadeia de efeitos copia nela), então é limpa
+        // mesmo quando o pass da cena também limpa
+        let mut to_transfer = vec![Self::barrier(
+            self.color.image,
+            color,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::TRANSFER_WRITE,
+        )];
+        let loads_depth = depth_layout != vk::ImageLayout::UNDEFINED;
+        if loads_depth {
+            to_transfer.push(Self::barrier(
+                self.depth.image,
+                depth,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+            ));
+        }
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &to_transfer,
+        );
+
+        let clear_color = match self.ops.color {
+            LoadOp::Clear(c) => c,
+            _ => [0.0, 0.0, 0.0, 1.0],
+        };
+        device.cmd_clear_color_image(
+            command_buffer,
+            self.color.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &vk::ClearColorValue {
+                float32: clear_color,
+            },
+            &[color],
+        );
+        if loads_depth {
+            device.cmd_clear_depth_stencil_image(
+                command_buffer,
+                self.depth.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearDepthStencilValue {
+                    depth: far_depth,
+                    stencil: 0,
+                },
+                &[depth],
+            );
+        }
+
+        let (depth_old, depth_src_access) = if loads_depth {
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+            )
+        } else {
+            (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty())
+        };
+        let to_attachments = &[
+            Self::barrier(
+                self.color.image,
+                color,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::SHADER_READ,
+            ),
+            Self::barrier(
+                self.depth.image,
+                depth,
+                depth_old,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                depth_src_access,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+        ];
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            to_attachments,
+        );
+    }
+
+    fn range(aspects: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(aspects)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()
+    }
+
+    fn barrier(
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_access: vk::AccessFlags,
+        dst_access: vk::AccessFlags,
+    ) -> vk::ImageMemoryBarrier {
+        vk::ImageMemoryBarrier::builder()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(range)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .build()
+    }
+
+    // Carrega a cor e o depth como o pass da cena deixou. O depth só é testado, mas
+    // continua guardado pra pirâmide Hi-Z
+    unsafe fn create_transparent_pass(
+        device: &Device,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+    ) -> Result<vk::RenderPass> {
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(color_format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        let depth_attachment = vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let color_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let color_attachments = &[color_attachment_ref];
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(color_attachments)
+            .depth_stencil_attachment(&depth_attachment_ref);
+
+        // A cena escreveu os dois logo antes
+        let dependency = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+            );
+
+        // Igual ao pass da cena: quem lê o alvo HDR depois
+        let output_dependency = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            )
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+        let attachments = &[color_attachment, depth_attachment];
+        let subpasses = &[subpass];
+        let dependencies = &[dependency, output_dependency];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(attachments)
+            .subpasses(subpasses)
+            .dependencies(dependencies);
+
+        Ok(device.create_render_pass(&info, None)?)
+    }
+
+    // Só o depth (e o stencil, limpo aqui pro contorno), com as operações configuradas.
+    // Guarda tudo pro pass da cena carregar
+    unsafe fn create_prepass(
+        device: &Device,
+        depth_format: vk::Format,
+        ops: &AttachmentOps,
+    ) -> Result<vk::RenderPass> {
+        let stencil = image::has_stencil_component(depth_format);
+        let (_, initial_layout) = ops.initial_layouts(stencil);
+
+        let stencil_load_op = if stencil {
+            ops.stencil.vk_load_op()
+        } else {
+            vk::AttachmentLoadOp::DONT_CARE
+        };
+
+        let depth_attachment = vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(ops.depth.vk_load_op())
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(stencil_load_op)
+            .stencil_store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(initial_layout)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .depth_stencil_attachment(&depth_attachment_ref);
+
+        // Espera o frame anterior parar de usar o depth (o pass da cena e a pirâmide Hi-Z)
+        let dependency = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::COMPUTE_SHADER,
+            )
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+            .dst_access_mask(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            );
+
+        let attachments = &[depth_attachment];
+        let subpasses = &[subpass];
+        let dependencies = &[dependency];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(attachments)
+            .subpasses(subpasses)
+            .dependencies(dependencies);
+
+        Ok(device.create_render_pass(&info, None)?)
+    }
+
+    unsafe fn create_render_pass(
+        device: &Device,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+        ops: &AttachmentOps,
+        path: RenderPath,
+    ) -> Result<vk::RenderPass> {
+        let stencil = image::has_stencil_component(depth_format);
+        let (color_initial_layout, depth_initial_layout) = ops.initial_layouts(stencil);
+
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(color_format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(ops.color.vk_load_op())
+            .store_op(ops.color.vk_store_op(true))
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(color_initial_layout)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        let (stencil_load_op, stencil_store_op) = if stencil {
+            (ops.stencil.vk_load_op(), ops.stencil.vk_store_op(false))
+        } else {
+            (
+                vk::AttachmentLoadOp::DONT_CARE,
+                vk::AttachmentStoreOp::DONT_CARE,
+            )
+        };
+
+        let depth_attachment = vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(ops.depth.vk_load_op())
+            // O pass das transparentes testa contra ele
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(stencil_load_op)
+            .stencil_store_op(stencil_store_op)
+            .initial_layout(depth_initial_layout)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let color_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        // O G-buffer só vive dentro do pass: começa limpo e é jogado fora no fim
+        let gbuffer_attachments = GBUFFER_FORMATS
+            .iter()
+            .map(|format| {
+                vk::AttachmentDescription::builder()
+                    .format(*format)
+                    .samples(vk::SampleCountFlags::_1)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let gbuffer_write_refs = (0..GBUFFER_FORMATS.len() as u32)
+            .map(|i| {
+                vk::AttachmentReference::builder()
+                    .attachment(2 + i)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let gbuffer_read_refs = (0..GBUFFER_FORMATS.len() as u32)
+            .map(|i| {
+                vk::AttachmentReference::builder()
+                    .attachment(2 + i)
+                    .layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let color_attachments = &[color_attachment_ref];
+        let forward_subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(color_attachments)
+            .depth_stencil_attachment(&depth_attachment_ref);
+
+        // O alvo HDR não é tocado pelo G-buffer, mas tem que chegar inteiro no subpass da
+        // luz (ele pode ter sido carregado do frame anterior)
+        let preserve_attachments = &[0];
+        let gbuffer_subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&gbuffer_write_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .preserve_attachments(preserve_attachments);
+
+        // Misturam na cor e na normal do G-buffer; o resto passa direto pra luz
+        let depth_read_refs = &[vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .build()];
+        let decal_preserve_attachments = &[0, 4, 5];
+        let decal_subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .input_attachments(depth_read_refs)
+            .color_attachments(&gbuffer_write_refs[..2])
+            .preserve_attachments(decal_preserve_attachments);
+
+        let lighting_subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .input_attachments(&gbuffer_read_refs)
+            .color_attachments(color_attachments)
+            .depth_stencil_attachment(&depth_attachment_ref);
+
+        // Espera o frame anterior parar de usar o depth e o alvo HDR (o tone mapping lê ele
+        // na fragment, o histograma da exposição na compute) antes de escrever. No deferred
+        // o frame anterior também pode estar lendo ou escrevendo o G-buffer
+        let src_stage_mask = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::FRAGMENT_SHADER
+            | vk::PipelineStageFlags::COMPUTE_SHADER;
+        let mut src_access_mask = vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+        if path == RenderPath::Deferred {
+            src_access_mask |= vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+        }
+
+        let dependency = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(src_stage_mask)
+            .src_access_mask(src_access_mask)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            );
+
+        // Os decals leem o depth e misturam com a cor e a normal que as malhas escreveram
+        let decal_dependency = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(1)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::INPUT_ATTACHMENT_READ,
+            )
+            .dependency_flags(vk::DependencyFlags::BY_REGION);
+
+        // E a luz lê o que eles escreveram, com o depth de volta pra teste e escrita
+        let decal_output_dependency = vk::SubpassDependency::builder()
+            .src_subpass(1)
+            .dst_subpass(2)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::INPUT_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .dependency_flags(vk::DependencyFlags::BY_REGION);
+
+        // A resolução lê o G-buffer do mesmo pixel, e o resto do subpass continua testando
+        // (e escrevendo) o depth das malhas
+        let gbuffer_dependency = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(2)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .dst_stage_mask(
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::INPUT_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .dependency_flags(vk::DependencyFlags::BY_REGION);
+
+        let mut attachments = vec![color_attachment.build(), depth_attachment.build()];
+        let (subpasses, mut dependencies) = match path {
+            RenderPath::Forward => (vec![forward_subpass.build()], vec![dependency.build()]),
+            RenderPath::Deferred => {
+                attachments.extend(gbuffer_attachments);
+                (
+                    vec![
+                        gbuffer_subpass.build(),
+                        decal_subpass.build(),
+                        lighting_subpass.build(),
+                    ],
+                    vec![
+                        dependency.build(),
+                        decal_dependency.build(),
+                        decal_output_dependency.build(),
+                        gbuffer_dependency.build(),
+                    ],
+                )
+            }
+        };
+
+        // O histograma e o tone mapping só leem o alvo HDR depois que ele foi escrito (e
+        // mudou de layout)
+        let output_dependency = vk::SubpassDependency::builder()
+            .src_subpass(subpasses.len() as u32 - 1)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            )
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+        dependencies.push(output_dependency.build());
+
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+
+        Ok(device.create_render_pass(&info, None)?)
+    }
+
+    // Subpass em que o resto da cena é desenhado (e as malhas também, no forward)
+    pub fn forward_subpass(&self) -> u32 {
+        match self.path {
+            RenderPath::Forward => 0,
+            RenderPath::Deferred => 2,
+        }
+    }
+
+    // Só existe no deferred
+    pub fn decal_subpass(&self) -> Option<u32> {
+        match self.path {
+            RenderPath::Forward => None,
+            RenderPath::Deferred => Some(1),
+        }
+    }
+
+    pub fn has_prepass(&self) -> bool {
+        !self.prepass.is_null()
+    }
+
+    // Operações que o pass da cena usa de fato
+    pub fn scene_ops(&self) -> AttachmentOps {
+        if self.has_prepass() {
+            self.ops.after_prepass()
+        } else {
+            self.ops
+        }
+    }
+
+    // Só o do depth
+    pub fn prepass_clear_values(&self) -> Vec<vk::ClearValue> {
+        vec![self.ops.clear_values()[1]]
+    }
+
+    pub fn clear_values(&self) -> Vec<vk::ClearValue> {
+        let mut values = self.ops.clear_values();
+        // Zero no G-buffer: profundidade 0 marca os pixels sem geometria
+        values.extend(self.gbuffer.iter().map(|_| vk::ClearValue {
+            color: vk::ClearColorValue { float32: [0.0; 4] },
+        }));
+        values
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_framebuffer(self.framebuffer, None);
+        self.color.destroy(device);
+        self.depth.destroy(device);
+        self.gbuffer.iter().for_each(|g| g.destroy(device));
+        device.destroy_render_pass(self.pass, None);
+        if self.has_prepass() {
+            device.destroy_framebuffer(self.prepass_framebuffer, None);
+            device.destroy_render_pass(self.prepass, None);
+        }
+        device.destroy_framebuffer(self.transparent_framebuffer, None);
+        device.destroy_render_pass(self.transparent, None);
+    }
+}
+
+// Render pass de uma cor só que carrega o alvo HDR e desenha por cima, pros efeitos de
+// tela cheia entre a cena e o pós-processamento (névoa, raios de sol). Quem vem antes
+// pode ter sido um compute
+pub unsafe fn create_overlay_pass(
+    device: &Device,
+    data: &AppData,
+) -> Result<(vk::RenderPass, vk::Framebuffer)> {
+    let color_attachment = vk::AttachmentDescription::builder()
+        .format(HDR_FORMAT)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::LOAD)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+    let color_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let color_attachments = &[color_attachment_ref];
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments);
+
+    // O alvo foi escrito logo antes, e o que a shader lê pode vir de um compute
+    let dependency = vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::COMPUTE_SHADER,
+        )
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::SHADER_WRITE)
+        .dst_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        )
+        .dst_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::SHADER_READ,
+        );
+
+    let output_dependency = vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+        )
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+    let attachments = &[color_attachment];
+    let subpasses = &[subpass];
+    let dependencies = &[dependency, output_dependency];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+    let render_pass = device.create_render_pass(&info, None)?;
+
+    let extent = data.swapchain.extent;
+    let attachments = &[data.render_pass.color.view];
+    let info = vk::FramebufferCreateInfo::builder()
+        .render_pass(render_pass)
+        .attachments(attachments)
+        .width(extent.width)
+        .height(extent.height)
+        .layers(1);
+    let framebuffer = device.create_framebuffer(&info, None)?;
+
+    Ok((render_pass, framebuffer))
+}
+
+// Estágios de tela cheia depois da cena, na ordem em que rodam
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PostStage {
+    ToneMap,
+    ColorGrading,
+    Fxaa,
+}
+
+// Alvo dos estágios que não são o último. 10 bits por canal, porque guarda a cor já
+// codificada (sRGB ou PQ), e nisso 8 bits já dão faixas no HDR10
+pub const LDR_FORMAT: vk::Format = vk::Format::A2B10G10R10_UNORM_PACK32;
+
+// Cadeia do pós-processamento. Cada estágio lê o alvo do anterior (o primeiro, o tone
+// mapping, lê o alvo HDR da cena); o último escreve direto na imagem da swapchain, e o 2D
+// (sprites, overlay, texto) vai por cima dele, já fora do HDR
+#[derive(Clone, Debug, Default)]
+pub struct PostPassData {
+    // Pass do último estágio
+    pub pass: vk::RenderPass,
+    // Um por imagem da swapchain
+    pub framebuffers: Vec<vk::Framebuffer>,
+    pub stages: Vec<PostStage>,
+    // Pass dos estágios intermediários, se tiver algum
+    intermediate_pass: vk::RenderPass,
+    // Um alvo (e framebuffer) por estágio antes do último
+    targets: Vec<AttachmentImage>,
+    target_framebuffers: Vec<vk::Framebuffer>,
+    // Transferência que cada estágio aplica na saída
+    transfers: Vec<OutputTransfer>,
+}
+
+impl PostPassData {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let mut stages = vec![PostStage::ToneMap];
+        if data.config.post_processing.color_grading {
+            stages.push(PostStage::ColorGrading);
+        }
+        if data.config.post_processing.fxaa {
+            stages.push(PostStage::Fxaa);
+        }
+
+        // Os intermediários guardam a cor codificada, que é o que o FXAA espera (e o que
+        // cabe em 10 bits). Quem escreve na swapchain decodifica se o formato for _SRGB
+        let output = data.swapchain.transfer;
+        // O P3 fica nas próprias primárias; o scRGB não cabe num UNORM e vai como sRGB
+        let intermediate = match output {
+            OutputTransfer::Pq => OutputTransfer::Pq,
+            OutputTransfer::DisplayP3 => OutputTransfer::DisplayP3,
+            _ => OutputTransfer::Srgb,
+        };
+        let mut transfers = vec![intermediate; stages.len() - 1];
+        transfers.push(output);
+
+        // O último estágio escreve todos os pixels, então o conteúdo anterior não importa
+        let pass = Self::create_render_pass(
+            device,
+            data.swapchain.format,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )?;
+
+        let framebuffers = data
+            .swapchain
+            .image_views
+            .iter()
+            .map(|v| Self::create_framebuffer(device, data, pass, *v))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut intermediate_pass = vk::RenderPass::null();
+        let mut targets = vec![];
+        let mut target_framebuffers = vec![];
+        if stages.len() > 1 {
+            // Lido pelo estágio seguinte
+            intermediate_pass = Self::create_render_pass(
+                device,
+                LDR_FORMAT,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )?;
+
+            for _ in 1..stages.len() {
+                let target = AttachmentImage::create(
+                    instance,
+                    device,
+                    data,
+                    data.swapchain.extent,
+                    LDR_FORMAT,
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    vk::ImageAspectFlags::COLOR,
+                )?;
+                target_framebuffers.push(Self::create_framebuffer(
+                    device,
+                    data,
+                    intermediate_pass,
+                    target.view,
+                )?);
+                targets.push(target);
+            }
+        }
+
+        Ok(Self {
+            pass,
+            framebuffers,
+            stages,
+            intermediate_pass,
+            targets,
+            target_framebuffers,
+            transfers,
+        })
+    }
+
+    unsafe fn create_render_pass(
+        device: &Device,
+        format: vk::Format,
+        final_layout: vk::ImageLayout,
+    ) -> Result<vk::RenderPass> {
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(final_layout);
+
+        let color_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let color_attachments = &[color_attachment_ref];
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(color_attachments);
+
+        // Espera a imagem da swapchain ficar disponível (ou o frame anterior terminar de
+        // ler o alvo intermediário) antes de escrever
+        let dependency = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            );
+
+        // O estágio seguinte só lê depois que o alvo foi escrito. Na swapchain não faz
+        // diferença, a apresentação espera o semáforo
+        let output_dependency = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+        let attachments = &[color_attachment];
+        let subpasses = &[subpass];
+        let dependencies = &[dependency, output_dependency];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(attachments)
+            .subpasses(subpasses)
+            .dependencies(dependencies);
+
+        Ok(device.create_render_pass(&info, None)?)
+    }
+
+    unsafe fn create_framebuffer(
+        device: &Device,
+        data: &AppData,
+        pass: vk::RenderPass,
+        view: vk::ImageView,
+    ) -> Result<vk::Framebuffer> {
+        let attachments = &[view];
+        let info = vk::FramebufferCreateInfo::builder()
+            .render_pass(pass)
+            .attachments(attachments)
+            .width(data.swapchain.extent.width)
+            .height(data.swapchain.extent.height)
+            .layers(1);
+
+        Ok(device.create_framebuffer(&info, None)?)
+    }
+
+    pub fn has_stage(&self, stage: PostStage) -> bool {
+        self.stages.contains(&stage)
+    }
+
+    fn index(&self, stage: PostStage) -> usize {
+        self.stages
+            .iter()
+            .position(|s| *s == stage)
+            .expect("post stage not in the chain")
+    }
+
+    // Pass em que o estágio desenha, pra criar a pipeline
+    pub fn stage_pass(&self, stage: PostStage) -> vk::RenderPass {
+        if self.index(stage) == self.stages.len() - 1 {
+            self.pass
+        } else {
+            self.intermediate_pass
+        }
+    }
+
+    // Saída do estágio anterior. O primeiro lê o alvo HDR da cena, que não é daqui
+    pub fn input(&self, stage: PostStage) -> vk::ImageView {
+        self.targets[self.index(stage) - 1].view
+    }
+
+    // Transferência do que o estágio lê (só faz sentido depois do primeiro)
+    pub fn input_transfer(&self, stage: PostStage) -> OutputTransfer {
+        self.transfers[self.index(stage) - 1]
+    }
+
+    pub fn transfer(&self, stage: PostStage) -> OutputTransfer {
+        self.transfers[self.index(stage)]
+    }
+
+    // Render pass e framebuffer do estágio `index`
+    pub fn target(&self, index: usize, image_index: usize) -> (vk::RenderPass, vk::Framebuffer) {
+        if index == self.stages.len() - 1 {
+            (self.pass, self.framebuffers[image_index])
+        } else {
+            (self.intermediate_pass, self.target_framebuffers[index])
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.framebuffers
+            .iter()
+            .chain(self.target_framebuffers.iter())
+            .for_each(|f| device.destroy_framebuffer(*f, None));
+        self.targets.iter().for_each(|t| t.destroy(device));
+        device.destroy_render_pass(self.intermediate_pass, None);
+        device.destroy_render_pass(self.pass, None);
+    }
+}
@@ -0,0 +1,406 @@
+use std::{mem::size_of, ptr};
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator,
+    app::AppData,
+    buffer::create_buffer,
+    image::{self, AttachmentImage},
+    leaks,
+    mesh::{MeshRenderer, Vertex},
+    pipeline::{Pipeline, PipelineDesc},
+    scene::{DrawItem, EntityId},
+    shader::spirv,
+    upload::UploadQueue,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+// Índice do item na lista do frame + 1; 0 é o fundo
+const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+// Cópia do pixel pedido num frame em voo, com quem era cada id nesse frame. Lida depois
+// da fence dele, como o staging do FrameRecorder
+#[derive(Debug, Default)]
+struct ReadbackSlot {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    pending: Option<(u32, u32)>,
+    entities: Vec<Option<EntityId>>,
+}
+
+// Pass opcional que desenha o id de cada objeto num R32_UINT e lê de volta o pixel
+// pedido, sem travar a CPU: a resposta chega um ou dois frames depois. O alvo tem 1x1;
+// a viewport é deslocada pra que só o pixel pedido caia dentro dele
+#[derive(Debug)]
+pub struct Picking {
+    ids: AttachmentImage,
+    depth: AttachmentImage,
+    pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    pipeline: Pipeline,
+    slots: Vec<ReadbackSlot>,
+    // Pixel a desenhar no próximo frame
+    request: Option<(u32, u32)>,
+    // Último pixel lido e o que tinha nele
+    result: Option<((u32, u32), Option<EntityId>)>,
+}
+
+impl Picking {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let extent = vk::Extent2D {
+            width: 1,
+            height: 1,
+        };
+        let ids = AttachmentImage::create(
+            instance,
+            device,
+            data,
+            extent,
+            ID_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+
+        let depth_format = image::get_depth_format(instance, data)?;
+        let depth_aspects = if image::has_stencil_component(depth_format) {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        };
+        let depth = AttachmentImage::create(
+            instance,
+            device,
+            data,
+            extent,
+            depth_format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            depth_aspects,
+        )?;
+
+        let pass = Self::create_render_pass(device, depth_format)?;
+
+        let attachments = &[ids.view, depth.view];
+        let info = vk::FramebufferCreateInfo::builder()
+            .render_pass(pass)
+            .attachments(attachments)
+            .width(1)
+            .height(1)
+            .layers(1);
+        let framebuffer = device.create_framebuffer(&info, None)?;
+
+        // A mesma vertex das sombras (matriz pronta, só a posição)
+        let vertex_shader = spirv!("shadow.vert");
+        let fragment_shader = spirv!("picking.frag");
+
+        let bindings = Vertex::binding_descriptions();
+        let attributes = &Vertex::attribute_descriptions()[..1];
+
+        // Projeção vezes mundo na vertex, o id na fragment
+        let push_constants = &[
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size(size_of::<glm::Mat4>() as u32)
+                .build(),
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(size_of::<glm::Mat4>() as u32)
+                .size(size_of::<u32>() as u32)
+                .build(),
+        ];
+
+        let mut desc = PipelineDesc::new(&vertex_shader[..], &fragment_shader[..], pass);
+        desc.bindings = &bindings;
+        desc.attributes = attributes;
+        desc.push_constants = push_constants;
+        // Igual às malhas na cena
+        desc.front_face = vk::FrontFace::COUNTER_CLOCKWISE;
+        if data.config.reverse_z {
+            desc.depth_compare = vk::CompareOp::GREATER;
+        }
+        let pipeline = Pipeline::create(device, &desc)?;
+
+        let mut slots = vec![];
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let (buffer, memory) = create_buffer(
+                instance,
+                device,
+                data,
+                size_of::<u32>() as vk::DeviceSize,
+                vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            slots.push(ReadbackSlot {
+                buffer,
+                memory,
+                ..Default::default()
+            });
+        }
+
+        Ok(Self {
+            ids,
+            depth,
+            pass,
+            framebuffer,
+            pipeline,
+            slots,
+            request: None,
+            result: None,
+        })
+    }
+
+    unsafe fn create_render_pass(
+        device: &Device,
+        depth_format: vk::Format,
+    ) -> Result<vk::RenderPass> {
+        // Fica em TRANSFER_SRC pra cópia logo depois do pass (o grafo conta com isso)
+        let id_attachment = vk::AttachmentDescription::builder()
+            .format(ID_FORMAT)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+        let depth_attachment = vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let id_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let color_attachments = &[id_attachment_ref];
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(color_attachments)
+            .depth_stencil_attachment(&depth_attachment_ref);
+
+        // O depth é só desse pass, mas o do frame anterior ainda pode estar em uso
+        let dependencies = &[
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+                .dst_access_mask(
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .build(),
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .build(),
+        ];
+
+        let attachments = &[id_attachment, depth_attachment];
+        let subpasses = &[subpass];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(attachments)
+            .subpasses(subpasses)
+            .dependencies(dependencies);
+
+        Ok(device.create_render_pass(&info, None)?)
+    }
+
+    // Pro grafo do frame
+    pub fn image(&self) -> vk::Image {
+        self.ids.image
+    }
+
+    // Objeto no pixel (x, y) do framebuffer, pela última leitura desse mesmo pixel. Pede
+    // uma leitura nova a cada chamada, então chamar todo frame acompanha a cena; None
+    // também quando a primeira leitura ainda não chegou
+    pub fn pick(&mut self, x: u32, y: u32, extent: vk::Extent2D) -> Option<EntityId> {
+        if x >= extent.width || y >= extent.height {
+            return None;
+        }
+
+        self.request = Some((x, y));
+        self.result
+            .filter(|(pixel, _)| *pixel == (x, y))
+            .and_then(|(_, entity)| entity)
+    }
+
+    // Tem leitura pedida ou a caminho
+    pub fn is_pending(&self) -> bool {
+        self.request.is_some() || self.slots.iter().any(|s| s.pending.is_some())
+    }
+
+    // Deve ser chamado depois de esperar a fence do frame `slot`: a cópia já terminou
+    pub unsafe fn collect(&mut self, device: &Device, slot: usize) -> Result<()> {
+        let slot = &mut self.slots[slot];
+        let pixel = match slot.pending.take() {
+            Some(pixel) => pixel,
+            None => return Ok(()),
+        };
+
+        let memory = device.map_memory(
+            slot.memory,
+            0,
+            size_of::<u32>() as vk::DeviceSize,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        let id = ptr::read(memory as *const u32);
+        device.unmap_memory(slot.memory);
+
+        let entity = match id {
+            0 => None,
+            id => slot.entities.get(id as usize - 1).copied().flatten(),
+        };
+        self.result = Some((pixel, entity));
+
+        Ok(())
+    }
+
+    // Desenha os ids de `items` no pixel pedido e copia ele pro buffer do `slot`. Sem
+    // pedido não grava nada. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        meshes: &MeshRenderer,
+        uploads: &UploadQueue,
+        view_projection: &glm::Mat4,
+        items: &[DrawItem],
+    ) -> u32 {
+        let (x, y) = match self.request.take() {
+            Some(pixel) => pixel,
+            None => return 0,
+        };
+
+        let depth = if data.config.reverse_z { 0.0 } else { 1.0 };
+        let clear_values = &[
+            vk::ClearValue {
+                color: vk::ClearColorValue { uint32: [0; 4] },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil: 0 },
+            },
+        ];
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(vk::Extent2D {
+                width: 1,
+                height: 1,
+            });
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.pass)
+            .framebuffer(self.framebuffer)
+            .render_area(render_area)
+            .clear_values(clear_values);
+
+        device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+
+        // A tela inteira, com o pixel pedido na origem
+        let extent = data.swapchain.extent;
+        let viewport = vk::Viewport::builder()
+            .x(-(x as f32))
+            .y(-(y as f32))
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+
+        let draw_calls = meshes.record_ids(
+            device,
+            command_buffer,
+            uploads,
+            self.pipeline.layout,
+            view_projection,
+            items,
+        );
+        device.cmd_end_render_pass(command_buffer);
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1);
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(subresource)
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            });
+        let readback = &mut self.slots[slot];
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            self.ids.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            readback.buffer,
+            &[region],
+        );
+
+        // A CPU só lê depois da fence, mas a escrita ainda precisa ficar visível pro host
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(readback.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[barrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        readback.pending = Some((x, y));
+        readback.entities = items.iter().map(|item| item.entity).collect();
+
+        draw_calls
+    }
+
+    // Esquece o que estava a caminho
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.slots.drain(..).for_each(|s| {
+            leaks::destroy_buffer(device, s.buffer);
+            allocator::free(device, s.memory);
+        });
+        self.pipeline.destroy(device);
+        device.destroy_framebuffer(self.framebuffer, None);
+        device.destroy_render_pass(self.pass, None);
+        self.depth.destroy(device);
+        self.ids.destroy(device);
+    }
+}
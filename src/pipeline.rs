@@ -0,0 +1,698 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::Handle;
+
+use crate::{app::App, features::PortabilitySubset, leaks, MAX_FRAMES_IN_FLIGHT};
+
+// Pipelines e shader modules iguais são criados uma vez só. A chave de uma pipeline é o
+// hash da descrição inteira (SPIR-V, estado, layouts e render pass); cada `create` igual
+// conta mais uma referência, e cada `destroy` tira uma. Sem referências, a pipeline vai
+// pra fila de `release_retired`, porque ainda pode estar em frames em voo
+struct Cache {
+    // Ficam até `clear`: não são usados pela GPU, e as mesmas shaders voltam a cada
+    // recriação da swapchain
+    modules: HashMap<u64, vk::ShaderModule>,
+    pipelines: HashMap<u64, CachedPipeline>,
+    // Handle da pipeline -> chave
+    keys: HashMap<u64, u64>,
+    retired: Vec<(usize, Pipeline)>,
+    // Topologias e modos de polígono que o dispositivo aceita (ver `configure`)
+    portability: PortabilitySubset,
+}
+
+struct CachedPipeline {
+    pipeline: Pipeline,
+    references: u32,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache {
+        modules: HashMap::new(),
+        pipelines: HashMap::new(),
+        keys: HashMap::new(),
+        retired: vec![],
+        portability: PortabilitySubset::default(),
+    });
+}
+
+// Chamado ao criar o dispositivo lógico
+pub fn configure(portability: PortabilitySubset) {
+    CACHE.lock().unwrap().portability = portability;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    Opaque,
+    // src * a + dst * (1 - a)
+    Alpha,
+    // Cor já multiplicada pelo alpha: src + dst * (1 - a)
+    Premultiplied,
+    // src + dst, sem olhar o alpha
+    Additive,
+    // src * dst: tinge o que está atrás (vidro colorido)
+    Multiply,
+}
+
+impl BlendMode {
+    // Os que misturam com o que já está no alvo, e por isso precisam ser desenhados de
+    // trás pra frente e sem escrever depth
+    pub fn is_transparent(self) -> bool {
+        self != BlendMode::Opaque
+    }
+}
+
+// Valor de uma specialization constant (`layout(constant_id = N) const ...` na shader).
+// Todos ocupam 4 bytes; bool vira VkBool32
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SpecValue {
+    Bool(bool),
+    Int(i32),
+    Uint(u32),
+    Float(f32),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpecConstant {
+    pub id: u32,
+    pub value: SpecValue,
+}
+
+impl SpecConstant {
+    pub fn bool(id: u32, value: bool) -> Self {
+        Self {
+            id,
+            value: SpecValue::Bool(value),
+        }
+    }
+
+    pub fn int(id: u32, value: i32) -> Self {
+        Self {
+            id,
+            value: SpecValue::Int(value),
+        }
+    }
+
+    pub fn uint(id: u32, value: u32) -> Self {
+        Self {
+            id,
+            value: SpecValue::Uint(value),
+        }
+    }
+
+    pub fn float(id: u32, value: f32) -> Self {
+        Self {
+            id,
+            value: SpecValue::Float(value),
+        }
+    }
+
+    fn bytes(&self) -> [u8; 4] {
+        match self.value {
+            SpecValue::Bool(value) => (value as vk::Bool32).to_ne_bytes(),
+            SpecValue::Int(value) => value.to_ne_bytes(),
+            SpecValue::Uint(value) => value.to_ne_bytes(),
+            SpecValue::Float(value) => value.to_ne_bytes(),
+        }
+    }
+}
+
+// As constantes em sequência, como o vk::SpecializationInfo quer. A mesma vale pra todos
+// os estágios: cada shader só lê os ids que declara
+struct Specialization {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl Specialization {
+    fn new(constants: &[SpecConstant]) -> Self {
+        let mut entries = vec![];
+        let mut data = vec![];
+        for constant in constants {
+            entries.push(
+                vk::SpecializationMapEntry::builder()
+                    .constant_id(constant.id)
+                    .offset(data.len() as u32)
+                    .size(4)
+                    .build(),
+            );
+            data.extend_from_slice(&constant.bytes());
+        }
+
+        Self { entries, data }
+    }
+
+    fn info(&self) -> vk::SpecializationInfoBuilder<'_> {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.entries)
+            .data(&self.data)
+    }
+}
+
+fn hash_specialization(hasher: &mut DefaultHasher, constants: &[SpecConstant]) {
+    for constant in constants {
+        (constant.id, constant.bytes()).hash(hasher);
+    }
+}
+
+// Shaders de tessellation (o dispositivo precisa do Feature::TessellationShader). Com
+// elas a topologia tem que ser PATCH_LIST
+#[derive(Copy, Clone, Debug)]
+pub struct TessellationStages<'a> {
+    pub control_shader: &'a [u8],
+    pub evaluation_shader: &'a [u8],
+    // Vértices por patch
+    pub patch_control_points: u32,
+}
+
+// Teste e escrita de stencil, iguais pras faces da frente e de trás. A referência é
+// dinâmica (cmd_set_stencil_reference), então a mesma pipeline marca valores diferentes.
+// O depth do render pass precisa ter stencil (ver image::get_depth_format)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StencilState {
+    pub compare: vk::CompareOp,
+    // Falhou no stencil, passou nos dois, passou no stencil e falhou no depth
+    pub fail: vk::StencilOp,
+    pub pass: vk::StencilOp,
+    pub depth_fail: vk::StencilOp,
+    pub compare_mask: u32,
+    pub write_mask: u32,
+}
+
+impl StencilState {
+    // Escreve a referência em tudo que desenhar, passando ou não no depth
+    pub fn write() -> Self {
+        Self {
+            compare: vk::CompareOp::ALWAYS,
+            fail: vk::StencilOp::KEEP,
+            pass: vk::StencilOp::REPLACE,
+            depth_fail: vk::StencilOp::REPLACE,
+            compare_mask: 0xff,
+            write_mask: 0xff,
+        }
+    }
+
+    // Só desenha onde o stencil é diferente da referência, sem mexer nele
+    pub fn not_equal() -> Self {
+        Self {
+            compare: vk::CompareOp::NOT_EQUAL,
+            fail: vk::StencilOp::KEEP,
+            pass: vk::StencilOp::KEEP,
+            depth_fail: vk::StencilOp::KEEP,
+            compare_mask: 0xff,
+            write_mask: 0,
+        }
+    }
+
+    fn vk(&self) -> vk::StencilOpState {
+        vk::StencilOpState {
+            fail_op: self.fail,
+            pass_op: self.pass,
+            depth_fail_op: self.depth_fail,
+            compare_op: self.compare,
+            compare_mask: self.compare_mask,
+            write_mask: self.write_mask,
+            reference: 0,
+        }
+    }
+}
+
+// Descrição de uma pipeline gráfica. Viewport e scissor são dinâmicos, então a pipeline
+// só precisa ser recriada quando o render pass muda
+#[derive(Clone, Debug)]
+pub struct PipelineDesc<'a> {
+    pub vertex_shader: &'a [u8],
+    pub fragment_shader: &'a [u8],
+    pub tessellation: Option<TessellationStages<'a>>,
+    pub bindings: &'a [vk::VertexInputBindingDescription],
+    pub attributes: &'a [vk::VertexInputAttributeDescription],
+    pub set_layouts: &'a [vk::DescriptorSetLayout],
+    pub push_constants: &'a [vk::PushConstantRange],
+    // Permutações da mesma shader (número de luzes, toggles...) sem pré-processar o GLSL
+    pub specialization: &'a [SpecConstant],
+    pub topology: vk::PrimitiveTopology,
+    // LINE (wireframe) precisa do Feature::FillModeNonSolid
+    pub polygon_mode: vk::PolygonMode,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    // GREATER quando a câmera usa reverse-Z
+    pub depth_compare: vk::CompareOp,
+    // Bias de profundidade ligado, com os valores vindo do cmd_set_depth_bias
+    pub dynamic_depth_bias: bool,
+    // None desliga o teste de stencil
+    pub stencil: Option<StencilState>,
+    pub blend: BlendMode,
+    // Canais escritos em todos os attachments de cor. Vazio, a pipeline só mexe em depth
+    // e stencil; sem o A, o alpha do alvo fica como estava (os decals no G-buffer)
+    pub color_mask: vk::ColorComponentFlags,
+    // Quantos attachments de cor o subpass tem (0 nos passes só de profundidade)
+    pub color_attachments: u32,
+    pub render_pass: vk::RenderPass,
+    pub subpass: u32,
+}
+
+impl<'a> PipelineDesc<'a> {
+    pub fn new(
+        vertex_shader: &'a [u8],
+        fragment_shader: &'a [u8],
+        render_pass: vk::RenderPass,
+    ) -> Self {
+        Self {
+            vertex_shader,
+            fragment_shader,
+            tessellation: None,
+            bindings: &[],
+            attributes: &[],
+            set_layouts: &[],
+            push_constants: &[],
+            specialization: &[],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::CLOCKWISE,
+            depth_test: true,
+            depth_write: true,
+            depth_compare: vk::CompareOp::LESS,
+            dynamic_depth_bias: false,
+            stencil: None,
+            blend: BlendMode::Opaque,
+            color_mask: vk::ColorComponentFlags::all(),
+            color_attachments: 1,
+            render_pass,
+            subpass: 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Pipeline {
+    pub layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+}
+
+impl<'a> PipelineDesc<'a> {
+    fn key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.vertex_shader.hash(&mut hasher);
+        self.fragment_shader.hash(&mut hasher);
+        if let Some(tessellation) = &self.tessellation {
+            tessellation.control_shader.hash(&mut hasher);
+            tessellation.evaluation_shader.hash(&mut hasher);
+            tessellation.patch_control_points.hash(&mut hasher);
+        }
+        for binding in self.bindings {
+            (binding.binding, binding.stride, binding.input_rate).hash(&mut hasher);
+        }
+        for attribute in self.attributes {
+            (
+                attribute.location,
+                attribute.binding,
+                attribute.format,
+                attribute.offset,
+            )
+                .hash(&mut hasher);
+        }
+        hash_layout(&mut hasher, self.set_layouts, self.push_constants);
+        hash_specialization(&mut hasher, self.specialization);
+        (
+            self.topology,
+            self.polygon_mode,
+            self.cull_mode,
+            self.front_face,
+            self.depth_test,
+            self.depth_write,
+            self.depth_compare,
+            self.dynamic_depth_bias,
+            self.blend,
+            self.color_attachments,
+            self.render_pass.as_raw(),
+            self.subpass,
+        )
+            .hash(&mut hasher);
+        (self.stencil, self.color_mask.bits()).hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+fn hash_layout(
+    hasher: &mut DefaultHasher,
+    set_layouts: &[vk::DescriptorSetLayout],
+    push_constants: &[vk::PushConstantRange],
+) {
+    for layout in set_layouts {
+        layout.as_raw().hash(hasher);
+    }
+    for range in push_constants {
+        (range.stage_flags, range.offset, range.size).hash(hasher);
+    }
+}
+
+// Shader module do cache, criado na primeira vez que o SPIR-V aparece
+unsafe fn shader_module(device: &Device, bytecode: &[u8]) -> Result<vk::ShaderModule> {
+    let mut hasher = DefaultHasher::new();
+    bytecode.hash(&mut hasher);
+    let key = hasher.finish();
+
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(module) = cache.modules.get(&key) {
+        return Ok(*module);
+    }
+
+    let module = App::create_shader_module(device, bytecode)?;
+    cache.modules.insert(key, module);
+    Ok(module)
+}
+
+// A pipeline com essa chave, se já existe, com mais uma referência
+fn reuse(key: u64) -> Option<Pipeline> {
+    let mut cache = CACHE.lock().unwrap();
+    let cached = cache.pipelines.get_mut(&key)?;
+    cached.references += 1;
+    Some(cached.pipeline)
+}
+
+fn insert(key: u64, pipeline: Pipeline) -> Pipeline {
+    let mut cache = CACHE.lock().unwrap();
+    cache.keys.insert(pipeline.pipeline.as_raw(), key);
+    cache.pipelines.insert(
+        key,
+        CachedPipeline {
+            pipeline,
+            references: 1,
+        },
+    );
+    pipeline
+}
+
+// Chamado uma vez por frame, depois de esperar a fence dele
+pub unsafe fn release_retired(device: &Device) {
+    let mut cache = CACHE.lock().unwrap();
+    for (frames, _) in &mut cache.retired {
+        *frames -= 1;
+    }
+
+    let (done, retired) = cache
+        .retired
+        .drain(..)
+        .partition::<Vec<_>, _>(|(frames, _)| *frames == 0);
+    cache.retired = retired;
+    done.iter().for_each(|(_, p)| p.destroy_now(device));
+}
+
+// Destrói tudo, inclusive o que ainda tem referências (que seria um vazamento). Só com o
+// dispositivo parado
+pub unsafe fn clear(device: &Device) {
+    let mut cache = CACHE.lock().unwrap();
+    cache
+        .retired
+        .drain(..)
+        .for_each(|(_, p)| p.destroy_now(device));
+    cache
+        .pipelines
+        .drain()
+        .for_each(|(_, c)| c.pipeline.destroy_now(device));
+    cache.keys.clear();
+    cache
+        .modules
+        .drain()
+        .for_each(|(_, m)| device.destroy_shader_module(m, None));
+}
+
+impl Pipeline {
+    pub unsafe fn create(device: &Device, desc: &PipelineDesc) -> Result<Self> {
+        let portability = CACHE.lock().unwrap().portability;
+        if !portability.allows_topology(desc.topology) {
+            return Err(anyhow!("Triangle fans are not supported by this device."));
+        }
+        if !portability.allows_polygon_mode(desc.polygon_mode) {
+            return Err(anyhow!(
+                "Point polygon mode is not supported by this device."
+            ));
+        }
+
+        let key = desc.key();
+        if let Some(pipeline) = reuse(key) {
+            return Ok(pipeline);
+        }
+
+        let vertex_shader_module = shader_module(device, desc.vertex_shader)?;
+        let fragment_shader_module = shader_module(device, desc.fragment_shader)?;
+
+        let specialization = Specialization::new(desc.specialization);
+        let specialization_info = specialization.info();
+
+        let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertex_shader_module)
+            .name(b"main\0")
+            .specialization_info(&specialization_info);
+
+        let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragment_shader_module)
+            .name(b"main\0")
+            .specialization_info(&specialization_info);
+
+        let mut stages = vec![vert_stage.build(), frag_stage.build()];
+        let mut tessellation_state = vk::PipelineTessellationStateCreateInfo::builder();
+        if let Some(tessellation) = &desc.tessellation {
+            let control = shader_module(device, tessellation.control_shader)?;
+            let evaluation = shader_module(device, tessellation.evaluation_shader)?;
+
+            for (stage, module) in [
+                (vk::ShaderStageFlags::TESSELLATION_CONTROL, control),
+                (vk::ShaderStageFlags::TESSELLATION_EVALUATION, evaluation),
+            ] {
+                let info = vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(stage)
+                    .module(module)
+                    .name(b"main\0")
+                    .specialization_info(&specialization_info);
+                stages.push(info.build());
+            }
+
+            tessellation_state =
+                tessellation_state.patch_control_points(tessellation.patch_control_points);
+        }
+
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(desc.bindings)
+            .vertex_attribute_descriptions(desc.attributes);
+
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(desc.topology)
+            .primitive_restart_enable(false);
+
+        // Só a quantidade importa, os valores vêm do cmd_set_viewport/cmd_set_scissor
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        if desc.dynamic_depth_bias {
+            dynamic_states.push(vk::DynamicState::DEPTH_BIAS);
+        }
+        if desc.stencil.is_some() {
+            dynamic_states.push(vk::DynamicState::STENCIL_REFERENCE);
+        }
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(desc.polygon_mode)
+            .line_width(1.0)
+            .cull_mode(desc.cull_mode)
+            .front_face(desc.front_face)
+            .depth_bias_enable(desc.dynamic_depth_bias);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .sample_shading_enable(false)
+            .rasterization_samples(vk::SampleCountFlags::_1);
+
+        let mut depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(desc.depth_test)
+            .depth_write_enable(desc.depth_write)
+            .depth_compare_op(desc.depth_compare)
+            .depth_bounds_test_enable(false)
+            .stencil_test_enable(desc.stencil.is_some());
+        if let Some(stencil) = &desc.stencil {
+            depth_stencil_state = depth_stencil_state.front(stencil.vk()).back(stencil.vk());
+        }
+
+        let src_color_blend_factor = match desc.blend {
+            BlendMode::Premultiplied | BlendMode::Additive => vk::BlendFactor::ONE,
+            BlendMode::Multiply => vk::BlendFactor::DST_COLOR,
+            _ => vk::BlendFactor::SRC_ALPHA,
+        };
+        let dst_blend_factor = match desc.blend {
+            BlendMode::Additive => vk::BlendFactor::ONE,
+            BlendMode::Multiply => vk::BlendFactor::ZERO,
+            _ => vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        };
+
+        let attachment = vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(desc.color_mask)
+            .blend_enable(desc.blend != BlendMode::Opaque)
+            .src_color_blend_factor(src_color_blend_factor)
+            .dst_color_blend_factor(dst_blend_factor)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(dst_blend_factor)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .build();
+
+        let attachments = vec![attachment; desc.color_attachments as usize];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .logic_op(vk::LogicOp::COPY)
+            .attachments(&attachments)
+            .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(desc.set_layouts)
+            .push_constant_ranges(desc.push_constants);
+        let layout = device.create_pipeline_layout(&layout_info, None)?;
+
+        let mut info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(layout)
+            .render_pass(desc.render_pass)
+            .subpass(desc.subpass);
+        if desc.tessellation.is_some() {
+            info = info.tessellation_state(&tessellation_state);
+        }
+
+        let pipeline = device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0[0];
+        leaks::created(pipeline);
+
+        Ok(insert(key, Self { layout, pipeline }))
+    }
+
+    // Pipeline de compute: só a shader e o layout
+    pub unsafe fn create_compute(
+        device: &Device,
+        shader: &[u8],
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constants: &[vk::PushConstantRange],
+    ) -> Result<Self> {
+        Self::create_compute_specialized(device, shader, set_layouts, push_constants, &[])
+    }
+
+    // Com specialization constants, que também podem dar o tamanho do grupo
+    // (`local_size_x_id`)
+    pub unsafe fn create_compute_specialized(
+        device: &Device,
+        shader: &[u8],
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constants: &[vk::PushConstantRange],
+        specialization: &[SpecConstant],
+    ) -> Result<Self> {
+        let mut hasher = DefaultHasher::new();
+        "compute".hash(&mut hasher);
+        shader.hash(&mut hasher);
+        hash_layout(&mut hasher, set_layouts, push_constants);
+        hash_specialization(&mut hasher, specialization);
+        let key = hasher.finish();
+        if let Some(pipeline) = reuse(key) {
+            return Ok(pipeline);
+        }
+
+        let module = shader_module(device, shader)?;
+        let specialization = Specialization::new(specialization);
+        let specialization_info = specialization.info();
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(b"main\0")
+            .specialization_info(&specialization_info);
+
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constants);
+        let layout = device.create_pipeline_layout(&layout_info, None)?;
+
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(layout);
+
+        let pipeline = device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[info], None)?
+            .0[0];
+        leaks::created(pipeline);
+
+        Ok(insert(key, Self { layout, pipeline }))
+    }
+
+    // Viewport e scissor cobrindo a imagem toda
+    pub unsafe fn set_viewport(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+    ) {
+        let viewport = vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+
+        let scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D { x: 0, y: 0 })
+            .extent(extent);
+
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+    }
+
+    // Solta uma referência. A última manda a pipeline pra fila de `release_retired`
+    pub unsafe fn destroy(&self, device: &Device) {
+        if self.pipeline.is_null() {
+            device.destroy_pipeline_layout(self.layout, None);
+            return;
+        }
+
+        let mut cache = CACHE.lock().unwrap();
+        let key = match cache.keys.get(&self.pipeline.as_raw()) {
+            Some(key) => *key,
+            None => {
+                drop(cache);
+                self.destroy_now(device);
+                return;
+            }
+        };
+
+        let cached = cache.pipelines.get_mut(&key).unwrap();
+        cached.references -= 1;
+        if cached.references == 0 {
+            cache.pipelines.remove(&key);
+            cache.keys.remove(&self.pipeline.as_raw());
+            cache.retired.push((MAX_FRAMES_IN_FLIGHT, *self));
+        }
+    }
+
+    unsafe fn destroy_now(&self, device: &Device) {
+        leaks::destroy_pipeline(device, self.pipeline);
+        device.destroy_pipeline_layout(self.layout, None);
+    }
+}
@@ -0,0 +1,473 @@
+use anyhow::{anyhow, Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    image::AttachmentImage,
+    info::QueueFamilyIndices,
+    pass::HDR_FORMAT,
+    pipeline::{Pipeline, SpecConstant},
+    sampler::{self, SamplerDesc},
+    shader::spirv,
+};
+
+// Tamanho do grupo da cadeia, que chega nas shaders pelas specialization constants 0 e 1
+// (`local_size_x_id = 0, local_size_y_id = 1`)
+pub const POST_GROUP_SIZE: u32 = 8;
+// Push constants de cada efeito; 128 bytes é o que toda implementação garante
+pub const MAX_POST_CONSTANTS: usize = 128;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PostEffectId(usize);
+
+// Efeito de tela cheia em compute. A shader lê a imagem anterior no binding 0 (sampler2D,
+// linear) e escreve a próxima no 1 (image2D rgba16f), em grupos de POST_GROUP_SIZE, com o
+// que veio de `set_constants` nas push constants. Ver vignette.comp
+#[derive(Clone, Debug)]
+struct PostEffect {
+    name: String,
+    // Guardado pra recriar a pipeline depois de perder o dispositivo
+    shader: Vec<u8>,
+    enabled: bool,
+    constants: Vec<u8>,
+    pipeline: Pipeline,
+}
+
+// Cadeia de efeitos em compute em cima do alvo HDR, antes do tone mapping. Os efeitos
+// ligados rodam em ordem, alternando entre duas imagens, e o resultado é copiado de volta
+// pro alvo HDR, então quem vem depois não precisa saber se ela rodou. Ligar e desligar
+// efeitos não recria nada
+#[derive(Clone, Debug, Default)]
+pub struct PostChain {
+    effects: Vec<PostEffect>,
+    // A fila de gráficos também faz compute (quase sempre); se não fizer, nada roda
+    supported: bool,
+    sampler: vk::Sampler,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    // Alvo HDR -> 0, 0 -> 1 e 1 -> 0
+    sets: [vk::DescriptorSet; 3],
+    // Do tamanho da swapchain, recriadas com ela
+    targets: Vec<AttachmentImage>,
+}
+
+impl PostChain {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let mut chain = Self::default();
+        chain.create_device_objects(instance, device, data)?;
+        chain.create_targets(instance, device, data)?;
+
+        // Já vem na cadeia, desligado
+        let vignette = spirv!("vignette.comp");
+        let id = chain.add(device, "Vignette", &vignette[..])?;
+        chain.set_constants(id, &[0.5f32.to_ne_bytes(), 0.4f32.to_ne_bytes()].concat())?;
+        chain.set_enabled(id, false);
+
+        Ok(chain)
+    }
+
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+        let families = instance.get_physical_device_queue_family_properties(data.physical_device);
+        self.supported = families[indices.graphics as usize]
+            .queue_flags
+            .contains(vk::QueueFlags::COMPUTE);
+        if !self.supported {
+            warn!("Graphics queue has no compute support, post effects disabled.");
+        }
+
+        // Linear, pra efeitos que amostram entre os pixels (distorção, blur)
+        self.sampler = sampler::get(device, &SamplerDesc::default())?;
+
+        let bindings = &[
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+        ];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(3)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(3)
+                .build(),
+        ];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(3);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[self.layout; 3];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(set_layouts);
+        let sets = device.allocate_descriptor_sets(&info)?;
+        self.sets.copy_from_slice(&sets);
+
+        for effect in &mut self.effects {
+            effect.pipeline = Self::create_pipeline(device, self.layout, &effect.shader)?;
+        }
+
+        Ok(())
+    }
+
+    unsafe fn create_pipeline(
+        device: &Device,
+        layout: vk::DescriptorSetLayout,
+        shader: &[u8],
+    ) -> Result<Pipeline> {
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(MAX_POST_CONSTANTS as u32)
+            .build()];
+        let specialization = &[
+            SpecConstant::uint(0, POST_GROUP_SIZE),
+            SpecConstant::uint(1, POST_GROUP_SIZE),
+        ];
+        Pipeline::create_compute_specialized(
+            device,
+            shader,
+            &[layout],
+            push_constants,
+            specialization,
+        )
+    }
+
+    // As duas imagens do ping-pong, e os sets que ligam elas ao alvo HDR
+    pub unsafe fn create_targets(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        for _ in 0..2 {
+            self.targets.push(AttachmentImage::create(
+                instance,
+                device,
+                data,
+                data.swapchain.extent,
+                HDR_FORMAT,
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+                vk::ImageAspectFlags::COLOR,
+            )?);
+        }
+
+        let links = [
+            (data.render_pass.color.view, self.targets[0].view),
+            (self.targets[0].view, self.targets[1].view),
+            (self.targets[1].view, self.targets[0].view),
+        ];
+        for (set, (input, output)) in self.sets.iter().zip(links) {
+            let input_info = &[vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(input)
+                .sampler(self.sampler)
+                .build()];
+            let input_write = vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(input_info);
+
+            let output_info = &[vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(output)
+                .build()];
+            let output_write = vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(output_info);
+
+            device.update_descriptor_sets(
+                &[input_write, output_write],
+                &[] as &[vk::CopyDescriptorSet],
+            );
+        }
+
+        Ok(())
+    }
+
+    // Põe um efeito no fim da cadeia, já ligado. `shader` é o SPIR-V do compute
+    pub unsafe fn add(
+        &mut self,
+        device: &Device,
+        name: &str,
+        shader: &[u8],
+    ) -> Result<PostEffectId> {
+        let pipeline = Self::create_pipeline(device, self.layout, shader)?;
+        self.effects.push(PostEffect {
+            name: name.to_string(),
+            shader: shader.to_vec(),
+            enabled: true,
+            constants: vec![],
+            pipeline,
+        });
+
+        Ok(PostEffectId(self.effects.len() - 1))
+    }
+
+    pub fn find(&self, name: &str) -> Option<PostEffectId> {
+        self.effects
+            .iter()
+            .position(|e| e.name == name)
+            .map(PostEffectId)
+    }
+
+    // Todos os efeitos, na ordem em que rodam, com o nome e se estão ligados
+    pub fn effects(&self) -> impl Iterator<Item = (PostEffectId, &str, bool)> {
+        self.effects
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (PostEffectId(i), e.name.as_str(), e.enabled))
+    }
+
+    pub fn is_enabled(&self, id: PostEffectId) -> bool {
+        self.effects[id.0].enabled
+    }
+
+    pub fn set_enabled(&mut self, id: PostEffectId, enabled: bool) {
+        self.effects[id.0].enabled = enabled;
+    }
+
+    // Vai pras push constants do efeito a cada frame
+    pub fn set_constants(&mut self, id: PostEffectId, constants: &[u8]) -> Result<()> {
+        if constants.len() > MAX_POST_CONSTANTS {
+            return Err(anyhow!(
+                "Post effect constants are {} bytes, the limit is {}.",
+                constants.len(),
+                MAX_POST_CONSTANTS
+            ));
+        }
+
+        self.effects[id.0].constants = constants.to_vec();
+        Ok(())
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.supported
+    }
+
+    // Entre a cena e o tone mapping, com o alvo HDR em SHADER_READ_ONLY_OPTIMAL. As
+    // barreiras entre os efeitos ficam por aqui; pro grafo o pass só lê e copia no alvo
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        let enabled = self
+            .effects
+            .iter()
+            .filter(|e| e.enabled)
+            .collect::<Vec<_>>();
+        if !self.supported || enabled.is_empty() {
+            return;
+        }
+
+        let extent = data.swapchain.extent;
+        for (index, effect) in enabled.iter().enumerate() {
+            // O primeiro lê o alvo HDR; depois alterna entre as duas imagens
+            let (set, target) = match index {
+                0 => (self.sets[0], 0),
+                i if i % 2 == 1 => (self.sets[1], 1),
+                _ => (self.sets[2], 0),
+            };
+            let image = self.targets[target].image;
+
+            // O conteúdo antigo não importa, só espera quem leu ele (o efeito anterior ou a
+            // cópia do frame passado)
+            Self::barrier(
+                device,
+                command_buffer,
+                image,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::GENERAL,
+                vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+            );
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                effect.pipeline.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                effect.pipeline.layout,
+                0,
+                &[set],
+                &[],
+            );
+            if !effect.constants.is_empty() {
+                device.cmd_push_constants(
+                    command_buffer,
+                    effect.pipeline.layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    &effect.constants,
+                );
+            }
+            device.cmd_dispatch(
+                command_buffer,
+                (extent.width + POST_GROUP_SIZE - 1) / POST_GROUP_SIZE,
+                (extent.height + POST_GROUP_SIZE - 1) / POST_GROUP_SIZE,
+                1,
+            );
+
+            // O próximo efeito lê numa shader; o último vai pra cópia
+            let (layout, stage, access) = if index == enabled.len() - 1 {
+                (
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_READ,
+                )
+            } else {
+                (
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ,
+                )
+            };
+            Self::barrier(
+                device,
+                command_buffer,
+                image,
+                vk::ImageLayout::GENERAL,
+                layout,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+                stage,
+                access,
+            );
+        }
+
+        // O resultado volta pro alvo HDR. O primeiro efeito já leu ele
+        let output = self.targets[(enabled.len() - 1) % 2].image;
+        let color = data.render_pass.color.image;
+        Self::barrier(
+            device,
+            command_buffer,
+            color,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        );
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+        let region = vk::ImageCopy::builder()
+            .src_subresource(subresource)
+            .dst_subresource(subresource)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            });
+        device.cmd_copy_image(
+            command_buffer,
+            output,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            color,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region],
+        );
+
+        // Quem vem depois é do grafo, que cuida da visibilidade da cópia
+        Self::barrier(
+            device,
+            command_buffer,
+            color,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+    }
+
+    unsafe fn barrier(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) {
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+    }
+
+    pub unsafe fn destroy_targets(&mut self, device: &Device) {
+        self.targets.drain(..).for_each(|t| t.destroy(device));
+    }
+
+    // Os efeitos continuam na lista, pra voltar com `create_device_objects`
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.effects.iter().for_each(|e| e.pipeline.destroy(device));
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+    }
+}
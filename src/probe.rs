@@ -0,0 +1,484 @@
+use anyhow::Result;
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator,
+    app::AppData,
+    image::{self, TrackedImage},
+    leaks,
+    mesh::MeshRenderer,
+    multiview::{MultiviewRenderer, View},
+    pass::HDR_FORMAT,
+    pipeline::Pipeline,
+    sampler::{self, SamplerDesc},
+    scene::DrawItem,
+    shader::spirv,
+    uniforms::ReflectionProbeUniform,
+    upload::UploadQueue,
+};
+
+// Lado de cada face na captura. Os mips vão até 8x8, que já é o lobo de rugosidade 1
+const PROBE_SIZE: u32 = 128;
+const PROBE_MIPS: u32 = 5;
+const PREFILTER_GROUP_SIZE: u32 = 8;
+
+// Planos das faces na captura
+const PROBE_NEAR: f32 = 0.05;
+const PROBE_FAR: f32 = 100.0;
+
+// Cubo com a cena vista de um ponto, pros reflexos especulares de quem está perto dele.
+// A captura desenha as 6 faces num pass só com multiview (ver multiview.rs), copia pro
+// mip 0 do cubo e um compute filtra os outros mips com o GGX, cada um numa rugosidade.
+// Só captura quando pedido (`place`, `capture`): a cena parada não precisa de outra.
+// O cubo existe mesmo desligado, porque o set 0 sempre aponta pra ele
+#[derive(Debug)]
+pub struct ReflectionProbe {
+    // Posição e raio de influência; None sem sonda
+    placement: Option<(glm::Vec3, f32)>,
+    pub intensity: f32,
+    // Captura pedida pro próximo frame
+    dirty: bool,
+    // O cubo tem a cena do `placement` atual
+    captured: bool,
+    // Com o `reflection_probes` da configuração e um dispositivo com multiview
+    capture: Option<MultiviewRenderer>,
+    cube: TrackedImage,
+    memory: vk::DeviceMemory,
+    // Todos os mips, lidos pela cena
+    view: vk::ImageView,
+    // Só o mip 0, lido pelo filtro
+    source_view: vk::ImageView,
+    // Do mip 1 em diante, escritos pelo filtro
+    mip_views: Vec<vk::ImageView>,
+    pub sampler: vk::Sampler,
+    set_layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    prefilter: Pipeline,
+}
+
+impl ReflectionProbe {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let mut probe = Self {
+            placement: None,
+            intensity: 1.0,
+            dirty: false,
+            captured: false,
+            capture: None,
+            cube: TrackedImage::default(),
+            memory: vk::DeviceMemory::null(),
+            view: vk::ImageView::null(),
+            source_view: vk::ImageView::null(),
+            mip_views: vec![],
+            sampler: vk::Sampler::null(),
+            set_layout: vk::DescriptorSetLayout::null(),
+            pool: vk::DescriptorPool::null(),
+            sets: vec![],
+            prefilter: Pipeline::default(),
+        };
+
+        probe.create_device_objects(instance, device, data)?;
+        Ok(probe)
+    }
+
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        self.capture = if !data.config.reflection_probes {
+            None
+        } else if !data.capabilities.multiview {
+            warn!("Reflection probes need VK_KHR_multiview, disabling them.");
+            None
+        } else {
+            Some(MultiviewRenderer::create_cube(
+                instance, device, data, PROBE_SIZE,
+            )?)
+        };
+        // O cubo novo está vazio
+        self.captured = false;
+        self.dirty = self.placement.is_some();
+
+        let (cube, memory) = image::create_cube_mips(
+            instance,
+            device,
+            data,
+            PROBE_SIZE,
+            PROBE_MIPS,
+            HDR_FORMAT,
+            vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.cube = TrackedImage::new(cube, vk::ImageAspectFlags::COLOR);
+        self.memory = memory;
+        self.view = image::create_cube_view(
+            device,
+            cube,
+            HDR_FORMAT,
+            vk::ImageViewType::CUBE,
+            0,
+            PROBE_MIPS,
+        )?;
+        self.source_view =
+            image::create_cube_view(device, cube, HDR_FORMAT, vk::ImageViewType::CUBE, 0, 1)?;
+        self.mip_views = (1..PROBE_MIPS)
+            .map(|mip| {
+                image::create_cube_view(
+                    device,
+                    cube,
+                    HDR_FORMAT,
+                    vk::ImageViewType::_2D_ARRAY,
+                    mip,
+                    1,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.sampler = sampler::get(
+            device,
+            &SamplerDesc {
+                mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+                max_lod: PROBE_MIPS as f32,
+                ..Default::default()
+            },
+        )?;
+
+        // A captura no binding 0, o mip escrito no 1
+        let bindings = &[
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+        ];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let filtered = PROBE_MIPS - 1;
+        let pool_sizes = &[
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(filtered)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(filtered)
+                .build(),
+        ];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(filtered);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = vec![self.set_layout; filtered as usize];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(&set_layouts);
+        self.sets = device.allocate_descriptor_sets(&info)?;
+
+        // O cubo inteiro fica em GENERAL durante o filtro: o mip 0 é lido enquanto os
+        // outros são escritos
+        let source_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.source_view)
+            .sampler(self.sampler)
+            .build()];
+        for (set, view) in self.sets.iter().zip(&self.mip_views) {
+            let target_info = &[vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(*view)
+                .build()];
+            let writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(source_info),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(1)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(target_info),
+            ];
+            device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+        }
+
+        // Só a rugosidade do mip
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(4)
+            .build()];
+        let shader = spirv!("prefilter.comp");
+        self.prefilter =
+            Pipeline::create_compute(device, &shader[..], &[self.set_layout], push_constants)?;
+
+        Ok(())
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    // Põe a sonda em `position`, afetando o que estiver a menos de `radius` dela, e
+    // captura no próximo frame
+    pub fn place(&mut self, position: glm::Vec3, radius: f32) {
+        self.placement = Some((position, radius.max(0.0)));
+        self.captured = false;
+        self.dirty = true;
+    }
+
+    pub fn remove(&mut self) {
+        self.placement = None;
+        self.captured = false;
+        self.dirty = false;
+    }
+
+    // Captura de novo no mesmo lugar, pra quando a cena em volta mudou
+    pub fn capture(&mut self) {
+        self.dirty = self.placement.is_some();
+    }
+
+    pub fn placement(&self) -> Option<(glm::Vec3, f32)> {
+        self.placement
+    }
+
+    // Cubo com todos os mips, em SHADER_READ_ONLY_OPTIMAL na hora do pass da cena
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    // Zerado (sem reflexo) até a primeira captura terminar
+    pub fn uniform(&self) -> ReflectionProbeUniform {
+        match self.placement {
+            Some((position, radius)) if self.captured => ReflectionProbeUniform {
+                position: glm::vec4(position.x, position.y, position.z, radius),
+                params: glm::vec4((PROBE_MIPS - 1) as f32, self.intensity, 0.0, 0.0),
+            },
+            _ => ReflectionProbeUniform::default(),
+        }
+    }
+
+    // Antes do pass da cena. Com uma captura pedida, desenha as 6 faces, copia pro cubo e
+    // filtra os mips; sem, só deixa o cubo no layout que o set 0 espera. Retorna quantos
+    // draw calls fez
+    pub unsafe fn record(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        meshes: &MeshRenderer,
+        uploads: &UploadQueue,
+        sun_direction: glm::Vec3,
+        ambient: f32,
+        items: &[DrawItem],
+    ) -> u32 {
+        let (capture, position) = match (&self.capture, self.placement) {
+            (Some(capture), Some((position, _))) if self.dirty => (capture, position),
+            _ => {
+                self.cube.transition_to(
+                    device,
+                    command_buffer,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::AccessFlags::SHADER_READ,
+                );
+                return 0;
+            }
+        };
+
+        // A lista inteira: o culling da câmera não vale pras faces
+        let views = View::cube(position, PROBE_NEAR, PROBE_FAR, data.config.reverse_z);
+        let draw_calls = capture.record(
+            device,
+            data,
+            command_buffer,
+            slot,
+            meshes,
+            uploads,
+            &views,
+            sun_direction,
+            ambient,
+            items,
+        );
+
+        // O render pass deixa a captura em SHADER_READ_ONLY; a cópia quer TRANSFER_SRC
+        Self::barrier(
+            device,
+            command_buffer,
+            capture.image(),
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_READ,
+        );
+        self.cube.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        );
+
+        let layers = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(6)
+            .build();
+        let region = vk::ImageCopy::builder()
+            .src_subresource(layers)
+            .src_offset(vk::Offset3D::default())
+            .dst_subresource(layers)
+            .dst_offset(vk::Offset3D::default())
+            .extent(vk::Extent3D {
+                width: PROBE_SIZE,
+                height: PROBE_SIZE,
+                depth: 1,
+            });
+        device.cmd_copy_image(
+            command_buffer,
+            capture.image(),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.cube.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region],
+        );
+
+        // Volta pro layout do fim do render pass, e a próxima captura espera a cópia
+        Self::barrier(
+            device,
+            command_buffer,
+            capture.image(),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::empty(),
+        );
+        self.cube.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.prefilter.pipeline,
+        );
+        // Todos leem só o mip 0, então um mip não espera o outro
+        for (index, set) in self.sets.iter().enumerate() {
+            let mip = index as u32 + 1;
+            let roughness = mip as f32 / (PROBE_MIPS - 1) as f32;
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.prefilter.layout,
+                0,
+                &[*set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.prefilter.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &roughness.to_ne_bytes(),
+            );
+
+            let size = (PROBE_SIZE >> mip).max(1);
+            let groups = (size + PREFILTER_GROUP_SIZE - 1) / PREFILTER_GROUP_SIZE;
+            device.cmd_dispatch(command_buffer, groups, groups, 6);
+        }
+
+        self.cube.transition_to(
+            device,
+            command_buffer,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        self.dirty = false;
+        self.captured = true;
+        draw_calls
+    }
+
+    unsafe fn barrier(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) {
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(6);
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.prefilter.destroy(device);
+        device.destroy_descriptor_pool(self.pool, None);
+        self.sets.clear();
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+        self.mip_views
+            .drain(..)
+            .for_each(|v| leaks::destroy_image_view(device, v));
+        leaks::destroy_image_view(device, self.source_view);
+        leaks::destroy_image_view(device, self.view);
+        leaks::destroy_image(device, self.cube.image);
+        self.cube = TrackedImage::default();
+        allocator::free(device, self.memory);
+        if let Some(mut capture) = self.capture.take() {
+            capture.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+#[cfg(feature = "profiling")]
+use std::{fmt, slice};
+
+#[cfg(feature = "profiling")]
+use anyhow::{anyhow, Result};
+#[cfg(feature = "profiling")]
+use tracy_client::{Client, GpuContext, GpuContextType, GpuSpan};
+#[cfg(feature = "profiling")]
+use vulkanalia::prelude::v1_0::*;
+
+#[cfg(feature = "profiling")]
+use crate::{app::AppData, error::RendererError, info::QueueFamilyIndices, MAX_FRAMES_IN_FLIGHT};
+
+// Zona de CPU no Tracy, que termina em `end` (ou quando sai do escopo). Sem a feature
+// `profiling` não faz nada
+pub struct Zone {
+    #[cfg(feature = "profiling")]
+    _span: tracy_client::Span,
+}
+
+impl Zone {
+    #[cfg(feature = "profiling")]
+    pub fn new(span: tracy_client::Span) -> Self {
+        Self { _span: span }
+    }
+
+    pub fn end(self) {}
+}
+
+macro_rules! zone {
+    ($name:literal) => {{
+        #[cfg(feature = "profiling")]
+        let zone = $crate::profiler::Zone::new(tracy_client::span!($name));
+        #[cfg(not(feature = "profiling"))]
+        let zone = $crate::profiler::Zone {};
+        zone
+    }};
+}
+
+pub(crate) use zone;
+
+// Quantas zonas de GPU cabem num frame (cada uma usa dois timestamps)
+#[cfg(feature = "profiling")]
+const ZONES_PER_FRAME: u32 = 32;
+
+// Zonas de GPU alimentadas por timestamp queries. Cada frame em voo tem sua faixa de
+// queries, lida depois que a fence dele é sinalizada
+#[cfg(feature = "profiling")]
+pub struct GpuProfiler {
+    context: GpuContext,
+    pool: vk::QueryPool,
+    // Zonas gravadas em cada frame, com o índice da query de início
+    spans: Vec<Vec<(GpuSpan, u32)>>,
+}
+
+#[cfg(feature = "profiling")]
+impl fmt::Debug for GpuProfiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GpuProfiler")
+            .field("pool", &self.pool)
+            .finish()
+    }
+}
+
+#[cfg(feature = "profiling")]
+impl GpuProfiler {
+    // Precisa do command pool, pra ler um timestamp inicial e sincronizar os relógios
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let client = Client::running().ok_or_else(|| anyhow!("Tracy client is not running."))?;
+
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+        let families = instance.get_physical_device_queue_family_properties(data.physical_device);
+        if families[indices.graphics as usize].timestamp_valid_bits == 0 {
+            return Err(anyhow!("Graphics queue does not support timestamps."));
+        }
+
+        let period = instance
+            .get_physical_device_properties(data.physical_device)
+            .limits
+            .timestamp_period;
+
+        let info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(MAX_FRAMES_IN_FLIGHT as u32 * ZONES_PER_FRAME * 2);
+        let pool = device.create_query_pool(&info, None)?;
+
+        let timestamp = calibrate(device, data, pool)?;
+        let context = client
+            .new_gpu_context(
+                Some("Vulkan"),
+                GpuContextType::Vulkan,
+                timestamp as i64,
+                period,
+            )
+            .map_err(|e| anyhow!("Failed to create Tracy GPU context: {:?}", e))?;
+
+        Ok(Self {
+            context,
+            pool,
+            spans: (0..MAX_FRAMES_IN_FLIGHT).map(|_| vec![]).collect(),
+        })
+    }
+
+    // Chamado depois de esperar a fence do frame `slot`: manda os tempos pro Tracy
+    pub unsafe fn collect(&mut self, device: &Device, slot: usize) -> Result<()> {
+        let spans = std::mem::take(&mut self.spans[slot]);
+        if spans.is_empty() {
+            return Ok(());
+        }
+
+        let first = slot as u32 * ZONES_PER_FRAME * 2;
+        let count = spans.len() as u32 * 2;
+        let mut timestamps = vec![0u64; count as usize];
+        let bytes =
+            slice::from_raw_parts_mut(timestamps.as_mut_ptr() as *mut u8, timestamps.len() * 8);
+
+        device
+            .get_query_pool_results(self.pool, first, count, bytes, 8, vk::QueryResultFlags::_64)
+            .map_err(RendererError::from)?;
+
+        for (span, query) in spans {
+            let index = (query - first) as usize;
+            span.upload_timestamp(timestamps[index] as i64, timestamps[index + 1] as i64);
+        }
+
+        Ok(())
+    }
+
+    // Antes de qualquer zona do frame, fora de render passes
+    pub unsafe fn reset(&self, device: &Device, command_buffer: vk::CommandBuffer, slot: usize) {
+        device.cmd_reset_query_pool(
+            command_buffer,
+            self.pool,
+            slot as u32 * ZONES_PER_FRAME * 2,
+            ZONES_PER_FRAME * 2,
+        );
+    }
+
+    pub unsafe fn begin(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        name: &str,
+    ) {
+        if self.spans[slot].len() as u32 == ZONES_PER_FRAME {
+            return;
+        }
+
+        let span = match self
+            .context
+            .span_alloc(name, "record_command_buffer", file!(), line!())
+        {
+            Ok(span) => span,
+            Err(_) => return,
+        };
+
+        let query = (slot as u32 * ZONES_PER_FRAME + self.spans[slot].len() as u32) * 2;
+        device.cmd_write_timestamp(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            self.pool,
+            query,
+        );
+
+        self.spans[slot].push((span, query));
+    }
+
+    pub unsafe fn end(&mut self, device: &Device, command_buffer: vk::CommandBuffer, slot: usize) {
+        if let Some((span, query)) = self.spans[slot].last_mut() {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.pool,
+                *query + 1,
+            );
+            span.end_zone();
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_query_pool(self.pool, None);
+    }
+}
+
+// Grava um timestamp numa submissão avulsa e espera ele, pra o Tracy alinhar o relógio da
+// GPU com o da CPU
+#[cfg(feature = "profiling")]
+unsafe fn calibrate(device: &Device, data: &AppData, pool: vk::QueryPool) -> Result<u64> {
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(data.command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    let begin_info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(command_buffer, &begin_info)?;
+    device.cmd_reset_query_pool(command_buffer, pool, 0, 1);
+    device.cmd_write_timestamp(
+        command_buffer,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        pool,
+        0,
+    );
+    device.end_command_buffer(command_buffer)?;
+
+    let command_buffers = &[command_buffer];
+    let submit_info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+    device.queue_submit(data.graphics_queue, &[submit_info], vk::Fence::null())?;
+    device.queue_wait_idle(data.graphics_queue)?;
+
+    let mut timestamp = 0u64;
+    let bytes = slice::from_raw_parts_mut(&mut timestamp as *mut u64 as *mut u8, 8);
+    device.get_query_pool_results(
+        pool,
+        0,
+        1,
+        bytes,
+        8,
+        vk::QueryResultFlags::_64 | vk::QueryResultFlags::WAIT,
+    )?;
+
+    device.free_command_buffers(data.command_pool, command_buffers);
+
+    Ok(timestamp)
+}
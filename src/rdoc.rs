@@ -0,0 +1,55 @@
+use anyhow::{anyhow, Result};
+use log::*;
+use renderdoc::{InputButton, RenderDoc, V110};
+use winit::event::VirtualKeyCode;
+
+pub const DEFAULT_CAPTURE_KEY: VirtualKeyCode = VirtualKeyCode::F12;
+
+// API in-application do RenderDoc. Só existe quando o app foi iniciado pelo RenderDoc
+// (ou com a layer dele injetada)
+pub struct RenderDocCapture {
+    api: RenderDoc<V110>,
+    key: VirtualKeyCode,
+}
+
+impl RenderDocCapture {
+    pub fn new(key: VirtualKeyCode) -> Result<Self> {
+        let mut api =
+            RenderDoc::<V110>::new().map_err(|e| anyhow!("RenderDoc is not attached: {}", e))?;
+
+        // As teclas do próprio RenderDoc nem sempre chegam pela janela do winit, e se
+        // chegarem capturariam duas vezes
+        api.set_capture_keys(&[] as &[InputButton]);
+
+        info!("RenderDoc attached, press {:?} to capture a frame.", key);
+        Ok(Self { api, key })
+    }
+
+    pub fn handle_key(&mut self, key: VirtualKeyCode) {
+        if key == self.key {
+            self.api.trigger_capture();
+            info!("Capturing next frame in RenderDoc.");
+        }
+    }
+}
+
+// Nomes aceitos em LV_RENDERDOC_KEY
+pub fn parse_key(name: &str) -> Option<VirtualKeyCode> {
+    let key = match name.to_ascii_uppercase().as_str() {
+        "F1" => VirtualKeyCode::F1,
+        "F2" => VirtualKeyCode::F2,
+        "F3" => VirtualKeyCode::F3,
+        "F4" => VirtualKeyCode::F4,
+        "F5" => VirtualKeyCode::F5,
+        "F6" => VirtualKeyCode::F6,
+        "F7" => VirtualKeyCode::F7,
+        "F8" => VirtualKeyCode::F8,
+        "F10" => VirtualKeyCode::F10,
+        "F11" => VirtualKeyCode::F11,
+        "F12" => VirtualKeyCode::F12,
+        "SNAPSHOT" | "PRINTSCREEN" => VirtualKeyCode::Snapshot,
+        _ => return None,
+    };
+
+    Some(key)
+}
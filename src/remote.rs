@@ -0,0 +1,440 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{IpAddr, TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use nalgebra_glm as glm;
+
+use crate::{app::App, report::escape_json};
+
+// Quanto o servidor espera o loop de renderização responder (janela minimizada não desenha)
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Limite de cada leitura e escrita na conexão. As conexões são atendidas uma de cada vez,
+// então um cliente parado não pode segurar as outras
+const IO_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Tamanho máximo da linha da requisição e de cada cabeçalho, em bytes
+const MAX_LINE: u64 = 4096;
+
+// Quantos cabeçalhos uma requisição pode ter
+const MAX_HEADERS: usize = 64;
+
+// Só a porta no --remote escuta aqui, pra não abrir o controle pra rede sem querer
+const DEFAULT_HOST: &str = "127.0.0.1";
+
+// Pasta dos screenshots pedidos pelo endpoint, se o --remote-captures não disser outra
+pub const DEFAULT_CAPTURE_DIR: &str = "captures";
+
+// Pasta de onde o endpoint pode carregar cenas e LUTs, se o --remote-assets não disser
+// outra
+pub const DEFAULT_ASSET_DIR: &str = "assets";
+
+// O que dá pra pedir pelo endpoint HTTP
+#[derive(Clone, Debug)]
+pub enum RemoteCommand {
+    // GET /stats
+    Stats,
+    // POST /screenshot?path=arquivo.png, relativo à pasta de capturas do servidor
+    Screenshot(PathBuf),
+    // POST /camera?position=x,y,z&target=x,y,z
+    SetCamera {
+        position: [f32; 3],
+        target: [f32; 3],
+    },
+    // POST /scene?path=cena.obj, relativo à pasta de assets do servidor
+    LoadScene(PathBuf),
+    // POST /lut?path=grade.cube, relativo à pasta de assets do servidor
+    LoadLut(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct RemoteResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl RemoteResponse {
+    pub fn ok(body: impl Into<String>) -> Self {
+        Self {
+            status: 200,
+            body: body.into(),
+        }
+    }
+
+    pub fn error(status: u16, message: impl AsRef<str>) -> Self {
+        Self {
+            status,
+            body: format!("{{\"error\":\"{}\"}}", escape_json(message.as_ref())),
+        }
+    }
+}
+
+pub struct RemoteRequest {
+    pub command: RemoteCommand,
+    reply: Sender<RemoteResponse>,
+}
+
+impl RemoteRequest {
+    pub fn reply(self, response: RemoteResponse) {
+        // Se a conexão já desistiu não tem pra quem responder
+        let _ = self.reply.send(response);
+    }
+}
+
+// Servidor HTTP mínimo numa thread própria. Ele só traduz requisições em comandos; quem
+// executa é o loop principal, chamando `poll` uma vez por frame
+pub struct RemoteServer {
+    requests: Receiver<RemoteRequest>,
+}
+
+impl RemoteServer {
+    // `address` é `host:porta` ou só a porta, que escuta no loopback. Os screenshots só
+    // podem ser escritos dentro de `captures`, e cenas e LUTs só são lidas de `assets`
+    pub fn listen(
+        address: &str,
+        captures: impl Into<PathBuf>,
+        assets: impl Into<PathBuf>,
+    ) -> Result<Self> {
+        let listener = match address.parse::<u16>() {
+            Ok(port) => TcpListener::bind((DEFAULT_HOST, port))?,
+            Err(_) => TcpListener::bind(address)?,
+        };
+        let local = listener.local_addr()?;
+        info!("Remote control listening on http://{}.", local);
+        if !local.ip().is_loopback() {
+            warn!(
+                "Remote control is reachable from other machines on {}; anyone there can \
+                 drive the renderer.",
+                local
+            );
+        }
+
+        let dirs = RemoteDirs {
+            captures: captures.into(),
+            assets: assets.into(),
+        };
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = serve(stream, &sender, &dirs) {
+                            warn!("Remote control request failed: {}", e);
+                        }
+                    }
+                    Err(e) => warn!("Failed to accept remote connection: {}", e),
+                }
+            }
+        });
+
+        Ok(Self { requests })
+    }
+
+    pub fn poll(&self) -> Option<RemoteRequest> {
+        self.requests.try_recv().ok()
+    }
+}
+
+// Executa um comando no app
+pub unsafe fn execute(app: &mut App, command: RemoteCommand) -> RemoteResponse {
+    match command {
+        RemoteCommand::Stats => {
+            let stats = app.stats();
+            let latency = match stats.present_latency {
+                Some(latency) => format!("{:.3}", latency.as_secs_f64() * 1000.0),
+                None => "null".to_string(),
+            };
+
+            RemoteResponse::ok(format!(
+                "{{\"frame_time_ms\":{:.3},\"present_latency_ms\":{},\"present_mode\":\"{:?}\",\"recording\":{}}}",
+                stats.frame_time.as_secs_f64() * 1000.0,
+                latency,
+                app.present_mode(),
+                app.is_recording(),
+            ))
+        }
+        RemoteCommand::Screenshot(path) => {
+            let result = match path.parent() {
+                Some(dir) => fs::create_dir_all(dir).map_err(anyhow::Error::from),
+                None => Ok(()),
+            };
+            match result.and_then(|_| app.screenshot(&path)) {
+                Ok(()) => RemoteResponse::ok(format!(
+                    "{{\"path\":\"{}\"}}",
+                    escape_json(&path.display().to_string())
+                )),
+                Err(e) => RemoteResponse::error(500, e.to_string()),
+            }
+        }
+        RemoteCommand::SetCamera { position, target } => {
+            let camera = app.camera_mut();
+            camera.position = glm::make_vec3(&position);
+            camera.look_at(glm::make_vec3(&target));
+            RemoteResponse::ok("{}")
+        }
+        // Substitui a cena inteira pelo modelo. A cena antiga só sai depois que o modelo
+        // carregou, pra um caminho errado não deixar a tela vazia
+        RemoteCommand::LoadScene(path) => {
+            let previous = app.scene().roots().to_vec();
+            match app.load_model(&path, None) {
+                Ok(_) => {
+                    for id in previous {
+                        app.scene_mut().remove(id);
+                    }
+                    RemoteResponse::ok(format!("{{\"nodes\":{}}}", app.scene().len()))
+                }
+                Err(e) => RemoteResponse::error(500, e.to_string()),
+            }
+        }
+        // Só troca a LUT; o estágio liga pela interface ou pelo AppBuilder
+        RemoteCommand::LoadLut(path) => match app.load_color_grading_lut(&path) {
+            Ok(()) => RemoteResponse::ok("{}"),
+            Err(e) => RemoteResponse::error(500, e.to_string()),
+        },
+    }
+}
+
+// Pastas a que o endpoint fica preso
+struct RemoteDirs {
+    captures: PathBuf,
+    assets: PathBuf,
+}
+
+fn serve(stream: TcpStream, sender: &Sender<RemoteRequest>, dirs: &RemoteDirs) -> Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let (request_line, origin) = match read_head(&mut reader) {
+        Ok(head) => head,
+        Err(e) => {
+            // Depois de um timeout pode não ter mais ninguém pra ler a resposta
+            let _ = respond(stream, &RemoteResponse::error(400, e.to_string()));
+            return Err(e);
+        }
+    };
+
+    // Navegadores mandam o Origin nas requisições feitas por páginas. Só as servidas da
+    // própria máquina passam, pra um site qualquer não conseguir mandar POST pro loopback.
+    // Clientes fora do navegador (curl, scripts) não mandam o cabeçalho
+    if let Some(origin) = origin.filter(|o| !is_local_origin(o)) {
+        let message = format!("Requests from origin '{}' are not allowed.", origin);
+        return respond(stream, &RemoteResponse::error(403, message));
+    }
+
+    let response = match parse(&request_line, dirs) {
+        Ok(command) => {
+            let (reply, response) = mpsc::channel();
+            sender.send(RemoteRequest { command, reply })?;
+            response
+                .recv_timeout(REPLY_TIMEOUT)
+                .unwrap_or_else(|_| RemoteResponse::error(503, "Renderer is not responding."))
+        }
+        Err(e) => RemoteResponse::error(400, e.to_string()),
+    };
+
+    respond(stream, &response)
+}
+
+// Linha da requisição e o Origin, se veio. O resto dos cabeçalhos só é consumido
+fn read_head(reader: &mut BufReader<TcpStream>) -> Result<(String, Option<String>)> {
+    let mut request_line = String::new();
+    read_line(reader, &mut request_line)?;
+
+    let mut origin = None;
+    let mut header = String::new();
+    for _ in 0..=MAX_HEADERS {
+        if read_line(reader, &mut header)? <= 2 {
+            return Ok((request_line, origin));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("origin") {
+                origin = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    Err(anyhow!("Request has more than {} headers.", MAX_HEADERS))
+}
+
+// Uma linha de até MAX_LINE bytes, pra um cliente não fazer o buffer crescer sem limite
+fn read_line(reader: &mut BufReader<TcpStream>, line: &mut String) -> Result<usize> {
+    line.clear();
+    let read = reader.by_ref().take(MAX_LINE + 1).read_line(line)?;
+    if read as u64 > MAX_LINE {
+        return Err(anyhow!(
+            "Request line or header is longer than {} bytes.",
+            MAX_LINE
+        ));
+    }
+
+    Ok(read)
+}
+
+// `http://localhost:porta`, `http://127.0.0.1`, `http://[::1]:porta`... O "null" das
+// páginas abertas de arquivo não conta
+fn is_local_origin(origin: &str) -> bool {
+    let authority = match origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+    {
+        Some(authority) => authority,
+        None => return false,
+    };
+    let host = match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next(),
+        None => authority.split(':').next(),
+    }
+    .unwrap_or("");
+
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+fn parse(request_line: &str, dirs: &RemoteDirs) -> Result<RemoteCommand> {
+    let mut words = request_line.split_whitespace();
+    let (method, target) = match (words.next(), words.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return Err(anyhow!("Malformed request line.")),
+    };
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.to_string(), decode(value)))
+        .collect::<HashMap<_, _>>();
+    let param = |name: &str| {
+        params
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Missing '{}' parameter.", name))
+    };
+
+    match (method, path) {
+        ("GET", "/stats") => Ok(RemoteCommand::Stats),
+        ("POST", "/screenshot") => Ok(RemoteCommand::Screenshot(confined_path(
+            &dirs.captures,
+            &param("path")?,
+            &["png"],
+        )?)),
+        ("POST", "/camera") => Ok(RemoteCommand::SetCamera {
+            position: parse_vec3(&param("position")?)?,
+            target: parse_vec3(&param("target")?)?,
+        }),
+        ("POST", "/scene") => Ok(RemoteCommand::LoadScene(confined_path(
+            &dirs.assets,
+            &param("path")?,
+            &["obj"],
+        )?)),
+        ("POST", "/lut") => Ok(RemoteCommand::LoadLut(confined_path(
+            &dirs.assets,
+            &param("path")?,
+            &["cube", "png"],
+        )?)),
+        _ => Err(anyhow!("Unknown route {} {}.", method, path)),
+    }
+}
+
+// `value` dentro de `root`: só caminhos relativos que não saem dela, e com uma das
+// extensões
+fn confined_path(root: &Path, value: &str, extensions: &[&str]) -> Result<PathBuf> {
+    let path = PathBuf::from(value);
+    let inside = path.components().count() > 0
+        && path.components().all(|c| matches!(c, Component::Normal(_)));
+    if !inside {
+        return Err(anyhow!(
+            "Path '{}' must be relative and stay inside '{}'.",
+            value,
+            root.display()
+        ));
+    }
+
+    let allowed = path.extension().is_some_and(|e| {
+        extensions
+            .iter()
+            .any(|allowed| e.eq_ignore_ascii_case(allowed))
+    });
+    if !allowed {
+        return Err(anyhow!(
+            "Path '{}' must end in .{}.",
+            value,
+            extensions.join(" or .")
+        ));
+    }
+
+    Ok(root.join(path))
+}
+
+fn parse_vec3(value: &str) -> Result<[f32; 3]> {
+    let values = value
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match values[..] {
+        [x, y, z] => Ok([x, y, z]),
+        _ => Err(anyhow!(
+            "Expected three comma separated values, got '{}'.",
+            value
+        )),
+    }
+}
+
+// Decodifica `+` e `%XX` da query string
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn respond(mut stream: TcpStream, response: &RemoteResponse) -> Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason,
+        response.body.len(),
+        response.body,
+    )?;
+
+    Ok(())
+}
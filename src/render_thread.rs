@@ -0,0 +1,666 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Result;
+use log::*;
+use winit::{
+    event::{
+        DeviceEvent, ElementState, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode,
+        WindowEvent,
+    },
+    event_loop::EventLoopProxy,
+    window::Window,
+};
+
+#[cfg(feature = "gamepad")]
+use crate::gamepad::Gamepads;
+#[cfg(feature = "renderdoc")]
+use crate::rdoc::{self, RenderDocCapture};
+use crate::{
+    app::{App, AppBuilder},
+    capture::CaptureOutput,
+    config::{DebugView, RenderPath},
+    controller::CameraController,
+    input::Input,
+    remote::{self, RemoteServer},
+    report::Report,
+    sync::{SyncSession, SyncState},
+    time::Time,
+    tonemap::ToneMapOperator,
+    write_fatal_report,
+};
+
+// No modo sob demanda, de quanto em quanto tempo o loop acorda pra ver o controle remoto
+// e os gamepads, que não chegam pelo canal
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// O que o loop de eventos manda pra thread de renderização
+#[derive(Debug)]
+pub enum RenderMessage {
+    Window(WindowEvent<'static>),
+    Device(DeviceEvent),
+    Shutdown,
+}
+
+// Mandado de volta pro loop de eventos quando a thread termina sozinha (erro fatal)
+#[derive(Copy, Clone, Debug)]
+pub struct RenderThreadExited;
+
+// Renderização numa thread própria: arrastar a janela ou um diálogo modal do SO
+// segurando o loop de eventos não para mais a produção de frames
+#[derive(Debug)]
+pub struct RenderThread {
+    sender: Sender<RenderMessage>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RenderThread {
+    pub fn spawn(
+        window: Arc<Window>,
+        builder: AppBuilder,
+        input: Input,
+        remote: Option<RemoteServer>,
+        sync: Option<SyncSession>,
+        frames: Option<u64>,
+        screenshot: Option<PathBuf>,
+        proxy: EventLoopProxy<RenderThreadExited>,
+    ) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+
+        let handle = thread::Builder::new()
+            .name("render".into())
+            .spawn(move || {
+                // O App é criado aqui mesmo, então ele nunca precisa atravessar threads
+                match unsafe { builder.build(&window) } {
+                    Ok(app) => {
+                        let mut render_loop = RenderLoop::new(app, window, input, remote, receiver);
+                        render_loop.sync = sync;
+                        render_loop.frames_left = frames;
+                        render_loop.screenshot = screenshot;
+                        unsafe { render_loop.run() };
+                    }
+                    Err(e) => write_fatal_report(Report::collect(), &e),
+                }
+
+                let _ = proxy.send_event(RenderThreadExited);
+            })?;
+
+        Ok(Self {
+            sender,
+            handle: Some(handle),
+        })
+    }
+
+    // Se a thread já terminou a mensagem só se perde
+    pub fn send(&self, message: RenderMessage) {
+        let _ = self.sender.send(message);
+    }
+
+    // Pede pra thread destruir o App e espera ela terminar
+    pub fn shutdown(&mut self) {
+        self.send(RenderMessage::Shutdown);
+
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("Render thread panicked.");
+            }
+        }
+    }
+}
+
+// Tudo que antes vivia no loop de eventos: o App, a entrada, a câmera e os atalhos
+struct RenderLoop {
+    app: App,
+    window: Arc<Window>,
+    receiver: Receiver<RenderMessage>,
+    input: Input,
+    remote: Option<RemoteServer>,
+    // Sessão de revisão: o líder manda o estado a cada frame, o seguidor aplica o que chega
+    sync: Option<SyncSession>,
+    #[cfg(feature = "gamepad")]
+    gamepads: Option<Gamepads>,
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<RenderDocCapture>,
+    camera_controller: CameraController,
+    time: Time,
+    modifiers: ModifiersState,
+    minimized: bool,
+    // Janela de debug do egui (F2)
+    show_ui: bool,
+    // Chegou algo desde o último frame que pode mudar a imagem (só importa sob demanda)
+    dirty: bool,
+    // Com --frames, quantos ainda faltam antes de sair sozinho
+    frames_left: Option<u64>,
+    // Com --screenshot, onde salvar o último desses frames
+    screenshot: Option<PathBuf>,
+    shutdown: bool,
+}
+
+impl RenderLoop {
+    fn new(
+        app: App,
+        window: Arc<Window>,
+        input: Input,
+        remote: Option<RemoteServer>,
+        receiver: Receiver<RenderMessage>,
+    ) -> Self {
+        #[cfg(feature = "gamepad")]
+        let gamepads = match Gamepads::new() {
+            Ok(gamepads) => Some(gamepads),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        };
+
+        // LV_RENDERDOC_KEY troca a tecla de captura (F12 por padrão)
+        #[cfg(feature = "renderdoc")]
+        let renderdoc = {
+            let key = std::env::var("LV_RENDERDOC_KEY")
+                .ok()
+                .and_then(|k| rdoc::parse_key(&k))
+                .unwrap_or(rdoc::DEFAULT_CAPTURE_KEY);
+
+            match RenderDocCapture::new(key) {
+                Ok(capture) => Some(capture),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            }
+        };
+
+        Self {
+            app,
+            window,
+            receiver,
+            input,
+            remote,
+            sync: None,
+            #[cfg(feature = "gamepad")]
+            gamepads,
+            #[cfg(feature = "renderdoc")]
+            renderdoc,
+            // Câmera em primeira pessoa (clique captura o mouse, Esc solta) ou órbita
+            camera_controller: CameraController::Fly(Default::default()),
+            time: Time::default(),
+            modifiers: ModifiersState::empty(),
+            minimized: false,
+            show_ui: false,
+            dirty: true,
+            frames_left: None,
+            screenshot: None,
+            shutdown: false,
+        }
+    }
+
+    unsafe fn run(&mut self) {
+        self.run_frames();
+        self.app.destroy();
+    }
+
+    unsafe fn run_frames(&mut self) {
+        while !self.shutdown {
+            while let Ok(message) = self.receiver.try_recv() {
+                self.handle_message(message);
+            }
+            if self.shutdown {
+                break;
+            }
+
+            while let Some(request) = self.remote.as_ref().and_then(|r| r.poll()) {
+                let response = remote::execute(&mut self.app, request.command.clone());
+                request.reply(response);
+                self.dirty = true;
+            }
+
+            if let Some(state) = self.sync.as_mut().and_then(SyncSession::poll) {
+                if let Err(e) = state.apply(&mut self.app) {
+                    error!("Failed to apply review session state: {}", e);
+                }
+                self.dirty = true;
+            }
+
+            #[cfg(feature = "gamepad")]
+            if let Some(gamepads) = &mut self.gamepads {
+                gamepads.poll(&mut self.input);
+            }
+
+            // Minimizado, ou sob demanda sem nada mudando: dorme até a próxima mensagem
+            let idle = self.app.redraw_on_demand()
+                && !self.dirty
+                && !self.input.is_active()
+                && !self.app.needs_redraw();
+            if self.minimized || idle {
+                self.time.pause();
+                self.input.end_frame();
+                self.wait();
+                continue;
+            }
+
+            self.dirty = false;
+            self.update();
+
+            if let Some(sync) = &mut self.sync {
+                sync.publish(&SyncState::capture(&self.app));
+            }
+
+            // A cópia é lida quando o App é destruído, logo depois desse frame
+            if self.frames_left == Some(1) {
+                if let Some(path) = self.screenshot.take() {
+                    if let Err(e) = self.app.screenshot(path) {
+                        error!("Failed to start the screenshot: {}", e);
+                    }
+                }
+            }
+
+            // Perder o dispositivo já é tratado dentro do render; o que chega aqui é fatal
+            if let Err(e) = self.app.render(&self.window) {
+                write_fatal_report(self.app.report(), &e);
+                break;
+            }
+
+            #[cfg(feature = "xr")]
+            if self.app.xr_exit_requested() {
+                info!("The VR session ended, exiting.");
+                break;
+            }
+
+            if let Some(frames) = &mut self.frames_left {
+                *frames = frames.saturating_sub(1);
+                if *frames == 0 {
+                    info!("Rendered the requested number of frames, exiting.");
+                    break;
+                }
+            }
+        }
+    }
+
+    fn wait(&mut self) {
+        let polling = self.remote.is_some() || self.sync.is_some() || cfg!(feature = "gamepad");
+        let message = if polling {
+            match self.receiver.recv_timeout(IDLE_POLL_INTERVAL) {
+                Ok(message) => Some(message),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => Some(RenderMessage::Shutdown),
+            }
+        } else {
+            Some(self.receiver.recv().unwrap_or(RenderMessage::Shutdown))
+        };
+
+        if let Some(message) = message {
+            unsafe { self.handle_message(message) };
+        }
+    }
+
+    unsafe fn update(&mut self) {
+        self.time.tick();
+
+        // Lógica que precisa ser determinística roda aqui, em passos fixos
+        #[cfg(feature = "audio")]
+        let app = &mut self.app;
+        self.time.fixed_update(|step| {
+            #[cfg(feature = "audio")]
+            app.update_audio();
+        });
+
+        // Seguindo uma sessão de revisão, a câmera é a do líder
+        if !self.sync.as_ref().is_some_and(|s| !s.is_leader()) {
+            self.camera_controller.update(
+                &self.input,
+                self.app.camera_mut(),
+                self.time.delta_seconds(),
+            );
+        }
+        self.input.end_frame();
+
+        if self.show_ui {
+            self.debug_ui();
+        }
+    }
+
+    unsafe fn debug_ui(&mut self) {
+        let app = &mut self.app;
+        let stats = *app.stats();
+        let present_mode = app.present_mode();
+        let frame = self.time.frame();
+        let mut fps_limit = app.fps_limit();
+        let mut on_demand = app.redraw_on_demand();
+        let mut frame_pacing = app.frame_pacing();
+        let mut debug_view = app.debug_view();
+        let mut wireframe = app.wireframe();
+        let mut frustum_culling = app.frustum_culling();
+        let occlusion_supported = app.occlusion_culling_supported();
+        let mut occlusion_culling = app.occlusion_culling();
+        let mut deferred = app.render_path() == RenderPath::Deferred;
+        let mut tone_mapping = app.tone_mapping();
+        let mut auto_exposure = app.auto_exposure();
+        let adapted_luminance = app.adapted_luminance();
+        let mut post_processing = app.post_processing();
+        let mut post_effects = app.post_effects();
+        let mut sky = app.sky();
+        let mut terrain = app.terrain();
+        let (mut sun_elevation, mut sun_azimuth) = sky.sun_angles();
+        let mut shadows = app.shadow_settings();
+        let mut show_cascades = app.show_cascades();
+        let mut anisotropy = app.max_anisotropy();
+        let anisotropy_limit = app.max_anisotropy_limit();
+        let result = app.ui(&self.window, |ctx| {
+            egui::Window::new("Renderer").show(ctx, |ui| {
+                ui.label(format!(
+                    "Frame time: {:.2} ms",
+                    stats.frame_time.as_secs_f32() * 1000.0
+                ));
+                ui.label(format!("Frame: {}", frame));
+                ui.label(format!("Draw calls: {}", stats.draw_calls));
+                ui.label(format!(
+                    "Meshes: {} submitted, {} culled",
+                    stats.submitted, stats.culled
+                ));
+                ui.label(format!("Present mode: {:?}", present_mode));
+                ui.label(match stats.present_latency {
+                    Some(latency) => {
+                        format!("Present latency: {:.2} ms", latency.as_secs_f32() * 1000.0)
+                    }
+                    None => "Present latency: unavailable".to_string(),
+                });
+                ui.label(format!(
+                    "Present interval: {:.2} ms (target {:.2}, jitter {:.2}, {} late){}",
+                    stats.pacing.interval.as_secs_f32() * 1000.0,
+                    stats.pacing.target_interval.as_secs_f32() * 1000.0,
+                    stats.pacing.jitter.as_secs_f32() * 1000.0,
+                    stats.pacing.late,
+                    if stats.pacing.display_timing {
+                        ""
+                    } else {
+                        " [CPU]"
+                    }
+                ));
+
+                let mut limited = fps_limit.is_some();
+                let mut fps = fps_limit.unwrap_or(60);
+                ui.checkbox(&mut limited, "Limit FPS (MAILBOX/IMMEDIATE)");
+                if limited {
+                    ui.add(egui::Slider::new(&mut fps, 15..=360).text("FPS"));
+                }
+                fps_limit = if limited { Some(fps) } else { None };
+
+                ui.checkbox(&mut on_demand, "Redraw on demand");
+                ui.checkbox(&mut frame_pacing, "Frame pacing");
+                egui::ComboBox::from_label("Debug view (1-7)")
+                    .selected_text(format!("{:?}", debug_view))
+                    .show_ui(ui, |ui| {
+                        for view in DebugView::ALL {
+                            ui.selectable_value(&mut debug_view, view, format!("{:?}", view));
+                        }
+                    });
+                ui.checkbox(&mut wireframe, "Wireframe (F3)");
+                ui.checkbox(&mut frustum_culling, "Frustum culling");
+                if occlusion_supported {
+                    ui.checkbox(&mut occlusion_culling, "Occlusion culling (GPU)");
+                }
+                ui.checkbox(&mut deferred, "Deferred shading");
+                if anisotropy_limit > 1.0 {
+                    ui.add(
+                        egui::Slider::new(&mut anisotropy, 1.0..=anisotropy_limit)
+                            .text("Anisotropy"),
+                    );
+                }
+
+                egui::ComboBox::from_label("Tone mapping")
+                    .selected_text(format!("{:?}", tone_mapping.operator))
+                    .show_ui(ui, |ui| {
+                        for operator in ToneMapOperator::ALL {
+                            ui.selectable_value(
+                                &mut tone_mapping.operator,
+                                operator,
+                                format!("{:?}", operator),
+                            );
+                        }
+                    });
+                ui.add(
+                    egui::Slider::new(&mut tone_mapping.exposure, -8.0..=8.0).text("Exposure (EV)"),
+                );
+                ui.checkbox(&mut auto_exposure.enabled, "Auto exposure");
+                if auto_exposure.enabled {
+                    ui.add(
+                        egui::Slider::new(&mut auto_exposure.min_ev, -10.0..=20.0).text("Min EV"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut auto_exposure.max_ev, -10.0..=20.0).text("Max EV"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut auto_exposure.speed, 0.1..=10.0)
+                            .text("Adaptation speed"),
+                    );
+                    if let Some(luminance) = adapted_luminance {
+                        ui.label(format!("Adapted luminance: {:.3}", luminance));
+                    }
+                }
+                ui.checkbox(&mut post_processing.color_grading, "Color grading");
+                ui.checkbox(&mut post_processing.fxaa, "FXAA");
+                for (_, name, enabled) in &mut post_effects {
+                    ui.checkbox(enabled, name.as_str());
+                }
+
+                ui.checkbox(&mut sky.enabled, "Sky");
+                if sky.enabled {
+                    ui.add(
+                        egui::Slider::new(&mut sun_elevation, -10.0..=90.0).text("Sun elevation"),
+                    );
+                    ui.add(egui::Slider::new(&mut sun_azimuth, -180.0..=180.0).text("Sun azimuth"));
+                    ui.add(egui::Slider::new(&mut sky.turbidity, 2.0..=10.0).text("Turbidity"));
+                    ui.add(
+                        egui::Slider::new(&mut sky.intensity, 0.001..=1.0)
+                            .logarithmic(true)
+                            .text("Sky intensity"),
+                    );
+                }
+
+                ui.checkbox(&mut terrain.enabled, "Terrain");
+                if terrain.enabled {
+                    ui.add(
+                        egui::Slider::new(&mut terrain.max_level, 1.0..=64.0)
+                            .text("Max tessellation"),
+                    );
+                    ui.add(egui::Slider::new(&mut terrain.far, 10.0..=1000.0).text("Detail range"));
+                    ui.add(
+                        egui::Slider::new(&mut terrain.height_scale, 0.0..=200.0).text("Height"),
+                    );
+                }
+
+                ui.checkbox(&mut shadows.enabled, "Shadows");
+                if shadows.enabled {
+                    ui.add(
+                        egui::Slider::new(&mut shadows.depth_bias_constant, 0.0..=10.0)
+                            .text("Constant bias"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut shadows.depth_bias_slope, 0.0..=10.0)
+                            .text("Slope bias"),
+                    );
+                    ui.add(egui::Slider::new(&mut shadows.pcf_radius, 0..=3).text("PCF radius"));
+                    ui.add(egui::Slider::new(&mut shadows.cascades, 1..=4).text("Cascades"));
+                    ui.checkbox(&mut show_cascades, "Show cascades");
+                }
+            });
+        });
+
+        if fps_limit != app.fps_limit() {
+            app.set_fps_limit(fps_limit);
+        }
+        app.set_redraw_on_demand(on_demand);
+        app.set_frame_pacing(frame_pacing);
+        app.set_debug_view(debug_view);
+        if wireframe != app.wireframe() {
+            app.set_wireframe(wireframe);
+        }
+        app.set_frustum_culling(frustum_culling);
+        app.set_occlusion_culling(occlusion_culling);
+        app.set_show_cascades(show_cascades);
+        app.set_tone_mapping(tone_mapping);
+        app.set_auto_exposure(auto_exposure);
+        for (id, _, enabled) in post_effects {
+            app.set_post_effect_enabled(id, enabled);
+        }
+        // Só refaz a direção se os ângulos mudaram, senão o arredondamento invalida a
+        // irradiância guardada todo frame
+        if (sun_elevation, sun_azimuth) != app.sky().sun_angles() {
+            sky.set_sun_angles(sun_elevation, sun_azimuth);
+        }
+        app.set_sky(sky);
+        app.set_terrain(terrain);
+        let path = if deferred {
+            RenderPath::Deferred
+        } else {
+            RenderPath::Forward
+        };
+        if let Err(e) = app.set_render_path(&self.window, path) {
+            error!("Failed to switch render path: {}", e);
+        }
+        if let Err(e) = app.set_post_processing(&self.window, post_processing) {
+            error!("Failed to rebuild the post-processing chain: {}", e);
+        }
+        if let Err(e) = app.set_max_anisotropy(anisotropy) {
+            error!("Failed to update the texture samplers: {}", e);
+        }
+        if shadows != app.shadow_settings() {
+            if let Err(e) = app.set_shadow_settings(shadows) {
+                error!("Failed to apply shadow settings: {}", e);
+            }
+        }
+
+        if let Err(e) = result {
+            error!("Failed to build UI: {}", e);
+        }
+    }
+
+    unsafe fn handle_message(&mut self, message: RenderMessage) {
+        match message {
+            RenderMessage::Window(event) => self.handle_window_event(event),
+            RenderMessage::Device(event) => {
+                self.input.handle_device_event(&event);
+                // O movimento do mouse chega mesmo fora da janela; só conta com ele preso
+                self.dirty |= self.camera_controller.is_grabbed();
+            }
+            RenderMessage::Shutdown => self.shutdown = true,
+        }
+    }
+
+    unsafe fn handle_window_event(&mut self, event: WindowEvent<'static>) {
+        // O que a interface consumir (ex: digitando num campo) não vira atalho
+        if self.app.ui_event(&event) {
+            self.dirty = true;
+            return;
+        }
+
+        self.input.handle_window_event(&event);
+        self.dirty = true;
+
+        match event {
+            WindowEvent::Resized(size) => {
+                // Não dá pra criar uma swapchain de tamanho 0, então paramos de desenhar
+                if size.width == 0 || size.height == 0 {
+                    self.minimized = true;
+                } else {
+                    self.minimized = false;
+                    self.app.resized = true;
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if self.camera_controller.can_grab() => {
+                if let Err(e) = self.camera_controller.set_grab(&self.window, true) {
+                    warn!("Failed to grab cursor: {}", e);
+                }
+            }
+            WindowEvent::Focused(false) if self.camera_controller.is_grabbed() => {
+                let _ = self.camera_controller.set_grab(&self.window, false);
+            }
+            WindowEvent::ModifiersChanged(state) => self.modifiers = state,
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.handle_key(key),
+            _ => {}
+        }
+    }
+
+    unsafe fn handle_key(&mut self, key: VirtualKeyCode) {
+        match key {
+            VirtualKeyCode::Escape if self.camera_controller.is_grabbed() => {
+                let _ = self.camera_controller.set_grab(&self.window, false);
+            }
+            // F9 liga/desliga a gravação dos frames em PNGs numerados
+            VirtualKeyCode::F9 => {
+                let result = if self.app.is_recording() {
+                    self.app.stop_recording()
+                } else {
+                    self.app
+                        .start_recording(CaptureOutput::Png("capture".into()))
+                };
+
+                if let Err(e) = result {
+                    error!("Failed to toggle recording: {}", e);
+                }
+            }
+            // Alt+Enter alterna a tela cheia sem borda
+            VirtualKeyCode::Return if self.modifiers.alt() => {
+                if let Err(e) = self.app.toggle_borderless(&self.window) {
+                    error!("Failed to toggle fullscreen: {}", e);
+                }
+            }
+            // F1 mostra/esconde as estatísticas do frame
+            VirtualKeyCode::F1 => self.app.toggle_overlay(),
+            // F2 mostra/esconde a janela de debug
+            VirtualKeyCode::F2 => self.show_ui = !self.show_ui,
+            // F3 alterna o wireframe
+            VirtualKeyCode::F3 => self.app.set_wireframe(!self.app.wireframe()),
+            // 1 a 7 escolhem a visão de debug, na ordem do DebugView::ALL
+            VirtualKeyCode::Key1 => self.app.set_debug_view(DebugView::ALL[0]),
+            VirtualKeyCode::Key2 => self.app.set_debug_view(DebugView::ALL[1]),
+            VirtualKeyCode::Key3 => self.app.set_debug_view(DebugView::ALL[2]),
+            VirtualKeyCode::Key4 => self.app.set_debug_view(DebugView::ALL[3]),
+            VirtualKeyCode::Key5 => self.app.set_debug_view(DebugView::ALL[4]),
+            VirtualKeyCode::Key6 => self.app.set_debug_view(DebugView::ALL[5]),
+            VirtualKeyCode::Key7 => self.app.set_debug_view(DebugView::ALL[6]),
+            // C troca entre a câmera em primeira pessoa e a de órbita
+            VirtualKeyCode::C => {
+                if let Err(e) = self
+                    .camera_controller
+                    .switch(&self.window, self.app.camera())
+                {
+                    warn!("Failed to release cursor: {}", e);
+                }
+            }
+            // V alterna entre FIFO (v-sync), MAILBOX e IMMEDIATE
+            VirtualKeyCode::V => {
+                if let Err(e) = self.app.cycle_present_mode(&self.window) {
+                    error!("Failed to switch present mode: {}", e);
+                }
+            }
+            #[cfg(feature = "renderdoc")]
+            key => {
+                if let Some(renderdoc) = &mut self.renderdoc {
+                    renderdoc.handle_key(key);
+                }
+            }
+            #[cfg(not(feature = "renderdoc"))]
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::{app::AppData, error};
+
+// Pacote de diagnóstico pra anexar em issues: cada entrada vira um arquivo dentro do zip
+#[derive(Clone, Debug)]
+pub struct Report {
+    entries: Vec<(String, String)>,
+}
+
+impl Report {
+    // Já começa com as mensagens recentes da validação, que existem mesmo sem App
+    pub fn collect() -> Self {
+        let mut validation = error::recent_messages().join("\n");
+        validation.push('\n');
+
+        Self {
+            entries: vec![("validation.log".to_string(), validation)],
+        }
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, contents: impl Into<String>) {
+        self.entries.push((name.into(), contents.into()));
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut zip = ZipWriter::new(File::create(path)?);
+
+        for (name, contents) in &self.entries {
+            zip.start_file(name.as_str(), FileOptions::default())?;
+            zip.write_all(contents.as_bytes())?;
+        }
+
+        zip.finish()?;
+        info!("Wrote diagnostics report to {:?}.", path);
+
+        Ok(())
+    }
+
+    // Grava em `report-<timestamp>.zip` no diretório atual
+    pub fn write_timestamped(&self) -> Result<PathBuf> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let path = PathBuf::from(format!("report-{}.zip", seconds));
+        self.write(&path)?;
+        Ok(path)
+    }
+}
+
+// Propriedades do dispositivo físico, memória e recursos habilitados em JSON
+pub unsafe fn device_json(instance: &Instance, data: &AppData) -> String {
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    let memory = instance.get_physical_device_memory_properties(data.physical_device);
+
+    let heaps = memory.memory_heaps[..memory.memory_heap_count as usize]
+        .iter()
+        .map(|h| format!("{{\"size\":{},\"flags\":\"{:?}\"}}", h.size, h.flags))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut features = data
+        .capabilities
+        .enabled
+        .iter()
+        .map(|f| format!("\"{:?}\"", f))
+        .collect::<Vec<_>>();
+    features.sort();
+
+    format!(
+        "{{\n  \"name\": \"{}\",\n  \"type\": \"{:?}\",\n  \"vendor_id\": {},\n  \"device_id\": {},\n  \"api_version\": \"{}.{}.{}\",\n  \"driver_version\": {},\n  \"memory_heaps\": [{}],\n  \"features\": [{}],\n  \"portability_subset\": {},\n  \"present_wait\": {},\n  \"full_screen_exclusive\": {}\n}}\n",
+        escape_json(&properties.device_name.to_string()),
+        properties.device_type,
+        properties.vendor_id,
+        properties.device_id,
+        vk::version_major(properties.api_version),
+        vk::version_minor(properties.api_version),
+        vk::version_patch(properties.api_version),
+        properties.driver_version,
+        heaps,
+        features.join(","),
+        data.capabilities.portability_subset,
+        data.capabilities.present_wait,
+        data.capabilities.full_screen_exclusive,
+    )
+}
+
+pub fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            // O resto dos caracteres de controle não pode aparecer cru numa string JSON
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -1,8 +1,16 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+
+layout(push_constant) uniform PushConstants {
+  // 0: o formato já codifica, 1: sRGB, 2: HDR10 (PQ)
+  layout(offset=64) uint transfer;
+} pcs;
 
 layout(location=0) in vec3 aColor;
 layout(location=0) out vec4 outColor;
 
 void main() {
-  outColor = vec4(aColor, 1.0);
-}
\ No newline at end of file
+  outColor = vec4(encodeOutput(aColor, pcs.transfer), 1.0);
+}
@@ -1,9 +1,10 @@
 #version 450
 
+// Triângulo no plano z = 0 do mundo (y pra cima)
 vec2 positions[3] = vec2[](
-  vec2(0.0, -0.5),
-  vec2(0.5, 0.5),
-  vec2(-0.5, 0.5)
+  vec2(0.0, 0.5),
+  vec2(0.5, -0.5),
+  vec2(-0.5, -0.5)
 );
 
 vec3 colors[3] = vec3[](
@@ -12,9 +13,21 @@ vec3 colors[3] = vec3[](
   vec3(0.0, 0.0, 1.0)
 );
 
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  // Já com a pré-rotação da swapchain
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+layout(push_constant) uniform PushConstants {
+  mat4 model;
+} pcs;
+
 layout(location=0) out vec3 aColor;
 
 void main() {
-  gl_Position = vec4(positions[gl_VertexIndex], 0.0, 1.0);
+  gl_Position = frame.viewProjection * pcs.model * vec4(positions[gl_VertexIndex], 0.0, 1.0);
   aColor = colors[gl_VertexIndex];
 }
\ No newline at end of file
@@ -0,0 +1,67 @@
+// Modelos de reflexão das superfícies, compartilhados entre o caminho forward (uma
+// shader por material) e a resolução do deferred
+
+const float PI = 3.14159265359;
+
+// Contribuição de uma luz vinda da direção `l` (da superfície pra luz), já atenuada
+vec3 shadeBlinnPhong(vec3 n, vec3 v, vec3 l, vec3 radiance, vec3 albedo, vec3 specularColor, float shininess) {
+  float diffuse = max(dot(n, l), 0.0);
+  if (diffuse == 0.0) {
+    return vec3(0.0);
+  }
+
+  vec3 h = normalize(l + v);
+  float specular = pow(max(dot(n, h), 0.0), max(shininess, 1.0));
+
+  return radiance * (albedo * diffuse + specularColor * specular);
+}
+
+float distributionGgx(float nDotH, float alpha) {
+  float a2 = alpha * alpha;
+  float d = nDotH * nDotH * (a2 - 1.0) + 1.0;
+  return a2 / (PI * d * d);
+}
+
+// Smith com a aproximação de Schlick, já dividido por 4 n.l n.v
+float visibilitySmith(float nDotL, float nDotV, float alpha) {
+  float k = alpha / 2.0;
+  float gl = nDotL / (nDotL * (1.0 - k) + k);
+  float gv = nDotV / (nDotV * (1.0 - k) + k);
+  return gl * gv / max(4.0 * nDotL * nDotV, 1e-4);
+}
+
+vec3 fresnelSchlick(float vDotH, vec3 f0) {
+  return f0 + (1.0 - f0) * pow(1.0 - vDotH, 5.0);
+}
+
+// Radiância refletida pra `v` de uma luz vinda de `l`
+vec3 shadePbr(vec3 n, vec3 v, vec3 l, vec3 radiance, vec3 albedo, float metallic, float alpha) {
+  float nDotL = max(dot(n, l), 0.0);
+  if (nDotL == 0.0) {
+    return vec3(0.0);
+  }
+
+  vec3 h = normalize(l + v);
+  float nDotV = max(dot(n, v), 1e-4);
+  float nDotH = max(dot(n, h), 0.0);
+  float vDotH = max(dot(v, h), 0.0);
+
+  // Dielétricos refletem ~4%; metais refletem a própria cor e não têm difusa
+  vec3 f0 = mix(vec3(0.04), albedo, metallic);
+  vec3 f = fresnelSchlick(vDotH, f0);
+  vec3 specular = f * distributionGgx(nDotH, alpha) * visibilitySmith(nDotL, nDotV, alpha);
+  vec3 diffuse = (1.0 - f) * (1.0 - metallic) * albedo / PI;
+
+  return (diffuse + specular) * radiance * nDotL;
+}
+
+// Integral do BRDF especular sobre o hemisfério (a parte do split sum que não depende da
+// luz), pela aproximação analítica do Karis pra mobile, sem LUT
+vec3 envBrdfApprox(vec3 f0, float roughness, float nDotV) {
+  const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+  const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+  vec4 r = roughness * c0 + c1;
+  float a004 = min(r.x * r.x, exp2(-9.28 * nDotV)) * r.x + r.y;
+  vec2 ab = vec2(-1.04, 1.04) * a004 + r.zw;
+  return f0 * ab.x + ab.y;
+}
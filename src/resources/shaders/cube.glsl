@@ -0,0 +1,14 @@
+// Direções das faces de um cubemap, compartilhadas pelos computes que leem as capturas
+// das sondas
+
+// Direção do texel (u, v em -1..1) na face, com a convenção das faces do Vulkan
+vec3 faceDirection(uint face, vec2 uv) {
+  switch (face) {
+    case 0u: return vec3(1.0, -uv.y, -uv.x);
+    case 1u: return vec3(-1.0, -uv.y, uv.x);
+    case 2u: return vec3(uv.x, 1.0, uv.y);
+    case 3u: return vec3(uv.x, -1.0, -uv.y);
+    case 4u: return vec3(uv.x, -uv.y, 1.0);
+  }
+  return vec3(-uv.x, -uv.y, -1.0);
+}
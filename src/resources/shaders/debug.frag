@@ -0,0 +1,55 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+#include "debug.glsl"
+
+// No lugar do tonemap.frag quando uma visão de debug está ligada: a cena tem o valor cru
+// (ver debugValue) e aqui vira cor
+layout(set=0, binding=0) uniform sampler2D sceneColor;
+
+layout(push_constant) uniform PushConstants {
+  uint transfer;
+  uint view;
+} pcs;
+
+layout(location=0) out vec4 outColor;
+
+// Azul com 1 camada até vermelho com 8 ou mais
+vec3 heatmap(float t) {
+  t = clamp(t, 0.0, 1.0);
+  return clamp(vec3(1.5 - abs(4.0 * t - 3.0), 1.5 - abs(4.0 * t - 2.0),
+    1.5 - abs(4.0 * t - 1.0)), 0.0, 1.0);
+}
+
+// Uma cor por cascata; fora de todas fica cinza
+vec3 cascadeColor(int cascade) {
+  const vec3 colors[4] = vec3[](
+    vec3(1.0, 0.2, 0.2),
+    vec3(0.2, 1.0, 0.2),
+    vec3(0.2, 0.4, 1.0),
+    vec3(1.0, 1.0, 0.2)
+  );
+  if (cascade < 0) {
+    return vec3(0.3);
+  }
+  return colors[cascade % 4];
+}
+
+void main() {
+  vec3 value = texelFetch(sceneColor, ivec2(gl_FragCoord.xy), 0).rgb;
+
+  vec3 color = value;
+  if (pcs.view == DEBUG_DEPTH) {
+    // Fundo (0) fica preto, o resto vai de branco perto até escuro longe
+    color = value.r > 0.0 ? vec3(exp(-value.r / 20.0)) : vec3(0.0);
+  } else if (pcs.view == DEBUG_OVERDRAW) {
+    float layers = value.r / OVERDRAW_STEP;
+    color = layers < 0.5 ? vec3(0.0) : heatmap((layers - 1.0) / 7.0);
+  } else if (pcs.view == DEBUG_CASCADES) {
+    // O que não foi desenhado também é 0: preto, não o cinza de fora das cascatas
+    color = value.r > 0.0 ? cascadeColor(int(round(value.r)) - 1) : vec3(0.0);
+  }
+
+  outColor = vec4(encodeOutput(clamp(color, 0.0, 1.0), pcs.transfer), 1.0);
+}
@@ -0,0 +1,37 @@
+// Visões de debug (DebugView do config.rs). As shaders da cena gravam o valor cru na cor
+// da cena e o debug.frag transforma em algo visível
+
+// Mesma ordem do DebugView::ALL
+#define DEBUG_FINAL 0u
+#define DEBUG_ALBEDO 1u
+#define DEBUG_NORMALS 2u
+#define DEBUG_DEPTH 3u
+#define DEBUG_ROUGHNESS_METALLIC 4u
+#define DEBUG_OVERDRAW 5u
+#define DEBUG_CASCADES 6u
+
+// Somado a cada camada no overdraw. Pequeno o bastante pra não estourar o albedo do
+// G-buffer (8 bits) antes de 32 camadas
+#define OVERDRAW_STEP (1.0 / 32.0)
+
+// Dados, não cor: sem função de transferência. `cascade` é o do cascadeIndex, e vai
+// como índice + 1 (0 fora de todas)
+vec3 debugValue(uint view, vec3 albedo, vec3 n, float viewDepth, float roughness,
+    float metallic, int cascade) {
+  if (view == DEBUG_ALBEDO) {
+    return albedo;
+  }
+  if (view == DEBUG_NORMALS) {
+    return n * 0.5 + 0.5;
+  }
+  if (view == DEBUG_DEPTH) {
+    return vec3(viewDepth);
+  }
+  if (view == DEBUG_ROUGHNESS_METALLIC) {
+    return vec3(roughness, metallic, 0.0);
+  }
+  if (view == DEBUG_CASCADES) {
+    return vec3(float(cascade + 1));
+  }
+  return vec3(0.0);
+}
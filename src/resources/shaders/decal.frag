@@ -0,0 +1,59 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "material.glsl"
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+  mat4 inverseView;
+  mat4 inverseProjection;
+} frame;
+
+// Depth das malhas, escrito no subpass do G-buffer
+layout(input_attachment_index=0, set=2, binding=0) uniform subpassInput depth;
+
+layout(push_constant) uniform PushConstants {
+  mat4 inverseWorld;
+  // Mistura também a normal do G-buffer
+  layout(offset=64) uint normals;
+  // 1 / tamanho do framebuffer, pra levar gl_FragCoord pro NDC
+  vec2 inverseExtent;
+} pcs;
+
+// Só rgb: o alpha dos dois guarda o modelo de luz e a profundidade (ver gbuffer.frag)
+layout(location=0) out vec4 outAlbedo;
+layout(location=1) out vec4 outNormal;
+
+void main() {
+  vec2 ndc = gl_FragCoord.xy * pcs.inverseExtent * 2.0 - 1.0;
+  vec4 view = frame.inverseProjection * vec4(ndc, subpassLoad(depth).r, 1.0);
+  vec3 world = (frame.inverseView * vec4(view.xyz / view.w, 1.0)).xyz;
+
+  // Normal da superfície pelas derivadas, antes do discard. Sai facetada, mas basta pra
+  // apagar o decal onde a superfície foge da direção da projeção
+  vec3 n = normalize(cross(dFdx(world), dFdy(world)));
+  if (dot(n, frame.cameraPosition.xyz - world) < 0.0) {
+    n = -n;
+  }
+
+  // Fora do volume (ou no céu, onde a posição vai pro infinito e vira NaN)
+  vec3 local = (pcs.inverseWorld * vec4(world, 1.0)).xyz;
+  if (!all(lessThanEqual(abs(local), vec3(0.5)))) {
+    discard;
+  }
+
+  // Linhas da inversa são os eixos do decal (divididos pela escala)
+  mat3 axes = transpose(mat3(pcs.inverseWorld));
+  vec3 axisX = normalize(axes[0]);
+  vec3 axisZ = normalize(axes[2]);
+
+  vec2 uv = vec2(local.x + 0.5, 0.5 - local.y);
+  vec4 base = texture(baseColorTexture, uv) * material.baseColor;
+  float alpha = base.a * smoothstep(0.2, 0.5, dot(n, axisZ));
+
+  outAlbedo = vec4(base.rgb, alpha);
+  outNormal = vec4(sampleNormal(n, vec4(axisX, 1.0), uv), pcs.normals != 0u ? alpha : 0.0);
+}
@@ -0,0 +1,31 @@
+#version 450
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+layout(push_constant) uniform PushConstants {
+  // Do mundo pro espaço do decal
+  mat4 inverseWorld;
+} pcs;
+
+// Cubo unitário em 36 vértices, sem vertex buffer: cada face é dois triângulos sobre os
+// cantos numerados pelos bits (x, y, z)
+const int indices[36] = int[36](
+  0, 2, 1, 1, 2, 3,
+  4, 5, 6, 5, 7, 6,
+  0, 1, 4, 1, 5, 4,
+  2, 6, 3, 3, 6, 7,
+  0, 4, 2, 2, 4, 6,
+  1, 3, 5, 3, 7, 5
+);
+
+void main() {
+  int corner = indices[gl_VertexIndex];
+  vec3 position = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1) - 0.5;
+
+  gl_Position = frame.viewProjection * inverse(pcs.inverseWorld) * vec4(position, 1.0);
+}
@@ -0,0 +1,140 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+#include "lights.glsl"
+#include "brdf.glsl"
+#include "debug.glsl"
+
+// Mesma ordem do ShaderVariant do material.rs
+#define MODEL_UNLIT 0u
+#define MODEL_HEADLIGHT 1u
+#define MODEL_BLINN_PHONG 2u
+#define MODEL_PBR 3u
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+  mat4 inverseView;
+  mat4 inverseProjection;
+} frame;
+
+// G-buffer escrito pelo subpass anterior (ver gbuffer.frag)
+layout(input_attachment_index=0, set=1, binding=0) uniform subpassInput gAlbedo;
+layout(input_attachment_index=1, set=1, binding=1) uniform subpassInput gNormal;
+layout(input_attachment_index=2, set=1, binding=2) uniform subpassInput gMaterial;
+layout(input_attachment_index=3, set=1, binding=3) uniform subpassInput gEmissive;
+
+layout(push_constant) uniform PushConstants {
+  uint transfer;
+  // DebugView::shader_index (ver debug.glsl)
+  uint debugView;
+  // 1 / tamanho do framebuffer, pra levar gl_FragCoord pro NDC
+  vec2 inverseExtent;
+} pcs;
+
+layout(location=0) out vec4 outColor;
+
+// Posição no mundo a partir da profundidade ao longo do olhar: acha o ponto do raio do
+// pixel com esse z na view, sem depender de como a projeção guarda o depth (reverse-Z,
+// plano distante infinito...)
+vec3 worldPosition(float viewDepth) {
+  vec2 ndc = gl_FragCoord.xy * pcs.inverseExtent * 2.0 - 1.0;
+  vec4 near = frame.inverseProjection * vec4(ndc, 0.25, 1.0);
+  vec4 far = frame.inverseProjection * vec4(ndc, 0.75, 1.0);
+  vec3 a = near.xyz / near.w;
+  vec3 b = far.xyz / far.w;
+
+  vec3 view = mix(a, b, (-viewDepth - a.z) / (b.z - a.z));
+  return (frame.inverseView * vec4(view, 1.0)).xyz;
+}
+
+vec3 shade(uint model, vec3 n, vec3 v, vec3 l, vec3 radiance, vec3 albedo, vec4 params) {
+  if (model == MODEL_PBR) {
+    return shadePbr(n, v, l, radiance, albedo, params.r, params.g * params.g);
+  }
+  return shadeBlinnPhong(n, v, l, radiance, albedo, params.rgb, params.a * 256.0);
+}
+
+void main() {
+  vec4 normalDepth = subpassLoad(gNormal);
+  float viewDepth = normalDepth.w;
+  // Nada foi desenhado aqui: fica o clear (ou o que estava na imagem)
+  if (viewDepth <= 0.0) {
+    discard;
+  }
+
+  vec4 albedo = subpassLoad(gAlbedo);
+  vec4 params = subpassLoad(gMaterial);
+  vec3 emissive = subpassLoad(gEmissive).rgb;
+  uint model = uint(round(albedo.a * 3.0));
+  vec3 n = normalize(normalDepth.xyz);
+
+  // O overdraw.frag somou um passo no albedo por camada
+  if (pcs.debugView == DEBUG_OVERDRAW) {
+    outColor = vec4(albedo.rgb, 1.0);
+    return;
+  }
+  if (pcs.debugView != DEBUG_FINAL) {
+    // Os parâmetros só são rugosidade e metálico no PBR
+    vec2 roughnessMetallic = model == MODEL_PBR ? params.gr : vec2(0.0);
+    vec3 value = debugValue(pcs.debugView, albedo.rgb, n, viewDepth, roughnessMetallic.x,
+      roughnessMetallic.y, cascadeIndex(viewDepth));
+    outColor = vec4(value, 1.0);
+    return;
+  }
+
+  vec3 position = worldPosition(viewDepth);
+  vec3 v = normalize(frame.cameraPosition.xyz - position);
+
+  if (model == MODEL_UNLIT) {
+    outColor = vec4(encodeOutput(albedo.rgb + emissive, pcs.transfer), 1.0);
+    return;
+  }
+
+  if (model == MODEL_HEADLIGHT) {
+    vec3 color = albedo.rgb * (0.2 + 0.8 * abs(dot(n, v))) + emissive;
+    outColor = vec4(encodeOutput(color, pcs.transfer), 1.0);
+    return;
+  }
+
+  // Só o PBR tem oclusão; no Blinn-Phong o b é a cor do especular
+  float occlusion = model == MODEL_PBR ? params.b : 1.0;
+  vec3 color = ambientLightAt(position, n) * albedo.rgb * occlusion;
+
+  // Especular do ambiente, da sonda de reflexo que cobre o ponto
+  if (model == MODEL_PBR) {
+    float roughness = params.g;
+    vec4 reflection = probeReflection(position, reflect(-v, n), roughness);
+    if (reflection.a > 0.0) {
+      vec3 f0 = mix(vec3(0.04), albedo.rgb, params.r);
+      vec3 brdf = envBrdfApprox(f0, roughness, max(dot(n, v), 1e-4));
+      color += reflection.rgb * brdf * reflection.a * occlusion;
+    }
+  }
+
+  vec3 sun = lights.directional.color.rgb;
+  if (max(sun.r, max(sun.g, sun.b)) > 0.0) {
+    vec3 l = -normalize(lights.directional.direction.xyz);
+    vec3 radiance = sun * directionalShadow(position, viewDepth);
+    color += shade(model, n, v, l, radiance, albedo.rgb, params);
+  }
+
+  for (uint i = 0; i < lights.pointCount; i++) {
+    PointLight light = lights.pointLights[i];
+    vec3 toLight = light.position.xyz - position;
+    float distance = length(toLight);
+    vec3 radiance = light.color.rgb * attenuation(distance, light.position.w)
+      * pointShadow(light, position);
+    color += shade(model, n, v, toLight / distance, radiance, albedo.rgb, params);
+  }
+
+  color += emissive;
+
+  if (lights.shadow.w != 0.0) {
+    color *= cascadeColor(viewDepth);
+  }
+  outColor = vec4(encodeOutput(color, pcs.transfer), 1.0);
+}
@@ -0,0 +1,34 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+  mat4 inverseView;
+  mat4 inverseProjection;
+} frame;
+
+#include "fog.glsl"
+
+layout(set=1, binding=2) uniform sampler3D integrated;
+layout(set=1, binding=3) uniform sampler2D depth;
+
+layout(location=0) out vec4 outColor;
+
+// Por cima da cena com o blend premultiplicado: a luz da névoa somada, a cena vezes a
+// transmitância
+void main() {
+  vec2 uv = gl_FragCoord.xy * pcs.inverseExtent;
+  vec4 view = frame.inverseProjection * vec4(uv * 2.0 - 1.0, texture(depth, uv).r, 1.0);
+  // No céu com o plano distante infinito o w vai a zero: conta como o fim do volume
+  float viewDepth = abs(view.w) > 1e-6 ? -view.z / view.w : pcs.albedo.w;
+
+  // A fatia i guarda o acumulado até o fim dela, e o centro dela está meia fatia antes
+  float slices = float(textureSize(integrated, 0).z);
+  float t = sliceCoordinate(viewDepth) - 0.5 / slices;
+  vec4 fog = texture(integrated, vec3(uv, t));
+
+  outColor = vec4(fog.rgb, 1.0 - fog.a);
+}
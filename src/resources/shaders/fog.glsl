@@ -0,0 +1,32 @@
+// Parâmetros e fatias da névoa volumétrica (fog.rs), compartilhados entre as duas
+// computes e a composição. Quem inclui já declarou o FrameUniforms
+
+layout(push_constant) uniform PushConstants {
+  // x: densidade, y: queda com a altura, z: altura em que a queda começa, w: anisotropia
+  vec4 medium;
+  // rgb: albedo, w: até que distância da câmera o volume vai
+  vec4 albedo;
+  // 1 / tamanho do framebuffer, só na composição
+  vec2 inverseExtent;
+} pcs;
+
+// As fatias ficam mais finas perto da câmera: a profundidade cresce com o quadrado de `t`,
+// que vai de 0 a 1 ao longo do volume
+float sliceDepth(float t) {
+  return pcs.albedo.w * t * t;
+}
+
+float sliceCoordinate(float viewDepth) {
+  return sqrt(clamp(viewDepth / pcs.albedo.w, 0.0, 1.0));
+}
+
+// Ponto da view no pixel `uv` (0 a 1 na tela) a `viewDepth` ao longo do olhar. Mesma ideia
+// do worldPosition do deferred.frag, que não depende de como a projeção guarda o depth
+vec3 viewPosition(vec2 uv, float viewDepth) {
+  vec2 ndc = uv * 2.0 - 1.0;
+  vec4 near = frame.inverseProjection * vec4(ndc, 0.25, 1.0);
+  vec4 far = frame.inverseProjection * vec4(ndc, 0.75, 1.0);
+  vec3 a = near.xyz / near.w;
+  vec3 b = far.xyz / far.w;
+  return mix(a, b, (-viewDepth - a.z) / (b.z - a.z));
+}
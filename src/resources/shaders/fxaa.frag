@@ -0,0 +1,168 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+
+// Saída do estágio anterior, já codificada (sRGB, PQ ou P3)
+layout(set=0, binding=0) uniform sampler2D inputColor;
+
+layout(push_constant) uniform PushConstants {
+  vec2 inverseExtent;
+  // Transferências da entrada e da saída (ver transfer.glsl). Diferentes, ex.: sRGB
+  // numa saída _SRGB, a cor é decodificada e codificada de novo
+  uint inputTransfer;
+  uint outputTransfer;
+} pcs;
+
+layout(location=0) out vec4 outColor;
+
+// Contraste mínimo pra considerar borda, absoluto e relativo ao pixel mais claro
+const float EDGE_THRESHOLD_MIN = 0.0312;
+const float EDGE_THRESHOLD_MAX = 0.125;
+// Quanto do serrilhado dentro de um pixel é suavizado
+const float SUBPIXEL_QUALITY = 0.75;
+// Passos da busca pelo fim da borda, cada vez mais largos
+const int SEARCH_STEPS = 12;
+const float STEP_SIZES[SEARCH_STEPS] = float[](
+  1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0
+);
+
+float luma(vec3 color) {
+  return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+float lumaAt(vec2 uv) {
+  return luma(textureLod(inputColor, uv, 0.0).rgb);
+}
+
+float lumaOffset(vec2 uv, ivec2 offset) {
+  return luma(textureLodOffset(inputColor, uv, 0.0, offset).rgb);
+}
+
+// FXAA 3.11 (Timothy Lottes), versão de qualidade simplificada
+vec3 fxaa(vec2 uv) {
+  vec3 center = textureLod(inputColor, uv, 0.0).rgb;
+  float lumaCenter = luma(center);
+
+  float lumaDown = lumaOffset(uv, ivec2(0, 1));
+  float lumaUp = lumaOffset(uv, ivec2(0, -1));
+  float lumaLeft = lumaOffset(uv, ivec2(-1, 0));
+  float lumaRight = lumaOffset(uv, ivec2(1, 0));
+
+  float lumaMin = min(lumaCenter, min(min(lumaDown, lumaUp), min(lumaLeft, lumaRight)));
+  float lumaMax = max(lumaCenter, max(max(lumaDown, lumaUp), max(lumaLeft, lumaRight)));
+  float range = lumaMax - lumaMin;
+
+  // Sem contraste suficiente: não é borda
+  if (range < max(EDGE_THRESHOLD_MIN, lumaMax * EDGE_THRESHOLD_MAX)) {
+    return center;
+  }
+
+  float lumaDownLeft = lumaOffset(uv, ivec2(-1, 1));
+  float lumaUpRight = lumaOffset(uv, ivec2(1, -1));
+  float lumaUpLeft = lumaOffset(uv, ivec2(-1, -1));
+  float lumaDownRight = lumaOffset(uv, ivec2(1, 1));
+
+  float lumaDownUp = lumaDown + lumaUp;
+  float lumaLeftRight = lumaLeft + lumaRight;
+  float lumaLeftCorners = lumaDownLeft + lumaUpLeft;
+  float lumaDownCorners = lumaDownLeft + lumaDownRight;
+  float lumaRightCorners = lumaDownRight + lumaUpRight;
+  float lumaUpCorners = lumaUpRight + lumaUpLeft;
+
+  // Borda horizontal ou vertical, pela variação em cada direção
+  float edgeHorizontal = abs(-2.0 * lumaLeft + lumaLeftCorners)
+    + abs(-2.0 * lumaCenter + lumaDownUp) * 2.0
+    + abs(-2.0 * lumaRight + lumaRightCorners);
+  float edgeVertical = abs(-2.0 * lumaUp + lumaUpCorners)
+    + abs(-2.0 * lumaCenter + lumaLeftRight) * 2.0
+    + abs(-2.0 * lumaDown + lumaDownCorners);
+  bool horizontal = edgeHorizontal >= edgeVertical;
+
+  // De qual lado do pixel a borda está
+  float luma1 = horizontal ? lumaUp : lumaLeft;
+  float luma2 = horizontal ? lumaDown : lumaRight;
+  float gradient1 = luma1 - lumaCenter;
+  float gradient2 = luma2 - lumaCenter;
+  bool steepest1 = abs(gradient1) >= abs(gradient2);
+  float gradientScaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+  float stepLength = horizontal ? pcs.inverseExtent.y : pcs.inverseExtent.x;
+  float lumaLocalAverage;
+  if (steepest1) {
+    stepLength = -stepLength;
+    lumaLocalAverage = 0.5 * (luma1 + lumaCenter);
+  } else {
+    lumaLocalAverage = 0.5 * (luma2 + lumaCenter);
+  }
+
+  // Começa no meio do caminho entre o pixel e o vizinho do outro lado da borda
+  vec2 edgeUv = uv;
+  if (horizontal) {
+    edgeUv.y += stepLength * 0.5;
+  } else {
+    edgeUv.x += stepLength * 0.5;
+  }
+
+  // Anda pros dois lados ao longo da borda até a luminância mudar
+  vec2 offset = horizontal ? vec2(pcs.inverseExtent.x, 0.0) : vec2(0.0, pcs.inverseExtent.y);
+  vec2 uv1 = edgeUv - offset;
+  vec2 uv2 = edgeUv + offset;
+  float lumaEnd1 = lumaAt(uv1) - lumaLocalAverage;
+  float lumaEnd2 = lumaAt(uv2) - lumaLocalAverage;
+  bool reached1 = abs(lumaEnd1) >= gradientScaled;
+  bool reached2 = abs(lumaEnd2) >= gradientScaled;
+
+  for (int i = 1; i < SEARCH_STEPS && !(reached1 && reached2); i++) {
+    if (!reached1) {
+      uv1 -= offset * STEP_SIZES[i];
+      lumaEnd1 = lumaAt(uv1) - lumaLocalAverage;
+      reached1 = abs(lumaEnd1) >= gradientScaled;
+    }
+    if (!reached2) {
+      uv2 += offset * STEP_SIZES[i];
+      lumaEnd2 = lumaAt(uv2) - lumaLocalAverage;
+      reached2 = abs(lumaEnd2) >= gradientScaled;
+    }
+  }
+
+  float distance1 = horizontal ? uv.x - uv1.x : uv.y - uv1.y;
+  float distance2 = horizontal ? uv2.x - uv.x : uv2.y - uv.y;
+  bool direction1 = distance1 < distance2;
+  float distanceFinal = min(distance1, distance2);
+  float edgeThickness = distance1 + distance2;
+
+  // Só desloca se a ponta mais próxima varia no sentido contrário ao centro
+  bool centerSmaller = lumaCenter < lumaLocalAverage;
+  bool correctVariation = ((direction1 ? lumaEnd1 : lumaEnd2) < 0.0) != centerSmaller;
+  float pixelOffset = correctVariation ? -distanceFinal / edgeThickness + 0.5 : 0.0;
+
+  // Serrilhado menor que um pixel, pela média do 3x3
+  float lumaAverage = (1.0 / 12.0) * (2.0 * (lumaDownUp + lumaLeftRight)
+    + lumaLeftCorners + lumaRightCorners);
+  float subPixelOffset1 = clamp(abs(lumaAverage - lumaCenter) / range, 0.0, 1.0);
+  float subPixelOffset2 = (-2.0 * subPixelOffset1 + 3.0) * subPixelOffset1 * subPixelOffset1;
+  float subPixelOffset = subPixelOffset2 * subPixelOffset2 * SUBPIXEL_QUALITY;
+
+  pixelOffset = max(pixelOffset, subPixelOffset);
+
+  vec2 finalUv = uv;
+  if (horizontal) {
+    finalUv.y += pixelOffset * stepLength;
+  } else {
+    finalUv.x += pixelOffset * stepLength;
+  }
+
+  return textureLod(inputColor, finalUv, 0.0).rgb;
+}
+
+void main() {
+  vec2 uv = gl_FragCoord.xy * pcs.inverseExtent;
+  vec3 color = fxaa(uv);
+
+  if (pcs.inputTransfer != pcs.outputTransfer) {
+    color = encodeOutput(decodeOutput(color, pcs.inputTransfer), pcs.outputTransfer);
+  }
+
+  outColor = vec4(color, 1.0);
+}
@@ -0,0 +1,70 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "material.glsl"
+
+// Mesma ordem do ShaderVariant do material.rs
+#define MODEL_UNLIT 0u
+#define MODEL_HEADLIGHT 1u
+#define MODEL_BLINN_PHONG 2u
+#define MODEL_PBR 3u
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+layout(push_constant) uniform PushConstants {
+  layout(offset=64) uint transfer;
+  // As visões de debug ficam pra resolução (deferred.frag)
+  uint debugView;
+  uint shadingModel;
+} pcs;
+
+layout(location=0) in vec3 aWorldPosition;
+layout(location=1) in vec3 aNormal;
+layout(location=2) in vec2 aUv;
+layout(location=3) in vec4 aTangent;
+
+// Mesma ordem do GBUFFER_FORMATS do pass.rs
+layout(location=0) out vec4 outAlbedo;
+layout(location=1) out vec4 outNormal;
+layout(location=2) out vec4 outMaterial;
+layout(location=3) out vec4 outEmissive;
+
+// Só grava o que cada modelo de shading precisa; a luz fica pra resolução
+void main() {
+  vec4 base = texture(baseColorTexture, aUv) * material.baseColor;
+  vec3 emissive = material.emissive.rgb;
+
+  vec3 n = normalize(aNormal);
+  if (!gl_FrontFacing) {
+    n = -n;
+  }
+
+  vec4 params = vec4(0.0);
+  if (pcs.shadingModel == MODEL_PBR) {
+    n = sampleNormal(n, aTangent, aUv);
+
+    vec4 metallicRoughness = texture(metallicRoughnessTexture, aUv);
+    params.r = clamp(metallicRoughness.b * material.metallic, 0.0, 1.0);
+    params.g = clamp(metallicRoughness.g * material.roughness, 0.04, 1.0);
+    params.b = mix(1.0, texture(occlusionTexture, aUv).r, material.occlusionStrength);
+    emissive *= texture(emissiveTexture, aUv).rgb;
+  } else if (pcs.shadingModel == MODEL_BLINN_PHONG) {
+    n = sampleNormal(n, aTangent, aUv);
+
+    // O expoente não cabe em 8 bits, então vai dividido por 256
+    params = vec4(material.specular.rgb, clamp(material.specular.a / 256.0, 0.0, 1.0));
+  }
+
+  // Distância ao longo do olhar da câmera, que nunca é 0 numa superfície visível
+  float viewDepth = -(frame.view * vec4(aWorldPosition, 1.0)).z;
+
+  outAlbedo = vec4(base.rgb, float(pcs.shadingModel) / 3.0);
+  outNormal = vec4(n, viewDepth);
+  outMaterial = params;
+  outEmissive = vec4(emissive, 0.0);
+}
@@ -0,0 +1,37 @@
+#version 450
+
+// Raios de sol em espaço de tela: cada pixel anda na direção do sol na tela e conta
+// quantas amostras do caminho são céu. Somado por cima da cena
+
+layout(set=0, binding=0) uniform sampler2D depth;
+
+layout(push_constant) uniform PushConstants {
+  // xy: sol na tela (0 a 1), z: amostras, w: peso de cada amostra sobre a anterior
+  vec4 sun;
+  // rgb: cor da luz já com a intensidade, w: depth do céu (o clear do pass da cena)
+  vec4 color;
+  // 1 / tamanho do framebuffer
+  vec2 inverseExtent;
+} pcs;
+
+layout(location=0) out vec4 outColor;
+
+void main() {
+  vec2 uv = gl_FragCoord.xy * pcs.inverseExtent;
+  int samples = int(pcs.sun.z);
+  vec2 step = (pcs.sun.xy - uv) / float(samples);
+
+  float light = 0.0;
+  float weight = 1.0;
+  vec2 coord = uv;
+  for (int i = 0; i < samples; i++) {
+    coord += step;
+    // Só onde nada foi desenhado o sol passa
+    if (texture(depth, coord).r == pcs.color.w) {
+      light += weight;
+    }
+    weight *= pcs.sun.w;
+  }
+
+  outColor = vec4(pcs.color.rgb * light / float(samples), 1.0);
+}
@@ -0,0 +1,37 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+
+// Saída do tone mapping, já codificada
+layout(set=0, binding=0) uniform sampler2D inputColor;
+// Recebe e devolve cor sRGB codificada, que é como as LUTs costumam ser feitas
+layout(set=0, binding=1) uniform sampler3D lut;
+
+layout(push_constant) uniform PushConstants {
+  uint inputTransfer;
+  uint outputTransfer;
+  // 0 enquanto a LUT ainda está subindo: a cor passa direto
+  uint useLut;
+  float lutSize;
+  // DOMAIN_MIN e 1 / (DOMAIN_MAX - DOMAIN_MIN) do .cube
+  vec4 domainMin;
+  vec4 domainScale;
+} pcs;
+
+layout(location=0) out vec4 outColor;
+
+void main() {
+  vec3 encoded = texelFetch(inputColor, ivec2(gl_FragCoord.xy), 0).rgb;
+  vec3 color = decodeOutput(encoded, pcs.inputTransfer);
+
+  if (pcs.useLut != 0u) {
+    vec3 coords = srgbEncode(clamp(color, 0.0, 1.0));
+    coords = clamp((coords - pcs.domainMin.xyz) * pcs.domainScale.xyz, 0.0, 1.0);
+    // As pontas do domínio caem no centro dos texels das bordas
+    coords = (coords * (pcs.lutSize - 1.0) + 0.5) / pcs.lutSize;
+    color = srgbDecode(clamp(textureLod(lut, coords, 0.0).rgb, 0.0, 1.0));
+  }
+
+  outColor = vec4(encodeOutput(color, pcs.outputTransfer), 1.0);
+}
@@ -0,0 +1,257 @@
+// Luzes do frame (set 0, binding 1). Tem que bater com o LightUniforms do uniforms.rs
+
+#include "sh.glsl"
+
+#define MAX_POINT_LIGHTS 16
+#define MAX_CASCADES 4
+#define MAX_POINT_SHADOWS 4
+
+struct DirectionalLight {
+  // xyz: pra onde a luz vai
+  vec4 direction;
+  // rgb: cor * intensidade
+  vec4 color;
+};
+
+struct PointLight {
+  // xyz: posição, w: alcance
+  vec4 position;
+  vec4 color;
+  // x: índice do cubo de sombra (-1 sem sombra), y: bias da comparação
+  vec4 shadow;
+};
+
+// Céu do Preetham (sky.rs)
+struct Sky {
+  // A..E da distribuição de Perez, xyz pra Y, x e y
+  vec4 perez[5];
+  // xyz: Yxy do zênite já normalizado, w: intensidade
+  vec4 zenith;
+  // xyz: pra onde o sol está, w: 1 com o céu ligado
+  vec4 sun;
+  // Irradiância em 9 harmônicos esféricos, já dividida por π
+  vec4 irradiance[9];
+};
+
+// Sonda de reflexo (probe.rs)
+struct ReflectionProbe {
+  // xyz: posição, w: raio de influência (0 sem sonda)
+  vec4 position;
+  // x: último mip do cubo, y: intensidade
+  vec4 params;
+};
+
+// Grade de sondas de irradiância (irradiance.rs)
+struct IrradianceGrid {
+  // xyz: posição da primeira sonda, w: 1 com a grade capturada
+  vec4 origin;
+  // xyz: distância entre as sondas em cada eixo
+  vec4 spacing;
+  // xyz: sondas em cada eixo
+  uvec4 counts;
+};
+
+layout(set=0, binding=1) uniform LightUniforms {
+  vec4 ambient;
+  DirectionalLight directional;
+  // Leva do mundo pra cada cascata do shadow map da luz direcional
+  mat4 cascadeViewProjection[MAX_CASCADES];
+  // Distância ao longo do olhar da câmera em que cada cascata termina
+  vec4 cascadeSplits;
+  // x: quantas cascatas (0 sem sombra), y: raio do PCF em texels, z: fração misturada
+  // com a próxima cascata, w: 1 pinta as cascatas
+  vec4 shadow;
+  PointLight pointLights[MAX_POINT_LIGHTS];
+  uint pointCount;
+  Sky sky;
+  ReflectionProbe probe;
+  IrradianceGrid irradianceGrid;
+} lights;
+
+layout(set=0, binding=2) uniform sampler2DArrayShadow shadowMap;
+// Distância até a luz dividida pelo alcance, em cada direção
+layout(set=0, binding=3) uniform samplerCubeShadow pointShadowMaps[MAX_POINT_SHADOWS];
+// Captura da sonda, com a rugosidade subindo a cada mip
+layout(set=0, binding=4) uniform samplerCube reflectionProbe;
+// 9 coeficientes (rgb) de irradiância por sonda da grade, já divididos por π
+layout(std430, set=0, binding=5) readonly buffer IrradianceProbes {
+  vec4 coefficients[];
+} irradianceProbes;
+
+// Luz ambiente na direção da normal: a irradiância do céu quando ligado, senão a constante
+vec3 ambientLight(vec3 n) {
+  if (lights.sky.sun.w == 0.0) {
+    return lights.ambient.rgb;
+  }
+
+  float basis[9] = shBasis(n);
+  vec3 irradiance = vec3(0.0);
+  for (int i = 0; i < 9; i++) {
+    irradiance += lights.sky.irradiance[i].rgb * basis[i];
+  }
+  return max(irradiance, vec3(0.0));
+}
+
+// Irradiância da sonda `index` da grade na direção da normal
+vec3 probeIrradiance(uint index, vec3 n) {
+  float basis[9] = shBasis(n);
+  vec3 irradiance = vec3(0.0);
+  for (uint i = 0u; i < 9u; i++) {
+    irradiance += irradianceProbes.coefficients[index * 9u + i].rgb * basis[i];
+  }
+  return irradiance;
+}
+
+// Luz ambiente num ponto: dentro da grade de sondas, a mistura trilinear das 8 em volta;
+// fora dela (ou sem ela), a mesma do ambientLight
+vec3 ambientLightAt(vec3 worldPosition, vec3 n) {
+  IrradianceGrid grid = lights.irradianceGrid;
+  if (grid.origin.w == 0.0) {
+    return ambientLight(n);
+  }
+
+  ivec3 counts = ivec3(grid.counts.xyz);
+  vec3 cell = (worldPosition - grid.origin.xyz) / grid.spacing.xyz;
+  if (any(lessThan(cell, vec3(-0.5))) || any(greaterThan(cell, vec3(counts) - 0.5))) {
+    return ambientLight(n);
+  }
+
+  cell = clamp(cell, vec3(0.0), vec3(counts - 1));
+  ivec3 base = ivec3(floor(cell));
+  vec3 t = cell - vec3(base);
+
+  vec3 irradiance = vec3(0.0);
+  for (int i = 0; i < 8; i++) {
+    ivec3 offset = ivec3(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+    ivec3 probe = min(base + offset, counts - 1);
+    vec3 weights = mix(1.0 - t, t, vec3(offset));
+    uint index = uint(probe.x + counts.x * (probe.y + counts.y * probe.z));
+    irradiance += probeIrradiance(index, n) * weights.x * weights.y * weights.z;
+  }
+  return max(irradiance, vec3(0.0));
+}
+
+// Cai com o inverso do quadrado, mas chega a zero certinho no alcance
+float attenuation(float distance, float range) {
+  float ratio = distance / range;
+  float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+  return window * window / (distance * distance + 1.0);
+}
+
+// Cascata que cobre o ponto, -1 se ele está além da última
+int cascadeIndex(float viewDepth) {
+  int count = int(lights.shadow.x);
+  for (int i = 0; i < count; i++) {
+    if (viewDepth < lights.cascadeSplits[i]) {
+      return i;
+    }
+  }
+  return -1;
+}
+
+// Cada amostra já compara e filtra 2x2 no hardware, e o PCF tira a média em volta
+float sampleCascade(int cascade, vec3 worldPosition) {
+  vec4 clip = lights.cascadeViewProjection[cascade] * vec4(worldPosition, 1.0);
+  vec3 ndc = clip.xyz / clip.w;
+  // Além do plano distante da luz não tem quem faça sombra
+  if (ndc.z > 1.0) {
+    return 1.0;
+  }
+
+  vec2 uv = ndc.xy * 0.5 + 0.5;
+  vec2 texel = 1.0 / vec2(textureSize(shadowMap, 0).xy);
+  int radius = int(lights.shadow.y);
+
+  float lit = 0.0;
+  for (int x = -radius; x <= radius; x++) {
+    for (int y = -radius; y <= radius; y++) {
+      lit += texture(shadowMap, vec4(uv + vec2(x, y) * texel, float(cascade), ndc.z));
+    }
+  }
+
+  float side = float(2 * radius + 1);
+  return lit / (side * side);
+}
+
+// Quanto da luz direcional chega no ponto: 1 iluminado, 0 na sombra. `viewDepth` é a
+// distância ao longo do olhar da câmera
+float directionalShadow(vec3 worldPosition, float viewDepth) {
+  int cascade = cascadeIndex(viewDepth);
+  if (cascade < 0) {
+    return 1.0;
+  }
+
+  float lit = sampleCascade(cascade, worldPosition);
+
+  // No fim da cascata mistura com a próxima (ou com "sem sombra", na última), pra
+  // troca de resolução não aparecer como uma linha
+  float start = cascade == 0 ? 0.0 : lights.cascadeSplits[cascade - 1];
+  float end = lights.cascadeSplits[cascade];
+  float band = lights.shadow.z * (end - start);
+  if (band > 0.0 && viewDepth > end - band) {
+    float next = 1.0;
+    if (cascade + 1 < int(lights.shadow.x)) {
+      next = sampleCascade(cascade + 1, worldPosition);
+    }
+    lit = mix(lit, next, (viewDepth - (end - band)) / band);
+  }
+
+  return lit;
+}
+
+// Debug: uma cor por cascata, branco além da última
+vec3 cascadeColor(float viewDepth) {
+  const vec3 colors[MAX_CASCADES] = vec3[](
+    vec3(1.0, 0.3, 0.3),
+    vec3(0.3, 1.0, 0.3),
+    vec3(0.3, 0.3, 1.0),
+    vec3(1.0, 1.0, 0.3)
+  );
+
+  int cascade = cascadeIndex(viewDepth);
+  return cascade < 0 ? vec3(1.0) : colors[cascade];
+}
+
+// Quanto da luz pontual chega no ponto: 1 iluminado, 0 na sombra
+float pointShadow(PointLight light, vec3 worldPosition) {
+  int index = int(light.shadow.x);
+  if (index < 0) {
+    return 1.0;
+  }
+
+  vec3 fromLight = worldPosition - light.position.xyz;
+  vec4 coord = vec4(fromLight, length(fromLight) / light.position.w - light.shadow.y);
+
+  // Índice constante em cada ramo: indexar um array de samplers com uma variável exige
+  // um recurso opcional do dispositivo
+  switch (index) {
+    case 0: return texture(pointShadowMaps[0], coord);
+    case 1: return texture(pointShadowMaps[1], coord);
+    case 2: return texture(pointShadowMaps[2], coord);
+    case 3: return texture(pointShadowMaps[3], coord);
+  }
+  return 1.0;
+}
+
+// Luz refletida da sonda na direção `r`, a partir de um ponto dentro da esfera de
+// influência (a é o peso, que cai a zero na borda). A direção é corrigida pela paralaxe:
+// o ambiente capturado fica na superfície da esfera, não no infinito
+vec4 probeReflection(vec3 worldPosition, vec3 r, float roughness) {
+  float radius = lights.probe.position.w;
+  vec3 fromCenter = worldPosition - lights.probe.position.xyz;
+  float distance = length(fromCenter);
+  if (radius <= 0.0 || distance >= radius) {
+    return vec4(0.0);
+  }
+
+  // Raio de dentro da esfera sempre acerta ela na frente
+  float b = dot(fromCenter, r);
+  float c = dot(fromCenter, fromCenter) - radius * radius;
+  float t = -b + sqrt(b * b - c);
+  vec3 direction = fromCenter + r * t;
+
+  float lod = roughness * lights.probe.params.x;
+  vec3 radiance = textureLod(reflectionProbe, direction, lod).rgb * lights.probe.params.y;
+  float weight = 1.0 - smoothstep(0.8 * radius, radius, distance);
+  return vec4(radiance, weight);
+}
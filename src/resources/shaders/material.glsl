@@ -0,0 +1,34 @@
+// Parâmetros e texturas do material (set 1), compartilhados entre as fragment shaders
+// das malhas. Tem que bater com o MaterialParams do material.rs
+
+layout(set=1, binding=0) uniform MaterialParams {
+  vec4 baseColor;
+  // rgb somado no fim
+  vec4 emissive;
+  // rgb: cor do especular, a: expoente do Blinn-Phong
+  vec4 specular;
+  float metallic;
+  float roughness;
+  float occlusionStrength;
+  float normalScale;
+} material;
+
+// Formato sRGB nas texturas de cor, então a amostra já vem linear
+layout(set=1, binding=1) uniform sampler2D baseColorTexture;
+layout(set=1, binding=2) uniform sampler2D normalTexture;
+// g: rugosidade, b: metálico
+layout(set=1, binding=3) uniform sampler2D metallicRoughnessTexture;
+layout(set=1, binding=4) uniform sampler2D occlusionTexture;
+layout(set=1, binding=5) uniform sampler2D emissiveTexture;
+
+// Normal do normal map levada pro mundo, com a base tangente interpolada dos vértices.
+// `n` já virado pro lado de quem olha
+vec3 sampleNormal(vec3 n, vec4 tangent, vec2 uv) {
+  vec3 mapped = texture(normalTexture, uv).xyz * 2.0 - 1.0;
+  mapped.xy *= material.normalScale;
+
+  // Gram-Schmidt, porque a interpolação tira a tangente do perpendicular
+  vec3 t = normalize(tangent.xyz - n * dot(n, tangent.xyz));
+  vec3 b = cross(n, t) * tangent.w;
+  return normalize(mat3(t, b, n) * mapped);
+}
@@ -0,0 +1,47 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+#include "material.glsl"
+#include "lights.glsl"
+#include "debug.glsl"
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+layout(push_constant) uniform PushConstants {
+  layout(offset=64) uint transfer;
+  // DebugView::shader_index (ver debug.glsl)
+  uint debugView;
+} pcs;
+
+layout(location=0) in vec3 aWorldPosition;
+layout(location=1) in vec3 aNormal;
+layout(location=2) in vec2 aUv;
+layout(location=3) in vec4 aTangent;
+
+layout(location=0) out vec4 outColor;
+
+void main() {
+  vec4 base = texture(baseColorTexture, aUv) * material.baseColor;
+
+  // Luz saindo da câmera, só pra dar forma enquanto a cena não tem iluminação
+  vec3 n = normalize(aNormal);
+  vec3 v = normalize(frame.cameraPosition.xyz - aWorldPosition);
+  float light = 0.2 + 0.8 * abs(dot(n, v));
+
+  if (pcs.debugView != DEBUG_FINAL) {
+    float viewDepth = -(frame.view * vec4(aWorldPosition, 1.0)).z;
+    vec3 value = debugValue(pcs.debugView, base.rgb, n, viewDepth, 0.0, 0.0,
+      cascadeIndex(viewDepth));
+    outColor = vec4(value, 1.0);
+    return;
+  }
+
+  vec3 color = base.rgb * light + material.emissive.rgb;
+  outColor = vec4(encodeOutput(color, pcs.transfer), base.a);
+}
@@ -0,0 +1,40 @@
+#version 450
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  // Já com a pré-rotação da swapchain
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+layout(push_constant) uniform PushConstants {
+  // Matriz de mundo do nó, já propagada pela hierarquia
+  mat4 model;
+} pcs;
+
+layout(location=0) in vec3 inPosition;
+layout(location=1) in vec3 inNormal;
+layout(location=2) in vec2 inUv;
+layout(location=3) in vec4 inTangent;
+
+layout(location=0) out vec3 aWorldPosition;
+layout(location=1) out vec3 aNormal;
+layout(location=2) out vec2 aUv;
+layout(location=3) out vec4 aTangent;
+
+// O pre-pass de depth usa esta mesma shader com outra fragment, e o pass da cena compara
+// com EQUAL: a posição não pode mudar com a otimização de cada pipeline
+invariant gl_Position;
+
+void main() {
+  vec4 world = pcs.model * vec4(inPosition, 1.0);
+  gl_Position = frame.viewProjection * world;
+
+  aWorldPosition = world.xyz;
+  // Inversa transposta, pra escala não uniforme não entortar a normal
+  aNormal = mat3(transpose(inverse(pcs.model))) * inNormal;
+  aUv = inUv;
+  // A tangente fica no plano da superfície, então vai com a matriz do modelo mesmo
+  aTangent = vec4(mat3(pcs.model) * inTangent.xyz, inTangent.w);
+}
@@ -0,0 +1,51 @@
+#version 450
+
+// Igual à mesh.vert, mas a matriz de mundo vem do buffer de instâncias do occlusion
+// culling, já que os desenhos indiretos não trocam push constants
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  // Já com a pré-rotação da swapchain
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+// Tem que bater com o GpuInstance do occlusion.rs
+struct Instance {
+  mat4 world;
+  vec4 boundsMin;
+  vec4 boundsMax;
+  // Só do cull.comp
+  uvec4 draw[2];
+};
+
+layout(std430, set=2, binding=0) readonly buffer Instances {
+  Instance instances[];
+};
+
+layout(location=0) in vec3 inPosition;
+layout(location=1) in vec3 inNormal;
+layout(location=2) in vec2 inUv;
+layout(location=3) in vec4 inTangent;
+
+layout(location=0) out vec3 aWorldPosition;
+layout(location=1) out vec3 aNormal;
+layout(location=2) out vec2 aUv;
+layout(location=3) out vec4 aTangent;
+
+// Pelo pre-pass de depth, como na mesh.vert
+invariant gl_Position;
+
+void main() {
+  // O cull.comp põe o índice da instância no firstInstance
+  mat4 model = instances[gl_InstanceIndex].world;
+
+  vec4 world = model * vec4(inPosition, 1.0);
+  gl_Position = frame.viewProjection * world;
+
+  aWorldPosition = world.xyz;
+  aNormal = mat3(transpose(inverse(model))) * inNormal;
+  aUv = inUv;
+  aTangent = vec4(mat3(model) * inTangent.xyz, inTangent.w);
+}
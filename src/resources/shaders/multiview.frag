@@ -0,0 +1,17 @@
+#version 450
+
+layout(set=0, binding=0) uniform Views {
+  mat4 viewProjection[6];
+  // xyz: pra onde o sol está, w: luz ambiente
+  vec4 light;
+} views;
+
+layout(location=0) in vec3 inNormal;
+
+layout(location=0) out vec4 outColor;
+
+void main() {
+  // Lambert simples, ainda sem os materiais
+  float diffuse = max(dot(normalize(inNormal), normalize(views.light.xyz)), 0.0);
+  outColor = vec4(vec3(diffuse + views.light.w), 1.0);
+}
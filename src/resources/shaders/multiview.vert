@@ -0,0 +1,24 @@
+#version 450
+#extension GL_EXT_multiview : require
+
+// Uma matriz por view; gl_ViewIndex diz qual camada do alvo está sendo desenhada
+layout(set=0, binding=0) uniform Views {
+  mat4 viewProjection[6];
+  // xyz: pra onde o sol está, w: luz ambiente
+  vec4 light;
+} views;
+
+layout(push_constant) uniform PushConstants {
+  // Só a de mundo; a da câmera vem das views
+  mat4 model;
+} pcs;
+
+layout(location=0) in vec3 inPosition;
+layout(location=1) in vec3 inNormal;
+
+layout(location=0) out vec3 outNormal;
+
+void main() {
+  gl_Position = views.viewProjection[gl_ViewIndex] * pcs.model * vec4(inPosition, 1.0);
+  outNormal = mat3(pcs.model) * inNormal;
+}
@@ -0,0 +1,11 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+  layout(offset=64) vec4 color;
+} pcs;
+
+layout(location=0) out vec4 outColor;
+
+void main() {
+  outColor = pcs.color;
+}
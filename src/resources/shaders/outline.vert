@@ -0,0 +1,24 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+  // Projeção vezes mundo
+  mat4 matrix;
+  // Espessura do contorno em NDC (zero na passada que só marca o stencil)
+  layout(offset=80) vec2 extrusion;
+} pcs;
+
+layout(location=0) in vec3 inPosition;
+layout(location=1) in vec3 inNormal;
+
+void main() {
+  vec4 position = pcs.matrix * vec4(inPosition, 1.0);
+
+  // A normal projetada dá a direção na tela; multiplicar pelo w desfaz a divisão da
+  // perspectiva, então a espessura fica igual a qualquer distância
+  vec2 direction = (pcs.matrix * vec4(inNormal, 0.0)).xy;
+  if (dot(direction, direction) > 0.0) {
+    position.xy += normalize(direction) * pcs.extrusion * position.w;
+  }
+
+  gl_Position = position;
+}
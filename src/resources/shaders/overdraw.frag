@@ -0,0 +1,19 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "debug.glsl"
+
+// Mesmas saídas do gbuffer.frag; no forward só a primeira tem attachment. Com blend
+// aditivo e sem depth, cada camada soma um passo
+layout(location=0) out vec4 outColor;
+layout(location=1) out vec4 outNormal;
+layout(location=2) out vec4 outMaterial;
+layout(location=3) out vec4 outEmissive;
+
+void main() {
+  outColor = vec4(OVERDRAW_STEP);
+  // O w é a profundidade no G-buffer: maior que 0 pra resolução não descartar o pixel
+  outNormal = vec4(0.0, 0.0, 0.0, 1.0);
+  outMaterial = vec4(0.0);
+  outEmissive = vec4(0.0);
+}
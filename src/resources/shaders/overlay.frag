@@ -0,0 +1,15 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+
+layout(push_constant) uniform PushConstants {
+  layout(offset=72) uint transfer;
+} pcs;
+
+layout(location=0) in vec4 aColor;
+layout(location=0) out vec4 outColor;
+
+void main() {
+  outColor = vec4(encodeOutput(aColor.rgb, pcs.transfer), aColor.a);
+}
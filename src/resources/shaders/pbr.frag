@@ -0,0 +1,88 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+#include "material.glsl"
+#include "lights.glsl"
+#include "brdf.glsl"
+#include "debug.glsl"
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+layout(push_constant) uniform PushConstants {
+  layout(offset=64) uint transfer;
+  // DebugView::shader_index (ver debug.glsl)
+  uint debugView;
+} pcs;
+
+layout(location=0) in vec3 aWorldPosition;
+layout(location=1) in vec3 aNormal;
+layout(location=2) in vec2 aUv;
+layout(location=3) in vec4 aTangent;
+
+layout(location=0) out vec4 outColor;
+
+void main() {
+  vec4 base = texture(baseColorTexture, aUv) * material.baseColor;
+  vec4 metallicRoughness = texture(metallicRoughnessTexture, aUv);
+  float metallic = clamp(metallicRoughness.b * material.metallic, 0.0, 1.0);
+  float roughness = clamp(metallicRoughness.g * material.roughness, 0.04, 1.0);
+  float alpha = roughness * roughness;
+  float occlusion = mix(1.0, texture(occlusionTexture, aUv).r, material.occlusionStrength);
+
+  vec3 n = normalize(aNormal);
+  if (!gl_FrontFacing) {
+    n = -n;
+  }
+  n = sampleNormal(n, aTangent, aUv);
+  vec3 v = normalize(frame.cameraPosition.xyz - aWorldPosition);
+
+  // Distância ao longo do olhar da câmera, que escolhe a cascata da sombra
+  float viewDepth = -(frame.view * vec4(aWorldPosition, 1.0)).z;
+
+  if (pcs.debugView != DEBUG_FINAL) {
+    vec3 value = debugValue(pcs.debugView, base.rgb, n, viewDepth, roughness, metallic,
+      cascadeIndex(viewDepth));
+    outColor = vec4(value, 1.0);
+    return;
+  }
+
+  // Ambiente difuso (constante, a irradiância do céu ou a das sondas), atenuado pela oclusão
+  vec3 color = ambientLightAt(aWorldPosition, n) * base.rgb * occlusion;
+
+  // Especular do ambiente, da sonda de reflexo que cobre o ponto
+  vec4 reflection = probeReflection(aWorldPosition, reflect(-v, n), roughness);
+  if (reflection.a > 0.0) {
+    vec3 f0 = mix(vec3(0.04), base.rgb, metallic);
+    vec3 brdf = envBrdfApprox(f0, roughness, max(dot(n, v), 1e-4));
+    color += reflection.rgb * brdf * reflection.a * occlusion;
+  }
+
+  vec3 sun = lights.directional.color.rgb;
+  if (max(sun.r, max(sun.g, sun.b)) > 0.0) {
+    vec3 l = -normalize(lights.directional.direction.xyz);
+    vec3 radiance = sun * directionalShadow(aWorldPosition, viewDepth);
+    color += shadePbr(n, v, l, radiance, base.rgb, metallic, alpha);
+  }
+
+  for (uint i = 0; i < lights.pointCount; i++) {
+    PointLight light = lights.pointLights[i];
+    vec3 toLight = light.position.xyz - aWorldPosition;
+    float distance = length(toLight);
+    vec3 radiance = light.color.rgb * attenuation(distance, light.position.w)
+      * pointShadow(light, aWorldPosition);
+    color += shadePbr(n, v, toLight / distance, radiance, base.rgb, metallic, alpha);
+  }
+
+  color += texture(emissiveTexture, aUv).rgb * material.emissive.rgb;
+
+  if (lights.shadow.w != 0.0) {
+    color *= cascadeColor(viewDepth);
+  }
+  outColor = vec4(encodeOutput(color, pcs.transfer), base.a);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+  // Índice do item na lista do frame + 1; o fundo fica com o 0 do clear
+  layout(offset=64) uint id;
+} pcs;
+
+layout(location=0) out uint outId;
+
+void main() {
+  outId = pcs.id;
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+  layout(offset=64) vec4 planes;
+} pcs;
+
+layout(location=0) in vec3 aViewPosition;
+
+void main() {
+  // A luz está na origem da vista, então o comprimento é a distância até ela
+  gl_FragDepth = length(aViewPosition) / pcs.planes.y;
+}
@@ -0,0 +1,23 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+  // Vista da face do cubo vezes a matriz de mundo
+  mat4 viewModel;
+  // x: plano próximo, y: alcance da luz
+  vec4 planes;
+} pcs;
+
+layout(location=0) in vec3 inPosition;
+
+layout(location=0) out vec3 aViewPosition;
+
+void main() {
+  vec4 view = pcs.viewModel * vec4(inPosition, 1.0);
+  aViewPosition = view.xyz;
+
+  // Perspectiva de 90° e aspect 1, igual à do glm::perspective_rh_zo
+  float near = pcs.planes.x;
+  float far = pcs.planes.y;
+  float z = view.z * far / (near - far) + near * far / (near - far);
+  gl_Position = vec4(view.x, view.y, z, -view.z);
+}
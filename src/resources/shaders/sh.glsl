@@ -0,0 +1,20 @@
+// Harmônicos esféricos de ordem 2 (9 coeficientes), na mesma ordem do sh_basis do sky.rs
+
+float[9] shBasis(vec3 n) {
+  return float[9](
+    0.282095,
+    0.488603 * n.y,
+    0.488603 * n.z,
+    0.488603 * n.x,
+    1.092548 * n.x * n.y,
+    1.092548 * n.y * n.z,
+    0.315392 * (3.0 * n.z * n.z - 1.0),
+    1.092548 * n.x * n.z,
+    0.546274 * (n.x * n.x - n.y * n.y)
+  );
+}
+
+// Convolução com o cosseno por banda (π, 2π/3, π/4), dividida por π
+const float SH_COSINE_BANDS[9] = float[9](
+  1.0, 2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 0.25, 0.25, 0.25, 0.25, 0.25
+);
@@ -0,0 +1,5 @@
+#version 450
+
+// Só a profundidade importa, escrita pelo rasterizador
+void main() {
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+  // Matriz da luz vezes a de mundo
+  mat4 lightModel;
+} pcs;
+
+layout(location=0) in vec3 inPosition;
+
+void main() {
+  gl_Position = pcs.lightModel * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,54 @@
+#version 450
+
+// Igual à mesh.vert, mas deforma o vértice pelas juntas do animador antes da matriz do nó
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  // Já com a pré-rotação da swapchain
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+// Poses de todos os animadores do frame, uma atrás da outra (ver animation.rs)
+layout(set=2, binding=0) readonly buffer JointMatrices {
+  mat4 joints[];
+} palette;
+
+layout(push_constant) uniform PushConstants {
+  // Matriz de mundo do nó, já propagada pela hierarquia
+  mat4 model;
+} pcs;
+
+layout(location=0) in vec3 inPosition;
+layout(location=1) in vec3 inNormal;
+layout(location=2) in vec2 inUv;
+layout(location=3) in vec4 inTangent;
+layout(location=4) in uvec4 inJoints;
+layout(location=5) in vec4 inWeights;
+
+layout(location=0) out vec3 aWorldPosition;
+layout(location=1) out vec3 aNormal;
+layout(location=2) out vec2 aUv;
+layout(location=3) out vec4 aTangent;
+
+// Pelo pre-pass de depth, como na mesh.vert
+invariant gl_Position;
+
+void main() {
+  // O firstInstance do desenho aponta pra primeira junta do animador
+  uint first = uint(gl_InstanceIndex);
+  mat4 skin = inWeights.x * palette.joints[first + inJoints.x]
+    + inWeights.y * palette.joints[first + inJoints.y]
+    + inWeights.z * palette.joints[first + inJoints.z]
+    + inWeights.w * palette.joints[first + inJoints.w];
+  mat4 model = pcs.model * skin;
+
+  vec4 world = model * vec4(inPosition, 1.0);
+  gl_Position = frame.viewProjection * world;
+
+  aWorldPosition = world.xyz;
+  aNormal = mat3(transpose(inverse(model))) * inNormal;
+  aUv = inUv;
+  aTangent = vec4(mat3(model) * inTangent.xyz, inTangent.w);
+}
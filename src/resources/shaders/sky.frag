@@ -0,0 +1,80 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+#include "lights.glsl"
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+  mat4 inverseView;
+  mat4 inverseProjection;
+} frame;
+
+layout(push_constant) uniform PushConstants {
+  uint transfer;
+  uint padding;
+  // 1 / tamanho do framebuffer, pra levar gl_FragCoord pro NDC
+  vec2 inverseExtent;
+} pcs;
+
+layout(location=0) out vec4 outColor;
+
+// Fração da cor do horizonte refletida pelo chão (igual ao sky.rs)
+const float GROUND_ALBEDO = 0.25;
+// Raio angular do sol, em radianos, e quanto ele brilha além do céu em volta
+const float SUN_RADIUS = 0.0047;
+const float SUN_BRIGHTNESS = 50.0;
+
+// F(θ, γ) da distribuição de Perez, pra Y, x e y de uma vez
+vec3 perez(float cosTheta, float gamma) {
+  vec3 a = lights.sky.perez[0].xyz;
+  vec3 b = lights.sky.perez[1].xyz;
+  vec3 c = lights.sky.perez[2].xyz;
+  vec3 d = lights.sky.perez[3].xyz;
+  vec3 e = lights.sky.perez[4].xyz;
+  float cosGamma = cos(gamma);
+  return (1.0 + a * exp(b / max(cosTheta, 0.01)))
+    * (1.0 + c * exp(d * gamma) + e * cosGamma * cosGamma);
+}
+
+vec3 skyRadiance(vec3 direction) {
+  vec3 sun = lights.sky.sun.xyz;
+  float gamma = acos(clamp(dot(direction, sun), -1.0, 1.0));
+  vec3 yxy = lights.sky.zenith.xyz * perez(direction.y, gamma);
+
+  // Yxy -> XYZ -> sRGB linear
+  float y = max(yxy.z, 1e-4);
+  vec3 xyz = vec3(yxy.y / y * yxy.x, yxy.x, (1.0 - yxy.y - y) / y * yxy.x);
+  vec3 rgb = mat3(
+    3.2406, -0.9689, 0.0557,
+    -1.5372, 1.8758, -0.2040,
+    -0.4986, 0.0415, 1.0570
+  ) * xyz;
+  return max(rgb, vec3(0.0)) * lights.sky.zenith.w;
+}
+
+void main() {
+  // Raio do pixel no mundo: só a direção importa, então qualquer profundidade serve
+  vec2 ndc = gl_FragCoord.xy * pcs.inverseExtent * 2.0 - 1.0;
+  vec4 view = frame.inverseProjection * vec4(ndc, 0.5, 1.0);
+  vec3 direction = normalize((frame.inverseView * vec4(view.xyz / view.w, 0.0)).xyz);
+
+  vec3 color;
+  if (direction.y < 0.0) {
+    // Abaixo do horizonte: o chão, com a cor do horizonte nessa direção
+    vec3 horizon = normalize(vec3(direction.x, 0.0, direction.z) + vec3(0.0, 1e-4, 0.0));
+    color = skyRadiance(horizon) * GROUND_ALBEDO;
+  } else {
+    color = skyRadiance(direction);
+
+    // Disco do sol, com a borda suavizada em um pixel mais ou menos
+    float angle = acos(clamp(dot(direction, lights.sky.sun.xyz), -1.0, 1.0));
+    float disk = 1.0 - smoothstep(SUN_RADIUS * 0.8, SUN_RADIUS, angle);
+    color += color * disk * SUN_BRIGHTNESS;
+  }
+
+  outColor = vec4(encodeOutput(color, pcs.transfer), 1.0);
+}
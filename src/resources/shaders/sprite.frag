@@ -0,0 +1,21 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+
+layout(push_constant) uniform PushConstants {
+  layout(offset=64) uint transfer;
+} pcs;
+
+// Formato sRGB, então a amostra já vem linear
+layout(binding=0) uniform sampler2D sprite;
+
+layout(location=0) in vec4 aColor;
+layout(location=1) in vec2 aUv;
+
+layout(location=0) out vec4 outColor;
+
+void main() {
+  vec4 color = texture(sprite, aUv) * aColor;
+  outColor = vec4(encodeOutput(color.rgb, pcs.transfer), color.a);
+}
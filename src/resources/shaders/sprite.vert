@@ -0,0 +1,37 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+  // Projeção ortográfica da câmera 2D, já com a pré-rotação
+  mat4 projection;
+} pcs;
+
+// Um sprite por instância
+layout(location=0) in vec2 inPosition;
+layout(location=1) in vec2 inSize;
+layout(location=2) in vec2 inOrigin;
+layout(location=3) in float inRotation;
+layout(location=4) in vec2 inUvMin;
+layout(location=5) in vec2 inUvMax;
+layout(location=6) in vec4 inColor;
+
+layout(location=0) out vec4 aColor;
+layout(location=1) out vec2 aUv;
+
+// Dois triângulos do quad, de (0, 0) a (1, 1)
+const vec2 CORNERS[6] = vec2[](
+  vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+  vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main() {
+  vec2 corner = CORNERS[gl_VertexIndex];
+  vec2 local = (corner - inOrigin) * inSize;
+
+  float c = cos(inRotation);
+  float s = sin(inRotation);
+  vec2 world = inPosition + vec2(local.x * c - local.y * s, local.x * s + local.y * c);
+
+  gl_Position = pcs.projection * vec4(world, 0.0, 1.0);
+  aColor = inColor;
+  aUv = mix(inUvMin, inUvMax, corner);
+}
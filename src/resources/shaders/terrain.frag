@@ -0,0 +1,45 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+#include "lights.glsl"
+#include "terrain.glsl"
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+layout(location=0) in vec3 aWorldPosition;
+
+layout(location=0) out vec4 outColor;
+
+const vec3 GRASS = vec3(0.18, 0.32, 0.10);
+const vec3 ROCK = vec3(0.35, 0.32, 0.28);
+const vec3 SNOW = vec3(0.90, 0.92, 0.95);
+
+void main() {
+  // A normal por pixel, direto do ruído, não depende de quanto o patch foi subdividido
+  float epsilon = pcs.size / float(pcs.patches) / 64.0;
+  vec3 n = terrainNormal(aWorldPosition.xz, epsilon);
+
+  // Pedra nas encostas, neve no alto do que não é muito íngreme
+  float slope = 1.0 - n.y;
+  float height = aWorldPosition.y / max(pcs.heightScale, 0.001);
+  vec3 albedo = mix(GRASS, ROCK, smoothstep(0.25, 0.45, slope));
+  albedo = mix(albedo, SNOW, smoothstep(0.7, 0.8, height) * (1.0 - smoothstep(0.4, 0.6, slope)));
+
+  float viewDepth = -(frame.view * vec4(aWorldPosition, 1.0)).z;
+  vec3 color = ambientLightAt(aWorldPosition, n) * albedo;
+
+  vec3 sun = lights.directional.color.rgb;
+  if (max(sun.r, max(sun.g, sun.b)) > 0.0) {
+    vec3 l = -normalize(lights.directional.direction.xyz);
+    float shadow = directionalShadow(aWorldPosition, viewDepth);
+    color += albedo * sun * max(dot(n, l), 0.0) * shadow;
+  }
+
+  outColor = vec4(encodeOutput(color, pcs.transfer), 1.0);
+}
@@ -0,0 +1,63 @@
+// Parâmetros e altura do terreno procedural (ver terrain.rs), iguais em todos os estágios
+
+layout(push_constant) uniform PushConstants {
+  // Lado do terreno e patches por lado
+  float size;
+  uint patches;
+  float heightScale;
+  // Frequência do primeiro oitavo do ruído
+  float frequency;
+  // Subdivisões perto (até `near`) e longe (a partir de `far`) da câmera
+  float maxLevel;
+  float minLevel;
+  float near;
+  float far;
+  uint transfer;
+  uint seed;
+} pcs;
+
+const int OCTAVES = 6;
+
+float hash(vec2 p) {
+  p = fract(p * vec2(123.34, 456.21) + float(pcs.seed) * 0.618034);
+  p += dot(p, p + 45.32);
+  return fract(p.x * p.y);
+}
+
+float valueNoise(vec2 p) {
+  vec2 i = floor(p);
+  vec2 f = fract(p);
+  vec2 u = f * f * (3.0 - 2.0 * f);
+
+  float a = hash(i);
+  float b = hash(i + vec2(1.0, 0.0));
+  float c = hash(i + vec2(0.0, 1.0));
+  float d = hash(i + vec2(1.0, 1.0));
+  return mix(mix(a, b, u.x), mix(c, d, u.x), u.y);
+}
+
+// Oitavos de ruído com a metade da amplitude e o dobro da frequência a cada passo
+float terrainHeight(vec2 xz) {
+  vec2 p = xz * pcs.frequency;
+  float height = 0.0;
+  float amplitude = 0.5;
+  for (int i = 0; i < OCTAVES; i++) {
+    height += amplitude * valueNoise(p);
+    // Deslocado pra os oitavos não alinharem na origem
+    p = p * 2.03 + vec2(17.1, 9.7);
+    amplitude *= 0.5;
+  }
+  return height * pcs.heightScale;
+}
+
+// Diferenças centrais em `epsilon`
+vec3 terrainNormal(vec2 xz, float epsilon) {
+  float dx = terrainHeight(xz + vec2(epsilon, 0.0)) - terrainHeight(xz - vec2(epsilon, 0.0));
+  float dz = terrainHeight(xz + vec2(0.0, epsilon)) - terrainHeight(xz - vec2(0.0, epsilon));
+  return normalize(vec3(-dx, 2.0 * epsilon, -dz));
+}
+
+// Canto da grade de patches no XZ do mundo, com o terreno centrado na origem
+vec2 gridPoint(uvec2 cell, float cellsPerSide) {
+  return vec2(cell) * (pcs.size / cellsPerSide) - pcs.size * 0.5;
+}
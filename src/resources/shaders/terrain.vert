@@ -0,0 +1,18 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "terrain.glsl"
+
+// Cantos dos patches pra tessellation, sem vertex buffer: 4 vértices por patch, e a
+// altura só vem na avaliação
+
+const uvec2 CORNERS[4] = uvec2[](uvec2(0, 0), uvec2(1, 0), uvec2(0, 1), uvec2(1, 1));
+
+void main() {
+  uint index = uint(gl_VertexIndex) / 4u;
+  uvec2 cell = uvec2(index % pcs.patches, index / pcs.patches);
+  vec2 xz = gridPoint(cell + CORNERS[gl_VertexIndex % 4], float(pcs.patches));
+
+  // No mundo, não no clip: o clip só sai da avaliação
+  gl_Position = vec4(xz.x, 0.0, xz.y, 1.0);
+}
@@ -0,0 +1,32 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "terrain.glsl"
+
+// Sem tessellation: cada patch vira uma grade fixa de GRID_DETAIL x GRID_DETAIL quadrados
+// (igual ao terrain.rs), 6 vértices por quadrado, sem vertex buffer
+const uint GRID_DETAIL = 8u;
+const uvec2 CORNERS[6] = uvec2[](
+  uvec2(0, 0), uvec2(0, 1), uvec2(1, 0),
+  uvec2(1, 0), uvec2(0, 1), uvec2(1, 1)
+);
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+layout(location=0) out vec3 aWorldPosition;
+
+void main() {
+  uint cells = pcs.patches * GRID_DETAIL;
+  uint index = uint(gl_VertexIndex) / 6u;
+  uvec2 cell = uvec2(index % cells, index / cells);
+  vec2 xz = gridPoint(cell + CORNERS[gl_VertexIndex % 6], float(cells));
+
+  vec3 position = vec3(xz.x, terrainHeight(xz), xz.y);
+  aWorldPosition = position;
+  gl_Position = frame.viewProjection * vec4(position, 1.0);
+}
@@ -0,0 +1,21 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+
+layout(push_constant) uniform PushConstants {
+  layout(offset=72) uint transfer;
+} pcs;
+
+// Cobertura dos glifos
+layout(binding=0) uniform sampler2D atlas;
+
+layout(location=0) in vec4 aColor;
+layout(location=1) in vec2 aUv;
+
+layout(location=0) out vec4 outColor;
+
+void main() {
+  float coverage = texture(atlas, aUv).r;
+  outColor = vec4(encodeOutput(aColor.rgb, pcs.transfer), aColor.a * coverage);
+}
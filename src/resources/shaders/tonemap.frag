@@ -0,0 +1,66 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+
+// Cor linear da cena, sem limite em 1.0
+layout(set=0, binding=0) uniform sampler2D sceneColor;
+
+// Escrito pelo exposure.comp
+layout(std430, set=0, binding=1) readonly buffer Exposure {
+  uint histogram[256];
+  float luminance;
+} adaptation;
+
+layout(push_constant) uniform PushConstants {
+  uint transfer;
+  // 0: ACES, 1: Reinhard, 2: só corta em 1
+  uint operator;
+  // 2^EV, aplicado antes da curva
+  float exposure;
+  // Diferente de 0: a luminância adaptada vai pro cinza médio, e `exposure` só compensa
+  uint autoExposure;
+} pcs;
+
+layout(location=0) out vec4 outColor;
+
+// Ajuste do Krzysztof Narkowicz pra curva do ACES (já com a exposição de referência)
+vec3 aces(vec3 x) {
+  const float a = 2.51;
+  const float b = 0.03;
+  const float c = 2.43;
+  const float d = 0.59;
+  const float e = 0.14;
+  return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+vec3 reinhard(vec3 x) {
+  return x / (1.0 + x);
+}
+
+void main() {
+  // O alvo tem o tamanho do framebuffer, então o pixel é o mesmo
+  float exposure = pcs.exposure;
+  if (pcs.autoExposure != 0u) {
+    exposure *= 0.18 / max(adaptation.luminance, 1e-4);
+  }
+
+  vec3 color = texelFetch(sceneColor, ivec2(gl_FragCoord.xy), 0).rgb * exposure;
+
+  // A curva é aplicada nas primárias da saída, pra uma tela P3 ou HDR10 ficar com as
+  // cores que cabem nela. O que nem lá cabe (cor negativa, sobra de alguma conta da
+  // cena) não faz sentido nas curvas e passa pelo mapeamento de gamut
+  float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+  color = gamutMap(outputPrimaries(pcs.transfer) * color, luminance);
+
+  if (pcs.operator == 0u) {
+    color = aces(color);
+  } else if (pcs.operator == 1u) {
+    color = reinhard(color);
+  } else {
+    color = clamp(color, 0.0, 1.0);
+  }
+  color = inverseOutputPrimaries(pcs.transfer) * color;
+
+  outColor = vec4(encodeOutput(color, pcs.transfer), 1.0);
+}
@@ -0,0 +1,128 @@
+// Funções de transferência da saída, compartilhadas entre as fragment shaders
+
+// Branco "de papel" em nits, usado pra levar a cor da cena pra escala absoluta do PQ
+const float PAPER_WHITE_NITS = 200.0;
+
+// Troca de primárias, todas com branco D65
+const mat3 REC709_TO_REC2020 = mat3(
+  0.6274, 0.0691, 0.0164,
+  0.3293, 0.9195, 0.0880,
+  0.0433, 0.0114, 0.8956
+);
+
+const mat3 REC2020_TO_REC709 = mat3(
+  1.6605, -0.1246, -0.0182,
+  -0.5876, 1.1329, -0.1006,
+  -0.0728, -0.0083, 1.1187
+);
+
+const mat3 REC709_TO_P3 = mat3(
+  0.8225, 0.0332, 0.0171,
+  0.1774, 0.9669, 0.0724,
+  0.0000, 0.0000, 0.9108
+);
+
+const mat3 P3_TO_REC709 = mat3(
+  1.2249, -0.0420, -0.0197,
+  -0.2247, 1.0419, -0.0786,
+  0.0000, 0.0000, 1.0979
+);
+
+vec3 srgbEncode(vec3 linear) {
+  vec3 low = linear * 12.92;
+  vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+  return mix(low, high, step(vec3(0.0031308), linear));
+}
+
+vec3 srgbDecode(vec3 encoded) {
+  vec3 low = encoded / 12.92;
+  vec3 high = pow((encoded + 0.055) / 1.055, vec3(2.4));
+  return mix(low, high, step(vec3(0.04045), encoded));
+}
+
+vec3 pqEncode(vec3 linear) {
+  vec3 y = clamp(REC709_TO_REC2020 * linear * PAPER_WHITE_NITS / 10000.0, 0.0, 1.0);
+
+  const float m1 = 0.1593017578125;
+  const float m2 = 78.84375;
+  const float c1 = 0.8359375;
+  const float c2 = 18.8515625;
+  const float c3 = 18.6875;
+
+  vec3 ym = pow(y, vec3(m1));
+  return pow((c1 + c2 * ym) / (1.0 + c3 * ym), vec3(m2));
+}
+
+vec3 pqDecode(vec3 encoded) {
+  const float m1 = 0.1593017578125;
+  const float m2 = 78.84375;
+  const float c1 = 0.8359375;
+  const float c2 = 18.8515625;
+  const float c3 = 18.6875;
+
+  vec3 ep = pow(encoded, vec3(1.0 / m2));
+  vec3 y = pow(max(ep - c1, 0.0) / (c2 - c3 * ep), vec3(1.0 / m1));
+
+  return REC2020_TO_REC709 * y * 10000.0 / PAPER_WHITE_NITS;
+}
+
+// 0: o formato já codifica, 1: sRGB, 2: HDR10 (PQ), 3: Display P3, 4: sRGB estendido
+// linear (scRGB, em ponto flutuante: passa como está, até o que sai do [0, 1])
+vec3 encodeOutput(vec3 color, uint transfer) {
+  if (transfer == 1u) {
+    return srgbEncode(color);
+  } else if (transfer == 2u) {
+    return pqEncode(color);
+  } else if (transfer == 3u) {
+    return srgbEncode(clamp(REC709_TO_P3 * color, 0.0, 1.0));
+  }
+
+  return color;
+}
+
+// O contrário, pra quem lê o que outro estágio escreveu
+vec3 decodeOutput(vec3 color, uint transfer) {
+  if (transfer == 1u) {
+    return srgbDecode(color);
+  } else if (transfer == 2u) {
+    return pqDecode(color);
+  } else if (transfer == 3u) {
+    return P3_TO_REC709 * srgbDecode(color);
+  }
+
+  return color;
+}
+
+// BT.709 -> primárias da saída. A cena é calculada em BT.709, mas uma saída mais larga
+// mostra cores que lá ficariam negativas
+mat3 outputPrimaries(uint transfer) {
+  if (transfer == 2u) {
+    return REC709_TO_REC2020;
+  } else if (transfer == 3u) {
+    return REC709_TO_P3;
+  }
+
+  return mat3(1.0);
+}
+
+mat3 inverseOutputPrimaries(uint transfer) {
+  if (transfer == 2u) {
+    return REC2020_TO_REC709;
+  } else if (transfer == 3u) {
+    return P3_TO_REC709;
+  }
+
+  return mat3(1.0);
+}
+
+// Mapeamento de gamut: o que fica negativo nas primárias da saída é misturado com o
+// cinza de mesma luminância até caber, em vez de cortar canal por canal (que muda o tom)
+vec3 gamutMap(vec3 color, float luminance) {
+  float gray = max(luminance, 0.0);
+  float lowest = min(color.r, min(color.g, color.b));
+  if (lowest < 0.0) {
+    color = mix(color, vec3(gray), lowest / (lowest - gray));
+  }
+
+  return max(color, vec3(0.0));
+}
@@ -0,0 +1,22 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+
+layout(push_constant) uniform PushConstants {
+  layout(offset=72) uint transfer;
+} pcs;
+
+// Cobertura dos glifos; formas sem texto apontam pra um texel branco
+layout(binding=0) uniform sampler2D font;
+
+layout(location=0) in vec4 aColor;
+layout(location=1) in vec2 aUv;
+
+layout(location=0) out vec4 outColor;
+
+void main() {
+  // O egui manda a cor em sRGB com alpha pré-multiplicado
+  vec4 color = vec4(srgbDecode(aColor.rgb), aColor.a) * texture(font, aUv).r;
+  outColor = vec4(encodeOutput(color.rgb, pcs.transfer), color.a);
+}
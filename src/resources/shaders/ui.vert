@@ -0,0 +1,21 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+  mat4 transform;
+  // Tamanho da tela em pontos do egui
+  vec2 screen;
+} pcs;
+
+layout(location=0) in vec2 inPosition;
+layout(location=1) in vec2 inUv;
+layout(location=2) in vec4 inColor;
+
+layout(location=0) out vec4 aColor;
+layout(location=1) out vec2 aUv;
+
+void main() {
+  vec2 ndc = inPosition / pcs.screen * 2.0 - 1.0;
+  gl_Position = pcs.transform * vec4(ndc, 0.0, 1.0);
+  aColor = inColor;
+  aUv = inUv;
+}
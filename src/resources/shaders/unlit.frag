@@ -0,0 +1,40 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "transfer.glsl"
+#include "material.glsl"
+#include "lights.glsl"
+#include "debug.glsl"
+
+layout(set=0, binding=0) uniform FrameUniforms {
+  mat4 view;
+  mat4 projection;
+  mat4 viewProjection;
+  vec4 cameraPosition;
+} frame;
+
+layout(push_constant) uniform PushConstants {
+  layout(offset=64) uint transfer;
+  // DebugView::shader_index (ver debug.glsl)
+  uint debugView;
+} pcs;
+
+layout(location=0) in vec3 aWorldPosition;
+layout(location=1) in vec3 aNormal;
+layout(location=2) in vec2 aUv;
+
+layout(location=0) out vec4 outColor;
+
+void main() {
+  vec4 base = texture(baseColorTexture, aUv) * material.baseColor;
+
+  if (pcs.debugView != DEBUG_FINAL) {
+    float viewDepth = -(frame.view * vec4(aWorldPosition, 1.0)).z;
+    vec3 value = debugValue(pcs.debugView, base.rgb, normalize(aNormal), viewDepth, 0.0,
+      0.0, cascadeIndex(viewDepth));
+    outColor = vec4(value, 1.0);
+    return;
+  }
+
+  outColor = vec4(encodeOutput(base.rgb + material.emissive.rgb, pcs.transfer), base.a);
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::error::RendererError;
+
+// Anisotropia pedida quando a configuração não diz outra, se o dispositivo aguentar
+pub const DEFAULT_MAX_ANISOTROPY: f32 = 16.0;
+
+// O que define um sampler. Dois pedidos iguais recebem o mesmo vk::Sampler: quem pede não
+// destrói, todos morrem juntos em `clear`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SamplerDesc {
+    pub filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode: vk::SamplerAddressMode,
+    pub border_color: vk::BorderColor,
+    // Sampler de comparação, pros mapas de sombra
+    pub compare_op: Option<vk::CompareOp>,
+    // Usa a anisotropia configurada (ver `set_max_anisotropy`). Só faz diferença com mips
+    pub anisotropic: bool,
+    pub min_lod: f32,
+    pub max_lod: f32,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+            compare_op: None,
+            anisotropic: false,
+            min_lod: 0.0,
+            max_lod: 0.0,
+        }
+    }
+}
+
+// Os f32 entram pelos bits, a anisotropia já resolvida
+type Key = (
+    vk::Filter,
+    vk::SamplerMipmapMode,
+    vk::SamplerAddressMode,
+    vk::BorderColor,
+    Option<vk::CompareOp>,
+    u32,
+    u32,
+    u32,
+);
+
+struct Cache {
+    samplers: HashMap<Key, vk::Sampler>,
+    // maxSamplerAnisotropy do dispositivo, ou 1 sem o Feature::SamplerAnisotropy
+    limit: f32,
+    // O que foi pedido na configuração, antes do limite
+    requested: f32,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache {
+        samplers: HashMap::new(),
+        limit: 1.0,
+        requested: 1.0,
+    });
+}
+
+// Chamado ao criar o dispositivo lógico
+pub fn configure(limit: f32, requested: f32) {
+    let mut cache = CACHE.lock().unwrap();
+    cache.limit = limit.max(1.0);
+    cache.requested = requested.max(1.0);
+}
+
+// Anisotropia que os samplers `anisotropic` pedidos daqui pra frente usam. Os já criados
+// continuam valendo, quem quiser a nova pede de novo
+pub fn set_max_anisotropy(value: f32) {
+    CACHE.lock().unwrap().requested = value.max(1.0);
+}
+
+// A pedida, já dentro do limite do dispositivo
+pub fn max_anisotropy() -> f32 {
+    let cache = CACHE.lock().unwrap();
+    cache.requested.min(cache.limit)
+}
+
+pub fn anisotropy_limit() -> f32 {
+    CACHE.lock().unwrap().limit
+}
+
+pub unsafe fn get(device: &Device, desc: &SamplerDesc) -> Result<vk::Sampler> {
+    let mut cache = CACHE.lock().unwrap();
+    let anisotropy = if desc.anisotropic {
+        cache.requested.min(cache.limit)
+    } else {
+        1.0
+    };
+
+    let key = (
+        desc.filter,
+        desc.mipmap_mode,
+        desc.address_mode,
+        desc.border_color,
+        desc.compare_op,
+        anisotropy.to_bits(),
+        desc.min_lod.to_bits(),
+        desc.max_lod.to_bits(),
+    );
+    if let Some(sampler) = cache.samplers.get(&key) {
+        return Ok(*sampler);
+    }
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(desc.filter)
+        .min_filter(desc.filter)
+        .mipmap_mode(desc.mipmap_mode)
+        .address_mode_u(desc.address_mode)
+        .address_mode_v(desc.address_mode)
+        .address_mode_w(desc.address_mode)
+        .border_color(desc.border_color)
+        .anisotropy_enable(anisotropy > 1.0)
+        .max_anisotropy(anisotropy)
+        .compare_enable(desc.compare_op.is_some())
+        .compare_op(desc.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+        .min_lod(desc.min_lod)
+        .max_lod(desc.max_lod)
+        .unnormalized_coordinates(false);
+
+    let sampler = device
+        .create_sampler(&info, None)
+        .map_err(RendererError::from)?;
+    cache.samplers.insert(key, sampler);
+    debug!(
+        "Created sampler {:?} ({} cached).",
+        desc,
+        cache.samplers.len()
+    );
+
+    Ok(sampler)
+}
+
+// Destrói todos. Só depois que nenhum descriptor set que usa algum deles vai ser lido
+pub unsafe fn clear(device: &Device) {
+    let mut cache = CACHE.lock().unwrap();
+    cache
+        .samplers
+        .drain()
+        .for_each(|(_, s)| device.destroy_sampler(s, None));
+}
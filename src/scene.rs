@@ -0,0 +1,559 @@
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+
+use crate::{
+    animation::AnimatorId,
+    camera::{Camera, Ray},
+    material::MaterialId,
+    mesh::{MeshId, MeshRenderer},
+};
+
+// Posição, rotação e escala de um nó em relação ao pai
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: glm::Vec3,
+    pub rotation: glm::Quat,
+    pub scale: glm::Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: glm::vec3(0.0, 0.0, 0.0),
+            rotation: glm::quat_identity(),
+            scale: glm::vec3(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: glm::Vec3) -> Self {
+        Self {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    // Escala, depois rotação, depois translação
+    pub fn matrix(&self) -> glm::Mat4 {
+        glm::translation(&self.translation)
+            * glm::quat_to_mat4(&self.rotation)
+            * glm::scaling(&self.scale)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Light {
+    // Ilumina na direção do -Z local do nó, vinda do infinito
+    Directional {
+        color: glm::Vec3,
+        intensity: f32,
+    },
+    // Sai da posição do nó e some em `range`
+    Point {
+        color: glm::Vec3,
+        intensity: f32,
+        range: f32,
+        // Projeta sombra num cubo (só as primeiras MAX_POINT_SHADOWS da cena)
+        shadows: bool,
+    },
+}
+
+// Luz já posicionada no mundo
+#[derive(Copy, Clone, Debug)]
+pub struct SceneLight {
+    pub light: Light,
+    pub position: glm::Vec3,
+    pub direction: glm::Vec3,
+}
+
+// Projeta um material nas superfícies dentro do cubo unitário do nó ([-0.5, 0.5] em cada
+// eixo), olhando pro -Z local como as luzes. A cor base (e a normal, se `normals`) é
+// misturada pelo alpha do material por cima do que as malhas deixaram no G-buffer. Só no
+// deferred
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Decal {
+    pub material: MaterialId,
+    pub normals: bool,
+}
+
+impl Decal {
+    pub fn new(material: MaterialId) -> Self {
+        Self {
+            material,
+            normals: true,
+        }
+    }
+}
+
+// Decal já posicionado no mundo: a escala da matriz é o tamanho do volume
+#[derive(Copy, Clone, Debug)]
+pub struct SceneDecal {
+    pub decal: Decal,
+    pub world: glm::Mat4,
+}
+
+// O índice é reaproveitado quando um nó é removido; a geração impede que um id antigo
+// acabe apontando pro nó novo
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: u32,
+    generation: u32,
+}
+
+// Dono de um DrawItem, que o picking devolve: um nó da cena ou um id qualquer de quem
+// desenha com `App::draw_mesh` (ex: a entidade do ECS)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EntityId {
+    Node(NodeId),
+    External(u64),
+}
+
+// O que o `Scene::raycast` testa em cada malha
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RayTest {
+    // Só a caixa da malha, no espaço do nó
+    Bounds,
+    // Os triângulos, depois de passar pela caixa
+    Triangles,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub node: NodeId,
+    // Em unidades do mundo, a partir da origem do raio
+    pub distance: f32,
+    pub position: glm::Vec3,
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: String,
+    pub transform: Transform,
+    // Esconde o nó e todos os filhos
+    pub visible: bool,
+    pub mesh: Option<MeshId>,
+    // None usa o material padrão
+    pub material: Option<MaterialId>,
+    // Pose que deforma a malha, se ela tiver skinning
+    pub animator: Option<AnimatorId>,
+    pub light: Option<Light>,
+    pub decal: Option<Decal>,
+    pub camera: Option<Camera>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    // Calculada no `Scene::update`
+    world: glm::Mat4,
+}
+
+impl Node {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            transform: Transform::default(),
+            visible: true,
+            mesh: None,
+            material: None,
+            animator: None,
+            light: None,
+            decal: None,
+            camera: None,
+            parent: None,
+            children: vec![],
+            world: glm::identity(),
+        }
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_mesh(mut self, mesh: MeshId) -> Self {
+        self.mesh = Some(mesh);
+        self
+    }
+
+    pub fn with_material(mut self, material: MaterialId) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn with_animator(mut self, animator: AnimatorId) -> Self {
+        self.animator = Some(animator);
+        self
+    }
+
+    pub fn with_light(mut self, light: Light) -> Self {
+        self.light = Some(light);
+        self
+    }
+
+    pub fn with_decal(mut self, decal: Decal) -> Self {
+        self.decal = Some(decal);
+        self
+    }
+
+    pub fn with_camera(mut self, camera: Camera) -> Self {
+        self.camera = Some(camera);
+        self
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    // Matriz de mundo do último `Scene::update`
+    pub fn world(&self) -> &glm::Mat4 {
+        &self.world
+    }
+}
+
+// Uma malha posicionada no mundo, pronta pro renderizador
+#[derive(Copy, Clone, Debug)]
+pub struct DrawItem {
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    pub world: glm::Mat4,
+    pub animator: Option<AnimatorId>,
+    // None não aparece no picking
+    pub entity: Option<EntityId>,
+}
+
+#[derive(Debug)]
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+// Hierarquia de nós. As matrizes de mundo só são recalculadas no `update`, uma vez por
+// frame, antes de montar a lista de desenho
+#[derive(Debug, Default)]
+pub struct Scene {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adiciona um nó sem pai
+    pub fn add(&mut self, mut node: Node) -> NodeId {
+        node.parent = None;
+        node.children.clear();
+
+        let id = self.insert(node);
+        self.roots.push(id);
+        id
+    }
+
+    pub fn add_child(&mut self, parent: NodeId, mut node: Node) -> Result<NodeId> {
+        if self.get(parent).is_none() {
+            return Err(anyhow!("Node {:?} does not exist.", parent));
+        }
+
+        node.parent = Some(parent);
+        node.children.clear();
+
+        let id = self.insert(node);
+        self.node_mut(parent).children.push(id);
+        Ok(id)
+    }
+
+    fn insert(&mut self, node: Node) -> NodeId {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    // Remove o nó e toda a subárvore dele. Retorna falso se o id já não valia
+    pub fn remove(&mut self, id: NodeId) -> bool {
+        let parent = match self.get(id) {
+            Some(node) => node.parent,
+            None => return false,
+        };
+        self.detach(id, parent);
+
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let slot = &mut self.slots[id.index as usize];
+            if let Some(node) = slot.node.take() {
+                stack.extend(node.children);
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(id.index);
+            }
+        }
+
+        true
+    }
+
+    // Move o nó (com os filhos) pra baixo de outro pai, ou pra raiz com None. A
+    // transformação local é mantida, então ele pode mudar de lugar no mundo
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> Result<()> {
+        let old_parent = match self.get(id) {
+            Some(node) => node.parent,
+            None => return Err(anyhow!("Node {:?} does not exist.", id)),
+        };
+
+        if let Some(parent) = parent {
+            if self.get(parent).is_none() {
+                return Err(anyhow!("Node {:?} does not exist.", parent));
+            }
+
+            // O novo pai não pode estar dentro da própria subárvore
+            let mut ancestor = Some(parent);
+            while let Some(current) = ancestor {
+                if current == id {
+                    return Err(anyhow!(
+                        "Node {:?} can't be parented to its own descendant {:?}.",
+                        id,
+                        parent
+                    ));
+                }
+                ancestor = self.node(current).parent;
+            }
+        }
+
+        self.detach(id, old_parent);
+        self.node_mut(id).parent = parent;
+        match parent {
+            Some(parent) => self.node_mut(parent).children.push(id),
+            None => self.roots.push(id),
+        }
+
+        Ok(())
+    }
+
+    // Tira o nó da lista de filhos do pai (ou das raízes)
+    fn detach(&mut self, id: NodeId, parent: Option<NodeId>) {
+        let siblings = match parent {
+            Some(parent) => &mut self.node_mut(parent).children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|child| *child != id);
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+    }
+
+    // Pros ids que a própria cena garante que existem
+    fn node(&self, id: NodeId) -> &Node {
+        self.get(id).expect("Scene references a removed node.")
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.get_mut(id).expect("Scene references a removed node.")
+    }
+
+    // Primeiro nó com esse nome, em nenhuma ordem em particular
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.iter()
+            .find(|(_, node)| node.name == name)
+            .map(|(id, _)| id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.node.as_ref().map(|node| {
+                let id = NodeId {
+                    index: index as u32,
+                    generation: slot.generation,
+                };
+                (id, node)
+            })
+        })
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Os ids antigos deixam de valer
+    pub fn clear(&mut self) {
+        let roots = std::mem::take(&mut self.roots);
+        roots.into_iter().for_each(|id| {
+            self.remove(id);
+        });
+    }
+
+    // Propaga as transformações de cima pra baixo
+    pub fn update(&mut self) {
+        let mut stack = self
+            .roots
+            .iter()
+            .map(|id| (*id, glm::identity::<f32, 4>()))
+            .collect::<Vec<_>>();
+
+        while let Some((id, parent_world)) = stack.pop() {
+            let node = self.node_mut(id);
+            node.world = parent_world * node.transform.matrix();
+
+            let world = node.world;
+            stack.extend(node.children.iter().map(|child| (*child, world)));
+        }
+    }
+
+    // Lista achatada dos nós visíveis com malha, com as matrizes do último `update`
+    pub fn draw_list(&self) -> Vec<DrawItem> {
+        let mut items = vec![];
+        self.visit_visible(|id, node| {
+            if let Some(mesh) = node.mesh {
+                items.push(DrawItem {
+                    mesh,
+                    material: node.material.unwrap_or_default(),
+                    world: node.world,
+                    animator: node.animator,
+                    entity: Some(EntityId::Node(id)),
+                });
+            }
+        });
+        items
+    }
+
+    pub fn lights(&self) -> Vec<SceneLight> {
+        let mut lights = vec![];
+        self.visit_visible(|_, node| {
+            if let Some(light) = node.light {
+                let position = node.world.column(3).xyz();
+                let direction = (node.world * glm::vec4(0.0, 0.0, -1.0, 0.0)).xyz();
+                lights.push(SceneLight {
+                    light,
+                    position,
+                    direction: direction.normalize(),
+                });
+            }
+        });
+        lights
+    }
+
+    pub fn decals(&self) -> Vec<SceneDecal> {
+        let mut decals = vec![];
+        self.visit_visible(|_, node| {
+            if let Some(decal) = node.decal {
+                decals.push(SceneDecal {
+                    decal,
+                    world: node.world,
+                });
+            }
+        });
+        decals
+    }
+
+    // O nó visível com malha mais perto da origem do raio, com as matrizes do último
+    // `update`. Não lê nada da GPU: malhas com skinning são testadas na pose de repouso
+    pub fn raycast(&self, ray: &Ray, meshes: &MeshRenderer, test: RayTest) -> Option<Hit> {
+        let mut closest: Option<(NodeId, f32)> = None;
+        self.visit_visible(|id, node| {
+            let (mesh, inverse) = match (node.mesh, node.world.try_inverse()) {
+                (Some(mesh), Some(inverse)) => (mesh, inverse),
+                _ => return,
+            };
+            let bounds = match meshes.bounds(mesh) {
+                Some(bounds) => bounds,
+                None => return,
+            };
+
+            // No espaço do nó a caixa continua alinhada aos eixos
+            let local = ray.transform(&inverse);
+            let nearest = closest.map_or(f32::INFINITY, |(_, distance)| distance);
+            let distance = match local.intersect_aabb(bounds) {
+                Some(distance) if distance < nearest => distance,
+                _ => return,
+            };
+
+            let distance = match (test, meshes.mesh_data(mesh)) {
+                (RayTest::Triangles, Some(data)) => data
+                    .indices
+                    .chunks_exact(3)
+                    .filter_map(|t| {
+                        let [a, b, c] = [0, 1, 2].map(|i| data.vertices[t[i] as usize].position);
+                        local.intersect_triangle(&a, &b, &c)
+                    })
+                    .fold(None, |min: Option<f32>, d| {
+                        Some(min.map_or(d, |m| m.min(d)))
+                    }),
+                _ => Some(distance),
+            };
+
+            if let Some(distance) = distance.filter(|d| *d < nearest) {
+                closest = Some((id, distance));
+            }
+        });
+
+        closest.map(|(node, distance)| Hit {
+            node,
+            distance,
+            position: ray.at(distance),
+        })
+    }
+
+    // A câmera do nó, colocada onde o nó está no mundo. A escala é descartada
+    pub fn camera(&self, id: NodeId) -> Option<Camera> {
+        let node = self.get(id)?;
+        let mut camera = node.camera?;
+
+        let world = &node.world;
+        let rotation = glm::mat4_to_mat3(world);
+        let x = rotation.column(0).normalize();
+        let y = rotation.column(1).normalize();
+        let z = rotation.column(2).normalize();
+
+        camera.position = world.column(3).xyz();
+        camera.rotation = glm::mat3_to_quat(&glm::Mat3::from_columns(&[x, y, z]));
+        Some(camera)
+    }
+
+    fn visit_visible(&self, mut f: impl FnMut(NodeId, &Node)) {
+        let mut stack = self.roots.clone();
+        while let Some(id) = stack.pop() {
+            let node = self.node(id);
+            if node.visible {
+                f(id, node);
+                stack.extend_from_slice(&node.children);
+            }
+        }
+    }
+}
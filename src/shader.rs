@@ -0,0 +1,484 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use log::*;
+
+use crate::error::RendererError;
+
+// Onde ficam as shaders do repositório, pra compilar em tempo de execução
+pub const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/resources/shaders");
+
+lazy_static! {
+    // SPIR-V já compilado, por arquivo e chave da permutação. O cache de pipelines junta
+    // o resto: permutações que dão o mesmo SPIR-V viram a mesma pipeline
+    static ref COMPILED: Mutex<HashMap<(PathBuf, String), Vec<u8>>> = Mutex::new(HashMap::new());
+}
+
+// SPIR-V das shaders do repositório, compilado pelo build.rs. A tabela `EMBEDDED` tem
+// o nome lógico (o nome do arquivo, `mesh.frag`) e os bytes de cada uma
+include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+
+// SPIR-V embutido, procurado pelo nome lógico. Pra quem só sabe o nome em tempo de
+// execução; no código, `spirv!` já falha na compilação se o nome estiver errado
+pub fn embedded(name: &str) -> Option<&'static [u8]> {
+    EMBEDDED
+        .iter()
+        .find(|(embedded, _)| *embedded == name)
+        .map(|(_, spirv)| *spirv)
+}
+
+// Os bytes de uma shader do repositório: `spirv!("mesh.frag")`
+macro_rules! spirv {
+    ($name:literal) => {
+        include_bytes!(concat!(env!("OUT_DIR"), "/shaders/", $name, ".spv"))
+    };
+}
+pub(crate) use spirv;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Compute,
+    TessellationControl,
+    TessellationEvaluation,
+    Geometry,
+}
+
+impl ShaderStage {
+    // Pela extensão, como o glslc faz. Em HLSL e WGSL o estágio vem antes da extensão da
+    // linguagem (`sky.frag.hlsl`)
+    pub fn from_path(path: &Path) -> Option<Self> {
+        if ShaderLanguage::from_path(path) != ShaderLanguage::Glsl {
+            return Self::from_path(Path::new(path.file_stem()?));
+        }
+
+        Some(match path.extension()?.to_str()? {
+            "vert" => ShaderStage::Vertex,
+            "frag" => ShaderStage::Fragment,
+            "comp" => ShaderStage::Compute,
+            "tesc" => ShaderStage::TessellationControl,
+            "tese" => ShaderStage::TessellationEvaluation,
+            "geom" => ShaderStage::Geometry,
+            _ => return None,
+        })
+    }
+}
+
+// Os #define de uma variante da shader. Ficam ordenados, pra mesma combinação dar sempre
+// a mesma chave
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Permutation {
+    defines: BTreeMap<String, String>,
+}
+
+impl Permutation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define<V: ToString>(mut self, name: &str, value: V) -> Self {
+        self.defines.insert(name.into(), value.to_string());
+        self
+    }
+
+    // `#define NAME 1` se ligado, nada se desligado (pra `#ifdef`)
+    pub fn flag(self, name: &str, enabled: bool) -> Self {
+        if enabled {
+            self.define(name, 1)
+        } else {
+            self
+        }
+    }
+
+    // "NAME=valor,..." na ordem dos nomes; vazia sem defines
+    pub fn key(&self) -> String {
+        self.defines
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+// Resolve os `#include "arquivo"` (relativos ao arquivo que inclui, depois às pastas de
+// `include_dir`) e coloca os defines da permutação logo depois do `#version`. Cada
+// arquivo entra uma vez só, e `#line` mantém as linhas dos erros certas
+#[derive(Clone, Debug)]
+pub struct Preprocessor {
+    include_dirs: Vec<PathBuf>,
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self {
+            include_dirs: vec![PathBuf::from(SHADER_DIR)],
+        }
+    }
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn include_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    pub fn process(&self, path: &Path, permutation: &Permutation) -> Result<String> {
+        let source = read(path)?;
+        let mut lines = source.lines();
+
+        let version = lines
+            .next()
+            .filter(|l| l.trim_start().starts_with("#version"))
+            .ok_or_else(|| anyhow!("{} does not start with #version.", path.display()))?;
+
+        let mut output = String::new();
+        writeln!(output, "{}", version)?;
+        writeln!(
+            output,
+            "#extension GL_GOOGLE_cpp_style_line_directive : require"
+        )?;
+        for (name, value) in &permutation.defines {
+            writeln!(output, "#define {} {}", name, value)?;
+        }
+
+        let mut included = HashSet::new();
+        included.insert(canonical(path)?);
+        self.expand(path, &source, 1, &mut included, &mut output)?;
+
+        Ok(output)
+    }
+
+    fn expand(
+        &self,
+        path: &Path,
+        source: &str,
+        skip: usize,
+        included: &mut HashSet<PathBuf>,
+        output: &mut String,
+    ) -> Result<()> {
+        writeln!(output, "#line {} \"{}\"", skip + 1, path.display())?;
+
+        for (index, line) in source.lines().enumerate().skip(skip) {
+            let trimmed = line.trim();
+
+            // A extensão só serve pro glslc resolver sozinho; aqui vira uma linha vazia
+            if trimmed.starts_with("#extension GL_GOOGLE_include_directive") {
+                output.push('\n');
+                continue;
+            }
+
+            let name = match trimmed.strip_prefix("#include") {
+                Some(rest) => rest
+                    .trim()
+                    .trim_matches(|c| c == '"' || c == '<' || c == '>'),
+                None => {
+                    output.push_str(line);
+                    output.push('\n');
+                    continue;
+                }
+            };
+
+            let include = self.resolve(path, name)?;
+            if included.insert(canonical(&include)?) {
+                let source = read(&include)?;
+                self.expand(&include, &source, 0, included, output)?;
+                writeln!(output, "#line {} \"{}\"", index + 2, path.display())?;
+            } else {
+                output.push('\n');
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve(&self, from: &Path, name: &str) -> Result<PathBuf> {
+        from.parent()
+            .into_iter()
+            .chain(self.include_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                anyhow!(RendererError::ShaderCompile(format!(
+                    "{}: include \"{}\" not found",
+                    from.display(),
+                    name
+                )))
+            })
+    }
+}
+
+fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))
+}
+
+fn canonical(path: &Path) -> Result<PathBuf> {
+    Ok(fs::canonicalize(path)?)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderLanguage {
+    Glsl,
+    Hlsl,
+    Wgsl,
+}
+
+impl ShaderLanguage {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("hlsl") => ShaderLanguage::Hlsl,
+            Some("wgsl") => ShaderLanguage::Wgsl,
+            _ => ShaderLanguage::Glsl,
+        }
+    }
+}
+
+// Função de entrada (HLSL e WGSL) e shader model (só HLSL). A entrada é renomeada pra
+// `main` no SPIR-V, que é o nome que as pipelines usam
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CompileOptions {
+    pub entry_point: String,
+    // "6_0" vira ps_6_0, vs_6_0...
+    pub shader_model: String,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            entry_point: "main".into(),
+            shader_model: "6_0".into(),
+        }
+    }
+}
+
+// SPIR-V da variante `permutation` de `path`, compilado uma vez só. GLSL passa pelo
+// `Preprocessor` e o shaderc; HLSL vai direto pro DXC, que resolve os includes; WGSL é
+// traduzido pelo naga, sem permutações (WGSL não tem pré-processador)
+pub fn load(path: &Path, permutation: &Permutation) -> Result<Vec<u8>> {
+    load_with(path, permutation, &CompileOptions::default())
+}
+
+pub fn load_with(
+    path: &Path,
+    permutation: &Permutation,
+    options: &CompileOptions,
+) -> Result<Vec<u8>> {
+    let language = ShaderLanguage::from_path(path);
+    let mut variant = permutation.key();
+    if language != ShaderLanguage::Glsl {
+        variant = format!(
+            "{};{}@{}",
+            variant, options.entry_point, options.shader_model
+        );
+    }
+    let key = (path.to_path_buf(), variant);
+    if let Some(spirv) = COMPILED.lock().unwrap().get(&key) {
+        return Ok(spirv.clone());
+    }
+
+    let stage = ShaderStage::from_path(path).ok_or_else(|| {
+        anyhow!(RendererError::ShaderCompile(format!(
+            "{}: unknown shader stage",
+            path.display()
+        )))
+    })?;
+    let spirv = match language {
+        ShaderLanguage::Glsl => {
+            let source = Preprocessor::new().process(path, permutation)?;
+            compile(&source, &path.display().to_string(), stage)?
+        }
+        ShaderLanguage::Hlsl => compile_hlsl(path, stage, permutation, options)?,
+        ShaderLanguage::Wgsl => compile_wgsl(&read(path)?, stage, &options.entry_point)?,
+    };
+    debug!(
+        "Compiled {} [{}] ({} bytes).",
+        path.display(),
+        key.1,
+        spirv.len()
+    );
+
+    COMPILED.lock().unwrap().insert(key, spirv.clone());
+    Ok(spirv)
+}
+
+// Esquece o que foi compilado de `path`, pra próxima `load` ler o arquivo de novo
+pub fn invalidate(path: &Path) {
+    COMPILED.lock().unwrap().retain(|(p, _), _| p != path);
+}
+
+// GLSL já pré-processado pra SPIR-V, pelo shaderc
+#[cfg(feature = "shader-compiler")]
+pub fn compile(source: &str, name: &str, stage: ShaderStage) -> Result<Vec<u8>> {
+    let kind = match stage {
+        ShaderStage::Vertex => shaderc::ShaderKind::Vertex,
+        ShaderStage::Fragment => shaderc::ShaderKind::Fragment,
+        ShaderStage::Compute => shaderc::ShaderKind::Compute,
+        ShaderStage::TessellationControl => shaderc::ShaderKind::TessControl,
+        ShaderStage::TessellationEvaluation => shaderc::ShaderKind::TessEvaluation,
+        ShaderStage::Geometry => shaderc::ShaderKind::Geometry,
+    };
+
+    let compiler = shaderc::Compiler::new()
+        .ok_or_else(|| RendererError::ShaderCompile("shaderc is not available".into()))?;
+    let mut options = shaderc::CompileOptions::new()
+        .ok_or_else(|| RendererError::ShaderCompile("shaderc is not available".into()))?;
+    options.set_target_env(
+        shaderc::TargetEnv::Vulkan,
+        shaderc::EnvVersion::Vulkan1_0 as u32,
+    );
+
+    let artifact = compiler
+        .compile_into_spirv(source, kind, name, "main", Some(&options))
+        .map_err(|e| RendererError::ShaderCompile(e.to_string()))?;
+    if artifact.get_num_warnings() > 0 {
+        warn!("{}: {}", name, artifact.get_warning_messages());
+    }
+
+    Ok(artifact.as_binary_u8().to_vec())
+}
+
+#[cfg(not(feature = "shader-compiler"))]
+pub fn compile(source: &str, name: &str, stage: ShaderStage) -> Result<Vec<u8>> {
+    Err(anyhow!(RendererError::ShaderCompile(format!(
+        "{}: built without the shader-compiler feature",
+        name
+    ))))
+}
+
+// HLSL pra SPIR-V pelo DXC (hassle-rs). Os includes são procurados na pasta do arquivo e
+// em SHADER_DIR
+#[cfg(feature = "hlsl")]
+pub fn compile_hlsl(
+    path: &Path,
+    stage: ShaderStage,
+    permutation: &Permutation,
+    options: &CompileOptions,
+) -> Result<Vec<u8>> {
+    let profile = match stage {
+        ShaderStage::Vertex => "vs",
+        ShaderStage::Fragment => "ps",
+        ShaderStage::Compute => "cs",
+        ShaderStage::TessellationControl => "hs",
+        ShaderStage::TessellationEvaluation => "ds",
+        ShaderStage::Geometry => "gs",
+    };
+    let profile = format!("{}_{}", profile, options.shader_model);
+
+    let mut args = vec![
+        "-spirv".to_string(),
+        "-fspv-target-env=vulkan1.0".to_string(),
+        "-fspv-entrypoint-name=main".to_string(),
+        format!("-I{}", SHADER_DIR),
+    ];
+    if let Some(dir) = path.parent() {
+        args.push(format!("-I{}", dir.display()));
+    }
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let defines = permutation
+        .defines
+        .iter()
+        .map(|(name, value)| (name.as_str(), Some(value.as_str())))
+        .collect::<Vec<_>>();
+
+    let source = read(path)?;
+    hassle_rs::compile_hlsl(
+        &path.display().to_string(),
+        &source,
+        &options.entry_point,
+        &profile,
+        &args,
+        &defines,
+    )
+    .map_err(|e| anyhow!(RendererError::ShaderCompile(e.to_string())))
+}
+
+#[cfg(not(feature = "hlsl"))]
+pub fn compile_hlsl(
+    path: &Path,
+    stage: ShaderStage,
+    permutation: &Permutation,
+    options: &CompileOptions,
+) -> Result<Vec<u8>> {
+    Err(anyhow!(RendererError::ShaderCompile(format!(
+        "{}: built without the hlsl feature",
+        path.display()
+    ))))
+}
+
+// WGSL pra SPIR-V pelo naga. Fica só a entrada do estágio (a chamada `entry_point`, se
+// tiver mais de uma), já com o nome `main`. Erros de sintaxe e de validação saem como
+// RendererError::ShaderValidation, com a mensagem do naga apontando a linha
+#[cfg(feature = "wgsl")]
+pub fn compile_wgsl(source: &str, stage: ShaderStage, entry_point: &str) -> Result<Vec<u8>> {
+    use naga::back::spv;
+    use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+    let naga_stage = match stage {
+        ShaderStage::Vertex => naga::ShaderStage::Vertex,
+        ShaderStage::Fragment => naga::ShaderStage::Fragment,
+        ShaderStage::Compute => naga::ShaderStage::Compute,
+        _ => {
+            return Err(anyhow!(RendererError::ShaderCompile(format!(
+                "WGSL has no {:?} stage",
+                stage
+            ))))
+        }
+    };
+
+    let mut module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| RendererError::ShaderValidation(e.emit_to_string(source)))?;
+
+    let candidates = module
+        .entry_points
+        .iter()
+        .filter(|e| e.stage == naga_stage)
+        .map(|e| e.name.clone())
+        .collect::<Vec<_>>();
+    let name = match candidates.iter().find(|n| *n == entry_point) {
+        Some(name) => name.clone(),
+        None if candidates.len() == 1 => candidates[0].clone(),
+        None => {
+            return Err(anyhow!(RendererError::ShaderCompile(format!(
+                "no {:?} entry point \"{}\" (found {:?})",
+                stage, entry_point, candidates
+            ))))
+        }
+    };
+    module
+        .entry_points
+        .retain(|e| e.stage == naga_stage && e.name == name);
+    module.entry_points[0].name = "main".into();
+
+    let info = Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|e| RendererError::ShaderValidation(e.emit_to_string(source)))?;
+
+    let options = spv::Options {
+        lang_version: (1, 0),
+        ..Default::default()
+    };
+    let pipeline_options = spv::PipelineOptions {
+        shader_stage: naga_stage,
+        entry_point: "main".into(),
+    };
+    let words = spv::write_vec(&module, &info, &options, Some(&pipeline_options))
+        .map_err(|e| RendererError::ShaderCompile(e.to_string()))?;
+
+    Ok(words.iter().flat_map(|w| w.to_ne_bytes()).collect())
+}
+
+#[cfg(not(feature = "wgsl"))]
+pub fn compile_wgsl(source: &str, stage: ShaderStage, entry_point: &str) -> Result<Vec<u8>> {
+    Err(anyhow!(RendererError::ShaderCompile(
+        "built without the wgsl feature".into()
+    )))
+}
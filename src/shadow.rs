@@ -0,0 +1,825 @@
+use std::{mem::size_of, slice};
+
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator,
+    app::AppData,
+    camera::{Camera, Projection},
+    image::{self, ImageState, TrackedImage},
+    leaks,
+    mesh::{MeshRenderer, Vertex},
+    pipeline::{Pipeline, PipelineDesc},
+    sampler::{self, SamplerDesc},
+    scene::{DrawItem, Light, SceneLight},
+    shader::spirv,
+    uniforms::LightUniforms,
+    upload::UploadQueue,
+};
+
+// Têm que bater com o lights.glsl
+pub const MAX_CASCADES: usize = 4;
+// Luzes pontuais com sombra além dessas ficam sem
+pub const MAX_POINT_SHADOWS: usize = 4;
+
+// Plano próximo das faces do cubo das luzes pontuais
+const POINT_SHADOW_NEAR: f32 = 0.05;
+
+// Pra onde cada face do cubo olha e qual é o "pra cima" dela, na ordem das camadas
+// (+X, -X, +Y, -Y, +Z, -Z) e com a orientação que a amostragem de cubemaps espera
+pub const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+// Configuração das sombras da luz direcional
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    // Lado de cada cascata em texels
+    pub resolution: u32,
+    // Em quantas fatias o volume da câmera é dividido (1 a MAX_CASCADES)
+    pub cascades: u32,
+    // Bias aplicado pelo rasterizador na passada de sombra: um valor fixo (em unidades
+    // mínimas de profundidade) e outro proporcional à inclinação do triângulo
+    pub depth_bias_constant: f32,
+    pub depth_bias_slope: f32,
+    // 0 usa só o filtro 2x2 do hardware; n faz a média de (2n + 1)² amostras
+    pub pcf_radius: u32,
+    // Até onde, a partir da câmera, as coisas recebem sombra
+    pub distance: f32,
+    // Como as fatias são cortadas: 0 em partes iguais, 1 logarítmico (mais resolução
+    // perto da câmera)
+    pub split_lambda: f32,
+    // Fração do fim de cada cascata que é misturada com a próxima
+    pub cascade_blend: f32,
+    // Lado de cada face dos cubos das luzes pontuais
+    pub point_resolution: u32,
+    // Descontado da distância na comparação das sombras pontuais, em fração do alcance
+    // da luz (o bias do rasterizador não vale quando a shader escreve a profundidade)
+    pub point_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            resolution: 2048,
+            cascades: 4,
+            depth_bias_constant: 1.25,
+            depth_bias_slope: 1.75,
+            pcf_radius: 1,
+            distance: 50.0,
+            split_lambda: 0.75,
+            cascade_blend: 0.1,
+            point_resolution: 512,
+            point_bias: 0.005,
+        }
+    }
+}
+
+// Matrizes de cada cascata de um frame
+#[derive(Copy, Clone, Debug)]
+pub struct Cascades {
+    pub count: usize,
+    pub view_projections: [glm::Mat4; MAX_CASCADES],
+    // Distância ao longo do olhar da câmera em que cada cascata termina
+    pub splits: [f32; MAX_CASCADES],
+}
+
+// Cubo de profundidade de uma luz pontual. Guarda a distância até a luz dividida pelo
+// alcance, não a profundidade da projeção, então dá pra comparar sem saber a face
+#[derive(Debug)]
+struct ShadowCube {
+    image: TrackedImage,
+    memory: vk::DeviceMemory,
+    // O cubo inteiro, lido pelas shaders
+    view: vk::ImageView,
+    // Uma view e um framebuffer por face
+    face_views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+// Shadow maps em cascata da luz direcional: o volume da câmera é fatiado em profundidade
+// e cada fatia ganha uma camada de uma imagem de profundidade, renderizada do ponto de
+// vista da luz antes do pass principal. As shaders iluminadas leem o array inteiro com
+// comparação (sampler2DArrayShadow). Também cuida dos cubos das luzes pontuais que
+// projetam sombra. Não depende da swapchain, então vive junto do dispositivo
+#[derive(Debug)]
+pub struct ShadowMap {
+    settings: ShadowSettings,
+    pass: vk::RenderPass,
+    format: vk::Format,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    // O array inteiro, lido pelas shaders
+    view: vk::ImageView,
+    // Uma view e um framebuffer por cascata
+    layer_views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+    pub sampler: vk::Sampler,
+    pipeline: Pipeline,
+    cubes: Vec<ShadowCube>,
+    point_pipeline: Pipeline,
+    // Pinta cada cascata de uma cor
+    pub show_cascades: bool,
+}
+
+impl ShadowMap {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        settings: ShadowSettings,
+    ) -> Result<Self> {
+        let mut shadows = Self {
+            settings: ShadowSettings {
+                cascades: settings.cascades.clamp(1, MAX_CASCADES as u32),
+                ..settings
+            },
+            pass: vk::RenderPass::null(),
+            format: vk::Format::UNDEFINED,
+            image: vk::Image::null(),
+            memory: vk::DeviceMemory::null(),
+            view: vk::ImageView::null(),
+            layer_views: vec![],
+            framebuffers: vec![],
+            sampler: vk::Sampler::null(),
+            pipeline: Pipeline::default(),
+            cubes: vec![],
+            point_pipeline: Pipeline::default(),
+            show_cascades: false,
+        };
+
+        shadows.create_device_objects(instance, device, data)?;
+        Ok(shadows)
+    }
+
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        self.format = get_shadow_format(instance, data)?;
+        self.pass = Self::create_render_pass(device, self.format)?;
+        self.create_target(instance, device, data)?;
+        self.create_cubes(instance, device, data)?;
+
+        // Fora do mapa conta como iluminado (borda branca = profundidade máxima)
+        self.sampler = sampler::get(
+            device,
+            &SamplerDesc {
+                address_mode: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+                border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+                compare_op: Some(vk::CompareOp::LESS_OR_EQUAL),
+                ..Default::default()
+            },
+        )?;
+
+        let vertex_shader = spirv!("shadow.vert");
+        let fragment_shader = spirv!("shadow.frag");
+
+        let bindings = Vertex::binding_descriptions();
+        // Só a posição interessa
+        let attributes = &Vertex::attribute_descriptions()[..1];
+
+        // Matriz da luz já multiplicada pela de mundo
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(size_of::<glm::Mat4>() as u32)
+            .build()];
+
+        let mut desc = PipelineDesc::new(&vertex_shader[..], &fragment_shader[..], self.pass);
+        desc.bindings = &bindings;
+        desc.attributes = attributes;
+        desc.push_constants = push_constants;
+        // A projeção da luz não inverte o y, então as faces da frente (anti-horárias)
+        // aparecem horárias no shadow map, que é o padrão do PipelineDesc
+        desc.dynamic_depth_bias = true;
+        desc.color_attachments = 0;
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        let vertex_shader = spirv!("point_shadow.vert");
+        let fragment_shader = spirv!("point_shadow.frag");
+
+        // Vista da face vezes a matriz de mundo na vertex; plano próximo e alcance nas duas
+        let push_constants = &[
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size(size_of::<glm::Mat4>() as u32)
+                .build(),
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                .offset(size_of::<glm::Mat4>() as u32)
+                .size(size_of::<glm::Vec4>() as u32)
+                .build(),
+        ];
+
+        let mut desc = PipelineDesc::new(&vertex_shader[..], &fragment_shader[..], self.pass);
+        desc.bindings = &bindings;
+        desc.attributes = attributes;
+        desc.push_constants = push_constants;
+        desc.color_attachments = 0;
+        self.point_pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    unsafe fn create_cubes(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        let size = self.settings.point_resolution.max(1);
+
+        for _ in 0..MAX_POINT_SHADOWS {
+            let (image, memory) = image::create_image_layers(
+                instance,
+                device,
+                data,
+                size,
+                size,
+                6,
+                vk::ImageCreateFlags::CUBE_COMPATIBLE,
+                self.format,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?;
+
+            let view = image::create_image_view_layers(
+                device,
+                image,
+                self.format,
+                vk::ImageAspectFlags::DEPTH,
+                vk::ImageViewType::CUBE,
+                0,
+                6,
+            )?;
+
+            let mut face_views = vec![];
+            let mut framebuffers = vec![];
+            for face in 0..6 {
+                let face_view = image::create_image_view_layers(
+                    device,
+                    image,
+                    self.format,
+                    vk::ImageAspectFlags::DEPTH,
+                    vk::ImageViewType::_2D,
+                    face,
+                    1,
+                )?;
+                face_views.push(face_view);
+
+                let attachments = &[face_view];
+                let info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(self.pass)
+                    .attachments(attachments)
+                    .width(size)
+                    .height(size)
+                    .layers(1);
+                framebuffers.push(device.create_framebuffer(&info, None)?);
+            }
+
+            self.cubes.push(ShadowCube {
+                image: TrackedImage::new(image, vk::ImageAspectFlags::DEPTH),
+                memory,
+                view,
+                face_views,
+                framebuffers,
+            });
+        }
+
+        Ok(())
+    }
+
+    unsafe fn create_render_pass(device: &Device, format: vk::Format) -> Result<vk::RenderPass> {
+        let depth_attachment = vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL);
+
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .depth_stencil_attachment(&depth_attachment_ref);
+
+        // O frame anterior ainda pode estar lendo o mapa no pass principal, e o pass
+        // principal desse frame só pode ler depois que ele foi escrito. Cada cascata é
+        // uma instância do pass
+        let dependencies = &[
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .src_access_mask(vk::AccessFlags::SHADER_READ)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .build(),
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build(),
+        ];
+
+        let attachments = &[depth_attachment];
+        let subpasses = &[subpass];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(attachments)
+            .subpasses(subpasses)
+            .dependencies(dependencies);
+
+        Ok(device.create_render_pass(&info, None)?)
+    }
+
+    unsafe fn create_target(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        let size = self.settings.resolution.max(1);
+        let layers = self.settings.cascades;
+
+        let (image, memory) = image::create_image_layers(
+            instance,
+            device,
+            data,
+            size,
+            size,
+            layers,
+            vk::ImageCreateFlags::empty(),
+            self.format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.image = image;
+        self.memory = memory;
+
+        self.view = image::create_image_view_layers(
+            device,
+            image,
+            self.format,
+            vk::ImageAspectFlags::DEPTH,
+            vk::ImageViewType::_2D_ARRAY,
+            0,
+            layers,
+        )?;
+
+        for layer in 0..layers {
+            let view = image::create_image_view_layers(
+                device,
+                image,
+                self.format,
+                vk::ImageAspectFlags::DEPTH,
+                vk::ImageViewType::_2D,
+                layer,
+                1,
+            )?;
+            self.layer_views.push(view);
+
+            let attachments = &[view];
+            let info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.pass)
+                .attachments(attachments)
+                .width(size)
+                .height(size)
+                .layers(1);
+            self.framebuffers
+                .push(device.create_framebuffer(&info, None)?);
+        }
+
+        Ok(())
+    }
+
+    // Troca as resoluções ou o número de cascatas. A GPU tem que estar parada, e o
+    // descriptor do frame precisa ser reescrito com as views novas
+    pub unsafe fn resize(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        settings: &ShadowSettings,
+    ) -> Result<()> {
+        self.destroy_target(device);
+        self.destroy_cubes(device);
+        self.settings.resolution = settings.resolution;
+        self.settings.cascades = settings.cascades.clamp(1, MAX_CASCADES as u32);
+        self.settings.point_resolution = settings.point_resolution;
+        self.create_target(instance, device, data)?;
+        self.create_cubes(instance, device, data)
+    }
+
+    pub fn settings(&self) -> ShadowSettings {
+        self.settings
+    }
+
+    // Se trocar pra essas configurações exige recriar a imagem (`resize`)
+    pub fn requires_resize(&self, settings: &ShadowSettings) -> bool {
+        settings.resolution != self.settings.resolution
+            || settings.cascades.clamp(1, MAX_CASCADES as u32) != self.settings.cascades
+            || settings.point_resolution != self.settings.point_resolution
+    }
+
+    // Tudo menos as resoluções e as cascatas, que passam pelo `resize`
+    pub fn set_settings(&mut self, settings: ShadowSettings) {
+        self.settings = ShadowSettings {
+            resolution: self.settings.resolution,
+            cascades: self.settings.cascades,
+            point_resolution: self.settings.point_resolution,
+            ..settings
+        };
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    // As cascatas, uma camada cada
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    // Um cubo por luz pontual com sombra, na ordem dos índices do LightUniforms
+    pub fn point_views(&self) -> Vec<vk::ImageView> {
+        self.cubes.iter().map(|c| c.view).collect()
+    }
+
+    // Fatia o volume da câmera e calcula a matriz da primeira luz direcional pra cada
+    // fatia. None se a cena não tem luz direcional ou as sombras estão desligadas
+    pub fn cascades(&self, lights: &[SceneLight], camera: &Camera) -> Option<Cascades> {
+        if !self.settings.enabled {
+            return None;
+        }
+
+        let direction = lights.iter().find_map(|light| match light.light {
+            Light::Directional { .. } => Some(light.direction),
+            _ => None,
+        })?;
+        if glm::length2(&direction) == 0.0 {
+            return None;
+        }
+        let direction = direction.normalize();
+
+        let (near, far) = match camera.projection {
+            Projection::Perspective { near, far, .. } => (near, far.unwrap_or(f32::MAX)),
+            Projection::Orthographic { near, far, .. } => (near, far),
+        };
+        let far = far.min(self.settings.distance);
+        if far <= near {
+            return None;
+        }
+
+        // Mistura da divisão logarítmica com a uniforme ("practical split scheme")
+        let count = self.settings.cascades as usize;
+        let lambda = self.settings.split_lambda.clamp(0.0, 1.0);
+        let mut splits = [far; MAX_CASCADES];
+        for (i, split) in splits.iter_mut().enumerate().take(count) {
+            let t = (i + 1) as f32 / count as f32;
+            let log = near * (far / near).powf(t);
+            let uniform = near + (far - near) * t;
+            *split = lambda * log + (1.0 - lambda) * uniform;
+        }
+
+        let mut view_projections = [glm::identity(); MAX_CASCADES];
+        let mut start = near;
+        let mut previous = near;
+        for i in 0..count {
+            let end = splits[i];
+            view_projections[i] = self.cascade_matrix(camera, direction, start, end);
+
+            // A próxima começa antes, cobrindo a faixa em que as duas são misturadas
+            start = end - self.settings.cascade_blend.clamp(0.0, 1.0) * (end - previous);
+            previous = end;
+        }
+
+        Some(Cascades {
+            count,
+            view_projections,
+            splits,
+        })
+    }
+
+    // Ortográfica em volta da esfera que envolve a fatia [near, far] da câmera. Com a
+    // esfera, o tamanho não muda quando a câmera gira, e a sombra não "respira"
+    fn cascade_matrix(
+        &self,
+        camera: &Camera,
+        direction: glm::Vec3,
+        near: f32,
+        far: f32,
+    ) -> glm::Mat4 {
+        let half_extent = |depth: f32| -> glm::Vec2 {
+            let half_height = match camera.projection {
+                Projection::Perspective { fov_y, .. } => depth * (fov_y / 2.0).tan(),
+                Projection::Orthographic { height, .. } => height / 2.0,
+            };
+            glm::vec2(half_height * camera.aspect(), half_height)
+        };
+
+        let forward = camera.forward();
+        let right = camera.right();
+        let up = camera.up();
+
+        let mut corners = vec![];
+        for depth in [near, far] {
+            let half = half_extent(depth);
+            let middle = camera.position + forward * depth;
+            for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+                corners.push(middle + right * half.x * x + up * half.y * y);
+            }
+        }
+
+        let center = corners.iter().fold(glm::Vec3::zeros(), |sum, c| sum + c) / 8.0;
+        let radius = corners
+            .iter()
+            .map(|c| glm::distance(c, &center))
+            .fold(0.0f32, f32::max);
+        // Arredondado pra cima, senão o erro de float muda o tamanho de um frame pro outro
+        let radius = ((radius * 16.0).ceil() / 16.0).max(0.01);
+
+        let light_up = if direction.y.abs() > 0.99 {
+            glm::vec3(0.0, 0.0, 1.0)
+        } else {
+            glm::vec3(0.0, 1.0, 0.0)
+        };
+
+        // A caixa vai bem pra trás na direção da luz, pra pegar quem projeta sombra de
+        // fora da fatia
+        let margin = self.settings.distance;
+        let eye = center - direction * (radius + margin);
+        let view = glm::look_at_rh(&eye, &center, &light_up);
+        let projection =
+            glm::ortho_rh_zo(-radius, radius, -radius, radius, 0.0, 2.0 * radius + margin);
+
+        // Anda o centro de texel em texel, senão as bordas das sombras tremem quando a
+        // câmera se move
+        let view_projection = projection * view;
+        let texel = 2.0 / self.settings.resolution.max(1) as f32;
+        let origin = view_projection * glm::vec4(center.x, center.y, center.z, 1.0);
+        let snapped = glm::vec2(
+            (origin.x / texel).round() * texel,
+            (origin.y / texel).round() * texel,
+        );
+        let offset = glm::translation(&glm::vec3(snapped.x - origin.x, snapped.y - origin.y, 0.0));
+
+        offset * view_projection
+    }
+
+    // Limpa cada cascata e, se tiver matrizes, desenha as malhas nelas. O pass roda
+    // mesmo sem sombra pra deixar a imagem no layout que o descriptor do frame espera.
+    // Depois desenha os cubos das luzes pontuais que têm um índice de sombra em `lights`
+    pub unsafe fn record(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        meshes: &MeshRenderer,
+        uploads: &UploadQueue,
+        cascades: Option<&Cascades>,
+        lights: &LightUniforms,
+        items: &[DrawItem],
+    ) -> Result<u32> {
+        let size = self.settings.resolution.max(1);
+        let extent = vk::Extent2D {
+            width: size,
+            height: size,
+        };
+
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(extent);
+        let clear_values = &[vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        }];
+
+        let mut draw_calls = 0;
+        for (layer, framebuffer) in self.framebuffers.iter().enumerate() {
+            let info = vk::RenderPassBeginInfo::builder()
+                .render_pass(self.pass)
+                .framebuffer(*framebuffer)
+                .render_area(render_area)
+                .clear_values(clear_values);
+
+            device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+            if let Some(cascades) = cascades.filter(|c| layer < c.count) {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline.pipeline,
+                );
+                Pipeline::set_viewport(device, command_buffer, extent);
+                device.cmd_set_depth_bias(
+                    command_buffer,
+                    self.settings.depth_bias_constant,
+                    0.0,
+                    self.settings.depth_bias_slope,
+                );
+
+                draw_calls += meshes.record_depth(
+                    device,
+                    command_buffer,
+                    uploads,
+                    self.pipeline.layout,
+                    &cascades.view_projections[layer],
+                    items,
+                );
+            }
+
+            device.cmd_end_render_pass(command_buffer);
+        }
+
+        draw_calls += self.record_cubes(device, command_buffer, meshes, uploads, lights, items);
+
+        Ok(draw_calls)
+    }
+
+    unsafe fn record_cubes(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        meshes: &MeshRenderer,
+        uploads: &UploadQueue,
+        lights: &LightUniforms,
+        items: &[DrawItem],
+    ) -> u32 {
+        let size = self.settings.point_resolution.max(1);
+        let extent = vk::Extent2D {
+            width: size,
+            height: size,
+        };
+
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(extent);
+        let clear_values = &[vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        }];
+
+        let mut casters = [None; MAX_POINT_SHADOWS];
+        for light in &lights.point_lights[..lights.point_count as usize] {
+            if light.shadow.x >= 0.0 {
+                casters[light.shadow.x as usize] = Some(light.position);
+            }
+        }
+
+        let mut draw_calls = 0;
+        for (cube, caster) in self.cubes.iter_mut().zip(casters) {
+            // Cubo sem luz esse frame: continua com o que tinha, só precisa estar no layout
+            // que o descriptor espera
+            if caster.is_none() {
+                cube.image.transition_to(
+                    device,
+                    command_buffer,
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::AccessFlags::SHADER_READ,
+                );
+                continue;
+            }
+
+            for (face, framebuffer) in cube.framebuffers.iter().enumerate() {
+                let info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(self.pass)
+                    .framebuffer(*framebuffer)
+                    .render_area(render_area)
+                    .clear_values(clear_values);
+
+                device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+                if let Some(position) = caster {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.point_pipeline.pipeline,
+                    );
+                    Pipeline::set_viewport(device, command_buffer, extent);
+
+                    let planes = glm::vec4(POINT_SHADOW_NEAR, position.w, 0.0, 0.0);
+                    device.cmd_push_constants(
+                        command_buffer,
+                        self.point_pipeline.layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        size_of::<glm::Mat4>() as u32,
+                        slice::from_raw_parts(planes.as_ptr() as *const u8, size_of::<glm::Vec4>()),
+                    );
+
+                    let (direction, up) = CUBE_FACES[face];
+                    let eye = position.xyz();
+                    let view = glm::look_at_rh(
+                        &eye,
+                        &(eye + glm::Vec3::from(direction)),
+                        &glm::Vec3::from(up),
+                    );
+
+                    draw_calls += meshes.record_depth(
+                        device,
+                        command_buffer,
+                        uploads,
+                        self.point_pipeline.layout,
+                        &view,
+                        items,
+                    );
+                }
+
+                device.cmd_end_render_pass(command_buffer);
+            }
+
+            // O render pass deixa no finalLayout
+            cube.image.assume(ImageState {
+                layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                stages: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            });
+        }
+
+        draw_calls
+    }
+
+    unsafe fn destroy_target(&mut self, device: &Device) {
+        self.framebuffers
+            .drain(..)
+            .for_each(|f| device.destroy_framebuffer(f, None));
+        self.layer_views
+            .drain(..)
+            .for_each(|v| leaks::destroy_image_view(device, v));
+        leaks::destroy_image_view(device, self.view);
+        leaks::destroy_image(device, self.image);
+        allocator::free(device, self.memory);
+    }
+
+    unsafe fn destroy_cubes(&mut self, device: &Device) {
+        for cube in self.cubes.drain(..) {
+            cube.framebuffers
+                .iter()
+                .for_each(|f| device.destroy_framebuffer(*f, None));
+            cube.face_views
+                .iter()
+                .for_each(|v| leaks::destroy_image_view(device, *v));
+            leaks::destroy_image_view(device, cube.view);
+            leaks::destroy_image(device, cube.image.image);
+            allocator::free(device, cube.memory);
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+        self.point_pipeline.destroy(device);
+        self.destroy_target(device);
+        self.destroy_cubes(device);
+        device.destroy_render_pass(self.pass, None);
+    }
+}
+
+// Precisa servir de attachment e ser lido com filtro linear (o PCF do hardware)
+unsafe fn get_shadow_format(instance: &Instance, data: &AppData) -> Result<vk::Format> {
+    let candidates = &[vk::Format::D32_SFLOAT, vk::Format::D16_UNORM];
+    let required = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+        | vk::FormatFeatureFlags::SAMPLED_IMAGE
+        | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+
+    candidates
+        .iter()
+        .cloned()
+        .find(|f| {
+            let properties =
+                instance.get_physical_device_format_properties(data.physical_device, *f);
+            properties.optimal_tiling_features.contains(required)
+        })
+        .ok_or_else(|| anyhow!("Failed to find supported shadow map format."))
+}
@@ -0,0 +1,363 @@
+use std::f32::consts::PI;
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    pass::SCENE_TRANSFER,
+    pipeline::{Pipeline, PipelineDesc},
+    shader::spirv,
+    uniforms::SkyUniform,
+};
+
+// Fração da cor do horizonte refletida pelo chão, que é o que aparece abaixo dele
+const GROUND_ALBEDO: f32 = 0.25;
+// Amostras da esfera na projeção nos harmônicos (em θ; o dobro em φ)
+const IRRADIANCE_SAMPLES: usize = 32;
+
+// Céu analítico do Preetham, calculado a partir da direção do sol. Aparece atrás da cena
+// e substitui a luz ambiente constante pela irradiância dele
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SkySettings {
+    pub enabled: bool,
+    // Pra onde o sol está, no mundo (o contrário da direção da luz direcional)
+    pub sun_direction: glm::Vec3,
+    // Quanto de névoa tem no ar: 2 é um dia limpo, 10 já é bem embaçado
+    pub turbidity: f32,
+    // Multiplica a luminância do modelo, que é em kcd/m²
+    pub intensity: f32,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sun_direction: glm::normalize(&glm::vec3(0.3, 0.6, 0.4)),
+            turbidity: 2.5,
+            intensity: 0.1,
+        }
+    }
+}
+
+impl SkySettings {
+    // Elevação e azimute do sol em graus, pra interface
+    pub fn sun_angles(&self) -> (f32, f32) {
+        let d = glm::normalize(&self.sun_direction);
+        (d.y.asin().to_degrees(), d.z.atan2(d.x).to_degrees())
+    }
+
+    pub fn set_sun_angles(&mut self, elevation: f32, azimuth: f32) {
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        self.sun_direction = glm::vec3(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        );
+    }
+}
+
+// Distribuição de Perez ajustada pro sol e a turbidez, com tudo em Yxy
+#[derive(Copy, Clone, Debug)]
+struct Preetham {
+    // A..E, cada um com (Y, x, y)
+    perez: [glm::Vec3; 5],
+    // Zênite dividido por F(0, θsol), então F(θ, γ) já sai na escala certa
+    zenith: glm::Vec3,
+    sun: glm::Vec3,
+}
+
+impl Preetham {
+    fn new(settings: &SkySettings) -> Self {
+        let t = settings.turbidity;
+        let sun = glm::normalize(&settings.sun_direction);
+        // O modelo não vale com o sol abaixo do horizonte
+        let theta = sun.y.clamp(0.01, 1.0).acos();
+
+        let perez = [
+            glm::vec3(
+                0.1787 * t - 1.4630,
+                -0.0193 * t - 0.2592,
+                -0.0167 * t - 0.2608,
+            ),
+            glm::vec3(
+                -0.3554 * t + 0.4275,
+                -0.0665 * t + 0.0008,
+                -0.0950 * t + 0.0092,
+            ),
+            glm::vec3(
+                -0.0227 * t + 5.3251,
+                -0.0004 * t + 0.2125,
+                -0.0079 * t + 0.2102,
+            ),
+            glm::vec3(
+                0.1206 * t - 2.5771,
+                -0.0641 * t - 0.8989,
+                -0.0441 * t - 1.6537,
+            ),
+            glm::vec3(
+                -0.0670 * t + 0.3703,
+                -0.0033 * t + 0.0452,
+                -0.0109 * t + 0.0529,
+            ),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let (t2, th, th2, th3) = (t * t, theta, theta * theta, theta * theta * theta);
+        let x = t2 * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let y = t2 * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        let mut model = Self {
+            perez,
+            zenith: glm::vec3(luminance, x, y),
+            sun,
+        };
+        let at_zenith = model.distribution(1.0, theta);
+        model.zenith = model.zenith.component_div(&at_zenith);
+        model
+    }
+
+    // F(θ, γ): θ a partir do zênite, γ até o sol
+    fn distribution(&self, cos_theta: f32, gamma: f32) -> glm::Vec3 {
+        let [a, b, c, d, e] = self.perez;
+        let cos_gamma = gamma.cos();
+        let perez = |i: usize| {
+            (1.0 + a[i] * (b[i] / cos_theta.max(0.01)).exp())
+                * (1.0 + c[i] * (d[i] * gamma).exp() + e[i] * cos_gamma * cos_gamma)
+        };
+
+        glm::vec3(perez(0), perez(1), perez(2))
+    }
+
+    // Radiância em RGB linear (sem o disco do sol). Abaixo do horizonte, o chão
+    fn radiance(&self, direction: &glm::Vec3, intensity: f32) -> glm::Vec3 {
+        if direction.y < 0.0 {
+            let horizon = glm::normalize(&glm::vec3(direction.x, 0.0, direction.z));
+            return self.radiance(&horizon, intensity) * GROUND_ALBEDO;
+        }
+
+        let gamma = glm::dot(direction, &self.sun).clamp(-1.0, 1.0).acos();
+        let yxy = self
+            .zenith
+            .component_mul(&self.distribution(direction.y, gamma));
+
+        // Yxy -> XYZ -> sRGB linear
+        let (big_y, x, y) = (yxy.x, yxy.y, yxy.z.max(1e-4));
+        let xyz = glm::vec3(x / y * big_y, big_y, (1.0 - x - y) / y * big_y);
+        let rgb = glm::mat3(
+            3.2406, -1.5372, -0.4986, //
+            -0.9689, 1.8758, 0.0415, //
+            0.0557, -0.2040, 1.0570,
+        ) * xyz;
+
+        glm::max(&rgb, 0.0) * intensity * sunset_fade(self.sun.y)
+    }
+
+    // Projeta o céu nos 9 harmônicos esféricos e convolui com o cosseno, já dividindo por
+    // π: a shader só avalia na normal e multiplica pelo albedo
+    fn irradiance(&self, intensity: f32) -> [glm::Vec4; 9] {
+        let mut coefficients = [glm::vec3(0.0, 0.0, 0.0); 9];
+        let (rows, columns) = (IRRADIANCE_SAMPLES, IRRADIANCE_SAMPLES * 2);
+        let (d_theta, d_phi) = (PI / rows as f32, 2.0 * PI / columns as f32);
+
+        for row in 0..rows {
+            let theta = (row as f32 + 0.5) * d_theta;
+            let solid_angle = theta.sin() * d_theta * d_phi;
+            for column in 0..columns {
+                let phi = (column as f32 + 0.5) * d_phi;
+                let direction = glm::vec3(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let radiance = self.radiance(&direction, intensity) * solid_angle;
+                for (c, basis) in coefficients.iter_mut().zip(sh_basis(&direction)) {
+                    *c += radiance * basis;
+                }
+            }
+        }
+
+        // Convolução com o cosseno por banda (π, 2π/3, π/4), dividida por π
+        let bands = [
+            1.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+        ];
+        let mut irradiance = [glm::vec4(0.0, 0.0, 0.0, 0.0); 9];
+        for ((out, c), band) in irradiance.iter_mut().zip(coefficients).zip(bands) {
+            *out = glm::vec4(c.x * band, c.y * band, c.z * band, 0.0);
+        }
+        irradiance
+    }
+}
+
+// Mesma ordem do ambientLight no lights.glsl
+fn sh_basis(n: &glm::Vec3) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * n.y,
+        0.488603 * n.z,
+        0.488603 * n.x,
+        1.092548 * n.x * n.y,
+        1.092548 * n.y * n.z,
+        0.315392 * (3.0 * n.z * n.z - 1.0),
+        1.092548 * n.x * n.z,
+        0.546274 * (n.x * n.x - n.y * n.y),
+    ]
+}
+
+// O céu escurece com o sol chegando no horizonte e some quando ele passa
+fn sunset_fade(elevation: f32) -> f32 {
+    let t = ((elevation + 0.1) / 0.15).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Desenha o céu no começo do subpass da cena, cobrindo a tela toda; o resto vai por cima.
+// Os parâmetros vão nas luzes do frame, junto com a irradiância usada como ambiente
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Sky {
+    pub settings: SkySettings,
+    // Projetar nos harmônicos custa umas milhares de avaliações do modelo, então só
+    // refaz quando as configurações mudam
+    cached: Option<(SkySettings, SkyUniform)>,
+    pipeline: Pipeline,
+}
+
+impl Sky {
+    pub unsafe fn create(device: &Device, data: &AppData, settings: SkySettings) -> Result<Self> {
+        let mut sky = Self {
+            settings,
+            ..Default::default()
+        };
+
+        sky.create_pipeline(device, data)?;
+        Ok(sky)
+    }
+
+    // Criada mesmo desligado, pra ligar sem recriar nada
+    pub unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        let vertex_shader = spirv!("fullscreen.vert");
+        let fragment_shader = spirv!("sky.frag");
+
+        // Função de transferência e 1 / tamanho do framebuffer
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(16)
+            .build()];
+        let set_layouts = &[data.frame_descriptors.layout];
+
+        let mut desc = PipelineDesc::new(
+            &vertex_shader[..],
+            &fragment_shader[..],
+            data.render_pass.pass,
+        );
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        // Fica atrás de tudo por ser desenhado primeiro
+        desc.depth_test = false;
+        desc.depth_write = false;
+        desc.subpass = data.render_pass.forward_subpass();
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    // Parâmetros pro LightUniforms. Zerado (e o ambiente volta a ser a constante) quando
+    // desligado
+    pub fn uniform(&mut self) -> SkyUniform {
+        if !self.settings.enabled {
+            return SkyUniform::default();
+        }
+
+        match self.cached {
+            Some((settings, uniform)) if settings == self.settings => return uniform,
+            _ => {}
+        }
+
+        let model = Preetham::new(&self.settings);
+        let mut uniform = SkyUniform {
+            zenith: glm::vec4(
+                model.zenith.x,
+                model.zenith.y,
+                model.zenith.z,
+                self.settings.intensity * sunset_fade(model.sun.y),
+            ),
+            sun: glm::vec4(model.sun.x, model.sun.y, model.sun.z, 1.0),
+            irradiance: model.irradiance(self.settings.intensity),
+            ..Default::default()
+        };
+        for (p, c) in uniform.perez.iter_mut().zip(model.perez) {
+            *p = glm::vec4(c.x, c.y, c.z, 0.0);
+        }
+
+        self.cached = Some((self.settings, uniform));
+        uniform
+    }
+
+    // Primeira coisa do subpass da cena. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+    ) -> u32 {
+        if !self.settings.enabled {
+            return 0;
+        }
+
+        let extent = data.swapchain.extent;
+        let constants = [
+            (SCENE_TRANSFER as u32).to_ne_bytes(),
+            0u32.to_ne_bytes(),
+            (1.0 / extent.width as f32).to_ne_bytes(),
+            (1.0 / extent.height as f32).to_ne_bytes(),
+        ]
+        .concat();
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            0,
+            &[data.frame_descriptors.set(slot)],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &constants,
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+        1
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+    }
+}
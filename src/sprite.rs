@@ -0,0 +1,488 @@
+use std::{mem::size_of, path::Path, slice};
+
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    buffer::DynamicBuffer,
+    pipeline::{BlendMode, Pipeline, PipelineDesc},
+    shader::spirv,
+    texture::{self, Texture},
+    upload::{UploadId, UploadQueue},
+};
+
+// Quantas texturas de sprite podem existir ao mesmo tempo (um descriptor set cada)
+const MAX_TEXTURES: u32 = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SpriteTextureId(usize);
+
+// Pedaço de uma textura, em coordenadas normalizadas
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtlasRegion {
+    pub uv_min: glm::Vec2,
+    pub uv_max: glm::Vec2,
+}
+
+impl AtlasRegion {
+    pub fn full() -> Self {
+        Self {
+            uv_min: glm::vec2(0.0, 0.0),
+            uv_max: glm::vec2(1.0, 1.0),
+        }
+    }
+
+    // Retângulo em pixels de uma textura de `width` x `height`
+    pub fn from_pixels(x: u32, y: u32, w: u32, h: u32, width: u32, height: u32) -> Self {
+        let size = glm::vec2(width as f32, height as f32);
+        Self {
+            uv_min: glm::vec2(x as f32, y as f32).component_div(&size),
+            uv_max: glm::vec2((x + w) as f32, (y + h) as f32).component_div(&size),
+        }
+    }
+
+    // Célula `index` de uma folha de sprites com `columns` x `rows` quadros iguais, lida
+    // da esquerda pra direita e de cima pra baixo
+    pub fn grid(columns: u32, rows: u32, index: u32) -> Self {
+        let cell = glm::vec2(1.0 / columns as f32, 1.0 / rows as f32);
+        let position = glm::vec2((index % columns) as f32, (index / columns % rows) as f32);
+        let uv_min = position.component_mul(&cell);
+
+        Self {
+            uv_min,
+            uv_max: uv_min + cell,
+        }
+    }
+}
+
+// Um sprite desenhado nesse frame. Posição e tamanho em unidades do mundo 2D (pixels com
+// zoom 1), y pra baixo
+#[derive(Copy, Clone, Debug)]
+pub struct Sprite {
+    pub texture: SpriteTextureId,
+    pub position: glm::Vec2,
+    pub size: glm::Vec2,
+    // Ponto de giro e de ancoragem, de (0, 0) no canto superior esquerdo a (1, 1)
+    pub origin: glm::Vec2,
+    // Em radianos, sentido horário na tela
+    pub rotation: f32,
+    pub region: AtlasRegion,
+    // Multiplicada pela textura, linear
+    pub color: glm::Vec4,
+    // Camadas maiores ficam por cima
+    pub layer: i32,
+}
+
+impl Sprite {
+    pub fn new(texture: SpriteTextureId, position: glm::Vec2, size: glm::Vec2) -> Self {
+        Self {
+            texture,
+            position,
+            size,
+            origin: glm::vec2(0.5, 0.5),
+            rotation: 0.0,
+            region: AtlasRegion::full(),
+            color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            layer: 0,
+        }
+    }
+}
+
+// Câmera ortográfica do mundo 2D
+#[derive(Copy, Clone, Debug)]
+pub struct Camera2D {
+    // Ponto do mundo no centro da tela
+    pub position: glm::Vec2,
+    pub zoom: f32,
+}
+
+impl Default for Camera2D {
+    fn default() -> Self {
+        Self {
+            position: glm::vec2(0.0, 0.0),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Camera2D {
+    // Projeção pra uma tela de `width` x `height` pixels
+    pub fn projection(&self, width: f32, height: f32) -> glm::Mat4 {
+        let half = glm::vec2(width, height) / (2.0 * self.zoom);
+        // O y do mundo cresce pra baixo, igual ao NDC do Vulkan
+        glm::ortho_rh_zo(
+            self.position.x - half.x,
+            self.position.x + half.x,
+            self.position.y - half.y,
+            self.position.y + half.y,
+            -1.0,
+            1.0,
+        )
+    }
+}
+
+// Dados de cada sprite na GPU. A vertex shader monta o quad a partir do gl_VertexIndex
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct SpriteInstance {
+    position: [f32; 2],
+    size: [f32; 2],
+    origin: [f32; 2],
+    rotation: f32,
+    _padding: f32,
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    color: [f32; 4],
+}
+
+#[derive(Debug)]
+struct SpriteTexture {
+    texture: Texture,
+    set: vk::DescriptorSet,
+    upload: UploadId,
+    // Mantido na CPU pra reenviar se o dispositivo for recriado
+    pixels: Vec<u8>,
+}
+
+// Agrupa os sprites do frame por camada e textura, e desenha cada grupo com um draw
+// instanciado. Milhares de sprites com poucas texturas viram poucos draw calls
+#[derive(Debug)]
+pub struct SpriteBatch {
+    pub camera: Camera2D,
+    textures: Vec<SpriteTexture>,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    pipeline: Pipeline,
+    instances: DynamicBuffer,
+    // Sprites pedidos desde o último `record`
+    queued: Vec<Sprite>,
+}
+
+impl SpriteBatch {
+    pub unsafe fn create(device: &Device, data: &AppData) -> Result<Self> {
+        let mut batch = Self {
+            camera: Camera2D::default(),
+            textures: vec![],
+            set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            pipeline: Pipeline::default(),
+            instances: DynamicBuffer::new(vk::BufferUsageFlags::VERTEX_BUFFER),
+            queued: vec![],
+        };
+
+        batch.create_descriptor_objects(device)?;
+        batch.create_pipeline(device, data)?;
+        Ok(batch)
+    }
+
+    unsafe fn create_descriptor_objects(&mut self, device: &Device) -> Result<()> {
+        self.set_layout = texture::create_set_layout(device)?;
+
+        let pool_sizes = &[vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(MAX_TEXTURES)
+            .build()];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(MAX_TEXTURES);
+        self.descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+        Ok(())
+    }
+
+    // Recria os descritores e reenvia as texturas que já existiam, depois de perder o
+    // dispositivo. Os ids continuam valendo
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+    ) -> Result<()> {
+        self.create_descriptor_objects(device)?;
+
+        for index in 0..self.textures.len() {
+            let texture = &self.textures[index];
+            let (width, height) = (texture.texture.width, texture.texture.height);
+            let pixels = std::mem::take(&mut self.textures[index].pixels);
+            self.textures[index] =
+                self.create_texture(instance, device, data, uploads, width, height, pixels)?;
+        }
+
+        Ok(())
+    }
+
+    // Depende do render pass, então é recriada junto com a swapchain
+    pub unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        let vertex_shader = spirv!("sprite.vert");
+        let fragment_shader = spirv!("sprite.frag");
+
+        let bindings = &[vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<SpriteInstance>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()];
+
+        // (location, formato, offset) de cada campo da instância
+        let fields = [
+            (0, vk::Format::R32G32_SFLOAT, 0),
+            (1, vk::Format::R32G32_SFLOAT, 8),
+            (2, vk::Format::R32G32_SFLOAT, 16),
+            (3, vk::Format::R32_SFLOAT, 24),
+            (4, vk::Format::R32G32_SFLOAT, 32),
+            (5, vk::Format::R32G32_SFLOAT, 40),
+            (6, vk::Format::R32G32B32A32_SFLOAT, 48),
+        ];
+        let attributes = fields
+            .iter()
+            .map(|(location, format, offset)| {
+                vk::VertexInputAttributeDescription::builder()
+                    .binding(0)
+                    .location(*location)
+                    .format(*format)
+                    .offset(*offset)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        // Projeção (já com a pré-rotação) na vertex, função de transferência na fragment
+        let push_constants = &[
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size(size_of::<glm::Mat4>() as u32)
+                .build(),
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(size_of::<glm::Mat4>() as u32)
+                .size(size_of::<u32>() as u32)
+                .build(),
+        ];
+
+        let set_layouts = &[self.set_layout];
+        let mut desc = PipelineDesc::new(
+            &vertex_shader[..],
+            &fragment_shader[..],
+            data.post_pass.pass,
+        );
+        desc.bindings = bindings;
+        desc.attributes = &attributes;
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        desc.depth_test = false;
+        desc.depth_write = false;
+        desc.blend = BlendMode::Alpha;
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+    }
+
+    pub unsafe fn load_texture(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        path: &Path,
+    ) -> Result<SpriteTextureId> {
+        let (width, height, pixels) = texture::load_png(path)?;
+        self.add_texture(instance, device, data, uploads, width, height, pixels)
+    }
+
+    // Pixels RGBA8 em sRGB, linha por linha
+    pub unsafe fn add_texture(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    ) -> Result<SpriteTextureId> {
+        if self.textures.len() as u32 == MAX_TEXTURES {
+            return Err(anyhow!("Sprite texture limit ({}) reached.", MAX_TEXTURES));
+        }
+
+        if pixels.len() != (width * height * 4) as usize {
+            return Err(anyhow!(
+                "Expected {}x{} RGBA pixels, got {} bytes.",
+                width,
+                height,
+                pixels.len()
+            ));
+        }
+
+        let texture =
+            self.create_texture(instance, device, data, uploads, width, height, pixels)?;
+        self.textures.push(texture);
+
+        Ok(SpriteTextureId(self.textures.len() - 1))
+    }
+
+    unsafe fn create_texture(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    ) -> Result<SpriteTexture> {
+        let texture = Texture::create(
+            instance,
+            device,
+            data,
+            width,
+            height,
+            vk::Format::R8G8B8A8_SRGB,
+            vk::Filter::NEAREST,
+        )?;
+
+        let set_layouts = &[self.set_layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(set_layouts);
+        let set = device.allocate_descriptor_sets(&info)?[0];
+        texture::write_set(device, set, &texture);
+
+        let upload = uploads.enqueue(texture.upload_target(4), pixels.clone());
+
+        Ok(SpriteTexture {
+            texture,
+            set,
+            upload,
+            pixels,
+        })
+    }
+
+    pub fn texture_size(&self, id: SpriteTextureId) -> Option<(u32, u32)> {
+        self.textures
+            .get(id.0)
+            .map(|t| (t.texture.width, t.texture.height))
+    }
+
+    pub fn draw(&mut self, sprite: Sprite) {
+        self.queued.push(sprite);
+    }
+
+    // Grava os sprites enfileirados dentro do render pass principal. Retorna quantos draw
+    // calls fez
+    pub unsafe fn record(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        uploads: &UploadQueue,
+    ) -> Result<u32> {
+        let mut sprites = std::mem::take(&mut self.queued);
+
+        // Texturas que ainda estão a caminho da GPU ficam de fora desse frame
+        sprites.retain(|s| {
+            self.textures
+                .get(s.texture.0)
+                .is_some_and(|t| !uploads.is_pending(t.upload))
+        });
+        if sprites.is_empty() {
+            return Ok(0);
+        }
+
+        // Estável, pra ordem de chamada decidir entre sprites da mesma camada
+        sprites.sort_by_key(|s| (s.layer, s.texture));
+
+        let instances = sprites
+            .iter()
+            .map(|s| SpriteInstance {
+                position: [s.position.x, s.position.y],
+                size: [s.size.x, s.size.y],
+                origin: [s.origin.x, s.origin.y],
+                rotation: s.rotation,
+                _padding: 0.0,
+                uv_min: [s.region.uv_min.x, s.region.uv_min.y],
+                uv_max: [s.region.uv_max.x, s.region.uv_max.y],
+                color: [s.color.x, s.color.y, s.color.z, s.color.w],
+            })
+            .collect::<Vec<_>>();
+
+        let bytes = slice::from_raw_parts(
+            instances.as_ptr() as *const u8,
+            instances.len() * size_of::<SpriteInstance>(),
+        );
+        let buffer = self.instances.write(instance, device, data, slot, bytes)?;
+
+        let extent = data.swapchain.logical_extent();
+        let projection = data.swapchain.pre_rotation()
+            * self
+                .camera
+                .projection(extent.width as f32, extent.height as f32);
+        let projection_bytes =
+            slice::from_raw_parts(projection.as_ptr() as *const u8, size_of::<glm::Mat4>());
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            projection_bytes,
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            size_of::<glm::Mat4>() as u32,
+            &(data.swapchain.transfer as u32).to_ne_bytes(),
+        );
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[0]);
+
+        // Um draw por sequência de sprites com a mesma textura
+        let mut draw_calls = 0;
+        let mut first = 0;
+        while first < sprites.len() {
+            let texture = sprites[first].texture;
+            let count = sprites[first..]
+                .iter()
+                .take_while(|s| s.texture == texture)
+                .count();
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &[self.textures[texture.0].set],
+                &[],
+            );
+            device.cmd_draw(command_buffer, 6, count as u32, 0, first as u32);
+
+            draw_calls += 1;
+            first += count;
+        }
+
+        Ok(draw_calls)
+    }
+
+    // Os pixels das texturas continuam na CPU, pra `create_device_objects` reenviar
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.instances.destroy(device);
+        self.textures.iter().for_each(|t| t.texture.destroy(device));
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use crate::pacing::PacingStats;
+
+// Números do frame que o app (e o HUD) pode consultar
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameStats {
+    // Tempo de CPU entre o começo de um frame e o do próximo
+    pub frame_time: Duration,
+    // Do começo do frame até a imagem realmente ir pra tela (VK_KHR_present_wait).
+    // None quando o dispositivo não suporta a medição
+    pub present_latency: Option<Duration>,
+    // Intervalo entre presents e quanto ele varia (ver pacing.rs)
+    pub pacing: PacingStats,
+    // Draw calls gravados no último frame
+    pub draw_calls: u32,
+    // Malhas da lista do frame que o frustum culling tirou, e as que foram pra cena
+    pub culled: u32,
+    pub submitted: u32,
+}
+
+impl FrameStats {
+    // Média móvel exponencial, pra leitura não ficar pulando de frame em frame
+    pub fn smooth(previous: Duration, sample: Duration) -> Duration {
+        if previous.is_zero() {
+            sample
+        } else {
+            previous.mul_f64(0.9) + sample.mul_f64(0.1)
+        }
+    }
+}
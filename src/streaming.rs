@@ -0,0 +1,291 @@
+use anyhow::Result;
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator,
+    app::AppData,
+    buffer::create_buffer,
+    leaks,
+    material::{MaterialTextureId, Materials},
+    upload::TransferContext,
+};
+
+// Da fila de transferência, que os assets também usam. Abaixo da principal: as cópias
+// podem esperar, o frame não
+pub const QUEUE_PRIORITY: f32 = 0.5;
+
+// Máximo de bytes de mips por lote. Um nível maior que isso ainda vai, sozinho
+const BATCH_BUDGET: vk::DeviceSize = 8 * 1024 * 1024;
+
+// Offsets no staging alinhados a 16: serve pra qualquer tamanho de bloco e pros 4 bytes
+// que filas só de transferência exigem
+const STAGING_ALIGNMENT: vk::DeviceSize = 16;
+
+// Níveis copiados num submit, com o staging deles
+#[derive(Debug)]
+struct Batch {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    // Com a imagem de cada textura, pra ignorar as que foram substituídas no meio
+    prepared: Vec<(MaterialTextureId, vk::Image)>,
+    levels: Vec<(MaterialTextureId, vk::Image, u32)>,
+}
+
+// Sobe os mips grandes das texturas dos materiais aos poucos, na fila de transferência
+// (ou na de gráficos, se não tiver uma). Um lote por vez: o próximo só sai quando o
+// anterior terminou e os materiais já trocaram o sampler pra usar os níveis novos
+#[derive(Debug)]
+pub struct TextureStreamer {
+    transfer: TransferContext,
+    batch: Option<Batch>,
+}
+
+impl TextureStreamer {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        Ok(Self {
+            transfer: TransferContext::create(instance, device, data)?,
+            batch: None,
+        })
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.batch.is_some()
+    }
+
+    // Se o lote em voo terminou, avisa os materiais dos níveis que chegaram
+    pub unsafe fn collect(&mut self, device: &Device, materials: &mut Materials) -> Result<()> {
+        if !self.transfer.poll(device)? {
+            return Ok(());
+        }
+
+        let batch = self.batch.take().unwrap();
+        for (texture, image) in &batch.prepared {
+            materials.mark_prepared(*texture, *image);
+        }
+        for (texture, image, level) in &batch.levels {
+            materials.level_arrived(device, *texture, *image, *level)?;
+        }
+
+        leaks::destroy_buffer(device, batch.buffer);
+        allocator::free(device, batch.memory);
+
+        Ok(())
+    }
+
+    // Manda o próximo lote com o que os materiais pediram nesse frame
+    pub unsafe fn submit(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        materials: &mut Materials,
+    ) -> Result<()> {
+        let work = materials.take_stream_work();
+        if self.is_busy() || (work.prepare.is_empty() && work.levels.is_empty()) {
+            return Ok(());
+        }
+
+        // Pela ordem de prioridade, até encher o orçamento
+        let mut levels = vec![];
+        let mut size = 0;
+        for (texture, level) in work.levels {
+            let bytes = materials.texture_level(texture, level).len() as vk::DeviceSize;
+            if !levels.is_empty() && size + bytes > BATCH_BUDGET {
+                break;
+            }
+            levels.push((texture, level, size));
+            size = align(size + bytes);
+        }
+
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            data,
+            size.max(STAGING_ALIGNMENT),
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        if !levels.is_empty() {
+            let mapped =
+                device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())? as *mut u8;
+            for (texture, level, offset) in &levels {
+                let bytes = materials.texture_level(*texture, *level);
+                std::ptr::copy_nonoverlapping(
+                    bytes.as_ptr(),
+                    mapped.add(*offset as usize),
+                    bytes.len(),
+                );
+            }
+            device.unmap_memory(memory);
+        }
+
+        let command_buffer = self.transfer.begin(device)?;
+
+        // Os níveis que ainda não chegaram ficam em SHADER_READ_ONLY com lixo, pra view
+        // inteira estar no layout que o descriptor diz. O sampler não deixa ler eles
+        for id in &work.prepare {
+            let image = materials.texture(*id).image;
+            let resident = materials.resident_level(*id);
+            barrier(
+                device,
+                command_buffer,
+                image,
+                0,
+                resident,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        }
+
+        // Cada nível: descarta o lixo, copia e volta pra leitura. A troca do sampler só
+        // acontece depois da fence, então o pass da cena nunca lê um nível no meio da cópia
+        for (id, level, offset) in &levels {
+            let texture = materials.texture(*id);
+            barrier(
+                device,
+                command_buffer,
+                texture.image,
+                *level,
+                1,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(*level)
+                .base_array_layer(0)
+                .layer_count(1);
+            let region = vk::BufferImageCopy::builder()
+                .buffer_offset(*offset)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(subresource)
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D {
+                    width: (texture.width >> level).max(1),
+                    height: (texture.height >> level).max(1),
+                    depth: 1,
+                });
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                buffer,
+                texture.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+
+            barrier(
+                device,
+                command_buffer,
+                texture.image,
+                *level,
+                1,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        }
+
+        self.transfer.submit(device)?;
+
+        trace!(
+            "Streaming {} mip levels ({} KB), preparing {} textures.",
+            levels.len(),
+            size >> 10,
+            work.prepare.len()
+        );
+
+        self.batch = Some(Batch {
+            buffer,
+            memory,
+            prepared: work
+                .prepare
+                .iter()
+                .map(|id| (*id, materials.texture(*id).image))
+                .collect(),
+            levels: levels
+                .iter()
+                .map(|(id, level, _)| (*id, materials.texture(*id).image, *level))
+                .collect(),
+        });
+
+        Ok(())
+    }
+
+    // Espera o lote em voo, pra destruir ou recriar o dispositivo
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.transfer.destroy(device);
+        if let Some(batch) = self.batch.take() {
+            leaks::destroy_buffer(device, batch.buffer);
+            allocator::free(device, batch.memory);
+        }
+    }
+}
+
+// Só estágios que uma fila de transferência tem. Quem lê depois (o pass da cena) espera
+// a fence na CPU antes de usar o nível
+unsafe fn barrier(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    base_level: u32,
+    level_count: u32,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) {
+    let (src_access_mask, src_stage_mask) = if old_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL {
+        (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        )
+    } else {
+        (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        )
+    };
+    let (dst_access_mask, dst_stage_mask) = if new_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL {
+        (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        )
+    } else {
+        (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        )
+    };
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(base_level)
+        .level_count(level_count)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource_range)
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage_mask,
+        dst_stage_mask,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+}
+
+fn align(offset: vk::DeviceSize) -> vk::DeviceSize {
+    (offset + STAGING_ALIGNMENT - 1) / STAGING_ALIGNMENT * STAGING_ALIGNMENT
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use nalgebra_glm as glm;
+
+use crate::app::App;
+
+// Prefixo das chaves que mostram/escondem nós da cena pelo nome
+const NODE_PREFIX: &str = "node:";
+
+// Quanto uma escrita pra um seguidor pode demorar antes dele ser desconectado. Quem
+// escreve é uma thread própria, então um seguidor lento nunca segura o frame
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Estado compartilhado numa sessão de revisão: câmera e chaves liga/desliga da cena
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncState {
+    pub camera_position: [f32; 3],
+    pub camera_target: [f32; 3],
+    pub toggles: BTreeMap<String, bool>,
+}
+
+impl SyncState {
+    // Protocolo de texto, uma linha por estado:
+    // `camera px py pz tx ty tz;toggle nome 1;toggle outro 0`. Nos nomes, `%`, `;`,
+    // espaços e caracteres de controle viram `%XX` (ver `escape`)
+    pub fn encode(&self) -> String {
+        let [px, py, pz] = self.camera_position;
+        let [tx, ty, tz] = self.camera_target;
+        let mut line = format!("camera {} {} {} {} {} {}", px, py, pz, tx, ty, tz);
+
+        for (name, value) in &self.toggles {
+            line.push_str(&format!(";toggle {} {}", escape(name), *value as u8));
+        }
+
+        line.push('\n');
+        line
+    }
+
+    pub fn decode(line: &str) -> Result<Self> {
+        let mut state = SyncState::default();
+
+        for part in line.trim().split(';') {
+            let mut words = part.split_whitespace();
+            match words.next() {
+                Some("camera") => {
+                    let values = words
+                        .map(|w| w.parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if values.len() != 6 {
+                        return Err(anyhow!("Malformed camera state '{}'.", part));
+                    }
+
+                    state.camera_position = [values[0], values[1], values[2]];
+                    state.camera_target = [values[3], values[4], values[5]];
+                }
+                Some("toggle") => match (words.next(), words.next()) {
+                    (Some(name), Some(value)) => {
+                        state.toggles.insert(unescape(name)?, value == "1");
+                    }
+                    _ => return Err(anyhow!("Malformed toggle '{}'.", part)),
+                },
+                Some(other) => debug!("Ignoring unknown sync field '{}'.", other),
+                None => {}
+            }
+        }
+
+        Ok(state)
+    }
+
+    // O que o líder transmite: a câmera, algumas opções do renderer e a visibilidade dos
+    // nós com nome
+    pub fn capture(app: &App) -> Self {
+        let camera = app.camera();
+        let target = camera.position + camera.forward();
+
+        let mut toggles = BTreeMap::new();
+        toggles.insert("wireframe".to_string(), app.wireframe());
+        toggles.insert("frustum_culling".to_string(), app.frustum_culling());
+        toggles.insert("sky".to_string(), app.sky().enabled);
+        toggles.insert("shadows".to_string(), app.shadow_settings().enabled);
+        toggles.insert("show_cascades".to_string(), app.show_cascades());
+        for (_, node) in app.scene().iter().filter(|(_, n)| !n.name.is_empty()) {
+            toggles.insert(format!("{}{}", NODE_PREFIX, node.name), node.visible);
+        }
+
+        Self {
+            camera_position: camera.position.into(),
+            camera_target: target.into(),
+            toggles,
+        }
+    }
+
+    // Seguidor: aplica o que veio do líder. Chaves desconhecidas e nós que não existem
+    // aqui são ignorados
+    pub unsafe fn apply(&self, app: &mut App) -> Result<()> {
+        let position = glm::Vec3::from(self.camera_position);
+        let target = glm::Vec3::from(self.camera_target);
+        let camera = app.camera_mut();
+        camera.position = position;
+        if target != position {
+            camera.look_at(target);
+        }
+
+        for (name, value) in &self.toggles {
+            let value = *value;
+            match name.as_str() {
+                "wireframe" if app.wireframe() != value => app.set_wireframe(value),
+                "frustum_culling" => app.set_frustum_culling(value),
+                "sky" => {
+                    let mut sky = app.sky();
+                    sky.enabled = value;
+                    app.set_sky(sky);
+                }
+                "shadows" if app.shadow_settings().enabled != value => {
+                    let mut shadows = app.shadow_settings();
+                    shadows.enabled = value;
+                    app.set_shadow_settings(shadows)?;
+                }
+                "show_cascades" => app.set_show_cascades(value),
+                _ => {
+                    if let Some(node) = name.strip_prefix(NODE_PREFIX) {
+                        let scene = app.scene_mut();
+                        let ids = scene
+                            .iter()
+                            .filter(|(_, n)| n.name == node)
+                            .map(|(id, _)| id)
+                            .collect::<Vec<_>>();
+                        for id in ids {
+                            if let Some(node) = scene.get_mut(id) {
+                                node.visible = value;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// `%XX` pra cada byte de `%`, `;`, espaço ou caractere de controle, que quebrariam a linha
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c == '%' || c == ';' || c.is_whitespace() || c.is_control() {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+fn unescape(name: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail
+                .get(..2)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| anyhow!("Malformed escape in '{}'.", name))?;
+            bytes.push(hex);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+// O que chega na thread que escreve pros seguidores
+pub enum LeaderEvent {
+    Joined(TcpStream),
+    State(SyncState),
+}
+
+// Uma instância lidera (transmite o estado) e as outras seguem
+pub enum SyncSession {
+    Leader {
+        events: Sender<LeaderEvent>,
+        // O último estado mandado, pra não repetir o mesmo todo frame
+        last: Option<SyncState>,
+    },
+    Follower {
+        latest: Arc<Mutex<Option<SyncState>>>,
+    },
+}
+
+impl SyncSession {
+    pub fn lead(address: impl ToSocketAddrs) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let local = listener.local_addr()?;
+        info!("Review session listening on {}.", local);
+        if !local.ip().is_loopback() {
+            warn!(
+                "Review session is reachable from other machines on {}; anyone there can \
+                 follow it.",
+                local
+            );
+        }
+
+        let (events, received) = mpsc::channel();
+        let accepted = events.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept follower: {}", e);
+                        continue;
+                    }
+                };
+
+                info!("Review session follower joined ({:?}).", stream.peer_addr());
+                let _ = stream.set_nodelay(true);
+                if let Err(e) = stream.set_write_timeout(Some(WRITE_TIMEOUT)) {
+                    warn!("Failed to set follower write timeout: {}", e);
+                    continue;
+                }
+                if accepted.send(LeaderEvent::Joined(stream)).is_err() {
+                    break;
+                }
+            }
+        });
+        thread::spawn(move || send_to_followers(received));
+
+        Ok(SyncSession::Leader { events, last: None })
+    }
+
+    pub fn follow(address: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        info!("Joined review session at {:?}.", stream.peer_addr());
+
+        let latest = Arc::new(Mutex::new(None));
+        let received = latest.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        warn!("Review session connection lost: {}", e);
+                        break;
+                    }
+                };
+
+                match SyncState::decode(&line) {
+                    Ok(state) => *received.lock().unwrap() = Some(state),
+                    Err(e) => warn!("Invalid review session message: {}", e),
+                }
+            }
+        });
+
+        Ok(SyncSession::Follower { latest })
+    }
+
+    // Líder: manda o estado pra todos (só se mudou). Só passa pra thread de envio, então
+    // não bloqueia
+    pub fn publish(&mut self, state: &SyncState) {
+        if let SyncSession::Leader { events, last } = self {
+            if last.as_ref() == Some(state) {
+                return;
+            }
+
+            let _ = events.send(LeaderEvent::State(state.clone()));
+            *last = Some(state.clone());
+        }
+    }
+
+    // Seguidor: o estado mais recente recebido desde a última chamada
+    pub fn poll(&mut self) -> Option<SyncState> {
+        match self {
+            SyncSession::Follower { latest } => latest.lock().unwrap().take(),
+            SyncSession::Leader { .. } => None,
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        matches!(self, SyncSession::Leader { .. })
+    }
+}
+
+// Thread de envio do líder: dona das conexões dos seguidores. Estados acumulados viram um
+// só, quem entra recebe o último na hora, e quem não consegue receber em WRITE_TIMEOUT
+// (ou caiu) é desconectado
+fn send_to_followers(events: Receiver<LeaderEvent>) {
+    let mut streams = Vec::new();
+    let mut last: Option<String> = None;
+    let mut joined = Vec::new();
+
+    while let Ok(event) = events.recv() {
+        // Só o estado mais novo é codificado: codificar cada um deixaria a fila crescendo
+        // mais rápido do que esvazia
+        let mut latest = None;
+        for event in std::iter::once(event).chain(events.try_iter()) {
+            match event {
+                LeaderEvent::Joined(stream) => joined.push(stream),
+                LeaderEvent::State(state) => latest = Some(state),
+            }
+        }
+
+        if let Some(state) = latest {
+            let line = state.encode();
+            streams.retain_mut(|stream| send(stream, &line));
+            last = Some(line);
+        }
+        for mut stream in joined.drain(..) {
+            if last.as_ref().is_none_or(|line| send(&mut stream, line)) {
+                streams.push(stream);
+            }
+        }
+    }
+}
+
+fn send(stream: &mut TcpStream, line: &str) -> bool {
+    match stream.write_all(line.as_bytes()) {
+        Ok(()) => true,
+        Err(e) => {
+            info!(
+                "Review session follower dropped ({:?}): {}",
+                stream.peer_addr(),
+                e
+            );
+            false
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::Result;
+use log::info;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    features::Feature,
+    pass::SCENE_TRANSFER,
+    pipeline::{Pipeline, PipelineDesc, TessellationStages},
+    shader::spirv,
+};
+
+// Sem tessellation, cada patch vira uma grade fixa com esse tanto de quadrados por lado
+// (igual ao terrain_grid.vert)
+const GRID_DETAIL: u32 = 8;
+// Limite garantido do maxTessellationGenerationLevel
+const MAX_TESSELLATION_LEVEL: f32 = 64.0;
+
+// Terreno procedural (ruído em oitavas) centrado na origem, no plano XZ. Com tessellation,
+// cada patch é subdividido de acordo com a distância até a câmera
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TerrainSettings {
+    pub enabled: bool,
+    // Lado do terreno, em unidades do mundo
+    pub size: f32,
+    // Patches por lado
+    pub patches: u32,
+    pub height_scale: f32,
+    // Frequência do primeiro oitavo do ruído: quanto maior, menores os morros
+    pub frequency: f32,
+    pub seed: u32,
+    // Subdivisões de cada borda de patch perto da câmera (até `near`) e longe (a partir
+    // de `far`), interpoladas no meio
+    pub max_level: f32,
+    pub min_level: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            size: 512.0,
+            patches: 32,
+            height_scale: 40.0,
+            frequency: 0.01,
+            seed: 0,
+            max_level: 32.0,
+            min_level: 1.0,
+            near: 20.0,
+            far: 300.0,
+        }
+    }
+}
+
+// Desenha o terreno no subpass da cena, depois das malhas. Usa shaders de tessellation se
+// o dispositivo tiver (peça o Feature::TessellationShader em `requirements.optional`),
+// senão uma grade fixa
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Terrain {
+    pub settings: TerrainSettings,
+    tessellated: bool,
+    pipeline: Pipeline,
+    // A mesma com polygon_mode LINE, se o dispositivo tem o fillModeNonSolid
+    wireframe_pipeline: Option<Pipeline>,
+    pub wireframe: bool,
+}
+
+impl Terrain {
+    pub unsafe fn create(
+        device: &Device,
+        data: &AppData,
+        settings: TerrainSettings,
+    ) -> Result<Self> {
+        if settings.enabled && !data.capabilities.has(Feature::TessellationShader) {
+            info!("Tessellation shaders unavailable, terrain uses a fixed grid.");
+        }
+
+        let mut terrain = Self {
+            settings,
+            ..Default::default()
+        };
+
+        terrain.create_pipeline(device, data)?;
+        Ok(terrain)
+    }
+
+    // Criada mesmo desligado, pra ligar sem recriar nada. O dispositivo pode ter mudado
+    // (DEVICE_LOST), então a escolha entre tessellation e grade é refeita aqui
+    pub unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        self.tessellated = data.capabilities.has(Feature::TessellationShader);
+
+        let fragment_shader = spirv!("terrain.frag");
+        let patch_shader = spirv!("terrain.vert");
+        let control_shader = spirv!("terrain.tesc");
+        let evaluation_shader = spirv!("terrain.tese");
+        let grid_shader = spirv!("terrain_grid.vert");
+
+        // Os parâmetros do terrain.glsl, lidos em todos os estágios
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(self.push_constant_stages())
+            .offset(0)
+            .size(40)
+            .build()];
+        let set_layouts = &[data.frame_descriptors.layout];
+
+        let vertex_shader = if self.tessellated {
+            &patch_shader[..]
+        } else {
+            &grid_shader[..]
+        };
+        let mut desc =
+            PipelineDesc::new(vertex_shader, &fragment_shader[..], data.render_pass.pass);
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        // Visto de baixo só quando a câmera entra no chão, mas aí é melhor ver alguma coisa
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        if data.config.reverse_z {
+            desc.depth_compare = vk::CompareOp::GREATER;
+        }
+        desc.subpass = data.render_pass.forward_subpass();
+        if self.tessellated {
+            desc.topology = vk::PrimitiveTopology::PATCH_LIST;
+            desc.tessellation = Some(TessellationStages {
+                control_shader: &control_shader[..],
+                evaluation_shader: &evaluation_shader[..],
+                patch_control_points: 4,
+            });
+        }
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        self.wireframe_pipeline = None;
+        if data.capabilities.has(Feature::FillModeNonSolid) {
+            desc.polygon_mode = vk::PolygonMode::LINE;
+            self.wireframe_pipeline = Some(Pipeline::create(device, &desc)?);
+        }
+
+        Ok(())
+    }
+
+    fn push_constant_stages(&self) -> vk::ShaderStageFlags {
+        let mut stages = vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT;
+        if self.tessellated {
+            stages |= vk::ShaderStageFlags::TESSELLATION_CONTROL
+                | vk::ShaderStageFlags::TESSELLATION_EVALUATION;
+        }
+        stages
+    }
+
+    // Grava dentro do subpass da cena. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+    ) -> u32 {
+        let settings = &self.settings;
+        if !settings.enabled || settings.patches == 0 {
+            return 0;
+        }
+
+        let constants = [
+            settings.size.to_ne_bytes(),
+            settings.patches.to_ne_bytes(),
+            settings.height_scale.to_ne_bytes(),
+            settings.frequency.to_ne_bytes(),
+            settings
+                .max_level
+                .clamp(1.0, MAX_TESSELLATION_LEVEL)
+                .to_ne_bytes(),
+            settings
+                .min_level
+                .clamp(1.0, MAX_TESSELLATION_LEVEL)
+                .to_ne_bytes(),
+            settings.near.to_ne_bytes(),
+            settings.far.to_ne_bytes(),
+            (SCENE_TRANSFER as u32).to_ne_bytes(),
+            settings.seed.to_ne_bytes(),
+        ]
+        .concat();
+
+        // Sem vertex buffer: as shaders tiram a posição do gl_VertexIndex. 4 cantos por
+        // patch, ou 2 triângulos por quadrado da grade
+        let patches = settings.patches * settings.patches;
+        let vertex_count = if self.tessellated {
+            patches * 4
+        } else {
+            patches * GRID_DETAIL * GRID_DETAIL * 6
+        };
+
+        let pipeline = match self.wireframe_pipeline {
+            Some(wireframe) if self.wireframe => wireframe,
+            _ => self.pipeline,
+        };
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            0,
+            &[data.frame_descriptors.set(slot)],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            pipeline.layout,
+            self.push_constant_stages(),
+            0,
+            &constants,
+        );
+        device.cmd_draw(command_buffer, vertex_count, 1, 0, 0);
+
+        1
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+        if let Some(pipeline) = self.wireframe_pipeline.take() {
+            pipeline.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use std::{collections::HashMap, mem::size_of, slice};
+
+use anyhow::{anyhow, Result};
+use fontdue::{Font, FontSettings};
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    buffer::DynamicBuffer,
+    pipeline::{BlendMode, Pipeline, PipelineDesc},
+    shader::spirv,
+    texture::{Texture, TextureSet},
+    upload::{UploadId, UploadQueue},
+};
+
+// Fonte padrão, embutida no executável
+const DEFAULT_FONT: &[u8] = include_bytes!("resources/fonts/DejaVuSansMono.ttf");
+
+// Tamanho em que os glifos são rasterizados. Outros tamanhos escalam o atlas
+const RASTER_SIZE: f32 = 32.0;
+const ATLAS_SIZE: u32 = 512;
+// Espaço vazio entre glifos, pra filtragem linear não puxar o vizinho
+const ATLAS_PADDING: u32 = 1;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct Vertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+// Onde um glifo está no atlas, e como posicionar ele (em pixels de RASTER_SIZE)
+#[derive(Copy, Clone, Debug)]
+struct Glyph {
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    offset: [f32; 2],
+    size: [f32; 2],
+    advance: f32,
+}
+
+// Texto simples pra HUD e debug, sem depender da interface: os glifos ASCII e Latin-1
+// são rasterizados num atlas uma vez, e cada `draw_text` vira quads num buffer só,
+// desenhados dentro do render pass principal
+#[derive(Debug)]
+pub struct TextRenderer {
+    glyphs: HashMap<char, Glyph>,
+    // Mantido na CPU pra reenviar se o dispositivo for recriado
+    pixels: Vec<u8>,
+    ascent: f32,
+    line_height: f32,
+    atlas: Texture,
+    upload: Option<UploadId>,
+    textures: TextureSet,
+    pipeline: Pipeline,
+    vertices: DynamicBuffer,
+    // Quads pedidos desde o último `record`
+    queued: Vec<Vertex>,
+}
+
+impl TextRenderer {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+    ) -> Result<Self> {
+        let font = Font::from_bytes(DEFAULT_FONT, FontSettings::default())
+            .map_err(|e| anyhow!("Failed to load font: {}", e))?;
+        let metrics = font
+            .horizontal_line_metrics(RASTER_SIZE)
+            .ok_or_else(|| anyhow!("Font has no horizontal metrics."))?;
+
+        let (glyphs, pixels) = build_atlas(&font)?;
+
+        let mut text = Self {
+            glyphs,
+            pixels,
+            ascent: metrics.ascent,
+            line_height: metrics.new_line_size,
+            atlas: Texture::default(),
+            upload: None,
+            textures: TextureSet::default(),
+            pipeline: Pipeline::default(),
+            vertices: DynamicBuffer::new(vk::BufferUsageFlags::VERTEX_BUFFER),
+            queued: vec![],
+        };
+
+        text.create_device_objects(instance, device, data, uploads)?;
+        text.create_pipeline(device, data)?;
+        Ok(text)
+    }
+
+    // Atlas e descritor. O conteúdo do atlas vai pela fila de uploads
+    pub unsafe fn create_device_objects(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+    ) -> Result<()> {
+        self.atlas = Texture::create(
+            instance,
+            device,
+            data,
+            ATLAS_SIZE,
+            ATLAS_SIZE,
+            vk::Format::R8_UNORM,
+            vk::Filter::LINEAR,
+        )?;
+        self.upload = Some(uploads.enqueue(self.atlas.upload_target(1), self.pixels.clone()));
+
+        self.textures = TextureSet::create(device)?;
+        self.textures.write(device, &self.atlas);
+
+        Ok(())
+    }
+
+    // Depende do render pass, então é recriada junto com a swapchain
+    pub unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        let vertex_shader = spirv!("text.vert");
+        let fragment_shader = spirv!("text.frag");
+
+        let bindings = &[vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Vertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()];
+
+        let attributes = &[
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(size_of::<[f32; 2]>() as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(2)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(size_of::<[f32; 4]>() as u32)
+                .build(),
+        ];
+
+        // Mesmo layout do overlay
+        let push_constants = &[
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size((size_of::<glm::Mat4>() + size_of::<[f32; 2]>()) as u32)
+                .build(),
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset((size_of::<glm::Mat4>() + size_of::<[f32; 2]>()) as u32)
+                .size(size_of::<u32>() as u32)
+                .build(),
+        ];
+
+        let set_layouts = &[self.textures.layout];
+        let mut desc = PipelineDesc::new(
+            &vertex_shader[..],
+            &fragment_shader[..],
+            data.post_pass.pass,
+        );
+        desc.bindings = bindings;
+        desc.attributes = attributes;
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        desc.depth_test = false;
+        desc.depth_write = false;
+        desc.blend = BlendMode::Alpha;
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+    }
+
+    // Enfileira um texto pro próximo frame. `position` é o canto superior esquerdo da
+    // primeira linha e `size` o tamanho da fonte, ambos em pixels. Cor linear
+    pub fn draw_text(&mut self, position: glm::Vec2, size: f32, color: glm::Vec4, text: &str) {
+        let scale = size / RASTER_SIZE;
+        let color = [color.x, color.y, color.z, color.w];
+
+        let mut x = position.x;
+        let mut baseline = position.y + self.ascent * scale;
+        for c in text.chars() {
+            if c == '\n' {
+                x = position.x;
+                baseline += self.line_height * scale;
+                continue;
+            }
+
+            let glyph = match self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?')) {
+                Some(glyph) => *glyph,
+                None => continue,
+            };
+
+            if glyph.size[0] > 0.0 && glyph.size[1] > 0.0 {
+                let x0 = x + glyph.offset[0] * scale;
+                let y0 = baseline + glyph.offset[1] * scale;
+                let x1 = x0 + glyph.size[0] * scale;
+                let y1 = y0 + glyph.size[1] * scale;
+                let [u0, v0] = glyph.uv_min;
+                let [u1, v1] = glyph.uv_max;
+
+                for (position, uv) in [
+                    ([x0, y0], [u0, v0]),
+                    ([x1, y0], [u1, v0]),
+                    ([x1, y1], [u1, v1]),
+                    ([x0, y0], [u0, v0]),
+                    ([x1, y1], [u1, v1]),
+                    ([x0, y1], [u0, v1]),
+                ] {
+                    self.queued.push(Vertex {
+                        position,
+                        uv,
+                        color,
+                    });
+                }
+            }
+
+            x += glyph.advance * scale;
+        }
+    }
+
+    // Largura e altura em pixels que `draw_text` ocuparia
+    pub fn measure(&self, size: f32, text: &str) -> glm::Vec2 {
+        let scale = size / RASTER_SIZE;
+        let mut width: f32 = 0.0;
+        let mut lines = 0;
+
+        for line in text.split('\n') {
+            let advance = line
+                .chars()
+                .filter_map(|c| self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?')))
+                .map(|g| g.advance)
+                .sum::<f32>();
+
+            width = width.max(advance * scale);
+            lines += 1;
+        }
+
+        glm::vec2(width, lines as f32 * self.line_height * scale)
+    }
+
+    // Grava os textos enfileirados dentro do render pass principal. Retorna quantos draw
+    // calls fez
+    pub unsafe fn record(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        uploads: &UploadQueue,
+    ) -> Result<u32> {
+        let vertices = std::mem::take(&mut self.queued);
+        if vertices.is_empty() {
+            return Ok(0);
+        }
+
+        // O atlas ainda está a caminho da GPU
+        if self.upload.is_none_or(|id| uploads.is_pending(id)) {
+            return Ok(0);
+        }
+
+        let bytes = slice::from_raw_parts(
+            vertices.as_ptr() as *const u8,
+            vertices.len() * size_of::<Vertex>(),
+        );
+        let buffer = self.vertices.write(instance, device, data, slot, bytes)?;
+
+        let extent = data.swapchain.logical_extent();
+        let screen = [extent.width as f32, extent.height as f32];
+        let transform = data.swapchain.pre_rotation();
+        let mut constants = [0u8; size_of::<glm::Mat4>() + size_of::<[f32; 2]>()];
+        constants[..64].copy_from_slice(slice::from_raw_parts(transform.as_ptr() as *const u8, 64));
+        constants[64..].copy_from_slice(slice::from_raw_parts(screen.as_ptr() as *const u8, 8));
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            0,
+            &[self.textures.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            &constants,
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            constants.len() as u32,
+            &(data.swapchain.transfer as u32).to_ne_bytes(),
+        );
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[0]);
+        device.cmd_draw(command_buffer, vertices.len() as u32, 1, 0, 0);
+
+        Ok(1)
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.vertices.destroy(device);
+        self.textures.destroy(device);
+        self.atlas.destroy(device);
+        self.upload = None;
+    }
+}
+
+// Empacota os glifos em prateleiras (linhas de altura fixa) num atlas R8
+fn build_atlas(font: &Font) -> Result<(HashMap<char, Glyph>, Vec<u8>)> {
+    let mut glyphs = HashMap::new();
+    let mut pixels = vec![0u8; (ATLAS_SIZE * ATLAS_SIZE) as usize];
+
+    let mut x = ATLAS_PADDING;
+    let mut y = ATLAS_PADDING;
+    let mut shelf_height = 0;
+
+    let characters = (' '..='~').chain('\u{a1}'..='\u{ff}');
+    for c in characters {
+        let (metrics, bitmap) = font.rasterize(c, RASTER_SIZE);
+        let (width, height) = (metrics.width as u32, metrics.height as u32);
+
+        if x + width + ATLAS_PADDING > ATLAS_SIZE {
+            x = ATLAS_PADDING;
+            y += shelf_height + ATLAS_PADDING;
+            shelf_height = 0;
+        }
+
+        if y + height + ATLAS_PADDING > ATLAS_SIZE {
+            return Err(anyhow!("Glyph atlas is full."));
+        }
+
+        for row in 0..height {
+            let src = (row * width) as usize;
+            let dst = ((y + row) * ATLAS_SIZE + x) as usize;
+            pixels[dst..dst + width as usize].copy_from_slice(&bitmap[src..src + width as usize]);
+        }
+
+        // O fontdue mede o ymin da base pra cima; na tela o y cresce pra baixo
+        glyphs.insert(
+            c,
+            Glyph {
+                uv_min: [x as f32 / ATLAS_SIZE as f32, y as f32 / ATLAS_SIZE as f32],
+                uv_max: [
+                    (x + width) as f32 / ATLAS_SIZE as f32,
+                    (y + height) as f32 / ATLAS_SIZE as f32,
+                ],
+                offset: [metrics.xmin as f32, -(metrics.ymin as f32 + height as f32)],
+                size: [width as f32, height as f32],
+                advance: metrics.advance_width,
+            },
+        );
+
+        x += width + ATLAS_PADDING;
+        shelf_height = shelf_height.max(height);
+    }
+
+    Ok((glyphs, pixels))
+}
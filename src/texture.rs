@@ -0,0 +1,501 @@
+use std::{collections::HashSet, fs::File, path::Path};
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator,
+    app::AppData,
+    dds,
+    image::{create_image_mips_shared, create_image_view_mips},
+    ktx, leaks,
+    sampler::{self, SamplerDesc},
+    upload::{UploadId, UploadQueue, UploadTarget},
+};
+
+// Imagem na CPU pronta pra enviar: um vetor de bytes por nível de mip, do maior pro menor,
+// já no formato da textura (que pode ser comprimido)
+#[derive(Clone, Debug, Default)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    pub format: vk::Format,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    // Pixels RGBA8, linha por linha, sem mips
+    pub fn rgba8(width: u32, height: u32, pixels: Vec<u8>, srgb: bool) -> Self {
+        Self {
+            width,
+            height,
+            format: if srgb {
+                vk::Format::R8G8B8A8_SRGB
+            } else {
+                vk::Format::R8G8B8A8_UNORM
+            },
+            levels: vec![pixels],
+        }
+    }
+
+    // Confere se cada nível tem o tamanho que o formato pede
+    pub fn validate(&self) -> Result<()> {
+        if self.levels.is_empty() {
+            return Err(anyhow!("Texture has no mip levels."));
+        }
+
+        let (block_size, bytes_per_block) = block_info(self.format);
+        if block_size == 0 {
+            return Err(anyhow!("Unsupported texture format {:?}.", self.format));
+        }
+
+        let blocks = |texels: u32| ((texels + block_size - 1) / block_size) as usize;
+        for (level, bytes) in self.levels.iter().enumerate() {
+            let width = (self.width >> level).max(1);
+            let height = (self.height >> level).max(1);
+            let expected = blocks(width) * blocks(height) * bytes_per_block as usize;
+            if bytes.len() != expected {
+                return Err(anyhow!(
+                    "Mip level {} of a {}x{} {:?} texture has {} bytes, expected {}.",
+                    level,
+                    self.width,
+                    self.height,
+                    self.format,
+                    bytes.len(),
+                    expected
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Lado do bloco em texels e bytes por bloco. Formatos não comprimidos têm blocos de 1x1;
+// (0, 0) pros que não sabemos enviar
+pub fn block_info(format: vk::Format) -> (u32, u32) {
+    match format {
+        vk::Format::R8_UNORM => (1, 1),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB => (1, 4),
+        vk::Format::R16G16B16A16_SFLOAT => (1, 8),
+        vk::Format::R32G32B32A32_SFLOAT => (1, 16),
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => (4, 8),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK
+        | vk::Format::ASTC_4X4_UNORM_BLOCK
+        | vk::Format::ASTC_4X4_SRGB_BLOCK => (4, 16),
+        _ => (0, 0),
+    }
+}
+
+// Todos os formatos de `block_info`
+const KNOWN_FORMATS: [vk::Format; 25] = [
+    vk::Format::R8_UNORM,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::B8G8R8A8_UNORM,
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R32G32B32A32_SFLOAT,
+    vk::Format::BC1_RGB_UNORM_BLOCK,
+    vk::Format::BC1_RGB_SRGB_BLOCK,
+    vk::Format::BC1_RGBA_UNORM_BLOCK,
+    vk::Format::BC1_RGBA_SRGB_BLOCK,
+    vk::Format::BC4_UNORM_BLOCK,
+    vk::Format::BC4_SNORM_BLOCK,
+    vk::Format::BC2_UNORM_BLOCK,
+    vk::Format::BC2_SRGB_BLOCK,
+    vk::Format::BC3_UNORM_BLOCK,
+    vk::Format::BC3_SRGB_BLOCK,
+    vk::Format::BC5_UNORM_BLOCK,
+    vk::Format::BC5_SNORM_BLOCK,
+    vk::Format::BC6H_UFLOAT_BLOCK,
+    vk::Format::BC6H_SFLOAT_BLOCK,
+    vk::Format::BC7_UNORM_BLOCK,
+    vk::Format::BC7_SRGB_BLOCK,
+    vk::Format::ASTC_4X4_UNORM_BLOCK,
+    vk::Format::ASTC_4X4_SRGB_BLOCK,
+];
+
+// Se o dispositivo consegue amostrar imagens OPTIMAL nesse formato
+pub unsafe fn supports_sampling(instance: &Instance, data: &AppData, format: vk::Format) -> bool {
+    block_info(format).0 != 0
+        && instance
+            .get_physical_device_format_properties(data.physical_device, format)
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+}
+
+// Quais dos formatos conhecidos o dispositivo amostra, consultados uma vez. Serve pra
+// decodificar fora da thread principal, sem precisar do Instance (ver assets.rs)
+#[derive(Clone, Debug, Default)]
+pub struct FormatSupport {
+    sampled: HashSet<vk::Format>,
+}
+
+impl FormatSupport {
+    pub unsafe fn query(instance: &Instance, data: &AppData) -> Self {
+        Self {
+            sampled: KNOWN_FORMATS
+                .iter()
+                .copied()
+                .filter(|f| supports_sampling(instance, data, *f))
+                .collect(),
+        }
+    }
+
+    pub fn can_sample(&self, format: vk::Format) -> bool {
+        self.sampled.contains(&format)
+    }
+}
+
+// Carrega pela extensão: KTX2 (Basis ou um formato Vulkan qualquer), DDS ou PNG. Falha
+// se o dispositivo não amostra o formato do arquivo, antes de criar qualquer coisa
+pub unsafe fn load(
+    instance: &Instance,
+    data: &AppData,
+    path: &Path,
+    srgb: bool,
+) -> Result<TextureData> {
+    decode(path, srgb, &FormatSupport::query(instance, data))
+}
+
+// O mesmo que `load`, mas só com CPU: pode rodar em qualquer thread
+pub fn decode(path: &Path, srgb: bool, support: &FormatSupport) -> Result<TextureData> {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+
+    let texture = match extension.as_deref() {
+        Some("ktx2") => ktx::load(path, srgb, support)?,
+        Some("dds") => dds::load(path, srgb)?,
+        _ => {
+            let (width, height, pixels) = load_png(path)?;
+            TextureData::rgba8(width, height, pixels, srgb)
+        }
+    };
+
+    texture.validate()?;
+    if !support.can_sample(texture.format) {
+        return Err(anyhow!(
+            "Device can't sample {:?}, used by {}.",
+            texture.format,
+            path.display()
+        ));
+    }
+
+    Ok(texture)
+}
+
+// Imagem amostrada por shaders, com a view e o sampler dela. O conteúdo é enviado pela
+// fila de uploads, que deixa a imagem em SHADER_READ_ONLY_OPTIMAL no fim
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Texture {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+    // Vem do cache de samplers (ver sampler.rs), a textura não destrói
+    pub sampler: vk::Sampler,
+    pub filter: vk::Filter,
+    // Primeiro mip que o sampler lê (ver `clamp_lod`)
+    pub min_level: u32,
+    pub width: u32,
+    pub height: u32,
+    pub format: vk::Format,
+    pub mip_levels: u32,
+}
+
+impl Texture {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        filter: vk::Filter,
+    ) -> Result<Self> {
+        Self::create_mips(
+            instance,
+            device,
+            data,
+            width,
+            height,
+            1,
+            format,
+            filter,
+            &[],
+        )
+    }
+
+    // Com `mip_levels` níveis, cada um enviado separado (ver `enqueue`). `families` são as
+    // famílias de filas que vão usar a imagem, se for mais de uma (ver streaming.rs)
+    pub unsafe fn create_mips(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        format: vk::Format,
+        filter: vk::Filter,
+        families: &[u32],
+    ) -> Result<Self> {
+        let (image, memory) = create_image_mips_shared(
+            instance,
+            device,
+            data,
+            width,
+            height,
+            mip_levels,
+            format,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            families,
+        )?;
+
+        let view = create_image_view_mips(
+            device,
+            image,
+            format,
+            vk::ImageAspectFlags::COLOR,
+            0,
+            mip_levels,
+        )?;
+
+        let mut texture = Self {
+            image,
+            memory,
+            view,
+            sampler: vk::Sampler::null(),
+            filter,
+            min_level: 0,
+            width,
+            height,
+            format,
+            mip_levels,
+        };
+        texture.update_sampler(device)?;
+
+        Ok(texture)
+    }
+
+    // Textura do tamanho, formato e número de mips de `texture`
+    pub unsafe fn from_data(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        texture: &TextureData,
+        filter: vk::Filter,
+        families: &[u32],
+    ) -> Result<Self> {
+        Self::create_mips(
+            instance,
+            device,
+            data,
+            texture.width,
+            texture.height,
+            texture.levels.len() as u32,
+            texture.format,
+            filter,
+            families,
+        )
+    }
+
+    // Troca o sampler por um que não desce abaixo do mip `min_level`, pra não ler níveis
+    // que ainda não chegaram. O antigo continua no cache, pros frames em voo
+    pub unsafe fn clamp_lod(&mut self, device: &Device, min_level: u32) -> Result<()> {
+        self.min_level = min_level;
+        self.update_sampler(device)
+    }
+
+    // Pega de novo o sampler no cache, depois de mudar a anisotropia. Só as texturas com
+    // mips e filtro linear são anisotrópicas
+    pub unsafe fn update_sampler(&mut self, device: &Device) -> Result<()> {
+        let desc = SamplerDesc {
+            filter: self.filter,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            anisotropic: self.filter == vk::Filter::LINEAR && self.mip_levels > 1,
+            min_lod: self.min_level as f32,
+            max_lod: (self.mip_levels - 1) as f32,
+            ..Default::default()
+        };
+        self.sampler = sampler::get(device, &desc)?;
+        Ok(())
+    }
+
+    // Destino pra `UploadQueue::enqueue` com os pixels da textura inteira
+    pub fn upload_target(&self, bytes_per_pixel: u32) -> UploadTarget {
+        self.level_upload_target(0, 1, bytes_per_pixel)
+    }
+
+    // Destino de um nível de mip, com o tamanho de bloco do formato (1 se não é comprimido)
+    pub fn level_upload_target(
+        &self,
+        level: u32,
+        block_size: u32,
+        bytes_per_block: u32,
+    ) -> UploadTarget {
+        leaks::check(self.image, "texture upload");
+        UploadTarget::Image {
+            image: self.image,
+            width: (self.width >> level).max(1),
+            height: (self.height >> level).max(1),
+            depth: 1,
+            mip_level: level,
+            block_size,
+            bytes_per_block,
+            final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    // Manda todos os níveis de `texture` pra fila. O id é o do último, que termina depois
+    // dos outros
+    pub fn enqueue(&self, uploads: &mut UploadQueue, texture: &TextureData) -> UploadId {
+        self.enqueue_from(uploads, texture, 0)
+    }
+
+    // Só os níveis a partir de `first_level` (os menores)
+    pub fn enqueue_from(
+        &self,
+        uploads: &mut UploadQueue,
+        texture: &TextureData,
+        first_level: u32,
+    ) -> UploadId {
+        let (block_size, bytes_per_block) = block_info(texture.format);
+        texture
+            .levels
+            .iter()
+            .enumerate()
+            .skip(first_level as usize)
+            .map(|(level, bytes)| {
+                let target = self.level_upload_target(level as u32, block_size, bytes_per_block);
+                uploads.enqueue(target, bytes.clone())
+            })
+            .last()
+            .unwrap()
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        leaks::destroy_image_view(device, self.view);
+        leaks::destroy_image(device, self.image);
+        allocator::free(device, self.memory);
+    }
+}
+
+// Descriptor set com uma textura só (combined image sampler no binding 0, lido pela
+// fragment shader), que é o que os passes 2D usam
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TextureSet {
+    pub layout: vk::DescriptorSetLayout,
+    pub pool: vk::DescriptorPool,
+    pub set: vk::DescriptorSet,
+}
+
+impl TextureSet {
+    pub unsafe fn create(device: &Device) -> Result<Self> {
+        let layout = create_set_layout(device)?;
+
+        let pool_sizes = &[vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .build()];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        let pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(set_layouts);
+        let set = device.allocate_descriptor_sets(&info)?[0];
+
+        Ok(Self { layout, pool, set })
+    }
+
+    // O set não pode estar em uso por um frame em voo
+    pub unsafe fn write(&self, device: &Device, texture: &Texture) {
+        write_set(device, self.set, texture);
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+    }
+}
+
+// Layout de um set com uma textura no binding 0
+pub unsafe fn create_set_layout(device: &Device) -> Result<vk::DescriptorSetLayout> {
+    let bindings = &[vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build()];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    Ok(device.create_descriptor_set_layout(&info, None)?)
+}
+
+pub unsafe fn write_set(device: &Device, set: vk::DescriptorSet, texture: &Texture) {
+    let image_info = &[vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(texture.view)
+        .sampler(texture.sampler)
+        .build()];
+    let write = vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_info);
+
+    device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+}
+
+// Lê um PNG como RGBA8, convertendo de RGB/cinza/paleta quando precisa
+pub fn load_png(path: &Path) -> Result<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer)?;
+
+    let pixels = match info.color_type {
+        png::ColorType::RGBA => buffer,
+        png::ColorType::RGB => buffer
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+        other => {
+            return Err(anyhow!(
+                "Unsupported PNG color type {:?} in {:?}.",
+                other,
+                path
+            ))
+        }
+    };
+
+    Ok((info.width, info.height, pixels))
+}
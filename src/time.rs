@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+// Passo padrão da simulação: 60 atualizações por segundo
+pub const DEFAULT_FIXED_STEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// Acima disso o delta é cortado (ex: depois de arrastar a janela ou de um breakpoint),
+// senão a simulação teria que correr um monte de passos pra alcançar e ficaria cada vez
+// mais pra trás
+const MAX_DELTA: Duration = Duration::from_millis(250);
+
+// Relógio do loop principal. `tick` marca o começo de cada frame; `fixed_update` roda a
+// simulação em passos de tamanho fixo, independente de quantos frames são desenhados
+#[derive(Copy, Clone, Debug)]
+pub struct Time {
+    pub fixed_step: Duration,
+    start: Instant,
+    last_tick: Option<Instant>,
+    delta: Duration,
+    frame: u64,
+    // Tempo ainda não consumido pelos passos fixos
+    accumulator: Duration,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self::new(DEFAULT_FIXED_STEP)
+    }
+}
+
+impl Time {
+    pub fn new(fixed_step: Duration) -> Self {
+        Self {
+            fixed_step,
+            start: Instant::now(),
+            last_tick: None,
+            delta: Duration::ZERO,
+            frame: 0,
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        self.delta = match self.last_tick {
+            Some(last) => (now - last).min(MAX_DELTA),
+            None => Duration::ZERO,
+        };
+        self.last_tick = Some(now);
+        self.frame += 1;
+        self.accumulator += self.delta;
+    }
+
+    // Pro loop parado esperando eventos: o tempo ocioso não vira um delta gigante no
+    // próximo `tick`
+    pub fn pause(&mut self) {
+        self.last_tick = None;
+    }
+
+    // Chama `update` uma vez por passo fixo acumulado e devolve quantas vezes chamou
+    pub fn fixed_update<F: FnMut(Duration)>(&mut self, mut update: F) -> u32 {
+        let mut steps = 0;
+
+        while self.accumulator >= self.fixed_step {
+            update(self.fixed_step);
+            self.accumulator -= self.fixed_step;
+            steps += 1;
+        }
+
+        steps
+    }
+
+    // Quanto do próximo passo fixo já passou (0 a 1), pra interpolar o estado da
+    // simulação na hora de desenhar
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.fixed_step.as_secs_f32()
+    }
+
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    // Desde a criação, em tempo real (inclui o que o MAX_DELTA cortou)
+    pub fn total(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    config::DebugView,
+    exposure::AutoExposure,
+    pass::PostStage,
+    pipeline::{Pipeline, PipelineDesc},
+    sampler::{self, SamplerDesc},
+    shader::spirv,
+};
+
+// Curva que leva a cor HDR da cena pro intervalo da tela
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    // Aproximação do ACES filmic: contraste e um ombro suave nos brilhos
+    Aces,
+    // x / (1 + x), que nunca estoura mas lava as cores
+    Reinhard,
+    // Sem curva: o que passar de 1 é cortado
+    Clamp,
+}
+
+impl ToneMapOperator {
+    pub const ALL: [ToneMapOperator; 3] = [
+        ToneMapOperator::Aces,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::Clamp,
+    ];
+
+    // Mesmo número do tonemap.frag
+    fn shader_index(self) -> u32 {
+        match self {
+            ToneMapOperator::Aces => 0,
+            ToneMapOperator::Reinhard => 1,
+            ToneMapOperator::Clamp => 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    // Em stops (EV): a cor é multiplicada por 2^exposure antes da curva. Com a exposição
+    // automática, vira uma compensação em cima dela
+    pub exposure: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::Aces,
+            exposure: 0.0,
+        }
+    }
+}
+
+// Primeiro estágio do pós-processamento: lê o alvo HDR da cena e escreve na swapchain (ou
+// no alvo do próximo estágio), já codificado. Tudo aqui depende do alvo HDR, então é
+// recriado com a swapchain
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ToneMapper {
+    pub settings: ToneMapping,
+    sampler: vk::Sampler,
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    pipeline: Pipeline,
+    // No lugar da curva quando uma visão de debug está ligada (ver debug.frag)
+    debug_pipeline: Pipeline,
+}
+
+impl ToneMapper {
+    pub unsafe fn create(
+        device: &Device,
+        data: &AppData,
+        exposure: &AutoExposure,
+        settings: ToneMapping,
+    ) -> Result<Self> {
+        let mut tone_mapper = Self {
+            settings,
+            ..Default::default()
+        };
+
+        tone_mapper.create_pipeline(device, data, exposure)?;
+        Ok(tone_mapper)
+    }
+
+    pub unsafe fn create_pipeline(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        exposure: &AutoExposure,
+    ) -> Result<()> {
+        // A shader lê texel a texel, o alvo tem o mesmo tamanho da swapchain
+        self.sampler = sampler::get(
+            device,
+            &SamplerDesc {
+                filter: vk::Filter::NEAREST,
+                ..Default::default()
+            },
+        )?;
+
+        // Alvo HDR no binding 0, luminância adaptada da exposição automática no 1
+        let bindings = &[
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        self.layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .build(),
+        ];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        self.pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[self.layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(set_layouts);
+        self.set = device.allocate_descriptor_sets(&info)?[0];
+
+        let image_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(data.render_pass.color.view)
+            .sampler(self.sampler)
+            .build()];
+        let image_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(image_info);
+
+        let buffer_info = &[vk::DescriptorBufferInfo::builder()
+            .buffer(exposure.buffer())
+            .offset(0)
+            .range(AutoExposure::buffer_size())
+            .build()];
+        let buffer_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(buffer_info);
+
+        device.update_descriptor_sets(
+            &[image_write, buffer_write],
+            &[] as &[vk::CopyDescriptorSet],
+        );
+
+        let vertex_shader = spirv!("fullscreen.vert");
+        let fragment_shader = spirv!("tonemap.frag");
+
+        // Função de transferência, curva, multiplicador da exposição e se a exposição
+        // automática está valendo
+        let push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(16)
+            .build()];
+        let set_layouts = &[self.layout];
+
+        let mut desc = PipelineDesc::new(
+            &vertex_shader[..],
+            &fragment_shader[..],
+            data.post_pass.stage_pass(PostStage::ToneMap),
+        );
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        desc.depth_test = false;
+        desc.depth_write = false;
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        // Mesmo set; só a função de transferência e a visão
+        let debug_shader = spirv!("debug.frag");
+        let debug_push_constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(8)
+            .build()];
+        desc.fragment_shader = &debug_shader[..];
+        desc.push_constants = debug_push_constants;
+        self.debug_pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    // Grava o estágio, já dentro do pass dele. Fora da visão final a cena tem dados, não
+    // cor, e vai pelo debug.frag sem exposição nem curva. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        auto_exposure: bool,
+        debug_view: DebugView,
+    ) -> u32 {
+        let transfer = (data.post_pass.transfer(PostStage::ToneMap) as u32).to_ne_bytes();
+        let (pipeline, constants) = if debug_view == DebugView::Final {
+            let constants = [
+                transfer,
+                self.settings.operator.shader_index().to_ne_bytes(),
+                self.settings.exposure.exp2().to_ne_bytes(),
+                (auto_exposure as u32).to_ne_bytes(),
+            ]
+            .concat();
+            (&self.pipeline, constants)
+        } else {
+            let constants = [transfer, debug_view.shader_index().to_ne_bytes()].concat();
+            (&self.debug_pipeline, constants)
+        };
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            0,
+            &[self.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &constants,
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+        1
+    }
+
+    pub unsafe fn destroy_pipeline(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+        self.debug_pipeline.destroy(device);
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+    }
+}
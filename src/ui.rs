@@ -0,0 +1,461 @@
+use std::{fmt, mem::size_of, slice};
+
+use anyhow::Result;
+use egui::{epaint, ClippedMesh, CtxRef, TextureId};
+use log::*;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+use winit::{event::WindowEvent, window::Window};
+
+use crate::{
+    app::AppData,
+    buffer::DynamicBuffer,
+    pipeline::{BlendMode, Pipeline, PipelineDesc},
+    shader::spirv,
+    texture::{Texture, TextureSet},
+    upload::{UploadId, UploadQueue},
+};
+
+// Atlas de fontes do egui na GPU, e qual versão dele foi enviada
+#[derive(Copy, Clone, Debug)]
+struct FontTexture {
+    texture: Texture,
+    version: u64,
+    upload: UploadId,
+}
+
+// Interface imediata (egui) desenhada por cima da cena, num render pass próprio que
+// carrega o que o pass principal deixou na imagem da swapchain
+pub struct Ui {
+    context: CtxRef,
+    state: egui_winit::State,
+    // Malhas do último `run`, consumidas pelo próximo `record`
+    meshes: Vec<ClippedMesh>,
+    // O egui pediu outro frame (animação, tooltip aparecendo...)
+    needs_repaint: bool,
+    font: Option<FontTexture>,
+    textures: TextureSet,
+    render_pass: vk::RenderPass,
+    framebuffers: Vec<vk::Framebuffer>,
+    pipeline: Pipeline,
+    vertices: DynamicBuffer,
+    indices: DynamicBuffer,
+}
+
+impl fmt::Debug for Ui {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ui")
+            .field("meshes", &self.meshes.len())
+            .field("font", &self.font)
+            .field("render_pass", &self.render_pass)
+            .field("pipeline", &self.pipeline)
+            .finish()
+    }
+}
+
+impl Ui {
+    pub unsafe fn create(device: &Device, data: &AppData, window: &Window) -> Result<Self> {
+        let mut ui = Self {
+            context: CtxRef::default(),
+            state: egui_winit::State::new(window),
+            meshes: vec![],
+            needs_repaint: false,
+            font: None,
+            textures: TextureSet::default(),
+            render_pass: vk::RenderPass::null(),
+            framebuffers: vec![],
+            pipeline: Pipeline::default(),
+            vertices: DynamicBuffer::new(vk::BufferUsageFlags::VERTEX_BUFFER),
+            indices: DynamicBuffer::new(vk::BufferUsageFlags::INDEX_BUFFER),
+        };
+
+        ui.create_device_objects(device)?;
+        ui.create_swapchain_objects(device, data)?;
+        Ok(ui)
+    }
+
+    // Descritor do atlas de fontes, que não depende da swapchain
+    pub unsafe fn create_device_objects(&mut self, device: &Device) -> Result<()> {
+        self.textures = TextureSet::create(device)?;
+        Ok(())
+    }
+
+    // Render pass, framebuffers e pipeline, recriados junto com a swapchain
+    pub unsafe fn create_swapchain_objects(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+    ) -> Result<()> {
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(data.swapchain.format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+
+        let color_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let color_attachments = &[color_attachment_ref];
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(color_attachments);
+
+        // Espera o pass principal terminar de escrever na imagem
+        let dependency = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            );
+
+        let attachments = &[color_attachment];
+        let subpasses = &[subpass];
+        let dependencies = &[dependency];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(attachments)
+            .subpasses(subpasses)
+            .dependencies(dependencies);
+        self.render_pass = device.create_render_pass(&info, None)?;
+
+        self.framebuffers = data
+            .swapchain
+            .image_views
+            .iter()
+            .map(|view| {
+                let attachments = &[*view];
+                let info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(self.render_pass)
+                    .attachments(attachments)
+                    .width(data.swapchain.extent.width)
+                    .height(data.swapchain.extent.height)
+                    .layers(1);
+
+                device.create_framebuffer(&info, None)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let vertex_shader = spirv!("ui.vert");
+        let fragment_shader = spirv!("ui.frag");
+
+        let bindings = &[vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<epaint::Vertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()];
+
+        // Posição em pontos, uv normalizado, cor em sRGB com alpha pré-multiplicado
+        let attributes = &[
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(size_of::<[f32; 2]>() as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(2)
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .offset(size_of::<[f32; 4]>() as u32)
+                .build(),
+        ];
+
+        // Mesmo layout do overlay: pré-rotação + tamanho da tela, e a função de transferência
+        let push_constants = &[
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size((size_of::<glm::Mat4>() + size_of::<[f32; 2]>()) as u32)
+                .build(),
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset((size_of::<glm::Mat4>() + size_of::<[f32; 2]>()) as u32)
+                .size(size_of::<u32>() as u32)
+                .build(),
+        ];
+
+        let set_layouts = &[self.textures.layout];
+        let mut desc =
+            PipelineDesc::new(&vertex_shader[..], &fragment_shader[..], self.render_pass);
+        desc.bindings = bindings;
+        desc.attributes = attributes;
+        desc.set_layouts = set_layouts;
+        desc.push_constants = push_constants;
+        desc.cull_mode = vk::CullModeFlags::NONE;
+        desc.depth_test = false;
+        desc.depth_write = false;
+        desc.blend = BlendMode::Premultiplied;
+
+        self.pipeline = Pipeline::create(device, &desc)?;
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy_swapchain_objects(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+        self.framebuffers
+            .drain(..)
+            .for_each(|f| device.destroy_framebuffer(f, None));
+        device.destroy_render_pass(self.render_pass, None);
+    }
+
+    // Repassa um evento da janela. Retorna true se o egui quer ele só pra si (ex: digitando
+    // numa caixa de texto), e o resto do app deve ignorar
+    pub fn on_event(&mut self, event: &WindowEvent) -> bool {
+        self.state.on_event(&self.context, event)
+    }
+
+    // Roda a interface de um frame e guarda as malhas pra desenhar
+    pub fn run(&mut self, window: &Window, f: impl FnOnce(&CtxRef)) {
+        let input = self.state.take_egui_input(window);
+        self.context.begin_frame(input);
+        f(&self.context);
+
+        let (output, shapes) = self.context.end_frame();
+        self.needs_repaint = output.needs_repaint;
+        self.state.handle_output(window, &self.context, output);
+        self.meshes = self.context.tessellate(shapes);
+    }
+
+    pub fn needs_repaint(&self) -> bool {
+        self.needs_repaint
+    }
+
+    pub fn clear_repaint(&mut self) {
+        self.needs_repaint = false;
+    }
+
+    // Envia o atlas de fontes se ele mudou desde o último envio
+    pub unsafe fn update_font(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        uploads: &mut UploadQueue,
+    ) -> Result<()> {
+        let atlas = self.context.texture();
+        if self.font.map(|f| f.version) == Some(atlas.version) {
+            return Ok(());
+        }
+
+        // Raro (só quando aparecem glifos novos), então esperar a GPU é aceitável
+        if let Some(old) = self.font.take() {
+            device.device_wait_idle()?;
+            old.texture.destroy(device);
+        }
+
+        let texture = Texture::create(
+            instance,
+            device,
+            data,
+            atlas.width as u32,
+            atlas.height as u32,
+            vk::Format::R8_UNORM,
+            vk::Filter::LINEAR,
+        )?;
+        let upload = uploads.enqueue(texture.upload_target(1), atlas.pixels.clone());
+
+        self.textures.write(device, &texture);
+
+        self.font = Some(FontTexture {
+            texture,
+            version: atlas.version,
+            upload,
+        });
+
+        Ok(())
+    }
+
+    pub fn has_content(&self) -> bool {
+        !self.meshes.is_empty()
+    }
+
+    // Grava o render pass da interface. Retorna quantos draw calls fez
+    pub unsafe fn record(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        slot: usize,
+        uploads: &UploadQueue,
+    ) -> Result<u32> {
+        let meshes = std::mem::take(&mut self.meshes);
+
+        // Sem o atlas na GPU ainda não tem o que desenhar
+        match self.font {
+            Some(font) if !uploads.is_pending(font.upload) => {}
+            _ => return Ok(0),
+        }
+
+        let meshes = meshes
+            .into_iter()
+            .filter(|ClippedMesh(_, mesh)| {
+                if mesh.texture_id != TextureId::Egui {
+                    debug!(
+                        "Skipping egui mesh with user texture {:?}.",
+                        mesh.texture_id
+                    );
+                    return false;
+                }
+
+                !mesh.indices.is_empty()
+            })
+            .collect::<Vec<_>>();
+        if meshes.is_empty() {
+            return Ok(0);
+        }
+
+        // Todas as malhas num buffer só, cada draw com o seu deslocamento
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        for ClippedMesh(_, mesh) in &meshes {
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+
+        let vertex_bytes = slice::from_raw_parts(
+            vertices.as_ptr() as *const u8,
+            vertices.len() * size_of::<epaint::Vertex>(),
+        );
+        let index_bytes = slice::from_raw_parts(
+            indices.as_ptr() as *const u8,
+            indices.len() * size_of::<u32>(),
+        );
+        let vertex_buffer = self
+            .vertices
+            .write(instance, device, data, slot, vertex_bytes)?;
+        let index_buffer = self
+            .indices
+            .write(instance, device, data, slot, index_bytes)?;
+
+        let pixels_per_point = self.context.pixels_per_point();
+        let extent = data.swapchain.logical_extent();
+        let screen = [
+            extent.width as f32 / pixels_per_point,
+            extent.height as f32 / pixels_per_point,
+        ];
+        let transform = data.swapchain.pre_rotation();
+        let mut constants = [0u8; size_of::<glm::Mat4>() + size_of::<[f32; 2]>()];
+        constants[..64].copy_from_slice(slice::from_raw_parts(transform.as_ptr() as *const u8, 64));
+        constants[64..].copy_from_slice(slice::from_raw_parts(screen.as_ptr() as *const u8, 8));
+
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(data.swapchain.extent);
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffers[image_index])
+            .render_area(render_area);
+
+        device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        Pipeline::set_viewport(device, command_buffer, data.swapchain.extent);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            0,
+            &[self.textures.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            &constants,
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            constants.len() as u32,
+            &(data.swapchain.transfer as u32).to_ne_bytes(),
+        );
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+        device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, vk::IndexType::UINT32);
+
+        let rotated = data.swapchain.transform != vk::SurfaceTransformFlagsKHR::IDENTITY;
+        let mut draw_calls = 0;
+        let mut first_index = 0;
+        let mut vertex_offset = 0;
+        for ClippedMesh(clip, mesh) in &meshes {
+            // Clip rect em pontos -> pixels, dentro da tela. Com a surface girada o scissor
+            // teria que girar junto, então só cobre a tela toda
+            let min_x = (clip.min.x * pixels_per_point).clamp(0.0, extent.width as f32);
+            let min_y = (clip.min.y * pixels_per_point).clamp(0.0, extent.height as f32);
+            let max_x = (clip.max.x * pixels_per_point).clamp(min_x, extent.width as f32);
+            let max_y = (clip.max.y * pixels_per_point).clamp(min_y, extent.height as f32);
+
+            let scissor = if rotated {
+                vk::Rect2D::builder()
+                    .offset(vk::Offset2D::default())
+                    .extent(data.swapchain.extent)
+                    .build()
+            } else {
+                vk::Rect2D::builder()
+                    .offset(vk::Offset2D {
+                        x: min_x.round() as i32,
+                        y: min_y.round() as i32,
+                    })
+                    .extent(vk::Extent2D {
+                        width: (max_x.round() - min_x.round()) as u32,
+                        height: (max_y.round() - min_y.round()) as u32,
+                    })
+                    .build()
+            };
+
+            let index_count = mesh.indices.len() as u32;
+            if scissor.extent.width > 0 && scissor.extent.height > 0 {
+                device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    index_count,
+                    1,
+                    first_index,
+                    vertex_offset,
+                    0,
+                );
+                draw_calls += 1;
+            }
+
+            first_index += index_count;
+            vertex_offset += mesh.vertices.len() as i32;
+        }
+
+        device.cmd_end_render_pass(command_buffer);
+
+        Ok(draw_calls)
+    }
+
+    // Tudo que pertence ao dispositivo, menos o que depende da swapchain. O atlas é
+    // reenviado no próximo `update_font`
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        if let Some(font) = self.font.take() {
+            font.texture.destroy(device);
+        }
+
+        self.vertices.destroy(device);
+        self.indices.destroy(device);
+        self.textures.destroy(device);
+    }
+}
@@ -0,0 +1,505 @@
+use std::{mem::size_of, ptr};
+
+use anyhow::Result;
+use nalgebra_glm as glm;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator,
+    app::AppData,
+    buffer::{create_buffer, StorageBuffer},
+    camera::Camera,
+    features::Feature,
+    leaks,
+    scene::{Light, SceneLight},
+    shadow::{Cascades, ShadowSettings, MAX_CASCADES, MAX_POINT_SHADOWS},
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+// Luzes pontuais além dessas são ignoradas (as primeiras da lista ganham)
+pub const MAX_POINT_LIGHTS: usize = 16;
+
+// Dados do frame lidos pelas shaders da cena (set 0, binding 0). O layout segue o std140
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FrameUniforms {
+    pub view: glm::Mat4,
+    // Já com a pré-rotação da swapchain
+    pub projection: glm::Mat4,
+    pub view_projection: glm::Mat4,
+    // xyz: posição da câmera no mundo
+    pub camera_position: glm::Vec4,
+    // Pra reconstruir a posição a partir da profundidade (resolução do deferred)
+    pub inverse_view: glm::Mat4,
+    pub inverse_projection: glm::Mat4,
+}
+
+impl FrameUniforms {
+    pub fn new(camera: &Camera, pre_rotation: &glm::Mat4) -> Self {
+        let view = camera.view();
+        let projection = pre_rotation * camera.projection_matrix();
+
+        Self {
+            view,
+            projection,
+            view_projection: projection * view,
+            camera_position: glm::vec4(
+                camera.position.x,
+                camera.position.y,
+                camera.position.z,
+                1.0,
+            ),
+            inverse_view: glm::inverse(&view),
+            inverse_projection: glm::inverse(&projection),
+        }
+    }
+}
+
+// Modelo do Preetham já avaliado pro sol atual (ver sky.rs)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SkyUniform {
+    // A..E da distribuição de Perez, xyz pra Y, x e y
+    pub perez: [glm::Vec4; 5],
+    // xyz: Yxy do zênite dividido por F(0, θsol), w: intensidade
+    pub zenith: glm::Vec4,
+    // xyz: pra onde o sol está, w: 1 com o céu ligado
+    pub sun: glm::Vec4,
+    // Irradiância em 9 harmônicos esféricos (rgb), já dividida por π
+    pub irradiance: [glm::Vec4; 9],
+}
+
+// Sonda de reflexo (ver probe.rs)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ReflectionProbeUniform {
+    // xyz: posição no mundo, w: raio de influência. Zero sem sonda
+    pub position: glm::Vec4,
+    // x: último mip do cubo, y: intensidade
+    pub params: glm::Vec4,
+}
+
+// Grade de sondas de irradiância (ver irradiance.rs)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct IrradianceGridUniform {
+    // xyz: posição da primeira sonda, w: 1 com a grade capturada
+    pub origin: glm::Vec4,
+    // xyz: distância entre as sondas em cada eixo
+    pub spacing: glm::Vec4,
+    // xyz: sondas em cada eixo
+    pub counts: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DirectionalLightUniform {
+    // xyz: pra onde a luz vai, no mundo
+    pub direction: glm::Vec4,
+    // rgb: cor * intensidade. Zero quando não tem luz direcional
+    pub color: glm::Vec4,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PointLightUniform {
+    // xyz: posição no mundo, w: alcance
+    pub position: glm::Vec4,
+    // rgb: cor * intensidade
+    pub color: glm::Vec4,
+    // x: índice do cubo de sombra (-1 sem sombra), y: bias da comparação
+    pub shadow: glm::Vec4,
+}
+
+impl Default for PointLightUniform {
+    fn default() -> Self {
+        Self {
+            position: glm::vec4(0.0, 0.0, 0.0, 0.0),
+            color: glm::vec4(0.0, 0.0, 0.0, 0.0),
+            shadow: glm::vec4(-1.0, 0.0, 0.0, 0.0),
+        }
+    }
+}
+
+// Luzes do frame (set 0, binding 1). O layout segue o std140
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct LightUniforms {
+    // rgb: luz ambiente, somada em tudo
+    pub ambient: glm::Vec4,
+    pub directional: DirectionalLightUniform,
+    // Leva do mundo pra cada cascata do shadow map da luz direcional
+    pub cascade_view_projections: [glm::Mat4; MAX_CASCADES],
+    // Distância ao longo do olhar da câmera em que cada cascata termina
+    pub cascade_splits: glm::Vec4,
+    // x: quantas cascatas (0 sem sombra), y: raio do PCF em texels, z: fração misturada
+    // com a próxima cascata, w: 1 pinta as cascatas
+    pub shadow: glm::Vec4,
+    pub point_lights: [PointLightUniform; MAX_POINT_LIGHTS],
+    pub point_count: u32,
+    _padding: [u32; 3],
+    // Céu procedural; tudo zero quando desligado
+    pub sky: SkyUniform,
+    // Sonda de reflexo capturada; tudo zero sem ela
+    pub probe: ReflectionProbeUniform,
+    // Grade de sondas de irradiância; tudo zero sem ela
+    pub irradiance_grid: IrradianceGridUniform,
+}
+
+impl LightUniforms {
+    // Só uma luz direcional é usada: a primeira da lista. As primeiras MAX_POINT_SHADOWS
+    // luzes pontuais com `shadows` ganham um cubo de sombra
+    pub fn new(ambient: glm::Vec3, lights: &[SceneLight]) -> Self {
+        let mut uniforms = Self {
+            ambient: glm::vec4(ambient.x, ambient.y, ambient.z, 0.0),
+            directional: DirectionalLightUniform::default(),
+            cascade_view_projections: [glm::identity(); MAX_CASCADES],
+            cascade_splits: glm::vec4(0.0, 0.0, 0.0, 0.0),
+            shadow: glm::vec4(0.0, 0.0, 0.0, 0.0),
+            point_lights: [PointLightUniform::default(); MAX_POINT_LIGHTS],
+            point_count: 0,
+            _padding: [0; 3],
+            sky: SkyUniform::default(),
+            probe: ReflectionProbeUniform::default(),
+            irradiance_grid: IrradianceGridUniform::default(),
+        };
+
+        let mut has_directional = false;
+        let mut shadow_count = 0;
+        for light in lights {
+            match light.light {
+                Light::Directional { color, intensity } if !has_directional => {
+                    let d = light.direction;
+                    let c = color * intensity;
+                    uniforms.directional = DirectionalLightUniform {
+                        direction: glm::vec4(d.x, d.y, d.z, 0.0),
+                        color: glm::vec4(c.x, c.y, c.z, 0.0),
+                    };
+                    has_directional = true;
+                }
+                Light::Point {
+                    color,
+                    intensity,
+                    range,
+                    shadows,
+                } if (uniforms.point_count as usize) < MAX_POINT_LIGHTS => {
+                    let p = light.position;
+                    let c = color * intensity;
+                    let mut shadow = glm::vec4(-1.0, 0.0, 0.0, 0.0);
+                    if shadows && shadow_count < MAX_POINT_SHADOWS {
+                        shadow.x = shadow_count as f32;
+                        shadow_count += 1;
+                    }
+
+                    uniforms.point_lights[uniforms.point_count as usize] = PointLightUniform {
+                        position: glm::vec4(p.x, p.y, p.z, range),
+                        color: glm::vec4(c.x, c.y, c.z, 0.0),
+                        shadow,
+                    };
+                    uniforms.point_count += 1;
+                }
+                _ => {}
+            }
+        }
+
+        uniforms
+    }
+
+    // Desligadas nas configurações, as luzes pontuais perdem o cubo; ligadas, recebem o bias
+    pub fn set_point_shadows(&mut self, settings: &ShadowSettings) {
+        for light in &mut self.point_lights {
+            if !settings.enabled {
+                light.shadow.x = -1.0;
+            }
+            light.shadow.y = settings.point_bias;
+        }
+    }
+
+    pub fn set_shadow(
+        &mut self,
+        cascades: &Cascades,
+        settings: &ShadowSettings,
+        show_cascades: bool,
+    ) {
+        self.cascade_view_projections = cascades.view_projections;
+        self.cascade_splits = glm::Vec4::from(cascades.splits);
+        self.shadow = glm::vec4(
+            cascades.count as f32,
+            settings.pcf_radius as f32,
+            settings.cascade_blend.clamp(0.0, 1.0),
+            if show_cascades { 1.0 } else { 0.0 },
+        );
+    }
+}
+
+// Buffer de uniforms mapeado o tempo todo, escrito direto pela CPU
+#[derive(Clone, Debug)]
+pub struct UniformBuffer {
+    pub buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    pub mapped: *mut u8,
+}
+
+impl UniformBuffer {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            data,
+            size,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let mapped = device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())? as *mut u8;
+
+        Ok(Self {
+            buffer,
+            memory,
+            mapped,
+        })
+    }
+
+    pub unsafe fn destroy(self, device: &Device) {
+        device.unmap_memory(self.memory);
+        leaks::destroy_buffer(device, self.buffer);
+        allocator::free(device, self.memory);
+    }
+}
+
+// Os buffers de uniforms do frame (câmera e luzes), mapeados e um de cada por frame em
+// voo, com um descriptor set por frame. O frame só escreve no seu depois de esperar a
+// fence, então não tem corrida com a GPU
+#[derive(Clone, Debug, Default)]
+pub struct FrameDescriptors {
+    pub layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    buffers: Vec<UniformBuffer>,
+    light_buffers: Vec<UniformBuffer>,
+}
+
+impl FrameDescriptors {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        // O terreno lê a câmera também nas shaders de tessellation, quando elas existem, e a
+        // névoa volumétrica lê câmera e luzes em compute
+        let light_stages = vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE;
+        let mut frame_stages = vk::ShaderStageFlags::VERTEX | light_stages;
+        if data.capabilities.has(Feature::TessellationShader) {
+            frame_stages |= vk::ShaderStageFlags::TESSELLATION_CONTROL
+                | vk::ShaderStageFlags::TESSELLATION_EVALUATION;
+        }
+
+        let bindings = &[
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(frame_stages)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(light_stages)
+                .build(),
+            // Shadow maps da luz direcional e das pontuais, escritos depois com
+            // `write_shadow_map`
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(2)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(light_stages)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(3)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(MAX_POINT_SHADOWS as u32)
+                .stage_flags(light_stages)
+                .build(),
+            // Cubo da sonda de reflexo, escrito depois com `write_reflection_probe`
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(4)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(light_stages)
+                .build(),
+            // Harmônicos das sondas de irradiância, escrito depois com
+            // `write_irradiance_probes`
+            StorageBuffer::binding(5, light_stages),
+        ];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        let layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = &[
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_count(MAX_FRAMES_IN_FLIGHT as u32 * 2)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(MAX_FRAMES_IN_FLIGHT as u32 * (2 + MAX_POINT_SHADOWS as u32))
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .type_(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(MAX_FRAMES_IN_FLIGHT as u32)
+                .build(),
+        ];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(MAX_FRAMES_IN_FLIGHT as u32);
+        let pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = vec![layout; MAX_FRAMES_IN_FLIGHT];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        let sets = device.allocate_descriptor_sets(&info)?;
+
+        let mut buffers = vec![];
+        let mut light_buffers = vec![];
+        for set in &sets {
+            let frame_size = size_of::<FrameUniforms>() as vk::DeviceSize;
+            let light_size = size_of::<LightUniforms>() as vk::DeviceSize;
+            let frame = UniformBuffer::create(instance, device, data, frame_size)?;
+            let lights = UniformBuffer::create(instance, device, data, light_size)?;
+
+            for (binding, buffer, size) in [(0, &frame, frame_size), (1, &lights, light_size)] {
+                let buffer_info = &[vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer.buffer)
+                    .offset(0)
+                    .range(size)
+                    .build()];
+                let write = vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(buffer_info);
+                device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+            }
+
+            buffers.push(frame);
+            light_buffers.push(lights);
+        }
+
+        Ok(Self {
+            layout,
+            pool,
+            sets,
+            buffers,
+            light_buffers,
+        })
+    }
+
+    pub unsafe fn write(&self, slot: usize, uniforms: &FrameUniforms) {
+        ptr::copy_nonoverlapping(
+            uniforms as *const FrameUniforms as *const u8,
+            self.buffers[slot].mapped,
+            size_of::<FrameUniforms>(),
+        );
+    }
+
+    pub unsafe fn write_lights(&self, slot: usize, lights: &LightUniforms) {
+        ptr::copy_nonoverlapping(
+            lights as *const LightUniforms as *const u8,
+            self.light_buffers[slot].mapped,
+            size_of::<LightUniforms>(),
+        );
+    }
+
+    // Aponta os bindings 2 (cascatas) e 3 (cubos) de todos os frames pras views dos
+    // shadow maps. Só pode ser chamado com a GPU parada (os sets de todos os frames mudam)
+    pub unsafe fn write_shadow_map(
+        &self,
+        device: &Device,
+        view: vk::ImageView,
+        point_views: &[vk::ImageView],
+        sampler: vk::Sampler,
+    ) {
+        let image_info = |view: vk::ImageView| {
+            vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                .image_view(view)
+                .sampler(sampler)
+                .build()
+        };
+        let cascade_info = &[image_info(view)];
+        let point_info = point_views
+            .iter()
+            .map(|v| image_info(*v))
+            .collect::<Vec<_>>();
+
+        for set in &self.sets {
+            let writes = &[
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(2)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(cascade_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(3)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&point_info)
+                    .build(),
+            ];
+            device.update_descriptor_sets(writes, &[] as &[vk::CopyDescriptorSet]);
+        }
+    }
+
+    // Aponta o binding 4 de todos os frames pro cubo da sonda de reflexo. Também só com a
+    // GPU parada
+    pub unsafe fn write_reflection_probe(
+        &self,
+        device: &Device,
+        view: vk::ImageView,
+        sampler: vk::Sampler,
+    ) {
+        let image_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(view)
+            .sampler(sampler)
+            .build()];
+
+        for set in &self.sets {
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(4)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(image_info);
+            device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+        }
+    }
+
+    // Aponta o binding 5 de todos os frames pro buffer das sondas de irradiância. Também
+    // só com a GPU parada
+    pub unsafe fn write_irradiance_probes(&self, device: &Device, buffer: &StorageBuffer) {
+        for set in &self.sets {
+            buffer.write_descriptor(device, *set, 5);
+        }
+    }
+
+    pub fn set(&self, slot: usize) -> vk::DescriptorSet {
+        self.sets[slot]
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.buffers
+            .drain(..)
+            .chain(self.light_buffers.drain(..))
+            .for_each(|b| b.destroy(device));
+
+        self.sets.clear();
+        device.destroy_descriptor_pool(self.pool, None);
+        device.destroy_descriptor_set_layout(self.layout, None);
+    }
+}
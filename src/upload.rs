@@ -0,0 +1,564 @@
+use std::{collections::VecDeque, ptr};
+
+use anyhow::Result;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocator, app::AppData, buffer, error::RendererError, info::QueueFamilyIndices, leaks,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+// Orçamento padrão de bytes transferidos por frame
+pub const DEFAULT_UPLOAD_BUDGET: u64 = 8 * 1024 * 1024;
+
+// Alinhamento dos pedaços dentro do buffer de staging (bufferOffset de cópias pra imagem
+// precisa ser múltiplo de 4 e do tamanho do texel)
+const STAGING_ALIGNMENT: usize = 16;
+
+#[derive(Copy, Clone, Debug)]
+pub enum UploadTarget {
+    Buffer {
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+    },
+    // Um nível de mip de uma imagem 2D (ou 3D, com `depth` fatias), copiado em faixas de
+    // linhas inteiras; `width` e `height` são os do nível. Em formatos comprimidos a linha
+    // é uma fileira de blocos de `block_size` texels de lado (1 nos outros). O nível vai de
+    // UNDEFINED pra TRANSFER_DST_OPTIMAL no primeiro pedaço e pra `final_layout` no último
+    Image {
+        image: vk::Image,
+        width: u32,
+        height: u32,
+        depth: u32,
+        mip_level: u32,
+        block_size: u32,
+        bytes_per_block: u32,
+        final_layout: vk::ImageLayout,
+    },
+}
+
+impl UploadTarget {
+    // Bytes por linha (de pixels ou de blocos) e quantas linhas, somando as fatias
+    fn rows(&self) -> Option<(usize, u32)> {
+        match *self {
+            UploadTarget::Buffer { .. } => None,
+            UploadTarget::Image {
+                width,
+                height,
+                depth,
+                block_size,
+                bytes_per_block,
+                ..
+            } => {
+                let blocks = |texels: u32| (texels + block_size - 1) / block_size;
+                Some((
+                    (blocks(width) * bytes_per_block) as usize,
+                    blocks(height) * depth,
+                ))
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UploadId(u64);
+
+#[derive(Debug)]
+struct PendingUpload {
+    id: UploadId,
+    target: UploadTarget,
+    bytes: Vec<u8>,
+    // Quanto já foi copiado
+    cursor: usize,
+}
+
+// O staging usado por um frame em voo, e quais uploads terminam quando ele terminar
+#[derive(Debug, Default)]
+struct StagingSlot {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    finishing: Vec<UploadId>,
+}
+
+// Fila de uploads espalhados por vários frames: cada frame copia no máximo `budget`
+// bytes, pra carregar uma cena grande no meio da sessão não travar a renderização
+#[derive(Debug)]
+pub struct UploadQueue {
+    pub budget: u64,
+    // Gravada pra uma fila que pode não ter gráficos (ver assets.rs): as barreiras só usam
+    // estágios de transferência, e quem lê espera a fence do submit na CPU
+    transfer_only: bool,
+    next_id: u64,
+    pending: VecDeque<PendingUpload>,
+    slots: Vec<Option<StagingSlot>>,
+}
+
+impl UploadQueue {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            transfer_only: false,
+            next_id: 0,
+            pending: VecDeque::new(),
+            slots: (0..MAX_FRAMES_IN_FLIGHT).map(|_| None).collect(),
+        }
+    }
+
+    pub fn for_transfer_queue(budget: u64) -> Self {
+        Self {
+            transfer_only: true,
+            ..Self::new(budget)
+        }
+    }
+
+    pub fn enqueue(&mut self, target: UploadTarget, bytes: Vec<u8>) -> UploadId {
+        let id = UploadId(self.next_id);
+        self.next_id += 1;
+
+        // Nada pra copiar: o id já nasce terminado, e um pedaço vazio travaria a fila
+        if bytes.is_empty() {
+            return id;
+        }
+
+        self.pending.push_back(PendingUpload {
+            id,
+            target,
+            bytes,
+            cursor: 0,
+        });
+
+        id
+    }
+
+    // Um upload continua pendente até o frame que copiou o último pedaço terminar
+    pub fn is_pending(&self, id: UploadId) -> bool {
+        self.pending.iter().any(|u| u.id == id)
+            || self
+                .slots
+                .iter()
+                .flatten()
+                .any(|s| s.finishing.contains(&id))
+    }
+
+    // Nada na fila nem esperando um frame terminar
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty() && self.slots.iter().flatten().all(|s| s.finishing.is_empty())
+    }
+
+    pub fn pending_bytes(&self) -> usize {
+        self.pending.iter().map(|u| u.bytes.len() - u.cursor).sum()
+    }
+
+    // Chamado depois de esperar a fence do frame `slot`
+    pub unsafe fn release(&mut self, device: &Device, slot: usize) {
+        if let Some(staging) = self.slots[slot].take() {
+            leaks::destroy_buffer(device, staging.buffer);
+            allocator::free(device, staging.memory);
+        }
+    }
+
+    // Grava as cópias desse frame, antes do render pass
+    pub unsafe fn record(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+    ) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        // Decide o que cabe no orçamento antes de criar o staging
+        let mut chunks = vec![];
+        let mut used = 0usize;
+        for (index, upload) in self.pending.iter().enumerate() {
+            let remaining = (self.budget as usize).saturating_sub(used);
+            let left = upload.bytes.len() - upload.cursor;
+
+            let size = match upload.target.rows() {
+                None => left.min(remaining),
+                Some((row, _)) => (remaining / row * row).min(left),
+            };
+
+            // Garante que pelo menos um pedaço anda por frame, mesmo com orçamento minúsculo:
+            // no máximo o orçamento, ou uma linha inteira se nem ela couber
+            let size = if size == 0 && chunks.is_empty() {
+                match upload.target.rows() {
+                    None => left.min((self.budget as usize).max(STAGING_ALIGNMENT)),
+                    Some((row, _)) => row.min(left),
+                }
+            } else {
+                size
+            };
+
+            if size == 0 {
+                break;
+            }
+
+            let offset = align(used, STAGING_ALIGNMENT);
+            chunks.push((index, offset, size));
+            used = offset + size;
+        }
+        if used == 0 {
+            return Ok(());
+        }
+
+        let (buffer, memory) = buffer::create_buffer(
+            instance,
+            device,
+            data,
+            used as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let mapped = device.map_memory(
+            memory,
+            0,
+            used as vk::DeviceSize,
+            vk::MemoryMapFlags::empty(),
+        )? as *mut u8;
+
+        let mut finishing = vec![];
+        for (index, offset, size) in chunks {
+            let upload = &mut self.pending[index];
+            ptr::copy_nonoverlapping(
+                upload.bytes[upload.cursor..].as_ptr(),
+                mapped.add(offset),
+                size,
+            );
+
+            record_chunk(
+                device,
+                command_buffer,
+                buffer,
+                offset,
+                size,
+                upload,
+                self.transfer_only,
+            );
+
+            upload.cursor += size;
+            if upload.cursor == upload.bytes.len() {
+                finishing.push(upload.id);
+            }
+        }
+
+        device.unmap_memory(memory);
+
+        self.pending.retain(|u| u.cursor < u.bytes.len());
+        self.slots[slot] = Some(StagingSlot {
+            buffer,
+            memory,
+            finishing,
+        });
+
+        if self.transfer_only {
+            return Ok(());
+        }
+
+        // Os dados copiados precisam estar visíveis pra quem ler eles nesse frame
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                    | vk::AccessFlags::INDEX_READ
+                    | vk::AccessFlags::UNIFORM_READ
+                    | vk::AccessFlags::SHADER_READ,
+            );
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::VERTEX_INPUT
+                | vk::PipelineStageFlags::VERTEX_SHADER
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for slot in 0..self.slots.len() {
+            self.release(device, slot);
+        }
+
+        self.pending.clear();
+    }
+}
+
+// Command buffer próprio na fila de transferência (ou na de gráficos, se não tiver uma),
+// pra cópias fora do frame. Um submit por vez, acompanhado por uma fence
+#[derive(Debug)]
+pub struct TransferContext {
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    submitted: bool,
+}
+
+impl TransferContext {
+    pub unsafe fn create(instance: &Instance, device: &Device, data: &AppData) -> Result<Self> {
+        let (family, queue) = match data.streaming_queue {
+            Some(transfer) => (transfer.family, transfer.queue),
+            None => {
+                let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+                (indices.graphics, data.graphics_queue)
+            }
+        };
+
+        let info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(family);
+        let command_pool = device.create_command_pool(&info, None)?;
+
+        let info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+        let fence = device.create_fence(&vk::FenceCreateInfo::builder(), None)?;
+
+        Ok(Self {
+            queue,
+            command_pool,
+            command_buffer,
+            fence,
+            submitted: false,
+        })
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.submitted
+    }
+
+    // Verdadeiro uma vez só, quando o submit em voo termina
+    pub unsafe fn poll(&mut self, device: &Device) -> Result<bool> {
+        if !self.submitted {
+            return Ok(false);
+        }
+        let status = device
+            .get_fence_status(self.fence)
+            .map_err(RendererError::from)?;
+        if status != vk::SuccessCode::SUCCESS {
+            return Ok(false);
+        }
+
+        device.reset_fences(&[self.fence])?;
+        self.submitted = false;
+        Ok(true)
+    }
+
+    pub unsafe fn begin(&mut self, device: &Device) -> Result<vk::CommandBuffer> {
+        device.reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())?;
+        let info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(self.command_buffer, &info)?;
+
+        Ok(self.command_buffer)
+    }
+
+    pub unsafe fn submit(&mut self, device: &Device) -> Result<()> {
+        device.end_command_buffer(self.command_buffer)?;
+
+        let command_buffers = &[self.command_buffer];
+        let info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+        device
+            .queue_submit(self.queue, &[info], self.fence)
+            .map_err(RendererError::from)?;
+        self.submitted = true;
+
+        Ok(())
+    }
+
+    // Espera o submit em voo, se tiver um
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        if self.submitted {
+            let _ = device.wait_for_fences(&[self.fence], true, u64::MAX);
+            self.submitted = false;
+        }
+
+        device.destroy_fence(self.fence, None);
+        device.destroy_command_pool(self.command_pool, None);
+    }
+}
+
+unsafe fn record_chunk(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    staging: vk::Buffer,
+    offset: usize,
+    size: usize,
+    upload: &PendingUpload,
+    transfer_only: bool,
+) {
+    match upload.target {
+        UploadTarget::Buffer {
+            buffer,
+            offset: dst_offset,
+        } => {
+            let region = vk::BufferCopy::builder()
+                .src_offset(offset as vk::DeviceSize)
+                .dst_offset(dst_offset + upload.cursor as vk::DeviceSize)
+                .size(size as vk::DeviceSize);
+
+            device.cmd_copy_buffer(command_buffer, staging, buffer, &[region]);
+        }
+        UploadTarget::Image {
+            image,
+            width,
+            height,
+            mip_level,
+            block_size,
+            final_layout,
+            ..
+        } => {
+            let (row_size, total_rows) = upload.target.rows().unwrap();
+            let first_row = (upload.cursor / row_size) as u32;
+            let rows = (size / row_size) as u32;
+            // Linhas (de blocos) por fatia
+            let slice_rows = (height + block_size - 1) / block_size;
+
+            if upload.cursor == 0 {
+                transition(
+                    device,
+                    command_buffer,
+                    image,
+                    mip_level,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    transfer_only,
+                );
+            }
+
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(mip_level)
+                .base_array_layer(0)
+                .layer_count(1);
+
+            // Num volume as linhas continuam na fatia seguinte, então o pedaço pode virar
+            // uma região por fatia. A última fileira de blocos pode passar da borda, e aí a
+            // altura copiada é só o que sobra da imagem
+            let mut regions = vec![];
+            let mut row = first_row;
+            while row < first_row + rows {
+                let y = row % slice_rows;
+                let count = (slice_rows - y).min(first_row + rows - row);
+                let region = vk::BufferImageCopy::builder()
+                    .buffer_offset(
+                        (offset + (row - first_row) as usize * row_size) as vk::DeviceSize,
+                    )
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(subresource)
+                    .image_offset(vk::Offset3D {
+                        x: 0,
+                        y: (y * block_size) as i32,
+                        z: (row / slice_rows) as i32,
+                    })
+                    .image_extent(vk::Extent3D {
+                        width,
+                        height: (count * block_size).min(height - y * block_size),
+                        depth: 1,
+                    })
+                    .build();
+                regions.push(region);
+                row += count;
+            }
+
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+
+            if first_row + rows == total_rows {
+                transition(
+                    device,
+                    command_buffer,
+                    image,
+                    mip_level,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    final_layout,
+                    transfer_only,
+                );
+            }
+        }
+    }
+}
+
+unsafe fn transition(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    mip_level: u32,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    transfer_only: bool,
+) {
+    let (src_access_mask, dst_access_mask, src_stage_mask, dst_stage_mask) =
+        if old_layout == vk::ImageLayout::UNDEFINED {
+            (
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+            )
+        } else if transfer_only {
+            (
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            )
+        } else {
+            (
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            )
+        };
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(mip_level)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource_range)
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage_mask,
+        dst_stage_mask,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+}
+
+fn align(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) / alignment * alignment
+}
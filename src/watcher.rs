@@ -0,0 +1,116 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use log::*;
+
+// Entre uma varredura e outra. Editores costumam salvar em mais de uma escrita, então
+// menos que isso só pegaria arquivos pela metade
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Avisa quais arquivos de uma pasta (e subpastas) mudaram. Uma thread compara a data de
+// modificação de tudo a cada POLL_INTERVAL: sem depender de inotify/FSEvents/etc, e uma
+// pasta de assets não é grande o bastante pra isso pesar
+#[derive(Debug)]
+pub struct FileWatcher {
+    root: PathBuf,
+    changes: mpsc::Receiver<PathBuf>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileWatcher {
+    pub fn new(root: &Path) -> Result<Self> {
+        let root = root.canonicalize()?;
+        let mut times = scan(&root)?;
+        let (sender, changes) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let root = root.clone();
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("file-watcher".into())
+                .spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        thread::sleep(POLL_INTERVAL);
+
+                        // Uma pasta sumindo no meio (git checkout, etc) só atrasa a varredura
+                        let current = match scan(&root) {
+                            Ok(current) => current,
+                            Err(e) => {
+                                trace!("Failed to scan {}: {}", root.display(), e);
+                                continue;
+                            }
+                        };
+
+                        // Novos também contam, pra quem salva num temporário e renomeia
+                        for (path, time) in &current {
+                            if times.get(path) != Some(time) && sender.send(path.clone()).is_err() {
+                                return;
+                            }
+                        }
+                        times = current;
+                    }
+                })?
+        };
+
+        info!("Watching {} for changes.", root.display());
+
+        Ok(Self {
+            root,
+            changes,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // Os que mudaram desde a última chamada, canônicos e sem repetição
+    pub fn changed(&self) -> Vec<PathBuf> {
+        let mut paths = self.changes.try_iter().collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn scan(root: &Path) -> io::Result<HashMap<PathBuf, SystemTime>> {
+    let mut times = HashMap::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                times.insert(entry.path(), metadata.modified()?);
+            }
+        }
+    }
+
+    Ok(times)
+}
@@ -0,0 +1,464 @@
+use std::{ffi::CString, fmt};
+
+use anyhow::{anyhow, Result};
+use log::*;
+use nalgebra_glm as glm;
+use openxr as xr;
+use vulkanalia::{prelude::v1_0::*, vk::Handle};
+
+use crate::{
+    app::AppData,
+    camera::{Camera, Projection},
+    multiview::{MultiviewRenderer, View},
+};
+
+const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+
+// Formatos da swapchain do headset, na ordem de preferência. Todos aceitam ser destino
+// de blit, que converte do HDR do alvo estéreo
+const COLOR_FORMATS: &[vk::Format] = &[vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_SRGB];
+
+// O que o runtime devolveu no começo do frame
+struct XrFrame {
+    time: xr::Time,
+    views: Vec<xr::View>,
+    // Imagem adquirida da swapchain; None quando o runtime disse pra não desenhar
+    image: Option<vk::Image>,
+}
+
+// Tudo que depende do dispositivo lógico: some junto com ele numa perda de dispositivo
+struct XrSession {
+    session: xr::Session<xr::Vulkan>,
+    waiter: xr::FrameWaiter,
+    stream: xr::FrameStream<xr::Vulkan>,
+    space: xr::Space,
+    // Uma imagem de duas camadas por vez, um olho em cada, igual ao alvo estéreo
+    swapchain: xr::Swapchain<xr::Vulkan>,
+    images: Vec<vk::Image>,
+    running: bool,
+    frame: Option<XrFrame>,
+}
+
+// VR pelo OpenXR. O runtime escolhe o dispositivo físico e diz quais extensões a
+// instância e o dispositivo precisam (XR_KHR_vulkan_enable); o App cria os dois como
+// sempre, com isso somado. A cada frame as poses dos olhos viram as views do pass
+// estéreo, e o alvo dele é copiado pra swapchain do headset
+pub struct XrContext {
+    instance: xr::Instance,
+    system: xr::SystemId,
+    blend_mode: xr::EnvironmentBlendMode,
+    extent: vk::Extent2D,
+    events: xr::EventDataBuffer,
+    session: Option<XrSession>,
+    exit_requested: bool,
+}
+
+impl fmt::Debug for XrContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XrContext")
+            .field("extent", &self.extent)
+            .field("running", &self.is_running())
+            .field("exit_requested", &self.exit_requested)
+            .finish()
+    }
+}
+
+impl XrContext {
+    // Carrega o loader do OpenXR e procura um headset. Falha sem runtime ou sem headset
+    // conectado
+    pub fn new() -> Result<Self> {
+        let entry = unsafe { xr::Entry::load() }
+            .map_err(|e| anyhow!("Failed to load the OpenXR loader: {}", e))?;
+
+        let available = entry.enumerate_extensions()?;
+        if !available.khr_vulkan_enable {
+            return Err(anyhow!("The OpenXR runtime doesn't support Vulkan."));
+        }
+
+        let mut extensions = xr::ExtensionSet::default();
+        extensions.khr_vulkan_enable = true;
+        let application = xr::ApplicationInfo {
+            application_name: "Learning Vulkan",
+            application_version: 1,
+            engine_name: "No Engine",
+            engine_version: 1,
+        };
+        let instance = entry.create_instance(&application, &extensions, &[])?;
+        let properties = instance.properties()?;
+        info!(
+            "OpenXR runtime: {} {}.",
+            properties.runtime_name, properties.runtime_version
+        );
+
+        let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+        let blend_mode = instance.enumerate_environment_blend_modes(system, VIEW_TYPE)?[0];
+
+        // A instância é 1.0; o runtime tem que aceitar isso. Consultar os requisitos
+        // também é obrigatório antes de criar a sessão
+        let requirements = instance.graphics_requirements::<xr::Vulkan>(system)?;
+        if requirements.min_api_version_supported > xr::Version::new(1, 0, 0) {
+            return Err(anyhow!(
+                "The OpenXR runtime needs Vulkan {}.",
+                requirements.min_api_version_supported
+            ));
+        }
+
+        // Os dois olhos com o mesmo tamanho, que é o caso de todo headset estéreo
+        let views = instance.enumerate_view_configuration_views(system, VIEW_TYPE)?;
+        let view = views
+            .first()
+            .ok_or_else(|| anyhow!("The OpenXR runtime reported no views."))?;
+        let extent = vk::Extent2D {
+            width: view.recommended_image_rect_width,
+            height: view.recommended_image_rect_height,
+        };
+        info!("Headset views: {}x{}.", extent.width, extent.height);
+
+        Ok(Self {
+            instance,
+            system,
+            blend_mode,
+            extent,
+            events: xr::EventDataBuffer::new(),
+            session: None,
+            exit_requested: false,
+        })
+    }
+
+    pub fn instance_extensions(&self) -> Result<Vec<CString>> {
+        let names = self
+            .instance
+            .vulkan_legacy_instance_extensions(self.system)?;
+        Ok(split_extensions(&names))
+    }
+
+    pub fn device_extensions(&self) -> Result<Vec<CString>> {
+        let names = self.instance.vulkan_legacy_device_extensions(self.system)?;
+        Ok(split_extensions(&names))
+    }
+
+    // O dispositivo ligado no headset; o App não pode escolher outro
+    pub unsafe fn physical_device(&self, instance: &Instance) -> Result<vk::PhysicalDevice> {
+        let physical_device = self
+            .instance
+            .vulkan_graphics_device(self.system, instance.handle().as_raw() as _)?;
+        Ok(vk::PhysicalDevice::from_raw(physical_device as usize))
+    }
+
+    // Tamanho de cada olho, o do alvo estéreo
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    // Com o dispositivo lógico pronto. A sessão só roda depois que o runtime mandar o
+    // READY (ver `poll_events`)
+    pub unsafe fn create_session(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        queue_family: u32,
+    ) -> Result<()> {
+        let info = xr::vulkan::SessionCreateInfo {
+            instance: instance.handle().as_raw() as _,
+            physical_device: data.physical_device.as_raw() as _,
+            device: device.handle().as_raw() as _,
+            queue_family_index: queue_family,
+            queue_index: 0,
+        };
+        let (session, waiter, stream) = self
+            .instance
+            .create_session::<xr::Vulkan>(self.system, &info)?;
+
+        // LOCAL existe em todo runtime: a origem é onde o headset estava ao começar
+        let space =
+            session.create_reference_space(xr::ReferenceSpaceType::LOCAL, xr::Posef::IDENTITY)?;
+
+        let formats = session.enumerate_swapchain_formats()?;
+        let format = COLOR_FORMATS
+            .iter()
+            .map(|f| f.as_raw() as u32)
+            .find(|f| formats.contains(f))
+            .ok_or_else(|| anyhow!("The OpenXR runtime has no sRGB swapchain format."))?;
+
+        let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+            create_flags: xr::SwapchainCreateFlags::EMPTY,
+            usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+                | xr::SwapchainUsageFlags::TRANSFER_DST,
+            format,
+            sample_count: 1,
+            width: self.extent.width,
+            height: self.extent.height,
+            face_count: 1,
+            array_size: 2,
+            mip_count: 1,
+        })?;
+        let images = swapchain
+            .enumerate_images()?
+            .into_iter()
+            .map(vk::Image::from_raw)
+            .collect();
+
+        self.session = Some(XrSession {
+            session,
+            waiter,
+            stream,
+            space,
+            swapchain,
+            images,
+            running: false,
+            frame: None,
+        });
+
+        Ok(())
+    }
+
+    // Antes de destruir o dispositivo lógico
+    pub fn destroy_session(&mut self) {
+        self.session = None;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.session.as_ref().is_some_and(|s| s.running)
+    }
+
+    // O runtime fechou a sessão (o usuário saiu pelo menu do headset, por exemplo)
+    pub fn exit_requested(&self) -> bool {
+        self.exit_requested
+    }
+
+    // Começa e termina a sessão conforme o runtime pede
+    pub fn poll_events(&mut self) -> Result<()> {
+        while let Some(event) = self.instance.poll_event(&mut self.events)? {
+            match event {
+                xr::Event::SessionStateChanged(change) => {
+                    let session = match &mut self.session {
+                        Some(session) => session,
+                        None => continue,
+                    };
+                    debug!("OpenXR session state: {:?}.", change.state());
+                    match change.state() {
+                        xr::SessionState::READY => {
+                            session.session.begin(VIEW_TYPE)?;
+                            session.running = true;
+                        }
+                        xr::SessionState::STOPPING => {
+                            session.session.end()?;
+                            session.running = false;
+                        }
+                        xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => {
+                            session.running = false;
+                            self.exit_requested = true;
+                        }
+                        _ => {}
+                    }
+                }
+                xr::Event::InstanceLossPending(_) => self.exit_requested = true,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    // Espera a vez do próximo frame do headset (é isso que segura o ritmo) e devolve as
+    // views dos olhos nesse frame, com a câmera como a origem do espaço do usuário. None
+    // quando não tem o que desenhar; `end_frame` precisa ser chamado do mesmo jeito
+    pub fn begin_frame(&mut self, camera: &Camera) -> Result<Option<[View; 2]>> {
+        let session = match &mut self.session {
+            Some(session) if session.running => session,
+            _ => return Ok(None),
+        };
+
+        let state = session.waiter.wait()?;
+        session.stream.begin()?;
+        let time = state.predicted_display_time;
+        if !state.should_render {
+            session.frame = Some(XrFrame {
+                time,
+                views: vec![],
+                image: None,
+            });
+            return Ok(None);
+        }
+
+        let (_, views) = session
+            .session
+            .locate_views(VIEW_TYPE, time, &session.space)?;
+        let index = session.swapchain.acquire_image()?;
+        session.swapchain.wait_image(xr::Duration::INFINITE)?;
+
+        let eye = |view: &xr::View| View {
+            view: pose_inverse(&view.pose) * camera.view(),
+            projection: fov_projection(&view.fov, camera),
+        };
+        let eyes = [eye(&views[0]), eye(&views[1])];
+
+        session.frame = Some(XrFrame {
+            time,
+            views,
+            image: Some(session.images[index as usize]),
+        });
+
+        Ok(Some(eyes))
+    }
+
+    // Imagem adquirida nesse frame, pro grafo
+    pub fn image(&self) -> vk::Image {
+        self.session
+            .as_ref()
+            .and_then(|s| s.frame.as_ref())
+            .and_then(|f| f.image)
+            .unwrap_or_else(vk::Image::null)
+    }
+
+    // As duas camadas do alvo estéreo pras da imagem do headset. O grafo deixa o alvo em
+    // TRANSFER_SRC e a imagem em TRANSFER_DST
+    pub unsafe fn record_copy(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        stereo: &MultiviewRenderer,
+    ) {
+        let image = self.image();
+        if image.is_null() {
+            return;
+        }
+
+        let layers = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(2)
+            .build();
+        let corner = |extent: vk::Extent2D| vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: 1,
+        };
+        let region = vk::ImageBlit::builder()
+            .src_subresource(layers)
+            .src_offsets([vk::Offset3D::default(), corner(stereo.extent())])
+            .dst_subresource(layers)
+            .dst_offsets([vk::Offset3D::default(), corner(self.extent)]);
+
+        device.cmd_blit_image(
+            command_buffer,
+            stereo.image(),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region],
+            vk::Filter::LINEAR,
+        );
+    }
+
+    // Depois do submit do frame: a imagem volta pro runtime (ele espera a fila) e a camada
+    // com os dois olhos vai pro compositor
+    pub fn end_frame(&mut self) -> Result<()> {
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        let frame = match session.frame.take() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        if frame.image.is_none() {
+            session.stream.end(frame.time, self.blend_mode, &[])?;
+            return Ok(());
+        }
+
+        session.swapchain.release_image()?;
+
+        let rect = xr::Rect2Di {
+            offset: xr::Offset2Di { x: 0, y: 0 },
+            extent: xr::Extent2Di {
+                width: self.extent.width as i32,
+                height: self.extent.height as i32,
+            },
+        };
+        let views = frame
+            .views
+            .iter()
+            .enumerate()
+            .map(|(layer, view)| {
+                xr::CompositionLayerProjectionView::new()
+                    .pose(view.pose)
+                    .fov(view.fov)
+                    .sub_image(
+                        xr::SwapchainSubImage::new()
+                            .swapchain(&session.swapchain)
+                            .image_array_index(layer as u32)
+                            .image_rect(rect),
+                    )
+            })
+            .collect::<Vec<_>>();
+        let layer = xr::CompositionLayerProjection::new()
+            .space(&session.space)
+            .views(&views);
+        session.stream.end(frame.time, self.blend_mode, &[&layer])?;
+
+        Ok(())
+    }
+}
+
+// O runtime manda os nomes separados por espaço
+fn split_extensions(names: &str) -> Vec<CString> {
+    names
+        .split_whitespace()
+        .filter_map(|name| CString::new(name).ok())
+        .collect()
+}
+
+// Do olho pro espaço do usuário, invertido: a view do olho relativa à câmera
+fn pose_inverse(pose: &xr::Posef) -> glm::Mat4 {
+    let o = pose.orientation;
+    let p = pose.position;
+    let rotation = glm::quat(o.x, o.y, o.z, o.w);
+
+    glm::quat_to_mat4(&glm::quat_conjugate(&rotation))
+        * glm::translation(&glm::vec3(-p.x, -p.y, -p.z))
+}
+
+// Frustum assimétrico do runtime, com os planos e o reverse-Z da câmera. Mesmas
+// convenções do Camera::projection_matrix (y do NDC pra baixo, profundidade de 0 a 1)
+fn fov_projection(fov: &xr::Fovf, camera: &Camera) -> glm::Mat4 {
+    let (near, far) = match camera.projection {
+        Projection::Perspective { near, far, .. } => (near, far),
+        Projection::Orthographic { near, far, .. } => (near, Some(far)),
+    };
+
+    let left = fov.angle_left.tan();
+    let right = fov.angle_right.tan();
+    let up = fov.angle_up.tan();
+    let down = fov.angle_down.tan();
+
+    // z' = a * z + b, com w = -z
+    let (a, b) = match (far, camera.reverse_z) {
+        (Some(far), false) => (far / (near - far), near * far / (near - far)),
+        (Some(far), true) => (near / (far - near), near * far / (far - near)),
+        (None, false) => (-1.0, -near),
+        (None, true) => (0.0, near),
+    };
+
+    glm::mat4(
+        2.0 / (right - left),
+        0.0,
+        (right + left) / (right - left),
+        0.0,
+        0.0,
+        -2.0 / (up - down),
+        -(up + down) / (up - down),
+        0.0,
+        0.0,
+        0.0,
+        a,
+        b,
+        0.0,
+        0.0,
+        -1.0,
+        0.0,
+    )
+}
@@ -0,0 +1,179 @@
+// Testes de imagem: roda o binário sem janela visível, salva o último frame e compara
+// com as referências em tests/golden. Precisam de um dispositivo Vulkan (lavapipe ou
+// SwiftShader servem) e de um display (xvfb-run), então só rodam com --ignored:
+//
+//     xvfb-run cargo test --test golden -- --ignored
+//
+// LV_UPDATE_GOLDEN=1 regrava as referências com o que foi renderizado. Quando uma
+// comparação falha, a imagem renderizada e a diferença ficam em target/golden. No CI
+// (.github/workflows/golden.yml) as referências valem pro lavapipe anotado em
+// tests/golden/lavapipe.txt; pra regravar, rode o workflow à mão com `update` e commite o
+// artefato golden-references aqui
+
+use std::{
+    env,
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const WIDTH: &str = "256";
+const HEIGHT: &str = "256";
+// Dá tempo dos uploads terminarem e da exposição automática assentar
+const FRAMES: &str = "30";
+const FRAME_TIME_MS: &str = "16";
+
+// Diferença máxima (0 a 1, na escala do YIQ) pra um pixel contar como igual. Absorve o
+// arredondamento de drivers diferentes sem deixar passar uma mudança de cor de verdade
+const PIXEL_THRESHOLD: f32 = 0.1;
+// Fração dos pixels que pode passar do limite (bordas com rasterização diferente)
+const MAX_DIFFERENT: f32 = 0.005;
+
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+fn render(name: &str, args: &[&str]) -> Image {
+    fs::create_dir_all(output_dir()).unwrap();
+    let path = output_dir().join(format!("{}.png", name));
+    let _ = fs::remove_file(&path);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_learning-vulkan"))
+        .args(["--headless", "--allow-software", "--no-validation"])
+        .args(["--present-mode", "fifo"])
+        .args(["--width", WIDTH, "--height", HEIGHT])
+        .args(["--frames", FRAMES, "--frame-time", FRAME_TIME_MS])
+        .arg("--screenshot")
+        .arg(&path)
+        .args(args)
+        .status()
+        .expect("failed to run the renderer");
+    assert!(status.success(), "renderer exited with {}", status);
+
+    load(&path).unwrap_or_else(|| panic!("renderer didn't write {}", path.display()))
+}
+
+fn load(path: &Path) -> Option<Image> {
+    let decoder = png::Decoder::new(File::open(path).ok()?);
+    let (info, mut reader) = decoder.read_info().ok()?;
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).ok()?;
+    assert_eq!(info.color_type, png::ColorType::RGBA);
+
+    Some(Image {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+fn save(path: &Path, image: &Image) {
+    let file = BufWriter::new(File::create(path).unwrap());
+    let mut encoder = png::Encoder::new(file, image.width, image.height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(&image.pixels)
+        .unwrap();
+}
+
+// Diferença entre duas cores no YIQ, pesada como no pixelmatch: o olho nota mais a
+// luminância que o croma. 0 é igual, 1 é preto contra branco
+fn yiq_delta(a: &[u8], b: &[u8]) -> f32 {
+    let yiq = |p: &[u8]| {
+        let [r, g, b] = [p[0], p[1], p[2]].map(|c| c as f32 / 255.0);
+        (
+            0.29889531 * r + 0.58662247 * g + 0.11448223 * b,
+            0.59597799 * r - 0.27417610 * g - 0.32180189 * b,
+            0.21147017 * r - 0.52261711 * g + 0.31114694 * b,
+        )
+    };
+    let (y1, i1, q1) = yiq(a);
+    let (y2, i2, q2) = yiq(b);
+    let (y, i, q) = (y1 - y2, i1 - i2, q1 - q2);
+
+    ((0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / 0.5053).sqrt()
+}
+
+fn check(name: &str, args: &[&str]) {
+    let actual = render(name, args);
+    let reference_path = golden_dir().join(format!("{}.png", name));
+
+    if env::var_os("LV_UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(golden_dir()).unwrap();
+        save(&reference_path, &actual);
+        return;
+    }
+
+    let expected = load(&reference_path).unwrap_or_else(|| {
+        panic!(
+            "missing reference {}; run with LV_UPDATE_GOLDEN=1 to create it",
+            reference_path.display()
+        )
+    });
+    assert_eq!(
+        (actual.width, actual.height),
+        (expected.width, expected.height),
+        "{}: size differs from the reference",
+        name
+    );
+
+    // Os pixels diferentes em vermelho sobre a referência apagada
+    let mut diff = Vec::with_capacity(actual.pixels.len());
+    let mut different = 0;
+    for (a, e) in actual
+        .pixels
+        .chunks_exact(4)
+        .zip(expected.pixels.chunks_exact(4))
+    {
+        if yiq_delta(a, e) > PIXEL_THRESHOLD {
+            different += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let gray = (e[0] as u32 + e[1] as u32 + e[2] as u32) / 12 + 191;
+            diff.extend_from_slice(&[gray as u8, gray as u8, gray as u8, 255]);
+        }
+    }
+
+    let fraction = different as f32 / (actual.width * actual.height) as f32;
+    if fraction > MAX_DIFFERENT {
+        let diff = Image {
+            width: actual.width,
+            height: actual.height,
+            pixels: diff,
+        };
+        let diff_path = output_dir().join(format!("{}.diff.png", name));
+        save(&diff_path, &diff);
+        panic!(
+            "{}: {:.2}% of the pixels differ from the reference (see {})",
+            name,
+            fraction * 100.0,
+            diff_path.display()
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs a Vulkan device and a display"]
+fn forward() {
+    check("forward", &[]);
+}
+
+#[test]
+#[ignore = "needs a Vulkan device and a display"]
+fn deferred() {
+    check("deferred", &["--deferred"]);
+}